@@ -6,12 +6,16 @@
 //! See the docs for the main Weld crate for more details on these functions.
 #![allow(clippy::missing_safety_doc)]
 
+#[macro_use]
+extern crate log;
+
 use weld;
 
 use libc::{c_char, c_void};
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::ptr;
+use std::slice;
 
 // Re-export the FFI for the runtime.
 pub use weld::runtime::ffi::*;
@@ -55,6 +59,23 @@ pub type weld_module_t = *mut WeldModule;
 #[allow(non_camel_case_types)]
 pub type weld_value_t = *mut WeldValue;
 
+/// A callback that provides the chunks of a chunk source.
+///
+/// The callback receives the state passed to `weld_context_add_source`. It writes a pointer to the
+/// next chunk into `data` and returns the chunk's size in bytes, or returns 0 if the source is
+/// exhausted. The chunk must remain valid until the next call.
+#[allow(non_camel_case_types)]
+pub type weld_chunk_callback_t =
+    unsafe extern "C" fn(state: *mut c_void, data: *mut *const u8) -> i64;
+
+/// A callback that receives log messages.
+///
+/// The callback receives the level of the message, the message as a null-terminated string that
+/// is only valid during the call, and the state passed to `weld_set_log_callback`.
+#[allow(non_camel_case_types)]
+pub type weld_log_callback_t =
+    unsafe extern "C" fn(level: u64, message: *const c_char, state: *mut c_void);
+
 pub use weld::WeldLogLevel;
 pub use weld::WeldRuntimeErrno;
 
@@ -103,6 +124,64 @@ pub unsafe extern "C" fn weld_context_memory_usage(context: weld_context_t) -> i
     context.memory_usage()
 }
 
+#[no_mangle]
+/// Frees the values returned by earlier runs in a context, keeping their memory for later runs.
+///
+/// Values returned by earlier runs in the context must not be used after this call. This function
+/// is a wrapper for `WeldContext::reset`.
+pub unsafe extern "C" fn weld_context_reset(context: weld_context_t) {
+    let context = context as *mut weld::WeldContext;
+    let context = &mut *context;
+    context.reset()
+}
+
+/// A chunk source implemented by a C callback.
+struct CallbackSource {
+    callback: weld_chunk_callback_t,
+    state: *mut c_void,
+}
+
+// The caller of `weld_context_add_source` guarantees that the callback can be called on the
+// thread that runs the module.
+unsafe impl Send for CallbackSource {}
+
+impl weld::ChunkSource for CallbackSource {
+    fn next_chunk(&mut self) -> Option<&[u8]> {
+        let mut data = ptr::null();
+        let size = unsafe { (self.callback)(self.state, &mut data) };
+        if size <= 0 || data.is_null() {
+            None
+        } else {
+            Some(unsafe { slice::from_raw_parts(data, size as usize) })
+        }
+    }
+}
+
+#[no_mangle]
+/// Adds a source of chunked input to a context and returns its handle.
+///
+/// Programs read the source with a `chunkiter` over the handle, which calls `callback` with
+/// `state` for each chunk. This function is a wrapper for `WeldContext::add_source`.
+pub unsafe extern "C" fn weld_context_add_source(
+    context: weld_context_t,
+    callback: weld_chunk_callback_t,
+    state: *mut c_void,
+) -> i64 {
+    let context = context as *mut weld::WeldContext;
+    let context = &mut *context;
+    context.add_source(Box::new(CallbackSource { callback, state }))
+}
+
+#[no_mangle]
+/// Removes the chunk sources added to a context.
+///
+/// This function is a wrapper for `WeldContext::clear_sources`.
+pub unsafe extern "C" fn weld_context_clear_sources(context: weld_context_t) {
+    let context = context as *mut weld::WeldContext;
+    let context = &mut *context;
+    context.clear_sources()
+}
+
 #[no_mangle]
 /// Frees a context.
 ///
@@ -283,7 +362,7 @@ pub unsafe extern "C" fn weld_module_run(
             Box::into_raw(Box::new(result)) as _
         }
         Err(runtime_err) => {
-            eprintln!("{:?}", runtime_err);
+            error!("{:?}", runtime_err);
             *err = runtime_err;
             ptr::null_mut() as _
         }
@@ -328,6 +407,28 @@ pub unsafe extern "C" fn weld_error_message(err: weld_error_t) -> *const c_char
     err.message().as_ptr()
 }
 
+#[no_mangle]
+/// Returns the line of the program text that caused a Weld error.
+///
+/// Lines are numbered from 1. This function returns 0 if the error has no span: only parse
+/// and type errors have spans. This function is a wrapper for `WeldError::span`.
+pub unsafe extern "C" fn weld_error_line(err: weld_error_t) -> u64 {
+    let err = err as *mut weld::WeldError;
+    let err = &*err;
+    err.span().map(|span| span.line as u64).unwrap_or(0)
+}
+
+#[no_mangle]
+/// Returns the column of the program text that caused a Weld error.
+///
+/// Columns are numbered from 1. This function returns 0 if the error has no span: only parse
+/// and type errors have spans. This function is a wrapper for `WeldError::span`.
+pub unsafe extern "C" fn weld_error_column(err: weld_error_t) -> u64 {
+    let err = err as *mut weld::WeldError;
+    let err = &*err;
+    err.span().map(|span| span.column as u64).unwrap_or(0)
+}
+
 #[no_mangle]
 /// Frees a Weld error object.
 pub unsafe extern "C" fn weld_error_free(err: weld_error_t) {
@@ -354,10 +455,59 @@ pub unsafe extern "C" fn weld_load_library(filename: *const c_char, err: weld_er
 }
 
 #[no_mangle]
-/// Enables logging to stderr in Weld with the given log level.
+/// Register a C UDF that a Weld program can call with `cudf`.
 ///
-/// This function is ignored if it has already been called once, or if some other code in the
-/// process has initialized logging using Rust's `log` crate.
+/// This function is a wrapper for `register_udf`.
+pub unsafe extern "C" fn weld_register_udf(
+    name: *const c_char,
+    function: *mut c_void,
+    err: weld_error_t,
+) {
+    let err = err as *mut weld::WeldError;
+    let err = &mut *err;
+    let name = name.to_str();
+    if let Err(e) = weld::register_udf(name, function) {
+        *err = e;
+    } else {
+        *err = weld::WeldError::new_success();
+    }
+}
+
+#[no_mangle]
+/// Sets the level of messages that Weld logs.
+///
+/// Messages are written to stderr unless a callback is set with `weld_set_log_callback`. This
+/// function is a wrapper for `set_log_level`.
 pub extern "C" fn weld_set_log_level(level: u64) {
     weld::set_log_level(level.into())
 }
+
+/// A log sink implemented by a C callback.
+struct CallbackSink {
+    callback: weld_log_callback_t,
+    state: *mut c_void,
+}
+
+// The caller of `weld_set_log_callback` guarantees that the callback can be called from any
+// thread.
+unsafe impl Send for CallbackSink {}
+unsafe impl Sync for CallbackSink {}
+
+#[no_mangle]
+/// Sets a callback that receives the messages Weld logs.
+///
+/// The callback is called with `state` and may be called from any thread. This function is a
+/// wrapper for `set_log_sink`.
+pub unsafe extern "C" fn weld_set_log_callback(callback: weld_log_callback_t, state: *mut c_void) {
+    let sink = CallbackSink { callback, state };
+    weld::set_log_sink(Some(Box::new(move |level, message| {
+        let message = CString::new(message.replace('\0', "")).unwrap();
+        (sink.callback)(level as u64, message.as_ptr(), sink.state);
+    })));
+}
+
+#[no_mangle]
+/// Removes the log callback, so that Weld writes log messages to stderr.
+pub extern "C" fn weld_clear_log_callback() {
+    weld::set_log_sink(None);
+}
@@ -98,7 +98,7 @@ impl CppHeaderGenerator {
         let result = match *ty {
             Scalar(ref kind) => Ok(format!("{}", kind)),
             Vector(ref elem) => Ok(format!("vec<{}>", self.generate_type(elem)?)),
-            Struct(ref elems) => self.generate_struct_definition(elems),
+            Struct(ref elems, _) => self.generate_struct_definition(elems),
             // Other types (Builders, Functions, etc.) cannot be passed into Weld.
             _ => weld_err!("Invalid C++ type {:?}", ty),
         };
@@ -125,7 +125,7 @@ impl CppHeaderGenerator {
             .generate_type(&types.return_type)
             .expect("Type generation failed!");
         let param_type = self
-            .generate_type(&ast::Type::Struct(types.param_types))
+            .generate_type(&ast::Type::Struct(types.param_types, FieldNames::none()))
             .expect("Type generation failed!");
 
         self.code.add("\n");
@@ -4,18 +4,26 @@
 use crate::error::*;
 use crate::util;
 
+use fnv::FnvHashSet;
+
 use self::BinOpKind::*;
 use self::ExprKind::*;
 use self::ScalarKind::*;
 
-use std::collections::BTreeMap;
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
-use std::rc::Rc;
+use std::hash::{Hash, Hasher};
+use std::slice;
+use std::sync::Arc;
 use std::vec;
 
 /// Name used for placeholder expressions.
 const PLACEHOLDER_NAME: &str = "#placeholder";
 
+/// Number of times `Expr::cost` assumes a loop body runs.
+pub const LOOP_COST_FACTOR: usize = 10;
+
 /// An annotation over a type or expression.
 ///
 /// Annotations are unstructured String key-value pairs. They can be added on expressions and
@@ -30,6 +38,7 @@ const PLACEHOLDER_NAME: &str = "#placeholder";
 /// The annotation system should in theory support arbitrary string key/value pairs: the parser
 /// will eventually be updated to support this.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Annotations {
     /// Holds the annotations.
     ///
@@ -95,8 +104,65 @@ impl fmt::Display for Annotations {
     }
 }
 
+/// The optional names of the fields of a struct type.
+///
+/// Field names let programs access a field with `x.name` in addition to its index. They do not
+/// change the type itself: two struct types with the same field types are equal and hash equally
+/// regardless of their field names, so named and positional structs can be used interchangeably.
+/// Type inference reports an error if it unifies two structs whose field names differ.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldNames {
+    /// The name of each field, or `None` if the fields are only positional.
+    names: Option<Vec<String>>,
+}
+
+impl FieldNames {
+    /// Returns field names for a positional struct.
+    pub fn none() -> FieldNames {
+        FieldNames { names: None }
+    }
+
+    /// Returns the given field names.
+    pub fn new(names: Vec<String>) -> FieldNames {
+        FieldNames { names: Some(names) }
+    }
+
+    /// Returns the names of the fields, or `None` if the fields are only positional.
+    pub fn names(&self) -> Option<&[String]> {
+        self.names.as_deref()
+    }
+
+    /// Returns whether the fields are named.
+    pub fn is_named(&self) -> bool {
+        self.names.is_some()
+    }
+
+    /// Returns the index of the field called `name`.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names
+            .as_ref()
+            .and_then(|names| names.iter().position(|n| n == name))
+    }
+}
+
+impl PartialEq for FieldNames {
+    /// Field names are ignored when comparing types.
+    fn eq(&self, _: &FieldNames) -> bool {
+        true
+    }
+}
+
+impl Eq for FieldNames {}
+
+impl Hash for FieldNames {
+    /// Field names are ignored when hashing types.
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
 /// Types in the Weld IR.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     /// A scalar.
     Scalar(ScalarKind),
@@ -108,8 +174,8 @@ pub enum Type {
     Dict(Box<Type>, Box<Type>),
     /// A mutable builder to construct results.
     Builder(BuilderKind, Annotations),
-    /// An ordered struct or tuple.
-    Struct(Vec<Type>),
+    /// An ordered struct or tuple, whose fields may be named.
+    Struct(Vec<Type>, FieldNames),
     /// A function with a list of arguments and return type.
     Function(Vec<Type>, Box<Type>),
     /// An alias for a type.
@@ -135,7 +201,7 @@ impl Type {
                 GroupMerger(ref key, ref value) => vec![key.as_ref(), value.as_ref()],
                 VecMerger(ref elem, _) => vec![elem.as_ref()],
             },
-            Struct(ref elems) => elems.iter().collect(),
+            Struct(ref elems, _) => elems.iter().collect(),
             Function(ref params, ref res) => {
                 let mut children = vec![];
                 for param in params.iter() {
@@ -164,7 +230,7 @@ impl Type {
                 GroupMerger(ref mut key, ref mut value) => vec![key.as_mut(), value.as_mut()],
                 VecMerger(ref mut elem, _) => vec![elem.as_mut()],
             },
-            Struct(ref mut elems) => elems.iter_mut().collect(),
+            Struct(ref mut elems, _) => elems.iter_mut().collect(),
             Function(ref mut params, ref mut res) => {
                 let mut children = vec![];
                 for param in params.iter_mut() {
@@ -191,7 +257,7 @@ impl Type {
         use self::Type::*;
         match *self {
             Simd(_) | Builder(_, _) => true,
-            Struct(ref fields) => fields.iter().all(|f| f.is_simd()),
+            Struct(ref fields, _) => fields.iter().all(|f| f.is_simd()),
             _ => false,
         }
     }
@@ -204,6 +270,11 @@ impl Type {
         }
     }
 
+    /// Returns whether this `Type` is a function.
+    pub fn is_function(&self) -> bool {
+        matches!(*self, Type::Function(_, _))
+    }
+
     /// Returns whether this `Type` contains a builder.
     pub fn contains_builder(&self) -> bool {
         use self::Type::Builder;
@@ -218,7 +289,7 @@ impl Type {
         use self::Type::{Builder, Struct};
         match *self {
             Builder(_, _) => true,
-            Struct(ref tys) => tys.iter().all(|t| t.is_builder()),
+            Struct(ref tys, _) => tys.iter().all(|t| t.is_builder()),
             _ => false,
         }
     }
@@ -230,7 +301,7 @@ impl Type {
             Scalar(_) => true,
             // XXX Is this hashable...?
             Simd(_) => true,
-            Struct(ref tys) => tys.iter().all(|t| t.is_hashable()),
+            Struct(ref tys, _) => tys.iter().all(|t| t.is_hashable()),
             Vector(ref elem) => elem.is_hashable(),
             Builder(_, _) => false,
             Dict(_, _) => false,
@@ -246,9 +317,9 @@ impl Type {
         match *self {
             Scalar(kind) => Ok(Simd(kind)),
             Builder(_, _) => Ok(self.clone()),
-            Struct(ref fields) => {
+            Struct(ref fields, ref names) => {
                 let result: WeldResult<_> = fields.iter().map(|f| f.simd_type()).collect();
-                Ok(Struct(result?))
+                Ok(Struct(result?, names.clone()))
             }
             _ => compile_err!("simd_type called on non-scalar type {}", self),
         }
@@ -262,9 +333,9 @@ impl Type {
         match *self {
             Simd(kind) => Ok(Scalar(kind)),
             Builder(_, _) => Ok(self.clone()),
-            Struct(ref fields) => {
+            Struct(ref fields, ref names) => {
                 let result: WeldResult<_> = fields.iter().map(|f| f.scalar_type()).collect();
-                Ok(Struct(result?))
+                Ok(Struct(result?, names.clone()))
             }
             _ => compile_err!("scalar_type called on non-SIMD type {}", self),
         }
@@ -302,7 +373,18 @@ impl fmt::Display for Type {
             Simd(ref kind) => format!("simd[{}]", kind),
             Vector(ref elem) => format!("vec[{}]", elem),
             Dict(ref key, ref value) => format!("dict[{},{}]", key, value),
-            Struct(ref elems) => util::join("{", ",", "}", elems.iter().map(|e| e.to_string())),
+            Struct(ref elems, ref names) => match names.names() {
+                Some(names) => util::join(
+                    "{",
+                    ",",
+                    "}",
+                    names
+                        .iter()
+                        .zip(elems)
+                        .map(|(name, e)| format!("{}:{}", name, e)),
+                ),
+                None => util::join("{", ",", "}", elems.iter().map(|e| e.to_string())),
+            },
             Function(ref params, ref return_type) => {
                 let mut res = util::join("|", ",", "|(", params.iter().map(|e| e.to_string()));
                 res.push_str(&return_type.to_string());
@@ -318,6 +400,7 @@ impl fmt::Display for Type {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Scalar types in the Weld IR.
 pub enum ScalarKind {
     Bool,
@@ -431,6 +514,7 @@ impl fmt::Display for ScalarKind {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Builder types in the Weld IR.
 pub enum BuilderKind {
     /// A builder that appends items to a list.
@@ -461,9 +545,13 @@ impl BuilderKind {
         match *self {
             Appender(ref elem) => *elem.clone(),
             Merger(ref elem, _) => *elem.clone(),
-            DictMerger(ref key, ref value, _) => Struct(vec![*key.clone(), *value.clone()]),
-            GroupMerger(ref key, ref value) => Struct(vec![*key.clone(), *value.clone()]),
-            VecMerger(ref elem, _) => Struct(vec![Scalar(I64), *elem.clone()]),
+            DictMerger(ref key, ref value, _) => {
+                Struct(vec![*key.clone(), *value.clone()], FieldNames::none())
+            }
+            GroupMerger(ref key, ref value) => {
+                Struct(vec![*key.clone(), *value.clone()], FieldNames::none())
+            }
+            VecMerger(ref elem, _) => Struct(vec![Scalar(I64), *elem.clone()], FieldNames::none()),
         }
     }
 
@@ -479,6 +567,33 @@ impl BuilderKind {
             VecMerger(ref elem, _) => Vector(elem.clone()),
         }
     }
+
+    /// Sets the types in this `BuilderKind` from the type merged into it.
+    ///
+    /// This is the inverse of `merge_type`. Returns an error if `ty` does not have the shape of
+    /// this builder's merge type.
+    pub fn set_merge_type(&mut self, ty: Type) -> WeldResult<()> {
+        use self::BuilderKind::*;
+        use self::Type::*;
+        match (self, ty) {
+            (&mut Appender(ref mut elem), ty) | (&mut Merger(ref mut elem, _), ty) => {
+                **elem = ty;
+            }
+            (&mut DictMerger(ref mut key, ref mut value, _), Struct(mut tys, _))
+            | (&mut GroupMerger(ref mut key, ref mut value), Struct(mut tys, _))
+                if tys.len() == 2 =>
+            {
+                **value = tys.pop().unwrap();
+                **key = tys.pop().unwrap();
+            }
+            // The first field is the index.
+            (&mut VecMerger(ref mut elem, _), Struct(mut tys, _)) if tys.len() == 2 => {
+                **elem = tys.pop().unwrap();
+            }
+            (kind, ty) => return compile_err!("Cannot merge type {} into {}", ty, kind),
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for BuilderKind {
@@ -499,15 +614,16 @@ impl fmt::Display for BuilderKind {
 
 /// A named symbol in the Weld AST.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Symbol {
-    name: Rc<String>,
+    name: Arc<String>,
     id: i32,
 }
 
 impl Symbol {
     pub fn new<T: Into<String>>(name: T, id: i32) -> Symbol {
         Symbol {
-            name: Rc::new(name.into()),
+            name: Arc::new(name.into()),
             id,
         }
     }
@@ -535,18 +651,86 @@ impl fmt::Display for Symbol {
     }
 }
 
+/// A region of the program text that an expression was parsed from.
+///
+/// `start` and `end` are byte offsets into the input, and `line` and `column` give the
+/// (one-indexed) position of `start`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Returns a span that starts at the beginning of `self` and ends at the end of `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end,
+            ..*self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Identifies an expression of a program.
+///
+/// The parser numbers the expressions of a program in pre-order, so the same program text always
+/// has the same IDs. Expressions introduced later (e.g., by macro expansion or optimization
+/// passes) are numbered by `Expr::assign_node_ids`, and copies of an expression keep its ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(pub u32);
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
 /// A typed Weld expression tree.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+///
+/// Expressions produced by the parser carry the `span` of the text they were parsed from and a
+/// node `id`, and transformations keep the span and ID of an expression they replace where
+/// possible. Spans and IDs are not considered when comparing or hashing expressions.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Expr {
     pub ty: Type,
     pub kind: ExprKind,
     pub annotations: Annotations,
+    pub span: Option<Span>,
+    pub id: Option<NodeId>,
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        self.ty == other.ty && self.kind == other.kind && self.annotations == other.annotations
+    }
+}
+
+impl Eq for Expr {}
+
+impl Hash for Expr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ty.hash(state);
+        self.kind.hash(state);
+        self.annotations.hash(state);
+    }
 }
 
 /// Iterator kinds in the Weld IR.
 ///
 /// An iterator defines how a for loop iterates over data.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IterKind {
     /// A standard scalar iterator.
     ScalarIter,
@@ -564,6 +748,12 @@ pub enum IterKind {
     NdIter,
     /// An interator over a finite integer range.
     RangeIter,
+    /// An iterator over data that the host provides in chunks.
+    ///
+    /// The iterator's `start` is the handle of a `ChunkSource` added to the context, and its data
+    /// is an empty vector that only records the element type. The loop requests chunks from the
+    /// source until it is exhausted, so the input never needs to be in memory all at once.
+    ChunkIter,
 }
 
 impl fmt::Display for IterKind {
@@ -573,8 +763,9 @@ impl fmt::Display for IterKind {
             ScalarIter => "",
             SimdIter => "simd",
             FringeIter => "fringe",
-            NdIter => "nditer",
+            NdIter => "nd",
             RangeIter => "range",
+            ChunkIter => "chunk",
         };
         f.write_str(text)?;
         f.write_str("iter")
@@ -584,6 +775,7 @@ impl fmt::Display for IterKind {
 /// An iterator, which specifies a vector to iterate over and optionally a start index,
 /// end index, and stride.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Iter {
     pub data: Box<Expr>,
     pub start: Option<Box<Expr>>,
@@ -613,6 +805,7 @@ impl Iter {
 /// sub-expressions, forming an expression tree. We use the term "expression" to refer to a
 /// particular `ExprKind`.
 #[derive(Clone, Debug, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExprKind {
     /// A literal expression.
     ///
@@ -656,6 +849,11 @@ pub enum ExprKind {
     Zip { vectors: Vec<Expr> },
     /// Access a struct field at the given index.
     GetField { expr: Box<Expr>, index: u32 },
+    /// Access a struct field by name.
+    ///
+    /// Type inference replaces this with a `GetField` once the type of `expr` is known, so later
+    /// stages of the compiler only see positional field accesses.
+    GetNamedField { expr: Box<Expr>, name: String },
     /// Get the length of a vector as an `i64`.
     Length { data: Box<Expr> },
     /// Lookup a value in a collection.
@@ -708,6 +906,15 @@ pub enum ExprKind {
         initial: Box<Expr>,
         update_func: Box<Expr>,
     },
+    /// Evaluate the branch of the case whose constant equals `value`, or `default` if no case
+    /// matches.
+    ///
+    /// `value` must be an integer, and each case constant is an integer literal of the same type.
+    Switch {
+        value: Box<Expr>,
+        cases: Vec<(LiteralKind, Expr)>,
+        default: Box<Expr>,
+    },
     /// Select `on_true` or `on_false` depending on `cond`.
     ///
     /// Both `on_true`and `on_false` are evaluated unconditionally.
@@ -775,6 +982,7 @@ impl ExprKind {
             MakeVector { .. } => "MakeVector",
             Zip { .. } => "Zip",
             GetField { .. } => "GetField",
+            GetNamedField { .. } => "GetNamedField",
             Length { .. } => "Length",
             Lookup { .. } => "Lookup",
             OptLookup { .. } => "OptLookup",
@@ -784,6 +992,7 @@ impl ExprKind {
             Let { .. } => "Let",
             If { .. } => "If",
             Iterate { .. } => "Iterate",
+            Switch { .. } => "Switch",
             Select { .. } => "Select",
             Lambda { .. } => "Lambda",
             Apply { .. } => "Apply",
@@ -804,10 +1013,47 @@ impl ExprKind {
             _ => false,
         }
     }
+
+    /// Returns `true` if evaluating the expression (excluding its children) may fail or have side
+    /// effects.
+    pub fn may_fail(&self) -> bool {
+        use self::LiteralKind::*;
+        match *self {
+            Lookup { .. } | Slice { .. } | Deserialize { .. } | Assert(_) | CUDF { .. } => true,
+            BinOp {
+                kind: Divide,
+                ref right,
+                ..
+            }
+            | BinOp {
+                kind: Modulo,
+                ref right,
+                ..
+            } => match right.kind {
+                Literal(ref lit) => matches!(
+                    *lit,
+                    I8Literal(0)
+                        | I16Literal(0)
+                        | I32Literal(0)
+                        | I64Literal(0)
+                        | U8Literal(0)
+                        | U16Literal(0)
+                        | U32Literal(0)
+                        | U64Literal(0)
+                ),
+                _ => match right.ty {
+                    Type::Scalar(s) | Type::Simd(s) => !s.is_float(),
+                    _ => true,
+                },
+            },
+            _ => false,
+        }
+    }
 }
 
 /// Literal types in the Weld IR.
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiteralKind {
     BoolLiteral(bool),
     I8Literal(i8),
@@ -832,10 +1078,10 @@ impl fmt::Display for LiteralKind {
             I16Literal(v) => format!("{}si", v),
             I32Literal(v) => format!("{}", v),
             I64Literal(v) => format!("{}L", v),
-            U8Literal(v) => format!("{}", v),
-            U16Literal(v) => format!("{}", v),
-            U32Literal(v) => format!("{}", v),
-            U64Literal(v) => format!("{}", v),
+            U8Literal(v) => format!("{}UC", v),
+            U16Literal(v) => format!("{}usi", v),
+            U32Literal(v) => format!("{}U", v),
+            U64Literal(v) => format!("{}UL", v),
             F32Literal(v) => {
                 let mut res = format!("{}", f32::from_bits(v));
                 // Hack to disambiguate from integers.
@@ -853,7 +1099,7 @@ impl fmt::Display for LiteralKind {
                 }
                 res
             }
-            StringLiteral(ref v) => format!("\"{}\"", v),
+            StringLiteral(ref v) => format!("\"{}\"", util::escape_string(v)),
         };
         f.write_str(text)
     }
@@ -861,6 +1107,7 @@ impl fmt::Display for LiteralKind {
 
 /// Binary operators over numerical values in the Weld IR.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinOpKind {
     Add,
     Subtract,
@@ -878,6 +1125,8 @@ pub enum BinOpKind {
     BitwiseAnd,
     BitwiseOr,
     Xor,
+    ShiftLeft,
+    ShiftRight,
     Max,
     Min,
     Pow,
@@ -892,6 +1141,10 @@ impl BinOpKind {
             _ => false,
         }
     }
+
+    pub fn is_shift(self) -> bool {
+        matches!(self, ShiftLeft | ShiftRight)
+    }
 }
 
 impl fmt::Display for BinOpKind {
@@ -913,6 +1166,8 @@ impl fmt::Display for BinOpKind {
             BitwiseAnd => "&",
             BitwiseOr => "|",
             Xor => "^",
+            ShiftLeft => "<<",
+            ShiftRight => ">>",
             Max => "max",
             Min => "min",
             Pow => "pow",
@@ -923,6 +1178,7 @@ impl fmt::Display for BinOpKind {
 
 /// Unary operators over numerical values in the Weld IR.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnaryOpKind {
     Exp,
     Log,
@@ -937,6 +1193,7 @@ pub enum UnaryOpKind {
     Cosh,
     Tanh,
     Erf,
+    Abs,
 }
 
 impl fmt::Display for UnaryOpKind {
@@ -950,6 +1207,7 @@ impl fmt::Display for UnaryOpKind {
 ///
 /// A parameter is a typed `Symbol`.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Parameter {
     pub name: Symbol,
     pub ty: Type,
@@ -961,285 +1219,384 @@ impl fmt::Display for Parameter {
     }
 }
 
+/// An iterator over the children of an `Expr`, returned by `Expr::children`.
+///
+/// Children are yielded in a fixed order: up to three leading fixed children, then any children
+/// stored in a list (struct elements, loop iterators, switch cases, etc.), and finally up to two
+/// trailing fixed children.
+#[derive(Default)]
+pub struct Children<'a> {
+    prefix: [Option<&'a Expr>; 3],
+    exprs: slice::Iter<'a, Expr>,
+    cases: slice::Iter<'a, (LiteralKind, Expr)>,
+    iters: slice::Iter<'a, Iter>,
+    iter_fields: [Option<&'a Expr>; 6],
+    suffix: [Option<&'a Expr>; 2],
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = &'a Expr;
+
+    fn next(&mut self) -> Option<&'a Expr> {
+        if let Some(e) = self.prefix.iter_mut().find_map(Option::take) {
+            return Some(e);
+        }
+        if let Some(e) = self.exprs.next() {
+            return Some(e);
+        }
+        if let Some(case) = self.cases.next() {
+            return Some(&case.1);
+        }
+        loop {
+            if let Some(e) = self.iter_fields.iter_mut().find_map(Option::take) {
+                return Some(e);
+            }
+            match self.iters.next() {
+                Some(iter) => {
+                    self.iter_fields = [
+                        Some(&iter.data),
+                        iter.start.as_deref(),
+                        iter.end.as_deref(),
+                        iter.stride.as_deref(),
+                        iter.shape.as_deref(),
+                        iter.strides.as_deref(),
+                    ]
+                }
+                None => break,
+            }
+        }
+        self.suffix.iter_mut().find_map(Option::take)
+    }
+}
+
+/// An iterator over mutable references to the children of an `Expr`, returned by
+/// `Expr::children_mut`.
+///
+/// Children are yielded in the same order as `Children`.
+#[derive(Default)]
+pub struct ChildrenMut<'a> {
+    prefix: [Option<&'a mut Expr>; 3],
+    exprs: slice::IterMut<'a, Expr>,
+    cases: slice::IterMut<'a, (LiteralKind, Expr)>,
+    iters: slice::IterMut<'a, Iter>,
+    iter_fields: [Option<&'a mut Expr>; 6],
+    suffix: [Option<&'a mut Expr>; 2],
+}
+
+impl<'a> Iterator for ChildrenMut<'a> {
+    type Item = &'a mut Expr;
+
+    fn next(&mut self) -> Option<&'a mut Expr> {
+        if let Some(e) = self.prefix.iter_mut().find_map(Option::take) {
+            return Some(e);
+        }
+        if let Some(e) = self.exprs.next() {
+            return Some(e);
+        }
+        if let Some(case) = self.cases.next() {
+            return Some(&mut case.1);
+        }
+        loop {
+            if let Some(e) = self.iter_fields.iter_mut().find_map(Option::take) {
+                return Some(e);
+            }
+            match self.iters.next() {
+                Some(Iter {
+                    data,
+                    start,
+                    end,
+                    stride,
+                    shape,
+                    strides,
+                    ..
+                }) => {
+                    self.iter_fields = [
+                        Some(data),
+                        start.as_deref_mut(),
+                        end.as_deref_mut(),
+                        stride.as_deref_mut(),
+                        shape.as_deref_mut(),
+                        strides.as_deref_mut(),
+                    ]
+                }
+                None => break,
+            }
+        }
+        self.suffix.iter_mut().find_map(Option::take)
+    }
+}
+
 impl Expr {
     /// Get an iterator for the children of this expression.
-    pub fn children(&self) -> vec::IntoIter<&Expr> {
+    ///
+    /// The iterator borrows the children in place and does not allocate.
+    pub fn children(&self) -> Children<'_> {
         use self::ExprKind::*;
+        let mut children = Children::default();
         match self.kind {
             BinOp {
                 ref left,
                 ref right,
                 ..
-            } => vec![left.as_ref(), right.as_ref()],
-            UnaryOp { ref value, .. } => vec![value.as_ref()],
-            Cast { ref child_expr, .. } => vec![child_expr.as_ref()],
-            ToVec { ref child_expr } => vec![child_expr.as_ref()],
+            } => children.prefix = [Some(left), Some(right), None],
+            UnaryOp { ref value, .. } => children.prefix[0] = Some(value),
+            Cast { ref child_expr, .. } => children.prefix[0] = Some(child_expr),
+            ToVec { ref child_expr } => children.prefix[0] = Some(child_expr),
             Let {
                 ref value,
                 ref body,
                 ..
-            } => vec![value.as_ref(), body.as_ref()],
-            Lambda { ref body, .. } => vec![body.as_ref()],
-            MakeStruct { ref elems } => elems.iter().collect(),
-            MakeVector { ref elems } => elems.iter().collect(),
-            Zip { ref vectors } => vectors.iter().collect(),
-            GetField { ref expr, .. } => vec![expr.as_ref()],
-            Length { ref data } => vec![data.as_ref()],
+            } => children.prefix = [Some(value), Some(body), None],
+            Lambda { ref body, .. } => children.prefix[0] = Some(body),
+            MakeStruct { ref elems } => children.exprs = elems.iter(),
+            MakeVector { ref elems } => children.exprs = elems.iter(),
+            Zip { ref vectors } => children.exprs = vectors.iter(),
+            GetField { ref expr, .. } | GetNamedField { ref expr, .. } => {
+                children.prefix[0] = Some(expr)
+            }
+            Length { ref data } => children.prefix[0] = Some(data),
             Lookup {
                 ref data,
                 ref index,
-            } => vec![data.as_ref(), index.as_ref()],
+            } => children.prefix = [Some(data), Some(index), None],
             OptLookup {
                 ref data,
                 ref index,
-            } => vec![data.as_ref(), index.as_ref()],
-            KeyExists { ref data, ref key } => vec![data.as_ref(), key.as_ref()],
+            } => children.prefix = [Some(data), Some(index), None],
+            KeyExists { ref data, ref key } => children.prefix = [Some(data), Some(key), None],
             Slice {
                 ref data,
                 ref index,
                 ref size,
-            } => vec![data.as_ref(), index.as_ref(), size.as_ref()],
+            } => children.prefix = [Some(data), Some(index), Some(size)],
             Sort {
                 ref data,
                 ref cmpfunc,
-            } => vec![data.as_ref(), cmpfunc.as_ref()],
+            } => children.prefix = [Some(data), Some(cmpfunc), None],
             Merge {
                 ref builder,
                 ref value,
-            } => vec![builder.as_ref(), value.as_ref()],
-            Res { ref builder } => vec![builder.as_ref()],
+            } => children.prefix = [Some(builder), Some(value), None],
+            Res { ref builder } => children.prefix[0] = Some(builder),
             For {
                 ref iters,
                 ref builder,
                 ref func,
             } => {
-                let mut res: Vec<&Expr> = vec![];
-                for iter in iters {
-                    res.push(iter.data.as_ref());
-                    if let Some(ref s) = iter.start {
-                        res.push(s);
-                    }
-                    if let Some(ref e) = iter.end {
-                        res.push(e);
-                    }
-                    if let Some(ref s) = iter.stride {
-                        res.push(s);
-                    }
-                }
-                res.push(builder.as_ref());
-                res.push(func.as_ref());
-                res
+                children.iters = iters.iter();
+                children.suffix = [Some(builder), Some(func)];
             }
             If {
                 ref cond,
                 ref on_true,
                 ref on_false,
-            } => vec![cond.as_ref(), on_true.as_ref(), on_false.as_ref()],
+            } => children.prefix = [Some(cond), Some(on_true), Some(on_false)],
             Iterate {
                 ref initial,
                 ref update_func,
-            } => vec![initial.as_ref(), update_func.as_ref()],
+            } => children.prefix = [Some(initial), Some(update_func), None],
+            Switch {
+                ref value,
+                ref cases,
+                ref default,
+            } => {
+                children.prefix[0] = Some(value);
+                children.cases = cases.iter();
+                children.suffix[0] = Some(default);
+            }
             Select {
                 ref cond,
                 ref on_true,
                 ref on_false,
-            } => vec![cond.as_ref(), on_true.as_ref(), on_false.as_ref()],
+            } => children.prefix = [Some(cond), Some(on_true), Some(on_false)],
             Apply {
                 ref func,
                 ref params,
             } => {
-                let mut res = vec![func.as_ref()];
-                res.extend(params.iter());
-                res
-            }
-            NewBuilder(ref opt) => {
-                if let Some(ref e) = *opt {
-                    vec![e.as_ref()]
-                } else {
-                    vec![]
-                }
+                children.prefix[0] = Some(func);
+                children.exprs = params.iter();
             }
-            Serialize(ref e) => vec![e.as_ref()],
-            Deserialize { ref value, .. } => vec![value.as_ref()],
-            CUDF { ref args, .. } => args.iter().collect(),
-            Negate(ref t) => vec![t.as_ref()],
-            Not(ref t) => vec![t.as_ref()],
-            Assert(ref t) => vec![t.as_ref()],
-            Broadcast(ref t) => vec![t.as_ref()],
+            NewBuilder(ref opt) => children.prefix[0] = opt.as_deref(),
+            Serialize(ref e) => children.prefix[0] = Some(e),
+            Deserialize { ref value, .. } => children.prefix[0] = Some(value),
+            CUDF { ref args, .. } => children.exprs = args.iter(),
+            Negate(ref t) => children.prefix[0] = Some(t),
+            Not(ref t) => children.prefix[0] = Some(t),
+            Assert(ref t) => children.prefix[0] = Some(t),
+            Broadcast(ref t) => children.prefix[0] = Some(t),
             // Explicitly list types instead of doing _ => ... to remember to add new types.
-            Literal(_) | Ident(_) => vec![],
+            Literal(_) | Ident(_) => (),
         }
-        .into_iter()
+        children
     }
 
     /// Get an iterator of mutable references to the children of this expression.
-    pub fn children_mut(&mut self) -> vec::IntoIter<&mut Expr> {
+    ///
+    /// The iterator borrows the children in place and does not allocate.
+    pub fn children_mut(&mut self) -> ChildrenMut<'_> {
         use self::ExprKind::*;
+        let mut children = ChildrenMut::default();
         match self.kind {
             BinOp {
                 ref mut left,
                 ref mut right,
                 ..
-            } => vec![left.as_mut(), right.as_mut()],
-            UnaryOp { ref mut value, .. } => vec![value.as_mut()],
+            } => children.prefix = [Some(left), Some(right), None],
+            UnaryOp { ref mut value, .. } => children.prefix[0] = Some(value),
             Cast {
                 ref mut child_expr, ..
-            } => vec![child_expr.as_mut()],
-            ToVec { ref mut child_expr } => vec![child_expr.as_mut()],
+            } => children.prefix[0] = Some(child_expr),
+            ToVec { ref mut child_expr } => children.prefix[0] = Some(child_expr),
             Let {
                 ref mut value,
                 ref mut body,
                 ..
-            } => vec![value.as_mut(), body.as_mut()],
-            Lambda { ref mut body, .. } => vec![body.as_mut()],
-            MakeStruct { ref mut elems } => elems.iter_mut().collect(),
-            MakeVector { ref mut elems } => elems.iter_mut().collect(),
-            Zip { ref mut vectors } => vectors.iter_mut().collect(),
-            GetField { ref mut expr, .. } => vec![expr.as_mut()],
-            Length { ref mut data } => vec![data.as_mut()],
+            } => children.prefix = [Some(value), Some(body), None],
+            Lambda { ref mut body, .. } => children.prefix[0] = Some(body),
+            MakeStruct { ref mut elems } => children.exprs = elems.iter_mut(),
+            MakeVector { ref mut elems } => children.exprs = elems.iter_mut(),
+            Zip { ref mut vectors } => children.exprs = vectors.iter_mut(),
+            GetField { ref mut expr, .. } | GetNamedField { ref mut expr, .. } => {
+                children.prefix[0] = Some(expr)
+            }
+            Length { ref mut data } => children.prefix[0] = Some(data),
             Lookup {
                 ref mut data,
                 ref mut index,
-            } => vec![data.as_mut(), index.as_mut()],
+            } => children.prefix = [Some(data), Some(index), None],
             OptLookup {
                 ref mut data,
                 ref mut index,
-            } => vec![data.as_mut(), index.as_mut()],
+            } => children.prefix = [Some(data), Some(index), None],
             KeyExists {
                 ref mut data,
                 ref mut key,
-            } => vec![data.as_mut(), key.as_mut()],
+            } => children.prefix = [Some(data), Some(key), None],
             Slice {
                 ref mut data,
                 ref mut index,
                 ref mut size,
-            } => vec![data.as_mut(), index.as_mut(), size.as_mut()],
+            } => children.prefix = [Some(data), Some(index), Some(size)],
             Sort {
                 ref mut data,
                 ref mut cmpfunc,
-            } => vec![data.as_mut(), cmpfunc.as_mut()],
+            } => children.prefix = [Some(data), Some(cmpfunc), None],
             Merge {
                 ref mut builder,
                 ref mut value,
-            } => vec![builder.as_mut(), value.as_mut()],
-            Res { ref mut builder } => vec![builder.as_mut()],
+            } => children.prefix = [Some(builder), Some(value), None],
+            Res { ref mut builder } => children.prefix[0] = Some(builder),
             For {
                 ref mut iters,
                 ref mut builder,
                 ref mut func,
             } => {
-                let mut res: Vec<&mut Expr> = vec![];
-                for iter in iters {
-                    res.push(iter.data.as_mut());
-                    if let Some(ref mut s) = iter.start {
-                        res.push(s);
-                    }
-                    if let Some(ref mut e) = iter.end {
-                        res.push(e);
-                    }
-                    if let Some(ref mut s) = iter.stride {
-                        res.push(s);
-                    }
-                }
-                res.push(builder.as_mut());
-                res.push(func.as_mut());
-                res
+                children.iters = iters.iter_mut();
+                children.suffix = [Some(builder), Some(func)];
             }
             If {
                 ref mut cond,
                 ref mut on_true,
                 ref mut on_false,
-            } => vec![cond.as_mut(), on_true.as_mut(), on_false.as_mut()],
+            } => children.prefix = [Some(cond), Some(on_true), Some(on_false)],
             Iterate {
                 ref mut initial,
                 ref mut update_func,
-            } => vec![initial.as_mut(), update_func.as_mut()],
+            } => children.prefix = [Some(initial), Some(update_func), None],
+            Switch {
+                ref mut value,
+                ref mut cases,
+                ref mut default,
+            } => {
+                children.prefix[0] = Some(value);
+                children.cases = cases.iter_mut();
+                children.suffix[0] = Some(default);
+            }
             Select {
                 ref mut cond,
                 ref mut on_true,
                 ref mut on_false,
-            } => vec![cond.as_mut(), on_true.as_mut(), on_false.as_mut()],
-
+            } => children.prefix = [Some(cond), Some(on_true), Some(on_false)],
             Apply {
                 ref mut func,
                 ref mut params,
             } => {
-                let mut res = vec![func.as_mut()];
-                res.extend(params.iter_mut());
-                res
-            }
-            NewBuilder(ref mut opt) => {
-                if let Some(ref mut e) = *opt {
-                    vec![e.as_mut()]
-                } else {
-                    vec![]
-                }
+                children.prefix[0] = Some(func);
+                children.exprs = params.iter_mut();
             }
-            Serialize(ref mut e) => vec![e.as_mut()],
-            Deserialize { ref mut value, .. } => vec![value.as_mut()],
-            CUDF { ref mut args, .. } => args.iter_mut().collect(),
-            Negate(ref mut t) => vec![t.as_mut()],
-            Not(ref mut t) => vec![t.as_mut()],
-            Assert(ref mut t) => vec![t.as_mut()],
-            Broadcast(ref mut t) => vec![t.as_mut()],
+            NewBuilder(ref mut opt) => children.prefix[0] = opt.as_deref_mut(),
+            Serialize(ref mut e) => children.prefix[0] = Some(e),
+            Deserialize { ref mut value, .. } => children.prefix[0] = Some(value),
+            CUDF { ref mut args, .. } => children.exprs = args.iter_mut(),
+            Negate(ref mut t) => children.prefix[0] = Some(t),
+            Not(ref mut t) => children.prefix[0] = Some(t),
+            Assert(ref mut t) => children.prefix[0] = Some(t),
+            Broadcast(ref mut t) => children.prefix[0] = Some(t),
             // Explicitly list types instead of doing _ => ... to remember to add new types.
-            Literal(_) | Ident(_) => vec![],
+            Literal(_) | Ident(_) => (),
         }
-        .into_iter()
+        children
     }
 
     /// Returns whether this `Expr` is partially typed.
     ///
-    /// A type is partial if it or any of its subtypes is `Unknown`.
+    /// An expression is partially typed if its type or the type of any of its subexpressions is
+    /// partial. A type is partial if it or any of its subtypes is `Unknown`.
     pub fn partially_typed(&self) -> bool {
-        use self::Type::Unknown;
-        match self.ty {
-            Unknown => true,
-            _ => self.children().any(|e| e.ty.partial_type()),
-        }
+        self.find_partially_typed().is_some()
+    }
+
+    /// Returns the innermost subexpression of this `Expr` whose type is partial, if any.
+    ///
+    /// Subexpressions are searched in post-order, so the first partially typed expression is the
+    /// one whose type could not be inferred rather than an expression that depends on it.
+    pub fn find_partially_typed(&self) -> Option<&Expr> {
+        self.children()
+            .find_map(|e| e.find_partially_typed())
+            .or_else(|| {
+                if self.ty.partial_type() {
+                    Some(self)
+                } else {
+                    None
+                }
+            })
     }
 
     /// Substitute Ident nodes with the given symbol for another expression, stopping when an
     /// expression in the tree redefines the symbol (e.g. Let or Lambda parameters).
     pub fn substitute(&mut self, symbol: &Symbol, replacement: &Expr) {
-        // Replace ourselves if we are exactly the symbol.
         use self::ExprKind::*;
-        let mut self_matches = false;
-        match self.kind {
-            Ident(ref sym) if *sym == *symbol => self_matches = true,
-            _ => (),
-        }
-        if self_matches {
-            *self = (*replacement).clone();
-            return;
-        }
-
-        // Otherwise, replace any relevant children, unless we redefine the symbol.
-        match self.kind {
-            Let {
-                ref name,
-                ref mut value,
-                ref mut body,
-            } => {
-                value.substitute(symbol, replacement);
-                if name != symbol {
-                    body.substitute(symbol, replacement);
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            // Replace the expression if it is exactly the symbol.
+            if let Ident(ref sym) = e.kind {
+                if *sym == *symbol {
+                    *e = (*replacement).clone();
                 }
+                continue;
             }
 
-            Lambda {
-                ref params,
-                ref mut body,
-            } => {
-                if params.iter().all(|p| p.name != *symbol) {
-                    body.substitute(symbol, replacement);
+            // Otherwise, replace any relevant children, unless the expression redefines the symbol.
+            match e.kind {
+                Let {
+                    ref name,
+                    ref mut value,
+                    ref mut body,
+                } => {
+                    if name != symbol {
+                        stack.push(body);
+                    }
+                    stack.push(value);
                 }
-            }
-
-            _ => {
-                for c in self.children_mut() {
-                    c.substitute(symbol, replacement);
+                Lambda {
+                    ref params,
+                    ref mut body,
+                } => {
+                    if params.iter().all(|p| p.name != *symbol) {
+                        stack.push(body);
+                    }
                 }
+                _ => stack.extend(e.children_mut()),
             }
         }
     }
@@ -1249,12 +1606,71 @@ impl Expr {
     where
         F: FnMut(&Expr) -> (),
     {
-        func(self);
-        for c in self.children() {
-            c.traverse(func);
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            func(e);
+            push_in_order(&mut stack, e.children());
         }
     }
 
+    /// Run a closure on this expression and every child, in pre-order, allowing mutation.
+    pub fn traverse_mut<F>(&mut self, func: &mut F)
+    where
+        F: FnMut(&mut Expr),
+    {
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            func(e);
+            push_in_order(&mut stack, e.children_mut());
+        }
+    }
+
+    /// Run a closure on this expression and every child, in post-order.
+    pub fn traverse_post<F>(&self, func: &mut F)
+    where
+        F: FnMut(&Expr),
+    {
+        // Each expression is pushed twice: once to push its children, and once to visit it after
+        // its children.
+        let mut stack = vec![(self, false)];
+        while let Some((e, children_visited)) = stack.pop() {
+            if children_visited {
+                func(e);
+            } else {
+                stack.push((e, true));
+                push_in_order(&mut stack, e.children().map(|c| (c, false)));
+            }
+        }
+    }
+
+    /// Run a closure on this expression and every child, in breadth-first order.
+    pub fn traverse_bfs<F>(&self, func: &mut F)
+    where
+        F: FnMut(&Expr),
+    {
+        let mut queue = VecDeque::new();
+        queue.push_back(self);
+        while let Some(e) = queue.pop_front() {
+            func(e);
+            queue.extend(e.children());
+        }
+    }
+
+    /// Run a closure on this expression and every child in post-order, allowing mutation and
+    /// threading an accumulator through each call.
+    ///
+    /// Returns the accumulator produced by the last call, i.e., the call on this expression.
+    pub fn fold_post_mut<T, F>(&mut self, init: T, func: &mut F) -> T
+    where
+        F: FnMut(&mut Expr, T) -> T,
+    {
+        let mut acc = init;
+        for c in self.children_mut() {
+            acc = c.fold_post_mut(acc, func);
+        }
+        func(self, acc)
+    }
+
     /// Returns `true` if this expression contains the symbol `sym` in an `Ident`.
     pub fn contains_symbol(&self, sym: &Symbol) -> bool {
         let mut found = false;
@@ -1268,51 +1684,212 @@ impl Expr {
         found
     }
 
+    /// Returns the symbols this expression references but does not define.
+    ///
+    /// A symbol defined by a `Let` is bound only in the `Let`'s body, and lambda parameters are
+    /// bound only in the lambda's body.
+    pub fn free_variables(&self) -> FnvHashSet<Symbol> {
+        let mut free = FnvHashSet::default();
+        self.collect_free_variables(&mut vec![], &mut free);
+        free
+    }
+
+    fn collect_free_variables(&self, bound: &mut Vec<Symbol>, free: &mut FnvHashSet<Symbol>) {
+        match self.kind {
+            Ident(ref sym) => {
+                if !bound.contains(sym) {
+                    free.insert(sym.clone());
+                }
+            }
+            Let {
+                ref name,
+                ref value,
+                ref body,
+            } => {
+                value.collect_free_variables(bound, free);
+                bound.push(name.clone());
+                body.collect_free_variables(bound, free);
+                bound.pop();
+            }
+            Lambda {
+                ref params,
+                ref body,
+            } => {
+                let depth = bound.len();
+                bound.extend(params.iter().map(|p| p.name.clone()));
+                body.collect_free_variables(bound, free);
+                bound.truncate(depth);
+            }
+            _ => {
+                for c in self.children() {
+                    c.collect_free_variables(bound, free);
+                }
+            }
+        }
+    }
+
+    /// Returns the symbols defined anywhere within this expression by a `Let` or as a lambda
+    /// parameter.
+    pub fn defined_in_scope(&self) -> FnvHashSet<Symbol> {
+        let mut defined = FnvHashSet::default();
+        self.traverse(&mut |e| match e.kind {
+            Let { ref name, .. } => {
+                defined.insert(name.clone());
+            }
+            Lambda { ref params, .. } => {
+                defined.extend(params.iter().map(|p| p.name.clone()));
+            }
+            _ => (),
+        });
+        defined
+    }
+
+    /// Gives every expression in this tree that has no node ID a new one, in pre-order.
+    ///
+    /// New IDs are larger than every ID already in the tree, so the IDs of existing expressions
+    /// do not change.
+    pub fn assign_node_ids(&mut self) {
+        let mut next = 0;
+        self.traverse(&mut |e| {
+            if let Some(NodeId(id)) = e.id {
+                next = cmp::max(next, id + 1);
+            }
+        });
+        self.traverse_mut(&mut |e| {
+            if e.id.is_none() {
+                e.id = Some(NodeId(next));
+                next += 1;
+            }
+        });
+    }
+
+    /// Returns the number of expression nodes in this tree, including this one.
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        self.traverse(&mut |_| count += 1);
+        count
+    }
+
+    /// Returns the number of operations in this tree, which are the nodes other than literals and
+    /// identifiers.
+    ///
+    /// Unlike `cost`, each operation counts once, even in a function that runs repeatedly, so the
+    /// count can be compared against a fixed budget. For an expression containing loops, it is a
+    /// lower bound on the operations one evaluation runs.
+    pub fn operation_count(&self) -> usize {
+        let mut count = 0;
+        self.traverse(&mut |e| {
+            if !matches!(e.kind, Literal(_) | Ident(_)) {
+                count += 1;
+            }
+        });
+        count
+    }
+
+    /// Returns the height of this tree. A leaf expression has depth 1.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((e, d)) = stack.pop() {
+            depth = cmp::max(depth, d);
+            stack.extend(e.children().map(|c| (c, d + 1)));
+        }
+        depth
+    }
+
+    /// Returns a rough estimate of the work this expression performs.
+    ///
+    /// Each node other than a literal or identifier costs one unit. Functions that may run
+    /// repeatedly (loop bodies, `iterate` updates, and sort comparators) are charged as if they
+    /// run `LOOP_COST_FACTOR` times, so nested loops compound. The estimate is only meaningful for
+    /// comparing two versions of a program, e.g., before and after a rewrite.
+    pub fn cost(&self) -> usize {
+        let own = match self.kind {
+            Literal(_) | Ident(_) => 0,
+            _ => 1,
+        };
+        let children: usize = match self.kind {
+            For {
+                ref iters,
+                ref builder,
+                ref func,
+            } => {
+                let iters: usize = iters
+                    .iter()
+                    .flat_map(|iter| {
+                        vec![
+                            Some(&iter.data),
+                            iter.start.as_ref(),
+                            iter.end.as_ref(),
+                            iter.stride.as_ref(),
+                            iter.shape.as_ref(),
+                            iter.strides.as_ref(),
+                        ]
+                    })
+                    .flatten()
+                    .map(|e| e.cost())
+                    .sum();
+                iters + builder.cost() + LOOP_COST_FACTOR * func.cost()
+            }
+            Iterate {
+                ref initial,
+                ref update_func,
+            } => initial.cost() + LOOP_COST_FACTOR * update_func.cost(),
+            Sort {
+                ref data,
+                ref cmpfunc,
+            } => data.cost() + LOOP_COST_FACTOR * cmpfunc.cost(),
+            _ => self.children().map(Expr::cost).sum(),
+        };
+        own + children
+    }
+
     /// Recursively transforms an expression in place by running a function on it and optionally replacing it with another expression.
+    ///
+    /// The function returns an optional replacement and whether to continue into the result:
+    ///
+    /// * `(Some(e), true)` replaces this expression with `e` and runs the transform on `e`.
+    /// * `(Some(e), false)` replaces this expression with `e` and does not visit `e` or its
+    ///   children. This prevents the transform from descending into subtrees it just built.
+    /// * `(None, true)` keeps this expression and transforms its children.
+    /// * `(None, false)` keeps this expression and skips its children.
     pub fn transform_and_continue<F>(&mut self, func: &mut F)
     where
         F: FnMut(&mut Expr) -> (Option<Expr>, bool),
     {
-        match func(self) {
-            (Some(e), true) => {
-                *self = e;
-                self.transform_and_continue(func)
-            }
-            (Some(e), false) => {
-                *self = e;
-            }
-            (None, true) => {
-                for c in self.children_mut() {
-                    c.transform_and_continue(func);
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            match func(e) {
+                (Some(replacement), true) => {
+                    e.replace(replacement);
+                    stack.push(e);
                 }
+                (Some(replacement), false) => e.replace(replacement),
+                (None, true) => push_in_order(&mut stack, e.children_mut()),
+                (None, false) => (),
             }
-            (None, false) => {}
         }
     }
 
-    /// Recursively transforms an expression in place by running a function on it and optionally replacing it with another expression.
-    /// Supports returning an error, which is treated as returning (None, false)
+    /// Like `transform_and_continue`, but the function may return an error, which is treated as
+    /// returning `(None, false)`.
     pub fn transform_and_continue_res<F>(&mut self, func: &mut F)
     where
         F: FnMut(&mut Expr) -> WeldResult<(Option<Expr>, bool)>,
     {
-        if let Ok(result) = func(self) {
-            match result {
-                (Some(e), true) => {
-                    *self = e;
-                    return self.transform_and_continue_res(func);
-                }
-                (Some(e), false) => {
-                    *self = e;
-                }
-                (None, true) => {
-                    for c in self.children_mut() {
-                        c.transform_and_continue_res(func);
-                    }
-                }
-                (None, false) => {}
-            }
+        self.transform_and_continue(&mut |e| func(e).unwrap_or((None, false)))
+    }
+
+    /// Replaces this expression with `other`, keeping this expression's span and node ID if
+    /// `other` has none.
+    fn replace(&mut self, mut other: Expr) {
+        if other.span.is_none() {
+            other.span = self.span;
+        }
+        if other.id.is_none() {
+            other.id = self.id;
         }
+        *self = other;
     }
 
     /// Recursively transforms an expression in place by running a function on it and optionally replacing it with another expression.
@@ -1320,13 +1897,7 @@ impl Expr {
     where
         F: FnMut(&mut Expr) -> Option<Expr>,
     {
-        if let Some(e) = func(self) {
-            *self = e;
-            return self.transform(func);
-        }
-        for c in self.children_mut() {
-            c.transform(func);
-        }
+        self.transform_and_continue(&mut |e| (func(e), true))
     }
 
     /// Recursively transforms an expression in place by running a function first on its children, then on the root
@@ -1339,7 +1910,7 @@ impl Expr {
             c.transform_up(func);
         }
         if let Some(e) = func(self) {
-            *self = e;
+            self.replace(e);
         }
     }
 
@@ -1350,35 +1921,42 @@ impl Expr {
     where
         F: FnMut(&mut Expr) -> Option<ExprKind>,
     {
-        if let Some(k) = func(self) {
-            self.kind = k;
-            return self.transform_kind(func);
-        }
-        for c in self.children_mut() {
-            c.transform_kind(func);
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            if let Some(kind) = func(e) {
+                e.kind = kind;
+                stack.push(e);
+            } else {
+                push_in_order(&mut stack, e.children_mut());
+            }
         }
     }
 
-    /// Returns true if this expressions contains `other`.
+    /// Returns true if this expression contains `other`.
     pub fn contains(&self, other: &Expr) -> bool {
-        if *self == *other {
-            return true;
-        }
-        for c in other.children() {
-            if self.contains(c) {
-                return true;
-            }
-        }
-        false
+        *self == *other || self.children().any(|c| c.contains(other))
     }
 }
 
+/// Pushes `items` onto `stack` so that they are popped in their original order.
+///
+/// The traversals over expressions use an explicit stack rather than recursion, so that deeply
+/// nested programs (e.g., generated programs with thousands of nested `Let`s) do not overflow the
+/// thread's stack.
+fn push_in_order<T>(stack: &mut Vec<T>, items: impl Iterator<Item = T>) {
+    let start = stack.len();
+    stack.extend(items);
+    stack[start..].reverse();
+}
+
 /// Create a box containing an untyped expression of the given kind.
 pub fn expr_box(kind: ExprKind, annot: Annotations) -> Box<Expr> {
     Box::new(Expr {
         ty: Type::Unknown,
         kind,
         annotations: annot,
+        span: None,
+        id: None,
     })
 }
 
@@ -1404,6 +1982,8 @@ impl Placeholder for Expr {
             ty: Type::Unknown,
             kind: Ident(Symbol::placeholder()),
             annotations: Annotations::new(),
+            span: None,
+            id: None,
         }
     }
 }
@@ -1418,6 +1998,8 @@ impl Placeholder for Box<Expr> {
             ty: Type::Unknown,
             kind: Ident(Symbol::placeholder()),
             annotations: Annotations::new(),
+            span: None,
+            id: None,
         })
     }
 }
@@ -1446,3 +2028,235 @@ impl Takeable for Box<Expr> {
         new
     }
 }
+
+#[test]
+fn free_variables() {
+    use crate::tests::*;
+    let names = |syms: FnvHashSet<Symbol>| {
+        let mut names: Vec<_> = syms.iter().map(|s| s.to_string()).collect();
+        names.sort();
+        names
+    };
+
+    let e = parse_expr("let a = b + 1; a + c").unwrap();
+    assert_eq!(names(e.free_variables()), ["b", "c"]);
+
+    // A Let's symbol is not bound in its own value.
+    let e = parse_expr("let a = a + 1; a").unwrap();
+    assert_eq!(names(e.free_variables()), ["a"]);
+
+    // Lambda parameters are bound only in the body.
+    let e = parse_expr("(|x:i32| x + y)(x)").unwrap();
+    assert_eq!(names(e.free_variables()), ["x", "y"]);
+
+    let e = parse_expr("for(v, appender[i32], |b,i,e| merge(b, e + k))").unwrap();
+    assert_eq!(names(e.free_variables()), ["k", "v"]);
+    assert_eq!(names(e.defined_in_scope()), ["b", "e", "i"]);
+
+    let e = parse_expr("let a = 1; |x:i32| let y = x; y + z").unwrap();
+    assert_eq!(names(e.free_variables()), ["z"]);
+    assert_eq!(names(e.defined_in_scope()), ["a", "x", "y"]);
+}
+
+#[test]
+fn size_metrics() {
+    use crate::tests::*;
+    let e = parse_expr("1 + 2 * 3").unwrap();
+    assert_eq!(e.node_count(), 5);
+    assert_eq!(e.operation_count(), 2);
+    assert_eq!(e.depth(), 3);
+    assert_eq!(e.cost(), 2);
+
+    let e = parse_expr("let a = 1; a").unwrap();
+    assert_eq!(e.node_count(), 3);
+    assert_eq!(e.depth(), 2);
+    assert_eq!(e.cost(), 1);
+
+    // The For and NewBuilder cost one unit each, and the lambda and its merge are charged for
+    // every assumed iteration.
+    let e = parse_expr("for(v, appender[i32], |b,i,e| merge(b, e))").unwrap();
+    assert_eq!(e.node_count(), 7);
+    assert_eq!(e.operation_count(), 4);
+    assert_eq!(e.depth(), 4);
+    assert_eq!(e.cost(), 2 + LOOP_COST_FACTOR * 2);
+
+    let nested =
+        parse_expr("for(v, appender[i32], |b,i,e| for(v, b, |b2,i2,e2| merge(b2, e2)))").unwrap();
+    assert!(nested.cost() > LOOP_COST_FACTOR * e.cost());
+}
+
+#[test]
+fn children_order() {
+    use crate::tests::*;
+    let names = |e: &Expr| {
+        e.children()
+            .map(print_expr_without_indent)
+            .collect::<Vec<_>>()
+    };
+
+    let e = parse_expr("for(iter(v, 0L, 4L, 2L), appender[i32], |b,i,e| merge(b, e))").unwrap();
+    assert_eq!(
+        names(&e),
+        [
+            "v",
+            "0L",
+            "4L",
+            "2L",
+            "appender[i32]",
+            "|b:?,i:?,e:?|merge(b,e)"
+        ]
+    );
+
+    let e = parse_expr("switch(x, 1: a, 2: b, c)").unwrap();
+    assert_eq!(names(&e), ["x", "a", "b", "c"]);
+
+    // Mutable iteration visits the same children.
+    let mut e = parse_expr("f(x, y, z)").unwrap();
+    let expected = names(&e);
+    let visited: Vec<_> = e
+        .children_mut()
+        .map(|c| print_expr_without_indent(c))
+        .collect();
+    assert_eq!(visited, expected);
+}
+
+#[test]
+fn traversal_orders() {
+    use crate::tests::*;
+    let mut e = parse_expr("(1 + 2) * (3 - 4)").unwrap();
+
+    let mut order = vec![];
+    e.traverse(&mut |e| order.push(print_expr_without_indent(e)));
+    assert_eq!(
+        order,
+        ["((1+2)*(3-4))", "(1+2)", "1", "2", "(3-4)", "3", "4"]
+    );
+
+    let mut order = vec![];
+    e.traverse_post(&mut |e| order.push(print_expr_without_indent(e)));
+    assert_eq!(
+        order,
+        ["1", "2", "(1+2)", "3", "4", "(3-4)", "((1+2)*(3-4))"]
+    );
+
+    let mut order = vec![];
+    e.traverse_bfs(&mut |e| order.push(print_expr_without_indent(e)));
+    assert_eq!(
+        order,
+        ["((1+2)*(3-4))", "(1+2)", "(3-4)", "1", "2", "3", "4"]
+    );
+
+    // Replace each literal with the number of literals seen before it.
+    let count = e.fold_post_mut(0, &mut |e, count| {
+        if let Literal(_) = e.kind {
+            e.kind = Literal(LiteralKind::I32Literal(count));
+            count + 1
+        } else {
+            count
+        }
+    });
+    assert_eq!(count, 4);
+    assert_eq!(print_expr_without_indent(&e), "((0+1)*(2-3))");
+}
+
+#[test]
+fn transform_and_continue_controls_children() {
+    use crate::ast::NewExpr;
+    use crate::tests::*;
+    let one = || Expr::new_literal(LiteralKind::I32Literal(1)).unwrap();
+    let negate = |e: &Expr| Expr::new_negate(e.clone()).unwrap();
+
+    // Wrap literals in a negation without revisiting the new node, which would loop forever.
+    let mut e = parse_expr("1 + 2").unwrap();
+    e.transform_and_continue(&mut |e| match e.kind {
+        Literal(_) => (Some(negate(e)), false),
+        _ => (None, true),
+    });
+    assert_eq!(print_expr_without_indent(&e), "((-1)+(-2))");
+
+    // Skipping children leaves the subtree untouched.
+    let mut e = parse_expr("(3 + 4) * 5").unwrap();
+    e.transform_and_continue(&mut |e| match e.kind {
+        BinOp {
+            kind: BinOpKind::Add,
+            ..
+        } => (None, false),
+        Literal(_) => (Some(one()), false),
+        _ => (None, true),
+    });
+    assert_eq!(print_expr_without_indent(&e), "((3+4)*1)");
+
+    // Continuing on a replacement runs the transform on the new node.
+    let mut e = parse_expr("--2").unwrap();
+    e.transform_and_continue(&mut |e| match e.kind {
+        Negate(ref inner) => (Some(inner.as_ref().clone()), true),
+        _ => (None, true),
+    });
+    assert_eq!(print_expr_without_indent(&e), "2");
+}
+
+#[test]
+fn contains_subexpression() {
+    use crate::tests::*;
+    let e = parse_expr("(a + 1) * b").unwrap();
+    let sub = parse_expr("a + 1").unwrap();
+    assert!(e.contains(&sub));
+    assert!(e.contains(&e));
+    assert!(!sub.contains(&e));
+    assert!(!e.contains(&parse_expr("a + 2").unwrap()));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    use crate::ast::InferTypes;
+    use crate::tests::*;
+    let mut e = parse_expr(
+        "|v:vec[i32], x:i32| let y = x + 1; result(for(v, appender[i32], |b,i,e| merge(b, e + y)))",
+    )
+    .unwrap();
+    e.infer_types().unwrap();
+
+    let json = serde_json::to_string(&e).unwrap();
+    let decoded: Expr = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, e);
+    assert_eq!(decoded.ty, e.ty);
+    assert_eq!(decoded.span, e.span);
+    assert_eq!(
+        print_typed_expr_without_indent(&decoded),
+        print_typed_expr_without_indent(&e)
+    );
+}
+
+#[test]
+fn builder_kind_types() {
+    use crate::syntax::parser::parse_type;
+    let kinds = [
+        ("appender[i32]", "i32", "vec[i32]"),
+        ("merger[f64,+]", "f64", "f64"),
+        ("dictmerger[i32,f32,+]", "{i32,f32}", "dict[i32,f32]"),
+        ("groupmerger[i32,i8]", "{i32,i8}", "dict[i32,vec[i8]]"),
+        ("vecmerger[i64,*]", "{i64,i64}", "vec[i64]"),
+    ];
+    for &(builder, merge, result) in kinds.iter() {
+        let ty = parse_type(builder).unwrap();
+        let mut kind = match ty {
+            Type::Builder(kind, _) => kind,
+            _ => unreachable!(),
+        };
+        assert_eq!(kind.merge_type().to_string(), merge);
+        assert_eq!(kind.result_type().to_string(), result);
+
+        // Setting the merge type is the inverse of reading it.
+        let merge_type = kind.merge_type();
+        let expected = kind.clone();
+        kind.set_merge_type(merge_type).unwrap();
+        assert_eq!(kind, expected);
+    }
+
+    let mut kind = BuilderKind::VecMerger(Box::new(Type::Unknown), BinOpKind::Add);
+    let merge_type = parse_type("{i64,f32}").unwrap();
+    kind.set_merge_type(merge_type).unwrap();
+    assert_eq!(kind.result_type().to_string(), "vec[f32]");
+    assert!(kind.set_merge_type(Type::Scalar(ScalarKind::I32)).is_err());
+}
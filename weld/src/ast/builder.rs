@@ -41,6 +41,8 @@ pub trait NewExpr {
     fn new_negate(expr: Expr) -> WeldResult<Expr>;
     /// Creates a new not operator expression.
     fn new_not(expr: Expr) -> WeldResult<Expr>;
+    /// Creates a new assertion expression.
+    fn new_assert(expr: Expr) -> WeldResult<Expr>;
     /// Creates a new scalar to SIMD broadcast expression.
     fn new_broadcast(expr: Expr) -> WeldResult<Expr>;
     /// Creates a new dictionary to vector expression.
@@ -54,6 +56,8 @@ pub trait NewExpr {
     /// This version can be used if `exprs` is empty and the type cannot be inferred with
     /// `new_make_vector`.
     fn new_make_vector_typed(exprs: Vec<Expr>, ty: Type) -> WeldResult<Expr>;
+    /// Creates a new zip expression over vectors.
+    fn new_zip(vectors: Vec<Expr>) -> WeldResult<Expr>;
     /// Creates a new field access expression on struct.
     fn new_get_field(expr: Expr, index: u32) -> WeldResult<Expr>;
     /// Creates a new vector length expression.
@@ -72,6 +76,10 @@ pub trait NewExpr {
     fn new_let(name: Symbol, value: Expr, body: Expr) -> WeldResult<Expr>;
     /// Creates a new if expression.
     fn new_if(cond: Expr, on_true: Expr, on_false: Expr) -> WeldResult<Expr>;
+    /// Creates a new iterate expression.
+    fn new_iterate(initial: Expr, update_func: Expr) -> WeldResult<Expr>;
+    /// Creates a new switch expression.
+    fn new_switch(value: Expr, cases: Vec<(LiteralKind, Expr)>, default: Expr) -> WeldResult<Expr>;
     /// Creates a new select expression.
     fn new_select(cond: Expr, on_true: Expr, on_false: Expr) -> WeldResult<Expr>;
     /// Creates a new lambda expression.
@@ -104,6 +112,8 @@ impl NewExpr for Expr {
             kind,
             ty,
             annotations: Annotations::new(),
+            span: None,
+            id: None,
         };
 
         // Check the type/infer unknown types locally.
@@ -165,6 +175,10 @@ impl NewExpr for Expr {
         Self::new(Not(Box::new(expr)))
     }
 
+    fn new_assert(expr: Expr) -> WeldResult<Expr> {
+        Self::new(Assert(Box::new(expr)))
+    }
+
     fn new_broadcast(expr: Expr) -> WeldResult<Expr> {
         Self::new(Broadcast(Box::new(expr)))
     }
@@ -187,6 +201,10 @@ impl NewExpr for Expr {
         Self::new_with_type(MakeVector { elems: exprs }, Vector(Box::new(ty)))
     }
 
+    fn new_zip(vectors: Vec<Expr>) -> WeldResult<Expr> {
+        Self::new(Zip { vectors })
+    }
+
     fn new_get_field(expr: Expr, index: u32) -> WeldResult<Expr> {
         Self::new(GetField {
             expr: Box::new(expr),
@@ -252,6 +270,21 @@ impl NewExpr for Expr {
         })
     }
 
+    fn new_iterate(initial: Expr, update_func: Expr) -> WeldResult<Expr> {
+        Self::new(Iterate {
+            initial: Box::new(initial),
+            update_func: Box::new(update_func),
+        })
+    }
+
+    fn new_switch(value: Expr, cases: Vec<(LiteralKind, Expr)>, default: Expr) -> WeldResult<Expr> {
+        Self::new(Switch {
+            value: Box::new(value),
+            cases,
+            default: Box::new(default),
+        })
+    }
+
     fn new_select(cond: Expr, on_true: Expr, on_false: Expr) -> WeldResult<Expr> {
         Self::new(Select {
             cond: Box::new(cond),
@@ -319,3 +352,50 @@ impl NewExpr for Expr {
         })
     }
 }
+
+#[test]
+fn typed_constructors() {
+    let i32_ty = Scalar(ScalarKind::I32);
+    let vec_ty = Vector(Box::new(i32_ty.clone()));
+
+    let one = Expr::new_literal(LiteralKind::I32Literal(1)).unwrap();
+    let x = Expr::new_ident(Symbol::new("x", 0), i32_ty.clone()).unwrap();
+    let sum = Expr::new_bin_op(BinOpKind::Add, x.clone(), one.clone()).unwrap();
+    assert_eq!(sum.ty, i32_ty);
+
+    let cmp = Expr::new_bin_op(BinOpKind::LessThan, x.clone(), one.clone()).unwrap();
+    assert_eq!(cmp.ty, Scalar(ScalarKind::Bool));
+    assert_eq!(Expr::new_assert(cmp).unwrap().ty, Scalar(ScalarKind::Bool));
+
+    let v = Expr::new_ident(Symbol::new("v", 0), vec_ty.clone()).unwrap();
+    let zipped = Expr::new_zip(vec![v.clone(), v]).unwrap();
+    assert_eq!(
+        zipped.ty,
+        Vector(Box::new(Struct(
+            vec![i32_ty.clone(), i32_ty.clone()],
+            FieldNames::none()
+        )))
+    );
+
+    // iterate(x, |x| {x + 1, x < 1})
+    let update = Expr::new_make_struct(vec![
+        sum,
+        Expr::new_bin_op(BinOpKind::LessThan, x.clone(), one.clone()).unwrap(),
+    ])
+    .unwrap();
+    let func = Expr::new_lambda(
+        vec![Parameter {
+            name: Symbol::new("x", 0),
+            ty: i32_ty.clone(),
+        }],
+        update,
+    )
+    .unwrap();
+    assert_eq!(Expr::new_iterate(x.clone(), func).unwrap().ty, i32_ty);
+
+    // Operand types are checked.
+    let f = Expr::new_literal(LiteralKind::F64Literal(1.0f64.to_bits())).unwrap();
+    assert!(Expr::new_bin_op(BinOpKind::Add, x.clone(), f).is_err());
+    assert!(Expr::new_assert(x.clone()).is_err());
+    assert!(Expr::new_zip(vec![x]).is_err());
+}
@@ -15,6 +15,15 @@ pub trait CompareIgnoringSymbols {
     /// Returns whether the two expressions are the same modulo symbol names.
     /// Returns an error if an undefined symbol is encountered.
     fn compare_ignoring_symbols(&self, other: &Self) -> WeldResult<bool>;
+
+    /// Returns whether `other` occurs in this expression modulo symbol names.
+    fn contains_ignoring_symbols(&self, other: &Self) -> WeldResult<bool> {
+        Ok(!self.find_ignoring_symbols(other)?.is_empty())
+    }
+
+    /// Returns every subexpression of this expression that is the same as `other` modulo symbol
+    /// names, in pre-order.
+    fn find_ignoring_symbols<'a>(&'a self, other: &Self) -> WeldResult<Vec<&'a Self>>;
 }
 
 impl CompareIgnoringSymbols for Expr {
@@ -24,7 +33,9 @@ impl CompareIgnoringSymbols for Expr {
         use fnv::FnvHashMap;
         let mut sym_map: FnvHashMap<&Symbol, &Symbol> = FnvHashMap::default();
         let mut reverse_sym_map: FnvHashMap<&Symbol, &Symbol> = FnvHashMap::default();
-        fn _compare_ignoring_symbols<'b, 'a>(
+        /// Compares the type and the non-expression fields of `e1` and `e2`, recording
+        /// corresponding symbol names. Children are compared by the caller.
+        fn compare_node<'b, 'a>(
             e1: &'a Expr,
             e2: &'b Expr,
             sym_map: &mut FnvHashMap<&'a Symbol, &'b Symbol>,
@@ -34,9 +45,9 @@ impl CompareIgnoringSymbols for Expr {
             if e1.ty != e2.ty {
                 return Ok(false);
             }
-            // Check the kind of each expression. same_kind is true if each *non-expression* field
+            // Check the kind of each expression. The result is true if each *non-expression* field
             // is equal and the kind of the expression matches. Also records corresponding symbol names.
-            let same_kind = match (&e1.kind, &e2.kind) {
+            match (&e1.kind, &e2.kind) {
                 (
                     &BinOp {
                         kind: ref kind1, ..
@@ -45,7 +56,7 @@ impl CompareIgnoringSymbols for Expr {
                         kind: ref kind2, ..
                     },
                 ) if kind1 == kind2 => Ok(true),
-                (&UnaryOp { .. }, &UnaryOp { .. }) => Ok(true),
+                (&UnaryOp { kind: kind1, .. }, &UnaryOp { kind: kind2, .. }) => Ok(kind1 == kind2),
                 (
                     &Cast {
                         kind: ref kind1, ..
@@ -86,6 +97,7 @@ impl CompareIgnoringSymbols for Expr {
                 (&NewBuilder(_), &NewBuilder(_)) => Ok(true),
                 (&Negate(_), &Negate(_)) => Ok(true),
                 (&Not(_), &Not(_)) => Ok(true),
+                (&Assert(_), &Assert(_)) => Ok(true),
                 (&Broadcast(_), &Broadcast(_)) => Ok(true),
                 (&MakeStruct { .. }, &MakeStruct { .. }) => Ok(true),
                 (&MakeVector { .. }, &MakeVector { .. }) => Ok(true),
@@ -93,6 +105,11 @@ impl CompareIgnoringSymbols for Expr {
                 (&GetField { index: idx1, .. }, &GetField { index: idx2, .. }) if idx1 == idx2 => {
                     Ok(true)
                 }
+                (GetNamedField { name: name1, .. }, GetNamedField { name: name2, .. })
+                    if name1 == name2 =>
+                {
+                    Ok(true)
+                }
                 (&Length { .. }, &Length { .. }) => Ok(true),
                 (&Lookup { .. }, &Lookup { .. }) => Ok(true),
                 (&OptLookup { .. }, &OptLookup { .. }) => Ok(true),
@@ -116,6 +133,10 @@ impl CompareIgnoringSymbols for Expr {
                         .all(|(ref l, ref r)| l.kind == r.kind))
                 }
                 (&If { .. }, &If { .. }) => Ok(true),
+                (Switch { cases: lcases, .. }, Switch { cases: rcases, .. }) => {
+                    // The case constants must match: the branches are compared as children.
+                    Ok(lcases.iter().map(|c| &c.0).eq(rcases.iter().map(|c| &c.0)))
+                }
                 (&Iterate { .. }, &Iterate { .. }) => Ok(true),
                 (&Select { .. }, &Select { .. }) => Ok(true),
                 (&Apply { .. }, &Apply { .. }) => Ok(true),
@@ -154,28 +175,43 @@ impl CompareIgnoringSymbols for Expr {
                     }
                 }
                 _ => Ok(false), // all else fail.
-            };
-
-            // Return if encountered and error or kind doesn't match.
-            if same_kind.is_err() || !same_kind.as_ref().unwrap() {
-                return same_kind;
             }
+        }
 
-            // Recursively check the children.
-            let e1_children: Vec<_> = e1.children().collect();
-            let e2_children: Vec<_> = e2.children().collect();
-            if e1_children.len() != e2_children.len() {
+        // Compare pairs of subexpressions in pre-order, using an explicit stack so that deeply
+        // nested expressions do not overflow the thread's stack.
+        let mut stack = vec![(self, other)];
+        while let Some((e1, e2)) = stack.pop() {
+            if !compare_node(e1, e2, &mut sym_map, &mut reverse_sym_map)? {
                 return Ok(false);
             }
-            for (c1, c2) in e1_children.iter().zip(e2_children) {
-                let res = _compare_ignoring_symbols(&c1, &c2, sym_map, reverse_sym_map);
-                if res.is_err() || !res.as_ref().unwrap() {
-                    return res;
+            let start = stack.len();
+            let mut e1_children = e1.children();
+            let mut e2_children = e2.children();
+            loop {
+                match (e1_children.next(), e2_children.next()) {
+                    (Some(c1), Some(c2)) => stack.push((c1, c2)),
+                    (None, None) => break,
+                    _ => return Ok(false),
                 }
             }
-            Ok(true)
+            stack[start..].reverse();
+        }
+        Ok(true)
+    }
+
+    fn find_ignoring_symbols<'a>(&'a self, other: &Expr) -> WeldResult<Vec<&'a Expr>> {
+        let mut found = vec![];
+        let mut stack = vec![self];
+        while let Some(e) = stack.pop() {
+            if e.compare_ignoring_symbols(other)? {
+                found.push(e);
+            }
+            let start = stack.len();
+            stack.extend(e.children());
+            stack[start..].reverse();
         }
-        _compare_ignoring_symbols(self, other, &mut sym_map, &mut reverse_sym_map)
+        Ok(found)
     }
 }
 
@@ -209,3 +245,26 @@ fn compare_expressions() {
     let e2 = parse_expr("|c, d| d + c").unwrap();
     assert!(!e1.compare_ignoring_symbols(&e2).unwrap());
 }
+
+#[test]
+fn find_subexpressions() {
+    let e = parse_expr("|x:i32| let a = x + 1; (|y:i32| y + 1)(a) + (x + 1)").unwrap();
+    let target = parse_expr("x + 1").unwrap();
+    // `y + 1` does not match since `y` and `x` are different free symbols in the target.
+    assert_eq!(e.find_ignoring_symbols(&target).unwrap().len(), 2);
+    assert!(e.contains_ignoring_symbols(&target).unwrap());
+
+    let target = parse_expr("|z:i32| z + 1").unwrap();
+    assert_eq!(e.find_ignoring_symbols(&target).unwrap().len(), 1);
+
+    let target = parse_expr("x + 2").unwrap();
+    assert!(!e.contains_ignoring_symbols(&target).unwrap());
+
+    // Unary operators only match if they apply the same function.
+    let e1 = parse_expr("sqrt(1.0)").unwrap();
+    let e2 = parse_expr("exp(1.0)").unwrap();
+    assert!(!e1.compare_ignoring_symbols(&e2).unwrap());
+
+    let e1 = parse_expr("assert(true)").unwrap();
+    assert!(e1.compare_ignoring_symbols(&e1.clone()).unwrap());
+}
@@ -0,0 +1,99 @@
+//! Exports expression trees as Graphviz DOT graphs.
+//!
+//! Each expression becomes a node labeled with its kind, the operator, literal or symbols it
+//! uses, and its type. Edges point from each expression to its children, in the order returned
+//! by `Expr::children`. This is useful for seeing how transformations such as fusion and inlining
+//! reshape a program.
+
+use std::fmt::Write;
+
+use super::ast::ExprKind::*;
+use super::ast::*;
+
+/// Returns a DOT graph of `expr`.
+///
+/// The graph can be rendered with Graphviz, e.g., `dot -Tsvg expr.dot -o expr.svg`.
+pub fn to_dot(expr: &Expr) -> String {
+    let mut graph = String::from("digraph weld {\n  node [shape=box, fontname=\"monospace\"];\n");
+    let mut next_id = 0;
+    add_node(expr, &mut graph, &mut next_id);
+    graph.push_str("}\n");
+    graph
+}
+
+/// Adds `expr` and its children to `graph`, returning the ID of the node for `expr`.
+fn add_node(expr: &Expr, graph: &mut String, next_id: &mut usize) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let label: Vec<_> = label(expr).iter().map(|line| escape(line)).collect();
+    // Writing to a `String` cannot fail.
+    let _ = writeln!(graph, "  n{} [label=\"{}\"];", id, label.join("\\n"));
+    for child in expr.children() {
+        let child_id = add_node(child, graph, next_id);
+        let _ = writeln!(graph, "  n{} -> n{};", id, child_id);
+    }
+    id
+}
+
+/// Returns the lines of the label for `expr`.
+fn label(expr: &Expr) -> Vec<String> {
+    let detail = match expr.kind {
+        Literal(ref lit) => Some(lit.to_string()),
+        Ident(ref symbol) => Some(symbol.to_string()),
+        BinOp { kind, .. } => Some(kind.to_string()),
+        UnaryOp { kind, .. } => Some(kind.to_string()),
+        Cast { kind, .. } => Some(kind.to_string()),
+        GetField { index, .. } => Some(format!("${}", index)),
+        Let { ref name, .. } => Some(name.to_string()),
+        Lambda { ref params, .. } => {
+            let params: Vec<_> = params.iter().map(|p| p.name.to_string()).collect();
+            Some(format!("|{}|", params.join(",")))
+        }
+        For { ref iters, .. } => {
+            let iters: Vec<_> = iters.iter().map(|i| i.kind.to_string()).collect();
+            Some(iters.join(","))
+        }
+        CUDF { ref sym_name, .. } => Some(sym_name.clone()),
+        Deserialize { ref value_ty, .. } => Some(value_ty.to_string()),
+        _ => None,
+    };
+
+    let mut lines = vec![expr.kind.name().to_string()];
+    lines.extend(detail);
+    lines.push(expr.ty.to_string());
+    if !expr.annotations.is_empty() {
+        lines.push(expr.annotations.to_string());
+    }
+    lines
+}
+
+/// Escapes a string for use in a quoted DOT label.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn dot_graph() {
+    use crate::tests::typed_expression;
+    let e = typed_expression("|x:i32| let y = x + 1; \"a\"");
+    assert_eq!(
+        to_dot(&e),
+        r#"digraph weld {
+  node [shape=box, fontname="monospace"];
+  n0 [label="Lambda\n|x|\n|i32|(vec[i8])"];
+  n1 [label="Let\ny\nvec[i8]"];
+  n2 [label="BinOp\n+\ni32"];
+  n3 [label="Ident\nx\ni32"];
+  n2 -> n3;
+  n4 [label="Literal\n1\ni32"];
+  n2 -> n4;
+  n1 -> n2;
+  n5 [label="Literal\n\"a\"\nvec[i8]"];
+  n1 -> n5;
+  n0 -> n1;
+}
+"#
+    );
+}
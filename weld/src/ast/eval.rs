@@ -0,0 +1,1321 @@
+//! Defines the `Eval` trait on expressions.
+//!
+//! The trait interprets a typed expression directly over Rust values, without compiling it. The
+//! interpreter is a reference implementation of the language: its results can be compared against
+//! those of the LLVM backend in differential tests, it can be used to debug a program without
+//! inspecting generated code, and it can compute the value of a constant subtree at compile time.
+//!
+//! The interpreter follows the semantics of the generated code where the language leaves them
+//! open: integer arithmetic wraps, shift amounts are masked to the width of the shifted value, and
+//! builders are updated in place. Errors the generated code does not check for, such as a
+//! division by zero or an out-of-bounds `lookup`, are reported as errors. Runtime failures carry the
+//! `WeldRuntimeErrno` that the generated code reports when it does check for them. SIMD values, C
+//! UDFs, and serialization are not supported.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use super::ast::BinOpKind::*;
+use super::ast::ExprKind::*;
+use super::ast::*;
+use crate::{WeldError, WeldResult, WeldRuntimeErrno};
+
+extern "C" {
+    // The generated code calls the same functions from libm.
+    fn erf(x: f64) -> f64;
+    fn erff(x: f32) -> f32;
+}
+
+/// Returns an error with the given `WeldRuntimeErrno`.
+///
+/// Failures that the generated code reports at runtime use the same code, so the results of the
+/// interpreter and of compiled code can be compared. Other errors use `CompileError`.
+macro_rules! eval_err {
+    ($code:ident, $($arg:tt)*) => ({
+        ::std::result::Result::Err(WeldError::new(format!($($arg)*), WeldRuntimeErrno::$code))
+    })
+}
+
+/// A trait that interprets expressions.
+pub trait Eval {
+    /// Evaluates the expression.
+    ///
+    /// If the expression is a function, it is applied to `args`; otherwise, `args` must be empty.
+    /// The expression must be typed, and it may not have free variables.
+    fn eval(&self, args: &[Value]) -> WeldResult<Value>;
+}
+
+impl Eval for Expr {
+    fn eval(&self, args: &[Value]) -> WeldResult<Value> {
+        let mut interpreter = Interpreter::default();
+        match self.kind {
+            Lambda { .. } => interpreter.call(self, args.to_vec()),
+            _ if args.is_empty() => interpreter.eval(self),
+            _ => eval_err!(
+                CompileError,
+                "Cannot apply arguments to a value that is not a function"
+            ),
+        }
+    }
+}
+
+/// A value computed by the interpreter.
+///
+/// Values are compared structurally. Floating-point values are ordered by their total order, so
+/// a `NaN` equals itself: this is the order dictionary keys use, and it differs from the order of
+/// the language's comparison operators.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Vector(Rc<Vec<Value>>),
+    /// A dictionary, ordered by its keys.
+    Dict(Rc<BTreeMap<Value, Value>>),
+    Struct(Vec<Value>),
+    Builder(BuilderValue),
+    Function(FunctionValue),
+}
+
+impl Value {
+    /// Returns a vector value with the given elements.
+    pub fn vector(elems: Vec<Value>) -> Value {
+        Value::Vector(Rc::new(elems))
+    }
+
+    /// Returns a dictionary value with the given entries.
+    ///
+    /// If a key appears more than once, the last entry is used.
+    pub fn dict(entries: Vec<(Value, Value)>) -> Value {
+        Value::Dict(Rc::new(entries.into_iter().collect()))
+    }
+
+    /// Returns the value of a literal.
+    pub fn from_literal(literal: &LiteralKind) -> Value {
+        use super::ast::LiteralKind::*;
+        match *literal {
+            BoolLiteral(v) => Value::Bool(v),
+            I8Literal(v) => Value::I8(v),
+            I16Literal(v) => Value::I16(v),
+            I32Literal(v) => Value::I32(v),
+            I64Literal(v) => Value::I64(v),
+            U8Literal(v) => Value::U8(v),
+            U16Literal(v) => Value::U16(v),
+            U32Literal(v) => Value::U32(v),
+            U64Literal(v) => Value::U64(v),
+            F32Literal(v) => Value::F32(f32::from_bits(v)),
+            F64Literal(v) => Value::F64(f64::from_bits(v)),
+            StringLiteral(ref v) => Value::vector(v.bytes().map(|b| Value::I8(b as i8)).collect()),
+        }
+    }
+
+    /// Returns the value of a type that the generated code zero-initializes.
+    fn zero(ty: &Type) -> WeldResult<Value> {
+        use super::ast::Type::*;
+        match *ty {
+            Scalar(kind) => Ok(Value::from_integer(0, kind)),
+            Vector(_) => Ok(Value::vector(vec![])),
+            Dict(_, _) => Ok(Value::dict(vec![])),
+            Struct(ref tys, _) => Ok(Value::Struct(
+                tys.iter().map(Value::zero).collect::<WeldResult<_>>()?,
+            )),
+            Alias(_, ref ty) => Value::zero(ty),
+            _ => eval_err!(CompileError, "Cannot create a value of type {}", ty),
+        }
+    }
+
+    /// Returns an integer converted to `kind`, truncating it like the generated code.
+    fn from_integer(v: i128, kind: ScalarKind) -> Value {
+        use super::ast::ScalarKind::*;
+        match kind {
+            Bool => Value::Bool(v & 1 != 0),
+            I8 => Value::I8(v as i8),
+            I16 => Value::I16(v as i16),
+            I32 => Value::I32(v as i32),
+            I64 => Value::I64(v as i64),
+            U8 => Value::U8(v as u8),
+            U16 => Value::U16(v as u16),
+            U32 => Value::U32(v as u32),
+            U64 => Value::U64(v as u64),
+            F32 => Value::F32(v as f32),
+            F64 => Value::F64(v as f64),
+        }
+    }
+
+    /// Returns the value of an integer or boolean, extended according to its signedness.
+    fn as_integer(&self) -> Option<i128> {
+        use self::Value::*;
+        match *self {
+            Bool(v) => Some(i128::from(v)),
+            I8(v) => Some(i128::from(v)),
+            I16(v) => Some(i128::from(v)),
+            I32(v) => Some(i128::from(v)),
+            I64(v) => Some(i128::from(v)),
+            U8(v) => Some(i128::from(v)),
+            U16(v) => Some(i128::from(v)),
+            U32(v) => Some(i128::from(v)),
+            U64(v) => Some(i128::from(v)),
+            _ => None,
+        }
+    }
+
+    fn as_i64(&self) -> WeldResult<i64> {
+        match *self {
+            Value::I64(v) => Ok(v),
+            _ => eval_err!(CompileError, "Expected an i64, got {:?}", self),
+        }
+    }
+
+    fn as_bool(&self) -> WeldResult<bool> {
+        match *self {
+            Value::Bool(v) => Ok(v),
+            _ => eval_err!(CompileError, "Expected a bool, got {:?}", self),
+        }
+    }
+
+    fn as_vector(&self) -> WeldResult<&Rc<Vec<Value>>> {
+        match *self {
+            Value::Vector(ref v) => Ok(v),
+            _ => eval_err!(CompileError, "Expected a vector, got {:?}", self),
+        }
+    }
+
+    fn as_dict(&self) -> WeldResult<&Rc<BTreeMap<Value, Value>>> {
+        match *self {
+            Value::Dict(ref v) => Ok(v),
+            _ => eval_err!(CompileError, "Expected a dictionary, got {:?}", self),
+        }
+    }
+
+    fn into_fields(self) -> WeldResult<Vec<Value>> {
+        match self {
+            Value::Struct(fields) => Ok(fields),
+            _ => eval_err!(CompileError, "Expected a struct, got {:?}", self),
+        }
+    }
+
+    /// Returns the position of the variant, which orders values of different variants.
+    fn rank(&self) -> u8 {
+        use self::Value::*;
+        match *self {
+            Bool(_) => 0,
+            I8(_) => 1,
+            I16(_) => 2,
+            I32(_) => 3,
+            I64(_) => 4,
+            U8(_) => 5,
+            U16(_) => 6,
+            U32(_) => 7,
+            U64(_) => 8,
+            F32(_) => 9,
+            F64(_) => 10,
+            Vector(_) => 11,
+            Dict(_) => 12,
+            Struct(_) => 13,
+            Builder(_) => 14,
+            Function(_) => 15,
+        }
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        use self::Value::*;
+        match (self, other) {
+            (Bool(l), Bool(r)) => l.cmp(r),
+            (I8(l), I8(r)) => l.cmp(r),
+            (I16(l), I16(r)) => l.cmp(r),
+            (I32(l), I32(r)) => l.cmp(r),
+            (I64(l), I64(r)) => l.cmp(r),
+            (U8(l), U8(r)) => l.cmp(r),
+            (U16(l), U16(r)) => l.cmp(r),
+            (U32(l), U32(r)) => l.cmp(r),
+            (U64(l), U64(r)) => l.cmp(r),
+            (F32(l), F32(r)) => l.total_cmp(r),
+            (F64(l), F64(r)) => l.total_cmp(r),
+            (Vector(l), Vector(r)) => l.cmp(r),
+            (Dict(l), Dict(r)) => l.cmp(r),
+            (Struct(l), Struct(r)) => l.cmp(r),
+            // Builders and functions are only equal to themselves.
+            (Builder(l), Builder(r)) => Rc::as_ptr(&l.0).cmp(&Rc::as_ptr(&r.0)),
+            (Function(l), Function(r)) => Rc::as_ptr(&l.0).cmp(&Rc::as_ptr(&r.0)),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+/// A builder. Like the builders of the generated code, it is updated in place by merges.
+#[derive(Clone, Debug)]
+pub struct BuilderValue(Rc<RefCell<BuilderState>>);
+
+#[derive(Debug)]
+enum BuilderState {
+    Appender(Vec<Value>),
+    Merger(BinOpKind, Value),
+    DictMerger(BinOpKind, BTreeMap<Value, Value>),
+    GroupMerger(BTreeMap<Value, Vec<Value>>),
+    VecMerger(BinOpKind, Vec<Value>),
+}
+
+impl BuilderValue {
+    fn merge(&self, value: Value) -> WeldResult<()> {
+        use self::BuilderState::*;
+        match *self.0.borrow_mut() {
+            Appender(ref mut elems) => elems.push(value),
+            Merger(op, ref mut acc) => *acc = binop(op, acc, &value)?,
+            DictMerger(op, ref mut map) => {
+                let (key, value) = pair(value)?;
+                let merged = match map.get(&key) {
+                    Some(old) => binop(op, old, &value)?,
+                    None => value,
+                };
+                map.insert(key, merged);
+            }
+            GroupMerger(ref mut map) => {
+                let (key, value) = pair(value)?;
+                map.entry(key).or_default().push(value);
+            }
+            VecMerger(op, ref mut elems) => {
+                let (index, value) = pair(value)?;
+                let index = index.as_i64()?;
+                let elem = match usize::try_from(index).ok().and_then(|i| elems.get_mut(i)) {
+                    Some(elem) => elem,
+                    None => {
+                        return eval_err!(
+                            ArrayOutOfBounds,
+                            "Index {} is out of bounds in vecmerger",
+                            index
+                        )
+                    }
+                };
+                *elem = binop(op, elem, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn result(&self) -> Value {
+        use self::BuilderState::*;
+        match *self.0.borrow() {
+            Appender(ref elems) | VecMerger(_, ref elems) => Value::vector(elems.clone()),
+            Merger(_, ref acc) => acc.clone(),
+            DictMerger(_, ref map) => Value::Dict(Rc::new(map.clone())),
+            GroupMerger(ref map) => Value::Dict(Rc::new(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), Value::vector(v.clone())))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// Returns the fields of a `{key, value}` pair merged into a builder.
+fn pair(value: Value) -> WeldResult<(Value, Value)> {
+    let mut fields = value.into_fields()?;
+    if fields.len() != 2 {
+        return eval_err!(CompileError, "Expected a pair of values");
+    }
+    let second = fields.pop().unwrap();
+    let first = fields.pop().unwrap();
+    Ok((first, second))
+}
+
+/// A function and the values of its free variables.
+#[derive(Clone, Debug)]
+pub struct FunctionValue(Rc<Closure>);
+
+#[derive(Debug)]
+struct Closure {
+    params: Vec<Symbol>,
+    body: Expr,
+    captured: Vec<(Symbol, Value)>,
+}
+
+/// Evaluates expressions in an environment of bound symbols.
+#[derive(Default)]
+struct Interpreter {
+    env: fnv::FnvHashMap<Symbol, Value>,
+}
+
+impl Interpreter {
+    /// Evaluates `f` with `bindings` added to the environment.
+    fn with_bindings<T, F>(&mut self, bindings: Vec<(Symbol, Value)>, f: F) -> WeldResult<T>
+    where
+        F: FnOnce(&mut Interpreter) -> WeldResult<T>,
+    {
+        let saved: Vec<_> = bindings
+            .into_iter()
+            .map(|(name, value)| {
+                let old = self.env.insert(name.clone(), value);
+                (name, old)
+            })
+            .collect();
+        let result = f(self);
+        for (name, old) in saved.into_iter().rev() {
+            match old {
+                Some(value) => self.env.insert(name, value),
+                None => self.env.remove(&name),
+            };
+        }
+        result
+    }
+
+    /// Calls the function `func` with `args`.
+    fn call(&mut self, func: &Expr, args: Vec<Value>) -> WeldResult<Value> {
+        // Calling a lambda directly avoids capturing its environment.
+        if let Lambda {
+            ref params,
+            ref body,
+        } = func.kind
+        {
+            if params.len() != args.len() {
+                return eval_err!(
+                    CompileError,
+                    "Expected {} arguments to function, got {}",
+                    params.len(),
+                    args.len()
+                );
+            }
+            let bindings = params.iter().map(|p| p.name.clone()).zip(args).collect();
+            return self.with_bindings(bindings, |interpreter| interpreter.eval(body));
+        }
+        let closure = match self.eval(func)? {
+            Value::Function(FunctionValue(closure)) => closure,
+            value => return eval_err!(CompileError, "Cannot call {:?}", value),
+        };
+        if closure.params.len() != args.len() {
+            return eval_err!(
+                CompileError,
+                "Expected {} arguments to function, got {}",
+                closure.params.len(),
+                args.len()
+            );
+        }
+        let mut bindings = closure.captured.clone();
+        bindings.extend(closure.params.iter().cloned().zip(args));
+        self.with_bindings(bindings, |interpreter| interpreter.eval(&closure.body))
+    }
+
+    fn lookup(&self, symbol: &Symbol) -> WeldResult<Value> {
+        match self.env.get(symbol) {
+            Some(value) => Ok(value.clone()),
+            None => eval_err!(CompileError, "Undefined symbol {} in interpreter", symbol),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> WeldResult<Value> {
+        match expr.kind {
+            Literal(ref literal) => Ok(Value::from_literal(literal)),
+            Ident(ref symbol) => self.lookup(symbol),
+            Not(ref child) => Ok(Value::Bool(!self.eval(child)?.as_bool()?)),
+            Negate(ref child) => negate(&self.eval(child)?),
+            Assert(ref child) => {
+                if self.eval(child)?.as_bool()? {
+                    Ok(Value::Bool(true))
+                } else {
+                    eval_err!(AssertionError, "Assertion failed")
+                }
+            }
+            BinOp {
+                kind,
+                ref left,
+                ref right,
+            } => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                binop(kind, &left, &right)
+            }
+            UnaryOp { kind, ref value } => unaryop(kind, &self.eval(value)?),
+            Cast {
+                kind,
+                ref child_expr,
+            } => cast(&self.eval(child_expr)?, kind),
+            ToVec { ref child_expr } => {
+                let dict = self.eval(child_expr)?;
+                let entries = dict
+                    .as_dict()?
+                    .iter()
+                    .map(|(k, v)| Value::Struct(vec![k.clone(), v.clone()]))
+                    .collect();
+                Ok(Value::vector(entries))
+            }
+            MakeStruct { ref elems } => Ok(Value::Struct(
+                elems
+                    .iter()
+                    .map(|e| self.eval(e))
+                    .collect::<WeldResult<_>>()?,
+            )),
+            MakeVector { ref elems } => Ok(Value::vector(
+                elems
+                    .iter()
+                    .map(|e| self.eval(e))
+                    .collect::<WeldResult<_>>()?,
+            )),
+            GetField { expr: ref e, index } => {
+                let mut fields = self.eval(e)?.into_fields()?;
+                if index as usize >= fields.len() {
+                    return eval_err!(CompileError, "Field {} is out of bounds", index);
+                }
+                Ok(fields.swap_remove(index as usize))
+            }
+            GetNamedField { ref name, .. } => {
+                eval_err!(
+                    CompileError,
+                    "Field {} must be resolved by type inference",
+                    name
+                )
+            }
+            Length { ref data } => {
+                let data = self.eval(data)?;
+                Ok(Value::I64(data.as_vector()?.len() as i64))
+            }
+            Lookup {
+                ref data,
+                ref index,
+            } => {
+                let data = self.eval(data)?;
+                let index = self.eval(index)?;
+                match data {
+                    Value::Dict(ref map) => match map.get(&index) {
+                        Some(value) => Ok(value.clone()),
+                        None => {
+                            eval_err!(KeyNotFoundError, "Key {:?} not found in dictionary", index)
+                        }
+                    },
+                    _ => {
+                        let elems = data.as_vector()?;
+                        let index = index.as_i64()?;
+                        match usize::try_from(index).ok().and_then(|i| elems.get(i)) {
+                            Some(value) => Ok(value.clone()),
+                            None => eval_err!(
+                                ArrayOutOfBounds,
+                                "Index {} is out of bounds for a vector of length {}",
+                                index,
+                                elems.len()
+                            ),
+                        }
+                    }
+                }
+            }
+            OptLookup {
+                ref data,
+                ref index,
+            } => {
+                let data = self.eval(data)?;
+                let index = self.eval(index)?;
+                let result = match data.as_dict()?.get(&index) {
+                    Some(value) => vec![Value::Bool(true), value.clone()],
+                    None => match expr.ty {
+                        Type::Struct(ref tys, _) if tys.len() == 2 => {
+                            vec![Value::Bool(false), Value::zero(&tys[1])?]
+                        }
+                        _ => {
+                            return eval_err!(CompileError, "Expected a struct type for optlookup")
+                        }
+                    },
+                };
+                Ok(Value::Struct(result))
+            }
+            KeyExists { ref data, ref key } => {
+                let data = self.eval(data)?;
+                let key = self.eval(key)?;
+                Ok(Value::Bool(data.as_dict()?.contains_key(&key)))
+            }
+            Slice {
+                ref data,
+                ref index,
+                ref size,
+            } => {
+                let data = self.eval(data)?;
+                let elems = data.as_vector()?;
+                let index = self.eval(index)?.as_i64()?;
+                let size = self.eval(size)?.as_i64()?;
+                if index < 0 || size < 0 {
+                    return eval_err!(
+                        ArrayOutOfBounds,
+                        "Invalid slice of size {} at index {}",
+                        size,
+                        index
+                    );
+                }
+                // The slice is truncated to the end of the vector.
+                let start = (index as usize).min(elems.len());
+                let end = start.saturating_add(size as usize).min(elems.len());
+                Ok(Value::vector(elems[start..end].to_vec()))
+            }
+            Sort {
+                ref data,
+                ref cmpfunc,
+            } => {
+                let data = self.eval(data)?;
+                let elems = data.as_vector()?.to_vec();
+                Ok(Value::vector(self.sort(elems, cmpfunc)?))
+            }
+            Let {
+                ref name,
+                ref value,
+                ref body,
+            } => {
+                let value = self.eval(value)?;
+                self.with_bindings(vec![(name.clone(), value)], |interpreter| {
+                    interpreter.eval(body)
+                })
+            }
+            If {
+                ref cond,
+                ref on_true,
+                ref on_false,
+            } => {
+                if self.eval(cond)?.as_bool()? {
+                    self.eval(on_true)
+                } else {
+                    self.eval(on_false)
+                }
+            }
+            Select {
+                ref cond,
+                ref on_true,
+                ref on_false,
+            } => {
+                let cond = self.eval(cond)?.as_bool()?;
+                let on_true = self.eval(on_true)?;
+                let on_false = self.eval(on_false)?;
+                Ok(if cond { on_true } else { on_false })
+            }
+            Switch {
+                ref value,
+                ref cases,
+                ref default,
+            } => {
+                let value = self.eval(value)?;
+                for (literal, case) in cases.iter() {
+                    if Value::from_literal(literal) == value {
+                        return self.eval(case);
+                    }
+                }
+                self.eval(default)
+            }
+            Iterate {
+                ref initial,
+                ref update_func,
+            } => {
+                let mut state = self.eval(initial)?;
+                loop {
+                    let (next, proceed) = pair(self.call(update_func, vec![state])?)?;
+                    state = next;
+                    if !proceed.as_bool()? {
+                        return Ok(state);
+                    }
+                }
+            }
+            Lambda {
+                ref params,
+                ref body,
+            } => {
+                let captured = expr
+                    .free_variables()
+                    .into_iter()
+                    .map(|symbol| {
+                        let value = self.lookup(&symbol)?;
+                        Ok((symbol, value))
+                    })
+                    .collect::<WeldResult<Vec<_>>>()?;
+                Ok(Value::Function(FunctionValue(Rc::new(Closure {
+                    params: params.iter().map(|p| p.name.clone()).collect(),
+                    body: body.as_ref().clone(),
+                    captured,
+                }))))
+            }
+            Apply {
+                ref func,
+                ref params,
+            } => {
+                let args = params
+                    .iter()
+                    .map(|p| self.eval(p))
+                    .collect::<WeldResult<_>>()?;
+                self.call(func, args)
+            }
+            NewBuilder(ref arg) => {
+                let arg = match *arg {
+                    Some(ref arg) => Some(self.eval(arg)?),
+                    None => None,
+                };
+                new_builder(&expr.ty, arg)
+            }
+            For {
+                ref iters,
+                ref builder,
+                ref func,
+            } => {
+                let elems = iters
+                    .iter()
+                    .map(|iter| self.iter_elements(iter))
+                    .collect::<WeldResult<Vec<_>>>()?;
+                let iterations = elems.first().map(|e| e.len()).unwrap_or(0);
+                if elems.iter().any(|e| e.len() != iterations) {
+                    return eval_err!(MismatchedZipSize, "Zipped iterators have different lengths");
+                }
+                let mut builder = self.eval(builder)?;
+                for i in 0..iterations {
+                    let elem = if elems.len() == 1 {
+                        elems[0][i].clone()
+                    } else {
+                        Value::Struct(elems.iter().map(|e| e[i].clone()).collect())
+                    };
+                    builder = self.call(func, vec![builder, Value::I64(i as i64), elem])?;
+                }
+                Ok(builder)
+            }
+            Merge {
+                ref builder,
+                ref value,
+            } => {
+                let builder = self.eval(builder)?;
+                let value = self.eval(value)?;
+                match builder {
+                    Value::Builder(ref b) => b.merge(value)?,
+                    _ => return eval_err!(CompileError, "Cannot merge into {:?}", builder),
+                }
+                Ok(builder)
+            }
+            Res { ref builder } => match self.eval(builder)? {
+                Value::Builder(ref b) => Ok(b.result()),
+                value => eval_err!(CompileError, "Cannot get the result of {:?}", value),
+            },
+            Broadcast(_) | Zip { .. } | CUDF { .. } | Serialize(_) | Deserialize { .. } => {
+                eval_err!(
+                    CompileError,
+                    "{} is not supported by the interpreter",
+                    expr.kind.name()
+                )
+            }
+        }
+    }
+
+    /// Returns the elements an iterator produces.
+    fn iter_elements(&mut self, iter: &Iter) -> WeldResult<Vec<Value>> {
+        match iter.kind {
+            IterKind::RangeIter => {
+                let (start, end, stride) = self.iter_bounds(iter)?;
+                if stride <= 0 {
+                    return eval_err!(BadIteratorLength, "Invalid rangeiter stride {}", stride);
+                }
+                Ok((start..end)
+                    .step_by(stride as usize)
+                    .map(Value::I64)
+                    .collect())
+            }
+            IterKind::ScalarIter if iter.start.is_none() => {
+                Ok(self.eval(&iter.data)?.as_vector()?.to_vec())
+            }
+            IterKind::ScalarIter => {
+                let (start, end, stride) = self.iter_bounds(iter)?;
+                let data = self.eval(&iter.data)?;
+                let elems = data.as_vector()?;
+                if stride <= 0
+                    || start < 0
+                    || start > end
+                    || end > elems.len() as i64
+                    || (end - start) % stride != 0
+                {
+                    return eval_err!(
+                        BadIteratorLength,
+                        "Invalid iterator bounds {}, {}, {} for a vector of length {}",
+                        start,
+                        end,
+                        stride,
+                        elems.len()
+                    );
+                }
+                Ok(elems[start as usize..end as usize]
+                    .iter()
+                    .step_by(stride as usize)
+                    .cloned()
+                    .collect())
+            }
+            IterKind::NdIter => {
+                let (start, _, _) = self.iter_bounds(iter)?;
+                let shape = self.eval_indices(&iter.shape)?;
+                let strides = self.eval_indices(&iter.strides)?;
+                if shape.len() != strides.len() || shape.iter().any(|d| *d < 0) {
+                    return eval_err!(
+                        BadIteratorLength,
+                        "Invalid nditer shape {:?} and strides {:?}",
+                        shape,
+                        strides
+                    );
+                }
+                let data = self.eval(&iter.data)?;
+                let elems = data.as_vector()?;
+                // Visit the multi-dimensional indices in row-major order.
+                let mut result = vec![];
+                let mut index = vec![0; shape.len()];
+                if shape.iter().all(|d| *d > 0) {
+                    loop {
+                        let offset = start
+                            + index
+                                .iter()
+                                .zip(strides.iter())
+                                .map(|(i, s)| i * s)
+                                .sum::<i64>();
+                        match usize::try_from(offset).ok().and_then(|i| elems.get(i)) {
+                            Some(elem) => result.push(elem.clone()),
+                            None => {
+                                return eval_err!(
+                                    ArrayOutOfBounds,
+                                    "nditer offset {} is out of bounds",
+                                    offset
+                                )
+                            }
+                        }
+                        // Advance the last dimension, carrying into earlier ones.
+                        let mut k = shape.len();
+                        while k > 0 {
+                            k -= 1;
+                            index[k] += 1;
+                            if index[k] < shape[k] {
+                                break;
+                            }
+                            index[k] = 0;
+                        }
+                        if index.iter().all(|i| *i == 0) {
+                            break;
+                        }
+                    }
+                }
+                Ok(result)
+            }
+            IterKind::SimdIter | IterKind::FringeIter | IterKind::ChunkIter => {
+                eval_err!(
+                    CompileError,
+                    "{} is not supported by the interpreter",
+                    iter.kind
+                )
+            }
+        }
+    }
+
+    /// Returns the start, end, and stride of an iterator.
+    fn iter_bounds(&mut self, iter: &Iter) -> WeldResult<(i64, i64, i64)> {
+        match (&iter.start, &iter.end, &iter.stride) {
+            (Some(start), Some(end), Some(stride)) => Ok((
+                self.eval(start)?.as_i64()?,
+                self.eval(end)?.as_i64()?,
+                self.eval(stride)?.as_i64()?,
+            )),
+            _ => eval_err!(CompileError, "Expected iterator bounds"),
+        }
+    }
+
+    /// Returns the elements of an optional `vec[i64]`.
+    fn eval_indices(&mut self, expr: &Option<Box<Expr>>) -> WeldResult<Vec<i64>> {
+        match *expr {
+            Some(ref expr) => self
+                .eval(expr)?
+                .as_vector()?
+                .iter()
+                .map(Value::as_i64)
+                .collect(),
+            None => eval_err!(CompileError, "Expected nditer shape and strides"),
+        }
+    }
+
+    /// Sorts `elems` with the comparison function `cmpfunc`.
+    ///
+    /// The sort is a stable merge sort, so an inconsistent comparison function does not cause a
+    /// panic.
+    fn sort(&mut self, mut elems: Vec<Value>, cmpfunc: &Expr) -> WeldResult<Vec<Value>> {
+        if elems.len() <= 1 {
+            return Ok(elems);
+        }
+        let right = elems.split_off(elems.len() / 2);
+        let left = self.sort(elems, cmpfunc)?;
+        let right = self.sort(right, cmpfunc)?;
+        let mut result = Vec::with_capacity(left.len() + right.len());
+        let mut left = left.into_iter().peekable();
+        let mut right = right.into_iter().peekable();
+        while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+            let order = self.call(cmpfunc, vec![l.clone(), r.clone()])?;
+            let next = match order {
+                Value::I32(c) if c > 0 => right.next(),
+                Value::I32(_) => left.next(),
+                _ => return eval_err!(CompileError, "Expected an i32 from sort comparator"),
+            };
+            result.extend(next);
+        }
+        result.extend(left);
+        result.extend(right);
+        Ok(result)
+    }
+}
+
+/// Returns a new builder of type `ty`.
+fn new_builder(ty: &Type, arg: Option<Value>) -> WeldResult<Value> {
+    use super::ast::BuilderKind::*;
+    let kind = match *ty {
+        Type::Builder(ref kind, _) => kind,
+        _ => return eval_err!(CompileError, "Expected a builder type, got {}", ty),
+    };
+    let state = match *kind {
+        Appender(_) => BuilderState::Appender(vec![]),
+        Merger(ref elem, op) => {
+            let initial = match arg {
+                Some(value) => value,
+                None => identity(op, elem)?,
+            };
+            BuilderState::Merger(op, initial)
+        }
+        DictMerger(_, _, op) => BuilderState::DictMerger(op, BTreeMap::new()),
+        GroupMerger(_, _) => BuilderState::GroupMerger(BTreeMap::new()),
+        VecMerger(_, op) => match arg {
+            Some(Value::Vector(elems)) => BuilderState::VecMerger(op, elems.to_vec()),
+            _ => return eval_err!(CompileError, "Expected an initial vector for vecmerger"),
+        },
+    };
+    Ok(Value::Builder(BuilderValue(Rc::new(RefCell::new(state)))))
+}
+
+/// Returns the identity of a merger's operator.
+fn identity(op: BinOpKind, ty: &Type) -> WeldResult<Value> {
+    use super::ast::ScalarKind::*;
+    let kind = match *ty {
+        Type::Scalar(kind) => kind,
+        _ => return eval_err!(CompileError, "Unsupported merger type {}", ty),
+    };
+    let value = match (op, kind) {
+        (Add, _) => Value::from_integer(0, kind),
+        (Multiply, _) => Value::from_integer(1, kind),
+        (Max, F32) => Value::F32(f32::MIN),
+        (Max, F64) => Value::F64(f64::MIN),
+        (Min, F32) => Value::F32(f32::MAX),
+        (Min, F64) => Value::F64(f64::MAX),
+        (Max, _) if kind.is_signed_integer() => {
+            Value::from_integer(-(1 << (kind.bits() - 1)), kind)
+        }
+        (Max, _) if kind.is_unsigned_integer() => Value::from_integer(0, kind),
+        (Min, _) if kind.is_signed_integer() => {
+            Value::from_integer((1 << (kind.bits() - 1)) - 1, kind)
+        }
+        // Truncating -1 gives the largest unsigned value.
+        (Min, _) if kind.is_unsigned_integer() => Value::from_integer(-1, kind),
+        _ => {
+            return eval_err!(
+                CompileError,
+                "Unsupported merger operator {} on {}",
+                op,
+                kind
+            )
+        }
+    };
+    Ok(value)
+}
+
+/// Applies a binary operator to two values.
+fn binop(op: BinOpKind, left: &Value, right: &Value) -> WeldResult<Value> {
+    use self::Value::*;
+    if op.is_comparison() {
+        return compare(op, left, right).map(Bool);
+    }
+
+    macro_rules! integer_binop {
+        ($l:expr, $r:expr) => {{
+            let (l, r) = ($l, $r);
+            match op {
+                Add => l.wrapping_add(r),
+                Subtract => l.wrapping_sub(r),
+                Multiply => l.wrapping_mul(r),
+                Divide | Modulo if r == 0 => return eval_err!(DivideByZero, "Division by zero"),
+                Divide => l.wrapping_div(r),
+                Modulo => l.wrapping_rem(r),
+                BitwiseAnd => l & r,
+                BitwiseOr => l | r,
+                Xor => l ^ r,
+                // The shift amount is masked to the width of the value.
+                ShiftLeft => l.wrapping_shl(r as u32),
+                ShiftRight => l.wrapping_shr(r as u32),
+                Max => l.max(r),
+                Min => l.min(r),
+                _ => return eval_err!(CompileError, "Unsupported binary op: {} on {:?}", op, left),
+            }
+        }};
+    }
+
+    macro_rules! float_binop {
+        ($l:expr, $r:expr) => {{
+            let (l, r) = ($l, $r);
+            match op {
+                Add => l + r,
+                Subtract => l - r,
+                Multiply => l * r,
+                Divide => l / r,
+                Modulo => l % r,
+                Pow => l.powf(r),
+                Max => {
+                    if l >= r {
+                        l
+                    } else {
+                        r
+                    }
+                }
+                Min => {
+                    if l <= r {
+                        l
+                    } else {
+                        r
+                    }
+                }
+                _ => return eval_err!(CompileError, "Unsupported binary op: {} on {:?}", op, left),
+            }
+        }};
+    }
+
+    let result = match (left, right) {
+        (Bool(l), Bool(r)) => match op {
+            LogicalAnd | BitwiseAnd => Bool(*l && *r),
+            LogicalOr | BitwiseOr => Bool(*l || *r),
+            Xor => Bool(*l != *r),
+            _ => return eval_err!(CompileError, "Unsupported binary op: {} on {:?}", op, left),
+        },
+        (I8(l), I8(r)) => I8(integer_binop!(*l, *r)),
+        (I16(l), I16(r)) => I16(integer_binop!(*l, *r)),
+        (I32(l), I32(r)) => I32(integer_binop!(*l, *r)),
+        (I64(l), I64(r)) => I64(integer_binop!(*l, *r)),
+        (U8(l), U8(r)) => U8(integer_binop!(*l, *r)),
+        (U16(l), U16(r)) => U16(integer_binop!(*l, *r)),
+        (U32(l), U32(r)) => U32(integer_binop!(*l, *r)),
+        (U64(l), U64(r)) => U64(integer_binop!(*l, *r)),
+        (F32(l), F32(r)) => F32(float_binop!(*l, *r)),
+        (F64(l), F64(r)) => F64(float_binop!(*l, *r)),
+        _ => {
+            return eval_err!(
+                CompileError,
+                "Mismatched types in binary op: {:?} and {:?}",
+                left,
+                right
+            )
+        }
+    };
+    Ok(result)
+}
+
+/// Applies a comparison operator to two values.
+///
+/// Floating-point comparisons with `NaN` are false, as in the generated code.
+fn compare(op: BinOpKind, left: &Value, right: &Value) -> WeldResult<bool> {
+    let ordering = partial_cmp(left, right)?;
+    let result = match op {
+        Equal => ordering == Some(Ordering::Equal),
+        NotEqual => matches!(ordering, Some(Ordering::Less) | Some(Ordering::Greater)),
+        LessThan => ordering == Some(Ordering::Less),
+        LessThanOrEqual => matches!(ordering, Some(Ordering::Less) | Some(Ordering::Equal)),
+        GreaterThan => ordering == Some(Ordering::Greater),
+        GreaterThanOrEqual => matches!(ordering, Some(Ordering::Greater) | Some(Ordering::Equal)),
+        _ => unreachable!(),
+    };
+    Ok(result)
+}
+
+/// Compares two values in the order of the language's comparison operators.
+///
+/// Vectors and structs are ordered lexicographically, and a vector is ordered before the vectors
+/// it is a prefix of.
+fn partial_cmp(left: &Value, right: &Value) -> WeldResult<Option<Ordering>> {
+    use self::Value::*;
+    let (left, right) = match (left, right) {
+        (F32(l), F32(r)) => return Ok(l.partial_cmp(r)),
+        (F64(l), F64(r)) => return Ok(l.partial_cmp(r)),
+        (Vector(l), Vector(r)) => (l.as_slice(), r.as_slice()),
+        (Struct(l), Struct(r)) => (l.as_slice(), r.as_slice()),
+        _ if left.rank() == right.rank() && left.as_integer().is_some() => {
+            return Ok(Some(left.cmp(right)));
+        }
+        _ => return eval_err!(CompileError, "Cannot compare {:?} and {:?}", left, right),
+    };
+    for (l, r) in left.iter().zip(right.iter()) {
+        match partial_cmp(l, r)? {
+            Some(Ordering::Equal) => (),
+            ordering => return Ok(ordering),
+        }
+    }
+    Ok(Some(left.len().cmp(&right.len())))
+}
+
+/// Negates a numeric value.
+fn negate(value: &Value) -> WeldResult<Value> {
+    use self::Value::*;
+    let result = match *value {
+        I8(v) => I8(v.wrapping_neg()),
+        I16(v) => I16(v.wrapping_neg()),
+        I32(v) => I32(v.wrapping_neg()),
+        I64(v) => I64(v.wrapping_neg()),
+        U8(v) => U8(v.wrapping_neg()),
+        U16(v) => U16(v.wrapping_neg()),
+        U32(v) => U32(v.wrapping_neg()),
+        U64(v) => U64(v.wrapping_neg()),
+        F32(v) => F32(-v),
+        F64(v) => F64(-v),
+        _ => return eval_err!(CompileError, "Cannot negate {:?}", value),
+    };
+    Ok(result)
+}
+
+/// Applies a unary operator to a floating-point value.
+fn unaryop(op: UnaryOpKind, value: &Value) -> WeldResult<Value> {
+    use super::ast::UnaryOpKind::*;
+    macro_rules! float_unaryop {
+        ($v:expr, $erf:ident) => {{
+            let v = $v;
+            match op {
+                Exp => v.exp(),
+                Log => v.ln(),
+                Sqrt => v.sqrt(),
+                Sin => v.sin(),
+                Cos => v.cos(),
+                Tan => v.tan(),
+                ASin => v.asin(),
+                ACos => v.acos(),
+                ATan => v.atan(),
+                Sinh => v.sinh(),
+                Cosh => v.cosh(),
+                Tanh => v.tanh(),
+                Erf => unsafe { $erf(v) },
+                Abs => v.abs(),
+            }
+        }};
+    }
+    match *value {
+        Value::F32(v) => Ok(Value::F32(float_unaryop!(v, erff))),
+        Value::F64(v) => Ok(Value::F64(float_unaryop!(v, erf))),
+        _ => eval_err!(
+            CompileError,
+            "Expected floating-point value for unary op '{}'",
+            op
+        ),
+    }
+}
+
+/// Casts a scalar value to `kind`.
+///
+/// Integers are truncated or extended according to the signedness of the source type, and a
+/// cast to `bool` keeps the lowest bit, as in the generated code.
+fn cast(value: &Value, kind: ScalarKind) -> WeldResult<Value> {
+    use super::ast::ScalarKind::*;
+    let float = match *value {
+        Value::F32(v) => f64::from(v),
+        Value::F64(v) => v,
+        Value::Bool(_) if kind.is_float() => {
+            return eval_err!(CompileError, "Cannot cast {:?} to {}", value, kind);
+        }
+        _ => match value.as_integer() {
+            Some(v) => return Ok(Value::from_integer(v, kind)),
+            None => return eval_err!(CompileError, "Cannot cast {:?} to {}", value, kind),
+        },
+    };
+    let result = match kind {
+        I8 => Value::I8(float as i8),
+        I16 => Value::I16(float as i16),
+        I32 => Value::I32(float as i32),
+        I64 => Value::I64(float as i64),
+        U8 => Value::U8(float as u8),
+        U16 => Value::U16(float as u16),
+        U32 => Value::U32(float as u32),
+        U64 => Value::U64(float as u64),
+        F32 => Value::F32(float as f32),
+        F64 => Value::F64(float),
+        Bool => return eval_err!(CompileError, "Cannot cast {:?} to {}", value, kind),
+    };
+    Ok(result)
+}
+
+/// Parses, types, and evaluates a program.
+#[cfg(test)]
+fn eval_program(code: &str, args: &[Value]) -> WeldResult<Value> {
+    use super::InferTypes;
+    let mut expr = super::parse(code)?;
+    expr.infer_types()?;
+    expr.eval(args)
+}
+
+#[test]
+fn eval_scalar_expressions() {
+    let eval = |code: &str, args: &[Value]| eval_program(code, args).unwrap();
+    assert_eq!(eval("|| 1 + 2 * 3", &[]), Value::I32(7));
+    assert_eq!(
+        eval("|x:i32, y:i32| x / y", &[Value::I32(7), Value::I32(2)]),
+        Value::I32(3)
+    );
+    assert_eq!(eval("|x:i8| x + 1c", &[Value::I8(127)]), Value::I8(-128));
+    assert_eq!(eval("|x:i32| x << 33", &[Value::I32(1)]), Value::I32(2));
+    assert_eq!(
+        eval("|x:f64| x < 1.0 || x > 2.0", &[Value::F64(1.5)]),
+        Value::Bool(false)
+    );
+    assert_eq!(
+        eval("|x:i64| i32(x)", &[Value::I64(1 << 32 | 5)]),
+        Value::I32(5)
+    );
+    assert_eq!(
+        eval("|x:i8| u32(x)", &[Value::I8(-1)]),
+        Value::U32(u32::MAX)
+    );
+    assert_eq!(
+        eval("|x:i32| let y = x * 2; {y, sqrt(f64(y))}", &[Value::I32(8)]),
+        Value::Struct(vec![Value::I32(16), Value::F64(4.0)])
+    );
+    assert_eq!(
+        eval("|x:i32| switch(x, 1: 10, 2: 20, 0)", &[Value::I32(2)]),
+        Value::I32(20)
+    );
+    assert_eq!(
+        eval("|| iterate(1, |x| {x * 2, x < 100})", &[]),
+        Value::I32(256)
+    );
+    assert_eq!(
+        eval("|| let f = |a:i32| a + 1; f(f(1))", &[]),
+        Value::I32(3)
+    );
+}
+
+#[test]
+fn eval_builders() {
+    let vector = |elems: Vec<i32>| Value::vector(elems.into_iter().map(Value::I32).collect());
+    let eval = |code: &str, args: &[Value]| eval_program(code, args).unwrap();
+    let v = &[vector(vec![3, 1, 2, 3])];
+
+    assert_eq!(
+        eval("|v:vec[i32]| map(v, |e| e * 2)", v),
+        vector(vec![6, 2, 4, 6])
+    );
+    assert_eq!(
+        eval("|v:vec[i32]| filter(v, |e| e > 2)", v),
+        vector(vec![3, 3])
+    );
+    assert_eq!(
+        eval(
+            "|v:vec[i32]| result(for(v, merger[i32,max], |b,i,e| merge(b, e)))",
+            v
+        ),
+        Value::I32(3)
+    );
+    assert_eq!(
+        eval(
+            "|v:vec[i32]| result(for(iter(v, 0L, 4L, 2L), merger[i32,+](10), |b,i,e| merge(b, e)))",
+            v
+        ),
+        Value::I32(15)
+    );
+    assert_eq!(
+        eval(
+            "|v:vec[i32]| result(for(zip(v, v), appender[i32], |b,i,e| merge(b, e.$0 + e.$1)))",
+            v
+        ),
+        vector(vec![6, 2, 4, 6])
+    );
+    assert_eq!(
+        eval(
+            "|| result(for(rangeiter(0L, 10L, 3L), appender[i64], |b,i,e| merge(b, e)))",
+            &[]
+        ),
+        Value::vector(vec![
+            Value::I64(0),
+            Value::I64(3),
+            Value::I64(6),
+            Value::I64(9)
+        ])
+    );
+    assert_eq!(
+        eval(
+            "|v:vec[i32]| result(for(v, vecmerger[i32,+]([0,0]), |b,i,e| merge(b, {i % 2L, e})))",
+            v
+        ),
+        vector(vec![5, 4])
+    );
+    // Reads a row-major 2x3 matrix in column-major order.
+    assert_eq!(
+        eval(
+            "|v:vec[i32]| result(for(nditer(v, 0L, 6L, 1L, [3L, 2L], [1L, 3L]), appender[i32],
+                |b,i,e| merge(b, e)))",
+            &[vector(vec![1, 2, 3, 4, 5, 6])]
+        ),
+        vector(vec![1, 4, 2, 5, 3, 6])
+    );
+    assert_eq!(
+        eval("|v:vec[i32]| sort(v, |a:i32, b:i32| compare(a, b))", v),
+        vector(vec![1, 2, 3, 3])
+    );
+
+    let counts = eval(
+        "|v:vec[i32]| tovec(result(for(v, dictmerger[i32,i64,+], |b,i,e| merge(b, {e, 1L}))))",
+        v,
+    );
+    let pair = |k, v| Value::Struct(vec![Value::I32(k), Value::I64(v)]);
+    assert_eq!(
+        counts,
+        Value::vector(vec![pair(1, 1), pair(2, 1), pair(3, 2)])
+    );
+    assert_eq!(
+        eval(
+            "|v:vec[i32]| let d = result(for(v, groupmerger[i32,i64], |b,i,e| merge(b, {e, i})));
+            lookup(d, 3)",
+            v
+        ),
+        Value::vector(vec![Value::I64(0), Value::I64(3)])
+    );
+}
+
+#[test]
+fn eval_errors() {
+    let eval = eval_program;
+    let code = |code: &str, args: &[Value]| eval(code, args).unwrap_err().code();
+    assert_eq!(
+        code("|x:i32| 1 / x", &[Value::I32(0)]),
+        WeldRuntimeErrno::DivideByZero
+    );
+    assert_eq!(
+        code("|v:vec[i32]| lookup(v, 4L)", &[Value::vector(vec![])]),
+        WeldRuntimeErrno::ArrayOutOfBounds
+    );
+    assert_eq!(
+        code(
+            "|| result(for(rangeiter(0L, 4L, 0L), appender[i64], |b,i,e| merge(b, e)))",
+            &[]
+        ),
+        WeldRuntimeErrno::BadIteratorLength
+    );
+    assert_eq!(code("|x:i32| x", &[]), WeldRuntimeErrno::CompileError);
+    let d = &[Value::dict(vec![(Value::I32(1), Value::I32(2))])];
+    assert_eq!(
+        code("|d:dict[i32,i32]| lookup(d, 2)", d),
+        WeldRuntimeErrno::KeyNotFoundError
+    );
+    assert_eq!(
+        eval("|d:dict[i32,i32]| optlookup(d, 2)", d).unwrap(),
+        Value::Struct(vec![Value::Bool(false), Value::I32(0)])
+    );
+}
@@ -0,0 +1,144 @@
+//! Defines the `ExtractDefinition` trait on expressions.
+//!
+//! This trait extracts the definition of a symbol as a standalone program, which is useful for
+//! testing optimizations on a fragment of a larger program or for caching fragments.
+
+use super::ast::ExprKind::*;
+use super::ast::*;
+use super::builder::NewExpr;
+use crate::error::*;
+
+#[cfg(test)]
+use crate::tests::*;
+
+/// A trait that extracts the definition of a symbol from an expression.
+pub trait ExtractDefinition {
+    /// Returns a function that computes the value `symbol` is bound to by a `let`.
+    ///
+    /// The function's body includes the enclosing `let` definitions the value depends on, and the
+    /// function takes the remaining free variables (e.g., parameters of enclosing functions) as
+    /// parameters, ordered by name. If `symbol` is defined more than once, the first definition
+    /// is extracted. Returns an error if `symbol` is not defined by a `let`.
+    fn extract_definition(&self, symbol: &Symbol) -> WeldResult<Expr>;
+}
+
+impl ExtractDefinition for Expr {
+    fn extract_definition(&self, symbol: &Symbol) -> WeldResult<Expr> {
+        let mut scope = vec![];
+        let value = match find_definition(self, symbol, &mut scope) {
+            Some(value) => value,
+            None => return compile_err!("Symbol {} is not defined by a let", symbol),
+        };
+
+        // Collect the definitions the value depends on, innermost first. Symbols bound by
+        // enclosing functions become parameters.
+        let mut needed = value.free_variables();
+        let mut dependencies = vec![];
+        let mut free = vec![];
+        for &(name, definition) in scope.iter().rev() {
+            if needed.remove(name) {
+                match definition {
+                    Some(definition) => {
+                        needed.extend(definition.free_variables());
+                        dependencies.push((name, definition));
+                    }
+                    None => free.push(name.clone()),
+                }
+            }
+        }
+        free.extend(needed);
+
+        let mut body = value.clone();
+        for (name, definition) in dependencies {
+            body = Expr::new_let(name.clone(), definition.clone(), body)?;
+        }
+
+        free.sort();
+        let params = free
+            .into_iter()
+            .map(|name| {
+                let ty = symbol_type(&body, &name);
+                Parameter { name, ty }
+            })
+            .collect();
+        Expr::new_lambda(params, body)
+    }
+}
+
+/// Returns the value bound to `symbol`, adding the bindings in scope at its definition to `scope`.
+///
+/// Function parameters are added to the scope without a definition.
+fn find_definition<'a>(
+    expr: &'a Expr,
+    symbol: &Symbol,
+    scope: &mut Vec<(&'a Symbol, Option<&'a Expr>)>,
+) -> Option<&'a Expr> {
+    let depth = scope.len();
+    let found = match expr.kind {
+        Let {
+            ref name,
+            ref value,
+            ref body,
+        } => {
+            if name == symbol {
+                return Some(value);
+            }
+            find_definition(value, symbol, scope).or_else(|| {
+                scope.push((name, Some(value)));
+                find_definition(body, symbol, scope)
+            })
+        }
+        Lambda {
+            ref params,
+            ref body,
+        } => {
+            scope.extend(params.iter().map(|p| (&p.name, None)));
+            find_definition(body, symbol, scope)
+        }
+        _ => expr
+            .children()
+            .find_map(|child| find_definition(child, symbol, scope)),
+    };
+    if found.is_none() {
+        scope.truncate(depth);
+    }
+    found
+}
+
+/// Returns the type of the identifiers named `symbol` in `expr`.
+fn symbol_type(expr: &Expr, symbol: &Symbol) -> Type {
+    let mut ty = Type::Unknown;
+    expr.traverse(&mut |e| {
+        if let Ident(ref name) = e.kind {
+            if name == symbol {
+                ty = e.ty.clone();
+            }
+        }
+    });
+    ty
+}
+
+#[test]
+fn extract_let_definitions() {
+    let program =
+        typed_expression("|x:i32, y:i32| let a = x + 1; let b = y * 2; let c = a + 3; c + b");
+    let extracted = program.extract_definition(&Symbol::new("c", 0)).unwrap();
+    let expect = typed_expression("|x:i32| let a = x + 1; a + 3");
+    assert!(extracted.compare_ignoring_symbols(&expect).unwrap());
+
+    // Parameters of enclosing functions become parameters of the extracted function.
+    let program = typed_expression(
+        "|v:vec[i32], k:i32| result(for(v, merger[i32,+], |b,i,e| let t = e * k; merge(b, t)))",
+    );
+    let extracted = program.extract_definition(&Symbol::new("t", 0)).unwrap();
+    let expect = typed_expression("|e:i32, k:i32| e * k");
+    assert!(extracted.compare_ignoring_symbols(&expect).unwrap());
+
+    assert!(program.extract_definition(&Symbol::new("k", 0)).is_err());
+
+    // Parameters shadow definitions outside their function.
+    let program = typed_expression("|x:i32| let a = x; (|a:i32| let t = a + 1; t)(x)");
+    let extracted = program.extract_definition(&Symbol::new("t", 0)).unwrap();
+    let expect = typed_expression("|a:i32| a + 1");
+    assert!(extracted.compare_ignoring_symbols(&expect).unwrap());
+}
@@ -11,9 +11,9 @@ use fnv;
 use super::ast::ExprKind::*;
 use super::ast::LiteralKind::*;
 use super::ast::*;
+use super::cmp::CompareIgnoringSymbols;
 use crate::error::*;
 
-use std::collections::hash_map::Entry;
 use std::hash::{Hash, Hasher};
 
 use std::fmt;
@@ -25,7 +25,8 @@ pub trait HashIgnoringSymbols {
     /// This method is useful for comparing two ASTs for structural equality, e.g., to check if an
     /// optimization pass changed the AST modulo changing symbol names. Comparing using a hash
     /// value is generally faster than cloning a tree, changing it, and then checking if it
-    /// changed. This method may return an error if it encounters an undefined symbol.
+    /// changed. Symbols that are not defined within the AST are hashed by name, matching
+    /// `compare_ignoring_symbols`.
     fn hash_ignoring_symbols(&self) -> WeldResult<u64>;
 }
 
@@ -35,6 +36,30 @@ impl HashIgnoringSymbols for Expr {
     }
 }
 
+/// An expression that is hashed and compared ignoring symbol names.
+///
+/// This allows using expressions as keys in hash-based collections, where two keys are equal if
+/// their expressions are the same modulo the names of the symbols they define.
+#[derive(Clone, Debug)]
+pub struct SymbolAgnosticExpr(pub Expr);
+
+impl Hash for SymbolAgnosticExpr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Hashing only fails on malformed expressions, which can still be stored: they just share
+        // a hash value.
+        let value = self.0.hash_ignoring_symbols().unwrap_or_default();
+        state.write_u64(value);
+    }
+}
+
+impl PartialEq for SymbolAgnosticExpr {
+    fn eq(&self, other: &SymbolAgnosticExpr) -> bool {
+        self.0.compare_ignoring_symbols(&other.0).unwrap_or(false)
+    }
+}
+
+impl Eq for SymbolAgnosticExpr {}
+
 /// A signature which uniquely represents an Expression in a concise manner.
 struct ExprHash {
     hasher: fnv::FnvHasher,
@@ -79,15 +104,21 @@ impl ExprHash {
             },
             Ident(ref sym) => {
                 // We track symbols to disambiguate redefinitions, but also to ignore the actual
-                // textual symbol name. By hashing a number representing the symbol, expressions
-                // which are structurally the same but have different names will produce the same
-                // hash value.
-                match symbol_positions.entry(sym) {
-                    Entry::Occupied(ref ent) => {
-                        ent.get().hash(&mut self.hasher);
+                // textual symbol name. By hashing a number representing the innermost definition
+                // of the symbol, expressions which are structurally the same but have different
+                // names will produce the same hash value. Symbols that are not defined in the
+                // expression must match exactly, so their names are hashed instead.
+                match symbol_positions
+                    .get(sym)
+                    .and_then(|positions| positions.last())
+                {
+                    Some(position) => {
+                        true.hash(&mut self.hasher);
+                        position.hash(&mut self.hasher);
                     }
-                    _ => {
-                        return compile_err!("Undefined symbol {}", sym);
+                    None => {
+                        false.hash(&mut self.hasher);
+                        sym.hash(&mut self.hasher);
                     }
                 }
             }
@@ -103,6 +134,9 @@ impl ExprHash {
             GetField { ref index, .. } => {
                 index.hash(&mut self.hasher);
             }
+            GetNamedField { ref name, .. } => {
+                name.hash(&mut self.hasher);
+            }
             Let {
                 ref name,
                 ref value,
@@ -155,6 +189,11 @@ impl ExprHash {
                     iter.kind.hash(&mut self.hasher);
                 }
             }
+            Switch { ref cases, .. } => {
+                for case in cases.iter() {
+                    case.0.hash(&mut self.hasher);
+                }
+            }
             // Other expressions (listed explicitly so we don't forget to add new ones). If the
             // expression doesn't have a non-Expr field, it goes here.
             Negate(_)
@@ -242,3 +281,33 @@ fn test_lambda() {
     let b = &parse_expr("|a: i32| let a = 1; let c = 1; a").unwrap();
     assert_eq!(ExprHash::from(a).unwrap(), ExprHash::from(b).unwrap());
 }
+
+#[test]
+fn test_shadowed_symbols() {
+    let a = &parse_expr("|| let a = 1; let a = 2; a").unwrap();
+    let b = &parse_expr("|| let a = 1; let b = 2; b").unwrap();
+    assert_eq!(ExprHash::from(a).unwrap(), ExprHash::from(b).unwrap());
+}
+
+#[test]
+fn test_free_symbols() {
+    // Symbols that are not defined in the expression are compared by name.
+    let a = &parse_expr("|b: i32| a + b").unwrap();
+    let b = &parse_expr("|c: i32| a + c").unwrap();
+    let c = &parse_expr("|b: i32| d + b").unwrap();
+    assert_eq!(ExprHash::from(a).unwrap(), ExprHash::from(b).unwrap());
+    assert!(ExprHash::from(a).unwrap() != ExprHash::from(c).unwrap());
+}
+
+#[test]
+fn test_symbol_agnostic_keys() {
+    use std::collections::HashMap;
+    let mut map = HashMap::new();
+    let key = |code| SymbolAgnosticExpr(parse_expr(code).unwrap());
+    map.insert(key("|a: i32| a + x"), 1);
+    map.insert(key("|b: i32| b + x"), 2);
+    map.insert(key("|b: i32| b + y"), 3);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get(&key("|c: i32| c + x")), Some(&2));
+    assert_eq!(map.get(&key("|c: i32| c + y")), Some(&3));
+}
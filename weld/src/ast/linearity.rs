@@ -0,0 +1,225 @@
+//! Defines the `CheckLinearity` trait on expressions.
+//!
+//! Builders are linear: each builder value must be consumed by at most one `merge`, `for`,
+//! `result`, or `let` along every control path. Code generation updates builders in place, so a
+//! program that consumes a builder twice would otherwise be silently miscompiled.
+
+use super::ast::ExprKind::*;
+use super::ast::*;
+use crate::error::*;
+
+#[cfg(test)]
+use crate::tests::*;
+
+/// A trait that checks that builders are used linearly.
+pub trait CheckLinearity {
+    /// Returns an error if a builder in the expression may be consumed more than once.
+    ///
+    /// Fields of a struct of builders are tracked separately, so `b.$0` and `b.$1` may each be
+    /// consumed once. The expression must be typed.
+    fn check_linearity(&self) -> WeldResult<()>;
+}
+
+impl CheckLinearity for Expr {
+    fn check_linearity(&self) -> WeldResult<()> {
+        uses(self).map(|_| ())
+    }
+}
+
+/// A builder consumed by an expression: a symbol and a (possibly empty) path of struct fields.
+#[derive(Clone, PartialEq)]
+struct Use {
+    symbol: Symbol,
+    fields: Vec<u32>,
+}
+
+impl Use {
+    /// Returns whether `self` and `other` refer to overlapping builders.
+    fn overlaps(&self, other: &Use) -> bool {
+        self.symbol == other.symbol
+            && self
+                .fields
+                .iter()
+                .zip(other.fields.iter())
+                .all(|(a, b)| a == b)
+    }
+}
+
+impl std::fmt::Display for Use {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbol)?;
+        for field in self.fields.iter() {
+            write!(f, ".${}", field)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the free builders consumed by `expr`, or an error if one is consumed twice.
+fn uses(expr: &Expr) -> WeldResult<Vec<Use>> {
+    if let Some(u) = builder_use(expr) {
+        return Ok(vec![u]);
+    }
+    match expr.kind {
+        If {
+            ref cond,
+            ref on_true,
+            ref on_false,
+        } => {
+            let branches = either(uses(on_true)?, uses(on_false)?);
+            join(uses(cond)?, branches)
+        }
+        Switch {
+            ref value,
+            ref cases,
+            ref default,
+        } => {
+            let mut branches = uses(default)?;
+            for (_, case) in cases.iter() {
+                branches = either(branches, uses(case)?);
+            }
+            join(uses(value)?, branches)
+        }
+        Let {
+            ref name,
+            ref value,
+            ref body,
+        } => {
+            let body_uses = uses(body)?
+                .into_iter()
+                .filter(|u| u.symbol != *name)
+                .collect();
+            join(uses(value)?, body_uses)
+        }
+        Lambda {
+            ref params,
+            ref body,
+        } => Ok(uses(body)?
+            .into_iter()
+            .filter(|u| params.iter().all(|p| p.name != u.symbol))
+            .collect()),
+        For {
+            ref iters,
+            ref builder,
+            ref func,
+        } => {
+            let mut result = vec![];
+            for iter in iters.iter() {
+                for child in iter_children(iter) {
+                    result = join(result, uses(child)?)?;
+                }
+            }
+            // Builders captured by the loop body may only be the one the loop updates.
+            join(result, either(uses(builder)?, uses(func)?))
+        }
+        Iterate {
+            ref initial,
+            ref update_func,
+        } => Ok(either(uses(initial)?, uses(update_func)?)),
+        _ => {
+            let mut result = vec![];
+            for child in expr.children() {
+                result = join(result, uses(child)?)?;
+            }
+            Ok(result)
+        }
+    }
+}
+
+/// Returns the expressions of an iterator.
+fn iter_children(iter: &Iter) -> Vec<&Expr> {
+    let mut children = vec![iter.data.as_ref()];
+    let optional = [
+        &iter.start,
+        &iter.end,
+        &iter.stride,
+        &iter.shape,
+        &iter.strides,
+    ];
+    children.extend(
+        optional
+            .iter()
+            .filter_map(|e| e.as_ref().map(|e| e.as_ref())),
+    );
+    children
+}
+
+/// Returns the builder `expr` refers to if it is a symbol or a field of one with a builder type.
+fn builder_use(expr: &Expr) -> Option<Use> {
+    if !expr.ty.contains_builder() {
+        return None;
+    }
+    match expr.kind {
+        Ident(ref symbol) => Some(Use {
+            symbol: symbol.clone(),
+            fields: vec![],
+        }),
+        GetField {
+            expr: ref inner,
+            index,
+        } => {
+            let mut u = builder_use(inner)?;
+            u.fields.push(index);
+            Some(u)
+        }
+        _ => None,
+    }
+}
+
+/// Combines the uses of two expressions that are both evaluated.
+fn join(mut first: Vec<Use>, second: Vec<Use>) -> WeldResult<Vec<Use>> {
+    // The uses of each expression were already checked against each other.
+    for u in second.iter() {
+        if first.iter().any(|f| f.overlaps(u)) {
+            return compile_err!("Builder {} is consumed more than once", u);
+        }
+    }
+    first.extend(second);
+    Ok(first)
+}
+
+/// Combines the uses of two expressions of which only one is evaluated.
+fn either(mut first: Vec<Use>, second: Vec<Use>) -> Vec<Use> {
+    for u in second {
+        if !first.contains(&u) {
+            first.push(u);
+        }
+    }
+    first
+}
+
+#[test]
+fn linear_builders() {
+    let codes = [
+        "|v:vec[i32]| result(for(v, appender[i32], |b,i,e| merge(b, e)))",
+        "|x:i32| let b = appender[i32]; result(if(x > 0, merge(b, x), b))",
+        "|x:i32| let b = {appender[i32], merger[i32,+]}; let c = merge(b.$0, x);
+            {result(c), result(merge(b.$1, x))}",
+        "|v:vec[i32]| for(v, {appender[i32], merger[i32,+]}, |b,i,e| {merge(b.$0, e), b.$1})",
+        "|v:vec[vec[i32]]| result(for(v, merger[i32,+], |b,i,e| for(e, b, |b2,j,f| merge(b2, f))))",
+        "|v:vec[i32]| result(for(v, appender[i32], |b0,i,x| for(v, b0, |b1,j,y| merge(b0, y))))",
+        "|v:vec[i32]| for(v, {appender[i32], merger[i32,+]}, |b,i,e|
+            if(e > 0, {merge(b.$0, e), merge(b.$1, e)}, b))",
+    ];
+    for code in codes.iter() {
+        assert!(typed_expression(code).check_linearity().is_ok(), "{}", code);
+    }
+}
+
+#[test]
+fn nonlinear_builders() {
+    let codes = [
+        "|x:i32| let b = appender[i32]; {result(merge(b, x)), result(merge(b, x))}",
+        "|x:i32| let b = appender[i32]; let c = b; {result(merge(c, x)), result(b)}",
+        "|x:i32| let b = {appender[i32], merger[i32,+]}; let c = b; {result(b.$0), result(c.$1)}",
+        "|x:i32| let b = appender[i32]; let c = if(x > 0, merge(b, x), b); {result(b), result(c)}",
+        "|v:vec[i32]| let b = merger[i32,+]; {result(for(v, b, |b2,i,e| merge(b, e))), result(b)}",
+    ];
+    for code in codes.iter() {
+        assert!(
+            typed_expression(code).check_linearity().is_err(),
+            "{}",
+            code
+        );
+    }
+}
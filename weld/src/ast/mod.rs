@@ -2,23 +2,79 @@
 //!
 //! Most of Weld's optimizations occur over the AST, which doubles as a "High-Level IR." The AST
 //! captures the expressions in Weld using a tree data structure.
+//!
+//! When the `serde` feature is enabled, the AST types (`Expr`, `Type`, `Symbol`, etc.)
+//! implement `serde::Serialize` and `serde::Deserialize`, so programs can be stored and exchanged
+//! without going through the text syntax.
 
 pub use self::ast::*;
 
 // Various convinience methods on the AST.
 pub use self::builder::NewExpr;
 pub use self::cmp::CompareIgnoringSymbols;
-pub use self::hash::HashIgnoringSymbols;
-pub use self::pretty_print::{PrettyPrint, PrettyPrintConfig};
+pub use self::dot::to_dot;
+pub use self::eval::{Eval, Value};
+pub use self::extract::ExtractDefinition;
+pub use self::hash::{HashIgnoringSymbols, SymbolAgnosticExpr};
+pub use self::linearity::CheckLinearity;
+pub use self::pretty_print::{print_expr, print_typed_expr, PrettyPrint, PrettyPrintConfig};
 pub use self::type_inference::InferTypes;
 pub use self::uniquify::Uniquify;
 
 pub mod prelude;
 
+/// Parses a Weld program into an expression.
+///
+/// The program may define macros and type aliases before its body; these are expanded and
+/// substituted, so the result contains only core expressions. The returned expression is
+/// untyped: types that are not written explicitly in the program are `Unknown` until
+/// `InferTypes::infer_types` is called.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate weld;
+/// use weld::ast::*;
+///
+/// let mut expr = parse("|v: vec[i32]| map(v, |x| x + 1)").unwrap();
+/// expr.infer_types().unwrap();
+/// assert_eq!(expr.ty.to_string(), "|vec[i32]|(vec[i32])");
+/// ```
+pub fn parse(code: &str) -> crate::error::WeldResult<Expr> {
+    let program = crate::syntax::parser::parse_program(code)?;
+    crate::syntax::macro_processor::process_program(&program)
+}
+
+/// Parses a Weld type, such as `vec[{i32,f64}]`.
+pub fn parse_type(code: &str) -> crate::error::WeldResult<Type> {
+    crate::syntax::parser::parse_type(code)
+}
+
 mod ast;
 mod builder;
 mod cmp;
+mod dot;
+mod eval;
+mod extract;
 mod hash;
+mod linearity;
 mod pretty_print;
 mod type_inference;
 mod uniquify;
+
+#[test]
+fn parse_programs() {
+    let mut expr = parse("type pair = {i32,i32}; |v: vec[pair]| map(v, |x| x.$0)").unwrap();
+    expr.infer_types().unwrap();
+    assert_eq!(expr.ty.to_string(), "|vec[{i32,i32}]|(vec[i32])");
+    assert_eq!(
+        parse_type("vec[{i32,f64}]").unwrap(),
+        Type::Vector(Box::new(Type::Struct(
+            vec![Type::Scalar(ScalarKind::I32), Type::Scalar(ScalarKind::F64)],
+            FieldNames::none()
+        )))
+    );
+
+    assert!(parse("|x: i32| x +").is_err());
+    assert!(parse_type("vec[").is_err());
+}
@@ -6,11 +6,14 @@
 use crate::ast::ExprKind::*;
 use crate::ast::*;
 
-use crate::util::join;
+use std::fmt::Write;
 
 /// The number of spaces used when indenting code.
 const INDENT_LEVEL: i32 = 2;
 
+/// The maximum line width used by `PrettyPrint::pretty_print`.
+const MAX_WIDTH: usize = 100;
+
 /// A trait for pretty printing expression trees.
 pub trait PrettyPrint {
     /// Pretty print an expression.
@@ -32,7 +35,8 @@ impl PrettyPrint for Expr {
     fn pretty_print(&self) -> String {
         let mut config = PrettyPrintConfig::default()
             .should_indent(true)
-            .show_types(false);
+            .show_types(false)
+            .max_width(MAX_WIDTH);
         to_string_impl(self, &mut config)
     }
 
@@ -44,6 +48,29 @@ impl PrettyPrint for Expr {
     }
 }
 
+/// Prints an expression on a single line.
+///
+/// Types are only shown for the parameters of a top-level function, so the printed program can
+/// be re-parsed and type checked.
+pub fn print_expr(expr: &Expr) -> String {
+    let config = PrettyPrintConfig::default()
+        .show_types(false)
+        .should_indent(false);
+    expr.pretty_print_config(&config)
+}
+
+/// Prints an expression on a single line, annotating each symbol with its type.
+///
+/// Re-parsing the printed program and inferring its types produces the original expression.
+/// Function types cannot be written in Weld, so symbols that refer to functions are printed
+/// without a type.
+pub fn print_typed_expr(expr: &Expr) -> String {
+    let config = PrettyPrintConfig::default()
+        .show_types(true)
+        .should_indent(false);
+    expr.pretty_print_config(&config)
+}
+
 /// A struct used to configure pretty printing.
 ///
 /// This struct is used with the `PrettyPrint::pretty_print_config` function to configure how the
@@ -54,10 +81,16 @@ pub struct PrettyPrintConfig {
     pub show_types: bool,
     /// Specifies whether to indent code.
     pub should_indent: bool,
+    /// The line width within which indented expressions are printed on a single line.
+    ///
+    /// If this is `None`, every expression that can span multiple lines does so.
+    pub max_width: Option<usize>,
     /// Tracks the indentation level.
     indent: i32,
     /// Specifies whether this is the top of the expression tree.
     top: bool,
+    /// The output length past which a single-line attempt is abandoned.
+    limit: usize,
 }
 
 impl std::default::Default for PrettyPrintConfig {
@@ -68,8 +101,10 @@ impl std::default::Default for PrettyPrintConfig {
         PrettyPrintConfig {
             show_types: false,
             should_indent: true,
+            max_width: None,
             indent: INDENT_LEVEL,
             top: true,
+            limit: usize::MAX,
         }
     }
 }
@@ -86,6 +121,12 @@ impl PrettyPrintConfig {
         self.should_indent = should_indent;
         self
     }
+
+    /// Set the line width within which indented expressions are printed on a single line.
+    pub fn max_width(mut self, max_width: usize) -> PrettyPrintConfig {
+        self.max_width = Some(max_width);
+        self
+    }
 }
 
 /// Returns strings used for indentation.
@@ -105,73 +146,201 @@ fn indentation(config: &PrettyPrintConfig) -> (String, String, String) {
     }
 }
 
-/// Prints a list of iterators for a For loop.
-fn print_iters(iters: &[Iter], config: &mut PrettyPrintConfig) -> String {
-    use self::IterKind::*;
-    let mut iter_strs = vec![];
+/// Prints an expression into a new string.
+fn to_string_impl(expr: &Expr, config: &mut PrettyPrintConfig) -> String {
+    let mut out = String::new();
+    write_expr(expr, config, &mut out);
+    out
+}
+
+/// Tries to print on a single line with `print`, keeping the output if it fits within the line
+/// width.
+///
+/// Returns `false` and leaves `out` unchanged if the configuration does not limit the line width
+/// or if the output does not fit. The attempt is abandoned as soon as the output passes the width,
+/// so an expression is not printed in full at every level of the tree.
+fn try_single_line<F>(config: &mut PrettyPrintConfig, out: &mut String, print: F) -> bool
+where
+    F: FnOnce(&mut PrettyPrintConfig, &mut String),
+{
+    let max_width = match (config.should_indent, config.max_width) {
+        (true, Some(max_width)) => max_width,
+        _ => return false,
+    };
+    let start = out.len();
+    let mut flat_config = config.clone().should_indent(false);
+    // The line starts at the indentation of the enclosing expression.
+    flat_config.limit = start + max_width.saturating_sub((config.indent - 2) as usize);
+    print(&mut flat_config, out);
+    if out.len() <= flat_config.limit {
+        config.top = false;
+        true
+    } else {
+        out.truncate(start);
+        false
+    }
+}
+
+/// Prints `exprs` separated by commas between `open` and `close`.
+fn write_args(
+    open: &str,
+    exprs: &[&Expr],
+    close: &str,
+    config: &mut PrettyPrintConfig,
+    out: &mut String,
+) {
+    out.push_str(open);
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_expr(expr, config, out);
+    }
+    out.push_str(close);
+}
+
+/// Prints `exprs` separated by commas after `open`, placing each expression on its own line when
+/// indenting.
+fn write_block(open: &str, exprs: &[&Expr], config: &mut PrettyPrintConfig, out: &mut String) {
     let (less_indent_str, indent_str, newline) = indentation(config);
-    for iter in iters {
-        match iter.kind {
-            NdIter => {
-                /* Need to check this first because NdIter also has iter.start */
-                iter_strs.push(format!(
-                    "{}({},{},{},{})",
-                    iter.kind,
-                    to_string_impl(iter.data.as_ref(), config),
-                    to_string_impl(iter.start.as_ref().unwrap(), config),
-                    to_string_impl(iter.shape.as_ref().unwrap(), config),
-                    to_string_impl(iter.strides.as_ref().unwrap(), config),
-                ));
-            }
-            _ if iter.start.is_some() => {
-                iter_strs.push(format!(
-                    "{}({},{},{},{})",
-                    iter.kind,
-                    to_string_impl(iter.data.as_ref(), config),
-                    to_string_impl(iter.start.as_ref().unwrap(), config),
-                    to_string_impl(iter.end.as_ref().unwrap(), config),
-                    to_string_impl(iter.stride.as_ref().unwrap(), config)
-                ));
-            }
-            ScalarIter => {
-                // don't print the Iter for ScalarIter for conciseness.
-                iter_strs.push(to_string_impl(iter.data.as_ref(), config));
-            }
-            _ => {
-                iter_strs.push(format!(
-                    "{}({})",
-                    iter.kind,
-                    to_string_impl(iter.data.as_ref(), config)
-                ));
+    config.indent += INDENT_LEVEL;
+    out.push_str(open);
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&newline);
+        out.push_str(&indent_str);
+        write_expr(expr, config, out);
+    }
+    out.push_str(&newline);
+    out.push_str(&less_indent_str);
+    out.push(')');
+    config.indent -= INDENT_LEVEL;
+}
+
+/// Prints a single iterator of a For loop.
+fn write_iter(iter: &Iter, config: &mut PrettyPrintConfig, out: &mut String) {
+    use self::IterKind::*;
+    let open = format!("{}(", iter.kind);
+    match iter.kind {
+        NdIter => {
+            /* Need to check this first because NdIter also has iter.start */
+            let exprs = [
+                iter.data.as_ref(),
+                iter.start.as_ref().unwrap(),
+                iter.end.as_ref().unwrap(),
+                iter.stride.as_ref().unwrap(),
+                iter.shape.as_ref().unwrap(),
+                iter.strides.as_ref().unwrap(),
+            ];
+            write_args(&open, &exprs, ")", config, out);
+        }
+        ChunkIter => {
+            let elem_ty = match iter.data.ty {
+                Type::Vector(ref elem) => elem.as_ref(),
+                _ => &Type::Unknown,
+            };
+            let open = format!("{}[{}](", iter.kind, elem_ty);
+            write_args(&open, &[iter.start.as_ref().unwrap()], ")", config, out);
+        }
+        _ if iter.start.is_some() => {
+            let exprs = [
+                iter.data.as_ref(),
+                iter.start.as_ref().unwrap(),
+                iter.end.as_ref().unwrap(),
+                iter.stride.as_ref().unwrap(),
+            ];
+            write_args(&open, &exprs, ")", config, out);
+        }
+        // don't print the Iter for ScalarIter for conciseness.
+        ScalarIter => write_expr(&iter.data, config, out),
+        _ => write_args(&open, &[iter.data.as_ref()], ")", config, out),
+    }
+}
+
+/// Prints the iterators of a For loop, zipping them if there are several.
+fn write_iters(iters: &[Iter], config: &mut PrettyPrintConfig, out: &mut String) {
+    if iters.len() == 1 {
+        write_iter(&iters[0], config, out);
+        return;
+    }
+    let print = |config: &mut PrettyPrintConfig, out: &mut String| {
+        let (less_indent_str, indent_str, newline) = indentation(config);
+        out.push_str("zip(");
+        for (i, iter) in iters.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
             }
+            out.push_str(&newline);
+            out.push_str(&indent_str);
+            write_iter(iter, config, out);
         }
+        out.push_str(&newline);
+        out.push_str(&less_indent_str);
+        out.push(')');
+    };
+    if !try_single_line(config, out, print) {
+        print(config, out);
     }
+}
 
-    if iters.len() > 1 {
-        format!(
-            "zip({}{}{}{}{})",
-            newline,
-            iter_strs.join(&format!(",{}{}", newline, indent_str)),
-            indent_str,
-            newline,
-            less_indent_str
-        )
-    } else {
-        iter_strs[0].clone()
+/// Prints the expressions in `exprs` separated by commas between `open` and `close`.
+///
+/// If the configuration limits the line width, each expression is placed on its own line.
+fn write_list(
+    open: &str,
+    exprs: &[&Expr],
+    close: &str,
+    config: &mut PrettyPrintConfig,
+    out: &mut String,
+) {
+    if !config.should_indent || config.max_width.is_none() {
+        write_args(open, exprs, close, config, out);
+        return;
     }
+    let (less_indent_str, indent_str, newline) = indentation(config);
+    config.indent += INDENT_LEVEL;
+    out.push_str(open);
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&newline);
+        out.push_str(&indent_str);
+        write_expr(expr, config, out);
+    }
+    out.push_str(&newline);
+    out.push_str(&less_indent_str);
+    out.push_str(close);
+    config.indent -= INDENT_LEVEL;
 }
 
-/// Pretty print each expression in an AST recursively.
-fn to_string_impl(expr: &Expr, config: &mut PrettyPrintConfig) -> String {
+/// Pretty print each expression in an AST recursively, appending the output to `out`.
+fn write_expr(expr: &Expr, config: &mut PrettyPrintConfig, out: &mut String) {
+    // The output already passed the width of a single-line attempt, which will be discarded.
+    if out.len() > config.limit {
+        return;
+    }
+
+    // Print the expression on one line if it fits.
+    if try_single_line(config, out, |config, out| write_expr(expr, config, out)) {
+        return;
+    }
+
     let (less_indent_str, indent_str, newline) = indentation(config);
     let top = config.top;
     config.top = false;
-    let expr_str = match expr.kind {
-        Literal(ref lit) => lit.to_string(),
+    write!(out, "{}", expr.annotations).unwrap();
+    match expr.kind {
+        Literal(ref lit) => write!(out, "{}", lit).unwrap(),
 
-        Ident(ref symbol) if config.show_types => format!("{}:{}", symbol, expr.ty),
+        // Function types cannot be written in Weld, so they are never shown.
+        Ident(ref symbol) if config.show_types && !expr.ty.is_function() => {
+            write!(out, "{}:{}", symbol, expr.ty).unwrap()
+        }
 
-        Ident(ref symbol) => symbol.to_string(),
+        Ident(ref symbol) => write!(out, "{}", symbol).unwrap(),
 
         BinOp {
             kind,
@@ -179,161 +348,173 @@ fn to_string_impl(expr: &Expr, config: &mut PrettyPrintConfig) -> String {
             ref right,
         } => {
             use super::ast::BinOpKind::*;
-            let left = to_string_impl(left, config);
-            let right = to_string_impl(right, config);
             match kind {
-                Max | Min | Pow => format!("{}({},{})", kind, left, right),
-                _ => format!("({}{}{})", left, kind, right),
+                Max | Min | Pow => {
+                    write_args(&format!("{}(", kind), &[left, right], ")", config, out)
+                }
+                _ => {
+                    out.push('(');
+                    write_expr(left, config, out);
+                    write!(out, "{}", kind).unwrap();
+                    write_expr(right, config, out);
+                    out.push(')');
+                }
             }
         }
 
-        UnaryOp { kind, ref value } => format!("({}({}))", kind, to_string_impl(value, config)),
+        UnaryOp { kind, ref value } => {
+            write_args(&format!("({}(", kind), &[value], "))", config, out)
+        }
 
-        Negate(ref e) => format!("(-{})", to_string_impl(e, config)),
+        Negate(ref e) => write_args("(-", &[e], ")", config, out),
 
-        Not(ref e) => format!("(!{})", to_string_impl(e, config)),
+        Not(ref e) => write_args("(!", &[e], ")", config, out),
 
-        Assert(ref e) => format!("assert({})", to_string_impl(e, config)),
+        Assert(ref e) => write_args("assert(", &[e], ")", config, out),
 
-        Broadcast(ref e) => format!("broadcast({})", to_string_impl(e, config)),
+        Broadcast(ref e) => write_args("broadcast(", &[e], ")", config, out),
 
         CUDF {
             ref sym_name,
             ref args,
             ref return_ty,
         } => {
-            let args = join(
-                "(",
-                ",",
-                ")",
-                args.iter().map(|e| to_string_impl(e, config)),
-            );
-            format!("cudf[{},{}]{}", sym_name, return_ty, args)
+            let args: Vec<_> = args.iter().collect();
+            let open = format!("cudf[{},{}](", sym_name, return_ty);
+            write_args(&open, &args, ")", config, out)
         }
 
-        Serialize(ref e) => format!("serialize({})", to_string_impl(e, config)),
+        Serialize(ref e) => write_args("serialize(", &[e], ")", config, out),
 
         Deserialize {
             ref value,
             ref value_ty,
-        } => format!(
-            "deserialize[{}]({})",
-            value_ty,
-            to_string_impl(value, config)
-        ),
+        } => {
+            let open = format!("deserialize[{}](", value_ty);
+            write_args(&open, &[value], ")", config, out)
+        }
 
         Cast {
             kind,
             ref child_expr,
-        } => format!("({}({}))", kind, to_string_impl(child_expr, config)),
-
-        ToVec { ref child_expr } => format!("tovec({})", to_string_impl(child_expr, config)),
+        } => write_args(&format!("({}(", kind), &[child_expr], "))", config, out),
+
+        ToVec { ref child_expr } => write_args("tovec(", &[child_expr], ")", config, out),
+
+        // Print a chain of lets with one binding on each line.
+        Let { .. } if config.should_indent => {
+            let align = " ".repeat((config.indent - 1) as usize);
+            let mut expr = expr;
+            out.push('(');
+            while let Let {
+                ref name,
+                ref value,
+                ref body,
+            } = expr.kind
+            {
+                if config.show_types && !value.ty.is_function() {
+                    write!(out, "let {}:{}=(", name, value.ty).unwrap();
+                } else {
+                    write!(out, "let {}=(", name).unwrap();
+                }
+                config.indent += INDENT_LEVEL;
+                write_expr(value, config, out);
+                config.indent -= INDENT_LEVEL;
+                out.push_str(");");
+                out.push_str(&newline);
+                out.push_str(&align);
+                expr = body;
+                if !expr.annotations.is_empty() {
+                    break;
+                }
+            }
+            config.indent += 1;
+            write_expr(expr, config, out);
+            config.indent -= 1;
+            out.push(')');
+        }
 
         Let {
             ref name,
             ref value,
             ref body,
         } => {
-            let value_str = to_string_impl(value, config);
-            let body = to_string_impl(body, config);
-            if config.show_types {
-                format!(
-                    "(let {}:{}=({});{}{}{})",
-                    name, value.ty, value_str, newline, indent_str, body
-                )
+            if config.show_types && !value.ty.is_function() {
+                write!(out, "(let {}:{}=(", name, value.ty).unwrap();
             } else {
-                format!(
-                    "(let {}=({});{}{}{})",
-                    name, value_str, newline, indent_str, body
-                )
+                write!(out, "(let {}=(", name).unwrap();
             }
+            write_expr(value, config, out);
+            out.push_str(");");
+            out.push_str(&newline);
+            out.push_str(&indent_str);
+            write_expr(body, config, out);
+            out.push(')');
         }
 
-        MakeStruct { ref elems } => join(
-            "{",
-            ",",
-            "}",
-            elems.iter().map(|e| to_string_impl(e, config)),
-        ),
+        MakeStruct { ref elems } => {
+            let elems: Vec<_> = elems.iter().collect();
+            write_list("{", &elems, "}", config, out)
+        }
 
-        MakeVector { ref elems } => join(
-            "[",
-            ",",
-            "]",
-            elems.iter().map(|e| to_string_impl(e, config)),
-        ),
+        MakeVector { ref elems } => {
+            let elems: Vec<_> = elems.iter().collect();
+            write_list("[", &elems, "]", config, out)
+        }
 
         Zip { ref vectors } => {
-            let begin = &format!("zip({}{}", newline, indent_str);
-            let newlines = &format!(",{}{}", newline, indent_str);
-            join(
-                begin,
-                newlines,
-                ")",
-                vectors.iter().map(&mut |e| {
-                    config.indent += INDENT_LEVEL;
-                    let result = to_string_impl(e, config);
-                    config.indent -= INDENT_LEVEL;
-                    result
-                }),
-            )
+            out.push_str("zip(");
+            for (i, e) in vectors.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&newline);
+                out.push_str(&indent_str);
+                config.indent += INDENT_LEVEL;
+                write_expr(e, config, out);
+                config.indent -= INDENT_LEVEL;
+            }
+            out.push(')');
         }
 
         GetField { ref expr, index } => {
-            // If the expression is a symbol, don't show its type in a GetField.
-            if config.show_types {
-                if let Ident(ref symbol) = expr.kind {
-                    return format!("{}.${}", symbol, index);
-                }
+            match expr.kind {
+                // If the expression is a symbol, don't show its type in a GetField.
+                Ident(ref symbol) if config.show_types => write!(out, "{}", symbol).unwrap(),
+                _ => write_expr(expr, config, out),
             }
-            format!("{}.${}", to_string_impl(expr, config), index)
+            write!(out, ".${}", index).unwrap();
+        }
+
+        GetNamedField { ref expr, ref name } => {
+            write_expr(expr, config, out);
+            write!(out, ".{}", name).unwrap();
         }
 
-        Length { ref data } => format!("len({})", to_string_impl(data, config)),
+        Length { ref data } => write_args("len(", &[data], ")", config, out),
 
         Lookup {
             ref data,
             ref index,
-        } => format!(
-            "lookup({},{})",
-            to_string_impl(data, config),
-            to_string_impl(index, config)
-        ),
+        } => write_args("lookup(", &[data, index], ")", config, out),
 
         OptLookup {
             ref data,
             ref index,
-        } => format!(
-            "optlookup({},{})",
-            to_string_impl(data, config),
-            to_string_impl(index, config)
-        ),
-
-        KeyExists { ref data, ref key } => format!(
-            "keyexists({},{})",
-            to_string_impl(data, config),
-            to_string_impl(key, config)
-        ),
+        } => write_args("optlookup(", &[data, index], ")", config, out),
+
+        KeyExists { ref data, ref key } => write_args("keyexists(", &[data, key], ")", config, out),
 
         Slice {
             ref data,
             ref index,
             ref size,
-        } => format!(
-            "slice({},{},{})",
-            to_string_impl(data, config),
-            to_string_impl(index, config),
-            to_string_impl(size, config)
-        ),
+        } => write_args("slice(", &[data, index, size], ")", config, out),
 
         Sort {
             ref data,
             ref cmpfunc,
-        } => format!(
-            "sort({},{})",
-            to_string_impl(data, config),
-            to_string_impl(cmpfunc, config)
-        ),
+        } => write_args("sort(", &[data, cmpfunc], ")", config, out),
 
         Lambda {
             ref params,
@@ -341,48 +522,36 @@ fn to_string_impl(expr: &Expr, config: &mut PrettyPrintConfig) -> String {
         } => {
             // Print types for a top-level Lambda even if show_types is disabled - this allows
             // type inference to infer the remaining types in the program.
-            let mut res = join(
-                "|",
-                ",",
-                "|",
-                params.iter().map(|e| {
-                    if top || config.show_types {
-                        e.to_string()
-                    } else {
-                        e.name.to_string()
-                    }
-                }),
-            );
+            out.push('|');
+            for (i, param) in params.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                if top || config.show_types {
+                    write!(out, "{}", param).unwrap();
+                } else {
+                    write!(out, "{}", param.name).unwrap();
+                }
+            }
+            out.push('|');
+            out.push_str(&newline);
+            out.push_str(&indent_str);
             config.indent += INDENT_LEVEL;
-            let body = to_string_impl(body, config);
+            write_expr(body, config, out);
             config.indent -= INDENT_LEVEL;
-            res.push_str(&format!("{}{}{}", newline, indent_str, body));
-            res
         }
 
         NewBuilder(ref arg) => match *arg {
-            Some(ref e) => format!("{}({})", expr.ty, to_string_impl(e, config)),
-            None => expr.ty.to_string(),
+            Some(ref e) => write_args(&format!("{}(", expr.ty), &[e], ")", config, out),
+            None => write!(out, "{}", expr.ty).unwrap(),
         },
 
-        Res { ref builder } => {
-            config.indent += INDENT_LEVEL;
-            let builder = to_string_impl(builder, config);
-            config.indent -= INDENT_LEVEL;
-            format!(
-                "result({}{}{}{}{})",
-                newline, indent_str, builder, newline, less_indent_str
-            )
-        }
+        Res { ref builder } => write_block("result(", &[builder], config, out),
 
         Merge {
             ref builder,
             ref value,
-        } => format!(
-            "merge({},{})",
-            to_string_impl(builder, config),
-            to_string_impl(value, config)
-        ),
+        } => write_list("merge(", &[builder, value], ")", config, out),
 
         For {
             ref iters,
@@ -390,101 +559,160 @@ fn to_string_impl(expr: &Expr, config: &mut PrettyPrintConfig) -> String {
             ref func,
         } => {
             config.indent += INDENT_LEVEL;
-            let res = format!(
-                "for({}{}{},{}{}{},{}{}{}{}{})",
-                newline,
-                indent_str,
-                print_iters(iters, config),
-                newline,
-                indent_str,
-                to_string_impl(builder, config),
-                newline,
-                indent_str,
-                to_string_impl(func, config),
-                newline,
-                less_indent_str
-            );
+            out.push_str("for(");
+            out.push_str(&newline);
+            out.push_str(&indent_str);
+            write_iters(iters, config, out);
+            for e in [builder, func].iter() {
+                out.push(',');
+                out.push_str(&newline);
+                out.push_str(&indent_str);
+                write_expr(e, config, out);
+            }
+            out.push_str(&newline);
+            out.push_str(&less_indent_str);
+            out.push(')');
             config.indent -= INDENT_LEVEL;
-            res
         }
 
         If {
             ref cond,
             ref on_true,
             ref on_false,
+        } => write_block("if(", &[cond, on_true, on_false], config, out),
+
+        Switch {
+            ref value,
+            ref cases,
+            ref default,
         } => {
             config.indent += INDENT_LEVEL;
-            let res = format!(
-                "if({}{}{},{}{}{},{}{}{}{}{})",
-                newline,
-                indent_str,
-                to_string_impl(cond, config),
-                newline,
-                indent_str,
-                to_string_impl(on_true, config),
-                newline,
-                indent_str,
-                to_string_impl(on_false, config),
-                newline,
-                less_indent_str
-            );
+            out.push_str("switch(");
+            out.push_str(&newline);
+            out.push_str(&indent_str);
+            write_expr(value, config, out);
+            for (constant, branch) in cases.iter() {
+                write!(out, ",{}{}{}:", newline, indent_str, constant).unwrap();
+                write_expr(branch, config, out);
+            }
+            out.push(',');
+            out.push_str(&newline);
+            out.push_str(&indent_str);
+            write_expr(default, config, out);
+            out.push_str(&newline);
+            out.push_str(&less_indent_str);
+            out.push(')');
             config.indent -= INDENT_LEVEL;
-            res
         }
 
         Iterate {
             ref initial,
             ref update_func,
-        } => {
-            config.indent += INDENT_LEVEL;
-            let res = format!(
-                "iterate({}{}{},{}{}{}{}{})",
-                newline,
-                indent_str,
-                to_string_impl(initial, config),
-                newline,
-                indent_str,
-                to_string_impl(update_func, config),
-                newline,
-                less_indent_str
-            );
-            config.indent -= INDENT_LEVEL;
-            res
-        }
+        } => write_block("iterate(", &[initial, update_func], config, out),
 
         Select {
             ref cond,
             ref on_true,
             ref on_false,
-        } => {
-            config.indent += INDENT_LEVEL;
-            let res = format!(
-                "select({}{}{},{}{}{},{}{}{}{}{})",
-                newline,
-                indent_str,
-                to_string_impl(cond, config),
-                newline,
-                indent_str,
-                to_string_impl(on_true, config),
-                newline,
-                indent_str,
-                to_string_impl(on_false, config),
-                newline,
-                less_indent_str
-            );
-            config.indent -= INDENT_LEVEL;
-            res
-        }
+        } => write_block("select(", &[cond, on_true, on_false], config, out),
 
         Apply {
             ref func,
             ref params,
         } => {
-            let mut res = format!("({})", to_string_impl(func, config));
-            let params = params.iter().map(|e| to_string_impl(e, config));
-            res.push_str(&join("(", ",", ")", params));
-            res
+            write_args("(", &[func], ")", config, out);
+            let params: Vec<_> = params.iter().collect();
+            write_args("(", &params, ")", config, out);
         }
-    };
-    format!("{}{}", expr.annotations, expr_str)
+    }
+}
+
+#[test]
+fn round_trip() {
+    use crate::syntax::parser::parse_expr;
+    let programs = [
+        "|a:i32,b:i64| let c = a + 1; i64(c) * b - 2L",
+        "|v:vec[i32]| result(for(v, appender[i32], |b,i,e| merge(b, e * 2)))",
+        "|v:vec[i32],w:vec[f32]| result(for(zip(v, w), merger[f32,+], |b,i,e| merge(b, f32(e.$0) * e.$1)))",
+        "|v:vec[i32]| result(for(iter(v, 0L, 4L, 2L), vecmerger[i32,*](v), |b,i,e| merge(b, {i, e})))",
+        "|x:f64| if(x > 0.5, sqrt(x), -x) + min(x, 1.0) + pow(x, 2.0)",
+        "|x:i8,y:u64,z:f32| {x == -1c, y / 7UL, z + 1e-3f, !(z > 2.5F), switch(x, 1c: 2, 3c: 4, 5)}",
+        "|d:dict[i32,i64]| {lookup(d, 1), keyexists(d, 2), len(tovec(d))}",
+        "|v:vec[u8]| {slice(v, 1L, 2L), sort(v, |a,b| i32(a) - i32(b)), serialize(v)}",
+        "|v:vec[u8]| deserialize[vec[i32]](v)",
+        "|x:i32| iterate(x, |y| {y - 1, y > 0})",
+        "|x:i32| let f = |a| a + 1; f(x)",
+        "|x:f32| {select(x > 0.0F, x, cudf[udf,f32](x, \"a\\nb\")), broadcast(x)}",
+        "|x:vec[i32]| @(loopsize: 10L)for(x, dictmerger[i32,i32,+], |b,i,e| merge(b, {e, 1}))",
+    ];
+    for code in programs.iter() {
+        let expr = parse_expr(code).unwrap();
+        check_round_trip(&expr, false);
+        check_round_trip(&expr, true);
+
+        let mut typed = expr.clone();
+        typed
+            .infer_types()
+            .unwrap_or_else(|e| panic!("{}: {}", e, code));
+        check_round_trip(&typed, true);
+    }
+}
+
+#[cfg(test)]
+fn check_round_trip(expr: &Expr, show_types: bool) {
+    use crate::syntax::parser::parse_expr;
+    let config = PrettyPrintConfig::default().show_types(show_types);
+    let configs = [
+        config.clone().should_indent(false),
+        config.clone(),
+        config.max_width(30),
+    ];
+    for config in configs.iter() {
+        let printed = expr.pretty_print_config(config);
+        let mut reparsed = parse_expr(&printed).unwrap_or_else(|e| panic!("{}: {}", e, printed));
+        // Only symbols are printed with their types, so infer the rest.
+        if expr.ty != Type::Unknown {
+            reparsed.infer_types().unwrap();
+        }
+        assert!(
+            reparsed.compare_ignoring_symbols(expr).unwrap(),
+            "{}",
+            printed
+        );
+    }
+}
+
+#[test]
+fn indented_printing() {
+    use crate::syntax::parser::parse_expr;
+    let e = parse_expr(
+        "|v:vec[i32],w:vec[f32]| let a = 1; let b = a + 2; \
+         result(for(zip(v, w), merger[f32,+], |b,i,e| \
+         merge(b, f32(e.$0 + a + b) * e.$1 + if(e.$0 > 1, 1.0f, 2.0f))))",
+    )
+    .unwrap();
+
+    let config = PrettyPrintConfig::default().max_width(40);
+    assert_eq!(
+        e.pretty_print_config(&config),
+        "\
+|v:vec[i32],w:vec[f32]|
+  (let a=(1);
+   let b=((a+2));
+   result(
+     for(
+       zip(v,w),
+       merger[f32,+],
+       |b,i,e|
+         merge(
+           b,
+           (((f32(((e.$0+a)+b)))*e.$1)+if((e.$0>1),1.0F,2.0F))
+         )
+     )
+   ))"
+    );
+
+    // Expressions that fit within the width are printed on one line.
+    let config = PrettyPrintConfig::default().max_width(200);
+    assert_eq!(e.pretty_print_config(&config), print_expr(&e));
 }
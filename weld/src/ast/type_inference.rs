@@ -33,6 +33,14 @@ pub trait InferTypes {
     /// Because this function only looks at local sub-expressions, identifiers with `Unknown` type
     /// are disallowed.
     fn infer_local(&mut self) -> WeldResult<()>;
+
+    /// Checks that a fully typed expression is well-typed.
+    ///
+    /// Unlike `infer_types`, this method does not modify the expression: it returns an error if
+    /// any type is partial, or if a type is inconsistent with the types around it (e.g., a
+    /// `Merge` whose value does not match the builder's merge type). This is useful for catching
+    /// transformations that produce ill-typed trees.
+    fn check_types(&self) -> WeldResult<()>;
 }
 
 impl InferTypes for Expr {
@@ -42,6 +50,10 @@ impl InferTypes for Expr {
     }
 
     fn infer_local(&mut self) -> WeldResult<()> {
+        // Without a surrounding context, integer literals have their default type.
+        if let Literal(I32Literal(_)) = self.kind {
+            self.ty.push(&Scalar(I32))?;
+        }
         let env = &mut TypeMap::default();
         if let Ident(ref sym) = self.kind {
             env.insert(sym.clone(), self.ty.clone());
@@ -49,6 +61,127 @@ impl InferTypes for Expr {
 
         self.infer_locally(env).map(|_| ())
     }
+
+    fn check_types(&self) -> WeldResult<()> {
+        if let Some(expr) = self.find_partially_typed() {
+            return partial_type_error(expr);
+        }
+        // Inference cannot add anything to a fully typed tree, so running it on a copy only
+        // checks that each expression's type is consistent with its subexpressions.
+        let mut expr = self.clone();
+        let env = &mut TypeMap::default();
+        expr.infer_up(env).map(|_| ())
+    }
+}
+
+/// Returns an integer literal of type `kind` with the given value.
+///
+/// Returns an error if the value does not fit in the type.
+fn integer_literal(value: i32, kind: ScalarKind) -> WeldResult<LiteralKind> {
+    use std::convert::TryFrom;
+    let literal = match kind {
+        I8 => i8::try_from(value).ok().map(I8Literal),
+        I16 => i16::try_from(value).ok().map(I16Literal),
+        I32 => Some(I32Literal(value)),
+        I64 => Some(I64Literal(value.into())),
+        U8 => u8::try_from(value).ok().map(U8Literal),
+        U16 => u16::try_from(value).ok().map(U16Literal),
+        U32 => u32::try_from(value).ok().map(U32Literal),
+        U64 => u64::try_from(value).ok().map(U64Literal),
+        _ => None,
+    };
+    match literal {
+        Some(literal) => Ok(literal),
+        None => compile_err!("Integer literal {} does not fit in type {}", value, kind),
+    }
+}
+
+/// Gives integer literals whose type was not constrained by their context the default type
+/// `i32`, returning whether any literal changed.
+fn default_literal_types(expr: &mut Expr) -> bool {
+    let mut changed = false;
+    expr.traverse_mut(&mut |e| {
+        if let Literal(I32Literal(_)) = e.kind {
+            if e.ty == Unknown {
+                e.ty = Scalar(I32);
+                changed = true;
+            }
+        }
+    });
+    changed
+}
+
+/// Specializes generic named functions at each of their uses.
+///
+/// A named function is generic if the types of its parameters are partial, e.g., `let add = |x:
+/// ?, y: ?| x + y`. Each use of such a function is replaced with a copy of its lambda, so type
+/// inference assigns each copy the types required where it is called.
+fn monomorphize(expr: &mut Expr) {
+    let specialized = match expr.kind {
+        Let {
+            ref name,
+            ref value,
+            ref body,
+        } => match value.kind {
+            Lambda { ref params, .. } if params.iter().any(|p| p.ty.partial_type()) => {
+                let mut body = body.as_ref().clone();
+                body.substitute(name, value);
+                Some(body)
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    match specialized {
+        Some(body) => {
+            *expr = body;
+            monomorphize(expr);
+        }
+        None => {
+            for child in expr.children_mut() {
+                monomorphize(child);
+            }
+        }
+    }
+}
+
+/// Returns an error describing the partially typed expression `expr`.
+fn partial_type_error(expr: &Expr) -> WeldResult<()> {
+    let what = match expr.kind {
+        Ident(ref sym) => format!("symbol {}", sym),
+        Lambda { ref params, .. } => match params.iter().find(|p| p.ty.partial_type()) {
+            Some(param) => format!("parameter {}", param.name),
+            None => "function".to_string(),
+        },
+        ref kind => format!("{} expression", kind.name()),
+    };
+    let err = format!(
+        "Could not infer the type of {} (inferred {})",
+        what, expr.ty
+    );
+    let err = match expr.span {
+        Some(span) => WeldCompileError::new(format!("{} (at {})", err, span)),
+        None => WeldCompileError::new(err),
+    };
+    Err(err.with_span(expr.span))
+}
+
+/// The maximum length of the expression text shown in a type error.
+const MAX_ERROR_TEXT: usize = 60;
+
+/// Returns `err`, which occurred while inferring the type of `expr`, with the text of `expr` and
+/// its location in the program.
+fn local_type_error(expr: &Expr, err: WeldCompileError) -> WeldResult<bool> {
+    let mut text = print_expr(expr);
+    if text.chars().count() > MAX_ERROR_TEXT {
+        text = text.chars().take(MAX_ERROR_TEXT - 3).collect();
+        text.push_str("...");
+    }
+    let err = match expr.span {
+        Some(span) => WeldCompileError::new(format!("{} in `{}` (at {})", err, text, span)),
+        None => WeldCompileError::new(format!("{} in `{}`", err, text)),
+    };
+    Err(err.with_span(expr.span))
 }
 
 /// A trait for updating a type based on types around it.
@@ -84,6 +217,22 @@ fn key_hashable(key: &Type) -> WeldResult<()> {
     }
 }
 
+/// Checks that `value` can be combined by a builder using a binary operator.
+///
+/// Such values must be scalars or structs of scalars.
+fn value_mergeable(value: &Type, builder_name: &str) -> WeldResult<()> {
+    let scalar_or_unknown = |ty: &Type| matches!(*ty, Unknown | Scalar(_));
+    let mergeable = match *value {
+        Struct(ref fields, _) => fields.iter().all(scalar_or_unknown),
+        ref other => scalar_or_unknown(other),
+    };
+    if mergeable {
+        Ok(())
+    } else {
+        compile_err!("Non-scalar type {} as {} value", value, builder_name)
+    }
+}
+
 impl PushType for Type {
     /// Sets this `Type` to be `other`.
     fn push_complete(&mut self, other: Type) -> WeldResult<bool> {
@@ -130,10 +279,27 @@ impl PushType for Type {
                 key_hashable(key.as_ref())?;
                 Ok(changed)
             }
-            (&mut Struct(ref mut types), &Struct(ref other_types))
+            (Struct(types, names), Struct(other_types, other_names))
                 if types.len() == other_types.len() =>
             {
+                // Field names are not part of the type, but are kept so fields can be accessed
+                // by name. Positional structs take the names of named ones, but two structs with
+                // different names do not match.
                 let mut changed = false;
+                match (names.names(), other_names.names()) {
+                    (None, Some(_)) => {
+                        *names = other_names.clone();
+                        changed = true;
+                    }
+                    (Some(these), Some(others)) if these != others => {
+                        return compile_err!(
+                            "Type mismatch: expected struct with fields {} but got {}",
+                            others.join(","),
+                            these.join(",")
+                        );
+                    }
+                    _ => (),
+                }
                 for (this, other) in types.iter_mut().zip(other_types) {
                     changed |= this.push(other)?;
                 }
@@ -165,20 +331,26 @@ impl PushType for Type {
                     ) if *op == *other_op => {
                         let changed = key.push(other_key)? || value.push(other_value)?;
                         key_hashable(key.as_ref())?;
+                        value_mergeable(value.as_ref(), "dictmerger")?;
                         Ok(changed)
                     }
                     (
                         &mut GroupMerger(ref mut key, ref mut value),
                         &GroupMerger(ref other_key, ref other_value),
                     ) => {
-                        let changed = key.push(other_key)? || value.push(other_value)?;
+                        // Push both types so neither is skipped when the key changes.
+                        let changed = key.push(other_key)? | value.push(other_value)?;
                         key_hashable(key.as_ref())?;
                         Ok(changed)
                     }
                     (
                         &mut VecMerger(ref mut elem, ref mut op),
                         &VecMerger(ref other_elem, ref other_op),
-                    ) if *op == *other_op => elem.push(other_elem),
+                    ) if *op == *other_op => {
+                        let changed = elem.push(other_elem)?;
+                        value_mergeable(elem.as_ref(), "vecmerger")?;
+                        Ok(changed)
+                    }
                     (
                         &mut Merger(ref mut elem, ref mut op),
                         &Merger(ref other_elem, ref other_op),
@@ -186,12 +358,12 @@ impl PushType for Type {
                     // Mismatches in the binary operator of DictMerger, VecMerger, and GroupMerger,
                     // or other type mismatches in the BuilderKind. We list them explicitly so the
                     // compiler will throw an error if we add new types.
-                    (&mut Appender(_), _)
-                    | (&mut DictMerger(_, _, _), _)
-                    | (&mut GroupMerger(_, _), _)
-                    | (&mut VecMerger(_, _), _)
-                    | (&mut Merger(_, _), _) => {
-                        compile_err!("Type mismatch: expected builder type {}", other)
+                    (this @ &mut Appender(_), _)
+                    | (this @ &mut DictMerger(_, _, _), _)
+                    | (this @ &mut GroupMerger(_, _), _)
+                    | (this @ &mut VecMerger(_, _), _)
+                    | (this @ &mut Merger(_, _), _) => {
+                        compile_err!("Type mismatch: expected {} but got {}", other_kind, this)
                     }
                 };
 
@@ -228,6 +400,112 @@ fn sync_function(expr: &mut Expr, tys: Vec<&Type>) -> WeldResult<bool> {
     }
 }
 
+/// Syncs the type of a builder with the type of a value merged into it.
+///
+/// Merging into a struct of builders merges each field of the value into the corresponding
+/// builder, so `value_ty` must be a struct with one field per builder.
+fn sync_merge_types(builder_ty: &mut Type, value_ty: &mut Type) -> WeldResult<bool> {
+    match *builder_ty {
+        Builder(ref mut builder_kind, _) => {
+            // Get the merge type, which is the expected type of the value merged into the
+            // builder, and the builder kind, whose precise type we will infer and then set back
+            // into the builder.
+            let mut merge_type = builder_kind.merge_type();
+            // If we are merging a SIMD value, sync with the SIMD merge type.
+            // NOTE: This currently only works under the assumption that each
+            // value in a SIMD program is SIMD-valued.
+            if value_ty.is_simd() {
+                merge_type = merge_type.simd_type()?;
+            }
+
+            let changed = value_ty.sync(&mut merge_type)?;
+
+            // To sync the builder type, remove any SIMD type: SIMD shouldn't appear
+            // in the Builder kind.
+            if merge_type.is_simd() {
+                merge_type = merge_type.scalar_type()?;
+            }
+
+            // Set the builder kind type.
+            builder_kind.set_merge_type(merge_type)?;
+            Ok(changed)
+        }
+        Struct(ref mut builders, _) => {
+            let mut changed = false;
+            if *value_ty == Unknown {
+                *value_ty = Struct(vec![Unknown; builders.len()], FieldNames::none());
+                changed = true;
+            }
+            match *value_ty {
+                Struct(ref mut values, _) if values.len() == builders.len() => {
+                    for (builder, value) in builders.iter_mut().zip(values.iter_mut()) {
+                        changed |= sync_merge_types(builder, value)?;
+                    }
+                    Ok(changed)
+                }
+                _ => compile_err!(
+                    "Mismatched types in merge into struct of builders: {} and {}",
+                    builder_ty,
+                    value_ty
+                ),
+            }
+        }
+        Unknown => Ok(false),
+        _ => compile_err!("Expected builder type in merge, got {}", builder_ty),
+    }
+}
+
+/// Returns the type produced by calling `result` on a value of type `builder_ty`.
+///
+/// Calling `result` on a struct of builders produces a struct of results. Returns `None` if the
+/// result type is not known yet.
+fn result_type(builder_ty: &Type) -> WeldResult<Option<Type>> {
+    match *builder_ty {
+        Builder(ref kind, _) => Ok(Some(kind.result_type())),
+        Struct(ref builders, _) => {
+            let mut results = vec![];
+            for builder in builders.iter() {
+                match result_type(builder)? {
+                    Some(ty) => results.push(ty),
+                    None => return Ok(None),
+                }
+            }
+            Ok(Some(Struct(results, FieldNames::none())))
+        }
+        Unknown => Ok(None),
+        _ => compile_err!("Expected builder type in result, got {}", builder_ty),
+    }
+}
+
+/// Returns whether values of type `ty` can be sorted.
+///
+/// Dictionaries, builders, and SIMD values cannot be sorted. Unknown types are assumed to be
+/// sortable until they are inferred.
+fn sortable(ty: &Type) -> bool {
+    match *ty {
+        Unknown | Scalar(_) => true,
+        Vector(ref elem) => sortable(elem),
+        Struct(ref fields, _) => fields.iter().all(sortable),
+        _ => false,
+    }
+}
+
+/// Returns the type of an integer literal used as a switch case constant.
+fn switch_case_type(constant: &LiteralKind) -> WeldResult<Type> {
+    let kind = match *constant {
+        I8Literal(_) => I8,
+        I16Literal(_) => I16,
+        I32Literal(_) => I32,
+        I64Literal(_) => I64,
+        U8Literal(_) => U8,
+        U16Literal(_) => U16,
+        U32Literal(_) => U32,
+        U64Literal(_) => U64,
+        _ => return compile_err!("Expected integer constant in switch case, got {}", constant),
+    };
+    Ok(Scalar(kind))
+}
+
 /// A module-internal implementation of type inference.
 ///
 /// This trait contains additional helper methods that are not exposed outside this module.
@@ -240,14 +518,17 @@ trait InferTypesInternal {
 impl InferTypesInternal for Expr {
     /// Internal implementation of type inference.
     fn infer_types_internal(&mut self) -> WeldResult<()> {
+        monomorphize(self);
         loop {
             let env = &mut TypeMap::default();
             if !self.infer_up(env)? {
-                if self.partially_typed() {
-                    return compile_err!("Could not infer some types");
-                } else {
-                    return Ok(());
+                if default_literal_types(self) {
+                    continue;
                 }
+                return match self.find_partially_typed() {
+                    Some(expr) => partial_type_error(expr),
+                    None => Ok(()),
+                };
             }
         }
     }
@@ -310,17 +591,34 @@ impl InferTypesInternal for Expr {
             };
         }
 
-        // Infer local type.
-        changed |= self.infer_locally(env)?;
-        Ok(changed)
+        // Infer local type, pointing errors back at the program text.
+        match self.infer_locally(env) {
+            Ok(local_changed) => Ok(changed | local_changed),
+            Err(err) => local_type_error(self, err),
+        }
     }
 
     /// Infer the types of an expression based on direct subexpressions.
     fn infer_locally(&mut self, env: &TypeMap) -> WeldResult<bool> {
         match self.kind {
+            // Vectorization broadcasts literals to SIMD values of the literal's type.
+            Literal(_) if self.ty.is_simd() => {
+                let mut scalar = self.clone();
+                scalar.ty = self.ty.scalar_type()?;
+                scalar.infer_locally(env)
+            }
             Literal(I8Literal(_)) => self.ty.push_complete(Scalar(I8)),
             Literal(I16Literal(_)) => self.ty.push_complete(Scalar(I16)),
-            Literal(I32Literal(_)) => self.ty.push_complete(Scalar(I32)),
+            // Integer literals take the integer type their context requires, and default to i32
+            // otherwise (see `default_literal_types`).
+            Literal(I32Literal(value)) => match self.ty {
+                Unknown => Ok(false),
+                Scalar(kind) if kind.is_integer() => {
+                    self.kind = Literal(integer_literal(value, kind)?);
+                    Ok(false)
+                }
+                _ => self.ty.push_complete(Scalar(I32)),
+            },
             Literal(I64Literal(_)) => self.ty.push_complete(Scalar(I64)),
             Literal(U8Literal(_)) => self.ty.push_complete(Scalar(U8)),
             Literal(U16Literal(_)) => self.ty.push_complete(Scalar(U16)),
@@ -345,6 +643,46 @@ impl InferTypesInternal for Expr {
                     elem_type.push(&self.ty)?;
                 }
 
+                // The math operators are only defined over numbers, pow only over floats, and
+                // shifts only over integers.
+                if let BinOpKind::Pow
+                | BinOpKind::Min
+                | BinOpKind::Max
+                | BinOpKind::ShiftLeft
+                | BinOpKind::ShiftRight = op
+                {
+                    match *elem_type {
+                        Scalar(ref kind) | Simd(ref kind) => {
+                            if op == BinOpKind::Pow && !kind.is_float() {
+                                return compile_err!(
+                                    "Expected floating-point type for pow, got {}",
+                                    elem_type
+                                );
+                            } else if op.is_shift() && !kind.is_integer() {
+                                return compile_err!(
+                                    "Expected integer type for {}, got {}",
+                                    op,
+                                    elem_type
+                                );
+                            } else if !kind.is_numeric() {
+                                return compile_err!(
+                                    "Expected numeric type for {}, got {}",
+                                    op,
+                                    elem_type
+                                );
+                            }
+                        }
+                        Unknown => (),
+                        _ => {
+                            return compile_err!(
+                                "Expected scalar type for {}, got {}",
+                                op,
+                                elem_type
+                            )
+                        }
+                    }
+                }
+
                 // Now, attempt to push the elem_type back into left and right to "sync" them.
                 let mut changed = left.ty.push(elem_type)?;
                 changed |= right.ty.push(elem_type)?;
@@ -371,17 +709,25 @@ impl InferTypesInternal for Expr {
                 _ => compile_err!("Expected floating-point type for unary op '{}'", kind),
             },
 
-            Cast { kind, .. } => self.ty.push_complete(Scalar(kind)),
+            Cast {
+                kind,
+                ref child_expr,
+            } => match child_expr.ty {
+                Scalar(_) | Unknown => self.ty.push_complete(Scalar(kind)),
+                Simd(_) => self.ty.push_complete(Simd(kind)),
+                _ => compile_err!("Expected scalar type in cast, got {}", child_expr.ty),
+            },
 
             ToVec { ref mut child_expr } => {
                 // The base type is vec[{?,?}] - infer the key and value type.
-                let base_type = &mut Vector(Box::new(Struct(vec![Unknown, Unknown])));
+                let base_type =
+                    &mut Vector(Box::new(Struct(vec![Unknown, Unknown], FieldNames::none())));
                 let mut changed = self.ty.push(base_type)?;
 
                 let mut set_types = false;
                 if let Dict(ref key, ref value) = child_expr.ty {
                     if let Vector(ref mut elem) = self.ty {
-                        if let Struct(ref mut fields) = **elem {
+                        if let Struct(ref mut fields, _) = **elem {
                             let pair_type = vec![key.clone(), value.clone()];
                             for (ref mut field, ref child) in fields.iter_mut().zip(pair_type) {
                                 changed |= field.push(child)?;
@@ -411,18 +757,22 @@ impl InferTypesInternal for Expr {
                 }
             }
 
-            Negate(ref c) => self.ty.push(&c.ty),
+            Negate(ref c) => match c.ty {
+                Scalar(ref kind) | Simd(ref kind) if kind.is_numeric() => self.ty.push(&c.ty),
+                Unknown => Ok(false),
+                _ => compile_err!("Expected numeric type for - operator, got {}", c.ty),
+            },
 
             Not(ref value) => match value.ty {
                 Scalar(ref kind) | Simd(ref kind) if kind.is_bool() => self.ty.push(&value.ty),
                 Unknown => Ok(false),
-                _ => compile_err!("Expected boolean type for ! operator"),
+                _ => compile_err!("Expected boolean type for ! operator, got {}", value.ty),
             },
 
-            Assert(ref value) => {
-                self.ty.push(&value.ty)?;
-                self.ty.push_complete(Scalar(Bool))
-            }
+            Assert(ref value) => match value.ty {
+                Scalar(Bool) | Unknown => self.ty.push_complete(Scalar(Bool)),
+                _ => compile_err!("Expected boolean type for assert, got {}", &value.ty),
+            },
 
             Broadcast(ref c) => {
                 if let Scalar(ref kind) = c.ty {
@@ -466,17 +816,24 @@ impl InferTypesInternal for Expr {
             Zip { ref mut vectors } => {
                 let mut changed = false;
 
-                let base_type = &Vector(Box::new(Struct(vec![Unknown; vectors.len()])));
+                let base_type = &Vector(Box::new(Struct(
+                    vec![Unknown; vectors.len()],
+                    FieldNames::none(),
+                )));
                 changed |= self.ty.push(base_type)?;
 
                 // Push the vector type to each vector.
                 let mut types = vec![];
                 let mut set_types = false;
                 if let Vector(ref mut elem_type) = self.ty {
-                    if let Struct(ref mut vec_types) = **elem_type {
+                    if let Struct(ref mut vec_types, _) = **elem_type {
                         for (vec_ty, vec_expr) in vec_types.iter_mut().zip(vectors.iter_mut()) {
-                            if let Vector(ref elem_type) = vec_expr.ty {
-                                changed |= vec_ty.push(elem_type)?;
+                            match vec_expr.ty {
+                                Vector(ref elem_type) => changed |= vec_ty.push(elem_type)?,
+                                Unknown => (),
+                                ref ty => {
+                                    return compile_err!("Expected vector type in zip, got {}", ty)
+                                }
                             }
                             types.push(vec_ty.clone());
                         }
@@ -494,7 +851,7 @@ impl InferTypesInternal for Expr {
                             .join(",")
                     )
                 } else {
-                    let base_type = &Vector(Box::new(Struct(types)));
+                    let base_type = &Vector(Box::new(Struct(types, FieldNames::none())));
                     changed |= self.ty.push(base_type)?;
                     Ok(changed)
                 }
@@ -502,10 +859,10 @@ impl InferTypesInternal for Expr {
 
             MakeStruct { ref mut elems } => {
                 let mut changed = false;
-                let base_type = &Struct(vec![Unknown; elems.len()]);
+                let base_type = &Struct(vec![Unknown; elems.len()], FieldNames::none());
                 changed |= self.ty.push(base_type)?;
 
-                if let Struct(ref mut elem_types) = self.ty {
+                if let Struct(ref mut elem_types, _) = self.ty {
                     // Sync the type of each element and the expression struct type.
                     for (elem_ty, elem_expr) in elem_types.iter_mut().zip(elems.iter_mut()) {
                         changed |= elem_ty.sync(&mut elem_expr.ty)?;
@@ -520,10 +877,14 @@ impl InferTypesInternal for Expr {
                 expr: ref mut param,
                 index,
             } => {
-                if let Struct(ref mut elem_types) = param.ty {
+                if let Struct(ref mut elem_types, _) = param.ty {
                     let index = index as usize;
                     if index >= elem_types.len() {
-                        compile_err!("struct index error")
+                        compile_err!(
+                            "Field index {} out of range for struct with {} fields",
+                            index,
+                            elem_types.len()
+                        )
                     } else {
                         self.ty.sync(&mut elem_types[index])
                     }
@@ -537,6 +898,39 @@ impl InferTypesInternal for Expr {
                 }
             }
 
+            GetNamedField {
+                expr: ref mut param,
+                ref name,
+            } => {
+                // Replace the access with a positional one once the field names are known.
+                let index = match param.ty {
+                    Struct(_, ref names) if names.is_named() => match names.index_of(name) {
+                        Some(index) => index as u32,
+                        None => {
+                            return compile_err!("No field {} in struct type {}", name, &param.ty)
+                        }
+                    },
+                    Struct(..) => {
+                        return compile_err!(
+                            "Cannot access field {} by name in struct type {} without field names",
+                            name,
+                            &param.ty
+                        )
+                    }
+                    Unknown => return Ok(false),
+                    _ => {
+                        return compile_err!(
+                            "Expected struct type for struct field access, got {}",
+                            &param.ty
+                        )
+                    }
+                };
+                let expr = param.take();
+                self.kind = GetField { expr, index };
+                self.infer_locally(env)?;
+                Ok(true)
+            }
+
             Length { ref mut data } => {
                 if let Vector(_) = data.ty {
                     self.ty.push_complete(Scalar(I64))
@@ -570,7 +964,13 @@ impl InferTypesInternal for Expr {
                 ref mut cmpfunc,
             } => {
                 if let Vector(ref elem_type) = data.ty {
+                    if !sortable(elem_type) {
+                        return compile_err!("Cannot sort vector with element type {}", elem_type);
+                    }
                     let mut changed = sync_function(cmpfunc, vec![&elem_type, &elem_type])?;
+                    if let Function(_, ref mut result) = cmpfunc.ty {
+                        changed |= result.push(&Scalar(I32))?;
+                    }
                     changed |= self.ty.push(&data.ty)?;
                     Ok(changed)
                 } else if data.ty == Unknown {
@@ -618,7 +1018,7 @@ impl InferTypesInternal for Expr {
                         // Push the value type.
                         changed |= (&mut my_type[1]).push(value_type)?;
 
-                        let struct_ty = &mut Struct(my_type);
+                        let struct_ty = &mut Struct(my_type, FieldNames::none());
                         changed |= self.ty.sync(struct_ty)?;
 
                         Ok(changed)
@@ -640,7 +1040,7 @@ impl InferTypesInternal for Expr {
                 } else if data.ty == Unknown {
                     Ok(false)
                 } else {
-                    compile_err!("Expected dict type in lookup, got {}", &data.ty)
+                    compile_err!("Expected dict type in keyexists, got {}", &data.ty)
                 }
             }
 
@@ -676,14 +1076,55 @@ impl InferTypesInternal for Expr {
                 Ok(changed)
             }
 
+            Switch {
+                ref mut value,
+                ref mut cases,
+                ref mut default,
+            } => {
+                let mut changed = false;
+                // Integer literal cases take the type of the value, like other literals.
+                if let Scalar(kind) = value.ty {
+                    for (constant, _) in cases.iter_mut() {
+                        if let I32Literal(v) = *constant {
+                            if kind.is_integer() {
+                                *constant = integer_literal(v, kind)?;
+                            }
+                        }
+                    }
+                }
+                for (i, (constant, _)) in cases.iter().enumerate() {
+                    if cases[..i].iter().any(|case| case.0 == *constant) {
+                        return compile_err!("Duplicate case {} in switch", constant);
+                    }
+                    changed |= value.ty.push(&switch_case_type(constant)?)?;
+                }
+                match value.ty {
+                    Scalar(ref kind) if kind.is_integer() => (),
+                    Unknown => (),
+                    _ => return compile_err!("Expected integer type in switch, got {}", value.ty),
+                }
+                for (_, ref mut branch) in cases.iter_mut() {
+                    changed |= self.ty.sync(&mut branch.ty)?;
+                }
+                changed |= self.ty.sync(&mut default.ty)?;
+                Ok(changed)
+            }
+
             Iterate {
                 ref mut initial,
                 ref mut update_func,
             } => {
                 let mut changed = self.ty.sync(&mut initial.ty)?;
                 match update_func.ty {
-                    Function(ref mut params, _) if params.len() == 1 => {
+                    Function(ref mut params, ref mut result) if params.len() == 1 => {
                         changed |= (&mut params[0]).sync(&mut initial.ty)?;
+                        // The update function returns the next state and whether to continue.
+                        let mut expected =
+                            Struct(vec![initial.ty.clone(), Scalar(Bool)], FieldNames::none());
+                        changed |= result.sync(&mut expected)?;
+                        if let Struct(ref fields, _) = expected {
+                            changed |= initial.ty.push(&fields[0])?;
+                        }
                         Ok(changed)
                     }
                     _ => compile_err!(
@@ -749,14 +1190,20 @@ impl InferTypesInternal for Expr {
                                 Ok(false)
                             }
                         }
-                        Appender(_) => {
+                        Appender(_) | DictMerger(_, _, _) => {
                             if let Some(ref mut argument) = argument {
                                 argument.ty.push(&Scalar(I64))
                             } else {
                                 Ok(false)
                             }
                         }
-                        _ => Ok(false),
+                        _ => {
+                            if argument.is_some() {
+                                compile_err!("Unexpected argument when creating {}", self.ty)
+                            } else {
+                                Ok(false)
+                            }
+                        }
                     }
                 } else if self.ty == Unknown {
                     Ok(false)
@@ -769,87 +1216,15 @@ impl InferTypesInternal for Expr {
                 ref mut builder,
                 ref mut value,
             } => {
-                use std::mem;
-                let mut changed = false;
-
-                // Get the merge type, which is the expected type of the value merged into the
-                // builder, and the builder kind, whose precise type we will infer and then set
-                // back into the builder.
-                let (mut merge_type, mut kind) = if let Builder(ref builder_kind, _) = builder.ty {
-                    let mut merge_type = builder_kind.merge_type();
-                    // If we are merging a SIMD value, sync with the SIMD merge type.
-                    // NOTE: This currently only works under the assumption that each
-                    // value in a SIMD program is SIMD-valued.
-                    if value.ty.is_simd() {
-                        merge_type = merge_type.simd_type()?;
-                    }
-                    (merge_type, builder_kind.clone())
-                } else if builder.ty == Unknown {
-                    return Ok(false);
-                } else {
-                    return compile_err!("Expected builder type in merge, got {}", &builder.ty);
-                };
-
-                changed |= value.ty.sync(&mut merge_type)?;
-
-                // To sync the builder type, remove any SIMD type: SIMD shouldn't appear
-                // in the Builder kind.
-                if merge_type.is_simd() {
-                    merge_type = merge_type.scalar_type()?;
-                }
-
-                // Set the builder kind type.
-                match kind {
-                    Appender(ref mut elem) => {
-                        **elem = merge_type;
-                    }
-                    Merger(ref mut elem, _) => {
-                        **elem = merge_type;
-                    }
-                    DictMerger(ref mut key, ref mut value, _) => {
-                        if let Struct(mut tys) = merge_type {
-                            mem::swap(key.as_mut(), &mut tys[0]);
-                            mem::swap(value.as_mut(), &mut tys[1]);
-                        } else {
-                            unreachable!()
-                        }
-                    }
-                    GroupMerger(ref mut key, ref mut value) => {
-                        if let Struct(mut tys) = merge_type {
-                            mem::swap(key.as_mut(), &mut tys[0]);
-                            mem::swap(value.as_mut(), &mut tys[1]);
-                        } else {
-                            unreachable!()
-                        }
-                    }
-                    VecMerger(ref mut elem, _) => {
-                        if let Struct(mut tys) = merge_type {
-                            // tys[0] is the index.
-                            mem::swap(elem.as_mut(), &mut tys[1]);
-                        } else {
-                            unreachable!()
-                        }
-                    }
-                };
-
-                if let Builder(ref mut builder_kind, _) = builder.ty {
-                    *builder_kind = kind;
-                } else {
-                    unreachable!()
-                }
+                let mut changed = sync_merge_types(&mut builder.ty, &mut value.ty)?;
                 changed |= self.ty.sync(&mut builder.ty)?;
                 Ok(changed)
             }
 
-            Res { ref mut builder } => {
-                if let Builder(ref mut kind, _) = builder.ty {
-                    self.ty.push(&kind.result_type())
-                } else if builder.ty == Unknown {
-                    Ok(false)
-                } else {
-                    compile_err!("Expected builder type in result, got {}", &builder.ty)
-                }
-            }
+            Res { ref mut builder } => match result_type(&builder.ty)? {
+                Some(ty) => self.ty.push(&ty),
+                None => Ok(false),
+            },
 
             For {
                 ref mut iters,
@@ -859,7 +1234,15 @@ impl InferTypesInternal for Expr {
                 let mut changed = false;
                 // First, for each Iter, if it has a start, end, stride, etc., make sure the types of
                 // those expressions is Scalar(I64).
+                if iters.len() > 1 && iters.iter().any(|i| i.kind == IterKind::ChunkIter) {
+                    return compile_err!("A chunkiter cannot be zipped with other iterators");
+                }
                 for iter in iters.iter_mut() {
+                    // For a ChunkIter, the start is the source handle.
+                    if iter.kind == IterKind::ChunkIter {
+                        changed |= iter.start.as_mut().unwrap().ty.push_complete(Scalar(I64))?;
+                        continue;
+                    }
                     // For ScalarIter, SimdIter, and RangeIter, start, end and stride must all be
                     // None or Some.
                     if iter.start.is_some() {
@@ -916,21 +1299,28 @@ impl InferTypesInternal for Expr {
                 let mut elem_types = if elem_types.len() == 1 {
                     elem_types[0].clone()
                 } else {
-                    Struct(elem_types)
+                    Struct(elem_types, FieldNames::none())
                 };
 
                 // Check the For loop's function, and change the element types to be SIMD types
                 // if necessary.
                 match func.kind {
                     Lambda { ref params, .. } if params.len() == 3 => {
-                        // Convert the expected element types to SIMD if the parameter in the builder
-                        // functinon is SIMD.
-                        if params[2].ty.is_simd() {
-                            if !iters.iter().all(|i| i.kind == IterKind::SimdIter) {
-                                return compile_err!("for loop requires that either all or none of the iters are simditer");
-                            }
-                            elem_types = elem_types.simd_type()?;
-                        } else if iters.iter().any(|i| i.kind == IterKind::SimdIter) {
+                        // Convert the expected element types to SIMD if the loop iterates over
+                        // simditers. The element parameter does not need a SIMD annotation, but if
+                        // it has one, every iter must be a simditer.
+                        let simd_iters = iters
+                            .iter()
+                            .filter(|i| i.kind == IterKind::SimdIter)
+                            .count();
+                        if simd_iters == iters.len() {
+                            // Wait until the element types are known before converting them.
+                            elem_types = if elem_types.partial_type() {
+                                Unknown
+                            } else {
+                                elem_types.simd_type()?
+                            };
+                        } else if simd_iters > 0 || params[2].ty.is_simd() {
                             return compile_err!("for loop requires that either all or none of the iters are simditer");
                         }
                     }
@@ -978,6 +1368,8 @@ fn infer_types_test() {
         kind: ExprKind::Ident(Symbol::new("a", 1)),
         ty: Type::Unknown,
         annotations: Annotations::new(),
+        span: None,
+        id: None,
     };
     assert_eq!(print_typed_expr_without_indent(&e).as_str(), "a__1:?");
 
@@ -1035,3 +1427,473 @@ fn infer_types_test() {
         "for([1],appender[i32],|b:appender[i32],i:i64,x:i32|merge(b:appender[i32],x:i32))"
     );
 }
+
+#[test]
+fn dictmerger_value_types() {
+    use crate::tests::*;
+    let mut e = parse_expr(
+        "|x:vec[i32]| result(for(x, dictmerger[i32,{i32,f64},+], |b,i,e| merge(b, {e, {e, 1.0}})))",
+    )
+    .unwrap();
+    assert!(e.infer_types().is_ok());
+
+    // Values must be scalars or structs of scalars.
+    let mut e = parse_expr(
+        "|x:vec[i32]| result(for(x, dictmerger[i32,vec[i32],+], |b,i,e| merge(b, {e, x})))",
+    )
+    .unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn vecmerger_value_types() {
+    use crate::tests::*;
+    let mut e =
+        parse_expr("|x:vec[f64]| result(for(x, vecmerger[f64,+](x), |b,i,e| merge(b, {i, e})))")
+            .unwrap();
+    assert!(e.infer_types().is_ok());
+
+    // Elements must be scalars or structs of scalars.
+    let mut e = parse_expr(
+        "|x:vec[vec[f64]]| result(for(x, vecmerger[vec[f64],+](x), |b,i,e| merge(b, {i, e})))",
+    )
+    .unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn groupmerger_types() {
+    use crate::tests::*;
+    let mut e = parse_expr(
+        "|x:vec[i32],y:vec[f64]| result(for(zip(x,y), groupmerger[?,?], |b,i,e| merge(b, e)))",
+    )
+    .unwrap();
+    e.infer_types().unwrap();
+    let result_ty = Dict(
+        Box::new(Scalar(I32)),
+        Box::new(Vector(Box::new(Scalar(F64)))),
+    );
+    if let Function(_, ref ty) = e.ty {
+        assert_eq!(**ty, result_ty);
+    } else {
+        unreachable!()
+    }
+
+    // A single push should fill in both the key and the value type.
+    let mut ty = Builder(
+        GroupMerger(Box::new(Unknown), Box::new(Unknown)),
+        Annotations::new(),
+    );
+    let other = Builder(
+        GroupMerger(Box::new(Scalar(I32)), Box::new(Scalar(F64))),
+        Annotations::new(),
+    );
+    assert!(ty.push(&other).unwrap());
+    assert_eq!(ty, other);
+}
+
+#[test]
+fn struct_of_builders_types() {
+    use crate::tests::*;
+    let mut e = parse_expr(
+        "|x:vec[i32]| result(for(x, {appender, merger[?,+]}, |b,i,e| merge(b, {e, i})))",
+    )
+    .unwrap();
+    assert!(e.infer_types().is_ok());
+    if let Function(_, ref ret) = e.ty {
+        assert_eq!(
+            **ret,
+            Struct(
+                vec![Vector(Box::new(Scalar(I32))), Scalar(I64)],
+                FieldNames::none()
+            )
+        );
+    } else {
+        unreachable!()
+    }
+
+    // The merged value must have one field per builder.
+    let mut e =
+        parse_expr("|x:vec[i32]| for(x, {appender[i32], appender[i32]}, |b,i,e| merge(b, {e}))")
+            .unwrap();
+    assert!(e.infer_types().is_err());
+
+    let mut e =
+        parse_expr("|x:vec[i32]| for(x, {appender[i32], appender[i32]}, |b,i,e| merge(b, e))")
+            .unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn type_error_locations() {
+    use crate::tests::*;
+    let mut e = parse_expr("|x:i32, y:f64|\n  let z = x + 1;\n  z + y").unwrap();
+    let err = e.infer_types().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Type mismatch: expected f64 but got i32 in `(z+y)` (at line 3, column 3)"
+    );
+
+    // Long expressions are shortened.
+    let product = vec!["a"; 20].join(" * ");
+    let mut e = parse_expr(&format!("|a:i32, b:f64| {} + b", product)).unwrap();
+    let err = e.infer_types().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Type mismatch: expected f64 but got i32 in \
+         `((((((((((((((((((((a*a)*a)*a)*a)*a)*a)*a)*a)*a)*a)*a)*a)...` (at line 1, column 16)"
+    );
+}
+
+#[test]
+fn check_types_of_typed_trees() {
+    use crate::tests::*;
+    let e = typed_expression("|v:vec[i32]| result(for(v, merger[i32,+], |b,i,x| merge(b, x)))");
+    assert!(e.check_types().is_ok());
+
+    // A merge value that does not match the builder's merge type.
+    let mut bad = e.clone();
+    bad.transform_and_continue(&mut |e| {
+        if let Merge { ref mut value, .. } = e.kind {
+            **value = Expr::new_literal(F64Literal(1.0f64.to_bits())).unwrap();
+        }
+        (None, true)
+    });
+    let err = bad.check_types().unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("Type mismatch: expected i32 but got f64"));
+
+    // Partial types are errors.
+    let mut bad = e;
+    bad.ty = Unknown;
+    assert!(bad.check_types().is_err());
+}
+
+#[test]
+fn generic_function_types() {
+    use crate::tests::*;
+    let mut e = parse_expr(
+        "let add = |x:?, y:?| x + y;
+         let first = |v:vec[?]| lookup(v, 0L);
+         |a:i32, b:f64, c:vec[i8]| {add(a, a), add(b, b), first(c)}",
+    )
+    .unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&e),
+        "|a:i32,b:f64,c:vec[i8]|{(|x:i32,y:i32|(x:i32+y:i32))(a:i32,a:i32),\
+         (|x:f64,y:f64|(x:f64+y:f64))(b:f64,b:f64),(|v:vec[i8]|lookup(v:vec[i8],0L))(c:vec[i8])}"
+    );
+
+    // Functions with fully typed parameters are not copied.
+    let mut e = parse_expr("let f = |x:i32| x + 1; |a:i32| f(f(a))").unwrap();
+    e.infer_types().unwrap();
+    assert!(matches!(e.kind, Let { .. }));
+}
+
+#[test]
+fn integer_literal_types() {
+    use crate::tests::*;
+    let check = |code: &str, expect: &str| {
+        let mut e = parse_expr(code).unwrap();
+        e.infer_types().unwrap();
+        assert_eq!(print_typed_expr_without_indent(&e), expect);
+    };
+    // Integer literals take the type required by their context.
+    check("|x:i64| x + 1", "|x:i64|(x:i64+1L)");
+    check("|x:u8| x > 200", "|x:u8|(x:u8>200UC)");
+    check(
+        "|v:vec[i32]| lookup(v, 1)",
+        "|v:vec[i32]|lookup(v:vec[i32],1L)",
+    );
+    check("[1, 2L]", "[1L,2L]");
+    check(
+        "|x:i16| switch(x, 1: x, 2)",
+        "|x:i16|switch(x:i16,1si:x:i16,2si)",
+    );
+    // Otherwise, they default to i32.
+    check("let a = 1; a + 2", "(let a:i32=(1);(a:i32+2))");
+
+    let mut e = parse_expr("|x:i8| x + 300").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|x:f64| x + 1").unwrap();
+    assert!(e.infer_types().is_err());
+
+    // A suffix fixes the type of a literal.
+    check("|x:i32| x + 1i32", "|x:i32|(x:i32+1)");
+    check("let a = 1i32; a", "(let a:i32=(1);a:i32)");
+    let mut e = parse_expr("|x:i64| x + 1i32").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|v:vec[i32]| lookup(v, 0i32)").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn partial_type_errors() {
+    use crate::tests::*;
+    let mut e = parse_expr("|x:i32| (|a| a)([])").unwrap();
+    let err = e.infer_types().unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "Could not infer the type of symbol a (inferred vec[?]) (at line 1, column 14)"
+    );
+
+    let mut e = parse_expr("|x:i32, y| x").unwrap();
+    let err = e.infer_types().unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "Could not infer the type of parameter y (inferred |i32,?|(i32)) (at line 1, column 1)"
+    );
+
+    // Partial types nested anywhere in the tree are errors.
+    let mut e = parse_expr("|x:i32| let v = [x]; let u = []; len(v)").unwrap();
+    let err = e.infer_types().unwrap_err().to_string();
+    assert!(err.starts_with("Could not infer the type of MakeVector expression (inferred vec[?])"));
+}
+
+#[test]
+fn cast_types() {
+    use crate::tests::*;
+    let mut e = parse_expr("|x:i32| f64(x)").unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&e).as_str(),
+        "|x:i32|(f64(x:i32))"
+    );
+
+    // Only scalars can be cast.
+    let mut e = parse_expr("|x:vec[i32]| f64(x)").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn sort_types() {
+    use crate::tests::*;
+    let mut e = parse_expr("|x:vec[{i32,f64}]| sort(x, |a,b| if(a.$0 > b.$0, 1, -1))").unwrap();
+    assert!(e.infer_types().is_ok());
+
+    // The comparator must return an i32.
+    let mut e = parse_expr("|x:vec[i32]| sort(x, |a,b| a > b)").unwrap();
+    assert!(e.infer_types().is_err());
+
+    // Dictionaries cannot be sorted.
+    let mut e = parse_expr("|x:vec[dict[i32,i32]]| sort(x, |a,b| 0)").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn iterate_types() {
+    use crate::tests::*;
+    let mut e = parse_expr("iterate(0L, |x| {x + 1L, x < 10L})").unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(e.ty, Scalar(I64));
+
+    // The update function must return a {T, bool}.
+    let mut e = parse_expr("iterate(0L, |x| x + 1L)").unwrap();
+    assert!(e.infer_types().is_err());
+
+    let mut e = parse_expr("iterate(0L, |x| {1.0, x < 10L})").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn simditer_types() {
+    use crate::tests::*;
+    // The element type is inferred as SIMD without an annotation.
+    let mut e =
+        parse_expr("|x:vec[i32]| result(for(simditer(x), merger[i32,+], |b,i,e| merge(b, e)))")
+            .unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&e).as_str(),
+        "|x:vec[i32]|result(for(simditer(x:vec[i32]),merger[i32,+],\
+         |b:merger[i32,+],i:i64,e:simd[i32]|merge(b:merger[i32,+],e:simd[i32])))"
+    );
+
+    // Mixing simditers and other iters is not allowed.
+    let mut e = parse_expr(
+        "|x:vec[i32]| result(for(zip(simditer(x), x), merger[i32,+], |b,i,e| merge(b, e.$0)))",
+    )
+    .unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn chunkiter_types() {
+    use crate::tests::*;
+    let mut e =
+        parse_expr("|s:i64| result(for(chunkiter[f32](s), merger[f32,+], |b,i,e| merge(b, e)))")
+            .unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&e).as_str(),
+        "|s:i64|result(for(chunkiter[f32](s:i64),merger[f32,+],\
+         |b:merger[f32,+],i:i64,e:f32|merge(b:merger[f32,+],e:f32)))"
+    );
+
+    // The source must be a handle, and a chunkiter cannot be zipped.
+    let mut e =
+        parse_expr("|s:i32| for(chunkiter[f32](s), appender, |b,i,e| merge(b, e))").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr(
+        "|s:i64, x:vec[f32]| for(zip(chunkiter[f32](s), x), appender, |b,i,e| merge(b, e.$0))",
+    )
+    .unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn dict_op_types() {
+    use crate::tests::*;
+    let mut e =
+        parse_expr("|d:dict[i32,f32]| {keyexists(d, 1), lookup(d, 1), optlookup(d, 1)}").unwrap();
+    e.infer_types().unwrap();
+    if let Function(_, ref result) = e.ty {
+        assert_eq!(
+            **result,
+            Struct(
+                vec![
+                    Scalar(Bool),
+                    Scalar(F32),
+                    Struct(vec![Scalar(Bool), Scalar(F32)], FieldNames::none())
+                ],
+                FieldNames::none()
+            )
+        );
+    } else {
+        panic!("Expected function type");
+    }
+
+    let mut e = parse_expr("|d:dict[i32,f32]| tovec(d)").unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&e).as_str(),
+        "|d:dict[i32,f32]|tovec(d:dict[i32,f32])"
+    );
+
+    // Keys must match the dictionary's key type.
+    let mut e = parse_expr("|d:dict[i32,f32]| keyexists(d, 1L)").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|d:dict[i32,f32]| lookup(d, 1.0)").unwrap();
+    assert!(e.infer_types().is_err());
+
+    // These operators only apply to dictionaries.
+    let mut e = parse_expr("|v:vec[i32]| keyexists(v, 1)").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|v:vec[i32]| tovec(v)").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn negate_not_types() {
+    use crate::tests::*;
+    let mut e = parse_expr("|x:f32, b:bool| {-x, !b}").unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&e).as_str(),
+        "|x:f32,b:bool|{(-x:f32),(!b:bool)}"
+    );
+
+    let mut e = parse_expr("|b:bool| -b").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|x:i32| !x").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn math_binop_types() {
+    use crate::tests::*;
+    let mut e = parse_expr("|x:f64, y:i32| {pow(x, 2.0), min(y, 1), max(y, 1)}").unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&e).as_str(),
+        "|x:f64,y:i32|{pow(x:f64,2.0),min(y:i32,1),max(y:i32,1)}"
+    );
+
+    let mut e = parse_expr("|x:i32| pow(x, 2)").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|x:bool| max(x, true)").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|x:vec[i32]| min(x, x)").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn newbuilder_argument_types() {
+    use crate::tests::*;
+    let mut e =
+        parse_expr("|n:i64| {appender[i32](n), dictmerger[i32,i32,+](n), merger[i32,+](1)}")
+            .unwrap();
+    assert!(e.infer_types().is_ok());
+
+    // Capacities must be i64.
+    let mut e = parse_expr("|n:i32| dictmerger[i32,i32,+](n)").unwrap();
+    assert!(e.infer_types().is_err());
+
+    // The initial value of a merger must match its element type.
+    let mut e = parse_expr("merger[i32,+](1.0)").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn switch_types() {
+    use crate::tests::*;
+    let mut e = parse_expr("|x:i64, y:f32| switch(x, 1L: y, 2L: y + 1.0F, 0.0F)").unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&e).as_str(),
+        "|x:i64,y:f32|switch(x:i64,1L:y:f32,2L:(y:f32+1.0F),0.0F)"
+    );
+
+    // The case constants must match the type of the value.
+    let mut e = parse_expr("|x:i64| switch(x, 1si: 2, 3)").unwrap();
+    assert!(e.infer_types().is_err());
+
+    // Only integers can be switched on.
+    let mut e = parse_expr("|x:f32| switch(x, 1)").unwrap();
+    assert!(e.infer_types().is_err());
+
+    // Branches must have the same type.
+    let mut e = parse_expr("|x:i32| switch(x, 1: 2.0, 3L)").unwrap();
+    assert!(e.infer_types().is_err());
+
+    // Case constants must be unique.
+    let mut e = parse_expr("|x:i32| switch(x, 1: 2, 1: 3, 4)").unwrap();
+    assert!(e.infer_types().is_err());
+}
+
+#[test]
+fn infer_named_fields() {
+    use crate::tests::*;
+    let mut e =
+        parse_expr("|v:vec[{a:i32,b:i64}]| lookup(v, 0L).b + i64(lookup(v, 0L).$0)").unwrap();
+    e.infer_types().unwrap();
+    assert_eq!(
+        print_expr_without_indent(&e).as_str(),
+        "|v:vec[{a:i32,b:i64}]|(lookup(v,0L).$1+(i64(lookup(v,0L).$0)))"
+    );
+
+    // Names flow through loops and ascriptions, and positional structs of the same types match.
+    let mut e = parse_expr(
+        "|v:vec[{a:i32,b:i64}]| let s = {1, 2L}:{x:i32,y:i64};
+        result(for(v, merger[i64,+], |b, i, e| merge(b, e.b + s.y)))",
+    )
+    .unwrap();
+    e.infer_types().unwrap();
+    if let Lambda { ref body, .. } = e.kind {
+        assert_eq!(body.ty, Scalar(I64));
+    }
+
+    let mut e = parse_expr("|x:{a:i32}| x.c").unwrap();
+    assert!(e.infer_types().is_err());
+    // Structs with conflicting field names do not match.
+    let mut e = parse_expr("|x:{a:i32}| x:{b:i32}").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|x:{a:i32}| if(true, x, {1}:{b:i32})").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|x:{i32}| x.a").unwrap();
+    assert!(e.infer_types().is_err());
+    let mut e = parse_expr("|x:i32| x.a").unwrap();
+    assert!(e.infer_types().is_err());
+}
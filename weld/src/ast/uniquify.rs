@@ -17,13 +17,22 @@ use crate::tests::*;
 pub trait Uniquify {
     /// Modifies an expression to make symbol names unique.
     ///
-    /// Returns an error if an undefined symbol is encountered.
+    /// Expressions without a node ID are also given one, so that every expression of a program
+    /// being compiled has an ID. Returns an error if an undefined symbol is encountered. The expression is left unchanged
+    /// if an error is returned.
     fn uniquify(&mut self) -> WeldResult<()>;
 }
 
 impl Uniquify for Expr {
     fn uniquify(&mut self) -> WeldResult<()> {
-        uniquify_helper(self, &mut SymbolStack::new())
+        // Rename a copy so that a failure part way through does not leave binders renamed while
+        // some of their uses still refer to the original names.
+        let mut expr = self.clone();
+        uniquify_helper(&mut expr, &mut SymbolStack::new())?;
+        // Number expressions introduced since parsing, such as macro expansions.
+        expr.assign_node_ids();
+        *self = expr;
+        Ok(())
     }
 }
 
@@ -180,3 +189,33 @@ fn parse_and_print_uniquified_expressions() {
         "(let b__1=(for([1],appender[i32],|b,i,e|merge(b,e)));b__1)"
     );
 }
+
+#[test]
+fn uniquify_sibling_scopes() {
+    // Bindings in sibling scopes still receive distinct names.
+    let mut e = parse_expr("|x:i32| (|a:i32| a)(x) + (|a:i32| a)(x)").unwrap();
+    e.uniquify().unwrap();
+    assert_eq!(
+        print_expr_without_indent(&e).as_str(),
+        "|x:i32|((|a|a)(x)+(|a__1|a__1)(x))"
+    );
+
+    // Uniquifying an already unique expression does not change it.
+    let before = e.clone();
+    e.uniquify().unwrap();
+    assert_eq!(e, before);
+    assert_eq!(
+        print_expr_without_indent(&e),
+        print_expr_without_indent(&before)
+    );
+}
+
+#[test]
+fn uniquify_error_leaves_expression_unchanged() {
+    // `b` is undefined, so renaming stops after the second `a` has been renamed. The trailing
+    // `a` must not be left pointing at the outer binding.
+    let mut e = parse_expr("let a = 1; let a = 2; b + a").unwrap();
+    let before = print_expr_without_indent(&e);
+    assert!(e.uniquify().is_err());
+    assert_eq!(print_expr_without_indent(&e), before);
+}
@@ -4,6 +4,21 @@
 //! this module with `llvm2::vector` one day. The main difference between a vector and an appender
 //! is that an appender has a third capacity field (in addition to the vector's data pointer and
 //! size). The appender also contains methods for dynamic resizing.
+//!
+//! # Layout
+//!
+//! An appender over elements of type `T` is the struct `{ T*, i64, i64 }`, holding a pointer to
+//! its buffer, the number of elements merged so far, and the buffer's capacity in elements. The
+//! buffer is allocated with `weld_runst_malloc` in the run that creates the appender.
+//!
+//! * `new(capacity, run)` allocates a buffer of `capacity` elements.
+//! * `merge(appender*, T, run)` and `vmerge(appender*, <W x T>, run)` append one or `W` elements.
+//!   A full buffer is grown with `weld_runst_realloc` to the larger of twice its capacity and the
+//!   required size, so merges take amortized constant time.
+//! * `extend(appender*, appender*, run)` appends the elements of another appender and frees its
+//!   buffer. Multi-threaded loops use it to concatenate the per-thread copies in order.
+//! * `result(appender*)` returns the vector `{ T*, i64 }` over the same buffer. The unused
+//!   capacity is not released.
 
 use llvm_sys;
 
@@ -40,6 +55,7 @@ pub struct Appender {
     merge: Option<LLVMValueRef>,
     vmerge: Option<LLVMValueRef>,
     result: Option<LLVMValueRef>,
+    extend: Option<LLVMValueRef>,
 }
 
 impl CodeGenExt for Appender {
@@ -78,6 +94,7 @@ impl Appender {
             merge: None,
             vmerge: None,
             result: None,
+            extend: None,
         }
     }
 
@@ -212,9 +229,13 @@ impl Appender {
         let full = LLVMBuildICmp(builder, LLVMIntSGT, new_size, capacity, c_str!("full"));
         LLVMBuildCondBr(builder, full, full_block, finish_block);
 
-        // Build the case where the appender is full and we need to alloate more memory.
+        // Build the case where the appender is full and we need to alloate more memory. Growing to
+        // at least the new size handles appenders created with a capacity smaller than a merge.
         LLVMPositionBuilderAtEnd(builder, full_block);
-        let new_capacity = LLVMBuildNSWMul(builder, capacity, self.i64(2), c_str!("newCapacity"));
+        let doubled = LLVMBuildNSWMul(builder, capacity, self.i64(2), c_str!(""));
+        let larger = LLVMBuildICmp(builder, LLVMIntSGT, doubled, new_size, c_str!(""));
+        let new_capacity =
+            LLVMBuildSelect(builder, larger, doubled, new_size, c_str!("newCapacity"));
         let elem_size = self.size_of(self.elem_ty);
         let alloc_size = LLVMBuildMul(builder, elem_size, new_capacity, c_str!("allocSize"));
         let base_pointer = self.gen_index(builder, appender, None)?;
@@ -297,6 +318,131 @@ impl Appender {
         }
     }
 
+    /// Generates code to append the elements of the appender pointed to by `other_arg` to the one
+    /// pointed to by `builder_arg`.
+    ///
+    /// The buffer of `other_arg` is freed, so it may not be used afterwards.
+    pub unsafe fn gen_extend(
+        &mut self,
+        builder: LLVMBuilderRef,
+        intrinsics: &mut Intrinsics,
+        run_arg: LLVMValueRef,
+        builder_arg: LLVMValueRef,
+        other_arg: LLVMValueRef,
+    ) -> WeldResult<LLVMValueRef> {
+        if self.extend.is_none() {
+            let mut arg_tys = [
+                LLVMPointerType(self.appender_ty, 0),
+                LLVMPointerType(self.appender_ty, 0),
+                self.run_handle_type(),
+            ];
+            let ret_ty = LLVMVoidTypeInContext(self.context);
+            let name = format!("{}.extend", self.name);
+            let (function, builder, _) = self.define_function(ret_ty, &mut arg_tys, name);
+
+            let grow_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!("grow"));
+            let check_block =
+                LLVMAppendBasicBlockInContext(self.context, function, c_str!("copy.check"));
+            let copy_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!("copy"));
+            let finish_block =
+                LLVMAppendBasicBlockInContext(self.context, function, c_str!("finish"));
+
+            let appender = LLVMGetParam(function, 0);
+            let other = LLVMGetParam(function, 1);
+            let run_handle = LLVMGetParam(function, 2);
+
+            let size_slot = LLVMBuildStructGEP(builder, appender, SIZE_INDEX, c_str!(""));
+            let size = LLVMBuildLoad(builder, size_slot, c_str!("size"));
+            let capacity_slot = LLVMBuildStructGEP(builder, appender, CAPACITY_INDEX, c_str!(""));
+            let capacity = LLVMBuildLoad(builder, capacity_slot, c_str!("capacity"));
+            let other_size_slot = LLVMBuildStructGEP(builder, other, SIZE_INDEX, c_str!(""));
+            let other_size = LLVMBuildLoad(builder, other_size_slot, c_str!("otherSize"));
+            let other_pointer = self.gen_index(builder, other, None)?;
+
+            let new_size = LLVMBuildNSWAdd(builder, size, other_size, c_str!("newSize"));
+            let full = LLVMBuildICmp(builder, LLVMIntSGT, new_size, capacity, c_str!("full"));
+            LLVMBuildCondBr(builder, full, grow_block, check_block);
+
+            // Grow to the larger of twice the capacity and the new size.
+            LLVMPositionBuilderAtEnd(builder, grow_block);
+            let doubled = LLVMBuildNSWMul(builder, capacity, self.i64(2), c_str!(""));
+            let larger = LLVMBuildICmp(builder, LLVMIntSGT, doubled, new_size, c_str!(""));
+            let new_capacity =
+                LLVMBuildSelect(builder, larger, doubled, new_size, c_str!("newCapacity"));
+            let elem_size = self.size_of(self.elem_ty);
+            let alloc_size = LLVMBuildMul(builder, elem_size, new_capacity, c_str!("allocSize"));
+            let base_pointer = self.gen_index(builder, appender, None)?;
+            let raw_pointer = LLVMBuildBitCast(
+                builder,
+                base_pointer,
+                LLVMPointerType(self.i8_type(), 0),
+                c_str!("rawPtr"),
+            );
+            let bytes = intrinsics.call_weld_run_realloc(
+                builder,
+                run_handle,
+                raw_pointer,
+                alloc_size,
+                Some(c_str!("bytes")),
+            );
+            let typed_bytes =
+                LLVMBuildBitCast(builder, bytes, LLVMTypeOf(base_pointer), c_str!("typed"));
+            let pointer_slot = LLVMBuildStructGEP(builder, appender, POINTER_INDEX, c_str!(""));
+            LLVMBuildStore(builder, typed_bytes, pointer_slot);
+            LLVMBuildStore(builder, new_capacity, capacity_slot);
+            LLVMBuildBr(builder, check_block);
+
+            LLVMPositionBuilderAtEnd(builder, check_block);
+            let any_elements =
+                LLVMBuildICmp(builder, LLVMIntSGT, other_size, self.i64(0), c_str!(""));
+            LLVMBuildCondBr(builder, any_elements, copy_block, finish_block);
+
+            // Copy the elements one at a time after the existing ones.
+            LLVMPositionBuilderAtEnd(builder, copy_block);
+            let i = LLVMBuildPhi(builder, self.i64_type(), c_str!("i"));
+            let source = LLVMBuildGEP(builder, other_pointer, [i].as_mut_ptr(), 1, c_str!(""));
+            let element = LLVMBuildLoad(builder, source, c_str!(""));
+            let index = LLVMBuildNSWAdd(builder, size, i, c_str!(""));
+            let destination = self.gen_index(builder, appender, Some(index))?;
+            LLVMBuildStore(builder, element, destination);
+            let updated = LLVMBuildNSWAdd(builder, i, self.i64(1), c_str!(""));
+            let finished = LLVMBuildICmp(builder, LLVMIntEQ, updated, other_size, c_str!(""));
+            LLVMBuildCondBr(builder, finished, finish_block, copy_block);
+
+            let mut blocks = [check_block, copy_block];
+            let mut values = [self.i64(0), updated];
+            LLVMAddIncoming(
+                i,
+                values.as_mut_ptr(),
+                blocks.as_mut_ptr(),
+                values.len() as u32,
+            );
+
+            LLVMPositionBuilderAtEnd(builder, finish_block);
+            LLVMBuildStore(builder, new_size, size_slot);
+            let raw_pointer = LLVMBuildBitCast(
+                builder,
+                other_pointer,
+                LLVMPointerType(self.i8_type(), 0),
+                c_str!(""),
+            );
+            let _ = intrinsics.call_weld_run_free(builder, run_handle, raw_pointer);
+            LLVMBuildRetVoid(builder);
+
+            self.extend = Some(function);
+            LLVMDisposeBuilder(builder);
+        }
+
+        let mut args = [builder_arg, other_arg, run_arg];
+        Ok(LLVMBuildCall(
+            builder,
+            self.extend.unwrap(),
+            args.as_mut_ptr(),
+            args.len() as u32,
+            c_str!(""),
+        ))
+    }
+
     /// Generates code to get the result from an appender.
     ///
     /// The Appender's result is a vector.
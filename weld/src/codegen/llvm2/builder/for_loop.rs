@@ -1,17 +1,45 @@
 //! Code generation for the parallel for loop.
 //!
-//! This backend currently only generates single threaded code, but its structure is amenable to
-//! parallelization (e.g., loops are still divided out into their own function, albeit marked with
-//! `alwaysinline` at the moment).
+//! Each loop body is generated as its own function, which runs a range of the loop's iterations
+//! and returns the updated builder. Loops run serially unless the `weld.threads` configuration is
+//! greater than one, in which case top-level loops over appenders, mergers, and dictmergers
+//! annotated with `impl:local` (or structs of them) are divided into chunks. Each chunk runs on a
+//! worker thread with its own piece of the builder, and the pieces are merged in order when all
+//! chunks finish, so the result does not depend on the number of threads. Floating point sums and
+//! products are only parallelized with fast math enabled, since merging the pieces changes the
+//! order of the operations. Loops over other builders, including dictmergers with a shared table,
+//! run serially.
+//!
+//! Loops nested in another loop's body (e.g., a loop over each group of a `groupmerger`) are
+//! divided into chunks in the same way, and the runtime runs their chunks on threads that are not
+//! busy with the chunks of enclosing loops, up to a maximum nesting depth. A loop with a single
+//! chunk calls its body function directly, so small nested loops add little overhead.
+//!
+//! Chunks have at least `grain_size` iterations, and threads take the next chunk as they finish
+//! the previous one, so a loop divided into many more chunks than threads stays balanced when some
+//! iterations are much more expensive than others (e.g., in a selective filter). The grain size is
+//! set by a `grain_size` annotation on the loop or by `weld.loop.grain_size`, and is otherwise
+//! estimated from the number of operations in the loop's body.
+//!
+//! Loops run serially with a single thread, so parallel floating point sums and products round
+//! differently with one thread than with several. With `weld.optimization.deterministicReduction`
+//! enabled, loops over such mergers are always divided into at most `DETERMINISTIC_CHUNKS` chunks,
+//! even with a single thread, so their results are reproducible.
+//!
+//! A loop over a `ChunkIter` calls its body function once per chunk that the host's chunk source
+//! provides, on the calling thread.
 //!
 //! The `GenForLoopInternal` is the main workhorse of this module, and provides methods for
 //! building a loop, creating bounds checks, loading elements, and so forth.
 
 use llvm_sys;
 
+use std::cmp;
 use std::ffi::CString;
 
+use crate::ast::BuilderKind::*;
 use crate::ast::IterKind::*;
+use crate::ast::Type::*;
 use crate::ast::*;
 use crate::error::*;
 use crate::runtime::WeldRuntimeErrno;
@@ -21,13 +49,43 @@ use self::llvm_sys::core::*;
 use self::llvm_sys::prelude::*;
 use self::llvm_sys::{LLVMIntPredicate, LLVMLinkage};
 
+use crate::codegen::llvm2::dict;
 use crate::codegen::llvm2::llvm_exts::LLVMExtAttribute::*;
 use crate::codegen::llvm2::llvm_exts::*;
-use crate::codegen::llvm2::vector::VectorExt;
+use crate::codegen::llvm2::vector::{VectorExt, POINTER_INDEX, SIZE_INDEX};
 use crate::codegen::llvm2::{LLVM_VECTOR_WIDTH, SIR_FUNC_CALL_CONV};
 
+use crate::codegen::llvm2::nvvm::NvvmGen;
+use crate::codegen::llvm2::profile::LoopProfileGen;
+
+use super::appender;
 use super::{CodeGenExt, FunctionContext, LlvmGenerator};
 
+/// The number of operations each chunk of a parallel loop runs, counted with
+/// `Expr::operation_count`.
+///
+/// Unless the grain size of a loop is set with a `grain_size` annotation or with
+/// `weld.loop.grain_size`, each chunk runs about this many operations, so loops with larger bodies
+/// are divided into chunks of fewer iterations. For example, the body `merge(b, e * 2)` has two
+/// operations (the merge and the multiplication), so its chunks have 4096 iterations. Loops nested
+/// in the body count as their own operations only, so chunks of such loops may run more work.
+pub const CHUNK_COST: i64 = 8192;
+
+/// The smallest grain size estimated from the size of a loop's body.
+pub const MIN_GRAIN_SIZE: i64 = 64;
+
+/// The maximum number of chunks in a parallel loop for each thread in `weld.threads`.
+///
+/// Having many more chunks than threads keeps loops with uneven iterations balanced. Each chunk
+/// has its own piece of the loop's builder, though, and a piece of an appender is allocated with
+/// `appender::DEFAULT_CAPACITY` elements, so this bounds the memory that holds the pieces and the
+/// time to merge them.
+pub const MAX_CHUNKS_PER_THREAD: i64 = 64;
+
+/// The maximum number of chunks in a parallel loop that reduces floating point values
+/// deterministically.
+pub const DETERMINISTIC_CHUNKS: i64 = 64;
+
 /// An internal trait for generating parallel For loops.
 pub trait ForLoopGenInternal {
     /// Entry point to generating a for loop.
@@ -79,13 +137,94 @@ pub trait ForLoopGenInternal {
         pass_block: LLVMBasicBlockRef,
         fail_block: LLVMBasicBlockRef,
     ) -> WeldResult<()>;
+    /// Generates a loop over a `ChunkIter`, which calls the body function for each chunk.
+    ///
+    /// Returns the number of iterations the loop ran.
+    unsafe fn gen_chunked_loop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        output: &Symbol,
+        parfor: &ParallelForData,
+    ) -> WeldResult<LLVMValueRef>;
+    /// Returns whether a loop over `builder` can be divided among threads.
+    fn is_parallel_builder(&self, builder: &Type) -> bool;
+    /// Returns whether a loop over `builder` must be divided into a fixed number of chunks to
+    /// produce reproducible results.
+    fn is_deterministic_builder(&self, builder: &Type) -> bool;
+    /// Returns the minimum number of iterations in each chunk of a parallel loop.
+    fn grain_size(&self, parfor: &ParallelForData) -> i64;
+    /// Calls the body function of a loop on the current thread and returns the updated builder.
+    ///
+    /// `arguments` are the loaded parameters of the body function.
+    unsafe fn gen_serial_loop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        func: &SirFunction,
+        arguments: &[LLVMValueRef],
+        iterations: LLVMValueRef,
+    ) -> LLVMValueRef;
+    /// Calls the body function of a loop over several threads and returns the updated builder.
+    ///
+    /// `arguments` are the loaded parameters of the body function.
+    unsafe fn gen_parallel_loop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        func: &SirFunction,
+        parfor: &ParallelForData,
+        arguments: &[LLVMValueRef],
+        iterations: LLVMValueRef,
+    ) -> WeldResult<LLVMValueRef>;
+    /// Returns the type of the struct passed to the chunk function of a parallel loop.
+    ///
+    /// The struct holds the parameters of the body function, the number of iterations, the number
+    /// of chunks, and a pointer to the array of builder pieces.
+    unsafe fn gen_chunk_arguments_type(&mut self, func: &SirFunction) -> WeldResult<LLVMTypeRef>;
+    /// Generates the function that runs a single chunk of a parallel loop.
+    ///
+    /// The function has the signature `void(i8* arguments, i64 chunk, run)` expected by the
+    /// runtime. The first chunk updates the loop's builder and other chunks update new pieces.
+    unsafe fn gen_chunk_function(
+        &mut self,
+        func: &SirFunction,
+        parfor: &ParallelForData,
+    ) -> WeldResult<LLVMValueRef>;
+    /// Generates code to create an empty piece of a builder.
+    unsafe fn gen_new_piece(
+        &mut self,
+        builder: LLVMBuilderRef,
+        ty: &Type,
+        run: LLVMValueRef,
+    ) -> WeldResult<LLVMValueRef>;
+    /// Generates code to merge the piece pointed to by `piece` into the builder pointed to by
+    /// `pointer`.
+    unsafe fn gen_merge_piece(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        ty: &Type,
+        run: LLVMValueRef,
+        pointer: LLVMValueRef,
+        piece: LLVMValueRef,
+    ) -> WeldResult<()>;
+    /// Generates code to merge each entry of the dictmerger piece pointed to by `piece` into the
+    /// dictmerger pointed to by `pointer`.
+    unsafe fn gen_merge_dict_piece(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        kind: &BuilderKind,
+        run: LLVMValueRef,
+        pointer: LLVMValueRef,
+        piece: LLVMValueRef,
+    ) -> WeldResult<()>;
     /// Generates code to load potentially zipped elements at index `i` into `e`.
     ///
-    /// `e` must be a pointer, and `i` must be a loaded index argument of type `i64`.
+    /// `e` must be a pointer, and `i` must be a loaded index argument of type `i64`. `start` is
+    /// the first iteration the body function runs, which is the index of the first element of the
+    /// current chunk for a `ChunkIter`.
     unsafe fn gen_loop_element(
         &mut self,
         ctx: &mut FunctionContext<'_>,
         i: LLVMValueRef,
+        start: LLVMValueRef,
         e: LLVMValueRef,
         parfor: &ParallelForData,
     ) -> WeldResult<()>;
@@ -99,13 +238,20 @@ impl ForLoopGenInternal for LlvmGenerator {
         output: &Symbol,
         parfor: &ParallelForData,
     ) -> WeldResult<()> {
+        let start_time = self.gen_loop_timer(ctx, parfor);
+        if parfor.data.iter().any(|iter| iter.kind == ChunkIter) {
+            let iterations = self.gen_chunked_loop(ctx, output, parfor)?;
+            return self.gen_loop_profile(ctx, parfor, start_time, iterations);
+        }
+
         let iterations = self.gen_bounds_check(ctx, parfor)?;
+        // If the loop may run on the GPU, the CPU loop below only runs if it cannot.
+        let gpu_done = self.gen_gpu_loop(ctx, output, parfor, iterations)?;
 
         let sir_function = &ctx.sir_program.funcs[parfor.body];
         assert!(sir_function.loop_body);
 
         self.gen_loop_body_function(ctx.sir_program, sir_function, parfor)?;
-        let body_function = self.functions[&parfor.body];
 
         // The parameters of the body function have symbol names that must exist in the current
         // context.
@@ -114,13 +260,207 @@ impl ForLoopGenInternal for LlvmGenerator {
             let value = self.load(ctx.builder, ctx.get_value(symbol)?)?;
             arguments.push(value);
         }
-        // The body function has an additional arguement representing the number of iterations.
+
+        let builder_type = ctx.sir_function.symbol_type(&parfor.builder)?;
+        let parallel = (self.conf.threads > 1 || self.is_deterministic_builder(builder_type))
+            && self.is_parallel_builder(builder_type);
+
+        let builder = if parallel {
+            self.gen_parallel_loop(ctx, sir_function, parfor, &arguments, iterations)?
+        } else {
+            self.gen_serial_loop(ctx, sir_function, &arguments, iterations)
+        };
+        LLVMBuildStore(ctx.builder, builder, ctx.get_value(&parfor.builder)?);
+        LLVMBuildStore(ctx.builder, builder, ctx.get_value(output)?);
+        if let Some(done_block) = gpu_done {
+            LLVMBuildBr(ctx.builder, done_block);
+            LLVMPositionBuilderAtEnd(ctx.builder, done_block);
+        }
+
+        self.gen_loop_profile(ctx, parfor, start_time, iterations)
+    }
+
+    /// Generates a loop over a `ChunkIter`, which looks as follows:
+    ///
+    /// loop.chunk:
+    ///     offset = phi [0, entry], [offset + size, loop.chunk.body]
+    ///     size = weld_runst_next_chunk(run, source, sizeof(elem), &data)
+    ///     if size == 0: goto loop.chunk.done
+    /// loop.chunk.body:
+    ///     chunk vector = { data, size }
+    ///     builder = body(args..., offset, offset + size, run)
+    ///     goto loop.chunk
+    /// loop.chunk.done:
+    ///
+    /// The chunk vector replaces the iterator's dummy data vector in the body function's
+    /// arguments. The loop index continues across chunks.
+    unsafe fn gen_chunked_loop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        output: &Symbol,
+        parfor: &ParallelForData,
+    ) -> WeldResult<LLVMValueRef> {
+        use self::llvm_sys::LLVMIntPredicate::LLVMIntEQ;
+        // Type inference does not allow zipping a chunk iterator.
+        assert_eq!(parfor.data.len(), 1);
+        let iter = &parfor.data[0];
+
+        let sir_function = &ctx.sir_program.funcs[parfor.body];
+        assert!(sir_function.loop_body);
+        self.gen_loop_body_function(ctx.sir_program, sir_function, parfor)?;
+        let body_function = self.functions[&parfor.body];
+
+        let vector_type = ctx.sir_function.symbol_type(&iter.data)?;
+        let elem_ty = match *vector_type {
+            Vector(ref elem) => self.llvm_type(elem)?,
+            _ => unreachable!(),
+        };
+        let vector_ty = self.llvm_type(vector_type)?;
+        let source = self.load(ctx.builder, ctx.get_value(iter.start.as_ref().unwrap())?)?;
+        let run = ctx.get_run();
+
+        // The runtime writes the chunk's data pointer here. Allocas belong in the entry block.
+        let entry_block = LLVMGetEntryBasicBlock(ctx.llvm_function);
+        let alloca_builder = LLVMCreateBuilderInContext(self.context);
+        LLVMPositionBuilderBefore(alloca_builder, LLVMGetFirstInstruction(entry_block));
+        let data_pointer = LLVMBuildAlloca(
+            alloca_builder,
+            LLVMPointerType(self.i8_type(), 0),
+            c_str!("chunk"),
+        );
+        LLVMDisposeBuilder(alloca_builder);
+
+        let start_block = LLVMGetInsertBlock(ctx.builder);
+        let chunk_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("loop.chunk"));
+        let body_block = LLVMAppendBasicBlockInContext(
+            self.context,
+            ctx.llvm_function,
+            c_str!("loop.chunk.body"),
+        );
+        let done_block = LLVMAppendBasicBlockInContext(
+            self.context,
+            ctx.llvm_function,
+            c_str!("loop.chunk.done"),
+        );
+        LLVMBuildBr(ctx.builder, chunk_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, chunk_block);
+        let offset = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!("offset"));
+        let elem_size = self.size_of(elem_ty);
+        let size = self.intrinsics.call_weld_run_next_chunk(
+            ctx.builder,
+            run,
+            source,
+            elem_size,
+            data_pointer,
+        );
+        let done = LLVMBuildICmp(ctx.builder, LLVMIntEQ, size, self.i64(0), c_str!(""));
+        LLVMBuildCondBr(ctx.builder, done, done_block, body_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, body_block);
+        let data = self.load(ctx.builder, data_pointer)?;
+        let elements = LLVMBuildBitCast(ctx.builder, data, LLVMPointerType(elem_ty, 0), c_str!(""));
+        let mut chunk = LLVMGetUndef(vector_ty);
+        chunk = LLVMBuildInsertValue(ctx.builder, chunk, elements, POINTER_INDEX, c_str!(""));
+        chunk = LLVMBuildInsertValue(ctx.builder, chunk, size, SIZE_INDEX, c_str!(""));
+        LLVMBuildStore(ctx.builder, chunk, ctx.get_value(&iter.data)?);
+
+        let mut arguments = vec![];
+        for (symbol, _) in sir_function.params.iter() {
+            let value = self.load(ctx.builder, ctx.get_value(symbol)?)?;
+            arguments.push(value);
+        }
+        let end = LLVMBuildNSWAdd(ctx.builder, offset, size, c_str!(""));
+        arguments.push(offset);
+        arguments.push(end);
+        arguments.push(run);
+        let builder = LLVMBuildCall(
+            ctx.builder,
+            body_function,
+            arguments.as_mut_ptr(),
+            arguments.len() as u32,
+            c_str!(""),
+        );
+        LLVMSetInstructionCallConv(builder, SIR_FUNC_CALL_CONV);
+        LLVMBuildStore(ctx.builder, builder, ctx.get_value(&parfor.builder)?);
+        let end_block = LLVMGetInsertBlock(ctx.builder);
+        LLVMBuildBr(ctx.builder, chunk_block);
+
+        let mut blocks = [start_block, end_block];
+        let mut values = [self.i64(0), end];
+        LLVMAddIncoming(
+            offset,
+            values.as_mut_ptr(),
+            blocks.as_mut_ptr(),
+            values.len() as u32,
+        );
+
+        LLVMPositionBuilderAtEnd(ctx.builder, done_block);
+        let builder = self.load(ctx.builder, ctx.get_value(&parfor.builder)?)?;
+        LLVMBuildStore(ctx.builder, builder, ctx.get_value(output)?);
+        Ok(offset)
+    }
+
+    fn is_parallel_builder(&self, builder: &Type) -> bool {
+        // Merging the pieces reorders the operations of a reduction.
+        let reorderable = |ty: &Type, op: BinOpKind| match *ty {
+            Scalar(kind) if kind.is_float() => {
+                self.conf.enable_fast_math || op == BinOpKind::Max || op == BinOpKind::Min
+            }
+            Scalar(_) => true,
+            _ => false,
+        };
+        match *builder {
+            Builder(Appender(_), _) => true,
+            Builder(Merger(ref elem, op), _) => reorderable(elem, op),
+            // Each chunk builds a local table, while a shared table is updated by a single thread.
+            Builder(DictMerger(_, ref value, op), ref annotations)
+                if annotations.get("impl") == Some("local") =>
+            {
+                match **value {
+                    Struct(ref fields, _) => fields.iter().all(|f| reorderable(f, op)),
+                    ref value => reorderable(value, op),
+                }
+            }
+            Struct(ref fields, _) => fields.iter().all(|f| self.is_parallel_builder(f)),
+            _ => false,
+        }
+    }
+
+    fn is_deterministic_builder(&self, builder: &Type) -> bool {
+        if !self.conf.deterministic_reduction {
+            return false;
+        }
+        match *builder {
+            Builder(Merger(ref elem, op), _) => match **elem {
+                Scalar(kind) => {
+                    kind.is_float() && (op == BinOpKind::Add || op == BinOpKind::Multiply)
+                }
+                _ => false,
+            },
+            Struct(ref fields, _) => fields.iter().any(|f| self.is_deterministic_builder(f)),
+            _ => false,
+        }
+    }
+
+    unsafe fn gen_serial_loop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        func: &SirFunction,
+        arguments: &[LLVMValueRef],
+        iterations: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let body_function = self.functions[&func.id];
+        let mut arguments = arguments.to_vec();
+        // The body function has additional arguments representing the range of iterations.
+        arguments.push(self.i64(0));
         arguments.push(iterations);
         // Last argument is always the run handle.
         arguments.push(ctx.get_run());
 
-        // Call the body function, which runs the loop and updates the builder. The updated builder
-        // is returned to the current function.
+        // Call the body function, which runs the loop and updates the builder. The updated
+        // builder is returned to the current function.
         let builder = LLVMBuildCall(
             ctx.builder,
             body_function,
@@ -129,10 +469,458 @@ impl ForLoopGenInternal for LlvmGenerator {
             c_str!(""),
         );
         LLVMSetInstructionCallConv(builder, SIR_FUNC_CALL_CONV);
-        // XXX what is parfor.builder now...
-        LLVMBuildStore(ctx.builder, builder, ctx.get_value(&parfor.builder)?);
-        LLVMBuildStore(ctx.builder, builder, ctx.get_value(&output)?);
+        builder
+    }
 
+    fn grain_size(&self, parfor: &ParallelForData) -> i64 {
+        parfor
+            .grain_size
+            .or(self.conf.grain_size)
+            .unwrap_or_else(|| {
+                let cost = cmp::max(parfor.iteration_cost as i64, 1);
+                cmp::max(CHUNK_COST / cost, MIN_GRAIN_SIZE)
+            })
+    }
+
+    /// Generates a parallel loop, which looks as follows:
+    ///
+    /// chunks = max(min(iterations / grain_size, max_chunks), 1)
+    /// if chunks == 1:
+    ///     return body(args..., 0, iterations)
+    /// pieces = weld_runst_malloc(run, chunks * sizeof(builder))
+    /// pieces[0] = builder
+    /// arguments = { args..., iterations, chunks, pieces }
+    /// weld_runst_parallel_for(run, chunk_function, arguments, chunks)
+    /// for k in 1..chunks:
+    ///     merge pieces[k] into pieces[0]
+    /// result = pieces[0]
+    /// weld_runst_free(run, pieces)
+    /// return result
+    ///
+    /// `max_chunks` is `MAX_CHUNKS_PER_THREAD` times the number of threads, or
+    /// `DETERMINISTIC_CHUNKS` if the builder is reduced deterministically.
+    unsafe fn gen_parallel_loop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        func: &SirFunction,
+        parfor: &ParallelForData,
+        arguments: &[LLVMValueRef],
+        iterations: LLVMValueRef,
+    ) -> WeldResult<LLVMValueRef> {
+        use self::llvm_sys::LLVMIntPredicate::{LLVMIntEQ, LLVMIntSGT, LLVMIntSLT};
+        let weld_ty = ctx.sir_function.symbol_type(&parfor.builder)?;
+        let builder_ty = self.llvm_type(weld_ty)?;
+        let arguments_ty = self.gen_chunk_arguments_type(func)?;
+        let chunk_function = self.gen_chunk_function(func, parfor)?;
+        let run = ctx.get_run();
+
+        let max_chunks = if self.is_deterministic_builder(weld_ty) {
+            DETERMINISTIC_CHUNKS
+        } else {
+            MAX_CHUNKS_PER_THREAD * i64::from(self.conf.threads)
+        };
+        let grain_size = self.i64(self.grain_size(parfor));
+        let chunks = LLVMBuildSDiv(ctx.builder, iterations, grain_size, c_str!(""));
+        let limit = self.i64(max_chunks);
+        let fewer = LLVMBuildICmp(ctx.builder, LLVMIntSLT, chunks, limit, c_str!(""));
+        let chunks = LLVMBuildSelect(ctx.builder, fewer, chunks, limit, c_str!(""));
+        let several = LLVMBuildICmp(ctx.builder, LLVMIntSGT, chunks, self.i64(1), c_str!(""));
+        let chunks = LLVMBuildSelect(ctx.builder, several, chunks, self.i64(1), c_str!("chunks"));
+
+        // A loop with a single chunk calls its body function directly, without allocating pieces
+        // or entering the runtime. This keeps small loops nested in another loop's body cheap.
+        let serial_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("loop.serial"));
+        let parallel_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("loop.parallel"));
+        let join_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("loop.join"));
+        LLVMBuildCondBr(ctx.builder, several, parallel_block, serial_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, serial_block);
+        let serial_result = self.gen_serial_loop(ctx, func, arguments, iterations);
+        let serial_end_block = LLVMGetInsertBlock(ctx.builder);
+        LLVMBuildBr(ctx.builder, join_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, parallel_block);
+
+        // A loop may have many more chunks than threads, so the pieces are allocated in the run's
+        // memory rather than on the stack. Allocas belong in the entry block.
+        let entry_block = LLVMGetEntryBasicBlock(ctx.llvm_function);
+        let alloca_builder = LLVMCreateBuilderInContext(self.context);
+        LLVMPositionBuilderBefore(alloca_builder, LLVMGetFirstInstruction(entry_block));
+        let arguments_pointer = LLVMBuildAlloca(alloca_builder, arguments_ty, c_str!("arguments"));
+        LLVMDisposeBuilder(alloca_builder);
+
+        // Builders of vectorized mergers hold SIMD values that need more alignment than the run's
+        // allocations provide, so the pieces start at the first aligned address of a larger
+        // allocation.
+        let layout = llvm_sys::target::LLVMGetModuleDataLayout(self.module());
+        let align = i64::from(llvm_sys::target::LLVMABIAlignmentOfType(layout, builder_ty));
+        let pieces_size =
+            LLVMBuildNSWMul(ctx.builder, chunks, self.size_of(builder_ty), c_str!(""));
+        let pieces_size = LLVMBuildNSWAdd(ctx.builder, pieces_size, self.i64(align), c_str!(""));
+        let pieces_bytes = self.intrinsics.call_weld_run_malloc(
+            ctx.builder,
+            run,
+            pieces_size,
+            Some(c_str!("pieces")),
+        );
+        let address = LLVMBuildPtrToInt(ctx.builder, pieces_bytes, self.i64_type(), c_str!(""));
+        let address = LLVMBuildNSWAdd(ctx.builder, address, self.i64(align - 1), c_str!(""));
+        let address = LLVMBuildAnd(ctx.builder, address, self.i64(-align), c_str!(""));
+        let pieces = LLVMBuildIntToPtr(
+            ctx.builder,
+            address,
+            LLVMPointerType(builder_ty, 0),
+            c_str!(""),
+        );
+        // The first chunk updates the loop's builder.
+        let initial = self.load(ctx.builder, ctx.get_value(&parfor.builder)?)?;
+        LLVMBuildStore(ctx.builder, initial, pieces);
+
+        let values = arguments
+            .iter()
+            .cloned()
+            .chain(vec![iterations, chunks, pieces]);
+        for (i, value) in values.enumerate() {
+            let pointer = LLVMBuildStructGEP(ctx.builder, arguments_pointer, i as u32, c_str!(""));
+            LLVMBuildStore(ctx.builder, value, pointer);
+        }
+        let arguments_bytes = LLVMBuildBitCast(
+            ctx.builder,
+            arguments_pointer,
+            LLVMPointerType(self.i8_type(), 0),
+            c_str!(""),
+        );
+
+        let function_pointer = LLVMConstBitCast(chunk_function, LLVMPointerType(self.i8_type(), 0));
+        let _ = self.intrinsics.call_weld_run_parallel_for(
+            ctx.builder,
+            run,
+            function_pointer,
+            arguments_bytes,
+            chunks,
+        );
+
+        // Merge the pieces into the first one in order.
+        let start_block = LLVMGetInsertBlock(ctx.builder);
+        let merge_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("merge.pieces"));
+        let done_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("merge.done"));
+        LLVMBuildBr(ctx.builder, merge_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, merge_block);
+        let k = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+        let piece = LLVMBuildGEP(ctx.builder, pieces, [k].as_mut_ptr(), 1, c_str!(""));
+        self.gen_merge_piece(ctx, weld_ty, run, pieces, piece)?;
+        let updated = LLVMBuildNSWAdd(ctx.builder, k, self.i64(1), c_str!(""));
+        let finished = LLVMBuildICmp(ctx.builder, LLVMIntEQ, updated, chunks, c_str!(""));
+        let end_block = LLVMGetInsertBlock(ctx.builder);
+        LLVMBuildCondBr(ctx.builder, finished, done_block, merge_block);
+
+        let mut blocks = [start_block, end_block];
+        let mut values = [self.i64(1), updated];
+        LLVMAddIncoming(
+            k,
+            values.as_mut_ptr(),
+            blocks.as_mut_ptr(),
+            values.len() as u32,
+        );
+
+        LLVMPositionBuilderAtEnd(ctx.builder, done_block);
+        let parallel_result = self.load(ctx.builder, pieces)?;
+        let _ = self
+            .intrinsics
+            .call_weld_run_free(ctx.builder, run, pieces_bytes);
+        let parallel_end_block = LLVMGetInsertBlock(ctx.builder);
+        LLVMBuildBr(ctx.builder, join_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, join_block);
+        let result = LLVMBuildPhi(ctx.builder, builder_ty, c_str!(""));
+        let mut blocks = [serial_end_block, parallel_end_block];
+        let mut values = [serial_result, parallel_result];
+        LLVMAddIncoming(
+            result,
+            values.as_mut_ptr(),
+            blocks.as_mut_ptr(),
+            values.len() as u32,
+        );
+        Ok(result)
+    }
+
+    unsafe fn gen_chunk_arguments_type(&mut self, func: &SirFunction) -> WeldResult<LLVMTypeRef> {
+        let builders: Vec<&Type> = func.params.values().filter(|v| v.is_builder()).collect();
+        assert_eq!(builders.len(), 1);
+        let builder_ty = self.llvm_type(builders[0])?;
+        let mut fields = self.argument_types(func)?;
+        fields.push(self.i64_type());
+        fields.push(self.i64_type());
+        fields.push(LLVMPointerType(builder_ty, 0));
+        Ok(LLVMStructTypeInContext(
+            self.context,
+            fields.as_mut_ptr(),
+            fields.len() as u32,
+            0,
+        ))
+    }
+
+    unsafe fn gen_chunk_function(
+        &mut self,
+        func: &SirFunction,
+        parfor: &ParallelForData,
+    ) -> WeldResult<LLVMValueRef> {
+        use self::llvm_sys::LLVMIntPredicate::{LLVMIntEQ, LLVMIntSLT};
+        let body_function = self.functions[&func.id];
+        let weld_ty = &func.params[&parfor.builder];
+        let arguments_ty = self.gen_chunk_arguments_type(func)?;
+
+        let mut arg_tys = [
+            LLVMPointerType(self.i8_type(), 0),
+            self.i64_type(),
+            self.run_handle_type(),
+        ];
+        let ret_ty = self.void_type();
+        let name = format!("f{}_chunk", func.id);
+        let (function, builder, _) = self.define_function(ret_ty, &mut arg_tys, name);
+
+        let chunk = LLVMGetParam(function, 1);
+        let run = LLVMGetParam(function, 2);
+        let arguments_pointer = LLVMBuildBitCast(
+            builder,
+            LLVMGetParam(function, 0),
+            LLVMPointerType(arguments_ty, 0),
+            c_str!("arguments"),
+        );
+        let mut arguments = vec![];
+        for i in 0..func.params.len() + 3 {
+            let pointer = LLVMBuildStructGEP(builder, arguments_pointer, i as u32, c_str!(""));
+            arguments.push(self.load(builder, pointer)?);
+        }
+        let pieces = arguments.pop().unwrap();
+        let chunks = arguments.pop().unwrap();
+        let iterations = arguments.pop().unwrap();
+
+        // Divide the iterations as evenly as possible: the first `iterations % chunks` chunks run
+        // one extra iteration.
+        //
+        // start = chunk * (iterations / chunks) + min(chunk, iterations % chunks)
+        let quotient = LLVMBuildSDiv(builder, iterations, chunks, c_str!(""));
+        let remainder = LLVMBuildSRem(builder, iterations, chunks, c_str!(""));
+        let extra = LLVMBuildICmp(builder, LLVMIntSLT, chunk, remainder, c_str!(""));
+        let offset = LLVMBuildSelect(builder, extra, chunk, remainder, c_str!(""));
+        let start = LLVMBuildNSWMul(builder, chunk, quotient, c_str!(""));
+        let start = LLVMBuildNSWAdd(builder, start, offset, c_str!("start"));
+        let extra = LLVMBuildZExt(builder, extra, self.i64_type(), c_str!(""));
+        let end = LLVMBuildNSWAdd(builder, start, quotient, c_str!(""));
+        let end = LLVMBuildNSWAdd(builder, end, extra, c_str!("end"));
+
+        let piece = LLVMBuildGEP(builder, pieces, [chunk].as_mut_ptr(), 1, c_str!("piece"));
+        let new_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!("new"));
+        let call_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!("call"));
+        let first = LLVMBuildICmp(builder, LLVMIntEQ, chunk, self.i64(0), c_str!(""));
+        LLVMBuildCondBr(builder, first, call_block, new_block);
+
+        LLVMPositionBuilderAtEnd(builder, new_block);
+        let new_piece = self.gen_new_piece(builder, weld_ty, run)?;
+        LLVMBuildStore(builder, new_piece, piece);
+        LLVMBuildBr(builder, call_block);
+
+        // The chunk runs the body function on its piece of the builder.
+        LLVMPositionBuilderAtEnd(builder, call_block);
+        let index = func
+            .params
+            .keys()
+            .position(|s| *s == parfor.builder)
+            .unwrap();
+        arguments[index] = self.load(builder, piece)?;
+        arguments.push(start);
+        arguments.push(end);
+        arguments.push(run);
+        let updated = LLVMBuildCall(
+            builder,
+            body_function,
+            arguments.as_mut_ptr(),
+            arguments.len() as u32,
+            c_str!(""),
+        );
+        LLVMSetInstructionCallConv(updated, SIR_FUNC_CALL_CONV);
+        LLVMBuildStore(builder, updated, piece);
+        LLVMBuildRetVoid(builder);
+
+        LLVMDisposeBuilder(builder);
+        Ok(function)
+    }
+
+    unsafe fn gen_new_piece(
+        &mut self,
+        builder: LLVMBuilderRef,
+        ty: &Type,
+        run: LLVMValueRef,
+    ) -> WeldResult<LLVMValueRef> {
+        match *ty {
+            Builder(ref kind @ Appender(_), _) => {
+                let capacity = self.i64(appender::DEFAULT_CAPACITY);
+                let methods = self.appenders.get_mut(kind).unwrap();
+                methods.gen_new(builder, &mut self.intrinsics, run, capacity)
+            }
+            Builder(ref kind @ Merger(_, _), _) => {
+                let methods = self.mergers.get_mut(kind).unwrap();
+                let identity = methods.binop_identity(methods.op, methods.scalar_kind)?;
+                methods.gen_new(builder, identity)
+            }
+            Builder(ref kind @ DictMerger(_, _, _), _) => {
+                let capacity = self.i64(dict::INITIAL_CAPACITY);
+                let methods = self.dictionaries.get_mut(&kind.result_type()).unwrap();
+                methods.gen_new(builder, &mut self.intrinsics, capacity, run)
+            }
+            Struct(ref fields, _) => {
+                let mut result = LLVMGetUndef(self.llvm_type(ty)?);
+                for (i, field) in fields.iter().enumerate() {
+                    let value = self.gen_new_piece(builder, field, run)?;
+                    result = LLVMBuildInsertValue(builder, result, value, i as u32, c_str!(""));
+                }
+                Ok(result)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    unsafe fn gen_merge_piece(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        ty: &Type,
+        run: LLVMValueRef,
+        pointer: LLVMValueRef,
+        piece: LLVMValueRef,
+    ) -> WeldResult<()> {
+        use super::merger::CheckedMergerGen;
+        let builder = ctx.builder;
+        match *ty {
+            Builder(ref kind @ Appender(_), _) => {
+                let methods = self.appenders.get_mut(kind).unwrap();
+                let _ = methods.gen_extend(builder, &mut self.intrinsics, run, pointer, piece)?;
+            }
+            Builder(ref kind @ Merger(_, _), _) if self.is_checked_merger(kind) => {
+                self.gen_checked_combine(ctx, kind, pointer, piece)?;
+            }
+            Builder(ref kind @ Merger(_, _), _) => {
+                let methods = self.mergers.get_mut(kind).unwrap();
+                methods.gen_combine(builder, pointer, piece)?;
+            }
+            Builder(ref kind @ DictMerger(_, _, _), _) => {
+                self.gen_merge_dict_piece(ctx, kind, run, pointer, piece)?;
+            }
+            Struct(ref fields, _) => {
+                for (i, field) in fields.iter().enumerate() {
+                    let field_pointer = LLVMBuildStructGEP(builder, pointer, i as u32, c_str!(""));
+                    let field_piece = LLVMBuildStructGEP(builder, piece, i as u32, c_str!(""));
+                    self.gen_merge_piece(ctx, field, run, field_pointer, field_piece)?;
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Generates a loop over the slots of the piece, which looks as follows:
+    ///
+    /// merge.dict:
+    ///     i = phi [0, entry], [i + 1, merge.dict.next]
+    ///     slot = piece.slots[i]
+    ///     if !slot.filled: goto merge.dict.next
+    /// merge.dict.slot:
+    ///     value = upsert(dict, slot.key, slot.hash, default)
+    ///     *value = *value <op> slot.value
+    /// merge.dict.next:
+    ///     if i + 1 == piece.capacity: goto merge.dict.done, else: goto merge.dict
+    /// merge.dict.done:
+    unsafe fn gen_merge_dict_piece(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        kind: &BuilderKind,
+        run: LLVMValueRef,
+        pointer: LLVMValueRef,
+        piece: LLVMValueRef,
+    ) -> WeldResult<()> {
+        use self::llvm_sys::LLVMIntPredicate::LLVMIntEQ;
+        use super::BuilderExpressionGen;
+        let (value_ty, op) = match *kind {
+            DictMerger(_, ref value, op) => (value.as_ref(), op),
+            _ => unreachable!(),
+        };
+        let dict_type = kind.result_type();
+        let default = self.dictmerger_default(value_ty, op)?;
+        let dict = self.load(ctx.builder, pointer)?;
+        let piece = self.load(ctx.builder, piece)?;
+        // Dictionaries always have a capacity of at least `dict::INITIAL_CAPACITY`.
+        let (slot_array, capacity) = self
+            .dictionaries
+            .get_mut(&dict_type)
+            .unwrap()
+            .gen_slots(ctx.builder, piece);
+
+        let start_block = LLVMGetInsertBlock(ctx.builder);
+        let top_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("merge.dict"));
+        let slot_block = LLVMAppendBasicBlockInContext(
+            self.context,
+            ctx.llvm_function,
+            c_str!("merge.dict.slot"),
+        );
+        let next_block = LLVMAppendBasicBlockInContext(
+            self.context,
+            ctx.llvm_function,
+            c_str!("merge.dict.next"),
+        );
+        let done_block = LLVMAppendBasicBlockInContext(
+            self.context,
+            ctx.llvm_function,
+            c_str!("merge.dict.done"),
+        );
+        LLVMBuildBr(ctx.builder, top_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, top_block);
+        let i = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+        let slot = LLVMBuildGEP(ctx.builder, slot_array, [i].as_mut_ptr(), 1, c_str!(""));
+        let methods = self.dictionaries.get_mut(&dict_type).unwrap();
+        let filled = methods.slot_ty.filled(ctx.builder, slot);
+        LLVMBuildCondBr(ctx.builder, filled, slot_block, next_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, slot_block);
+        let key = methods.slot_ty.key(ctx.builder, slot);
+        let hash_pointer = methods.slot_ty.hash(ctx.builder, slot);
+        let hash = LLVMBuildLoad(ctx.builder, hash_pointer, c_str!(""));
+        let value = methods.slot_ty.value(ctx.builder, slot);
+        let target = methods.gen_upsert(
+            ctx.builder,
+            &mut self.intrinsics,
+            dict,
+            key,
+            hash,
+            default,
+            run,
+        )?;
+        let target_value = methods.slot_ty.value(ctx.builder, target);
+        self.merge_values(ctx, value_ty, op, target_value, value)?;
+        LLVMBuildBr(ctx.builder, next_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, next_block);
+        let updated = LLVMBuildNSWAdd(ctx.builder, i, self.i64(1), c_str!(""));
+        let finished = LLVMBuildICmp(ctx.builder, LLVMIntEQ, updated, capacity, c_str!(""));
+        LLVMBuildCondBr(ctx.builder, finished, done_block, top_block);
+
+        let mut blocks = [start_block, next_block];
+        let mut values = [self.i64(0), updated];
+        LLVMAddIncoming(
+            i,
+            values.as_mut_ptr(),
+            blocks.as_mut_ptr(),
+            values.len() as u32,
+        );
+
+        LLVMPositionBuilderAtEnd(ctx.builder, done_block);
         Ok(())
     }
 
@@ -215,13 +1003,13 @@ impl ForLoopGenInternal for LlvmGenerator {
     ///
     /// A loop body function has the following layout:
     ///
-    /// { builders } FuncName(arg1, arg2, ..., iterations, run):
+    /// { builders } FuncName(arg1, arg2, ..., start, end, run):
     /// entry:
     ///     alloca all variables except the local builder.
     ///     alias builder argument with parfor.builder_arg
     ///     br loop.begin
     /// loop.begin:
-    ///     i = <initializer code>
+    ///     i = start
     ///     br loop.entry
     /// loop.entry:
     ///     if i >= end:
@@ -230,6 +1018,8 @@ impl ForLoopGenInternal for LlvmGenerator {
     ///         br loop.body
     /// loop.body:
     ///     e = < load elements > based on i
+    ///     br body.start
+    /// body.start:
     ///     < generate function body>, replace EndFunction with Br loop.check
     /// loop.check:
     ///     update i
@@ -256,8 +1046,10 @@ impl ForLoopGenInternal for LlvmGenerator {
         let weld_ty = &builders[0];
 
         let mut arg_tys = self.argument_types(func)?;
-        // The second-to-last argument is the *total* number of iterations across all threads (in a
-        // multi-threaded setting) that this loop will execute for.
+        // The next two arguments are the range of iterations [start, end) this call executes. The
+        // loop index is relative to the whole loop, even if the call only runs one chunk of it.
+        arg_tys.push(self.i64_type());
+        let start_index = (arg_tys.len() - 1) as u32;
         arg_tys.push(self.i64_type());
         let num_iterations_index = (arg_tys.len() - 1) as u32;
         // Last argument is run handle, as always.
@@ -270,17 +1062,16 @@ impl ForLoopGenInternal for LlvmGenerator {
         LLVMSetLinkage(function, LLVMLinkage::LLVMPrivateLinkage);
 
         LLVMExtAddDefaultAttrs(self.context(), function);
-        // We always inline this since it will appear only once in the program, so there's no code
-        // size cost to doing it.
-        //
-        // XXX this should only be here in the single threaded setting.
+        // We always inline this since it is only called once, either by the function containing
+        // the loop or by the loop's chunk function, so there's no code size cost to doing it.
         LLVMExtAddAttrsOnFunction(self.context, function, &[AlwaysInline]);
 
         self.functions.insert(func.id, function);
 
         // Create a context for the function.
         let context = &mut FunctionContext::new(self.context, program, func, function);
-        // Reference to the parameter storing the max number of iterations.
+        // References to the parameters storing the range of iterations.
+        let start = LLVMGetParam(context.llvm_function, start_index);
         let max = LLVMGetParam(context.llvm_function, num_iterations_index);
         // Create the entry basic block, where we define alloca'd variables.
         let entry_bb =
@@ -296,11 +1087,7 @@ impl ForLoopGenInternal for LlvmGenerator {
             self.load(context.builder, context.get_value(&parfor.builder)?)?,
             context.get_value(&parfor.builder_arg)?,
         );
-        LLVMBuildStore(
-            context.builder,
-            self.i64(0),
-            context.get_value(&parfor.idx_arg)?,
-        );
+        LLVMBuildStore(context.builder, start, context.get_value(&parfor.idx_arg)?);
 
         // Add the SIR function basic blocks.
         self.gen_basic_block_defs(context)?;
@@ -316,25 +1103,22 @@ impl ForLoopGenInternal for LlvmGenerator {
             context.builder,
             LLVMIntPredicate::LLVMIntNE,
             max,
-            self.i64(0),
+            start,
             c_str!(""),
         );
-        // First body block of the SIR function.
-        let first_body_block = context.blocks[&0];
-        LLVMBuildCondBr(
-            context.builder,
-            any_iters_cond,
-            first_body_block,
-            loop_exit_bb,
-        );
-
-        // Build the loop body.
-        LLVMPositionBuilderAtEnd(context.builder, first_body_block);
+        // The loop body loads the loop element and then jumps to the first body block of the SIR
+        // function. Loading an element may require control flow of its own (e.g., for `NdIter`), so
+        // it gets a separate block.
+        let loop_body_bb =
+            LLVMAppendBasicBlockInContext(self.context, context.llvm_function, c_str!("loop.body"));
+        LLVMBuildCondBr(context.builder, any_iters_cond, loop_body_bb, loop_exit_bb);
 
         // Load the loop element.
+        LLVMPositionBuilderAtEnd(context.builder, loop_body_bb);
         let i = self.load(context.builder, context.get_value(&parfor.idx_arg)?)?;
         let e = context.get_value(&parfor.data_arg)?;
-        self.gen_loop_element(context, i, e, parfor)?;
+        self.gen_loop_element(context, i, start, e, parfor)?;
+        LLVMBuildBr(context.builder, context.blocks[&0]);
 
         // Generate the body - this resembles the usual SIR function generation, but we pass a
         // basic block ID to gen_terminator to change the `EndFunction` terminators to a basic
@@ -371,12 +1155,7 @@ impl ForLoopGenInternal for LlvmGenerator {
             updated,
             c_str!(""),
         );
-        let _ = LLVMBuildCondBr(
-            context.builder,
-            finished_cond,
-            loop_exit_bb,
-            first_body_block,
-        );
+        let _ = LLVMBuildCondBr(context.builder, finished_cond, loop_exit_bb, loop_body_bb);
 
         // The last basic block loads the updated builder and returns it.
         LLVMPositionBuilderAtEnd(context.builder, loop_exit_bb);
@@ -390,6 +1169,7 @@ impl ForLoopGenInternal for LlvmGenerator {
         &mut self,
         ctx: &mut FunctionContext<'_>,
         i: LLVMValueRef,
+        start: LLVMValueRef,
         e: LLVMValueRef,
         parfor: &ParallelForData,
     ) -> WeldResult<()> {
@@ -420,6 +1200,15 @@ impl ForLoopGenInternal for LlvmGenerator {
                     let element = self.load(ctx.builder, element_pointer)?;
                     values.push(element);
                 }
+                ChunkIter => {
+                    // The data is the current chunk: Index = i - start.
+                    let i = LLVMBuildNSWSub(ctx.builder, i, start, c_str!(""));
+                    let vector = self.load(ctx.builder, ctx.get_value(&iter.data)?)?;
+                    let vector_type = ctx.sir_function.symbol_type(&iter.data)?;
+                    let element_pointer = self.gen_at(ctx.builder, vector_type, vector, i)?;
+                    let element = self.load(ctx.builder, element_pointer)?;
+                    values.push(element);
+                }
                 SimdIter if iter.start.is_some() => unreachable!(),
                 SimdIter => {
                     let i = LLVMBuildNSWMul(
@@ -466,7 +1255,89 @@ impl ForLoopGenInternal for LlvmGenerator {
                     let i = LLVMBuildNSWAdd(ctx.builder, start, tmp, c_str!(""));
                     values.push(i);
                 }
-                NdIter => unimplemented!(), // NdIter Load Element
+                NdIter => {
+                    use self::llvm_sys::LLVMIntPredicate::LLVMIntSGT;
+                    let start =
+                        self.load(ctx.builder, ctx.get_value(iter.start.as_ref().unwrap())?)?;
+                    let shape =
+                        self.load(ctx.builder, ctx.get_value(iter.shape.as_ref().unwrap())?)?;
+                    let shape_type = ctx.sir_function.symbol_type(iter.shape.as_ref().unwrap())?;
+                    let strides =
+                        self.load(ctx.builder, ctx.get_value(iter.strides.as_ref().unwrap())?)?;
+                    let strides_type = ctx
+                        .sir_function
+                        .symbol_type(iter.strides.as_ref().unwrap())?;
+                    let dims = self.gen_size(ctx.builder, shape_type, shape)?;
+
+                    // Linearize `i` in row-major order, starting from the innermost dimension:
+                    //
+                    // Index = start + sum over k of ((i / prod(shape[k+1..])) % shape[k]) * strides[k]
+                    let start_block = LLVMGetInsertBlock(ctx.builder);
+                    let loop_block = LLVMAppendBasicBlockInContext(
+                        self.context,
+                        ctx.llvm_function,
+                        c_str!("nditer.index"),
+                    );
+                    let end_block = LLVMAppendBasicBlockInContext(
+                        self.context,
+                        ctx.llvm_function,
+                        c_str!("nditer.load"),
+                    );
+                    let any_dims =
+                        LLVMBuildICmp(ctx.builder, LLVMIntSGT, dims, self.i64(0), c_str!(""));
+                    let _ = LLVMBuildCondBr(ctx.builder, any_dims, loop_block, end_block);
+
+                    LLVMPositionBuilderAtEnd(ctx.builder, loop_block);
+                    let k = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+                    let remaining = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+                    let offset = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+
+                    let updated_k = LLVMBuildNSWSub(ctx.builder, k, self.i64(1), c_str!(""));
+                    let pointer = self.gen_at(ctx.builder, shape_type, shape, updated_k)?;
+                    let dim = self.load(ctx.builder, pointer)?;
+                    let pointer = self.gen_at(ctx.builder, strides_type, strides, updated_k)?;
+                    let stride = self.load(ctx.builder, pointer)?;
+
+                    let index = LLVMBuildSRem(ctx.builder, remaining, dim, c_str!(""));
+                    let updated_remaining = LLVMBuildSDiv(ctx.builder, remaining, dim, c_str!(""));
+                    let tmp = LLVMBuildNSWMul(ctx.builder, index, stride, c_str!(""));
+                    let updated_offset = LLVMBuildNSWAdd(ctx.builder, offset, tmp, c_str!(""));
+                    let compare =
+                        LLVMBuildICmp(ctx.builder, LLVMIntSGT, updated_k, self.i64(0), c_str!(""));
+                    let _ = LLVMBuildCondBr(ctx.builder, compare, loop_block, end_block);
+
+                    let mut blocks = [start_block, loop_block];
+                    let phis = [
+                        (k, dims, updated_k),
+                        (remaining, i, updated_remaining),
+                        (offset, start, updated_offset),
+                    ];
+                    for (phi, initial, updated) in phis.iter() {
+                        let mut incoming = [*initial, *updated];
+                        LLVMAddIncoming(
+                            *phi,
+                            incoming.as_mut_ptr(),
+                            blocks.as_mut_ptr(),
+                            incoming.len() as u32,
+                        );
+                    }
+
+                    LLVMPositionBuilderAtEnd(ctx.builder, end_block);
+                    let i = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+                    let mut incoming = [start, updated_offset];
+                    LLVMAddIncoming(
+                        i,
+                        incoming.as_mut_ptr(),
+                        blocks.as_mut_ptr(),
+                        incoming.len() as u32,
+                    );
+
+                    let vector = self.load(ctx.builder, ctx.get_value(&iter.data)?)?;
+                    let vector_type = ctx.sir_function.symbol_type(&iter.data)?;
+                    let element_pointer = self.gen_at(ctx.builder, vector_type, vector, i)?;
+                    let element = self.load(ctx.builder, element_pointer)?;
+                    values.push(element);
+                }
             }
         }
 
@@ -547,6 +1418,8 @@ impl ForLoopGenInternal for LlvmGenerator {
                 let _ = LLVMBuildBr(ctx.builder, pass_block);
                 Ok(iterations)
             }
+            // Chunked loops do not know their number of iterations up front.
+            ChunkIter => unreachable!(),
             FringeIter if iter.start.is_some() => unreachable!(),
             FringeIter => {
                 let iterations = LLVMBuildSRem(
@@ -559,26 +1432,179 @@ impl ForLoopGenInternal for LlvmGenerator {
                 Ok(iterations)
             }
             RangeIter => {
-                use self::llvm_sys::LLVMIntPredicate::{LLVMIntEQ, LLVMIntSLT};
+                use self::llvm_sys::LLVMIntPredicate::LLVMIntSGT;
                 let start = self.load(ctx.builder, ctx.get_value(iter.start.as_ref().unwrap())?)?;
                 let stride =
                     self.load(ctx.builder, ctx.get_value(iter.stride.as_ref().unwrap())?)?;
                 let end = self.load(ctx.builder, ctx.get_value(iter.end.as_ref().unwrap())?)?;
+
+                // The range is [start, end), so it does not need to be a multiple of the stride
+                // and may be empty. Since there is no vector to read from, no other checks are
+                // required.
+                //
+                // Iterations = max((end - start + stride - 1) / stride, 0)
                 let diff = LLVMBuildNSWSub(ctx.builder, end, start, c_str!(""));
+                let stride_minus_one =
+                    LLVMBuildNSWSub(ctx.builder, stride, self.i64(1), c_str!(""));
+                let diff = LLVMBuildNSWAdd(ctx.builder, diff, stride_minus_one, c_str!(""));
                 let iterations = LLVMBuildSDiv(ctx.builder, diff, stride, c_str!(""));
+                let nonempty =
+                    LLVMBuildICmp(ctx.builder, LLVMIntSGT, iterations, self.i64(0), c_str!(""));
+                let iterations =
+                    LLVMBuildSelect(ctx.builder, nonempty, iterations, self.i64(0), c_str!(""));
 
                 if self.conf.enable_bounds_checks {
                     // Checks required:
-                    // start < end
-                    // (end - start) % stride == 0
-                    // Iterations = (end - start) / stride
-                    let end_start_check =
-                        LLVMBuildICmp(ctx.builder, LLVMIntSLT, start, end, c_str!(""));
-                    let mod_check = LLVMBuildSRem(ctx.builder, diff, stride, c_str!(""));
-                    let mod_check =
-                        LLVMBuildICmp(ctx.builder, LLVMIntEQ, mod_check, self.i64(0), c_str!(""));
+                    // stride > 0
+                    let check =
+                        LLVMBuildICmp(ctx.builder, LLVMIntSGT, stride, self.i64(0), c_str!(""));
+                    let _ = LLVMBuildCondBr(ctx.builder, check, pass_block, fail_block);
+                } else {
+                    let _ = LLVMBuildBr(ctx.builder, pass_block);
+                }
+
+                Ok(iterations)
+            }
+            NdIter => {
+                use self::llvm_sys::LLVMIntPredicate::{
+                    LLVMIntEQ, LLVMIntSGE, LLVMIntSGT, LLVMIntSLT,
+                };
+                let start = self.load(ctx.builder, ctx.get_value(iter.start.as_ref().unwrap())?)?;
+                let shape = self.load(ctx.builder, ctx.get_value(iter.shape.as_ref().unwrap())?)?;
+                let shape_type = ctx.sir_function.symbol_type(iter.shape.as_ref().unwrap())?;
+                let strides =
+                    self.load(ctx.builder, ctx.get_value(iter.strides.as_ref().unwrap())?)?;
+                let strides_type = ctx
+                    .sir_function
+                    .symbol_type(iter.strides.as_ref().unwrap())?;
+                let dims = self.gen_size(ctx.builder, shape_type, shape)?;
+                let strides_size = self.gen_size(ctx.builder, strides_type, strides)?;
+
+                // The loop over the dimensions reads `strides`, so its length is checked first.
+                let dims_block = LLVMAppendBasicBlockInContext(
+                    self.context,
+                    ctx.llvm_function,
+                    c_str!("nditer.dims"),
+                );
+                if self.conf.enable_bounds_checks {
+                    let check =
+                        LLVMBuildICmp(ctx.builder, LLVMIntEQ, dims, strides_size, c_str!(""));
+                    let _ = LLVMBuildCondBr(ctx.builder, check, dims_block, fail_block);
+                } else {
+                    let _ = LLVMBuildBr(ctx.builder, dims_block);
+                }
+
+                // Walk the dimensions to compute the number of iterations (the product of the
+                // shape) and the smallest and largest offsets the iterator will touch.
+                //
+                // nditer.dims:
+                //      if dims > 0: goto nditer.loop, else goto nditer.end
+                // nditer.loop:
+                //      k, iterations, min, max, valid = phi ...
+                //      contribution = (shape[k] - 1) * strides[k]
+                //      update iterations, min, max, valid
+                //      if k + 1 < dims: goto nditer.loop, else goto nditer.end
+                // nditer.end:
+                //      iterations, min, max, valid = phi ...
+                LLVMPositionBuilderAtEnd(ctx.builder, dims_block);
+                let loop_block = LLVMAppendBasicBlockInContext(
+                    self.context,
+                    ctx.llvm_function,
+                    c_str!("nditer.loop"),
+                );
+                let end_block = LLVMAppendBasicBlockInContext(
+                    self.context,
+                    ctx.llvm_function,
+                    c_str!("nditer.end"),
+                );
+                let any_dims =
+                    LLVMBuildICmp(ctx.builder, LLVMIntSGT, dims, self.i64(0), c_str!(""));
+                let _ = LLVMBuildCondBr(ctx.builder, any_dims, loop_block, end_block);
 
-                    let check = LLVMBuildAnd(ctx.builder, end_start_check, mod_check, c_str!(""));
+                LLVMPositionBuilderAtEnd(ctx.builder, loop_block);
+                let k = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+                let phi_iterations = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+                let phi_min = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+                let phi_max = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+                let phi_valid = LLVMBuildPhi(ctx.builder, self.i1_type(), c_str!(""));
+
+                let pointer = self.gen_at(ctx.builder, shape_type, shape, k)?;
+                let dim = self.load(ctx.builder, pointer)?;
+                let pointer = self.gen_at(ctx.builder, strides_type, strides, k)?;
+                let stride = self.load(ctx.builder, pointer)?;
+
+                let updated_iterations =
+                    LLVMBuildNSWMul(ctx.builder, phi_iterations, dim, c_str!(""));
+                let dim_minus_one = LLVMBuildNSWSub(ctx.builder, dim, self.i64(1), c_str!(""));
+                let contribution = LLVMBuildNSWMul(ctx.builder, dim_minus_one, stride, c_str!(""));
+                let positive = LLVMBuildICmp(
+                    ctx.builder,
+                    LLVMIntSGT,
+                    contribution,
+                    self.i64(0),
+                    c_str!(""),
+                );
+                let tmp =
+                    LLVMBuildSelect(ctx.builder, positive, contribution, self.i64(0), c_str!(""));
+                let updated_max = LLVMBuildNSWAdd(ctx.builder, phi_max, tmp, c_str!(""));
+                let tmp =
+                    LLVMBuildSelect(ctx.builder, positive, self.i64(0), contribution, c_str!(""));
+                let updated_min = LLVMBuildNSWAdd(ctx.builder, phi_min, tmp, c_str!(""));
+                let dim_check =
+                    LLVMBuildICmp(ctx.builder, LLVMIntSGE, dim, self.i64(0), c_str!(""));
+                let updated_valid = LLVMBuildAnd(ctx.builder, phi_valid, dim_check, c_str!(""));
+
+                let updated_k = LLVMBuildNSWAdd(ctx.builder, k, self.i64(1), c_str!(""));
+                let compare = LLVMBuildICmp(ctx.builder, LLVMIntSLT, updated_k, dims, c_str!(""));
+                let _ = LLVMBuildCondBr(ctx.builder, compare, loop_block, end_block);
+
+                let mut blocks = [dims_block, loop_block];
+                let phis = [
+                    (k, self.i64(0), updated_k),
+                    (phi_iterations, self.i64(1), updated_iterations),
+                    (phi_min, start, updated_min),
+                    (phi_max, start, updated_max),
+                    (phi_valid, self.i1(true), updated_valid),
+                ];
+                for (phi, initial, updated) in phis.iter() {
+                    let mut values = [*initial, *updated];
+                    LLVMAddIncoming(
+                        *phi,
+                        values.as_mut_ptr(),
+                        blocks.as_mut_ptr(),
+                        values.len() as u32,
+                    );
+                }
+
+                LLVMPositionBuilderAtEnd(ctx.builder, end_block);
+                let mut results = vec![];
+                for (_, initial, updated) in phis.iter().skip(1) {
+                    let phi = LLVMBuildPhi(ctx.builder, LLVMTypeOf(*initial), c_str!(""));
+                    let mut values = [*initial, *updated];
+                    LLVMAddIncoming(
+                        phi,
+                        values.as_mut_ptr(),
+                        blocks.as_mut_ptr(),
+                        values.len() as u32,
+                    );
+                    results.push(phi);
+                }
+                let (iterations, min, max, valid) =
+                    (results[0], results[1], results[2], results[3]);
+
+                if self.conf.enable_bounds_checks {
+                    // Checks required:
+                    // len(shape) == len(strides) (checked above)
+                    // shape[k] >= 0 for all k
+                    // iterations == 0 || (min >= 0 && max < size)
+                    let empty =
+                        LLVMBuildICmp(ctx.builder, LLVMIntEQ, iterations, self.i64(0), c_str!(""));
+                    let min_check =
+                        LLVMBuildICmp(ctx.builder, LLVMIntSGE, min, self.i64(0), c_str!(""));
+                    let max_check = LLVMBuildICmp(ctx.builder, LLVMIntSLT, max, size, c_str!(""));
+                    let in_bounds = LLVMBuildAnd(ctx.builder, min_check, max_check, c_str!(""));
+                    let in_bounds = LLVMBuildOr(ctx.builder, empty, in_bounds, c_str!(""));
+                    let check = LLVMBuildAnd(ctx.builder, valid, in_bounds, c_str!(""));
                     let _ = LLVMBuildCondBr(ctx.builder, check, pass_block, fail_block);
                 } else {
                     let _ = LLVMBuildBr(ctx.builder, pass_block);
@@ -586,7 +1612,6 @@ impl ForLoopGenInternal for LlvmGenerator {
 
                 Ok(iterations)
             }
-            NdIter => unimplemented!(), // NdIter Compute Bounds Check
         }
     }
 
@@ -611,3 +1636,27 @@ impl ForLoopGenInternal for LlvmGenerator {
         Ok(())
     }
 }
+
+#[test]
+fn parallel_dictmergers() {
+    use crate::conf::ParsedConf;
+    use crate::syntax::parser::parse_expr;
+
+    let conf = ParsedConf::parse(&crate::WeldConf::new()).unwrap();
+    let gen = unsafe { LlvmGenerator::new(conf).unwrap() };
+    let ty = |code: &str| {
+        let mut expr = parse_expr(code).unwrap();
+        expr.infer_types().unwrap();
+        expr.ty
+    };
+    // Only dictmergers with local tables are divided among threads.
+    assert!(gen.is_parallel_builder(&ty("@(impl:local) dictmerger[i32,i64,+]")));
+    assert!(gen.is_parallel_builder(&ty(
+        "{appender[i32], @(impl:local) dictmerger[i32,{i32,i64},+]}"
+    )));
+    assert!(!gen.is_parallel_builder(&ty("@(impl:shared) dictmerger[i32,i64,+]")));
+    assert!(!gen.is_parallel_builder(&ty("dictmerger[i32,i64,+]")));
+    // Merging the tables would reorder floating point sums.
+    assert!(!gen.is_parallel_builder(&ty("@(impl:local) dictmerger[i32,f64,+]")));
+    assert!(gen.is_parallel_builder(&ty("@(impl:local) dictmerger[i32,f64,max]")));
+}
@@ -1,10 +1,27 @@
 //! Code generation for the merger builder type.
+//!
+//! # Layout
+//!
+//! A merger over a scalar `T` with operator `op` is the struct `{ T, <W x T> }`, where `W` is the
+//! `LLVM_VECTOR_WIDTH`. The first field accumulates scalar merges and starts at the initial value;
+//! the second accumulates vectorized merges and starts with `op`'s identity in every lane.
+//!
+//! * `merge(merger*, T)` and `vmerge(merger*, <W x T>)` update the respective field in place.
+//! * `result(merger*)` folds the lanes of the vector field into the scalar field, in order.
+//!
+//! Multi-threaded loops give each thread its own copy created with the identity as the initial
+//! value, and merge the copies into the original in order when the loop finishes.
+//!
+//! These functions do not take the run handle, so they cannot raise errors. With
+//! `weld.compile.checkedArithmetic` enabled, integer sums and products are instead generated inline
+//! in the calling function by `CheckedMergerGen`, so that an overflow raises an error.
 
 use llvm_sys;
 
 use std::ffi::CString;
 
 use crate::ast::BinOpKind;
+use crate::ast::BuilderKind;
 use crate::ast::ScalarKind;
 use crate::ast::Type::{Scalar, Simd};
 use crate::error::*;
@@ -15,8 +32,9 @@ use self::llvm_sys::LLVMTypeKind;
 
 use crate::codegen::llvm2::llvm_exts::*;
 use crate::codegen::llvm2::numeric::gen_binop;
+use crate::codegen::llvm2::numeric::NumericExpressionGen;
 use crate::codegen::llvm2::CodeGenExt;
-use crate::codegen::llvm2::LLVM_VECTOR_WIDTH;
+use crate::codegen::llvm2::{FunctionContext, LlvmGenerator, LLVM_VECTOR_WIDTH};
 
 const SCALAR_INDEX: u32 = 0;
 const VECTOR_INDEX: u32 = 1;
@@ -188,6 +206,21 @@ impl Merger {
         }
     }
 
+    /// Generates code to merge the merger pointed to by `other` into the one pointed to by `builder`.
+    pub unsafe fn gen_combine(
+        &mut self,
+        llvm_builder: LLVMBuilderRef,
+        builder: LLVMValueRef,
+        other: LLVMValueRef,
+    ) -> WeldResult<()> {
+        for index in [SCALAR_INDEX, VECTOR_INDEX].iter() {
+            let pointer = LLVMBuildStructGEP(llvm_builder, other, *index, c_str!(""));
+            let value = LLVMBuildLoad(llvm_builder, pointer, c_str!(""));
+            let _ = self.gen_merge(llvm_builder, builder, value)?;
+        }
+        Ok(())
+    }
+
     pub unsafe fn gen_result(
         &mut self,
         llvm_builder: LLVMBuilderRef,
@@ -236,3 +269,97 @@ impl Merger {
         ))
     }
 }
+
+/// Generates merger operations that check integer arithmetic for overflow.
+pub trait CheckedMergerGen {
+    /// Returns whether operations on a merger of kind `kind` must be checked for overflow.
+    fn is_checked_merger(&self, kind: &BuilderKind) -> bool;
+    /// Merges `value`, a scalar or SIMD value, into the merger pointed to by `builder`.
+    unsafe fn gen_checked_merge(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        kind: &BuilderKind,
+        builder: LLVMValueRef,
+        value: LLVMValueRef,
+    ) -> WeldResult<()>;
+    /// Merges the merger pointed to by `other` into the one pointed to by `builder`.
+    unsafe fn gen_checked_combine(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        kind: &BuilderKind,
+        builder: LLVMValueRef,
+        other: LLVMValueRef,
+    ) -> WeldResult<()>;
+    /// Returns the result of the merger pointed to by `builder`.
+    unsafe fn gen_checked_result(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        kind: &BuilderKind,
+        builder: LLVMValueRef,
+    ) -> WeldResult<LLVMValueRef>;
+}
+
+impl CheckedMergerGen for LlvmGenerator {
+    fn is_checked_merger(&self, kind: &BuilderKind) -> bool {
+        let merger = &self.mergers[kind];
+        self.conf.checked_arithmetic
+            && merger.scalar_kind.is_integer()
+            && (merger.op == BinOpKind::Add || merger.op == BinOpKind::Multiply)
+    }
+
+    unsafe fn gen_checked_merge(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        kind: &BuilderKind,
+        builder: LLVMValueRef,
+        value: LLVMValueRef,
+    ) -> WeldResult<()> {
+        let (op, scalar_kind) = (self.mergers[kind].op, self.mergers[kind].scalar_kind);
+        let vectorized = LLVMGetTypeKind(LLVMTypeOf(value)) == LLVMTypeKind::LLVMVectorTypeKind;
+        let (index, ty) = if vectorized {
+            (VECTOR_INDEX, Simd(scalar_kind))
+        } else {
+            (SCALAR_INDEX, Scalar(scalar_kind))
+        };
+        let pointer = LLVMBuildStructGEP(ctx.builder, builder, index, c_str!(""));
+        let elem = LLVMBuildLoad(ctx.builder, pointer, c_str!(""));
+        let result = self.gen_arith_binop(ctx, op, elem, value, &ty, None)?;
+        LLVMBuildStore(ctx.builder, result, pointer);
+        Ok(())
+    }
+
+    unsafe fn gen_checked_combine(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        kind: &BuilderKind,
+        builder: LLVMValueRef,
+        other: LLVMValueRef,
+    ) -> WeldResult<()> {
+        for index in [SCALAR_INDEX, VECTOR_INDEX].iter() {
+            let pointer = LLVMBuildStructGEP(ctx.builder, other, *index, c_str!(""));
+            let value = LLVMBuildLoad(ctx.builder, pointer, c_str!(""));
+            self.gen_checked_merge(ctx, kind, builder, value)?;
+        }
+        Ok(())
+    }
+
+    unsafe fn gen_checked_result(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        kind: &BuilderKind,
+        builder: LLVMValueRef,
+    ) -> WeldResult<LLVMValueRef> {
+        let (op, scalar_kind) = (self.mergers[kind].op, self.mergers[kind].scalar_kind);
+        let scalar_pointer = LLVMBuildStructGEP(ctx.builder, builder, SCALAR_INDEX, c_str!(""));
+        let mut result = LLVMBuildLoad(ctx.builder, scalar_pointer, c_str!(""));
+        let vector_pointer = LLVMBuildStructGEP(ctx.builder, builder, VECTOR_INDEX, c_str!(""));
+        let vector = LLVMBuildLoad(ctx.builder, vector_pointer, c_str!(""));
+        for i in 0..LLVM_VECTOR_WIDTH {
+            let vector_element =
+                LLVMBuildExtractElement(ctx.builder, vector, self.i32(i as i32), c_str!(""));
+            result =
+                self.gen_arith_binop(ctx, op, result, vector_element, &Scalar(scalar_kind), None)?;
+        }
+        Ok(result)
+    }
+}
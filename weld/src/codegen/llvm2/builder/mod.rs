@@ -16,12 +16,14 @@ use crate::ast::Type::*;
 use crate::ast::*;
 
 use crate::error::*;
+use crate::runtime::WeldRuntimeErrno;
 
 use crate::sir::StatementKind::*;
 use crate::sir::*;
 
 use self::llvm_sys::core::*;
 use self::llvm_sys::prelude::*;
+use self::llvm_sys::LLVMIntPredicate::LLVMIntSGT;
 
 use super::dict;
 
@@ -45,12 +47,19 @@ pub trait BuilderExpressionGen {
     /// Specifically, performs `*builder_value = *builder_value <binop> *merge_value`
     unsafe fn merge_values(
         &mut self,
-        builder: LLVMBuilderRef,
+        ctx: &mut FunctionContext<'_>,
         merge_ty: &Type,
         binop: BinOpKind,
         builder_value_pointer: LLVMValueRef,
         merge_value_pointer: LLVMValueRef,
     ) -> WeldResult<()>;
+    /// Returns the value a dictmerger with values of type `value_ty` stores for a new key, before
+    /// the merged value is combined with it using `binop`.
+    unsafe fn dictmerger_default(
+        &mut self,
+        value_ty: &Type,
+        binop: BinOpKind,
+    ) -> WeldResult<LLVMValueRef>;
     /// Generates code for the `NewBuilder` statement.
     unsafe fn gen_new_builder(
         &mut self,
@@ -171,31 +180,36 @@ impl BuilderExpressionGen for LlvmGenerator {
     /// Specifically, performs `*builder_value = *builder_value <binop> *merge_value`
     unsafe fn merge_values(
         &mut self,
-        builder: LLVMBuilderRef,
+        ctx: &mut FunctionContext<'_>,
         merge_ty: &Type,
         binop: BinOpKind,
         builder_value_pointer: LLVMValueRef,
         merge_value_pointer: LLVMValueRef,
     ) -> WeldResult<()> {
+        use self::numeric::NumericExpressionGen;
         match *merge_ty {
             Scalar(_) => {
-                let merge_value = self.load(builder, merge_value_pointer)?;
-                let builder_value = self.load(builder, builder_value_pointer)?;
+                let merge_value = self.load(ctx.builder, merge_value_pointer)?;
+                let builder_value = self.load(ctx.builder, builder_value_pointer)?;
                 let merged =
-                    numeric::gen_binop(builder, binop, builder_value, merge_value, merge_ty)?;
-                LLVMBuildStore(builder, merged, builder_value_pointer);
+                    self.gen_arith_binop(ctx, binop, builder_value, merge_value, merge_ty, None)?;
+                LLVMBuildStore(ctx.builder, merged, builder_value_pointer);
             }
-            Struct(ref elems) => {
+            Struct(ref elems, _) => {
                 for (i, elem) in elems.iter().enumerate() {
-                    let builder_elem_pointer =
-                        LLVMBuildStructGEP(builder, builder_value_pointer, i as u32, c_str!(""));
-                    let builder_value = self.load(builder, builder_elem_pointer)?;
+                    let builder_elem_pointer = LLVMBuildStructGEP(
+                        ctx.builder,
+                        builder_value_pointer,
+                        i as u32,
+                        c_str!(""),
+                    );
+                    let builder_value = self.load(ctx.builder, builder_elem_pointer)?;
                     let merge_elem_pointer =
-                        LLVMBuildStructGEP(builder, merge_value_pointer, i as u32, c_str!(""));
-                    let merge_value = self.load(builder, merge_elem_pointer)?;
+                        LLVMBuildStructGEP(ctx.builder, merge_value_pointer, i as u32, c_str!(""));
+                    let merge_value = self.load(ctx.builder, merge_elem_pointer)?;
                     let merged =
-                        numeric::gen_binop(builder, binop, builder_value, merge_value, elem)?;
-                    LLVMBuildStore(builder, merged, builder_elem_pointer);
+                        self.gen_arith_binop(ctx, binop, builder_value, merge_value, elem, None)?;
+                    LLVMBuildStore(ctx.builder, merged, builder_elem_pointer);
                 }
             }
             _ => unreachable!(),
@@ -203,6 +217,35 @@ impl BuilderExpressionGen for LlvmGenerator {
         Ok(())
     }
 
+    unsafe fn dictmerger_default(
+        &mut self,
+        value_ty: &Type,
+        binop: BinOpKind,
+    ) -> WeldResult<LLVMValueRef> {
+        let default = match *value_ty {
+            Scalar(ref kind) => self.binop_identity(binop, *kind)?,
+            Struct(ref elems, _) => {
+                let mut default = LLVMGetUndef(self.llvm_type(value_ty)?);
+                for (i, elem) in elems.iter().enumerate() {
+                    if let Scalar(ref kind) = *elem {
+                        let mut indices = [i as u32];
+                        default = LLVMConstInsertValue(
+                            default,
+                            self.binop_identity(binop, *kind)?,
+                            indices.as_mut_ptr(),
+                            indices.len() as u32,
+                        );
+                    } else {
+                        unreachable!()
+                    }
+                }
+                default
+            }
+            _ => unreachable!(),
+        };
+        Ok(default)
+    }
+
     unsafe fn gen_new_builder(
         &mut self,
         ctx: &mut FunctionContext<'_>,
@@ -226,23 +269,32 @@ impl BuilderExpressionGen for LlvmGenerator {
                 LLVMBuildStore(ctx.builder, appender, output_pointer);
                 Ok(())
             }
-            DictMerger(ref key, ref val, _) => {
-                let dict_type = &Dict(key.clone(), val.clone());
-                let default_capacity = self.i64(dict::INITIAL_CAPACITY);
+            DictMerger(_, _, _) => {
+                let dict_type = &nb.kind.result_type();
+                // The argument is either the provided capacity or the default capacity.
+                let capacity = if let Some(arg) = nb.arg {
+                    let capacity = self.load(ctx.builder, ctx.get_value(arg)?)?;
+                    let too_large = LLVMBuildICmp(
+                        ctx.builder,
+                        LLVMIntSGT,
+                        capacity,
+                        self.i64(dict::MAX_CAPACITY),
+                        c_str!(""),
+                    );
+                    self.gen_error_if(ctx, too_large, WeldRuntimeErrno::OutOfMemory, None);
+                    capacity
+                } else {
+                    self.i64(dict::INITIAL_CAPACITY)
+                };
                 let dictmerger = {
                     let methods = self.dictionaries.get_mut(dict_type).unwrap();
-                    methods.gen_new(
-                        ctx.builder,
-                        &mut self.intrinsics,
-                        default_capacity,
-                        ctx.get_run(),
-                    )?
+                    methods.gen_new(ctx.builder, &mut self.intrinsics, capacity, ctx.get_run())?
                 };
                 LLVMBuildStore(ctx.builder, dictmerger, output_pointer);
                 Ok(())
             }
-            GroupMerger(ref key, ref val) => {
-                let dict_type = &Dict(key.clone(), Box::new(Vector(val.clone())));
+            GroupMerger(_, _) => {
+                let dict_type = &nb.kind.result_type();
                 let default_capacity = self.i64(dict::INITIAL_CAPACITY);
                 let groupmerger = {
                     let methods = self.dictionaries.get_mut(dict_type).unwrap();
@@ -271,13 +323,14 @@ impl BuilderExpressionGen for LlvmGenerator {
                 LLVMBuildStore(ctx.builder, merger, output_pointer);
                 Ok(())
             }
-            VecMerger(ref elem, _) => {
+            VecMerger(_, _) => {
                 use super::vector::VectorExt;
                 let argument = nb.arg.unwrap();
                 let argument = self.load(ctx.builder, ctx.get_value(argument)?)?;
                 // XXX This is currently a shallow copy, which isn't quite correct in all cases...
+                let vec_type = &nb.kind.result_type();
                 let builder_value =
-                    self.gen_clone(ctx.builder, &Vector(elem.clone()), argument, ctx.get_run())?;
+                    self.gen_clone(ctx.builder, vec_type, argument, ctx.get_run())?;
                 LLVMBuildStore(ctx.builder, builder_value, output_pointer);
                 Ok(())
             }
@@ -307,29 +360,8 @@ impl BuilderExpressionGen for LlvmGenerator {
             DictMerger(ref key, ref val, ref binop) => {
                 use self::hash::*;
 
-                // Build the default value that we upsert if the key is not present in the
-                // dictionary yet.
-                let default = match *val.as_ref() {
-                    Scalar(ref kind) => self.binop_identity(*binop, *kind)?,
-                    Struct(ref elems) => {
-                        let mut default = LLVMGetUndef(self.llvm_type(val)?);
-                        for (i, elem) in elems.iter().enumerate() {
-                            if let Scalar(ref kind) = *elem {
-                                let mut indices = [i as u32];
-                                default = LLVMConstInsertValue(
-                                    default,
-                                    self.binop_identity(*binop, *kind)?,
-                                    indices.as_mut_ptr(),
-                                    indices.len() as u32,
-                                );
-                            } else {
-                                unreachable!()
-                            }
-                        }
-                        default
-                    }
-                    _ => unreachable!(),
-                };
+                // The default value that we upsert if the key is not present in the dictionary yet.
+                let default = self.dictmerger_default(val, *binop)?;
 
                 // The type of the merge value is {key, value} so use GEP to extract
                 // the key and the key pointer.
@@ -337,7 +369,7 @@ impl BuilderExpressionGen for LlvmGenerator {
                 let (key_pointer, value_pointer) = match *merge_type {
                     // We need this to make sure LLVM doesn't freak out when we use GEP on a
                     // non-struct type or access something out-of-bounds.
-                    Struct(ref elems) if elems.len() == 2 => {
+                    Struct(ref elems, _) if elems.len() == 2 => {
                         let merge_value = ctx.get_value(m.value)?;
                         let key_pointer =
                             LLVMBuildStructGEP(ctx.builder, merge_value, 0, c_str!(""));
@@ -351,7 +383,7 @@ impl BuilderExpressionGen for LlvmGenerator {
                 let hash = self.gen_hash(key, ctx.builder, key_pointer, None)?;
                 let builder_loaded = self.load(ctx.builder, builder_pointer)?;
 
-                let dict_type = &Dict(key.clone(), val.clone());
+                let dict_type = &m.kind.result_type();
                 let slot_value_pointer = {
                     let methods = self.dictionaries.get_mut(dict_type).unwrap();
                     let slot = methods.gen_upsert(
@@ -368,13 +400,7 @@ impl BuilderExpressionGen for LlvmGenerator {
 
                 // Generate the merge code. We either load the values and add them, or, if the
                 // values are structs, we load each element at a time and apply the binop.
-                self.merge_values(
-                    ctx.builder,
-                    val.as_ref(),
-                    *binop,
-                    slot_value_pointer,
-                    value_pointer,
-                )
+                self.merge_values(ctx, val.as_ref(), *binop, slot_value_pointer, value_pointer)
             }
             GroupMerger(ref key, ref value) => {
                 use self::dict::GroupingDict;
@@ -389,7 +415,7 @@ impl BuilderExpressionGen for LlvmGenerator {
 
                 let builder_loaded = self.load(ctx.builder, builder_pointer)?;
 
-                let dict_type = &Dict(key.clone(), Box::new(Vector(value.clone())));
+                let dict_type = &m.kind.result_type();
                 let methods = self.dictionaries.get_mut(dict_type).unwrap();
                 let _ = methods.gen_merge_grouped(
                     ctx.builder,
@@ -404,7 +430,11 @@ impl BuilderExpressionGen for LlvmGenerator {
                 Ok(())
             }
             Merger(_, _) => {
+                use self::merger::CheckedMergerGen;
                 let merge_value = self.load(ctx.builder, ctx.get_value(m.value)?)?;
+                if self.is_checked_merger(m.kind) {
+                    return self.gen_checked_merge(ctx, m.kind, builder_pointer, merge_value);
+                }
                 let methods = self.mergers.get_mut(m.kind).unwrap();
                 let _ = methods.gen_merge(ctx.builder, builder_pointer, merge_value)?;
                 Ok(())
@@ -418,7 +448,7 @@ impl BuilderExpressionGen for LlvmGenerator {
                 let (index_pointer, value_pointer) = match *merge_type {
                     // We need this to make sure LLVM doesn't freak out when we use GEP on a
                     // non-struct type or access something out-of-bounds.
-                    Struct(ref elems) if elems.len() == 2 => {
+                    Struct(ref elems, _) if elems.len() == 2 => {
                         let merge_value = ctx.get_value(m.value)?;
                         let index_pointer =
                             LLVMBuildStructGEP(ctx.builder, merge_value, 0, c_str!(""));
@@ -429,13 +459,14 @@ impl BuilderExpressionGen for LlvmGenerator {
                     _ => unreachable!(),
                 };
                 let index = self.load(ctx.builder, index_pointer)?;
+                let vec_type = &m.kind.result_type();
                 let slot_value_pointer =
-                    self.gen_at(ctx.builder, &Vector(elem.clone()), builder_loaded, index)?;
+                    self.gen_at(ctx.builder, vec_type, builder_loaded, index)?;
 
                 // Generate the merge code. We either load the values and add them, or, if the
                 // values are structs, we load each element at a time and apply the binop.
                 self.merge_values(
-                    ctx.builder,
+                    ctx,
                     elem.as_ref(),
                     *binop,
                     slot_value_pointer,
@@ -454,9 +485,8 @@ impl BuilderExpressionGen for LlvmGenerator {
         let output_pointer = ctx.get_value(m.output)?;
         let builder_pointer = ctx.get_value(m.builder)?;
         match *m.kind {
-            Appender(ref elem_type) => {
-                let vector = &Vector(elem_type.clone());
-                let vector_type = self.llvm_type(vector)?;
+            Appender(_) => {
+                let vector_type = self.llvm_type(&m.kind.result_type())?;
                 let result = {
                     let methods = self.appenders.get_mut(m.kind).unwrap();
                     methods.gen_result(ctx.builder, vector_type, builder_pointer)?
@@ -472,7 +502,10 @@ impl BuilderExpressionGen for LlvmGenerator {
                 Ok(())
             }
             Merger(_, _) => {
-                let result = {
+                use self::merger::CheckedMergerGen;
+                let result = if self.is_checked_merger(m.kind) {
+                    self.gen_checked_result(ctx, m.kind, builder_pointer)?
+                } else {
                     let methods = self.mergers.get_mut(m.kind).unwrap();
                     methods.gen_result(ctx.builder, builder_pointer)?
                 };
@@ -521,14 +554,9 @@ impl BuilderExpressionGen for LlvmGenerator {
                     }
                     Ok(self.appenders[kind].appender_ty)
                 }
-                DictMerger(ref key, ref value, _) => {
-                    let dict_type = &Dict(key.clone(), value.clone());
-                    self.llvm_type(dict_type)
-                }
-                GroupMerger(ref key, ref value) => {
-                    // GroupMerger is backed by dictionary, but the value type is a vector.
-                    let dict_type = &Dict(key.clone(), Box::new(Vector(value.clone())));
-                    self.llvm_type(dict_type)
+                // These builders are backed by the dictionary or vector they produce.
+                DictMerger(_, _, _) | GroupMerger(_, _) | VecMerger(_, _) => {
+                    self.llvm_type(&kind.result_type())
                 }
                 Merger(ref elem_type, ref binop) => {
                     if !self.mergers.contains_key(kind) {
@@ -550,10 +578,6 @@ impl BuilderExpressionGen for LlvmGenerator {
                     }
                     Ok(self.mergers[kind].merger_ty)
                 }
-                VecMerger(ref elem, _) => {
-                    let vec_type = &Vector(elem.clone());
-                    self.llvm_type(vec_type)
-                }
             }
         } else {
             unreachable!()
@@ -162,7 +162,7 @@ impl GenCmp for LlvmGenerator {
 
                 LLVMBuildRet(builder, result);
             }
-            Struct(ref elems) => {
+            Struct(ref elems, _) => {
                 // recursively apply cmp to elements
                 let result = self.i32(0);
                 for (i, elem) in elems.iter().enumerate() {
@@ -62,6 +62,10 @@ const MAX_LOAD_FACTOR: i64 = 7;
 /// After the initial capacity is resized, the load factor should be less than the maximum allowed
 /// load factor.
 pub const INITIAL_CAPACITY: i64 = 16;
+/// The largest capacity a dictionary can be created with.
+///
+/// Larger capacities overflow an `i64` when rounded up to a power-of-two.
+pub const MAX_CAPACITY: i64 = 1 << 62;
 
 /// The default capacity of a grouping vector.
 const DEFAULT_GROUP_CAPACITY: i64 = 8;
@@ -824,6 +828,19 @@ impl Dict {
                 capacity,
                 c_str!(""),
             );
+
+            // Round the capacity up to a power-of-2, since slots are found by masking the hash.
+            // This computes `1 << (64 - ctlz(capacity - 1))`.
+            let ctlz = "llvm.ctlz.i64";
+            let mut ctlz_arg_tys = [self.i64_type(), self.i1_type()];
+            intrinsics.add(ctlz, self.i64_type(), &mut ctlz_arg_tys);
+            let capacity_minus_one = LLVMBuildSub(builder, capacity, self.i64(1), c_str!(""));
+            let zero_is_undef = LLVMConstInt(self.i1_type(), 0, 0);
+            let leading_zeros =
+                intrinsics.call(builder, ctlz, &mut [capacity_minus_one, zero_is_undef])?;
+            let shift = LLVMBuildSub(builder, self.i64(64), leading_zeros, c_str!(""));
+            let capacity = LLVMBuildShl(builder, self.i64(1), shift, c_str!(""));
+
             let dict_inner = self.gen_new_dict_with_capacity(builder, intrinsics, capacity, run);
 
             // Wrap the dictionary in a pointer - the external view of a dictionary is always a
@@ -848,6 +865,17 @@ impl Dict {
         ))
     }
 
+    /// Returns the slot array and the capacity of `dict`.
+    ///
+    /// The entries of the dictionary are the slots in the array that are filled.
+    pub unsafe fn gen_slots(
+        &mut self,
+        builder: LLVMBuilderRef,
+        dict: LLVMValueRef,
+    ) -> (LLVMValueRef, LLVMValueRef) {
+        (self.slot_array(builder, dict), self.capacity(builder, dict))
+    }
+
     /// Returns the pointer to the slot for a key.
     ///
     /// If the key does not exist, a slot is initialized for the key, and a default value is
@@ -107,7 +107,7 @@ impl GenEq for LlvmGenerator {
                 let right = self.load(builder, right)?;
                 gen_binop(builder, Equal, left, right, ty)?
             }
-            Struct(ref elems) => {
+            Struct(ref elems, _) => {
                 let mut result = self.i1(true);
                 for (i, elem) in elems.iter().enumerate() {
                     let func = self.gen_eq_fn(elem)?;
@@ -454,7 +454,7 @@ impl Hash for LlvmGenerator {
                 hash
             }
             */
-            Struct(ref elems) => {
+            Struct(ref elems, _) => {
                 // We don't want to hash pointers, so just hash each struct element
                 // individually.
                 let mut hash = seed;
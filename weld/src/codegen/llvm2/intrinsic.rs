@@ -313,6 +313,24 @@ impl Intrinsics {
         )
     }
 
+    /// Convinience wrapper for calling the `weld_runst_set_error` intrinsic.
+    pub unsafe fn call_weld_run_set_error(
+        &mut self,
+        builder: LLVMBuilderRef,
+        run: LLVMValueRef,
+        errno: LLVMValueRef,
+        site: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let mut args = [run, errno, site];
+        LLVMBuildCall(
+            builder,
+            self.get("weld_runst_set_error").unwrap(),
+            args.as_mut_ptr(),
+            args.len() as u32,
+            c_str!(""),
+        )
+    }
+
     /// Convinience wrapper for calling the `weld_runst_assert` intrinsic.
     pub unsafe fn call_weld_run_assert(
         &mut self,
@@ -331,6 +349,79 @@ impl Intrinsics {
         )
     }
 
+    /// Convinience wrapper for calling the `weld_runst_parallel_for` intrinsic.
+    ///
+    /// `function` and `arguments` must both be `i8*` values.
+    pub unsafe fn call_weld_run_parallel_for(
+        &mut self,
+        builder: LLVMBuilderRef,
+        run: LLVMValueRef,
+        function: LLVMValueRef,
+        arguments: LLVMValueRef,
+        chunks: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let mut args = [run, function, arguments, chunks];
+        LLVMBuildCall(
+            builder,
+            self.get("weld_runst_parallel_for").unwrap(),
+            args.as_mut_ptr(),
+            args.len() as u32,
+            c_str!(""),
+        )
+    }
+
+    /// Convinience wrapper for calling the `weld_runst_gpu_for` intrinsic.
+    ///
+    /// `args` are the arguments after the run handle. Returns 1 if the kernel ran on the GPU.
+    pub unsafe fn call_weld_run_gpu_for(
+        &mut self,
+        builder: LLVMBuilderRef,
+        run: LLVMValueRef,
+        args: &[LLVMValueRef],
+    ) -> LLVMValueRef {
+        let mut args: Vec<_> = Some(run).into_iter().chain(args.iter().cloned()).collect();
+        LLVMBuildCall(
+            builder,
+            self.get("weld_runst_gpu_for").unwrap(),
+            args.as_mut_ptr(),
+            args.len() as u32,
+            c_str!("gpu.ran"),
+        )
+    }
+
+    /// Convinience wrapper for calling the `weld_runst_next_chunk` intrinsic.
+    ///
+    /// `data` must be an `i8**` value, which receives the chunk. Returns the number of elements
+    /// in the chunk.
+    pub unsafe fn call_weld_run_next_chunk(
+        &mut self,
+        builder: LLVMBuilderRef,
+        run: LLVMValueRef,
+        source: LLVMValueRef,
+        elem_size: LLVMValueRef,
+        data: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let mut args = [run, source, elem_size, data];
+        LLVMBuildCall(
+            builder,
+            self.get("weld_runst_next_chunk").unwrap(),
+            args.as_mut_ptr(),
+            args.len() as u32,
+            c_str!("chunk.size"),
+        )
+    }
+
+    /// Convinience wrapper for calling the `weld_runst_profile_time` intrinsic.
+    pub unsafe fn call_weld_run_profile_time(&mut self, builder: LLVMBuilderRef) -> LLVMValueRef {
+        LLVMBuildCall(
+            builder,
+            self.get("weld_runst_profile_time").unwrap(),
+            [].as_mut_ptr(),
+            0,
+            c_str!("time"),
+        )
+    }
+
     /// Convinience wrapper for calling the `weld_run_print` intrinsic.
     pub unsafe fn call_weld_run_print(
         &mut self,
@@ -516,6 +607,22 @@ impl Intrinsics {
             Intrinsic::FunctionPointer(function, ffi::weld_runst_set_errno as *mut c_void),
         );
 
+        let mut params = vec![self.run_handle_type(), self.i64_type(), self.i64_type()];
+        let name = CString::new("weld_runst_set_error").unwrap();
+        let fn_type = LLVMFunctionType(
+            self.void_type(),
+            params.as_mut_ptr(),
+            params.len() as u32,
+            0,
+        );
+        let function = LLVMAddFunction(self.module, name.as_ptr(), fn_type);
+        LLVMExtAddAttrsOnFunction(self.context, function, &[NoReturn]);
+        LLVMExtAddAttrsOnParameter(self.context, function, &[NoCapture, NoAlias, NonNull], 0);
+        self.intrinsics.insert(
+            name.into_string().unwrap(),
+            Intrinsic::FunctionPointer(function, ffi::weld_runst_set_error as *mut c_void),
+        );
+
         let mut params = vec![self.run_handle_type(), self.bool_type()];
         let name = CString::new("weld_runst_assert").unwrap();
         let fn_type = LLVMFunctionType(
@@ -557,6 +664,68 @@ impl Intrinsics {
             Intrinsic::FunctionPointer(function, ffi::weld_runst_print as *mut c_void),
         );
 
+        let mut params = vec![self.run_handle_type(), int8p, int8p, self.i64_type()];
+        let name = CString::new("weld_runst_parallel_for").unwrap();
+        let fn_type = LLVMFunctionType(
+            self.void_type(),
+            params.as_mut_ptr(),
+            params.len() as u32,
+            0,
+        );
+        let function = LLVMAddFunction(self.module, name.as_ptr(), fn_type);
+        LLVMExtAddAttrsOnParameter(self.context, function, &[NoCapture, NoAlias, NonNull], 0);
+        self.intrinsics.insert(
+            name.into_string().unwrap(),
+            Intrinsic::FunctionPointer(function, ffi::weld_runst_parallel_for as *mut c_void),
+        );
+
+        let mut params = vec![
+            self.run_handle_type(),
+            int8p,
+            int8p,
+            self.i64_type(),
+            self.i64_type(),
+            int8p,
+            self.i64_type(),
+            self.i64_type(),
+            LLVMPointerType(int8p, 0),
+            self.i64_type(),
+        ];
+        let name = CString::new("weld_runst_gpu_for").unwrap();
+        let fn_type =
+            LLVMFunctionType(self.i64_type(), params.as_mut_ptr(), params.len() as u32, 0);
+        let function = LLVMAddFunction(self.module, name.as_ptr(), fn_type);
+        LLVMExtAddAttrsOnFunction(self.context, function, &[NoUnwind]);
+        self.intrinsics.insert(
+            name.into_string().unwrap(),
+            Intrinsic::FunctionPointer(function, ffi::weld_runst_gpu_for as *mut c_void),
+        );
+
+        let mut params = vec![
+            self.run_handle_type(),
+            self.i64_type(),
+            self.i64_type(),
+            LLVMPointerType(int8p, 0),
+        ];
+        let name = CString::new("weld_runst_next_chunk").unwrap();
+        let fn_type =
+            LLVMFunctionType(self.i64_type(), params.as_mut_ptr(), params.len() as u32, 0);
+        let function = LLVMAddFunction(self.module, name.as_ptr(), fn_type);
+        LLVMExtAddAttrsOnParameter(self.context, function, &[NoCapture, NoAlias, NonNull], 0);
+        LLVMExtAddAttrsOnParameter(self.context, function, &[NoCapture, NoAlias, NonNull], 3);
+        self.intrinsics.insert(
+            name.into_string().unwrap(),
+            Intrinsic::FunctionPointer(function, ffi::weld_runst_next_chunk as *mut c_void),
+        );
+
+        let name = CString::new("weld_runst_profile_time").unwrap();
+        let fn_type = LLVMFunctionType(self.i64_type(), [].as_mut_ptr(), 0, 0);
+        let function = LLVMAddFunction(self.module, name.as_ptr(), fn_type);
+        self.intrinsics.insert(
+            name.into_string().unwrap(),
+            Intrinsic::FunctionPointer(function, ffi::weld_runst_profile_time as *mut c_void),
+        );
+
         let mut params = vec![
             int8p,
             int8p,
@@ -9,12 +9,15 @@ use time;
 use std::ffi::{CStr, CString};
 use std::mem;
 use std::ptr;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Once;
+use std::time::Duration;
 
 use libc::c_char;
 
 use self::time::PreciseTime;
 
+use crate::ast::{NodeId, Span};
 use crate::conf::ParsedConf;
 use crate::error::*;
 use crate::util::stats::CompilationStats;
@@ -25,16 +28,19 @@ use self::llvm_sys::prelude::*;
 use self::llvm_sys::target::*;
 use self::llvm_sys::target_machine::*;
 
-use crate::codegen::Runnable;
+use crate::codegen::{LoopProfile, Runnable};
 
 use crate::codegen::llvm2::intrinsic;
 use crate::codegen::llvm2::llvm_exts::*;
+use crate::codegen::llvm2::profile::*;
 
 static ONCE: Once = Once::new();
 static mut INITIALIZE_FAILED: bool = false;
 
 /// The callable function type.
-type I64Func = extern "C" fn(i64) -> i64;
+///
+/// The function unwinds if the program raises a runtime error.
+type I64Func = extern "C-unwind" fn(i64) -> i64;
 
 /// A compiled, runnable LLVM module.
 pub struct CompiledModule {
@@ -42,6 +48,12 @@ pub struct CompiledModule {
     module: LLVMModuleRef,
     engine: LLVMExecutionEngineRef,
     run_function: I64Func,
+    /// The expression ID and location of each profiled loop.
+    loop_spans: Vec<(NodeId, Option<Span>)>,
+    /// The location of each site that can raise a runtime error.
+    error_spans: Vec<Option<Span>>,
+    /// The profiling counters of the loops, or null if loops are not profiled.
+    loop_counters: *const AtomicI64,
 }
 
 // The codegen interface requires that modules implement this trait. This allows supporting
@@ -50,6 +62,30 @@ impl Runnable for CompiledModule {
     fn run(&self, arg: i64) -> i64 {
         (self.run_function)(arg)
     }
+
+    fn loop_profile(&self) -> Vec<LoopProfile> {
+        self.loop_spans
+            .iter()
+            .enumerate()
+            .map(|(i, &(id, span))| LoopProfile {
+                id,
+                span,
+                iterations: self.loop_counter(i, ITERATIONS_INDEX),
+                time: Duration::from_nanos(self.loop_counter(i, TIME_INDEX) as u64),
+                bytes_merged: self.loop_counter(i, BYTES_MERGED_INDEX),
+            })
+            .collect()
+    }
+
+    fn error_span(&self, site: i64) -> Option<Span> {
+        self.error_spans.get(site as usize).cloned().flatten()
+    }
+
+    fn reset_loop_profile(&self) {
+        for i in 0..self.loop_spans.len() * COUNTERS_PER_LOOP {
+            unsafe { (*self.loop_counters.add(i)).store(0, Ordering::Relaxed) }
+        }
+    }
 }
 
 // LLVM modules are thread-safe.
@@ -57,6 +93,11 @@ unsafe impl Send for CompiledModule {}
 unsafe impl Sync for CompiledModule {}
 
 impl CompiledModule {
+    /// Returns a counter of the loop with index `i`.
+    fn loop_counter(&self, i: usize, index: usize) -> i64 {
+        unsafe { (*self.loop_counters.add(i * COUNTERS_PER_LOOP + index)).load(Ordering::Relaxed) }
+    }
+
     /// Dumps assembly for this module.
     pub fn asm(&self) -> WeldResult<String> {
         unsafe {
@@ -124,6 +165,8 @@ pub unsafe fn compile(
     context: LLVMContextRef,
     module: LLVMModuleRef,
     mappings: &[intrinsic::Mapping],
+    loop_spans: Vec<(NodeId, Option<Span>)>,
+    error_spans: Vec<Option<Span>>,
     conf: &ParsedConf,
     stats: &mut CompilationStats,
 ) -> WeldResult<CompiledModule> {
@@ -158,11 +201,20 @@ pub unsafe fn compile(
         .llvm_times
         .push(("Find Run Func Address".to_string(), start.to(end)));
 
+    let loop_counters = if loop_spans.is_empty() {
+        ptr::null()
+    } else {
+        find_global(engine, LOOP_COUNTERS_NAME)? as *const AtomicI64
+    };
+
     let result = CompiledModule {
         context,
         module,
         engine,
         run_function: run_func,
+        loop_spans,
+        error_spans,
+        loop_counters,
     };
     Ok(result)
 }
@@ -240,7 +292,7 @@ pub unsafe fn set_triple_and_layout(module: LLVMModuleRef) -> WeldResult<()> {
 }
 
 /// Verify a module using LLVM's verifier.
-unsafe fn verify_module(module: LLVMModuleRef) -> WeldResult<()> {
+pub unsafe fn verify_module(module: LLVMModuleRef) -> WeldResult<()> {
     use self::llvm_sys::analysis::LLVMVerifierFailureAction::*;
     use self::llvm_sys::analysis::LLVMVerifyModule;
     let mut error_str = ptr::null_mut();
@@ -410,6 +462,16 @@ unsafe fn create_exec_engine(
     }
 }
 
+/// Get the address of a named global variable in an execution engine.
+unsafe fn find_global(engine: LLVMExecutionEngineRef, name: &str) -> WeldResult<u64> {
+    let c_name = CString::new(name).unwrap();
+    let addr = LLVMGetGlobalValueAddress(engine, c_name.as_ptr());
+    if addr == 0 {
+        return compile_err!("No global named {} in module", name);
+    }
+    Ok(addr)
+}
+
 /// Get a pointer to a named function in an execution engine.
 unsafe fn find_function(engine: LLVMExecutionEngineRef, name: &str) -> WeldResult<I64Func> {
     let c_name = CString::new(name).unwrap();
@@ -1,4 +1,4 @@
-//! An LLVM backend currently optimized for single-threaded execution.
+//! An LLVM backend that runs loops on multiple threads if `weld.threads` is greater than one.
 //!
 //! The `LlvmGenerator` struct is responsible for converting an SIR program into an LLVM module.
 //! The LLVM module is then JIT'd and returned as a runnable executable.
@@ -44,6 +44,10 @@
 //! * The `numeric` module generates code for numeric expressions such as binary and unary
 //! operators, comparisons, etc.
 //!
+//! * The `nvvm` module compiles eligible loops to GPU kernels when the `nvvm` backend is selected.
+//!
+//! * The `profile` module generates per-loop profiling counters.
+//!
 //! * The `serde` module generates code for serializing and deserializing types.
 //!
 //! * The `target` module provides parsed target specific feature information.
@@ -59,6 +63,7 @@ use std::mem;
 
 use fnv::FnvHashMap;
 use libc::{c_char, c_uint, c_ulonglong};
+use time::PreciseTime;
 
 use crate::conf::ParsedConf;
 use crate::error::*;
@@ -71,6 +76,8 @@ use self::llvm_sys::LLVMLinkage;
 
 use super::*;
 
+use self::profile::LoopProfileGen;
+
 lazy_static! {
     /// Name of the run handle struct in generated code.
     static ref RUN_HANDLE_NAME: CString = CString::new("RunHandle").unwrap();
@@ -99,6 +106,8 @@ mod intrinsic;
 mod jit;
 mod llvm_exts;
 mod numeric;
+mod nvvm;
+mod profile;
 mod serde;
 mod target;
 mod vector;
@@ -119,6 +128,19 @@ pub fn load_library(libname: &str) -> WeldResult<()> {
     }
 }
 
+/// Registers a symbol in the current process using LLVMAddSymbol.
+///
+/// Generated code that references `name` (e.g., via a `cudf`) resolves it to `address`. Registering
+/// the same name again replaces the previous address.
+pub unsafe fn register_symbol(name: &str, address: *mut libc::c_void) -> WeldResult<()> {
+    if let Ok(c_string) = CString::new(name) {
+        llvm_sys::support::LLVMAddSymbol(c_string.as_ptr(), address);
+        Ok(())
+    } else {
+        compile_err!("Invalid symbol name {}", name)
+    }
+}
+
 /// Returns the size of a type in bytes.
 pub fn size_of(ty: &Type) -> usize {
     unsafe {
@@ -141,7 +163,12 @@ pub fn compile(
 
     info!("Compiling using single thread runtime");
 
+    let start = PreciseTime::now();
     let codegen = unsafe { LlvmGenerator::generate(conf.clone(), &program)? };
+    let end = PreciseTime::now();
+    stats
+        .llvm_times
+        .push(("Code Generation".to_string(), start.to(end)));
 
     nonfatal!(write_code(
         codegen.to_string(),
@@ -154,7 +181,19 @@ pub fn compile(
     }
 
     let mappings = &codegen.intrinsics.mappings();
-    let module = unsafe { jit::compile(codegen.context, codegen.module, mappings, conf, stats)? };
+    let loop_spans = codegen.loop_spans.clone();
+    let error_spans = codegen.error_spans.clone();
+    let module = unsafe {
+        jit::compile(
+            codegen.context,
+            codegen.module,
+            mappings,
+            loop_spans,
+            error_spans,
+            conf,
+            stats,
+        )?
+    };
 
     nonfatal!(write_code(
         module.asm()?,
@@ -265,7 +304,7 @@ impl HasPointer for Type {
             Vector(_) => true,
             Dict(_, _) => true,
             Builder(_, _) => true,
-            Struct(ref tys) => tys.iter().any(|ref t| t.has_pointer()),
+            Struct(ref tys, _) => tys.iter().any(|t| t.has_pointer()),
             Function(_, _) | Unknown | Alias(_, _) => unreachable!(),
         }
     }
@@ -323,6 +362,16 @@ pub struct LlvmGenerator {
     struct_names: FnvHashMap<Type, CString>,
     /// Counter for unique struct names.
     struct_index: u32,
+    /// Maps the expression ID of each loop to the index of its profiling counters.
+    ///
+    /// This is empty unless loop profiling is enabled.
+    loop_counters: FnvHashMap<NodeId, usize>,
+    /// The expression ID and location of each profiled loop, by counter index.
+    loop_spans: Vec<(NodeId, Option<Span>)>,
+    /// Maps builders in each function to the counter indices of the loops that own them.
+    builder_loops: FnvHashMap<(FunctionId, Symbol), Vec<usize>>,
+    /// The location of each operation that raises a runtime error with a site, by site index.
+    error_spans: Vec<Option<Span>>,
 }
 
 /// Defines helper methods for LLVM code generation.
@@ -447,13 +496,18 @@ pub trait CodeGenExt {
             _ if kind.is_integer() => {
                 let ty = LLVMIntTypeInContext(self.context(), kind.bits());
                 let signed = kind.is_signed() as i32;
+                // The smallest and largest values of this type, which LLVM truncates to its width.
+                let (min, max) = if kind.is_signed() {
+                    let max = (1u64 << (kind.bits() - 1)) - 1;
+                    (!max, max)
+                } else {
+                    (u64::MIN, u64::MAX)
+                };
                 match op {
                     Add => Ok(LLVMConstInt(ty, 0, signed)),
                     Multiply => Ok(LLVMConstInt(ty, 1, signed)),
-                    Max if kind.is_signed() => Ok(LLVMConstInt(ty, ::std::i64::MIN as u64, signed)),
-                    Max => Ok(LLVMConstInt(ty, ::std::u64::MIN, signed)),
-                    Min if kind.is_signed() => Ok(LLVMConstInt(ty, ::std::i64::MAX as u64, signed)),
-                    Min => Ok(LLVMConstInt(ty, ::std::u64::MAX, signed)),
+                    Max => Ok(LLVMConstInt(ty, min, signed)),
+                    Min => Ok(LLVMConstInt(ty, max, signed)),
                     _ => unreachable!(),
                 }
             }
@@ -733,6 +787,10 @@ impl LlvmGenerator {
             deserialize_fns: FnvHashMap::default(),
             struct_names: FnvHashMap::default(),
             struct_index: 0,
+            loop_counters: FnvHashMap::default(),
+            loop_spans: vec![],
+            builder_loops: FnvHashMap::default(),
+            error_spans: vec![],
             intrinsics,
         })
     }
@@ -740,6 +798,7 @@ impl LlvmGenerator {
     /// Generate code for an SIR program.
     unsafe fn generate(conf: ParsedConf, program: &SirProgram) -> WeldResult<LlvmGenerator> {
         let mut gen = LlvmGenerator::new(conf)?;
+        gen.declare_loop_counters(program);
 
         // Declare each function first to create a reference to it. Loop body functions are only
         // called by their ParallelForData terminators, so those are generated on-the-fly during
@@ -785,6 +844,34 @@ impl LlvmGenerator {
         Ok(())
     }
 
+    /// Generates code that raises `errno` if `failed` is true.
+    ///
+    /// `span` is the location of the failing expression, which is reported with the error. The
+    /// builder is positioned in the block that runs if the check passes.
+    unsafe fn gen_error_if(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        failed: LLVMValueRef,
+        errno: WeldRuntimeErrno,
+        span: Option<Span>,
+    ) {
+        let fail_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("check.fail"));
+        let pass_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("check.passed"));
+        LLVMBuildCondBr(ctx.builder, failed, fail_block, pass_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, fail_block);
+        let errno = self.i64(errno as i64);
+        let site = self.i64(self.error_spans.len() as i64);
+        self.error_spans.push(span);
+        self.intrinsics
+            .call_weld_run_set_error(ctx.builder, ctx.get_run(), errno, site);
+        LLVMBuildUnreachable(ctx.builder);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, pass_block);
+    }
+
     /// Generates the entry point to the Weld program.
     ///
     /// The entry function takes an `i64` and returns an `i64`. Both represent pointers that
@@ -875,7 +962,10 @@ impl LlvmGenerator {
         // Still a pointer, but now as an integer.
         let arg_pointer = self.load(builder, arg_pointer)?;
         // The first SIR function is the entry point.
-        let arg_ty = &Struct(program.top_params.iter().map(|p| p.ty.clone()).collect());
+        let arg_ty = &Struct(
+            program.top_params.iter().map(|p| p.ty.clone()).collect(),
+            FieldNames::none(),
+        );
         let llvm_arg_ty = self.llvm_type(arg_ty)?;
         let arg_struct_pointer = LLVMBuildIntToPtr(
             builder,
@@ -1235,6 +1325,7 @@ impl LlvmGenerator {
             Lookup {
                 ref child,
                 ref index,
+                span,
             } => {
                 let output_pointer = context.get_value(output)?;
                 let child_value = self.load(context.builder, context.get_value(child)?)?;
@@ -1242,6 +1333,24 @@ impl LlvmGenerator {
                 if let Vector(_) = *child_type {
                     use self::vector::VectorExt;
                     let index_value = self.load(context.builder, context.get_value(index)?)?;
+                    if self.conf.enable_bounds_checks {
+                        use self::llvm_sys::LLVMIntPredicate::LLVMIntUGE;
+                        // An unsigned comparison also catches negative indices.
+                        let size = self.gen_size(context.builder, child_type, child_value)?;
+                        let failed = LLVMBuildICmp(
+                            context.builder,
+                            LLVMIntUGE,
+                            index_value,
+                            size,
+                            c_str!(""),
+                        );
+                        self.gen_error_if(
+                            context,
+                            failed,
+                            WeldRuntimeErrno::ArrayOutOfBounds,
+                            span,
+                        );
+                    }
                     let pointer =
                         self.gen_at(context.builder, child_type, child_value, index_value)?;
                     let result = self.load(context.builder, pointer)?;
@@ -1337,8 +1446,12 @@ impl LlvmGenerator {
                 LLVMBuildStore(context.builder, vector, output_pointer);
                 Ok(())
             }
-            Merge { .. } => {
+            Merge {
+                ref builder,
+                ref value,
+            } => {
                 use self::builder::BuilderExpressionGen;
+                self.gen_merge_profile(context, builder, value)?;
                 self.gen_merge(context, statement)
             }
             Negate(_) => {
@@ -1590,6 +1703,26 @@ impl LlvmGenerator {
                     context.get_block(*on_false)?,
                 );
             }
+            Switch {
+                ref value,
+                ref cases,
+                ref default,
+            } => {
+                let value = self.load(context.builder, context.get_value(value)?)?;
+                let switch = LLVMBuildSwitch(
+                    context.builder,
+                    value,
+                    context.get_block(*default)?,
+                    cases.len() as u32,
+                );
+                for (constant, block) in cases.iter() {
+                    LLVMAddCase(
+                        switch,
+                        self.scalar_literal(constant),
+                        context.get_block(*block)?,
+                    );
+                }
+            }
             JumpBlock(ref id) => {
                 LLVMBuildBr(context.builder, context.get_block(*id)?);
             }
@@ -1662,7 +1795,7 @@ impl LlvmGenerator {
                 let base = self.llvm_type(&Scalar(kind))?;
                 LLVMVectorType(base, LLVM_VECTOR_WIDTH)
             }
-            Struct(ref elems) => {
+            Struct(ref elems, _) => {
                 if !self.struct_names.contains_key(ty) {
                     let name = CString::new(format!("s{}", self.struct_index)).unwrap();
                     self.struct_index += 1;
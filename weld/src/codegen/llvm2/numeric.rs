@@ -11,6 +11,7 @@ use llvm_sys;
 
 use crate::ast::*;
 use crate::error::*;
+use crate::runtime::WeldRuntimeErrno;
 use crate::sir::*;
 
 use std::ffi::CString;
@@ -68,10 +69,34 @@ pub trait NumericExpressionGen {
         ctx: &mut FunctionContext<'_>,
         statement: &Statement,
     ) -> WeldResult<()>;
+    /// Generates a binary operator over loaded scalar or SIMD values.
+    ///
+    /// With `weld.compile.checkedArithmetic` enabled, integer arithmetic raises an
+    /// `IntegerOverflow` error if the result overflows, and division and modulo raise a
+    /// `DivideByZero` error if the divisor is zero. `span` is the location of the operator, which
+    /// is reported with the error.
+    unsafe fn gen_arith_binop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        op: BinOpKind,
+        left: LLVMValueRef,
+        right: LLVMValueRef,
+        ty: &Type,
+        span: Option<Span>,
+    ) -> WeldResult<LLVMValueRef>;
 }
 
 /// Helper trait for generating numeric code.
 trait NumericExpressionGenInternal {
+    /// Returns an `i1` that is true if any lane of the SIMD flag `flag` is true.
+    ///
+    /// Returns `flag` itself if `simd` is false.
+    unsafe fn any_lane(
+        &mut self,
+        builder: LLVMBuilderRef,
+        flag: LLVMValueRef,
+        simd: bool,
+    ) -> LLVMValueRef;
     /// Generates the math `Pow` operator.
     unsafe fn gen_pow(
         &mut self,
@@ -83,6 +108,22 @@ trait NumericExpressionGenInternal {
 }
 
 impl NumericExpressionGenInternal for LlvmGenerator {
+    unsafe fn any_lane(
+        &mut self,
+        builder: LLVMBuilderRef,
+        flag: LLVMValueRef,
+        simd: bool,
+    ) -> LLVMValueRef {
+        if simd {
+            let mask_ty = LLVMIntTypeInContext(self.context, LLVM_VECTOR_WIDTH);
+            let mask = LLVMBuildBitCast(builder, flag, mask_ty, c_str!(""));
+            let zero = LLVMConstInt(mask_ty, 0, 0);
+            LLVMBuildICmp(builder, LLVMIntNE, mask, zero, c_str!(""))
+        } else {
+            flag
+        }
+    }
+
     unsafe fn gen_pow(
         &mut self,
         ctx: &mut FunctionContext<'_>,
@@ -143,6 +184,7 @@ impl UnaryOpSupport for UnaryOpKind {
             Sqrt => Some("sqrt"),
             Sin => Some("sin"),
             Cos => Some("cos"),
+            Abs => Some("fabs"),
             _ => None,
         }
     }
@@ -239,7 +281,8 @@ impl NumericExpressionGen for LlvmGenerator {
         use crate::sir::StatementKind::Not;
         if let Not(ref child) = statement.kind {
             let value = self.load(ctx.builder, ctx.get_value(child)?)?;
-            let result = LLVMBuildICmp(ctx.builder, LLVMIntEQ, value, self.bool(false), c_str!(""));
+            let zero = LLVMConstNull(LLVMTypeOf(value));
+            let result = LLVMBuildICmp(ctx.builder, LLVMIntEQ, value, zero, c_str!(""));
             let result = self.i1_to_bool(ctx.builder, result);
             let output = ctx.get_value(statement.output.as_ref().unwrap())?;
             let _ = LLVMBuildStore(ctx.builder, result, output);
@@ -254,33 +297,23 @@ impl NumericExpressionGen for LlvmGenerator {
         ctx: &mut FunctionContext<'_>,
         statement: &Statement,
     ) -> WeldResult<()> {
-        use crate::ast::BinOpKind::Subtract;
-        use crate::ast::ScalarKind::{F32, F64};
         use crate::ast::Type::{Scalar, Simd};
         use crate::sir::StatementKind::Negate;
         if let Negate(ref child) = statement.kind {
             let ty = ctx.sir_function.symbol_type(child)?;
-            let (kind, simd) = match *ty {
-                Scalar(kind) => (kind, false),
-                Simd(kind) => (kind, true),
+            let kind = match *ty {
+                Scalar(kind) | Simd(kind) => kind,
                 _ => unreachable!(),
             };
 
-            let mut zero = match kind {
-                F32 => LLVMConstReal(self.f32_type(), 0.0),
-                F64 => LLVMConstReal(self.f64_type(), 0.0),
-                _ => LLVMConstInt(LLVMIntTypeInContext(self.context, kind.bits()), 0, 1),
-            };
-
-            if simd {
-                zero = LLVMConstVector(
-                    [zero; LLVM_VECTOR_WIDTH as usize].as_mut_ptr(),
-                    LLVM_VECTOR_WIDTH,
-                );
-            }
-
+            // Use a true negation rather than subtracting from zero, so that negating a
+            // floating-point zero produces a zero with the opposite sign.
             let child = self.load(ctx.builder, ctx.get_value(child)?)?;
-            let result = gen_binop(ctx.builder, Subtract, zero, child, ty)?;
+            let result = if kind.is_float() {
+                LLVMBuildFNeg(ctx.builder, child, c_str!(""))
+            } else {
+                LLVMBuildNeg(ctx.builder, child, c_str!(""))
+            };
             let output = ctx.get_value(statement.output.as_ref().unwrap())?;
             let _ = LLVMBuildStore(ctx.builder, result, output);
             Ok(())
@@ -300,6 +333,7 @@ impl NumericExpressionGen for LlvmGenerator {
             op,
             ref left,
             ref right,
+            span,
         } = statement.kind
         {
             let ty = ctx.sir_function.symbol_type(left)?;
@@ -309,7 +343,7 @@ impl NumericExpressionGen for LlvmGenerator {
                     let llvm_right = self.load(ctx.builder, ctx.get_value(right)?)?;
                     let result = match op {
                         BinOpKind::Pow => self.gen_pow(ctx, llvm_left, llvm_right, ty)?,
-                        _ => gen_binop(ctx.builder, op, llvm_left, llvm_right, ty)?,
+                        _ => self.gen_arith_binop(ctx, op, llvm_left, llvm_right, ty, span)?,
                     };
 
                     // Extend the returned `i1` to the `i8` boolean type.
@@ -319,7 +353,7 @@ impl NumericExpressionGen for LlvmGenerator {
                         result
                     }
                 }
-                Vector(_) | Struct(_) if op.is_comparison() => {
+                Vector(_) | Struct(..) if op.is_comparison() => {
                     // Note that we assume structs being compared have the same type.
                     let result = match op {
                         BinOpKind::Equal | BinOpKind::NotEqual => {
@@ -465,6 +499,106 @@ impl NumericExpressionGen for LlvmGenerator {
             unreachable!()
         }
     }
+
+    unsafe fn gen_arith_binop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        op: BinOpKind,
+        left: LLVMValueRef,
+        right: LLVMValueRef,
+        ty: &Type,
+        span: Option<Span>,
+    ) -> WeldResult<LLVMValueRef> {
+        use crate::ast::Type::{Scalar, Simd};
+        let (kind, simd) = match *ty {
+            Scalar(kind) => (kind, false),
+            Simd(kind) => (kind, true),
+            _ => unreachable!(),
+        };
+        if !self.conf.checked_arithmetic || !kind.is_integer() {
+            return gen_binop(ctx.builder, op, left, right, ty);
+        }
+        let value_ty = LLVMTypeOf(left);
+        let splat = |value: LLVMValueRef| {
+            if simd {
+                LLVMConstVector(
+                    [value; LLVM_VECTOR_WIDTH as usize].as_mut_ptr(),
+                    LLVM_VECTOR_WIDTH,
+                )
+            } else {
+                value
+            }
+        };
+
+        // The overflow flag is an `i1`, or a vector of `i1` for SIMD values.
+        let (result, overflow) = match op {
+            BinOpKind::Add | BinOpKind::Subtract | BinOpKind::Multiply => {
+                let name = format!(
+                    "llvm.{}{}.with.overflow.{}i{}",
+                    if kind.is_signed_integer() { "s" } else { "u" },
+                    match op {
+                        BinOpKind::Add => "add",
+                        BinOpKind::Subtract => "sub",
+                        _ => "mul",
+                    },
+                    if simd {
+                        format!("v{}", LLVM_VECTOR_WIDTH)
+                    } else {
+                        String::new()
+                    },
+                    kind.bits()
+                );
+                let flag_ty = if simd {
+                    LLVMVectorType(self.i1_type(), LLVM_VECTOR_WIDTH)
+                } else {
+                    self.i1_type()
+                };
+                let mut fields = [value_ty, flag_ty];
+                let ret_ty = LLVMStructTypeInContext(
+                    self.context,
+                    fields.as_mut_ptr(),
+                    fields.len() as u32,
+                    0,
+                );
+                let mut arg_tys = [value_ty, value_ty];
+                self.intrinsics.add(&name, ret_ty, &mut arg_tys);
+                let pair = self
+                    .intrinsics
+                    .call(ctx.builder, &name, &mut [left, right])?;
+                let result = LLVMBuildExtractValue(ctx.builder, pair, 0, c_str!(""));
+                let overflow = LLVMBuildExtractValue(ctx.builder, pair, 1, c_str!(""));
+                (Some(result), overflow)
+            }
+            BinOpKind::Divide | BinOpKind::Modulo => {
+                // The division is generated after the checks, since dividing by zero or
+                // overflowing traps on some targets.
+                let zero = LLVMConstNull(value_ty);
+                let is_zero = LLVMBuildICmp(ctx.builder, LLVMIntEQ, right, zero, c_str!(""));
+                let is_zero = self.any_lane(ctx.builder, is_zero, simd);
+                self.gen_error_if(ctx, is_zero, WeldRuntimeErrno::DivideByZero, span);
+                if !kind.is_signed_integer() {
+                    return gen_binop(ctx.builder, op, left, right, ty);
+                }
+                // Only the minimum value divided by -1 overflows.
+                let elem_ty = LLVMIntTypeInContext(self.context, kind.bits());
+                let min = splat(LLVMConstInt(elem_ty, 1 << (kind.bits() - 1), 0));
+                let is_min = LLVMBuildICmp(ctx.builder, LLVMIntEQ, left, min, c_str!(""));
+                let minus_one = LLVMConstAllOnes(value_ty);
+                let is_minus_one =
+                    LLVMBuildICmp(ctx.builder, LLVMIntEQ, right, minus_one, c_str!(""));
+                let overflow = LLVMBuildAnd(ctx.builder, is_min, is_minus_one, c_str!(""));
+                (None, overflow)
+            }
+            _ => return gen_binop(ctx.builder, op, left, right, ty),
+        };
+
+        let overflow = self.any_lane(ctx.builder, overflow, simd);
+        self.gen_error_if(ctx, overflow, WeldRuntimeErrno::IntegerOverflow, span);
+        match result {
+            Some(result) => Ok(result),
+            None => gen_binop(ctx.builder, op, left, right, ty),
+        }
+    }
 }
 
 /// Workhorse for generating casts.
@@ -476,9 +610,10 @@ pub unsafe fn gen_cast(
     to_ll: LLVMTypeRef,
 ) -> WeldResult<LLVMValueRef> {
     use crate::ast::ScalarKind::*;
-    use crate::ast::Type::Scalar;
+    use crate::ast::Type::{Scalar, Simd};
     let result = match (from, to) {
-        (&Scalar(s1), &Scalar(s2)) => {
+        // SIMD vectors of every type have the same number of lanes, so they are cast lane-wise.
+        (&Scalar(s1), &Scalar(s2)) | (&Simd(s1), &Simd(s2)) => {
             match (s1, s2) {
                 // Floating point extension and truncation.
                 (F32, F64) => LLVMBuildFPExt(builder, value, to_ll, c_str!("")),
@@ -632,6 +767,28 @@ pub unsafe fn gen_binop(
 
             Xor if s.is_integer() || s.is_bool() => LLVMBuildXor(builder, left, right, name),
 
+            // Shifting by the bit width or more is undefined in LLVM, so mask the shift amount.
+            ShiftLeft | ShiftRight if s.is_integer() => {
+                let elem_ty = if let Simd(_) = *ty {
+                    LLVMGetElementType(LLVMTypeOf(right))
+                } else {
+                    LLVMTypeOf(right)
+                };
+                let mut mask = LLVMConstInt(elem_ty, u64::from(s.bits() - 1), 0);
+                if let Simd(_) = *ty {
+                    mask = LLVMConstVector(
+                        [mask; LLVM_VECTOR_WIDTH as usize].as_mut_ptr(),
+                        LLVM_VECTOR_WIDTH,
+                    );
+                }
+                let amount = LLVMBuildAnd(builder, right, mask, name);
+                match op {
+                    ShiftLeft => LLVMBuildShl(builder, left, amount, name),
+                    _ if s.is_signed_integer() => LLVMBuildAShr(builder, left, amount, name),
+                    _ => LLVMBuildLShr(builder, left, amount, name),
+                }
+            }
+
             Max => {
                 let compare = gen_binop(builder, GreaterThanOrEqual, left, right, ty)?;
                 LLVMBuildSelect(builder, compare, left, right, c_str!(""))
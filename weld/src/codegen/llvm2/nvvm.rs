@@ -0,0 +1,684 @@
+//! Runs simple loops on an NVIDIA GPU when `weld.compile.backend` is `nvvm`.
+//!
+//! A loop is eligible if it iterates over a single vector of scalars, and its body only computes
+//! scalar values and merges them into an `appender` (exactly once per iteration) or a `merger`.
+//! The body of each eligible loop is compiled to a PTX kernel with LLVM's NVPTX backend, and the
+//! PTX is embedded in the module. At runtime, `weld_runst_gpu_for` copies the loop's data to the
+//! GPU and runs the kernel. Each GPU thread runs every `threads`-th iteration: for an appender, it
+//! writes the merged value of each iteration to the iteration's index in the output, and for a
+//! merger, it writes its partial result to the thread's index. The generated code then merges the
+//! outputs into the loop's builder in order on the CPU.
+//!
+//! If the CUDA driver or a GPU is not available, or the kernel fails, the loop runs on the CPU as
+//! it would with the `llvm` backend. Loops that are not eligible always run on the CPU, and the
+//! reason is logged at the `info` level.
+
+use llvm_sys;
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+use std::sync::Once;
+
+use crate::ast::BuilderKind::*;
+use crate::ast::IterKind::*;
+use crate::ast::Type::*;
+use crate::ast::*;
+use crate::conf::{Backend, ParsedConf};
+use crate::error::*;
+use crate::runtime::cuda::KERNEL_NAME;
+use crate::sir::StatementKind::*;
+use crate::sir::*;
+
+use self::llvm_sys::core::*;
+use self::llvm_sys::prelude::*;
+use self::llvm_sys::target_machine::*;
+use self::llvm_sys::LLVMIntPredicate;
+
+use super::vector::VectorExt;
+use super::{jit, numeric};
+use super::{CodeGenExt, FunctionContext, LlvmGenerator};
+
+/// The target triple of kernels.
+const NVPTX_TRIPLE: &str = "nvptx64-nvidia-cuda";
+
+/// The data layout of kernels.
+const NVPTX_DATA_LAYOUT: &str = "e-i64:64-i128:128-v16:16-v32:32-n16:32:64";
+
+/// The GPU architecture that kernels are compiled for.
+///
+/// The driver compiles the PTX for the actual GPU, so this is the oldest supported architecture.
+const NVPTX_CPU: &str = "sm_50";
+
+/// The `ptx_kernel` calling convention, which marks a function as a kernel entry point.
+const PTX_KERNEL_CALL_CONV: u32 = 71;
+
+/// The maximum number of partial results of a loop over a merger.
+///
+/// Each GPU thread reduces every `GPU_PARTIALS`-th element, and the CPU merges the partial
+/// results.
+pub const GPU_PARTIALS: i64 = 16384;
+
+static NVPTX_INIT: Once = Once::new();
+
+/// How an eligible loop produces its output.
+#[derive(Clone, Copy)]
+enum GpuOutput {
+    /// Each iteration merges one value into an appender.
+    Map(ScalarKind),
+    /// Iterations merge values into a merger.
+    Reduce(ScalarKind, BinOpKind),
+}
+
+impl GpuOutput {
+    /// Returns the kind of the values written to the output.
+    fn kind(self) -> ScalarKind {
+        match self {
+            GpuOutput::Map(kind) | GpuOutput::Reduce(kind, _) => kind,
+        }
+    }
+}
+
+/// A loop that can run on the GPU.
+struct GpuLoop<'a> {
+    /// The body of the loop.
+    body: &'a SirFunction,
+    /// The kind of the loop's elements.
+    elem: ScalarKind,
+    /// How the loop produces its output.
+    output: GpuOutput,
+    /// Values from the enclosing function that the body uses, which are passed to the kernel.
+    captures: Vec<(Symbol, ScalarKind)>,
+}
+
+/// Trait for generating code that runs loops on the GPU.
+pub trait NvvmGen {
+    /// Generates code to run the loop `parfor` on the GPU if it is eligible.
+    ///
+    /// Returns `None` if the loop always runs on the CPU. Otherwise, the builder is positioned in
+    /// the block that runs the loop if the GPU is not available, which should branch to the
+    /// returned block after storing the loop's builder in `output`. `iterations` is the number of
+    /// iterations of the loop.
+    unsafe fn gen_gpu_loop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        output: &Symbol,
+        parfor: &ParallelForData,
+        iterations: LLVMValueRef,
+    ) -> WeldResult<Option<LLVMBasicBlockRef>>;
+}
+
+impl NvvmGen for LlvmGenerator {
+    unsafe fn gen_gpu_loop(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        output: &Symbol,
+        parfor: &ParallelForData,
+        iterations: LLVMValueRef,
+    ) -> WeldResult<Option<LLVMBasicBlockRef>> {
+        use self::LLVMIntPredicate::{LLVMIntEQ, LLVMIntNE, LLVMIntSGT, LLVMIntSLT};
+        if self.conf.backend != Backend::Nvvm {
+            return Ok(None);
+        }
+        let gpu = match gpu_loop(&self.conf, ctx, parfor) {
+            Ok(gpu) => gpu,
+            Err(reason) => {
+                info!("Running loop on the CPU: {}", reason);
+                return Ok(None);
+            }
+        };
+        let ptx = match gen_ptx(&self.conf, ctx.sir_program, &gpu, parfor) {
+            Ok(ptx) => ptx,
+            Err(err) => {
+                info!("Running loop on the CPU: {}", err);
+                return Ok(None);
+            }
+        };
+        trace!("Generated GPU kernel:\n{}", ptx);
+
+        let run = ctx.get_run();
+        let int8p = self.void_pointer_type();
+        let vector_type = ctx.sir_function.symbol_type(&parfor.data[0].data)?;
+        let vector = self.load(ctx.builder, ctx.get_value(&parfor.data[0].data)?)?;
+        let data = self.gen_at(ctx.builder, vector_type, vector, self.i64(0))?;
+        let data = LLVMBuildBitCast(ctx.builder, data, int8p, c_str!(""));
+        let elem_ty = self.llvm_type(&Scalar(gpu.elem))?;
+        let elem_size = self.size_of(elem_ty);
+
+        let out_ty = self.llvm_type(&Scalar(gpu.output.kind()))?;
+        let threads = match gpu.output {
+            GpuOutput::Map(_) => iterations,
+            GpuOutput::Reduce(..) => {
+                let partials = self.i64(GPU_PARTIALS);
+                let fewer =
+                    LLVMBuildICmp(ctx.builder, LLVMIntSLT, iterations, partials, c_str!(""));
+                LLVMBuildSelect(ctx.builder, fewer, iterations, partials, c_str!("threads"))
+            }
+        };
+        let out_size = LLVMBuildNSWMul(ctx.builder, threads, self.size_of(out_ty), c_str!(""));
+        let out = self
+            .intrinsics
+            .call_weld_run_malloc(ctx.builder, run, out_size, None);
+
+        // The runtime passes the values that the pointers in `captures` point to as the kernel's
+        // arguments. Allocas belong in the entry block.
+        let captures = if gpu.captures.is_empty() {
+            self.null_ptr(int8p)
+        } else {
+            let entry_block = LLVMGetEntryBasicBlock(ctx.llvm_function);
+            let alloca_builder = LLVMCreateBuilderInContext(self.context);
+            LLVMPositionBuilderBefore(alloca_builder, LLVMGetFirstInstruction(entry_block));
+            let count = self.i64(gpu.captures.len() as i64);
+            let captures = LLVMBuildArrayAlloca(alloca_builder, int8p, count, c_str!("captures"));
+            LLVMDisposeBuilder(alloca_builder);
+            for (i, (symbol, _)) in gpu.captures.iter().enumerate() {
+                let pointer = LLVMBuildGEP(
+                    ctx.builder,
+                    captures,
+                    [self.i64(i as i64)].as_mut_ptr(),
+                    1,
+                    c_str!(""),
+                );
+                let value =
+                    LLVMBuildBitCast(ctx.builder, ctx.get_value(symbol)?, int8p, c_str!(""));
+                LLVMBuildStore(ctx.builder, value, pointer);
+            }
+            captures
+        };
+
+        let ptx = self.gen_global_string(ctx.builder, CString::new(ptx).unwrap());
+        let ptx = LLVMConstBitCast(ptx, int8p);
+        let ran = self.intrinsics.call_weld_run_gpu_for(
+            ctx.builder,
+            run,
+            &[
+                ptx,
+                data,
+                elem_size,
+                iterations,
+                out,
+                out_size,
+                threads,
+                captures,
+                self.i64(gpu.captures.len() as i64),
+            ],
+        );
+
+        let ran_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("gpu.ran"));
+        let merge_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("gpu.merge"));
+        let merged_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("gpu.merged"));
+        let fallback_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("gpu.fallback"));
+        let done_block =
+            LLVMAppendBasicBlockInContext(self.context, ctx.llvm_function, c_str!("gpu.done"));
+        let ran = LLVMBuildICmp(ctx.builder, LLVMIntNE, ran, self.i64(0), c_str!(""));
+        LLVMBuildCondBr(ctx.builder, ran, ran_block, fallback_block);
+
+        // Merge the outputs into the builder in order.
+        LLVMPositionBuilderAtEnd(ctx.builder, ran_block);
+        let outputs = LLVMBuildBitCast(ctx.builder, out, LLVMPointerType(out_ty, 0), c_str!(""));
+        let any = LLVMBuildICmp(ctx.builder, LLVMIntSGT, threads, self.i64(0), c_str!(""));
+        LLVMBuildCondBr(ctx.builder, any, merge_block, merged_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, merge_block);
+        let k = LLVMBuildPhi(ctx.builder, self.i64_type(), c_str!(""));
+        let pointer = LLVMBuildGEP(ctx.builder, outputs, [k].as_mut_ptr(), 1, c_str!(""));
+        let value = self.load(ctx.builder, pointer)?;
+        let builder_pointer = ctx.get_value(&parfor.builder)?;
+        match *ctx.sir_function.symbol_type(&parfor.builder)? {
+            Builder(ref kind @ Appender(_), _) => {
+                let methods = self.appenders.get_mut(kind).unwrap();
+                let _ = methods.gen_merge(
+                    ctx.builder,
+                    &mut self.intrinsics,
+                    run,
+                    builder_pointer,
+                    value,
+                )?;
+            }
+            Builder(ref kind @ Merger(_, _), _) => {
+                let methods = self.mergers.get_mut(kind).unwrap();
+                let _ = methods.gen_merge(ctx.builder, builder_pointer, value)?;
+            }
+            _ => unreachable!(),
+        }
+        let updated = LLVMBuildNSWAdd(ctx.builder, k, self.i64(1), c_str!(""));
+        let finished = LLVMBuildICmp(ctx.builder, LLVMIntEQ, updated, threads, c_str!(""));
+        let end_block = LLVMGetInsertBlock(ctx.builder);
+        LLVMBuildCondBr(ctx.builder, finished, merged_block, merge_block);
+
+        let mut blocks = [ran_block, end_block];
+        let mut values = [self.i64(0), updated];
+        LLVMAddIncoming(
+            k,
+            values.as_mut_ptr(),
+            blocks.as_mut_ptr(),
+            values.len() as u32,
+        );
+
+        LLVMPositionBuilderAtEnd(ctx.builder, merged_block);
+        self.intrinsics.call_weld_run_free(ctx.builder, run, out);
+        let builder = self.load(ctx.builder, builder_pointer)?;
+        LLVMBuildStore(ctx.builder, builder, ctx.get_value(output)?);
+        LLVMBuildBr(ctx.builder, done_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, fallback_block);
+        self.intrinsics.call_weld_run_free(ctx.builder, run, out);
+        Ok(Some(done_block))
+    }
+}
+
+/// Returns the loop `parfor` if it can run on the GPU, or the reason it cannot.
+fn gpu_loop<'a>(
+    conf: &ParsedConf,
+    ctx: &FunctionContext<'a>,
+    parfor: &ParallelForData,
+) -> Result<GpuLoop<'a>, String> {
+    if ctx.sir_function.loop_body {
+        return Err("the loop is nested in another loop".to_string());
+    }
+    if conf.checked_arithmetic || conf.trace_run {
+        return Err("checked arithmetic and tracing are not supported".to_string());
+    }
+    if parfor.data.len() != 1 {
+        return Err("the loop iterates over more than one vector".to_string());
+    }
+    let iter = &parfor.data[0];
+    if iter.kind != ScalarIter || iter.start.is_some() {
+        return Err("the loop uses an iterator other than a vector".to_string());
+    }
+
+    let body = &ctx.sir_program.funcs[parfor.body];
+    let scalar = |symbol: &Symbol| match body.symbol_type(symbol) {
+        Ok(Scalar(kind)) => Some(*kind),
+        _ => None,
+    };
+    let elem = scalar(&parfor.data_arg).ok_or("the loop's elements are not scalars")?;
+    let output = match body.symbol_type(&parfor.builder_arg) {
+        Ok(Builder(Appender(ref elem), _)) => match **elem {
+            Scalar(kind) => GpuOutput::Map(kind),
+            _ => return Err("the loop appends values that are not scalars".to_string()),
+        },
+        Ok(Builder(Merger(ref elem, op), _)) => match **elem {
+            Scalar(kind)
+                if kind.is_float()
+                    && !conf.enable_fast_math
+                    && (*op == BinOpKind::Add || *op == BinOpKind::Multiply) =>
+            {
+                return Err("floating point sums and products require fast math".to_string());
+            }
+            Scalar(kind) => GpuOutput::Reduce(kind, *op),
+            _ => return Err("the loop merges values that are not scalars".to_string()),
+        },
+        _ => return Err("the loop's builder is not an appender or a merger".to_string()),
+    };
+
+    let mut merges = 0;
+    let mut end_blocks = 0;
+    let mut merges_in_end_block = 0;
+    for block in body.blocks.iter() {
+        let mut block_merges = 0;
+        for statement in block.statements.iter() {
+            let supported = match statement.kind {
+                Merge { ref builder, .. } => {
+                    block_merges += 1;
+                    *builder == parfor.builder_arg
+                }
+                BinOp { op, ref left, .. } => match op {
+                    BinOpKind::Pow => false,
+                    BinOpKind::Modulo => scalar(left).is_some_and(|k| !k.is_float()),
+                    _ => true,
+                },
+                UnaryOp { op, ref child } => {
+                    let float = scalar(child).is_some_and(|k| k.is_float());
+                    float && (op == UnaryOpKind::Sqrt || op == UnaryOpKind::Abs)
+                }
+                Assign(_) | AssignLiteral(_) | Cast(_, _) | Negate(_) | Not(_) | Select { .. } => {
+                    true
+                }
+                _ => false,
+            };
+            if !supported {
+                return Err(format!("the loop's body contains `{}`", statement));
+            }
+            let builder_arg = Some(&parfor.builder_arg);
+            let uses_non_scalar = statement
+                .kind
+                .children()
+                .chain(statement.output.iter())
+                .filter(|symbol| !(block_merges > 0 && Some(*symbol) == builder_arg))
+                .any(|symbol| scalar(symbol).is_none());
+            if uses_non_scalar {
+                return Err(format!(
+                    "the loop's body uses a non-scalar in `{}`",
+                    statement
+                ));
+            }
+        }
+        match block.terminator {
+            Terminator::Branch { .. } | Terminator::JumpBlock(_) => (),
+            Terminator::EndFunction(ref symbol) if *symbol == parfor.builder_arg => {
+                end_blocks += 1;
+                merges_in_end_block = block_merges;
+            }
+            _ => return Err(format!("the loop's body contains `{}`", block.terminator)),
+        }
+        merges += block_merges;
+    }
+    if let GpuOutput::Map(_) = output {
+        if merges != 1 || end_blocks != 1 || merges_in_end_block != 1 {
+            return Err("the loop does not append exactly one value per iteration".to_string());
+        }
+    }
+
+    let mut captures = vec![];
+    for symbol in body.params.keys() {
+        if *symbol == iter.data || *symbol == parfor.builder {
+            continue;
+        }
+        match scalar(symbol) {
+            Some(kind) => captures.push((symbol.clone(), kind)),
+            None => return Err(format!("the loop's body uses the non-scalar {}", symbol)),
+        }
+    }
+
+    Ok(GpuLoop {
+        body,
+        elem,
+        output,
+        captures,
+    })
+}
+
+/// Generates the kernel for `gpu` and returns its PTX.
+unsafe fn gen_ptx(
+    conf: &ParsedConf,
+    program: &SirProgram,
+    gpu: &GpuLoop<'_>,
+    parfor: &ParallelForData,
+) -> WeldResult<String> {
+    let mut gen = LlvmGenerator::new(conf.clone())?;
+    let triple = CString::new(NVPTX_TRIPLE).unwrap();
+    let layout = CString::new(NVPTX_DATA_LAYOUT).unwrap();
+    LLVMSetTarget(gen.module, triple.as_ptr());
+    LLVMSetDataLayout(gen.module, layout.as_ptr());
+
+    let result = gen
+        .gen_kernel(program, gpu, parfor)
+        .and_then(|_| gen.emit_ptx());
+    LLVMDisposeModule(gen.module);
+    LLVMContextDispose(gen.context);
+    result
+}
+
+impl LlvmGenerator {
+    /// Generates the kernel function for `gpu`.
+    ///
+    /// The kernel has the signature `void(T* data, U* out, i64 iterations, i64 threads,
+    /// captures...)`. It is generated in the same way as a loop body function, except that merges
+    /// write to the output, and each thread runs every `threads`-th iteration starting at its
+    /// index.
+    unsafe fn gen_kernel(
+        &mut self,
+        program: &SirProgram,
+        gpu: &GpuLoop<'_>,
+        parfor: &ParallelForData,
+    ) -> WeldResult<()> {
+        use self::LLVMIntPredicate::LLVMIntSLT;
+        let elem_ty = self.llvm_type(&Scalar(gpu.elem))?;
+        let out_ty = self.llvm_type(&Scalar(gpu.output.kind()))?;
+        let mut arg_tys = vec![
+            LLVMPointerType(elem_ty, 0),
+            LLVMPointerType(out_ty, 0),
+            self.i64_type(),
+            self.i64_type(),
+        ];
+        for (_, kind) in gpu.captures.iter() {
+            arg_tys.push(self.llvm_type(&Scalar(*kind))?);
+        }
+        let fn_ty = LLVMFunctionType(
+            self.void_type(),
+            arg_tys.as_mut_ptr(),
+            arg_tys.len() as u32,
+            0,
+        );
+        let name = CString::new(KERNEL_NAME).unwrap();
+        let function = LLVMAddFunction(self.module, name.as_ptr(), fn_ty);
+        LLVMSetFunctionCallConv(function, PTX_KERNEL_CALL_CONV);
+
+        let ctx = &mut FunctionContext::new(self.context, program, gpu.body, function);
+        let entry_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!(""));
+        LLVMPositionBuilderAtEnd(ctx.builder, entry_block);
+
+        // Every symbol that the body uses is a scalar.
+        for (symbol, ty) in gpu.body.params.iter().chain(gpu.body.locals.iter()) {
+            if let Scalar(_) = *ty {
+                let name = CString::new(symbol.to_string()).unwrap();
+                let value = LLVMBuildAlloca(ctx.builder, self.llvm_type(ty)?, name.as_ptr());
+                ctx.symbols.insert(symbol.clone(), value);
+            }
+        }
+        for (i, (symbol, _)) in gpu.captures.iter().enumerate() {
+            let value = LLVMGetParam(function, 4 + i as u32);
+            LLVMBuildStore(ctx.builder, value, ctx.get_value(symbol)?);
+        }
+        let data = LLVMGetParam(function, 0);
+        let out = LLVMGetParam(function, 1);
+        let iterations = LLVMGetParam(function, 2);
+        let threads = LLVMGetParam(function, 3);
+
+        // tid = ctaid.x * ntid.x + tid.x
+        let block_index = self.gen_special_register(ctx.builder, "ctaid.x")?;
+        let block_size = self.gen_special_register(ctx.builder, "ntid.x")?;
+        let thread_index = self.gen_special_register(ctx.builder, "tid.x")?;
+        let tid = LLVMBuildNSWMul(ctx.builder, block_index, block_size, c_str!(""));
+        let tid = LLVMBuildNSWAdd(ctx.builder, tid, thread_index, c_str!("tid"));
+
+        let index = LLVMBuildAlloca(ctx.builder, self.i64_type(), c_str!("index"));
+        LLVMBuildStore(ctx.builder, tid, index);
+        let accumulator = match gpu.output {
+            GpuOutput::Reduce(kind, op) => {
+                let accumulator = LLVMBuildAlloca(ctx.builder, out_ty, c_str!("acc"));
+                LLVMBuildStore(ctx.builder, self.binop_identity(op, kind)?, accumulator);
+                accumulator
+            }
+            GpuOutput::Map(_) => ptr::null_mut(),
+        };
+
+        self.gen_basic_block_defs(ctx)?;
+        let loop_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!("loop"));
+        let body_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!("loop.body"));
+        let end_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!("loop.end"));
+        let exit_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!("loop.exit"));
+        LLVMPositionBuilderAtEnd(ctx.builder, entry_block);
+        LLVMBuildBr(ctx.builder, loop_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, loop_block);
+        let i = self.load(ctx.builder, index)?;
+        let more = LLVMBuildICmp(ctx.builder, LLVMIntSLT, i, iterations, c_str!(""));
+        LLVMBuildCondBr(ctx.builder, more, body_block, exit_block);
+
+        LLVMPositionBuilderAtEnd(ctx.builder, body_block);
+        let pointer = LLVMBuildGEP(ctx.builder, data, [i].as_mut_ptr(), 1, c_str!(""));
+        let element = self.load(ctx.builder, pointer)?;
+        LLVMBuildStore(ctx.builder, element, ctx.get_value(&parfor.data_arg)?);
+        LLVMBuildStore(ctx.builder, i, ctx.get_value(&parfor.idx_arg)?);
+        LLVMBuildBr(ctx.builder, ctx.get_block(gpu.body.blocks[0].id)?);
+
+        for bb in gpu.body.blocks.iter() {
+            LLVMPositionBuilderAtEnd(ctx.builder, ctx.get_block(bb.id)?);
+            for statement in bb.statements.iter() {
+                if let Merge { ref value, .. } = statement.kind {
+                    let value = self.load(ctx.builder, ctx.get_value(value)?)?;
+                    match gpu.output {
+                        GpuOutput::Map(_) => {
+                            let pointer =
+                                LLVMBuildGEP(ctx.builder, out, [i].as_mut_ptr(), 1, c_str!(""));
+                            LLVMBuildStore(ctx.builder, value, pointer);
+                        }
+                        GpuOutput::Reduce(kind, op) => {
+                            let current = self.load(ctx.builder, accumulator)?;
+                            let result =
+                                numeric::gen_binop(ctx.builder, op, current, value, &Scalar(kind))?;
+                            LLVMBuildStore(ctx.builder, result, accumulator);
+                        }
+                    }
+                } else {
+                    self.gen_statement(ctx, statement)?;
+                }
+            }
+            if let Terminator::EndFunction(_) = bb.terminator {
+                LLVMBuildBr(ctx.builder, end_block);
+            } else {
+                self.gen_terminator(ctx, bb, None)?;
+            }
+        }
+
+        LLVMPositionBuilderAtEnd(ctx.builder, end_block);
+        let updated = LLVMBuildNSWAdd(ctx.builder, i, threads, c_str!(""));
+        LLVMBuildStore(ctx.builder, updated, index);
+        LLVMBuildBr(ctx.builder, loop_block);
+
+        // Each thread of a reduction writes its partial result. The launch may round the number
+        // of threads up, and the extra threads have no partial result.
+        LLVMPositionBuilderAtEnd(ctx.builder, exit_block);
+        if let GpuOutput::Reduce(..) = gpu.output {
+            let write_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!(""));
+            let return_block = LLVMAppendBasicBlockInContext(self.context, function, c_str!(""));
+            let in_range = LLVMBuildICmp(ctx.builder, LLVMIntSLT, tid, threads, c_str!(""));
+            LLVMBuildCondBr(ctx.builder, in_range, write_block, return_block);
+            LLVMPositionBuilderAtEnd(ctx.builder, write_block);
+            let pointer = LLVMBuildGEP(ctx.builder, out, [tid].as_mut_ptr(), 1, c_str!(""));
+            let result = self.load(ctx.builder, accumulator)?;
+            LLVMBuildStore(ctx.builder, result, pointer);
+            LLVMBuildBr(ctx.builder, return_block);
+            LLVMPositionBuilderAtEnd(ctx.builder, return_block);
+        }
+        LLVMBuildRetVoid(ctx.builder);
+        Ok(())
+    }
+
+    /// Generates code to read the special register `llvm.nvvm.read.ptx.sreg.<name>` as an `i64`.
+    unsafe fn gen_special_register(
+        &mut self,
+        builder: LLVMBuilderRef,
+        name: &str,
+    ) -> WeldResult<LLVMValueRef> {
+        let name = format!("llvm.nvvm.read.ptx.sreg.{}", name);
+        let ret_ty = self.i32_type();
+        self.intrinsics.add(&name, ret_ty, &mut []);
+        let value = self.intrinsics.call(builder, &name, &mut [])?;
+        Ok(LLVMBuildZExt(builder, value, self.i64_type(), c_str!("")))
+    }
+
+    /// Optimizes the kernel module and compiles it to PTX.
+    unsafe fn emit_ptx(&mut self) -> WeldResult<String> {
+        use self::llvm_sys::transforms::pass_manager_builder::*;
+
+        // The module declares the runtime functions, which the kernel cannot call.
+        let mut function = LLVMGetFirstFunction(self.module);
+        while !function.is_null() {
+            let next = LLVMGetNextFunction(function);
+            if LLVMIsDeclaration(function) != 0 && LLVMGetFirstUse(function).is_null() {
+                LLVMDeleteFunction(function);
+            }
+            function = next;
+        }
+        jit::verify_module(self.module)?;
+
+        let mpm = LLVMCreatePassManager();
+        let builder = LLVMPassManagerBuilderCreate();
+        LLVMPassManagerBuilderSetOptLevel(builder, 2);
+        LLVMPassManagerBuilderPopulateModulePassManager(builder, mpm);
+        LLVMPassManagerBuilderDispose(builder);
+        LLVMRunPassManager(mpm, self.module);
+        LLVMDisposePassManager(mpm);
+
+        NVPTX_INIT.call_once(|| {
+            use self::llvm_sys::target::*;
+            LLVMInitializeNVPTXTargetInfo();
+            LLVMInitializeNVPTXTarget();
+            LLVMInitializeNVPTXTargetMC();
+            LLVMInitializeNVPTXAsmPrinter();
+        });
+
+        let triple = CString::new(NVPTX_TRIPLE).unwrap();
+        let cpu = CString::new(NVPTX_CPU).unwrap();
+        let mut target = mem::MaybeUninit::uninit();
+        let mut err = ptr::null_mut();
+        if LLVMGetTargetFromTriple(triple.as_ptr(), target.as_mut_ptr(), &mut err) == 1 {
+            let message = CStr::from_ptr(err).to_string_lossy().into_owned();
+            LLVMDisposeMessage(err);
+            return compile_err!("NVPTX target initialization failed with error {}", message);
+        }
+        let target_machine = LLVMCreateTargetMachine(
+            target.assume_init(),
+            triple.as_ptr(),
+            cpu.as_ptr(),
+            c_str!(""),
+            LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            LLVMRelocMode::LLVMRelocDefault,
+            LLVMCodeModel::LLVMCodeModelDefault,
+        );
+
+        let mut buffer = ptr::null_mut();
+        let result = LLVMTargetMachineEmitToMemoryBuffer(
+            target_machine,
+            self.module,
+            LLVMCodeGenFileType::LLVMAssemblyFile,
+            &mut err,
+            &mut buffer,
+        );
+        LLVMDisposeTargetMachine(target_machine);
+        if result == 1 {
+            let message = CStr::from_ptr(err).to_string_lossy().into_owned();
+            libc::free(err as *mut libc::c_void);
+            return compile_err!("PTX generation failed with error {}", message);
+        }
+        let start = LLVMGetBufferStart(buffer) as *const u8;
+        let bytes = std::slice::from_raw_parts(start, LLVMGetBufferSize(buffer));
+        let ptx = String::from_utf8_lossy(bytes).into_owned();
+        LLVMDisposeMemoryBuffer(buffer);
+        Ok(ptx)
+    }
+}
+
+#[test]
+fn gpu_loop_ptx() {
+    use crate::conf::constants::*;
+    use crate::WeldConf;
+
+    let code = "|x:vec[i64], k:i64| result(for(x, merger[i64,+], |b,i,e| merge(b, e * k)))";
+    let mut conf = WeldConf::new();
+    conf.set(CONF_BACKEND_KEY, "nvvm");
+    let conf = ParsedConf::parse(&conf).unwrap();
+    let program = crate::syntax::parser::parse_program(code).unwrap();
+    let mut expr = crate::syntax::macro_processor::process_program(&program).unwrap();
+    expr.infer_types().unwrap();
+    let sir = crate::sir::ast_to_sir(&expr).unwrap();
+
+    let func = &sir.funcs[0];
+    let parfor = func.blocks[0]
+        .statements
+        .iter()
+        .filter_map(|s| match s.kind {
+            ParallelFor(ref parfor) => Some(parfor),
+            _ => None,
+        })
+        .next()
+        .unwrap();
+    let gen = unsafe { LlvmGenerator::new(conf.clone()).unwrap() };
+    let llvm_function = unsafe {
+        let fn_ty = LLVMFunctionType(gen.void_type(), ptr::null_mut(), 0, 0);
+        LLVMAddFunction(gen.module, c_str!("f"), fn_ty)
+    };
+    let ctx = FunctionContext::new(gen.context, &sir, func, llvm_function);
+    let gpu = gpu_loop(&conf, &ctx, parfor).unwrap();
+    assert_eq!(gpu.captures.len(), 1);
+
+    let ptx = unsafe { gen_ptx(&conf, &sir, &gpu, parfor).unwrap() };
+    assert!(ptx.contains(&format!(".entry {}", KERNEL_NAME)));
+}
@@ -0,0 +1,238 @@
+//! Generates per-loop profiling counters.
+//!
+//! When `weld.compile.profileLoops` is enabled, the module defines a global array with three
+//! `i64` counters for each loop: the number of iterations, the nanoseconds spent in the loop, and
+//! the number of bytes merged into the loop's builder. Loops update their counters with atomic
+//! adds, so parallel loops and concurrent runs of the module are counted correctly. The JIT looks
+//! up the array by name after compilation so that the host can read it.
+//!
+//! Counters are keyed by the expression ID of each loop. A merge is counted for every loop that
+//! owns the builder it merges into: the builder argument of a loop body is owned by the loop, and
+//! ownership follows the builder into structs, nested loops, and the functions of nested loops.
+
+use llvm_sys;
+
+use fnv::FnvHashMap;
+
+use std::ffi::CString;
+
+use crate::ast::*;
+use crate::error::*;
+use crate::sir::StatementKind::ParallelFor;
+use crate::sir::*;
+
+use self::llvm_sys::core::*;
+use self::llvm_sys::prelude::*;
+use self::llvm_sys::{LLVMAtomicOrdering, LLVMAtomicRMWBinOp, LLVMLinkage};
+
+use super::{CodeGenExt, FunctionContext, LlvmGenerator};
+
+/// The name of the global array of loop counters.
+pub const LOOP_COUNTERS_NAME: &str = "weld_loop_counters";
+
+/// The number of counters for each loop.
+pub const COUNTERS_PER_LOOP: usize = 3;
+/// Index of the iteration counter of a loop.
+pub const ITERATIONS_INDEX: usize = 0;
+/// Index of the time counter of a loop.
+pub const TIME_INDEX: usize = 1;
+/// Index of the merged bytes counter of a loop.
+pub const BYTES_MERGED_INDEX: usize = 2;
+
+/// Trait for generating code that updates loop counters.
+///
+/// Each method does nothing if loop profiling is disabled.
+pub trait LoopProfileGen {
+    /// Assigns counters to each loop in `program` and defines the global array of counters.
+    unsafe fn declare_loop_counters(&mut self, program: &SirProgram);
+    /// Generates code to read the time before running the loop `parfor`.
+    ///
+    /// Returns the time, which should be passed to `gen_loop_profile`.
+    unsafe fn gen_loop_timer(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        parfor: &ParallelForData,
+    ) -> Option<LLVMValueRef>;
+    /// Generates code to add the iterations and time of the loop `parfor` to its counters.
+    unsafe fn gen_loop_profile(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        parfor: &ParallelForData,
+        start_time: Option<LLVMValueRef>,
+        iterations: LLVMValueRef,
+    ) -> WeldResult<()>;
+    /// Generates code to count merging `value` into `builder` for the loops that own `builder`.
+    unsafe fn gen_merge_profile(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        builder: &Symbol,
+        value: &Symbol,
+    ) -> WeldResult<()>;
+}
+
+impl LoopProfileGen for LlvmGenerator {
+    unsafe fn declare_loop_counters(&mut self, program: &SirProgram) {
+        if !self.conf.profile_loops {
+            return;
+        }
+        for func in program.funcs.iter() {
+            for block in func.blocks.iter() {
+                for statement in block.statements.iter() {
+                    if let ParallelFor(ref parfor) = statement.kind {
+                        if let Some(id) = parfor.id {
+                            let next = self.loop_spans.len();
+                            let index = *self.loop_counters.entry(id).or_insert(next);
+                            if index == next {
+                                self.loop_spans.push((id, parfor.span));
+                            }
+                            add_owners(
+                                &mut self.builder_loops,
+                                (parfor.body, parfor.builder_arg.clone()),
+                                &[index],
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        propagate_owners(program, &mut self.builder_loops);
+
+        let length = self.loop_spans.len() * COUNTERS_PER_LOOP;
+        let ty = LLVMArrayType(self.i64_type(), length as u32);
+        let name = CString::new(LOOP_COUNTERS_NAME).unwrap();
+        let global = LLVMAddGlobal(self.module, ty, name.as_ptr());
+        LLVMSetInitializer(global, self.zero(ty));
+        LLVMSetLinkage(global, LLVMLinkage::LLVMExternalLinkage);
+    }
+
+    unsafe fn gen_loop_timer(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        parfor: &ParallelForData,
+    ) -> Option<LLVMValueRef> {
+        if self.loop_counters.contains_key(&parfor.id?) {
+            Some(self.intrinsics.call_weld_run_profile_time(ctx.builder))
+        } else {
+            None
+        }
+    }
+
+    unsafe fn gen_loop_profile(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        parfor: &ParallelForData,
+        start_time: Option<LLVMValueRef>,
+        iterations: LLVMValueRef,
+    ) -> WeldResult<()> {
+        if let Some(start_time) = start_time {
+            let end_time = self.intrinsics.call_weld_run_profile_time(ctx.builder);
+            let time = LLVMBuildSub(ctx.builder, end_time, start_time, c_str!(""));
+            let index = self.loop_counters[&parfor.id.unwrap()];
+            self.gen_add_counter(ctx.builder, index, ITERATIONS_INDEX, iterations);
+            self.gen_add_counter(ctx.builder, index, TIME_INDEX, time);
+        }
+        Ok(())
+    }
+
+    unsafe fn gen_merge_profile(
+        &mut self,
+        ctx: &mut FunctionContext<'_>,
+        builder: &Symbol,
+        value: &Symbol,
+    ) -> WeldResult<()> {
+        let key = (ctx.sir_function.id, builder.clone());
+        if let Some(loops) = self.builder_loops.get(&key).cloned() {
+            let ty = self.llvm_type(ctx.sir_function.symbol_type(value)?)?;
+            let size = self.size_of(ty);
+            for index in loops {
+                self.gen_add_counter(ctx.builder, index, BYTES_MERGED_INDEX, size);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LlvmGenerator {
+    /// Generates code to atomically add `value` to a counter of the loop with counter index `i`.
+    unsafe fn gen_add_counter(
+        &mut self,
+        builder: LLVMBuilderRef,
+        i: usize,
+        index: usize,
+        value: LLVMValueRef,
+    ) {
+        let name = CString::new(LOOP_COUNTERS_NAME).unwrap();
+        let global = LLVMGetNamedGlobal(self.module, name.as_ptr());
+        let offset = i * COUNTERS_PER_LOOP + index;
+        let mut indices = [self.i64(0), self.i64(offset as i64)];
+        let pointer = LLVMBuildInBoundsGEP(
+            builder,
+            global,
+            indices.as_mut_ptr(),
+            indices.len() as u32,
+            c_str!(""),
+        );
+        LLVMBuildAtomicRMW(
+            builder,
+            LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpAdd,
+            pointer,
+            value,
+            LLVMAtomicOrdering::LLVMAtomicOrderingMonotonic,
+            0,
+        );
+    }
+}
+
+type BuilderLoops = FnvHashMap<(FunctionId, Symbol), Vec<usize>>;
+
+/// Adds the loops `loops` to the owners of the builder `key`, returning whether any was new.
+fn add_owners(owners: &mut BuilderLoops, key: (FunctionId, Symbol), loops: &[usize]) -> bool {
+    let entry = owners.entry(key).or_default();
+    let mut changed = false;
+    for index in loops {
+        if !entry.contains(index) {
+            entry.push(*index);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Propagates the owners of builders in `owners` through `program` until nothing changes.
+///
+/// A symbol whose type contains a builder is owned by the loops that own any symbol it is computed
+/// from. A loop's builder passes its owners to the builder argument of the loop's body, and each
+/// parameter of a loop body has the owners of the symbol with the same name in the enclosing
+/// function.
+fn propagate_owners(program: &SirProgram, owners: &mut BuilderLoops) {
+    let mut changed = !owners.is_empty();
+    while changed {
+        changed = false;
+        for func in program.funcs.iter() {
+            for block in func.blocks.iter() {
+                for statement in block.statements.iter() {
+                    let mut edges = vec![];
+                    if let Some(ref output) = statement.output {
+                        if matches!(func.symbol_type(output), Ok(ty) if ty.contains_builder()) {
+                            for child in statement.kind.children() {
+                                edges.push((child.clone(), (func.id, output.clone())));
+                            }
+                        }
+                    }
+                    if let ParallelFor(ref parfor) = statement.kind {
+                        let body = parfor.body;
+                        edges.push((parfor.builder.clone(), (body, parfor.builder_arg.clone())));
+                        for param in program.funcs[body].params.keys() {
+                            edges.push((param.clone(), (body, param.clone())));
+                        }
+                    }
+                    for (from, to) in edges {
+                        if let Some(loops) = owners.get(&(func.id, from)).cloned() {
+                            changed |= add_owners(owners, to, &loops);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
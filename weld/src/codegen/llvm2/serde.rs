@@ -24,7 +24,7 @@ lazy_static! {
     /// The serialized type, which is a vec[u8].
     static ref SER_TY: Type = Type::Vector(Box::new(Type::Scalar(ScalarKind::U8)));
     /// The type returned by the serialization function.
-    static ref SER_RET_TY: Type = Type::Struct(vec![SER_TY.clone(), Scalar(ScalarKind::I64)]);
+    static ref SER_RET_TY: Type = Type::Struct(vec![SER_TY.clone(), Scalar(ScalarKind::I64)], FieldNames::none());
 }
 
 /// Trait for generating serialization and deserialization code.
@@ -256,7 +256,7 @@ impl SerHelper for LlvmGenerator {
                     let value = self.load(builder, value)?;
                     self.gen_put_value(builder, value, buffer, run, position)?
                 }
-                Struct(_) if !ty.has_pointer() => {
+                Struct(..) if !ty.has_pointer() => {
                     // Use a memcpy intrinsic instead of a load and store for structs.
                     let count = self.i64(1);
                     self.gen_put_values(builder, value, count, buffer, run, position)?
@@ -272,7 +272,7 @@ impl SerHelper for LlvmGenerator {
                         self.gen_put_value(builder, vec_size, buffer, run, position)?;
                     self.gen_put_values(builder, vec_ptr, vec_size, buffer, run, position)?
                 }
-                Struct(ref tys) => {
+                Struct(ref tys, _) => {
                     let mut buffer = buffer;
                     let mut position = position;
                     for (i, ty) in tys.iter().enumerate() {
@@ -616,7 +616,7 @@ impl DeHelper for LlvmGenerator {
                     LLVMBuildStore(builder, value, output);
                     position
                 }
-                Struct(_) if !ty.has_pointer() => {
+                Struct(..) if !ty.has_pointer() => {
                     // Copy a single value of the given type.
                     let one = self.i64(1);
                     self.gen_get_values(builder, output, one, buffer, position)?
@@ -633,7 +633,7 @@ impl DeHelper for LlvmGenerator {
                     LLVMBuildStore(builder, vector, output);
                     position
                 }
-                Struct(ref tys) => {
+                Struct(ref tys, _) => {
                     let mut position = position;
                     for (i, ty) in tys.iter().enumerate() {
                         let value_pointer =
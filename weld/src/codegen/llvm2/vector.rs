@@ -413,8 +413,11 @@ impl Vector {
             let size = LLVMGetParam(function, 2);
 
             // Compute the size of the array. We use the remaining size if the new size does not
-            // accomodate the vector starting at the given index.
+            // accomodate the vector starting at the given index. Indices past the end of the
+            // vector are clamped to its size, so such slices are empty.
             let cur_size = LLVMBuildExtractValue(builder, vector, SIZE_INDEX, c_str!(""));
+            let index_cmp = LLVMBuildICmp(builder, LLVMIntUGT, index, cur_size, c_str!(""));
+            let index = LLVMBuildSelect(builder, index_cmp, cur_size, index, c_str!(""));
             let remaining = LLVMBuildSub(builder, cur_size, index, c_str!(""));
             let size_cmp = LLVMBuildICmp(builder, LLVMIntUGT, size, remaining, c_str!(""));
             let new_size = LLVMBuildSelect(builder, size_cmp, remaining, size, c_str!(""));
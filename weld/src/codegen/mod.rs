@@ -4,12 +4,11 @@
 //! generation for converts the Weld AST to the Weld SIR to facilitate code generation, and then
 //! maps each SIR instruction to a compiler IR instruction (e.g., LLVM).
 //!
-//! For now, we only support LLVM, so this module is just a wrapper for the LLVM module.
-//! Eventually we will refactor it so common tasks such as Weld AST optimizations and AST-to-SIR
-//! conversion occur here and submodules implement backends for different hardware platforms. For
-//! example, an NVPTX GPU-based backend would be a new submodule that emits NVPTX code, and this
-//! module will provide the shared Weld optimization and SIR conversion logic that currently lives in the
-//! `llvm` module.
+//! For now, we only support LLVM, so this module is just a wrapper for the LLVM module. The
+//! experimental NVVM backend reuses the LLVM code generator to compile simple loops to NVPTX, and
+//! lives in the `llvm2::nvvm` submodule. Eventually we will refactor it so common tasks such as Weld
+//! AST optimizations and AST-to-SIR conversion occur here and submodules implement backends for
+//! different hardware platforms.
 
 use crate::ast::*;
 use crate::conf::ParsedConf;
@@ -19,10 +18,14 @@ use crate::sir::*;
 use crate::util::stats::CompilationStats;
 
 use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod llvm2;
 
 pub use self::llvm2::load_library;
+pub use self::llvm2::register_symbol;
+pub use self::llvm2::LLVM_VECTOR_WIDTH;
 
 /// A wrapper for a struct passed as input to Weld.
 #[derive(Clone, Debug)]
@@ -54,14 +57,47 @@ pub struct WeldOutputArgs {
     pub errno: WeldRuntimeErrno,
 }
 
+/// Profiling counters of a loop, collected when `weld.compile.profileLoops` is enabled.
+///
+/// Counters accumulate over every run of a module until they are reset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoopProfile {
+    /// The ID of the loop's expression.
+    ///
+    /// Copies of a loop made by optimizations share its ID and its counters.
+    pub id: NodeId,
+    /// The location of the loop in the program, if known.
+    ///
+    /// Optimizations such as loop fusion combine loops, in which case the location is that of
+    /// one of the original loops.
+    pub span: Option<Span>,
+    /// The number of iterations the loop ran.
+    pub iterations: i64,
+    /// The time spent in the loop, including loops nested in its body.
+    pub time: Duration,
+    /// The number of bytes merged into the loop's builder.
+    ///
+    /// This includes merges into the builder from loops nested in the body.
+    pub bytes_merged: i64,
+}
+
 /// A trait implemented by trait objects for running a Weld program.
 pub trait Runnable {
     fn run(&self, arg: i64) -> i64;
+    /// Returns the profiling counters of each loop in the program.
+    fn loop_profile(&self) -> Vec<LoopProfile>;
+    /// Resets the profiling counters of each loop to zero.
+    fn reset_loop_profile(&self);
+    /// Returns the location of the site `site` that raised a runtime error, if known.
+    fn error_span(&self, site: i64) -> Option<Span>;
 }
 
 /// A compiled, runnable module.
+///
+/// Clones share the same compiled code.
+#[derive(Clone)]
 pub struct CompiledModule {
-    runnable: Box<dyn Runnable + Send + Sync>,
+    runnable: Arc<dyn Runnable + Send + Sync>,
 }
 
 impl CompiledModule {
@@ -71,6 +107,21 @@ impl CompiledModule {
     pub fn run(&self, arg: i64) -> i64 {
         self.runnable.run(arg)
     }
+
+    /// Returns the profiling counters of each loop in the module.
+    pub fn loop_profile(&self) -> Vec<LoopProfile> {
+        self.runnable.loop_profile()
+    }
+
+    /// Returns the location of the site `site` that raised a runtime error, if known.
+    pub fn error_span(&self, site: i64) -> Option<Span> {
+        self.runnable.error_span(site)
+    }
+
+    /// Resets the profiling counters of each loop in the module.
+    pub fn reset_loop_profile(&self) {
+        self.runnable.reset_loop_profile()
+    }
 }
 
 impl fmt::Debug for CompiledModule {
@@ -83,14 +134,16 @@ impl fmt::Debug for CompiledModule {
 ///
 /// This function dispatches to the backend specified in `conf` to generate a compiled, runnable
 /// module. Statistics about compilation (e.g., time to generate code) are written into `stats`. If
-/// the `dumpCode` option is enabled, code is dumped to a filename `code-<timestamp>.[ll|S].
+/// the `dumpCode` option is enabled, code is dumped to a filename `code-<uuid>.[ll|S]`.
 pub fn compile_program(
     program: &SirProgram,
     conf: &mut ParsedConf,
     stats: &mut CompilationStats,
 ) -> WeldResult<CompiledModule> {
     let runnable = llvm2::compile(&program, conf, stats)?;
-    let result = CompiledModule { runnable };
+    let result = CompiledModule {
+        runnable: Arc::from(runnable),
+    };
     Ok(result)
 }
 
@@ -15,18 +15,36 @@ pub const CONF_MEMORY_LIMIT_KEY: &str = "weld.memory.limit";
 /// This parameter should be set in a configuration passed to a `WeldContext`.
 pub const CONF_THREADS_KEY: &str = "weld.threads";
 
+/// Pins the worker threads of parallel loops to cores.
+///
+/// Each worker runs only on one of the CPUs the calling thread is allowed to run on, so workers
+/// do not migrate between cores or sockets during a loop. This is only supported on Linux.
+///
+/// This parameter should be set in a configuration passed to a `WeldContext`.
+pub const CONF_THREAD_PINNING_KEY: &str = "weld.threads.pinning";
+
+/// Places memory allocated by a thread on the thread's NUMA node.
+///
+/// On multi-socket machines, builders allocated by the workers of a parallel loop are then local
+/// to the socket that updates them. This works best with thread pinning, and is only supported on
+/// Linux.
+///
+/// This parameter should be set in a configuration passed to a `WeldContext`.
+pub const CONF_NUMA_LOCAL_KEY: &str = "weld.memory.numaLocal";
+
 /// Specifies whether tracing should be enabled when compiling the program.
 ///
-/// Tracing will print, during runtime, the internal Weld instruction before executing it. This has
-/// a very large performance overhead and will print lots of output on large inputs: it should only
-/// be enabled for debugging (e.g., to identify a crash).
+/// Tracing will log, during runtime, the internal Weld instruction before executing it. Traces are
+/// logged at the `info` level. This has a very large performance overhead and will log lots of
+/// output on large inputs: it should only be enabled for debugging (e.g., to identify a crash).
 ///
 /// This parameter should be set for compilation.
 pub const CONF_TRACE_RUN_KEY: &str = "weld.compile.traceExecution";
 
 /// Specifies an ordered list of the optimizations to apply to a Weld program.
 ///
-/// This supercedes the default optimization set.
+/// This supercedes the default optimization set. Alternatively, the value can list changes to the
+/// default set, where `-name` disables a pass and `+name` appends one (e.g., `-vectorize,+cse`).
 ///
 /// This parameter should be set for compilation.
 pub const CONF_OPTIMIZATION_PASSES_KEY: &str = "weld.optimization.passes";
@@ -39,6 +57,37 @@ pub const CONF_OPTIMIZATION_PASSES_KEY: &str = "weld.optimization.passes";
 /// This parameter should be set for compilation.
 pub const CONF_EXPERIMENTAL_PASSES_KEY: &str = "weld.optimization.applyExperimentalTransforms";
 
+/// Enables optimizations that assume floating point arithmetic is associative.
+///
+/// This lets the optimizer reassociate and distribute `f32` and `f64` arithmetic, and lets
+/// mergers that add or multiply floating point values combine partial results in any order
+/// (e.g., when a loop is vectorized). Results may differ from strict evaluation due to rounding.
+///
+/// This parameter should be set for compilation.
+pub const CONF_FAST_MATH_KEY: &str = "weld.optimization.fastMath";
+
+/// Makes parallel floating point reductions reproducible.
+///
+/// Loops that add or multiply floating point values into a merger divide their iterations into a
+/// fixed number of chunks that does not depend on `weld.threads`, and combine the chunks' partial
+/// results in order, so the result is the same bit-for-bit for any number of threads. This only
+/// affects mergers that are reduced in parallel, which requires fast math.
+///
+/// This parameter should be set for compilation.
+pub const CONF_DETERMINISTIC_REDUCTION_KEY: &str = "weld.optimization.deterministicReduction";
+
+/// The minimum number of iterations in each task of a parallel loop.
+///
+/// Parallel loops are split into tasks of at least this many iterations, which worker threads
+/// take as they finish earlier tasks. Smaller tasks balance the load better when some iterations
+/// are much more expensive than others (e.g., in a filter whose selected elements are clustered),
+/// at the cost of more overhead per task. By default, the grain size of each loop is estimated from
+/// the number of operations in its body. A `grain_size` annotation on a loop overrides this
+/// setting.
+///
+/// This parameter should be set for compilation.
+pub const CONF_GRAIN_SIZE_KEY: &str = "weld.loop.grain_size";
+
 /// Enables internal Sequential IR (SIR) optimizations.
 ///
 /// This enables optimizations over the internal "sequential" Weld IR. Certain optimizations are
@@ -104,7 +153,8 @@ pub const CONF_DUMP_CODE_DIR_KEY: &str = "weld.compile.dumpCodeDir";
 
 /// Specifies the filename prefix for dumped code.
 ///
-/// This parameter should be set for compilation.
+/// The prefix is followed by the UUID of the compilation, so that files from different
+/// compilations do not conflict. This parameter should be set for compilation.
 pub const CONF_DUMP_CODE_FILENAME_KEY: &str = "weld.compile.dumpCodeFilename";
 
 /// Specifies the formats to dump for dumped code.
@@ -112,26 +162,115 @@ pub const CONF_DUMP_CODE_FILENAME_KEY: &str = "weld.compile.dumpCodeFilename";
 /// This key does not have an explicit default value: if it is not specified, all formats
 /// are dumped. The possible formats are:
 ///
-/// weld,weldopt,llvm,llvmopt,sir,assembly.
+/// weld,weldopt,weldpasses,llvm,llvmopt,sir,assembly.
+///
+/// `weldpasses` writes the Weld program after each optimization pass to a separate file.
 ///
 /// This parameter should be set for compilation.
 pub const CONF_DUMP_CODE_FORMATS_KEY: &str = "weld.compile.dumpCodeFormats";
 
-/// Enables runtime bounds checking for loops before executing them.
+/// Enables runtime bounds checking for loops before executing them, and for vector `lookup`
+/// expressions.
 ///
-/// This parameter should be set for compilation.
+/// An out-of-bounds access raises an `ArrayOutOfBounds` error that records the location of the
+/// failing expression. This parameter should be set for compilation.
 pub const CONF_ENABLE_BOUNDS_CHECKS_KEY: &str = "weld.compile.enableBoundsChecks";
 
+/// Enables overflow checks on integer arithmetic in generated code.
+///
+/// Integer addition, subtraction, multiplication, division and modulo that overflow raise an
+/// `IntegerOverflow` error instead of wrapping. This parameter should be set for compilation.
+pub const CONF_CHECKED_ARITHMETIC_KEY: &str = "weld.compile.checkedArithmetic";
+
+/// Enables per-loop profiling counters in generated code.
+///
+/// Each loop counts its iterations, the time spent in it, and the number of bytes merged into
+/// builders in its body. The counters are read with `WeldModule::loop_profile`. This parameter
+/// should be set for compilation.
+pub const CONF_PROFILE_LOOPS_KEY: &str = "weld.compile.profileLoops";
+
+/// Specifies the backend that runs loops, either `llvm` or `nvvm`.
+///
+/// The `nvvm` backend is experimental. It compiles simple map and reduce loops to PTX, and runs
+/// them on the first NVIDIA GPU with the CUDA driver. Other loops, and every loop on machines
+/// without a GPU, run on the CPU as with the `llvm` backend.
+///
+/// This parameter should be set for compilation.
+pub const CONF_BACKEND_KEY: &str = "weld.compile.backend";
+
+/// Enables reusing modules compiled earlier in this process from the same program and
+/// configuration.
+///
+/// This parameter should be set for compilation.
+pub const CONF_COMPILE_CACHE_KEY: &str = "weld.compile.cache";
+
+/// Specifies the maximum number of compiled modules to cache.
+///
+/// When the cache is full, the least recently used module is evicted. This parameter should be set
+/// for compilation.
+pub const CONF_COMPILE_CACHE_SIZE_KEY: &str = "weld.compile.cacheSize";
+
+/// Specifies the number of threads used to compile a program that returns a struct.
+///
+/// With more than one thread, each field of the returned struct is typed and optimized on its own,
+/// in parallel with the other fields. Loops in different fields are then not fused with each
+/// other. This parameter should be set for compilation.
+pub const CONF_COMPILE_THREADS_KEY: &str = "weld.compile.threads";
+
+/// Every configuration key that Weld reads.
+///
+/// Compiling a module or creating a context with any other key that starts with `weld.` fails,
+/// since the key is most likely misspelled.
+pub const CONF_KEYS: &[&str] = &[
+    CONF_MEMORY_LIMIT_KEY,
+    CONF_THREADS_KEY,
+    CONF_THREAD_PINNING_KEY,
+    CONF_NUMA_LOCAL_KEY,
+    CONF_TRACE_RUN_KEY,
+    CONF_OPTIMIZATION_PASSES_KEY,
+    CONF_EXPERIMENTAL_PASSES_KEY,
+    CONF_FAST_MATH_KEY,
+    CONF_DETERMINISTIC_REDUCTION_KEY,
+    CONF_GRAIN_SIZE_KEY,
+    CONF_SIR_OPT_KEY,
+    CONF_LLVM_OPTIMIZATION_LEVEL_KEY,
+    CONF_LLVM_UNROLLER_KEY,
+    CONF_LLVM_VECTORIZER_KEY,
+    CONF_LLVM_TARGET_PASSES_KEY,
+    CONF_LLVM_MODULE_OPTS_KEY,
+    CONF_LLVM_FUNC_OPTS_KEY,
+    CONF_LLVM_RUN_FUNC_NAME_KEY,
+    CONF_DUMP_CODE_KEY,
+    CONF_DUMP_CODE_DIR_KEY,
+    CONF_DUMP_CODE_FILENAME_KEY,
+    CONF_DUMP_CODE_FORMATS_KEY,
+    CONF_ENABLE_BOUNDS_CHECKS_KEY,
+    CONF_CHECKED_ARITHMETIC_KEY,
+    CONF_PROFILE_LOOPS_KEY,
+    CONF_BACKEND_KEY,
+    CONF_COMPILE_CACHE_KEY,
+    CONF_COMPILE_CACHE_SIZE_KEY,
+    CONF_COMPILE_THREADS_KEY,
+];
+
 /// Default memory limit.
 pub const CONF_MEMORY_LIMIT_DEFAULT: i64 = 1_000_000_000;
 
 /// Default number of threads.
 pub const CONF_THREADS_DEFAULT: i32 = 1;
 
+/// Default setting for whether to pin worker threads to cores.
+pub const CONF_THREAD_PINNING_DEFAULT: bool = false;
+
+/// Default setting for whether to place allocations on the allocating thread's NUMA node.
+pub const CONF_NUMA_LOCAL_DEFAULT: bool = false;
+
 /// Default setting for SIR optimization.
 pub const CONF_SIR_OPT_DEFAULT: bool = true;
 
 /// Default LLVM optimization level.
+///
+/// Levels range from 0 to 3, as in Clang.
 pub const CONF_LLVM_OPTIMIZATION_LEVEL_DEFAULT: u32 = 2;
 
 /// Default LLVM loop unroller setting.
@@ -161,9 +300,36 @@ pub const CONF_TRACE_RUN_DEFAULT: bool = false;
 /// Default setting for whether to enable experimental (unstable) optimizations.
 pub const CONF_EXPERIMENTAL_PASSES_DEFAULT: bool = false;
 
+/// Default setting for whether to enable fast math optimizations.
+pub const CONF_FAST_MATH_DEFAULT: bool = false;
+
+/// Default setting for whether to make floating point reductions reproducible.
+pub const CONF_DETERMINISTIC_REDUCTION_DEFAULT: bool = false;
+
 /// Default setting for whether to enable bounds checking.
 pub const CONF_ENABLE_BOUNDS_CHECKS_DEFAULT: bool = false;
 
+/// Default setting for whether to check integer arithmetic for overflow.
+pub const CONF_CHECKED_ARITHMETIC_DEFAULT: bool = false;
+
+/// Default setting for whether to profile loops.
+pub const CONF_PROFILE_LOOPS_DEFAULT: bool = false;
+
+/// Default backend.
+pub const CONF_BACKEND_DEFAULT: &str = "llvm";
+
+/// Default setting for whether to cache compiled modules.
+pub const CONF_COMPILE_CACHE_DEFAULT: bool = true;
+
+/// Default maximum number of cached modules.
+pub const CONF_COMPILE_CACHE_SIZE_DEFAULT: usize = 64;
+
+/// Default number of compiler threads.
+pub const CONF_COMPILE_THREADS_DEFAULT: usize = 1;
+
+/// Default filename prefix for dumped code.
+pub const CONF_DUMP_CODE_FILENAME_DEFAULT: &str = "code";
+
 /// Default directory for dumping code.
 pub const CONF_DUMP_CODE_DIR_DEFAULT: &str = ".";
 
@@ -173,10 +339,13 @@ pub const CONF_OPTIMIZATION_PASSES_DEFAULT: &[&str] = &[
     "inline-apply",
     "inline-let",
     "loop-fusion",
+    "dead-code",
     "unroll-static-loop",
     "infer-size",
+    "dict-impl",
     "algebraic",
     "inline-literals",
+    "loop-invariants",
     "cse",
     "short-circuit-booleans",
     "predicate",
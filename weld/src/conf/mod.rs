@@ -5,11 +5,14 @@ use super::WeldConf;
 use crate::optimizer::Pass;
 use crate::optimizer::OPTIMIZATION_PASSES;
 
-use crate::util::dump::{unique_filename, DumpCodeFormat};
+use crate::util::dump::DumpCodeFormat;
 
 use std::collections::HashSet;
+use std::fmt;
 use std::str::FromStr;
 
+use uuid::Uuid;
+
 pub mod constants;
 
 use self::constants::*;
@@ -29,8 +32,10 @@ lazy_static! {
 pub struct DumpCodeConfig {
     /// Toggles code dump.
     pub enabled: bool,
-    /// Filename of dumped code.
+    /// Filename prefix of dumped code.
     pub filename: String,
+    /// Identifies the compilation in the names of dumped files.
+    pub id: String,
     /// Directory of dumped code.
     pub directory: String,
     /// Formats to dump.
@@ -41,13 +46,23 @@ impl Default for DumpCodeConfig {
     fn default() -> Self {
         DumpCodeConfig {
             enabled: CONF_DUMP_CODE_DEFAULT,
-            filename: unique_filename(),
+            filename: CONF_DUMP_CODE_FILENAME_DEFAULT.to_string(),
+            id: Uuid::new_v4().to_hyphenated().to_string(),
             directory: CONF_DUMP_CODE_DIR_DEFAULT.to_string(),
             formats: DumpCodeFormat::all().into_iter().collect::<HashSet<_>>(),
         }
     }
 }
 
+/// The backend that runs loops.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Runs everything on the CPU.
+    Llvm,
+    /// Runs eligible loops on an NVIDIA GPU, and everything else on the CPU.
+    Nvvm,
+}
+
 /// LLVM configuration.
 #[derive(Clone, Debug)]
 pub struct LLVMConfig {
@@ -99,16 +114,38 @@ pub struct ParsedConf {
     pub memory_limit: i64,
     /// Worker threads to use on backends that support threading.
     pub threads: i32,
+    /// Pins worker threads to cores.
+    pub pin_threads: bool,
+    /// Places allocations on the NUMA node of the allocating thread.
+    pub numa_local: bool,
     /// Toggles tracing in generated code.
     pub trace_run: bool,
     /// Enables SIR optimizations.
     pub enable_sir_opt: bool,
     /// Enables experimental optimization passes over the Weld IR.
     pub enable_experimental_passes: bool,
+    /// Enables optimizations that treat floating point arithmetic as associative.
+    pub enable_fast_math: bool,
+    /// Reduces floating point mergers in an order that does not depend on the number of threads.
+    pub deterministic_reduction: bool,
+    /// The minimum number of iterations per task of a parallel loop, if not estimated per loop.
+    pub grain_size: Option<i64>,
     /// Optimization pipeline to use.
     pub optimization_passes: Vec<Pass>,
     /// Enables bounds checking in generated code.
     pub enable_bounds_checks: bool,
+    /// Enables overflow checks on integer arithmetic in generated code.
+    pub checked_arithmetic: bool,
+    /// Enables per-loop profiling counters in generated code.
+    pub profile_loops: bool,
+    /// The backend that runs loops.
+    pub backend: Backend,
+    /// Enables reusing cached compiled modules.
+    pub enable_cache: bool,
+    /// Maximum number of cached compiled modules.
+    pub cache_size: usize,
+    /// Number of threads that type and optimize the fields of a program returning a struct.
+    pub compile_threads: usize,
     /// LLVM options.
     pub llvm: LLVMConfig,
     /// Options for writing code to a file.
@@ -120,11 +157,22 @@ impl Default for ParsedConf {
         ParsedConf {
             memory_limit: CONF_MEMORY_LIMIT_DEFAULT,
             threads: CONF_THREADS_DEFAULT,
+            pin_threads: CONF_THREAD_PINNING_DEFAULT,
+            numa_local: CONF_NUMA_LOCAL_DEFAULT,
             trace_run: CONF_TRACE_RUN_DEFAULT,
             enable_sir_opt: CONF_SIR_OPT_DEFAULT,
             enable_experimental_passes: CONF_EXPERIMENTAL_PASSES_DEFAULT,
+            enable_fast_math: CONF_FAST_MATH_DEFAULT,
+            deterministic_reduction: CONF_DETERMINISTIC_REDUCTION_DEFAULT,
+            grain_size: None,
             optimization_passes: CONF_OPTIMIZATION_PASSES.clone(),
             enable_bounds_checks: CONF_ENABLE_BOUNDS_CHECKS_DEFAULT,
+            checked_arithmetic: CONF_CHECKED_ARITHMETIC_DEFAULT,
+            profile_loops: CONF_PROFILE_LOOPS_DEFAULT,
+            backend: parse_backend(CONF_BACKEND_DEFAULT).unwrap(),
+            enable_cache: CONF_COMPILE_CACHE_DEFAULT,
+            cache_size: CONF_COMPILE_CACHE_SIZE_DEFAULT,
+            compile_threads: CONF_COMPILE_THREADS_DEFAULT,
             llvm: LLVMConfig::default(),
             dump_code: DumpCodeConfig::default(),
         }
@@ -167,16 +215,43 @@ impl ConfigParser for WeldConf {
 }
 
 impl ParsedConf {
+    /// Parses and validates the options in `conf`.
+    ///
+    /// This returns an error if a value has the wrong type or is out of range, or if `conf` has a
+    /// key that starts with `weld.` but is not one of the `CONF_KEYS`.
     pub fn parse(conf: &WeldConf) -> WeldResult<ParsedConf> {
+        if let Some(key) = conf
+            .keys()
+            .find(|key| key.starts_with("weld.") && !CONF_KEYS.contains(key))
+        {
+            return compile_err!("Unknown configuration key '{}'", key);
+        }
+
         let conf = ParsedConf {
-            memory_limit: conf.parse_str(CONF_MEMORY_LIMIT_KEY, CONF_MEMORY_LIMIT_DEFAULT)?,
-            threads: conf.parse_str(CONF_THREADS_KEY, CONF_THREADS_DEFAULT)?,
+            memory_limit: conf.parse_map(
+                CONF_MEMORY_LIMIT_KEY,
+                CONF_MEMORY_LIMIT_DEFAULT,
+                |v| positive(CONF_MEMORY_LIMIT_KEY, v),
+            )?,
+            threads: conf.parse_map(CONF_THREADS_KEY, CONF_THREADS_DEFAULT, |v| {
+                positive(CONF_THREADS_KEY, v)
+            })?,
+            pin_threads: conf.parse_str(CONF_THREAD_PINNING_KEY, CONF_THREAD_PINNING_DEFAULT)?,
+            numa_local: conf.parse_str(CONF_NUMA_LOCAL_KEY, CONF_NUMA_LOCAL_DEFAULT)?,
             trace_run: conf.parse_str(CONF_TRACE_RUN_KEY, CONF_TRACE_RUN_DEFAULT)?,
             enable_sir_opt: conf.parse_str(CONF_SIR_OPT_KEY, CONF_SIR_OPT_DEFAULT)?,
             enable_experimental_passes: conf.parse_str(
                 CONF_EXPERIMENTAL_PASSES_KEY,
                 CONF_EXPERIMENTAL_PASSES_DEFAULT,
             )?,
+            enable_fast_math: conf.parse_str(CONF_FAST_MATH_KEY, CONF_FAST_MATH_DEFAULT)?,
+            deterministic_reduction: conf.parse_str(
+                CONF_DETERMINISTIC_REDUCTION_KEY,
+                CONF_DETERMINISTIC_REDUCTION_DEFAULT,
+            )?,
+            grain_size: conf.parse_map(CONF_GRAIN_SIZE_KEY, None, |v| {
+                positive(CONF_GRAIN_SIZE_KEY, v).map(Some)
+            })?,
             optimization_passes: conf.parse_map(
                 CONF_OPTIMIZATION_PASSES_KEY,
                 CONF_OPTIMIZATION_PASSES.clone(),
@@ -186,10 +261,35 @@ impl ParsedConf {
                 CONF_ENABLE_BOUNDS_CHECKS_KEY,
                 CONF_ENABLE_BOUNDS_CHECKS_DEFAULT,
             )?,
+            checked_arithmetic: conf
+                .parse_str(CONF_CHECKED_ARITHMETIC_KEY, CONF_CHECKED_ARITHMETIC_DEFAULT)?,
+            profile_loops: conf.parse_str(CONF_PROFILE_LOOPS_KEY, CONF_PROFILE_LOOPS_DEFAULT)?,
+            backend: parse_backend(
+                &conf.parse_str(CONF_BACKEND_KEY, CONF_BACKEND_DEFAULT.to_string())?,
+            )?,
+            enable_cache: conf.parse_str(CONF_COMPILE_CACHE_KEY, CONF_COMPILE_CACHE_DEFAULT)?,
+            cache_size: conf.parse_map(
+                CONF_COMPILE_CACHE_SIZE_KEY,
+                CONF_COMPILE_CACHE_SIZE_DEFAULT,
+                |v| positive(CONF_COMPILE_CACHE_SIZE_KEY, v),
+            )?,
+            compile_threads: conf.parse_map(
+                CONF_COMPILE_THREADS_KEY,
+                CONF_COMPILE_THREADS_DEFAULT,
+                |v| positive(CONF_COMPILE_THREADS_KEY, v),
+            )?,
             llvm: LLVMConfig {
-                opt_level: conf.parse_str(
+                opt_level: conf.parse_map(
                     CONF_LLVM_OPTIMIZATION_LEVEL_KEY,
                     CONF_LLVM_OPTIMIZATION_LEVEL_DEFAULT,
+                    |v| match v {
+                        0..=3 => Ok(v),
+                        _ => compile_err!(
+                            "Invalid configuration value '{}' for '{}': expected 0 to 3",
+                            v,
+                            CONF_LLVM_OPTIMIZATION_LEVEL_KEY
+                        ),
+                    },
                 )?,
                 llvm_unroller: conf
                     .parse_str(CONF_LLVM_UNROLLER_KEY, CONF_LLVM_UNROLLER_DEFAULT)?,
@@ -208,7 +308,11 @@ impl ParsedConf {
             },
             dump_code: DumpCodeConfig {
                 enabled: conf.parse_str(CONF_DUMP_CODE_KEY, CONF_DUMP_CODE_DEFAULT)?,
-                filename: conf.parse_str(CONF_DUMP_CODE_FILENAME_KEY, unique_filename())?,
+                filename: conf.parse_str(
+                    CONF_DUMP_CODE_FILENAME_KEY,
+                    CONF_DUMP_CODE_FILENAME_DEFAULT.to_string(),
+                )?,
+                id: Uuid::new_v4().to_hyphenated().to_string(),
                 directory: conf.parse_str(
                     CONF_DUMP_CODE_DIR_KEY,
                     CONF_DUMP_CODE_DIR_DEFAULT.to_string(),
@@ -224,6 +328,19 @@ impl ParsedConf {
     }
 }
 
+/// Checks that the value `v` of `key` is positive.
+fn positive<T: PartialOrd + Default + fmt::Display>(key: &str, v: T) -> WeldResult<T> {
+    if v > T::default() {
+        Ok(v)
+    } else {
+        compile_err!(
+            "Invalid configuration value '{}' for '{}': expected a positive number",
+            v,
+            key
+        )
+    }
+}
+
 /// Parses a comma separated list of formats.
 fn parse_dump_code_formats(s: String) -> WeldResult<HashSet<DumpCodeFormat>> {
     use crate::util::dump::DumpCodeFormat::*;
@@ -231,6 +348,7 @@ fn parse_dump_code_formats(s: String) -> WeldResult<HashSet<DumpCodeFormat>> {
         .map(|s| match s.to_lowercase().as_ref() {
             "weld" => Ok(Weld),
             "weldopt" => Ok(WeldOpt),
+            "weldpasses" => Ok(WeldPasses),
             "llvm" => Ok(LLVM),
             "llvmopt" => Ok(LLVMOpt),
             "assembly" => Ok(Assembly),
@@ -240,25 +358,139 @@ fn parse_dump_code_formats(s: String) -> WeldResult<HashSet<DumpCodeFormat>> {
         .collect::<WeldResult<HashSet<DumpCodeFormat>>>()
 }
 
+/// Parses the name of a backend.
+fn parse_backend(s: &str) -> WeldResult<Backend> {
+    match s.to_lowercase().as_ref() {
+        "llvm" => Ok(Backend::Llvm),
+        "nvvm" => Ok(Backend::Nvvm),
+        other => compile_err!("Unknown backend '{}'", other),
+    }
+}
+
+/// Passes that run before the requested optimization passes.
+///
+/// These lower expressions such as zips outside of a for loop, so they run even if no passes are
+/// requested.
+///
+/// TODO: These shouldn't be passes, since things break if we don't run them...
+const MANDATORY_PASSES: &[&str] = &["inline-zip", "inline-let", "inline-apply"];
+
 /// Parse a list of optimization passes.
+///
+/// The list is either a comma-separated list of pass names, which replaces the default passes, or
+/// a list of changes to the default passes: `-name` removes a pass and `+name` appends one.
 fn parse_passes(s: String) -> WeldResult<Vec<Pass>> {
+    // Insert mandatory passes to the beginning.
+    let mut result: Vec<_> = MANDATORY_PASSES
+        .iter()
+        .map(|name| OPTIMIZATION_PASSES.get(name).unwrap().clone())
+        .collect();
+
     if s.is_empty() {
-        return Ok(vec![]); // Special case because split() creates an empty piece here
+        return Ok(result); // Special case because split() creates an empty piece here
     }
-    let mut result = vec![];
 
-    // Insert mandatory passes to the beginning.
-    //
-    // TODO: These shouldn't be passes, since things break if we don't run them...
-    result.push(OPTIMIZATION_PASSES.get("inline-zip").unwrap().clone());
-    result.push(OPTIMIZATION_PASSES.get("inline-let").unwrap().clone());
-    result.push(OPTIMIZATION_PASSES.get("inline-apply").unwrap().clone());
-
-    for piece in s.split(',') {
-        match OPTIMIZATION_PASSES.get(piece) {
+    let pieces: Vec<_> = s.split(',').map(str::trim).collect();
+    let is_change = |piece: &&str| piece.starts_with('+') || piece.starts_with('-');
+    let names = if pieces.iter().all(is_change) {
+        let mut names: Vec<_> = CONF_OPTIMIZATION_PASSES_DEFAULT.to_vec();
+        for piece in pieces {
+            let name = &piece[1..];
+            if !OPTIMIZATION_PASSES.contains_key(name) {
+                return compile_err!("Unknown optimization pass: {}", name);
+            }
+            names.retain(|n| *n != name);
+            if piece.starts_with('+') {
+                names.push(name);
+            }
+        }
+        names
+    } else if pieces.iter().any(is_change) {
+        return compile_err!(
+            "Cannot mix pass names with changes to the default passes: {}",
+            s
+        );
+    } else {
+        pieces
+    };
+
+    for name in names {
+        match OPTIMIZATION_PASSES.get(name) {
             Some(pass) => result.push(pass.clone()),
-            None => return compile_err!("Unknown optimization pass: {}", piece),
+            None => return compile_err!("Unknown optimization pass: {}", name),
         }
     }
     Ok(result)
 }
+
+#[test]
+fn optimization_pass_lists() {
+    // Returns the requested passes, which follow the mandatory ones.
+    let names = |s: &str| -> Vec<String> {
+        let mut names: Vec<_> = parse_passes(s.to_string())
+            .unwrap()
+            .iter()
+            .map(|p| p.pass_name())
+            .collect();
+        let requested = names.split_off(MANDATORY_PASSES.len());
+        assert_eq!(names, MANDATORY_PASSES);
+        requested
+    };
+    assert_eq!(names("loop-fusion, cse"), ["loop-fusion", "cse"]);
+    assert!(names("").is_empty());
+
+    let defaults = CONF_OPTIMIZATION_PASSES_DEFAULT;
+    let without_vectorize = names("-vectorize,-predicate");
+    assert_eq!(without_vectorize, &defaults[..defaults.len() - 2]);
+    assert_eq!(names("-cse,+cse").last().unwrap(), "cse");
+    assert_eq!(names("+unroll-structs").len(), defaults.len() + 1);
+
+    assert!(parse_passes("vectorize,-cse".to_string()).is_err());
+    assert!(parse_passes("-fuse".to_string()).is_err());
+    assert!(parse_passes("fuse".to_string()).is_err());
+}
+
+#[test]
+fn validate_conf() {
+    let parse = |key: &str, value: &str| {
+        let mut conf = WeldConf::new();
+        conf.set(key, value);
+        ParsedConf::parse(&conf)
+    };
+    assert_eq!(parse(CONF_THREADS_KEY, "4").unwrap().threads, 4);
+    assert!(parse(CONF_THREAD_PINNING_KEY, "true").unwrap().pin_threads);
+    assert_eq!(
+        parse(CONF_COMPILE_THREADS_KEY, "8")
+            .unwrap()
+            .compile_threads,
+        8
+    );
+    assert_eq!(
+        parse(CONF_GRAIN_SIZE_KEY, "256").unwrap().grain_size,
+        Some(256)
+    );
+    assert_eq!(
+        parse(CONF_LLVM_OPTIMIZATION_LEVEL_KEY, "3")
+            .unwrap()
+            .llvm
+            .opt_level,
+        3
+    );
+    assert!(parse("host.option", "1").is_ok());
+
+    assert!(parse(CONF_THREADS_KEY, "0").is_err());
+    assert!(parse(CONF_NUMA_LOCAL_KEY, "yes").is_err());
+    assert!(parse(CONF_GRAIN_SIZE_KEY, "0").is_err());
+    assert!(parse(CONF_COMPILE_THREADS_KEY, "0").is_err());
+    assert!(parse(CONF_MEMORY_LIMIT_KEY, "-1").is_err());
+    assert!(parse(CONF_LLVM_OPTIMIZATION_LEVEL_KEY, "4").is_err());
+    assert!(parse(CONF_FAST_MATH_KEY, "yes").is_err());
+    assert_eq!(
+        parse(CONF_BACKEND_KEY, "NVVM").unwrap().backend,
+        Backend::Nvvm
+    );
+    assert!(parse(CONF_BACKEND_KEY, "cuda").is_err());
+
+    let err = parse("weld.thread", "4").unwrap_err();
+    assert_eq!(err.to_string(), "Unknown configuration key 'weld.thread'");
+}
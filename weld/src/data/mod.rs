@@ -1,7 +1,8 @@
 //! Structures that can be passed into Weld.
 //!
 //! This currently defines the type layout specified for the single threaded backend. In general,
-//! type layouts vary from backend to backend especially for builders.
+//! type layouts vary from backend to backend especially for builders. The `runtime::layout` module
+//! describes the layout of every type and provides helpers to build inputs without these structs.
 //!
 //! # Primitives
 //!
@@ -1,6 +1,8 @@
 use std::error;
 use std::fmt;
 
+use crate::ast::Span;
+
 /// Internal macro for creating a compile error.
 macro_rules! compile_err {
     ( $($arg:tt)* ) => ({
@@ -18,24 +20,44 @@ macro_rules! nonfatal {
 }
 
 /// A compilation error produced by Weld.
+///
+/// Errors raised while parsing or type checking carry the span of the program text that caused
+/// them, if it is known.
 #[derive(Debug, Clone)]
-pub struct WeldCompileError(String);
+pub struct WeldCompileError {
+    message: String,
+    span: Option<Span>,
+}
 
 impl WeldCompileError {
     pub fn new<T: Into<String>>(description: T) -> WeldCompileError {
-        WeldCompileError(description.into())
+        WeldCompileError {
+            message: description.into(),
+            span: None,
+        }
+    }
+
+    /// Returns this error with its span set to `span`.
+    pub fn with_span(mut self, span: Option<Span>) -> WeldCompileError {
+        self.span = span;
+        self
+    }
+
+    /// Returns the span of the program text that caused this error.
+    pub fn span(&self) -> Option<Span> {
+        self.span
     }
 }
 
 impl fmt::Display for WeldCompileError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.message)
     }
 }
 
 impl error::Error for WeldCompileError {
     fn description(&self) -> &str {
-        &self.0
+        &self.message
     }
 
     fn cause(&self) -> Option<&dyn error::Error> {
@@ -45,7 +67,7 @@ impl error::Error for WeldCompileError {
 
 impl From<String> for WeldCompileError {
     fn from(string: String) -> WeldCompileError {
-        WeldCompileError(string)
+        WeldCompileError::new(string)
     }
 }
 
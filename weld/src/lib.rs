@@ -125,8 +125,6 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
-use chrono;
-use env_logger;
 use fnv;
 use libc;
 use time;
@@ -134,12 +132,15 @@ use time;
 use self::time::PreciseTime;
 
 use std::default::Default;
-use std::error::Error;
 use std::ffi::{CStr, CString};
 use std::fmt;
 
 use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use uuid::Uuid;
 
@@ -182,13 +183,19 @@ pub use crate::conf::constants::*;
 #[cfg(test)]
 mod tests;
 
-use crate::conf::ParsedConf;
+use crate::conf::{Backend, ParsedConf};
 use crate::runtime::WeldRuntimeContext;
+use crate::util::cache::{CacheKey, ModuleCache};
 use crate::util::dump::{write_code, DumpCodeFormat};
-use crate::util::stats::CompilationStats;
 
 // Error codes are exposed publicly.
 pub use crate::runtime::WeldRuntimeErrno;
+// Hosts implement chunk sources to provide chunked loop input.
+pub use crate::runtime::ChunkSource;
+// Compile times are exposed publicly.
+pub use crate::util::stats::CompilationStats;
+// Loop profiles are exposed publicly.
+pub use crate::codegen::LoopProfile;
 
 /// A wrapper for a C pointer.
 pub type Data = *const libc::c_void;
@@ -204,6 +211,7 @@ pub type RunId = i64;
 pub struct WeldError {
     message: CString,
     code: WeldRuntimeErrno,
+    span: Option<ast::Span>,
 }
 
 /// A `Result` that uses `WeldError`.
@@ -251,7 +259,9 @@ impl WeldContext {
         let threads = conf.threads;
         let mem_limit = conf.memory_limit;
 
-        let run = WeldRuntimeContext::new(threads as i32, mem_limit);
+        let mut run = WeldRuntimeContext::new(threads, mem_limit);
+        run.set_thread_pinning(conf.pin_threads);
+        run.set_numa_local(conf.numa_local);
         Ok(WeldContext {
             context: Rc::new(RefCell::new(run)),
         })
@@ -289,6 +299,73 @@ impl WeldContext {
     pub fn memory_limit(&self) -> i64 {
         self.context.borrow().memory_limit()
     }
+
+    /// Adds a source of chunked input to this context and returns its handle.
+    ///
+    /// A program iterates over the source with `chunkiter[T](handle)`, where the handle is usually
+    /// passed as an `i64` argument. The loop requests chunks from the source while it runs, so the
+    /// host can load input (e.g., from a file) on demand instead of passing the entire input
+    /// vector up front. Sources stay in the context until `clear_sources` is called.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use weld::{ChunkSource, WeldConf, WeldContext};
+    ///
+    /// /// Provides the chunks of a vector of buffers in order.
+    /// struct Chunks(Vec<Vec<u8>>, usize);
+    ///
+    /// impl ChunkSource for Chunks {
+    ///     fn next_chunk(&mut self) -> Option<&[u8]> {
+    ///         self.1 += 1;
+    ///         self.0.get(self.1 - 1).map(|chunk| chunk.as_slice())
+    ///     }
+    /// }
+    ///
+    /// let mut context = WeldContext::new(&WeldConf::new()).unwrap();
+    /// let handle = context.add_source(Box::new(Chunks(vec![vec![1, 2], vec![3]], 0)));
+    /// assert_eq!(handle, 0);
+    /// ```
+    pub fn add_source(&mut self, source: Box<dyn ChunkSource>) -> i64 {
+        self.context.borrow_mut().add_source(source)
+    }
+
+    /// Removes the chunk sources added to this context.
+    ///
+    /// Handles of removed sources may be returned again by `add_source`.
+    pub fn clear_sources(&mut self) {
+        self.context.borrow_mut().clear_sources()
+    }
+
+    /// Frees the values returned by earlier runs in this context, keeping their memory for later
+    /// runs.
+    ///
+    /// The context keeps freed memory in an arena, and later runs take blocks from the arena when
+    /// they allocate the same sizes. Running a module repeatedly on inputs of the same shape (e.g.,
+    /// batches in a stream) and resetting the context between runs thus avoids most calls to the
+    /// system allocator. Memory in the arena counts towards the memory limit, and is returned to
+    /// the system if a run needs it for allocations of other sizes.
+    ///
+    /// The arena only reuses memory: runs in the same context do not share results, so each run
+    /// computes its values from its own inputs.
+    ///
+    /// # Safety
+    ///
+    /// Values returned by earlier runs in this context, and any pointers into them, must not be
+    /// used after this call.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use weld::{WeldConf, WeldContext};
+    ///
+    /// let mut context = WeldContext::new(&WeldConf::new()).unwrap();
+    /// unsafe { context.reset() };
+    /// assert_eq!(context.memory_usage(), 0);
+    /// ```
+    pub unsafe fn reset(&mut self) {
+        self.context.borrow_mut().free_all()
+    }
 }
 
 impl WeldError {
@@ -305,6 +382,7 @@ impl WeldError {
         WeldError {
             message: CString::new(message).unwrap(),
             code,
+            span: None,
         }
     }
 
@@ -322,6 +400,7 @@ impl WeldError {
         WeldError {
             message: CString::new(message).unwrap(),
             code: WeldRuntimeErrno::Unknown,
+            span: None,
         }
     }
 
@@ -369,6 +448,34 @@ impl WeldError {
     pub fn message(&self) -> &CStr {
         self.message.as_ref()
     }
+
+    /// Returns the span of the program text that caused this error.
+    ///
+    /// Only errors with the codes `ParseError` and `TypeError` have spans, as do `IntegerOverflow`
+    /// errors if the location of the operator that overflowed is known.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use weld::*;
+    ///
+    /// let err = WeldModule::compile("|x:i32|\n  x + 1.0", &WeldConf::new()).unwrap_err();
+    /// assert_eq!(err.code(), WeldRuntimeErrno::TypeError);
+    ///
+    /// let span = err.span().unwrap();
+    /// assert_eq!((span.line, span.column), (2, 3));
+    /// ```
+    pub fn span(&self) -> Option<ast::Span> {
+        self.span
+    }
+
+    /// Converts an error from a particular compilation stage into an error with the given code.
+    fn from_compile_error(err: error::WeldCompileError, code: WeldRuntimeErrno) -> WeldError {
+        WeldError {
+            span: err.span(),
+            ..WeldError::new(err.to_string(), code)
+        }
+    }
 }
 
 impl Default for WeldError {
@@ -376,6 +483,7 @@ impl Default for WeldError {
         WeldError {
             message: CString::new("").unwrap(),
             code: WeldRuntimeErrno::Success,
+            span: None,
         }
     }
 }
@@ -383,10 +491,7 @@ impl Default for WeldError {
 // Conversion from a compilation error to an external WeldError.
 impl From<error::WeldCompileError> for WeldError {
     fn from(err: error::WeldCompileError) -> WeldError {
-        WeldError::new(
-            CString::new(err.description()).unwrap(),
-            WeldRuntimeErrno::CompileError,
-        )
+        WeldError::from_compile_error(err, WeldRuntimeErrno::CompileError)
     }
 }
 
@@ -561,10 +666,32 @@ impl WeldConf {
     pub fn get(&self, key: &str) -> Option<&CString> {
         self.dict.get(key)
     }
+
+    /// Returns the keys set in this configuration, in no particular order.
+    ///
+    /// # Examples
+    ///
+    ///```rust
+    /// use weld::WeldConf;
+    ///
+    /// let mut conf = WeldConf::new();
+    /// conf.set("weld.memory.limit", "1024");
+    ///
+    /// let keys: Vec<_> = conf.keys().collect();
+    /// assert_eq!(keys, ["weld.memory.limit"]);
+    /// ```
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.dict.keys().map(String::as_str)
+    }
+}
+
+lazy_static! {
+    /// Modules compiled in this process, shared by all threads.
+    static ref MODULE_CACHE: Mutex<ModuleCache<WeldModule>> = Mutex::new(ModuleCache::new());
 }
 
 /// A compiled runnable Weld module.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct WeldModule {
     /// A compiled, runnable module.
     llvm_module: codegen::CompiledModule,
@@ -574,6 +701,8 @@ pub struct WeldModule {
     return_type: ast::Type,
     /// A unique identifier for a module.
     module_id: Uuid,
+    /// The time spent compiling this module.
+    stats: Arc<CompilationStats>,
 }
 
 impl WeldModule {
@@ -587,10 +716,22 @@ impl WeldModule {
     ///
     /// # Errors
     ///
-    /// * If the provided code does not compile (e.g., due to a syntax error), a compile error is
-    /// returned.
+    /// * If the provided code cannot be parsed, an error with the code `ParseError` is returned.
+    /// * If type inference fails on the provided code, an error with the code `TypeError` is
+    ///   returned.
+    /// * If the provided code does not compile for any other reason, a compile error is
+    ///   returned.
     /// * If the provided configuration has an invalid configuration option, a compile
-    /// error is returned.
+    ///   error is returned.
+    ///
+    /// # Caching
+    ///
+    /// Compiled modules are cached in the current process. Compiling a program that only differs
+    /// from a cached one in formatting, comments, or macro use, with the same configuration,
+    /// returns the cached module without invoking the compiler. Configuration options that do not
+    /// affect compilation, such as `weld.memory.limit`, are ignored when looking up a module.
+    /// Programs that call UDFs and compilations that dump code are never cached. The cache is
+    /// configured with `weld.compile.cache` and `weld.compile.cacheSize`.
     ///
     /// # Examples
     ///
@@ -617,7 +758,7 @@ impl WeldModule {
     /// assert!(module.is_err());
     ///
     /// let err = module.unwrap_err();
-    /// assert_eq!(err.code(), WeldRuntimeErrno::CompileError);
+    /// assert_eq!(err.code(), WeldRuntimeErrno::TypeError);
     ///
     /// conf.set("weld.memory.limit", "invalidLimit");
     /// let mut module = WeldModule::compile("|| 1", conf);
@@ -627,30 +768,88 @@ impl WeldModule {
     /// assert_eq!(err.code(), WeldRuntimeErrno::CompileError);
     /// ```
     pub fn compile<S: AsRef<str>>(code: S, conf: &WeldConf) -> WeldResult<WeldModule> {
-        use self::ast::*;
-
         let e2e_start = PreciseTime::now();
         let mut stats = CompilationStats::new();
+
+        // Parse the string into a Weld AST.
+        let start = PreciseTime::now();
+        let program = syntax::parser::parse_program(code.as_ref())
+            .map_err(|e| WeldError::from_compile_error(e, WeldRuntimeErrno::ParseError))?;
+        let end = PreciseTime::now();
+        stats
+            .weld_times
+            .push(("Parsing".to_string(), start.to(end)));
+
+        // Type inference, printing, conversion to SIR, and dropping the program recurse once per
+        // level of nesting, so deeply nested programs (e.g., generated programs with thousands of
+        // nested `Let`s) are compiled on a thread with a stack sized for the program. The program
+        // is moved to that thread so that it is also dropped there.
+        let stack_size = util::compile_stack_size(program.body.depth());
+        if stack_size <= util::MIN_COMPILE_STACK {
+            return Self::compile_program(program, conf, stats, e2e_start);
+        }
+        thread::scope(|scope| {
+            let compiler = thread::Builder::new()
+                .name("weld-compile".to_string())
+                .stack_size(stack_size)
+                .spawn_scoped(scope, move || {
+                    Self::compile_program(program, conf, stats, e2e_start)
+                });
+            match compiler {
+                Ok(handle) => handle
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload)),
+                Err(err) => Err(WeldError::new(
+                    format!("Could not start a compiler thread: {}", err),
+                    WeldRuntimeErrno::CompileError,
+                )),
+            }
+        })
+    }
+
+    /// Compiles a parsed Weld program on the calling thread.
+    fn compile_program(
+        program: syntax::program::Program,
+        conf: &WeldConf,
+        mut stats: CompilationStats,
+        e2e_start: PreciseTime,
+    ) -> WeldResult<WeldModule> {
+        use self::ast::*;
+
+        let weld_conf = conf;
         let conf = &mut ParsedConf::parse(conf)?;
-        let code = code.as_ref();
 
         let uuid = Uuid::new_v4();
+        conf.dump_code.id = uuid.to_hyphenated().to_string();
 
         // Configuration.
         debug!("{:?}", conf);
 
-        // Parse the string into a Weld AST.
+        // Substitute macros and type aliases in the parsed program.
         let start = PreciseTime::now();
-        let program = syntax::parser::parse_program(code)?;
+        let mut expr = syntax::macro_processor::process_program(&program)?;
         let end = PreciseTime::now();
         stats
             .weld_times
-            .push(("Parsing".to_string(), start.to(end)));
-
-        // Substitute macros and type aliases in the parsed program.
-        let mut expr = syntax::macro_processor::process_program(&program)?;
+            .push(("Macro Substitution".to_string(), start.to(end)));
         debug!("After macro substitution:\n{}\n", expr.pretty_print());
 
+        // Reuse a cached module if possible. Dumping code requires running the compiler.
+        let cache_key = if conf.enable_cache && !conf.dump_code.enabled {
+            CacheKey::new(&expr, weld_conf)
+        } else {
+            None
+        };
+        if let Some(ref key) = cache_key {
+            if let Some(module) = MODULE_CACHE.lock().unwrap().get(key) {
+                info!(
+                    "Reusing cached module with UUID={}",
+                    module.module_id.to_hyphenated()
+                );
+                return Ok(module);
+            }
+        }
+
         let unoptimized_code = expr.pretty_print();
         info!(
             "Compiling module with UUID={}, code\n{}",
@@ -671,22 +870,50 @@ impl WeldModule {
         let end = PreciseTime::now();
         let mut uniquify_dur = start.to(end);
 
+        // Programs that return a struct with several fields can be typed and optimized one field
+        // at a time, compiling the fields in parallel.
+        let mut fields = if conf.compile_threads > 1 {
+            util::fields::split(&expr)
+        } else {
+            vec![]
+        };
+        let split = !fields.is_empty();
+        let exprs = if split {
+            &mut fields[..]
+        } else {
+            slice::from_mut(&mut expr)
+        };
+
         // Infer types of expressions.
         let start = PreciseTime::now();
-        expr.infer_types()?;
+        util::fields::try_for_each(exprs, conf.compile_threads, |e| e.infer_types())
+            .map_err(|e| WeldError::from_compile_error(e, WeldRuntimeErrno::TypeError))?;
         let end = PreciseTime::now();
         stats
             .weld_times
             .push(("Type Inference".to_string(), start.to(end)));
-        debug!("After type inference:\n{}\n", expr.pretty_print());
+        debug!(
+            "After type inference:\n{}\n",
+            util::fields::pretty_print(exprs)?
+        );
+
+        // Reject programs that consume a builder more than once, which codegen cannot handle.
+        exprs.iter().try_for_each(|e| e.check_linearity())?;
+
+        // Apply optimization passes. GPU kernels run one iteration per thread, so loops that may
+        // run on the GPU are not vectorized.
+        let mut passes = conf.optimization_passes.clone();
+        if conf.backend == Backend::Nvvm {
+            passes.retain(|pass| pass.pass_name() != "vectorize");
+        }
+        optimizer::apply_passes(exprs, &passes, &mut stats, conf)?;
+        if split {
+            util::fields::join(&mut expr, fields)?;
+        }
 
-        // Apply optimization passes.
-        optimizer::apply_passes(
-            &mut expr,
-            &conf.optimization_passes,
-            &mut stats,
-            conf.enable_experimental_passes,
-        )?;
+        // `&&` and `||` must not evaluate their right operand unless needed, regardless of which
+        // passes ran.
+        optimizer::transforms::short_circuit::lower_short_circuit(&mut expr);
 
         // Uniquify again.
         let start = PreciseTime::now();
@@ -744,21 +971,113 @@ impl WeldModule {
             };
 
         let end = PreciseTime::now();
-        let duration = e2e_start.to(end);
-        let us = duration.num_microseconds().unwrap_or(std::i64::MAX);
-        let e2e_ms: f64 = us as f64 / 1000.0;
+        stats.total_time = e2e_start.to(end);
         info!(
-            "Compiled module with UUID={} in {} ms",
+            "Compiled module with UUID={} in {}",
             uuid.to_hyphenated(),
-            e2e_ms
+            stats.summary()
         );
 
-        Ok(WeldModule {
+        let module = WeldModule {
             llvm_module: compiled_module,
             param_types,
             return_type,
             module_id: uuid,
-        })
+            stats: Arc::new(stats),
+        };
+        if let Some(key) = cache_key {
+            MODULE_CACHE
+                .lock()
+                .unwrap()
+                .insert(key, module.clone(), conf.cache_size);
+        }
+        Ok(module)
+    }
+
+    /// Removes all modules from the compiled module cache.
+    ///
+    /// Modules that were already returned by `compile` remain valid.
+    pub fn clear_cache() {
+        MODULE_CACHE.lock().unwrap().clear();
+    }
+
+    /// Returns the time spent compiling this module.
+    ///
+    /// The statistics break compilation down into stages, such as parsing, type inference, each
+    /// optimization pass, LLVM optimization, and JIT compilation. A module returned from the
+    /// compiled module cache has the statistics of the compilation that produced it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use weld::*;
+    ///
+    /// let conf = &WeldConf::new();
+    /// let module = WeldModule::compile("|x:i32| x + 1", conf).unwrap();
+    ///
+    /// let stats = module.compilation_stats();
+    /// let (stage, _) = stats.weld_times()[0];
+    /// assert_eq!(stage, "Parsing");
+    /// assert!(stats.total_time() > std::time::Duration::from_millis(0));
+    /// ```
+    pub fn compilation_stats(&self) -> &CompilationStats {
+        &self.stats
+    }
+
+    /// Returns the profiling counters of each loop in this module.
+    ///
+    /// Loops are only profiled if the module was compiled with `weld.compile.profileLoops`
+    /// enabled: otherwise, this returns an empty list. The counters accumulate over every run of
+    /// the module, including concurrent runs, until `reset_loop_profile` is called. Since modules
+    /// returned from the compiled module cache share their code, they also share counters.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use weld::*;
+    ///
+    /// let conf = &mut WeldConf::new();
+    /// conf.set("weld.compile.profileLoops", "true");
+    /// let code = "|v:vec[i32]| result(for(v, merger[i32,+], |b,i,e| merge(b,e)))";
+    /// let module = WeldModule::compile(code, conf).unwrap();
+    ///
+    /// // Run the module...
+    ///
+    /// // Find the loop that took the longest.
+    /// let profile = module.loop_profile();
+    /// if let Some(hot) = profile.iter().max_by_key(|p| p.time) {
+    ///     println!("{:?} ran {} iterations in {:?}", hot.span, hot.iterations, hot.time);
+    /// }
+    /// ```
+    pub fn loop_profile(&self) -> Vec<LoopProfile> {
+        self.llvm_module.loop_profile()
+    }
+
+    /// Resets the profiling counters of each loop in this module to zero.
+    pub fn reset_loop_profile(&self) {
+        self.llvm_module.reset_loop_profile()
+    }
+
+    /// Returns a unique identifier for this module.
+    ///
+    /// Modules returned from the compiled module cache share the identifier of the cached module.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use weld::*;
+    ///
+    /// let conf = &mut WeldConf::new();
+    /// let first = WeldModule::compile("|x:i32| x + 1", conf).unwrap();
+    /// let second = WeldModule::compile("|x:i32|\n  x + 1", conf).unwrap();
+    /// assert_eq!(first.id(), second.id());
+    ///
+    /// conf.set("weld.compile.cache", "false");
+    /// let third = WeldModule::compile("|x:i32| x + 1", conf).unwrap();
+    /// assert_ne!(first.id(), third.id());
+    /// ```
+    pub fn id(&self) -> String {
+        self.module_id.to_hyphenated().to_string()
     }
 
     /// Run this `WeldModule` with a context and argument.
@@ -793,14 +1112,15 @@ impl WeldModule {
     /// # Errors
     ///
     /// This method may return any of the errors specified in `WeldRuntimeErrno`, if a runtime
-    /// error occurs during the execution of the program. Currently, the implementation panics if a
-    /// runtime error is thrown.
+    /// error occurs during the execution of the program. For example, a run that allocates more
+    /// than the context's `weld.memory.limit` fails with `OutOfMemory`. Memory allocated by a
+    /// failed run stays in the context, and counts towards its `memory_usage`, until the context
+    /// is dropped or reset with `WeldContext::reset`.
     ///
     /// # Panics
     ///
-    /// The current implementation panics whenever the runtime throws an error. This function will
-    /// also panic if the same context is passed to `run` at once (this is possible if, e.g., if a
-    /// context is cloned).
+    /// This function panics if the same context is passed to `run` at once (this is possible if,
+    /// e.g., if a context is cloned).
     ///
     /// # Examples
     ///
@@ -837,9 +1157,11 @@ impl WeldModule {
         let nworkers = context.context.borrow().threads();
         let mem_limit = context.context.borrow().memory_limit();
 
-        // Borrow the inner context mutably since we pass a mutable pointer to it to the compiled
-        // module. This enforces the single-mutable-borrow rule manually for contexts.
-        let mut context_borrowed = context.context.borrow_mut();
+        context.context.borrow_mut().reset_errno();
+        // The compiled module only accesses the context through shared references, so hold a
+        // shared borrow while it runs. This keeps the host from mutating the context (e.g.,
+        // freeing its values) until the run finishes.
+        let context_borrowed = context.context.borrow();
 
         let (raw, result) = {
             // This is the required input format of data passed into a compiled module.
@@ -851,14 +1173,35 @@ impl WeldModule {
             });
             let ptr = Box::into_raw(input) as i64;
 
-            // Runs the Weld program.
-            let raw = self.llvm_module.run(ptr) as *const codegen::WeldOutputArgs;
-            let result = (*raw).clone();
+            // Runs the Weld program. The program unwinds out of the generated code if it raises a
+            // runtime error.
+            let raw = panic::catch_unwind(AssertUnwindSafe(|| self.llvm_module.run(ptr)));
 
             // Free the boxed input.
             let _ = Box::from_raw(ptr as *mut codegen::WeldInputArgs);
 
-            (raw, result)
+            let raw = match raw {
+                Ok(raw) => raw as *const codegen::WeldOutputArgs,
+                Err(payload) => match payload.downcast_ref::<runtime::RunError>() {
+                    Some(&runtime::RunError(errno)) => {
+                        let span = context_borrowed
+                            .error_site()
+                            .and_then(|site| self.llvm_module.error_span(site));
+                        let message = match span {
+                            Some(span) => {
+                                format!("Weld program failed with error {:?} at {}", errno, span)
+                            }
+                            None => format!("Weld program failed with error {:?}", errno),
+                        };
+                        return Err(WeldError {
+                            span,
+                            ..WeldError::new(message, errno)
+                        });
+                    }
+                    None => panic::resume_unwind(payload),
+                },
+            };
+            (raw, (*raw).clone())
         };
 
         let value = WeldValue {
@@ -960,6 +1303,43 @@ impl From<log::LogLevelFilter> for WeldLogLevel {
     }
 }
 
+/// A function that receives log messages with their level.
+pub type WeldLogSink = Box<dyn Fn(WeldLogLevel, &str) + Send + Sync>;
+
+/// Sets a function that receives the messages logged by the compiler and runtime.
+///
+/// Embedding applications can use this to route Weld's logs into their own logging system. The
+/// sink receives each message at or above the level set by `set_log_level`, and may be called
+/// from any thread. Passing `None` writes messages to stderr again.
+///
+/// # Examples
+///
+/// ```rust
+/// use weld::*;
+/// use std::sync::{Arc, Mutex};
+///
+/// let messages = Arc::new(Mutex::new(vec![]));
+/// let sink = messages.clone();
+/// set_log_sink(Some(Box::new(move |level, message| {
+///     sink.lock().unwrap().push(format!("{}: {}", level, message));
+/// })));
+/// set_log_level(WeldLogLevel::Info);
+///
+/// let _ = WeldModule::compile("|x:i32| x + 1", &WeldConf::new());
+/// assert!(!messages.lock().unwrap().is_empty());
+///
+/// // Messages below the log level are dropped.
+/// set_log_level(WeldLogLevel::Off);
+/// messages.lock().unwrap().clear();
+/// let _ = WeldModule::compile("|x:i32| x + 1", &WeldConf::new());
+/// assert!(messages.lock().unwrap().is_empty());
+///
+/// set_log_sink(None);
+/// ```
+pub fn set_log_sink(sink: Option<WeldLogSink>) {
+    util::logging::set_sink(sink);
+}
+
 /// Load a dynamic library that a Weld program can access.
 ///
 /// The dynamic library is a C dynamic library identified by its filename.
@@ -967,32 +1347,26 @@ pub fn load_linked_library<S: AsRef<str>>(filename: S) -> WeldResult<()> {
     codegen::load_library(filename.as_ref()).map_err(WeldError::from)
 }
 
-/// Enables logging to stderr in Weld with the given log level.
+/// Register a C UDF that a Weld program can call with `cudf`.
 ///
-/// This function is ignored if it has already been called once, or if some other code in the
-/// process has initialized logging using Rust's `log` crate.
-pub fn set_log_level(level: WeldLogLevel) {
-    use crate::util::colors::Color::*;
-    use crate::util::colors::*;
-
-    let filter: log::LogLevelFilter = level.into();
-    let format = |rec: &log::LogRecord<'_>| {
-        let prefix = match rec.level() {
-            log::LogLevel::Error => format_color(Red, "error"),
-            log::LogLevel::Warn => format_color(Yellow, "warn"),
-            log::LogLevel::Info => format_color(Yellow, "info"),
-            log::LogLevel::Debug => format_color(Green, "debug"),
-            log::LogLevel::Trace => format_color(Green, "trace"),
-        };
-        let date = chrono::Local::now().format("%T%.3f");
-        format!("[{}] {}: {}", prefix, date, rec.args())
-    };
-
-    let mut builder = env_logger::LogBuilder::new();
-    builder.format(format);
-    builder.filter(None, filter);
-    builder.init().unwrap_or(());
+/// This makes functions defined in the calling process available to Weld without loading them
+/// from a dynamic library. `function` must point to a function with the UDF calling convention
+/// described in the language documentation.
+///
+/// # Safety
+///
+/// `function` must remain a valid function pointer for as long as programs that call it run.
+pub unsafe fn register_udf<S: AsRef<str>>(name: S, function: *mut libc::c_void) -> WeldResult<()> {
+    codegen::register_symbol(name.as_ref(), function).map_err(WeldError::from)
+}
 
+/// Sets the level of messages that the compiler and runtime log.
+///
+/// Messages are written to stderr unless a sink is set with `set_log_sink`. The level can be
+/// changed at any time. If some other code in the process has initialized logging using Rust's
+/// `log` crate, Weld's messages go to that logger instead.
+pub fn set_log_level(level: WeldLogLevel) {
+    util::logging::set_level(level);
     info!(
         "Weld Version {} (Build {})",
         VERSION.unwrap_or("unknown"),
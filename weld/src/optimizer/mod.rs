@@ -11,27 +11,59 @@ use time;
 use time::PreciseTime;
 
 use crate::ast::*;
+use crate::conf::ParsedConf;
 use crate::error::*;
+use crate::util::dump::write_pass_code;
+use crate::util::fields;
 use crate::util::stats::CompilationStats;
 
 pub use self::passes::*;
 
 mod passes;
+pub mod rules;
 pub mod transforms;
 
 /// Apply passes from a list until fix point.
+///
+/// `exprs` are independent programs, such as the fields of a program split with
+/// `util::fields::split`. Each pass runs on every program before the next pass starts, using up
+/// to `conf.compile_threads` threads.
 pub fn apply_passes(
-    expr: &mut Expr,
+    exprs: &mut [Expr],
     passes: &[Pass],
     stats: &mut CompilationStats,
-    use_experimental: bool,
+    conf: &ParsedConf,
 ) -> WeldResult<()> {
-    for pass in passes {
+    for (i, pass) in passes.iter().enumerate() {
         let start = PreciseTime::now();
-        pass.transform(expr, use_experimental)?;
+        fields::try_for_each(exprs, conf.compile_threads, |expr| {
+            pass.transform(
+                expr,
+                conf.enable_experimental_passes,
+                conf.enable_fast_math,
+                conf.checked_arithmetic,
+            )?;
+            // Catch passes that produce ill-typed programs before they reach code generation.
+            if cfg!(debug_assertions) {
+                if let Err(err) = expr.check_types() {
+                    return compile_err!(
+                        "{} pass produced an ill-typed program: {}",
+                        pass.pass_name(),
+                        err
+                    );
+                }
+            }
+            Ok(())
+        })?;
         let end = PreciseTime::now();
         stats.pass_times.push((pass.pass_name(), start.to(end)));
-        debug!("After {} pass:\n{}", pass.pass_name(), expr.pretty_print());
+        if log_enabled!(log::LogLevel::Debug) || conf.dump_code.enabled {
+            let code = fields::pretty_print(exprs)?;
+            debug!("After {} pass:\n{}", pass.pass_name(), code);
+            if conf.dump_code.enabled {
+                nonfatal!(write_pass_code(code, i, &pass.pass_name(), &conf.dump_code));
+            }
+        }
     }
     Ok(())
 }
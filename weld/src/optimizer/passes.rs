@@ -7,9 +7,12 @@ use std::fmt;
 use crate::ast::*;
 use crate::error::*;
 
+use super::transforms::adaptive;
 use super::transforms::algebraic;
 use super::transforms::cse;
+use super::transforms::dead_code;
 use super::transforms::inliner;
+use super::transforms::licm;
 use super::transforms::loop_fusion;
 use super::transforms::loop_fusion_2;
 use super::transforms::short_circuit;
@@ -26,11 +29,17 @@ pub type PassFn = fn(&mut Expr);
 pub struct Transformation {
     pub func: PassFn,
     pub experimental: bool,
+    pub fast_math: bool,
+    pub wrapping: bool,
 }
 
 impl fmt::Debug for Transformation {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Transformation(experimental={})", self.experimental)
+        write!(
+            f,
+            "Transformation(experimental={}, fast_math={}, wrapping={})",
+            self.experimental, self.fast_math, self.wrapping
+        )
     }
 }
 
@@ -39,6 +48,8 @@ impl Transformation {
         Transformation {
             func,
             experimental: false,
+            fast_math: false,
+            wrapping: false,
         }
     }
 
@@ -46,6 +57,31 @@ impl Transformation {
         Transformation {
             func,
             experimental: true,
+            fast_math: false,
+            wrapping: false,
+        }
+    }
+
+    /// Creates a transformation that only runs if fast math is enabled, since it may change the
+    /// result of floating point arithmetic.
+    pub fn new_fast_math(func: PassFn) -> Transformation {
+        Transformation {
+            func,
+            experimental: false,
+            fast_math: true,
+            wrapping: false,
+        }
+    }
+
+    /// Creates a transformation that only runs if integer arithmetic is not checked for overflow,
+    /// since it assumes that integer arithmetic wraps instead of failing. Such transformations
+    /// may change which operations overflow, or evaluate operations that would not otherwise run.
+    pub fn new_wrapping(func: PassFn) -> Transformation {
+        Transformation {
+            func,
+            experimental: false,
+            fast_math: false,
+            wrapping: true,
         }
     }
 }
@@ -64,7 +100,13 @@ impl Pass {
         }
     }
 
-    pub fn transform(&self, mut expr: &mut Expr, use_experimental: bool) -> WeldResult<()> {
+    pub fn transform(
+        &self,
+        mut expr: &mut Expr,
+        use_experimental: bool,
+        fast_math: bool,
+        checked_arithmetic: bool,
+    ) -> WeldResult<()> {
         let mut continue_pass = true;
         let mut before = expr.hash_ignoring_symbols()?;
         while continue_pass {
@@ -73,6 +115,12 @@ impl Pass {
                 if transform.experimental && !use_experimental {
                     continue;
                 }
+                if transform.fast_math && !fast_math {
+                    continue;
+                }
+                if transform.wrapping && checked_arithmetic {
+                    continue;
+                }
                 (transform.func)(&mut expr);
             }
             let after = expr.hash_ignoring_symbols()?;
@@ -93,30 +141,42 @@ lazy_static! {
         m.insert("inline-apply",
                  Pass::new(vec![Transformation::new(inliner::inline_apply)], "inline-apply"));
         m.insert("inline-let",
-                 Pass::new(vec![Transformation::new(inliner::inline_let)], "inline-let"));
+                 Pass::new(vec![Transformation::new(inliner::inline_let),
+                                Transformation::new(inliner::inline_trivial_let)],
+                 "inline-let"));
         m.insert("inline-zip",
                  Pass::new(vec![Transformation::new(inliner::inline_zips)], "inline-zip"));
         m.insert("loop-fusion",
-                 Pass::new(vec![Transformation::new(loop_fusion::fuse_loops_vertical),
+                 Pass::new(vec![Transformation::new(loop_fusion::fuse_reductions),
+                                Transformation::new(loop_fusion::fuse_loops_vertical),
                                 Transformation::new(loop_fusion_2::fuse_loops_2),
                                 Transformation::new(loop_fusion_2::move_merge_before_let),
                                 Transformation::new(inliner::inline_get_field),
                                 Transformation::new(inliner::inline_let),
                                 Transformation::new_experimental(loop_fusion_2::aggressive_inline_let),
-                                Transformation::new_experimental(loop_fusion_2::merge_makestruct_loops)],
+                                Transformation::new(loop_fusion_2::merge_makestruct_loops)],
                  "loop-fusion"));
+        m.insert("dead-code",
+                 Pass::new(vec![Transformation::new(dead_code::eliminate_dead_code)],
+                 "dead-code"));
         m.insert("unroll-static-loop",
                  Pass::new(vec![Transformation::new(unroller::unroll_static_loop)],
                  "unroll-static-loop"));
         m.insert("infer-size",
                  Pass::new(vec![Transformation::new(size_inference::infer_size)],
                  "infer-size"));
+        m.insert("dict-impl",
+                 Pass::new(vec![Transformation::new(adaptive::select_dict_impl)],
+                 "dict-impl"));
         m.insert(
             "algebraic",
             Pass::new(
                 vec![
-                    Transformation::new(algebraic::shift_work_to_constants),
+                    Transformation::new_wrapping(algebraic::shift_work_to_constants),
+                    Transformation::new_fast_math(algebraic::reassociate_floats),
                     Transformation::new(algebraic::eliminate_redundant_negation),
+                    Transformation::new(algebraic::fold_constants),
+                    Transformation::new_wrapping(algebraic::reduce_strength),
                 ],
                 "algebraic",
             ),
@@ -126,6 +186,9 @@ lazy_static! {
                                 Transformation::new(inliner::inline_cast),
                                 Transformation::new(inliner::simplify_branch_conditions)],
                  "inline-literals"));
+        m.insert("loop-invariants",
+                 Pass::new(vec![Transformation::new_wrapping(licm::hoist_loop_invariants)],
+                 "loop-invariants"));
         m.insert("cse",
                  // Calls inline_let internally.
                  Pass::new(vec![Transformation::new(cse::common_subexpression_elimination)],
@@ -137,11 +200,12 @@ lazy_static! {
                  Pass::new(vec![Transformation::new(short_circuit::short_circuit_booleans)],
                  "short-circuit-booleans"));
         m.insert("predicate",
-                 Pass::new(vec![Transformation::new(vectorizer::predicate_merge_expr),
+                 Pass::new(vec![Transformation::new_wrapping(vectorizer::predicate_merge_expr),
                            Transformation::new(vectorizer::predicate_simple_expr)],
                  "predicate"));
         m.insert("vectorize",
-                 Pass::new(vec![Transformation::new(vectorizer::vectorize)],
+                 Pass::new(vec![Transformation::new(vectorizer::vectorize),
+                                Transformation::new_fast_math(vectorizer::vectorize_fast_math)],
                  "vectorize"));
         m
     };
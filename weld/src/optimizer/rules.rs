@@ -0,0 +1,284 @@
+//! Declarative rewrite rules over the Weld AST.
+//!
+//! A `Rule` pairs a *pattern* with a *template*, both written in Weld syntax. Every identifier in
+//! the pattern is a pattern variable that matches any sub-expression; repeated variables must
+//! match equal sub-expressions. All other nodes must match the target structurally (e.g., a `+` in
+//! the pattern only matches an addition). The template is instantiated by replacing its
+//! identifiers with the sub-expressions bound in the pattern, and the new nodes are typed using
+//! local type inference. For example, the rule
+//!
+//! ```text
+//! Rule::new("!x == !y", "x == y")
+//! ```
+//!
+//! removes the negations from both sides of an equality.
+//!
+//! Rules that need more than a structural match can attach a condition over the bound variables
+//! with `Rule::when`, or build the replacement in Rust with `Rule::with_rewrite`.
+//!
+//! Patterns and templates cannot define symbols (i.e., they cannot contain `Let` or `Lambda`
+//! expressions), but pattern variables can match expressions that do.
+
+use fnv::FnvHashMap;
+
+use crate::ast::ExprKind::*;
+use crate::ast::*;
+use crate::error::*;
+use crate::syntax::parser::parse_expr;
+
+#[cfg(test)]
+use crate::tests::*;
+
+/// Sub-expressions bound to the variables of a pattern.
+pub type Bindings<'a> = FnvHashMap<Symbol, &'a Expr>;
+
+/// A function that checks a match before the rule is applied.
+pub type Condition = fn(&Bindings<'_>) -> bool;
+
+/// A function that builds a replacement from a match.
+pub type RewriteFn = fn(&Bindings<'_>) -> Option<Expr>;
+
+#[derive(Clone)]
+enum Replacement {
+    Template(Expr),
+    Func(RewriteFn),
+}
+
+/// A rewrite rule matching a pattern and producing a replacement.
+#[derive(Clone)]
+pub struct Rule {
+    pattern: Expr,
+    replacement: Replacement,
+    condition: Option<Condition>,
+}
+
+impl Rule {
+    /// Creates a rule that replaces expressions matching `pattern` with `template`.
+    ///
+    /// Returns an error if either string fails to parse, if either defines a symbol, or if the
+    /// template uses an identifier that is not bound by the pattern.
+    pub fn new(pattern: &str, template: &str) -> WeldResult<Rule> {
+        let pattern = Self::parse(pattern)?;
+        let template = Self::parse(template)?;
+        let vars = pattern.free_variables();
+        if let Some(sym) = template.free_variables().iter().find(|s| !vars.contains(s)) {
+            return compile_err!(
+                "Symbol {} in rewrite template is not bound by the pattern",
+                sym
+            );
+        }
+        Ok(Rule {
+            pattern,
+            replacement: Replacement::Template(template),
+            condition: None,
+        })
+    }
+
+    /// Creates a rule that replaces expressions matching `pattern` with the result of `func`.
+    ///
+    /// The rule does not apply if `func` returns `None`.
+    pub fn with_rewrite(pattern: &str, func: RewriteFn) -> WeldResult<Rule> {
+        Ok(Rule {
+            pattern: Self::parse(pattern)?,
+            replacement: Replacement::Func(func),
+            condition: None,
+        })
+    }
+
+    /// Only apply this rule to matches for which `condition` returns `true`.
+    pub fn when(mut self, condition: Condition) -> Rule {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Returns the replacement for `expr` if this rule applies to it.
+    pub fn rewrite(&self, expr: &Expr) -> Option<Expr> {
+        let mut bindings = Bindings::default();
+        if !matches(&self.pattern, expr, &mut bindings) {
+            return None;
+        }
+        if let Some(condition) = self.condition {
+            if !condition(&bindings) {
+                return None;
+            }
+        }
+        match self.replacement {
+            Replacement::Template(ref template) => instantiate(template, &bindings).ok(),
+            Replacement::Func(func) => func(&bindings),
+        }
+    }
+
+    fn parse(code: &str) -> WeldResult<Expr> {
+        let expr = parse_expr(code)?;
+        let mut binds = false;
+        expr.traverse(&mut |e| match e.kind {
+            Let { .. } | Lambda { .. } => binds = true,
+            _ => (),
+        });
+        if binds {
+            return compile_err!("Rewrite rules cannot define symbols: {}", code);
+        }
+        Ok(expr)
+    }
+}
+
+/// Returns the replacement produced by the first rule in `rules` that applies to `expr`.
+///
+/// This function can be passed directly to `Expr::transform`.
+pub fn rewrite_with(rules: &[Rule], expr: &Expr) -> Option<Expr> {
+    rules.iter().find_map(|rule| rule.rewrite(expr))
+}
+
+/// Matches `pattern` against `expr`, adding bound variables to `bindings`.
+fn matches<'a>(pattern: &Expr, expr: &'a Expr, bindings: &mut Bindings<'a>) -> bool {
+    if let Ident(ref sym) = pattern.kind {
+        return match bindings.get(sym) {
+            Some(bound) => *bound == expr,
+            None => {
+                bindings.insert(sym.clone(), expr);
+                true
+            }
+        };
+    }
+
+    if pattern.ty != Type::Unknown && pattern.ty != expr.ty {
+        return false;
+    }
+
+    if !same_node(&pattern.kind, &expr.kind) {
+        return false;
+    }
+
+    let pattern_children: Vec<_> = pattern.children().collect();
+    let expr_children: Vec<_> = expr.children().collect();
+    pattern_children.len() == expr_children.len()
+        && pattern_children
+            .into_iter()
+            .zip(expr_children)
+            .all(|(p, e)| matches(p, e, bindings))
+}
+
+/// Returns whether two expression kinds are equal, ignoring their children.
+fn same_node(pattern: &ExprKind, expr: &ExprKind) -> bool {
+    match (pattern, expr) {
+        (Literal(a), Literal(b)) => a == b,
+        (BinOp { kind: a, .. }, BinOp { kind: b, .. }) => a == b,
+        (UnaryOp { kind: a, .. }, UnaryOp { kind: b, .. }) => a == b,
+        (Cast { kind: a, .. }, Cast { kind: b, .. }) => a == b,
+        (GetField { index: a, .. }, GetField { index: b, .. }) => a == b,
+        (Deserialize { value_ty: a, .. }, Deserialize { value_ty: b, .. }) => a == b,
+        (
+            CUDF {
+                sym_name: a,
+                return_ty: a_ty,
+                ..
+            },
+            CUDF {
+                sym_name: b,
+                return_ty: b_ty,
+                ..
+            },
+        ) => a == b && a_ty == b_ty,
+        (Switch { cases: a, .. }, Switch { cases: b, .. }) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.0 == y.0)
+        }
+        (For { iters: a, .. }, For { iters: b, .. }) => {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(x, y)| {
+                    x.kind == y.kind
+                        && x.start.is_some() == y.start.is_some()
+                        && x.end.is_some() == y.end.is_some()
+                        && x.stride.is_some() == y.stride.is_some()
+                        && x.shape.is_some() == y.shape.is_some()
+                        && x.strides.is_some() == y.strides.is_some()
+                })
+        }
+        (NewBuilder(a), NewBuilder(b)) => a.is_some() == b.is_some(),
+        (Ident(_), _) | (Let { .. }, _) | (Lambda { .. }, _) => false,
+        _ => std::mem::discriminant(pattern) == std::mem::discriminant(expr),
+    }
+}
+
+/// Builds an expression from `template`, substituting bound variables and inferring the types of
+/// the new nodes.
+fn instantiate(template: &Expr, bindings: &Bindings<'_>) -> WeldResult<Expr> {
+    if let Ident(ref sym) = template.kind {
+        return match bindings.get(sym) {
+            Some(bound) => Ok((*bound).clone()),
+            None => compile_err!("Unbound symbol {} in rewrite template", sym),
+        };
+    }
+    let mut expr = template.clone();
+    expr.span = None;
+    for child in expr.children_mut() {
+        *child = instantiate(child, bindings)?;
+    }
+    expr.infer_local()?;
+    Ok(expr)
+}
+
+#[cfg(test)]
+fn rewrite_code(rules: &[Rule], code: &str) -> String {
+    let mut e = typed_expression(code);
+    e.transform(&mut |e| rewrite_with(rules, e));
+    print_typed_expr_without_indent(&e)
+}
+
+#[test]
+fn simple_rules() {
+    /// Replaces `x - x` with a zero of the same type. Floats are skipped, since `x - x` is NaN
+    /// when `x` is infinite or NaN.
+    fn zero(b: &Bindings<'_>) -> Option<Expr> {
+        let literal = match b[&Symbol::new("x", 0)].ty {
+            Type::Scalar(ScalarKind::I32) => LiteralKind::I32Literal(0),
+            Type::Scalar(ScalarKind::I64) => LiteralKind::I64Literal(0),
+            _ => return None,
+        };
+        Expr::new_literal(literal).ok()
+    }
+
+    let rules = [
+        Rule::new("!x == !y", "x == y").unwrap(),
+        Rule::with_rewrite("x - x", zero).unwrap(),
+    ];
+    assert_eq!(
+        rewrite_code(&rules, "|a:bool,b:bool| !a == !b"),
+        "|a:bool,b:bool|(a:bool==b:bool)"
+    );
+    // Repeated variables must match equal expressions.
+    assert_eq!(
+        rewrite_code(&rules, "|a:i32,b:i32| (a - a) + (a - b)"),
+        "|a:i32,b:i32|(0+(a:i32-b:i32))"
+    );
+    assert_eq!(rewrite_code(&rules, "|a:i64| a - a"), "|a:i64|0L");
+    assert_eq!(
+        rewrite_code(&rules, "|a:f64| a - a"),
+        "|a:f64|(a:f64-a:f64)"
+    );
+}
+
+#[test]
+fn conditional_rules() {
+    fn is_literal(b: &Bindings<'_>) -> bool {
+        matches!(b[&Symbol::new("y", 0)].kind, Literal(_))
+    }
+    fn swap(b: &Bindings<'_>) -> Option<Expr> {
+        let x = b[&Symbol::new("x", 0)].clone();
+        let y = b[&Symbol::new("y", 0)].clone();
+        Expr::new_bin_op(BinOpKind::Add, y, x).ok()
+    }
+
+    // Move literals to the left of additions.
+    let rules = [Rule::with_rewrite("x + y", swap).unwrap().when(is_literal)];
+    assert_eq!(
+        rewrite_code(&rules, "|a:i32| (a + 1) + a"),
+        "|a:i32|((1+a:i32)+a:i32)"
+    );
+}
+
+#[test]
+fn invalid_rules() {
+    assert!(Rule::new("x + y", "x + z").is_err());
+    assert!(Rule::new("let a = x; a", "x").is_err());
+    assert!(Rule::new("x", "|a:i32| a").is_err());
+}
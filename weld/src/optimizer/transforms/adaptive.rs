@@ -0,0 +1,108 @@
+//! Selects implementations of builders based on their annotations.
+//!
+//! A `dictmerger` can either be built with a local hash table for each chunk of a parallel loop,
+//! which are merged when the loop finishes, or with a single shared table, which the backend
+//! updates from one thread. Local tables let the loop run on several threads but duplicate every
+//! key across tables, so they are chosen when the `num_keys` annotation promises few distinct
+//! keys:
+//!
+//! ```weld
+//! @(num_keys:100) dictmerger[i32,i64,+]
+//! ```
+//!
+//! becomes:
+//!
+//! ```weld
+//! @(impl:local,num_keys:100) dictmerger[i32,i64,+](200L)
+//! ```
+//!
+//! The choice is recorded in the `impl` annotation, so a program can override it by annotating
+//! the builder with `impl` itself. Dictionaries with neither annotation use a shared table. The
+//! expected number of keys is also used as the initial capacity of the dictionary if none was
+//! given.
+
+use crate::ast::BuilderKind::*;
+use crate::ast::ExprKind::*;
+use crate::ast::LiteralKind::*;
+use crate::ast::Type::*;
+use crate::ast::*;
+
+#[cfg(test)]
+use crate::tests::*;
+
+/// The largest number of keys for which a dictionary uses local tables.
+pub const LOCAL_DICT_MAX_KEYS: i64 = 1 << 16;
+
+/// Chooses an implementation for each `dictmerger` with a `num_keys` annotation.
+pub fn select_dict_impl(expr: &mut Expr) {
+    expr.traverse_mut(&mut |e| {
+        // Every occurrence of a builder's type has the same annotations, so the types stay
+        // consistent if they are all updated.
+        annotate_type(&mut e.ty);
+        if let Lambda { ref mut params, .. } = e.kind {
+            for param in params.iter_mut() {
+                annotate_type(&mut param.ty);
+            }
+        }
+        if let NewBuilder(ref mut arg @ None) = e.kind {
+            if let Builder(DictMerger(_, _, _), ref annotations) = e.ty {
+                if let Some(num_keys) = num_keys(annotations) {
+                    // Twice the keys keeps the dictionary below its maximum load factor.
+                    let capacity = I64Literal(num_keys.saturating_mul(2));
+                    *arg = Some(Box::new(Expr::new_literal(capacity).unwrap()));
+                }
+            }
+        }
+    });
+}
+
+/// Sets the `impl` annotation of the dictmergers in `ty`.
+fn annotate_type(ty: &mut Type) {
+    if let Builder(DictMerger(_, _, _), ref mut annotations) = *ty {
+        if annotations.get("impl").is_none() {
+            if let Some(num_keys) = num_keys(annotations) {
+                let choice = if num_keys <= LOCAL_DICT_MAX_KEYS {
+                    "local"
+                } else {
+                    "shared"
+                };
+                annotations.set("impl", choice);
+            }
+        }
+    }
+    for child in ty.children_mut() {
+        annotate_type(child);
+    }
+}
+
+/// Returns the expected number of keys from a `num_keys` annotation.
+fn num_keys(annotations: &Annotations) -> Option<i64> {
+    annotations
+        .get("num_keys")?
+        .parse()
+        .ok()
+        .filter(|n| *n >= 0)
+}
+
+#[test]
+fn dict_impl_from_num_keys() {
+    check_transform(
+        "|v:vec[i32]| for(v, @(num_keys:100) dictmerger[i32,i32,+], |b,i,e| merge(b, {e, 1}))",
+        "|v:vec[i32]| for(v, @(impl:local, num_keys:100) dictmerger[i32,i32,+](200L),
+            |b,i,e| merge(b, {e, 1}))",
+        select_dict_impl,
+    );
+    check_transform(
+        "|| @(num_keys:1000000) dictmerger[i32,i32,+](10L)",
+        "|| @(impl:shared, num_keys:1000000) dictmerger[i32,i32,+](10L)",
+        select_dict_impl,
+    );
+    // An explicit implementation overrides the choice, and unannotated dictionaries are unchanged.
+    let codes = [
+        "|| @(impl:shared, num_keys:100) dictmerger[i32,i32,+](200L)",
+        "|| dictmerger[i32,i32,+]",
+    ];
+    for code in codes.iter() {
+        check_transform(code, code, select_dict_impl);
+    }
+}
@@ -6,218 +6,469 @@ use crate::ast::ExprKind::*;
 use crate::ast::LiteralKind::*;
 use crate::ast::Type::*;
 use crate::ast::*;
+use crate::optimizer::rules::{rewrite_with, Rule};
 
 use self::num_integer::Integer;
 
+thread_local! {
+    static NEGATION_RULES: Vec<Rule> = [
+        ("--x", "x"),
+        ("x - -y", "x + y"),
+        ("!x == !y", "x == y"),
+        ("!x == y", "x != y"),
+        ("x == !y", "x != y"),
+        ("!x != !y", "x != y"),
+        ("!x != y", "x == y"),
+        ("x != !y", "x == y"),
+        ("!true", "false"),
+        ("!false", "true"),
+        ("!!x", "x"),
+        ("!(x == y)", "x != y"),
+        ("!(x != y)", "x == y"),
+    ]
+    .iter()
+    .map(|(pattern, template)| Rule::new(pattern, template).unwrap())
+    .collect();
+}
+
 pub fn eliminate_redundant_negation(expr: &mut Expr) {
-    expr.transform_kind(&mut eliminate_redundant_negation_impl)
+    NEGATION_RULES.with(|rules| {
+        expr.transform(&mut |e| rewrite_with(rules, e).or_else(|| distribute_negation(e)))
+    })
 }
 
-fn eliminate_redundant_negation_impl(expr: &mut Expr) -> Option<ExprKind> {
-    match expr.kind {
-        Negate(ref mut outer) => {
-            match outer.as_mut().kind {
-                Negate(ref mut inner) => Some(inner.take().kind), // --x = x
-                _ => None,
+/// Applies De Morgan's laws to push a negation into a logical operator.
+fn distribute_negation(expr: &Expr) -> Option<Expr> {
+    if let Not(ref outer) = expr.kind {
+        if let BinOp {
+            kind,
+            left: ref lhs,
+            right: ref rhs,
+        } = outer.kind
+        {
+            /*
+             * !(e1 && e1) <=> !e1 || !e2 and !(e1 || e2) <=> !e1 && !e2
+             * This change is not a contraction, so it's a bit risky.
+             * The hope is that it allows eliminations further down the AST.
+             * But if not, it might actually increase the code size.
+             */
+            match (&lhs.kind, &rhs.kind) {
+                (&Ident(_), &Ident(_)) => return None, // nothing to gain here
+                _ if kind == BinOpKind::LogicalAnd || kind == BinOpKind::LogicalOr => {
+                    let lhs = Expr::new_not(lhs.as_ref().clone()).ok()?;
+                    let rhs = Expr::new_not(rhs.as_ref().clone()).ok()?;
+                    return Expr::new_bin_op(flip_logical(kind), lhs, rhs).ok();
+                }
+                _ => (),
             }
         }
+    }
+    None
+}
+
+/// Evaluates operators over literals and removes identity operations.
+///
+/// Binary operators over two literals are replaced with their result, operations with an
+/// identity operand (e.g., `x * 1`, `x + 0`, or `x && true`) are replaced with the other operand,
+/// and conditionals over a literal condition are replaced with the branch they take. Operations
+/// whose result depends on the target (e.g., integer division by zero) are left unchanged.
+pub fn fold_constants(expr: &mut Expr) {
+    expr.transform_up(&mut |e| match e.kind {
         BinOp {
-            kind: binop_kind,
-            left: ref mut lhs,
-            right: ref mut rhs,
-        } => {
-            use crate::ast::BinOpKind::*;
-            match binop_kind {
-                Subtract => {
-                    match rhs.as_mut().kind {
-                        Negate(ref mut inner) => {
-                            // x - -y = x + y
-                            let new = BinOp {
-                                kind: BinOpKind::Add,
-                                left: lhs.take(),
-                                right: inner.take(),
-                            };
-                            Some(new)
-                        }
-                        _ => None,
-                    }
+            kind,
+            ref left,
+            ref right,
+        } => match (&left.kind, &right.kind) {
+            (Literal(ref l), Literal(ref r)) => {
+                let mut folded = Expr::new_literal(fold_bin_op(kind, l, r)?).ok()?;
+                // Keep the type of vectorized operators.
+                folded.ty = e.ty.clone();
+                Some(folded)
+            }
+            _ => simplify_identity(kind, left, right, &e.ty),
+        },
+        If {
+            ref cond,
+            ref on_true,
+            ref on_false,
+        }
+        | Select {
+            ref cond,
+            ref on_true,
+            ref on_false,
+        } => match cond.kind {
+            Literal(BoolLiteral(true)) => Some(on_true.as_ref().clone()),
+            Literal(BoolLiteral(false)) => Some(on_false.as_ref().clone()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Evaluates a comparison between two values using the semantics of the generated code.
+macro_rules! fold_comparison {
+    ($kind:expr, $a:expr, $b:expr) => {
+        match $kind {
+            BinOpKind::Equal => $a == $b,
+            // Comparisons with NaN are always false.
+            BinOpKind::NotEqual => matches!(
+                $a.partial_cmp(&$b),
+                Some(::std::cmp::Ordering::Less) | Some(::std::cmp::Ordering::Greater)
+            ),
+            BinOpKind::LessThan => $a < $b,
+            BinOpKind::LessThanOrEqual => $a <= $b,
+            BinOpKind::GreaterThan => $a > $b,
+            BinOpKind::GreaterThanOrEqual => $a >= $b,
+            _ => return None,
+        }
+    };
+}
+
+/// Evaluates an integer operator.
+///
+/// Arithmetic that overflows is not folded, since it raises an error at run time with
+/// `weld.compile.checkedArithmetic` enabled.
+macro_rules! fold_integer {
+    ($kind:expr, $a:expr, $b:expr, $lit:ident) => {{
+        let (a, b) = ($a, $b);
+        match $kind {
+            BinOpKind::Add => $lit(a.checked_add(b)?),
+            BinOpKind::Subtract => $lit(a.checked_sub(b)?),
+            BinOpKind::Multiply => $lit(a.checked_mul(b)?),
+            BinOpKind::Divide => $lit(a.checked_div(b)?),
+            BinOpKind::Modulo => $lit(a.checked_rem(b)?),
+            BinOpKind::BitwiseAnd => $lit(a & b),
+            BinOpKind::BitwiseOr => $lit(a | b),
+            BinOpKind::Xor => $lit(a ^ b),
+            // Like the generated code, shifts use the amount modulo the bit width.
+            BinOpKind::ShiftLeft => $lit(a.wrapping_shl(b as u32)),
+            BinOpKind::ShiftRight => $lit(a.wrapping_shr(b as u32)),
+            BinOpKind::Max => $lit(if a >= b { a } else { b }),
+            BinOpKind::Min => $lit(if a <= b { a } else { b }),
+            kind => BoolLiteral(fold_comparison!(kind, a, b)),
+        }
+    }};
+}
+
+/// Evaluates a floating point operator.
+macro_rules! fold_float {
+    ($kind:expr, $a:expr, $b:expr, $ty:ty, $lit:ident) => {{
+        let (a, b) = (<$ty>::from_bits($a), <$ty>::from_bits($b));
+        let value = match $kind {
+            BinOpKind::Add => a + b,
+            BinOpKind::Subtract => a - b,
+            BinOpKind::Multiply => a * b,
+            BinOpKind::Divide => a / b,
+            BinOpKind::Modulo => a % b,
+            BinOpKind::Pow => a.powf(b),
+            BinOpKind::Max => {
+                if a >= b {
+                    a
+                } else {
+                    b
                 }
-                Equal => {
-                    // Awkward workaround for https://github.com/rust-lang/rfcs/issues/811
-                    let lhs_is_not = match lhs.kind {
-                        Not(_) => true,
-                        _ => false,
-                    };
-                    let rhs_is_not = match rhs.kind {
-                        Not(_) => true,
-                        _ => false,
-                    };
-                    match (lhs_is_not, rhs_is_not) {
-                        (true, true) => {
-                            // !x == !y <=> x == y
-                            if let Not(ref mut lhs_inner) = lhs.as_mut().kind {
-                                if let Not(ref mut rhs_inner) = rhs.as_mut().kind {
-                                    Some(BinOp {
-                                        kind: Equal,
-                                        left: lhs_inner.take(),
-                                        right: rhs_inner.take(),
-                                    })
-                                } else {
-                                    unreachable!();
-                                }
-                            } else {
-                                unreachable!();
-                            }
-                        }
-                        (true, false) => {
-                            // !x == y <=> x != y
-                            if let Not(ref mut lhs_inner) = lhs.as_mut().kind {
-                                Some(BinOp {
-                                    kind: NotEqual,
-                                    left: lhs_inner.take(),
-                                    right: rhs.take(),
-                                })
-                            } else {
-                                unreachable!();
-                            }
-                        }
-                        (false, true) => {
-                            // x == !y <=> x != y
-                            if let Not(ref mut rhs_inner) = rhs.as_mut().kind {
-                                Some(BinOp {
-                                    kind: NotEqual,
-                                    left: lhs.take(),
-                                    right: rhs_inner.take(),
-                                })
-                            } else {
-                                unreachable!();
-                            }
-                        }
-                        (false, false) => None,
-                    }
+            }
+            BinOpKind::Min => {
+                if a <= b {
+                    a
+                } else {
+                    b
                 }
-                NotEqual => {
-                    // Awkward workaround for https://github.com/rust-lang/rfcs/issues/811
-                    let lhs_is_not = match lhs.kind {
-                        Not(_) => true,
-                        _ => false,
-                    };
-                    let rhs_is_not = match rhs.kind {
-                        Not(_) => true,
-                        _ => false,
-                    };
-                    match (lhs_is_not, rhs_is_not) {
-                        (true, true) => {
-                            // !x != !y <=> x != y
-                            if let Not(ref mut lhs_inner) = lhs.as_mut().kind {
-                                if let Not(ref mut rhs_inner) = rhs.as_mut().kind {
-                                    Some(BinOp {
-                                        kind: NotEqual,
-                                        left: lhs_inner.take(),
-                                        right: rhs_inner.take(),
-                                    })
-                                } else {
-                                    unreachable!();
-                                }
-                            } else {
-                                unreachable!();
-                            }
-                        }
-                        (true, false) => {
-                            // !x != y <=> x == y
-                            if let Not(ref mut lhs_inner) = lhs.as_mut().kind {
-                                Some(BinOp {
-                                    kind: Equal,
-                                    left: lhs_inner.take(),
-                                    right: rhs.take(),
-                                })
-                            } else {
-                                unreachable!();
-                            }
-                        }
-                        (false, true) => {
-                            // x != !y <=> x == y
-                            if let Not(ref mut rhs_inner) = rhs.as_mut().kind {
-                                Some(BinOp {
-                                    kind: Equal,
-                                    left: lhs.take(),
-                                    right: rhs_inner.take(),
-                                })
-                            } else {
-                                unreachable!();
-                            }
-                        }
-                        (false, false) => None,
-                    }
+            }
+            kind => return Some(BoolLiteral(fold_comparison!(kind, a, b))),
+        };
+        $lit(value.to_bits())
+    }};
+}
+
+/// Returns the result of applying `kind` to two literals, if it can be computed at compile time.
+fn fold_bin_op(kind: BinOpKind, left: &LiteralKind, right: &LiteralKind) -> Option<LiteralKind> {
+    let result = match (left, right) {
+        (&BoolLiteral(a), &BoolLiteral(b)) => BoolLiteral(match kind {
+            BinOpKind::LogicalAnd | BinOpKind::BitwiseAnd => a && b,
+            BinOpKind::LogicalOr | BinOpKind::BitwiseOr => a || b,
+            BinOpKind::Xor | BinOpKind::NotEqual => a != b,
+            BinOpKind::Equal => a == b,
+            _ => return None,
+        }),
+        (&I8Literal(a), &I8Literal(b)) => fold_integer!(kind, a, b, I8Literal),
+        (&I16Literal(a), &I16Literal(b)) => fold_integer!(kind, a, b, I16Literal),
+        (&I32Literal(a), &I32Literal(b)) => fold_integer!(kind, a, b, I32Literal),
+        (&I64Literal(a), &I64Literal(b)) => fold_integer!(kind, a, b, I64Literal),
+        (&U8Literal(a), &U8Literal(b)) => fold_integer!(kind, a, b, U8Literal),
+        (&U16Literal(a), &U16Literal(b)) => fold_integer!(kind, a, b, U16Literal),
+        (&U32Literal(a), &U32Literal(b)) => fold_integer!(kind, a, b, U32Literal),
+        (&U64Literal(a), &U64Literal(b)) => fold_integer!(kind, a, b, U64Literal),
+        (&F32Literal(a), &F32Literal(b)) => fold_float!(kind, a, b, f32, F32Literal),
+        (&F64Literal(a), &F64Literal(b)) => fold_float!(kind, a, b, f64, F64Literal),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Returns the non-identity operand of `left <kind> right`, if the other operand is an identity.
+///
+/// Adding zero is only removed for integers since `-0.0 + 0.0` is `0.0`.
+fn simplify_identity(kind: BinOpKind, left: &Expr, right: &Expr, ty: &Type) -> Option<Expr> {
+    let scalar = match *ty {
+        Scalar(s) => s,
+        _ => return None,
+    };
+    let result = match kind {
+        BinOpKind::Add if scalar.is_integer() && is_number(right, 0) => left,
+        BinOpKind::Add if scalar.is_integer() && is_number(left, 0) => right,
+        BinOpKind::Subtract if is_number(right, 0) => left,
+        BinOpKind::Multiply if is_number(right, 1) => left,
+        BinOpKind::Multiply if is_number(left, 1) => right,
+        BinOpKind::Divide if is_number(right, 1) => left,
+        BinOpKind::LogicalAnd if is_bool(right, true) => left,
+        BinOpKind::LogicalAnd if is_bool(left, true) => right,
+        BinOpKind::LogicalOr if is_bool(right, false) => left,
+        BinOpKind::LogicalOr if is_bool(left, false) => right,
+        _ => return None,
+    };
+    Some(result.clone())
+}
+
+/// Returns whether `expr` is a numeric literal equal to `value`.
+fn is_number(expr: &Expr, value: i8) -> bool {
+    let integer = i128::from(value);
+    match expr.kind {
+        Literal(I8Literal(v)) => i128::from(v) == integer,
+        Literal(I16Literal(v)) => i128::from(v) == integer,
+        Literal(I32Literal(v)) => i128::from(v) == integer,
+        Literal(I64Literal(v)) => i128::from(v) == integer,
+        Literal(U8Literal(v)) => i128::from(v) == integer,
+        Literal(U16Literal(v)) => i128::from(v) == integer,
+        Literal(U32Literal(v)) => i128::from(v) == integer,
+        Literal(U64Literal(v)) => i128::from(v) == integer,
+        Literal(F32Literal(v)) => f32::from_bits(v) == f32::from(value),
+        Literal(F64Literal(v)) => f64::from_bits(v) == f64::from(value),
+        _ => false,
+    }
+}
+
+/// Returns whether `expr` is the boolean literal `value`.
+fn is_bool(expr: &Expr, value: bool) -> bool {
+    match expr.kind {
+        Literal(BoolLiteral(v)) => v == value,
+        _ => false,
+    }
+}
+
+/// Replaces integer multiplication, division and modulo by constants with cheaper operators.
+///
+/// Division and modulo by a power of two become shifts and masks, multiplication by a power of
+/// two becomes a left shift, and multiplication of an identifier by a power of two plus or minus
+/// one becomes a shift and an addition or subtraction. Signed division is rounded towards zero
+/// by adding a bias to negative dividends before shifting.
+pub fn reduce_strength(expr: &mut Expr) {
+    expr.transform_up(&mut |e| {
+        let scalar = match e.ty {
+            Scalar(s) if s.is_integer() => s,
+            _ => return None,
+        };
+        if let BinOp {
+            kind,
+            ref left,
+            ref right,
+        } = e.kind
+        {
+            match kind {
+                BinOpKind::Multiply => reduce_multiply(left, right, scalar)
+                    .or_else(|| reduce_multiply(right, left, scalar)),
+                BinOpKind::Divide | BinOpKind::Modulo => {
+                    reduce_divide(kind, left, log2(right)?, scalar)
                 }
                 _ => None,
             }
+        } else {
+            None
         }
-        Not(ref mut outer) => {
-            match outer.as_mut().kind {
-                Literal(ref kind) => {
-                    match kind {
-                        // !true = false and !false = true
-                        BoolLiteral(b) => Some(Literal(BoolLiteral(!b))),
-                        _ => None,
-                    }
-                }
-                Not(ref mut inner) => Some(inner.take().kind), // !!x = x
-                BinOp {
-                    kind: ref binop_kind,
-                    left: ref mut lhs,
-                    right: ref mut rhs,
-                } => {
-                    use crate::ast::BinOpKind::*;
-                    match binop_kind {
-                        LogicalAnd | LogicalOr => {
-                            /*
-                             * !(e1 && e1) <=> !e1 || !e2 and !(e1 || e2) <=> !e1 && !e2
-                             * This change is not a contraction, so it's a bit risky.
-                             * The hope is that it allows eliminations further down the AST.
-                             * But if not, it might actually increase the code size.
-                             */
-                            match (&lhs.kind, &rhs.kind) {
-                                (&Ident(_), &Ident(_)) => None, // nothing to gain here
-                                (_, _) => {
-                                    let kind = flip_logical(*binop_kind);
-                                    Some(BinOp {
-                                        kind,
-                                        left: Box::new(Expr::new_not(lhs.as_mut().take()).unwrap()),
-                                        right: Box::new(
-                                            Expr::new_not(rhs.as_mut().take()).unwrap(),
-                                        ),
-                                    })
-                                }
-                            }
-                        }
-                        Equal => Some(BinOp {
-                            // !(a == b) <=> a != b
-                            kind: NotEqual,
-                            left: lhs.take(),
-                            right: rhs.take(),
-                        }),
-                        NotEqual => Some(BinOp {
-                            // !(a != b) <=> a == b
-                            kind: Equal,
-                            left: lhs.take(),
-                            right: rhs.take(),
-                        }),
-                        _ => None,
-                    }
-                }
-                _ => None,
+    })
+}
+
+/// Returns `x * constant` without a multiplication, if the constant has a cheaper form.
+fn reduce_multiply(x: &Expr, constant: &Expr, scalar: ScalarKind) -> Option<Expr> {
+    if let Some(k) = log2(constant) {
+        return shift(BinOpKind::ShiftLeft, x.clone(), k, scalar);
+    }
+    // The rewrites below use `x` twice, so only apply them if it is free to compute.
+    if let Ident(_) = x.kind {
+        let value = integer_value(constant)?;
+        for &(kind, delta) in [(BinOpKind::Add, -1), (BinOpKind::Subtract, 1)].iter() {
+            // Shift amounts are masked to the width of the type, so `x << bits` is `x`, not zero.
+            if let Some(k) = log2_of(value + delta).filter(|&k| k < scalar.bits()) {
+                let shifted = shift(BinOpKind::ShiftLeft, x.clone(), k, scalar)?;
+                return Expr::new_bin_op(kind, shifted, x.clone()).ok();
             }
         }
-        _ => None,
     }
+    None
+}
+
+/// Returns `x / 2^k` or `x % 2^k` using shifts and masks.
+fn reduce_divide(kind: BinOpKind, x: &Expr, k: u32, scalar: ScalarKind) -> Option<Expr> {
+    let mask = integer(scalar, (1 << k) - 1)?;
+    if scalar.is_unsigned_integer() {
+        return match kind {
+            BinOpKind::Divide => shift(BinOpKind::ShiftRight, x.clone(), k, scalar),
+            _ => Expr::new_bin_op(BinOpKind::BitwiseAnd, x.clone(), mask).ok(),
+        };
+    }
+    if let Ident(_) = x.kind {
+        // Negative dividends are biased by `2^k - 1` so the shift rounds towards zero.
+        let sign = shift(BinOpKind::ShiftRight, x.clone(), scalar.bits() - 1, scalar)?;
+        let bias = Expr::new_bin_op(BinOpKind::BitwiseAnd, sign, mask).ok()?;
+        let biased = Expr::new_bin_op(BinOpKind::Add, x.clone(), bias).ok()?;
+        match kind {
+            BinOpKind::Divide => shift(BinOpKind::ShiftRight, biased, k, scalar),
+            _ => {
+                let multiple = integer(scalar, -(1 << k))?;
+                let rounded = Expr::new_bin_op(BinOpKind::BitwiseAnd, biased, multiple).ok()?;
+                Expr::new_bin_op(BinOpKind::Subtract, x.clone(), rounded).ok()
+            }
+        }
+    } else {
+        None
+    }
+}
+
+/// Returns `x << k` or `x >> k`.
+fn shift(kind: BinOpKind, x: Expr, k: u32, scalar: ScalarKind) -> Option<Expr> {
+    Expr::new_bin_op(kind, x, integer(scalar, i128::from(k))?).ok()
+}
+
+/// Returns `k` if `expr` is an integer literal equal to `2^k` with `k > 0`.
+fn log2(expr: &Expr) -> Option<u32> {
+    log2_of(integer_value(expr)?)
+}
+
+/// Returns `k` if `value` is `2^k` with `k > 0`.
+fn log2_of(value: i128) -> Option<u32> {
+    if value > 1 && value.count_ones() == 1 {
+        Some(value.trailing_zeros())
+    } else {
+        None
+    }
+}
+
+/// Returns the value of an integer literal.
+fn integer_value(expr: &Expr) -> Option<i128> {
+    let value = match expr.kind {
+        Literal(I8Literal(v)) => i128::from(v),
+        Literal(I16Literal(v)) => i128::from(v),
+        Literal(I32Literal(v)) => i128::from(v),
+        Literal(I64Literal(v)) => i128::from(v),
+        Literal(U8Literal(v)) => i128::from(v),
+        Literal(U16Literal(v)) => i128::from(v),
+        Literal(U32Literal(v)) => i128::from(v),
+        Literal(U64Literal(v)) => i128::from(v),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Returns an integer literal of type `scalar`, if `value` fits in the type.
+fn integer(scalar: ScalarKind, value: i128) -> Option<Expr> {
+    use std::convert::TryFrom;
+    let literal = match scalar {
+        ScalarKind::I8 => I8Literal(i8::try_from(value).ok()?),
+        ScalarKind::I16 => I16Literal(i16::try_from(value).ok()?),
+        ScalarKind::I32 => I32Literal(i32::try_from(value).ok()?),
+        ScalarKind::I64 => I64Literal(i64::try_from(value).ok()?),
+        ScalarKind::U8 => U8Literal(u8::try_from(value).ok()?),
+        ScalarKind::U16 => U16Literal(u16::try_from(value).ok()?),
+        ScalarKind::U32 => U32Literal(u32::try_from(value).ok()?),
+        ScalarKind::U64 => U64Literal(u64::try_from(value).ok()?),
+        _ => return None,
+    };
+    Expr::new_literal(literal).ok()
 }
 
 /// Changes the associativity of expressions to compute operations over constants first.
 ///
 /// This enables constant folding in downstream transformations.
 pub fn shift_work_to_constants(expr: &mut Expr) {
-    expr.transform_kind(&mut shift_work_to_constants_impl)
+    // Reassociating floating point arithmetic changes its rounding, so it is only done by
+    // `reassociate_floats`.
+    expr.transform_kind(&mut |e| {
+        if is_float_op(e) {
+            None
+        } else {
+            shift_work_to_constants_impl(e)
+        }
+    })
+}
+
+/// Reassociates and distributes floating point arithmetic.
+///
+/// This applies the rewrites of `shift_work_to_constants` to `f32` and `f64` operators, and
+/// factors out common multiplicands, e.g., `x * a + x * b` becomes `x * (a + b)`. The results may
+/// differ due to rounding, so this transform only runs if fast math is enabled.
+pub fn reassociate_floats(expr: &mut Expr) {
+    expr.transform_kind(&mut |e| {
+        if is_float_op(e) {
+            shift_work_to_constants_impl(e).or_else(|| factor_common_multiplicand(e))
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns whether `expr` is a binary operator over floating point operands.
+fn is_float_op(expr: &Expr) -> bool {
+    match expr.kind {
+        BinOp { ref left, .. } => match left.ty {
+            Scalar(kind) | Simd(kind) => kind.is_float(),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Rewrites a sum or difference of two products that share a multiplicand into a product.
+fn factor_common_multiplicand(expr: &Expr) -> Option<ExprKind> {
+    use crate::ast::BinOpKind::*;
+    if let BinOp {
+        kind,
+        ref left,
+        ref right,
+    } = expr.kind
+    {
+        if kind != Add && kind != Subtract {
+            return None;
+        }
+        if let (
+            BinOp {
+                kind: Multiply,
+                left: ref a,
+                right: ref b,
+            },
+            BinOp {
+                kind: Multiply,
+                left: ref c,
+                right: ref d,
+            },
+        ) = (&left.kind, &right.kind)
+        {
+            let (common, x, y) = if a == c {
+                (a, b, d)
+            } else if a == d {
+                (a, b, c)
+            } else if b == c {
+                (b, a, d)
+            } else if b == d {
+                (b, a, c)
+            } else {
+                return None;
+            };
+            let rest = Expr::new_bin_op(kind, x.as_ref().clone(), y.as_ref().clone()).ok()?;
+            let product = Expr::new_bin_op(Multiply, common.as_ref().clone(), rest).ok()?;
+            return Some(product.kind);
+        }
+    }
+    None
 }
 
 fn shift_work_to_constants_impl(expr: &mut Expr) -> Option<ExprKind> {
@@ -1356,6 +1607,11 @@ fn classify(e: &Expr) -> RangeClassification {
                     Zero => Zero,
                     Unknown => Unknown,
                 },
+                Abs => match ec {
+                    Positive | Negative => Positive,
+                    Zero => Zero,
+                    Unknown => Unknown,
+                },
                 _ => Unknown,
             }
         }
@@ -1412,3 +1668,115 @@ fn classify(e: &Expr) -> RangeClassification {
         _ => RangeClassification::Unknown,
     }
 }
+
+#[test]
+fn redundant_negation() {
+    use crate::tests::*;
+    let negation = eliminate_redundant_negation;
+    check_transform("|x:i32| --x", "|x:i32| x", negation);
+    check_transform("|x:i32,y:i32| x - -y", "|x:i32,y:i32| x + y", negation);
+    check_transform("|x:bool| !!x", "|x:bool| x", negation);
+    check_transform("|x:bool| !x == !true", "|x:bool| x == true", negation);
+    check_transform(
+        "|x:bool,y:bool| !x != y",
+        "|x:bool,y:bool| x == y",
+        negation,
+    );
+    check_transform("|x:i32,y:i32| !(x == y)", "|x:i32,y:i32| x != y", negation);
+    check_transform(
+        "|x:i32,y:bool| !(x > 1 && y)",
+        "|x:i32,y:bool| !(x > 1) || !y",
+        negation,
+    );
+}
+
+#[test]
+fn constant_folding() {
+    use crate::tests::*;
+    check_transform("|| 1 + 2 * 3", "|| 7", fold_constants);
+    check_transform("|| 7L / 2L > 3L", "|| false", fold_constants);
+    check_transform("|| 2.0 * 1.5 == 3.0", "|| true", fold_constants);
+    check_transform("|| 250u8 - 10u8", "|| 240u8", fold_constants);
+    check_transform("|| max(3, 4) - min(3, 4)", "|| 1", fold_constants);
+    check_transform("|| true && !false", "|| !false", fold_constants);
+    // Division by zero depends on the target, and overflow on whether arithmetic is checked.
+    check_transform("|| 1 / 0", "|| 1 / 0", fold_constants);
+    check_transform("|| 250u8 + 10u8", "|| 250u8 + 10u8", fold_constants);
+}
+
+#[test]
+fn identity_simplification() {
+    use crate::tests::*;
+    check_transform("|x:i32| (x + 0) * 1", "|x:i32| x", fold_constants);
+    check_transform("|x:f64| 1.0 * x / 1.0 - 0.0", "|x:f64| x", fold_constants);
+    check_transform("|x:f64| x + 0.0", "|x:f64| x + 0.0", fold_constants);
+    check_transform(
+        "|x:bool| (x && true) || false",
+        "|x:bool| x",
+        fold_constants,
+    );
+    check_transform("|x:i32| if(1 < 2, x, x + 1)", "|x:i32| x", fold_constants);
+    check_transform(
+        "|x:i32| select(1 + 1 == 3, x, x * 2)",
+        "|x:i32| x * 2",
+        fold_constants,
+    );
+}
+
+#[test]
+fn float_reassociation() {
+    use crate::tests::*;
+    // Floating point arithmetic is only reassociated with fast math.
+    let code = "|x:f64| (x + 1.0) < 3.0";
+    check_transform(code, code, shift_work_to_constants);
+    check_transform(code, "|x:f64| x < 3.0 - 1.0", reassociate_floats);
+    check_transform(
+        "|x:i32| (x + 1) < 3",
+        "|x:i32| x < 3 - 1",
+        shift_work_to_constants,
+    );
+    check_transform(
+        "|x:f64, y:f64| x * 2.0 - 3.0 * x",
+        "|x:f64, y:f64| x * (2.0 - 3.0)",
+        reassociate_floats,
+    );
+    let code = "|x:f64, y:f64| x * 2.0 + y * 3.0";
+    check_transform(code, code, reassociate_floats);
+}
+
+#[test]
+fn strength_reduction() {
+    use crate::tests::*;
+    check_transform("|x:i32| x * 8", "|x:i32| x << 3", reduce_strength);
+    check_transform("|x:i64| 4L * x", "|x:i64| x << 2L", reduce_strength);
+    check_transform("|x:i32| x * 9", "|x:i32| (x << 3) + x", reduce_strength);
+    check_transform("|x:i32| x * 7", "|x:i32| (x << 3) - x", reduce_strength);
+    check_transform("|x:u32| x / 16u32", "|x:u32| x >> 4u32", reduce_strength);
+    check_transform("|x:u32| x % 16u32", "|x:u32| x & 15u32", reduce_strength);
+    check_transform(
+        "|x:i32| x / 4",
+        "|x:i32| (x + ((x >> 31) & 3)) >> 2",
+        reduce_strength,
+    );
+    // The parser reads `-4` as a negation, so fold it into a literal in the expected expression.
+    let input = &mut typed_expression("|x:i32| x % 4");
+    let expect = &mut typed_expression("|x:i32| x - ((x + ((x >> 31) & 3)) & -4)");
+    reduce_strength(input);
+    crate::optimizer::transforms::inliner::inline_negate(expect);
+    assert!(input.compare_ignoring_symbols(expect).unwrap());
+    // Operands that would be computed twice and non-powers of two are left alone.
+    let codes = [
+        "|x:i32| (x + 1) * 7",
+        "|x:i32| (x + 1) / 4",
+        "|x:i32| x / 6",
+        "|x:i32| x * 11",
+        "|x:f64| x * 2.0",
+        // `2^bits - 1` would need a shift by the width of the type.
+        "|x:u8| x * 255u8",
+        "|x:u32| x * 4294967295u32",
+        "|x:u64| x * 18446744073709551615u64",
+    ];
+    for code in codes.iter() {
+        check_transform(code, code, reduce_strength);
+    }
+}
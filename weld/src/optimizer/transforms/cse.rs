@@ -380,6 +380,8 @@ impl Cse {
                 ty,
                 kind: Ident(name),
                 annotations: Annotations::new(),
+                span: None,
+                id: None,
             };
 
             Some(replacement)
@@ -422,6 +424,22 @@ impl Cse {
                 self.build_site_map_helper(on_false, bindings, site_map, current_site);
                 current_site.pop();
             }
+            Switch {
+                ref value,
+                ref cases,
+                ref default,
+            } => {
+                self.build_site_map_helper(value, bindings, site_map, current_site);
+
+                // Update current site for each branch target.
+                let branches = cases.iter().map(|case| &case.1);
+                for branch in branches.chain(Some(default.as_ref())) {
+                    current_site.push(self.counter);
+                    self.counter += 1;
+                    self.build_site_map_helper(branch, bindings, site_map, current_site);
+                    current_site.pop();
+                }
+            }
             Lambda { ref body, .. } => {
                 current_site.push(self.counter);
                 self.counter += 1;
@@ -507,8 +525,8 @@ impl Cse {
         stack: &mut Vec<Vec<Binding>>,
         sites: &mut SiteMap,
     ) {
-        // NOTE: Because of the way paths are built, this method must traverse Lambdas and If
-        // statements in the exact same order as build_site_map_helper!
+        // NOTE: Because of the way paths are built, this method must traverse Lambdas, If, and
+        // Switch statements in the exact same order as build_site_map_helper!
         let handled = match expr.kind {
             Lambda { ref mut body, .. } => {
                 self.generate_bindings_scoped(
@@ -567,6 +585,30 @@ impl Cse {
                 );
                 true
             }
+            Switch {
+                ref mut value,
+                ref mut cases,
+                ref mut default,
+            } => {
+                self.generate_bindings(value, bindings, generated, current_site, stack, sites);
+
+                // As with If, keep definitions within each branch target.
+                let branches = cases
+                    .iter_mut()
+                    .map(|case| &mut case.1)
+                    .chain(Some(default.as_mut()));
+                for branch in branches {
+                    self.generate_bindings_scoped(
+                        branch,
+                        bindings,
+                        generated,
+                        current_site,
+                        stack,
+                        sites,
+                    );
+                }
+                true
+            }
             Ident(ref mut sym) if bindings.contains_key(sym) => {
                 // If the site list for this symbol contains an ancestor or the current site, we
                 // have not resolved it yet. Generate the binding at the appropriate site and
@@ -0,0 +1,239 @@
+//! Dead code elimination.
+//!
+//! Removes `Let` bindings whose symbol is never used, and builders in loops over structs of
+//! builders whose results are never read. Frontends that generate Weld mechanically tend to leave
+//! both behind, as do transforms such as loop fusion that merge several loops into one.
+
+use crate::ast::ExprKind::*;
+use crate::ast::*;
+
+#[cfg(test)]
+use crate::tests::*;
+
+/// Removes unused `Let` bindings and unused builders from loops.
+///
+/// A builder is only removed from a loop if every use of the loop's result reads a field of the
+/// struct it returns, and the loop body only touches the removed builder through fields of its
+/// builder parameter. This guarantees that no other expression consumes the removed builder.
+pub fn eliminate_dead_code(expr: &mut Expr) {
+    expr.uniquify().unwrap();
+    remove_dead_lets(expr);
+    remove_dead_builders(expr);
+}
+
+/// Replaces `Let` expressions whose symbol does not appear in the body with the body.
+fn remove_dead_lets(expr: &mut Expr) {
+    expr.transform_up(&mut |e| match e.kind {
+        Let {
+            ref name, ref body, ..
+        } if !body.contains_symbol(name) => Some(body.as_ref().clone()),
+        _ => None,
+    });
+}
+
+/// Removes builders that are never read from loops over structs of builders.
+fn remove_dead_builders(expr: &mut Expr) {
+    expr.transform_up(&mut |e| match e.kind {
+        Let {
+            ref name,
+            ref value,
+            ref body,
+        } => {
+            let used = used_fields(body, name)?;
+            let pruned = prune_builders(value, &used)?;
+            let ident = Expr::new_ident(name.clone(), pruned.ty.clone()).ok()?;
+            let mut body = body.as_ref().clone();
+            replace_fields(&mut body, name, &ident, &used);
+            Expr::new_let(name.clone(), pruned, body).ok()
+        }
+        GetField { ref expr, index } => prune_builders(expr, &[index]),
+        _ => None,
+    });
+}
+
+/// Returns the sorted field indices of `sym` read in `expr`.
+///
+/// Returns `None` if `sym` is used other than in a `GetField` expression.
+fn used_fields(expr: &Expr, sym: &Symbol) -> Option<Vec<u32>> {
+    let mut uses = 0;
+    let mut fields = vec![];
+    expr.traverse(&mut |e| match e.kind {
+        Ident(ref s) if s == sym => uses += 1,
+        GetField { ref expr, index } => {
+            if let Ident(ref s) = expr.kind {
+                if s == sym {
+                    fields.push(index);
+                }
+            }
+        }
+        _ => (),
+    });
+    if uses != fields.len() {
+        return None;
+    }
+    fields.sort_unstable();
+    fields.dedup();
+    Some(fields)
+}
+
+/// Returns `loop_expr` with only the builders at the indices in `used`.
+///
+/// If `used` has one index, the new loop uses that builder directly instead of a struct.
+fn prune_builders(loop_expr: &Expr, used: &[u32]) -> Option<Expr> {
+    if let For {
+        ref iters,
+        ref builder,
+        ref func,
+    } = loop_expr.kind
+    {
+        if let MakeStruct { ref elems } = builder.kind {
+            if used.is_empty() || used.len() == elems.len() {
+                return None;
+            }
+            if let Lambda {
+                ref params,
+                ref body,
+            } = func.kind
+            {
+                let builder = select(elems, used)?;
+                let param = &params[0].name;
+                let ident = Expr::new_ident(param.clone(), builder.ty.clone()).ok()?;
+
+                let mut body = project(body, param, used)?;
+                remove_dead_lets(&mut body);
+                replace_fields(&mut body, param, &ident, used);
+
+                // Any remaining use of the old parameter touches a removed builder.
+                let mut stale = false;
+                body.traverse(&mut |e| {
+                    if let Ident(ref s) = e.kind {
+                        stale |= s == param && e.ty != ident.ty;
+                    }
+                });
+                if stale {
+                    return None;
+                }
+
+                let mut params = params.clone();
+                params[0].ty = ident.ty.clone();
+                let func = Expr::new_lambda(params, body).ok()?;
+                return Expr::new_for(iters.clone(), builder, func).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Returns the elements at the indices in `used`, or the element itself if there is only one.
+fn select(elems: &[Expr], used: &[u32]) -> Option<Expr> {
+    let mut selected = used
+        .iter()
+        .map(|i| elems.get(*i as usize).cloned())
+        .collect::<Option<Vec<_>>>()?;
+    if selected.len() == 1 {
+        selected.pop()
+    } else {
+        Expr::new_make_struct(selected).ok()
+    }
+}
+
+/// Restricts a loop body returning a struct of builders to the builders in `used`.
+///
+/// Returns `None` if the body does not build the struct it returns in a way this function
+/// understands.
+fn project(body: &Expr, param: &Symbol, used: &[u32]) -> Option<Expr> {
+    match body.kind {
+        MakeStruct { ref elems } => select(elems, used),
+        If {
+            ref cond,
+            ref on_true,
+            ref on_false,
+        } => {
+            let on_true = project(on_true, param, used)?;
+            let on_false = project(on_false, param, used)?;
+            Expr::new_if(cond.as_ref().clone(), on_true, on_false).ok()
+        }
+        Let {
+            ref name,
+            ref value,
+            body: ref inner,
+        } => {
+            let inner = project(inner, param, used)?;
+            Expr::new_let(name.clone(), value.as_ref().clone(), inner).ok()
+        }
+        Ident(ref sym) if sym == param => {
+            if let Type::Struct(ref tys, _) = body.ty {
+                let fields = (0..tys.len() as u32)
+                    .map(|i| Expr::new_get_field(body.clone(), i).ok())
+                    .collect::<Option<Vec<_>>>()?;
+                select(&fields, used)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Replaces reads of the fields in `used` from `sym` with reads from `ident`, which holds only
+/// those fields.
+fn replace_fields(expr: &mut Expr, sym: &Symbol, ident: &Expr, used: &[u32]) {
+    expr.transform_and_continue(&mut |e| {
+        if let GetField { ref expr, index } = e.kind {
+            if let Ident(ref s) = expr.kind {
+                if s == sym {
+                    let replacement = match used.iter().position(|i| *i == index) {
+                        Some(_) if used.len() == 1 => Some(ident.clone()),
+                        Some(pos) => Expr::new_get_field(ident.clone(), pos as u32).ok(),
+                        None => None,
+                    };
+                    return (replacement, false);
+                }
+            }
+        }
+        (None, true)
+    });
+}
+
+#[test]
+fn dead_lets() {
+    check_transform(
+        "|x:i32| let y = x + 1; let z = y * 2; x",
+        "|x:i32| x",
+        eliminate_dead_code,
+    );
+    check_transform(
+        "|v:vec[i32]| let r = result(for(v, appender[i32], |b,i,e| merge(b, e))); len(v)",
+        "|v:vec[i32]| len(v)",
+        eliminate_dead_code,
+    );
+    check_transform(
+        "|x:i32| let y = x + 1; y * 2",
+        "|x:i32| let y = x + 1; y * 2",
+        eliminate_dead_code,
+    );
+}
+
+#[test]
+fn dead_builders() {
+    check_transform(
+        "|v:vec[i32]| let r = for(v, {appender[i32], merger[i32,+], merger[i32,*]},
+            |b,i,e| {merge(b.$0, e), merge(b.$1, e), merge(b.$2, e)});
+            {result(r.$2), result(r.$0)}",
+        "|v:vec[i32]| let r = for(v, {appender[i32], merger[i32,*]},
+            |b,i,e| {merge(b.$0, e), merge(b.$1, e)});
+            {result(r.$1), result(r.$0)}",
+        eliminate_dead_code,
+    );
+    check_transform(
+        "|v:vec[i32]| result(for(v, {appender[i32], merger[i32,+]},
+            |b,i,e| let m = merge(b.$1, e); if(e > 0, {merge(b.$0, e), m}, {b.$0, m})).$0)",
+        "|v:vec[i32]| result(for(v, appender[i32],
+            |b,i,e| if(e > 0, merge(b, e), b)))",
+        eliminate_dead_code,
+    );
+    // The loop body passes the whole struct of builders to another expression.
+    let nested = "|v:vec[i32]| result(for(v, {appender[i32], merger[i32,+]},
+            |b,i,e| for(v, b, |b2,j,f| {merge(b2.$0, f), b2.$1})).$0)";
+    check_transform(nested, nested, eliminate_dead_code);
+}
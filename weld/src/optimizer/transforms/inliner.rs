@@ -63,6 +63,8 @@ pub fn inline_zips(expr: &mut Expr) {
                             func: func.clone(),
                         },
                         annotations: Annotations::new(),
+                        span: None,
+                        id: None,
                     });
                 }
             }
@@ -72,7 +74,8 @@ pub fn inline_zips(expr: &mut Expr) {
 }
 
 /// Inlines Apply nodes whose argument is a Lambda expression. These often arise during macro
-/// expansion but it's simpler to inline them before doing type inference.
+/// expansion but it's simpler to inline them before doing type inference. Lambdas bound with
+/// `Let` (i.e., named functions) are first substituted into their call sites.
 /// Unlike many of the other transformations, we make this one independent of types so that
 /// we can apply it before type inference.
 ///
@@ -81,8 +84,25 @@ pub fn inline_zips(expr: &mut Expr) {
 ///   it to a temporary as would happen with function application.
 /// - Does not complete inlining if some of the functions take functions as arguments (in that
 ///   case, the expressions after inlining may lead to more inlining).
+///
+/// An argument is substituted for each use of its parameter if it is a literal or identifier, if
+/// the parameter is used at most once, or if copying it grows the program by at most
+/// `INLINE_SIZE_BUDGET` nodes. Otherwise, it is bound to the parameter with a `Let`, so it is
+/// only computed once.
 pub fn inline_apply(expr: &mut Expr) {
     expr.transform(&mut |ref mut expr| {
+        if let Let {
+            ref name,
+            ref value,
+            ref body,
+        } = expr.kind
+        {
+            if let Lambda { .. } = value.kind {
+                let mut new = *body.clone();
+                new.substitute(name, value);
+                return Some(new);
+            }
+        }
         if let Apply {
             ref func,
             params: ref args,
@@ -93,10 +113,70 @@ pub fn inline_apply(expr: &mut Expr) {
                 ref body,
             } = func.kind
             {
+                // Binding an argument with a `Let` is only safe if no other argument refers to a
+                // symbol with the same name as its parameter.
+                let free: Vec<_> = args.iter().map(|arg| arg.free_variables()).collect();
                 let mut new = *body.clone();
+                let mut lets = vec![];
                 for (param, arg) in params.iter().zip(args) {
-                    new.substitute(&param.name, &arg);
+                    let uses = symbol_uses(&new, &param.name);
+                    let growth = arg.node_count() * uses.saturating_sub(1);
+                    let captured = free.iter().any(|vars| vars.contains(&param.name));
+                    if is_trivial(arg)
+                        || growth <= INLINE_SIZE_BUDGET
+                        || arg.ty.contains_builder()
+                        || captured
+                    {
+                        new.substitute(&param.name, arg);
+                    } else {
+                        lets.push((param.name.clone(), arg.clone()));
+                    }
                 }
+                for (name, value) in lets.into_iter().rev() {
+                    new = Expr::new_let(name, value, new).ok()?;
+                }
+                return Some(new);
+            }
+        }
+        None
+    });
+}
+
+/// The number of nodes by which `inline_apply` may grow a program when it copies an argument.
+const INLINE_SIZE_BUDGET: usize = 8;
+
+/// Returns whether `expr` is a literal or identifier, which can be copied freely.
+fn is_trivial(expr: &Expr) -> bool {
+    matches!(expr.kind, Literal(_) | Ident(_))
+}
+
+/// Returns the number of times `sym` is used in `expr`.
+fn symbol_uses(expr: &Expr, sym: &Symbol) -> usize {
+    let mut uses = 0;
+    expr.traverse(&mut |e| {
+        if let Ident(ref name) = e.kind {
+            if name == sym {
+                uses += 1;
+            }
+        }
+    });
+    uses
+}
+
+/// Inlines Let expressions whose value is a literal or identifier, regardless of how often the
+/// symbol is used.
+pub fn inline_trivial_let(expr: &mut Expr) {
+    expr.uniquify().unwrap();
+    expr.transform(&mut |ref mut expr| {
+        if let Let {
+            ref name,
+            ref value,
+            ref body,
+        } = expr.kind
+        {
+            if is_trivial(value) {
+                let mut new = *body.clone();
+                new.substitute(name, value);
                 return Some(new);
             }
         }
@@ -418,6 +498,59 @@ pub fn unroll_structs(expr: &mut Expr) {
     });
 }
 
+#[test]
+fn inline_named_functions() {
+    let mut e1 = typed_expression("|a:i32| let f = |x:i32| x + 1; f(a) * f(2)");
+    inline_apply(&mut e1);
+    let e2 = typed_expression("|a:i32| (a + 1) * (2 + 1)");
+    assert!(e1.compare_ignoring_symbols(&e2).unwrap());
+}
+
+#[test]
+fn inline_apply_budget() {
+    // Small arguments are copied into each use.
+    let mut e1 = typed_expression("|a:i32| (|x:i32| x * x)(a + 1)");
+    inline_apply(&mut e1);
+    let e2 = typed_expression("|a:i32| (a + 1) * (a + 1)");
+    assert!(e1.compare_ignoring_symbols(&e2).unwrap());
+
+    // Large arguments used more than once are bound with a Let.
+    let mut e1 = typed_expression("|a:i32| (|x:i32| x * x * x)(a * 2 + a * 3 + 1)");
+    inline_apply(&mut e1);
+    let e2 = typed_expression("|a:i32| let x = a * 2 + a * 3 + 1; x * x * x");
+    assert!(e1.compare_ignoring_symbols(&e2).unwrap());
+
+    // Arguments are still substituted if binding them would capture another argument.
+    let mut e1 =
+        typed_expression("|x:i32, y:i32| (|x:i32, z:i32| x * x * x + z)(y * 2 + y * 3 + 1, x)");
+    inline_apply(&mut e1);
+    let e2 = typed_expression(
+        "|x:i32, y:i32| (y * 2 + y * 3 + 1) * (y * 2 + y * 3 + 1) * (y * 2 + y * 3 + 1) + x",
+    );
+    assert!(e1.compare_ignoring_symbols(&e2).unwrap());
+}
+
+#[test]
+fn inline_trivial_lets() {
+    let mut e1 = typed_expression(
+        "|v:vec[i64], y:i64| let a = 1L; let b = y; \
+         for(v, appender, |bs,i,e| merge(bs, e + a + b + a))",
+    );
+    inline_trivial_let(&mut e1);
+    let e2 = typed_expression(
+        "|v:vec[i64], y:i64| for(v, appender, |bs,i,e| merge(bs, e + 1L + y + 1L))",
+    );
+    assert!(e1.compare_ignoring_symbols(&e2).unwrap());
+
+    // Other values are not inlined.
+    let code = "|y:i64| let a = y + 1L; a + a";
+    let mut e1 = typed_expression(code);
+    inline_trivial_let(&mut e1);
+    assert!(e1
+        .compare_ignoring_symbols(&typed_expression(code))
+        .unwrap());
+}
+
 #[test]
 fn inline_lets() {
     let mut e1 = typed_expression("let a = 1; a + 2");
@@ -444,9 +577,11 @@ fn inline_lets() {
     assert!(e1.compare_ignoring_symbols(&e2).unwrap());
 
     let mut e1 = typed_expression("let a = 1; let b = 2; let c = 3; a + b + c");
+    let before = e1.node_count();
     inline_let(&mut e1);
     let e2 = typed_expression("1 + 2 + 3");
     assert!(e1.compare_ignoring_symbols(&e2).unwrap());
+    assert!(e1.node_count() < before);
 
     let mut e1 = typed_expression(
         "|input: vec[i32]|
@@ -0,0 +1,161 @@
+//! Implements loop-invariant code motion (LICM).
+//!
+//! Loop-invariant code motion moves subexpressions of a `For` loop's function that do not depend
+//! on the function's parameters (or on symbols defined inside the function) into `let`
+//! expressions before the loop, so they are computed once instead of once per element. For
+//! example:
+//!
+//! ```weld
+//! |v: vec[i32], x: i32| for(v, appender, |b,i,e| merge(b, e * (x + 1)))
+//! ```
+//!
+//! becomes:
+//!
+//! ```weld
+//! |v: vec[i32], x: i32| let licm = x + 1; for(v, appender, |b,i,e| merge(b, e * licm))
+//! ```
+//!
+//! The transform does not hoist expressions out of the branches of an `If` or the cases of a
+//! `Switch`, since they may not be evaluated at all. For the same reason, it never hoists
+//! expressions that can fail at runtime (e.g., lookups, slices, or integer division), since the
+//! loop may have no iterations. Expressions with builder types are never hoisted.
+
+use fnv::FnvHashSet;
+
+use crate::ast::ExprKind::*;
+use crate::ast::*;
+use crate::util::SymbolGenerator;
+
+use std::mem;
+
+#[cfg(test)]
+use crate::tests::*;
+
+/// Hoists loop-invariant subexpressions out of `For` loops.
+pub fn hoist_loop_invariants(expr: &mut Expr) {
+    expr.uniquify().unwrap();
+    let mut gen = SymbolGenerator::from_expression(expr);
+    // Visiting inner loops first lets invariants move out of a whole loop nest.
+    expr.transform_up(&mut |e| {
+        let hoisted = match e.kind {
+            For { ref mut func, .. } => {
+                let bound = defined_symbols(func);
+                let mut hoisted = vec![];
+                if let Lambda { ref mut body, .. } = func.kind {
+                    hoist(body, &bound, &mut gen, &mut hoisted);
+                }
+                hoisted
+            }
+            _ => return None,
+        };
+        if hoisted.is_empty() {
+            return None;
+        }
+        let mut result = e.clone();
+        for (name, value) in hoisted.into_iter().rev() {
+            result = Expr::new_let(name, value, result).unwrap();
+        }
+        Some(result)
+    });
+}
+
+/// Returns the symbols defined in `expr`.
+fn defined_symbols(expr: &Expr) -> FnvHashSet<Symbol> {
+    let mut defined = FnvHashSet::default();
+    expr.traverse(&mut |e| match e.kind {
+        Lambda { ref params, .. } => defined.extend(params.iter().map(|p| p.name.clone())),
+        Let { ref name, .. } => {
+            defined.insert(name.clone());
+        }
+        _ => (),
+    });
+    defined
+}
+
+/// Replaces the largest invariant subexpressions of `expr` with new identifiers, adding the
+/// replaced values to `hoisted`.
+fn hoist(
+    expr: &mut Expr,
+    bound: &FnvHashSet<Symbol>,
+    gen: &mut SymbolGenerator,
+    hoisted: &mut Vec<(Symbol, Expr)>,
+) {
+    if is_invariant(expr, bound) {
+        let name = gen.new_symbol("licm");
+        let ident = Expr::new_ident(name.clone(), expr.ty.clone()).unwrap();
+        hoisted.push((name, mem::replace(expr, ident)));
+        return;
+    }
+    match expr.kind {
+        If { ref mut cond, .. }
+        | Switch {
+            value: ref mut cond,
+            ..
+        } => hoist(cond, bound, gen, hoisted),
+        _ => {
+            for child in expr.children_mut() {
+                hoist(child, bound, gen, hoisted);
+            }
+        }
+    }
+}
+
+/// Returns whether `expr` can be computed once outside a loop whose function defines `bound`.
+fn is_invariant(expr: &Expr, bound: &FnvHashSet<Symbol>) -> bool {
+    match expr.kind {
+        // Nothing is gained by hoisting these.
+        Literal(_) | Ident(_) | Lambda { .. } => return false,
+        _ => (),
+    }
+    if expr.ty.contains_builder() || expr.free_variables().iter().any(|s| bound.contains(s)) {
+        return false;
+    }
+    let mut fails = false;
+    expr.traverse(&mut |e| fails |= e.kind.may_fail());
+    !fails
+}
+
+#[test]
+fn hoist_invariants() {
+    check_transform(
+        "|v:vec[i32], x:i32| for(v, appender[i32], |b,i,e| merge(b, e * (x + 1)))",
+        "|v:vec[i32], x:i32| let y = x + 1; for(v, appender[i32], |b,i,e| merge(b, e * y))",
+        hoist_loop_invariants,
+    );
+    // Expressions that use a symbol defined in the loop body stay in the loop.
+    check_transform(
+        "|v:vec[i32], x:i32| for(v, merger[i32,+], |b,i,e| let y = e + x; merge(b, y * x))",
+        "|v:vec[i32], x:i32| for(v, merger[i32,+], |b,i,e| let y = e + x; merge(b, y * x))",
+        hoist_loop_invariants,
+    );
+    // Invariants move out of nested loops.
+    check_transform(
+        "|v:vec[vec[i32]], w:vec[i32]| for(v, merger[i32,+], |b,i,e|
+            for(e, b, |b2,j,f| merge(b2, f + result(for(w, merger[i32,+], |b3,k,g| merge(b3, g))))))",
+        "|v:vec[vec[i32]], w:vec[i32]|
+            let s = result(for(w, merger[i32,+], |b3,k,g| merge(b3, g)));
+            for(v, merger[i32,+], |b,i,e| let t = s; for(e, b, |b2,j,f| merge(b2, f + t)))",
+        hoist_loop_invariants,
+    );
+}
+
+#[test]
+fn hoist_invariants_conservatively() {
+    // Expressions in branches and expressions that may fail are not hoisted.
+    let codes = [
+        "|v:vec[i32], x:i32| for(v, appender[i32], |b,i,e| if(e > 0, merge(b, x * 2), b))",
+        "|v:vec[i32], x:i32| for(v, appender[i32], |b,i,e| merge(b, e + lookup(v, 0L)))",
+        "|v:vec[i32], x:i32| for(v, appender[i32], |b,i,e| merge(b, e + 10 / x))",
+        "|v:vec[i32], x:i32| for(v, appender[i32], |b,i,e| switch(e, 1: merge(b, x * 2), b))",
+        "|v:vec[i32], x:i64| for(v, appender[i64], |b,i,e| merge(b, len(slice(v, x, 2L))))",
+    ];
+    for code in codes.iter() {
+        check_transform(code, code, hoist_loop_invariants);
+    }
+    // The condition of a branch is always evaluated.
+    check_transform(
+        "|v:vec[i32], x:i32| for(v, appender[i32], |b,i,e| if(x > 2, merge(b, e), b))",
+        "|v:vec[i32], x:i32| let c = x > 2; for(v, appender[i32], |b,i,e| if(c, merge(b, e), b))",
+        hoist_loop_invariants,
+    );
+}
@@ -115,6 +115,7 @@ pub fn fuse_loops_horizontal(expr: &mut Expr) {
                             .iter()
                             .map(|ref e| e.1.ty.clone())
                             .collect::<Vec<_>>(),
+                        FieldNames::none(),
                     );
                     // TODO(Deepak): Fix this to something meaningful.
                     let builder_type =
@@ -145,16 +146,22 @@ pub fn fuse_loops_horizontal(expr: &mut Expr) {
                         ty: builder_type.clone(),
                         kind: Ident(new_params[0].name.clone()),
                         annotations: Annotations::new(),
+                        span: None,
+                        id: None,
                     };
                     let new_index_expr = Expr {
                         ty: Scalar(ScalarKind::I64),
                         kind: Ident(new_params[1].name.clone()),
                         annotations: Annotations::new(),
+                        span: None,
+                        id: None,
                     };
                     let new_elem_expr = Expr {
                         ty: func_elem_type,
                         kind: Ident(new_params[2].name.clone()),
                         annotations: Annotations::new(),
+                        span: None,
+                        id: None,
                     };
                     for &mut (ref mut args, ref mut expr) in lambdas.iter_mut() {
                         expr.substitute(&args[0].name, &new_bldr_expr);
@@ -183,9 +190,13 @@ pub fn fuse_loops_horizontal(expr: &mut Expr) {
                                         .collect::<Vec<_>>(),
                                 },
                                 annotations: Annotations::new(),
+                                span: None,
+                                id: None,
                             }),
                         },
                         annotations: Annotations::new(),
+                        span: None,
+                        id: None,
                     };
                     let new_func = Expr {
                         ty: Function(
@@ -200,6 +211,8 @@ pub fn fuse_loops_horizontal(expr: &mut Expr) {
                             body: Box::new(new_merge_expr),
                         },
                         annotations: Annotations::new(),
+                        span: None,
+                        id: None,
                     };
                     let new_iter_expr = Expr {
                         ty: Vector(Box::new(merge_type)),
@@ -212,13 +225,19 @@ pub fn fuse_loops_horizontal(expr: &mut Expr) {
                                         ty: builder_type,
                                         kind: NewBuilder(None),
                                         annotations: Annotations::new(),
+                                        span: None,
+                                        id: None,
                                     }),
                                     func: Box::new(new_func),
                                 },
                                 annotations: Annotations::new(),
+                                span: None,
+                                id: None,
                             }),
                         },
                         annotations: Annotations::new(),
+                        span: None,
+                        id: None,
                     };
 
                     // TODO(shoumik): Any way to avoid the clones here?
@@ -238,6 +257,8 @@ pub fn fuse_loops_horizontal(expr: &mut Expr) {
                             func: outer_func.clone(),
                         },
                         annotations: Annotations::new(),
+                        span: None,
+                        id: None,
                     });
                 }
             }
@@ -248,6 +269,11 @@ pub fn fuse_loops_horizontal(expr: &mut Expr) {
 
 /// Fuses loops where one for loop takes another as it's input, which prevents intermediate results
 /// from being materialized.
+///
+/// The fused loop runs the outer loop's function on each value the inner loop would have appended,
+/// with the inner loop's index. That index is only the position of the value in the intermediate
+/// vector if the inner loop appends exactly one value per iteration, so loops whose outer function
+/// uses its index are only fused in that case.
 pub fn fuse_loops_vertical(expr: &mut Expr) {
     expr.transform_and_continue_res(&mut |ref mut expr| {
         let mut sym_gen = SymbolGenerator::from_expression(expr);
@@ -269,7 +295,9 @@ pub fn fuse_loops_vertical(expr: &mut Expr) {
                         func: ref lambda,
                     } = res_bldr.kind
                     {
-                        if iters2.iter().all(|ref i| consumes_all(&i)) {
+                        if iters2.iter().all(consumes_all)
+                            && (!uses_index(nested) || merges_once(lambda))
+                        {
                             if let NewBuilder(_) = bldr2.kind {
                                 if let Builder(ref kind, _) = bldr2.ty {
                                     if let Appender(_) = *kind {
@@ -292,6 +320,143 @@ pub fn fuse_loops_vertical(expr: &mut Expr) {
     });
 }
 
+/// Moves an appender loop bound by a `Let` into the reduction that consumes its result, so the two
+/// loops can be fused by `fuse_loops_vertical` without materializing the intermediate vector.
+///
+/// `inline_let` already does this for symbols used once. This transform also handles results that
+/// are used for their length, which is common when computing averages:
+///
+/// ```weld
+/// let r = result(for(v, appender, |b,i,e| merge(b, e * 2)));
+/// result(for(r, merger[i32,+], |b,i,e| merge(b, e))) / i32(len(r))
+/// ```
+///
+/// If the producer merges exactly once per iteration, `len(r)` is replaced with `len(v)` and the
+/// producer is moved into the reduction. The result must not be used inside a function, since the
+/// producer would then be recomputed on each call.
+pub fn fuse_reductions(expr: &mut Expr) {
+    expr.uniquify().unwrap();
+    expr.transform_up(&mut |e| {
+        if let Let {
+            ref name,
+            ref value,
+            ref body,
+        } = e.kind
+        {
+            if let Res { ref builder } = value.kind {
+                if let For {
+                    ref iters,
+                    builder: ref init,
+                    ref func,
+                } = builder.kind
+                {
+                    if let (NewBuilder(_), Builder(Appender(_), _)) = (&init.kind, &init.ty) {
+                        let mut reductions = 0;
+                        let mut lengths = 0;
+                        if !reduction_uses(body, name, &mut reductions, &mut lengths)
+                            || reductions != 1
+                        {
+                            return None;
+                        }
+                        let length = if lengths > 0 {
+                            Some(producer_length(iters, func)?)
+                        } else {
+                            None
+                        };
+                        let mut body = body.as_ref().clone();
+                        body.transform(&mut |e| match e.kind {
+                            Length { ref data } if data.kind == Ident(name.clone()) => {
+                                length.clone()
+                            }
+                            Ident(ref sym) if sym == name => Some(value.as_ref().clone()),
+                            _ => None,
+                        });
+                        return Some(body);
+                    }
+                }
+            }
+        }
+        None
+    });
+}
+
+/// Counts the uses of `sym` in `expr` as the input of a loop into a merger and as the argument
+/// of `len`.
+///
+/// Returns `false` if `sym` is used in any other way or inside a function.
+fn reduction_uses(expr: &Expr, sym: &Symbol, reductions: &mut i32, lengths: &mut i32) -> bool {
+    let is_sym = |e: &Expr| e.kind == Ident(sym.clone());
+    match expr.kind {
+        Length { ref data } if is_sym(data) => {
+            *lengths += 1;
+            true
+        }
+        For {
+            ref iters,
+            ref builder,
+            ref func,
+        } if iters.len() == 1 && is_sym(&iters[0].data) => {
+            *reductions += 1;
+            matches!(builder.ty, Builder(Merger(_, _), _))
+                && iters[0].kind == IterKind::ScalarIter
+                && iters[0].start.is_none()
+                && !func.contains_symbol(sym)
+                && reduction_uses(builder, sym, reductions, lengths)
+        }
+        Ident(ref s) => s != sym,
+        Lambda { .. } => !expr.contains_symbol(sym),
+        _ => expr
+            .children()
+            .all(|child| reduction_uses(child, sym, reductions, lengths)),
+    }
+}
+
+/// Returns the number of values the loop with `iters` and function `func` appends, if it can be
+/// computed without evaluating the loop.
+fn producer_length(iters: &[Iter], func: &Expr) -> Option<Expr> {
+    if !merges_once(func) || !iters.iter().all(consumes_all) {
+        return None;
+    }
+    match iters[0].data.kind {
+        Ident(_) if iters[0].start.is_none() => {
+            Expr::new_length(iters[0].data.as_ref().clone()).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the loop function `func` uses its index parameter.
+fn uses_index(func: &Expr) -> bool {
+    match func.kind {
+        Lambda {
+            ref params,
+            ref body,
+        } => body.contains_symbol(&params[1].name),
+        _ => true,
+    }
+}
+
+/// Returns whether the loop function `func` merges exactly one value into its builder in every
+/// iteration.
+fn merges_once(func: &Expr) -> bool {
+    if let Lambda {
+        ref params,
+        ref body,
+    } = func.kind
+    {
+        if let Merge {
+            ref builder,
+            ref value,
+        } = body.kind
+        {
+            if let Ident(ref name) = builder.kind {
+                return *name == params[0].name && !value.contains_symbol(name);
+            }
+        }
+    }
+    false
+}
+
 /// Given an iterator, returns whether the iterator consumes every element of its data vector.
 fn consumes_all(iter: &Iter) -> bool {
     if let Iter {
@@ -449,6 +614,16 @@ fn match_types(root_ty: &Type, expr: &mut Expr) {
             match_types(root_ty, on_true);
             match_types(root_ty, on_false);
         }
+        Switch {
+            ref mut cases,
+            ref mut default,
+            ..
+        } => {
+            for case in cases.iter_mut() {
+                match_types(root_ty, &mut case.1);
+            }
+            match_types(root_ty, default);
+        }
         Let { ref mut body, .. } => {
             match_types(root_ty, body);
         }
@@ -589,6 +764,23 @@ fn simple_vertical_loop_fusion() {
     );
     assert!(e1.compare_ignoring_symbols(&e2).unwrap());
 
+    // The outer loop uses its index, which is only preserved if the inner loop appends one value
+    // per iteration.
+    let mut e1 = typed_expression(
+        "for(result(for([1,2,3], appender, |b,i,e| merge(b,e+2))), \
+         appender[i64], |b,h,f| merge(b, h))",
+    );
+    fuse_loops_vertical(&mut e1);
+    let e2 = typed_expression("for([1,2,3], appender[i64], |b,i,e| merge(b, i))");
+    assert!(e1.compare_ignoring_symbols(&e2).unwrap());
+
+    let code = "for(result(for([1,2,3], appender, |b,i,e| if(e>1, merge(b,e), b))), \
+                appender[i64], |b,h,f| merge(b, h))";
+    let mut e1 = typed_expression(code);
+    fuse_loops_vertical(&mut e1);
+    let e2 = typed_expression(code);
+    assert!(e1.compare_ignoring_symbols(&e2).unwrap());
+
     // Inner data not consumed fully.
     let mut e1 = typed_expression(
         "for(result(for(iter([1,2,3], 0L, 1L, 1L), appender, |b,i,e| \
@@ -602,3 +794,28 @@ fn simple_vertical_loop_fusion() {
     );
     assert!(e1.compare_ignoring_symbols(&e2).unwrap());
 }
+
+#[test]
+fn reduction_fusion() {
+    check_transform(
+        "|v:vec[i32]| let r = result(for(v, appender[i32], |b,i,e| merge(b, e * 2)));
+            result(for(r, merger[i32,+], |b,i,e| merge(b, e))) + i32(len(r))",
+        "|v:vec[i32]| result(for(result(for(v, appender[i32], |b,i,e| merge(b, e * 2))),
+            merger[i32,+], |b,i,e| merge(b, e))) + i32(len(v))",
+        fuse_reductions,
+    );
+    let codes = [
+        // The length of a filtered vector is not known without computing it.
+        "|v:vec[i32]| let r = result(for(v, appender[i32], |b,i,e| if(e > 0, merge(b, e), b)));
+            result(for(r, merger[i32,+], |b,i,e| merge(b, e))) + i32(len(r))",
+        // The result is also read by a loop that is not a reduction.
+        "|v:vec[i32]| let r = result(for(v, appender[i32], |b,i,e| merge(b, e * 2)));
+            {result(for(r, merger[i32,+], |b,i,e| merge(b, e))), result(for(r, appender[i32], |b,i,e| merge(b, e)))}",
+        // The reduction is inside a loop body and would recompute the result.
+        "|v:vec[i32]| let r = result(for(v, appender[i32], |b,i,e| merge(b, e * 2)));
+            for(v, merger[i32,+], |b,i,e| merge(b, result(for(r, merger[i32,+], |b2,j,f| merge(b2, f + e)))))",
+    ];
+    for code in codes.iter() {
+        check_transform(code, code, fuse_reductions);
+    }
+}
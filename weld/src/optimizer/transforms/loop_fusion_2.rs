@@ -8,8 +8,6 @@ use crate::error::*;
 
 use crate::util::SymbolGenerator;
 
-use fnv;
-
 struct MergeSingle<'a> {
     params: &'a Vec<Parameter>,
     value: &'a Expr,
@@ -75,6 +73,43 @@ impl<'a> ResForAppender<'a> {
     }
 }
 
+/// A `result(for(iters, newbuilder, |b,i,e| body))` expression.
+struct ResForNewBuilder<'a> {
+    iters: &'a Vec<Iter>,
+    builder: &'a Expr,
+    params: &'a Vec<Parameter>,
+    body: &'a Expr,
+}
+
+impl<'a> ResForNewBuilder<'a> {
+    fn extract(expr: &'a Expr) -> Option<ResForNewBuilder<'a>> {
+        if let Res { ref builder } = expr.kind {
+            if let For {
+                ref iters,
+                ref builder,
+                ref func,
+            } = builder.kind
+            {
+                if let NewBuilder(_) = builder.kind {
+                    if let Lambda {
+                        ref params,
+                        ref body,
+                    } = func.kind
+                    {
+                        return Some(ResForNewBuilder {
+                            iters,
+                            builder,
+                            params,
+                            body,
+                        });
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
 struct MapIter<'a> {
     iters: &'a Vec<Iter>,
     merge_params: &'a Vec<Parameter>,
@@ -141,7 +176,7 @@ pub fn fuse_loops_2(expr: &mut Expr) {
                 let mut new_body = body.as_ref().clone();
 
                 let elem_types: Vec<Type>;
-                if let Struct(ref types) = params[2].ty {
+                if let Struct(ref types, _) = params[2].ty {
                     elem_types = types.clone();
                 } else {
                     elem_types = vec![params[2].ty.clone()];
@@ -237,7 +272,7 @@ pub fn fuse_loops_2(expr: &mut Expr) {
                 }
 
                 let new_param_type = if new_elem_types.len() > 1 {
-                    Struct(new_elem_types.clone())
+                    Struct(new_elem_types.clone(), FieldNames::none())
                 } else {
                     new_elem_types[0].clone()
                 };
@@ -395,199 +430,174 @@ pub fn aggressive_inline_let(expr: &mut Expr) {
     });
 }
 
-/// Merges { result(for(appender, result(for(appender( } over the same set of loops into
-/// a single loop of result(for({appender,appender} ...)).
+/// Merges a struct of `result(for(...))` loops over the same data into a single loop over a struct
+/// of builders. For example,
+///
+/// ```text
+/// {result(for(v, merger[i32,+], |b,i,e| merge(b, e))),
+///  result(for(v, merger[i32,min], |b,i,e| merge(b, e)))}
+/// ```
+///
+/// becomes
+///
+/// ```text
+/// let tmp = for(v, {merger[i32,+], merger[i32,min]}, |bs,i,e| {merge(bs.$0, e), merge(bs.$1, e)});
+/// {result(tmp.$0), result(tmp.$1)}
+/// ```
+///
+/// The loops must either have the same iterators, or iterate over all the elements of the same
+/// identifiers, possibly zipped in different orders.
 ///
 /// TODO this can definitely be generalized to capture more cases (e.g., the result doesn't
 /// necessarily need to be in a `MakeStruct` expression).
 ///
 /// Prerequisites: Expression is uniquified.
-/// Caveats: This transformation will only fire if each vector in the iterator is bound to an
-/// identifier.
 pub fn merge_makestruct_loops(expr: &mut Expr) {
     expr.uniquify().unwrap();
+    let mut gen = SymbolGenerator::from_expression(expr);
     expr.transform(&mut |ref mut expr| {
         if let MakeStruct { ref elems } = expr.kind {
-            // Each member of the `GetStruct` must be a ResForAppender pattern.
-            if elems.len() > 2
-                || !elems
-                    .iter()
-                    .all(|ref e| ResForAppender::extract(e).is_some())
-            {
+            if elems.len() < 2 {
                 return None;
             }
-            let rfas: Vec<_> = elems
+            let loops = elems
                 .iter()
-                .map(|ref e| ResForAppender::extract(e).unwrap())
-                .collect();
-
-            // Make sure all the iterators are simple, and each map just has a single merge.
-            let all_iters_simple = rfas.iter().all(|ref rfa| {
-                rfa.iters.iter().all(|ref iter| iter.is_simple())
-                    && MergeSingle::extract(rfa.func).is_some()
-            });
-
-            if !all_iters_simple {
-                return None;
-            }
-
-            // For each Iter, holds a map from name -> index. The indices are required to rewrite
-            // struct accesses. Also keep a HashSet of the identifiers in each iterator.
-            let mut ident_indices = vec![];
-            let mut idents = vec![];
-
-            // This is the "authoratative map", i.e., the GetField indexing every other body will be transformed
-            // to use. It maps the *index to the name* (reverse of all the other maps).
-            let mut first_rfa_map = fnv::FnvHashMap::default();
-
-            for (i, rfa) in rfas.iter().enumerate() {
-                let mut map = fnv::FnvHashMap::default();
-                let mut set = fnv::FnvHashSet::default();
-                for (j, iter) in rfa.iters.iter().enumerate() {
-                    if let Ident(ref name) = iter.data.kind {
-                        map.insert(j, name);
-                        set.insert(name);
-                        // Only for first one.
-                        if i == 0 {
-                            first_rfa_map.insert(name, j);
-                        }
-                    } else {
+                .map(ResForNewBuilder::extract)
+                .collect::<Option<Vec<_>>>()?;
+            let first = &loops[0];
+
+            // For each loop, maps the index of each field of its element to the index of the
+            // field with the same data in the first loop's element. This is `None` if every loop
+            // has the same iterators.
+            let field_maps = if loops.iter().all(|l| same_iters(l.iters, first.iters)) {
+                None
+            } else {
+                Some(zipped_field_indices(&loops)?)
+            };
+
+            let builder_ty = Struct(
+                loops.iter().map(|l| l.builder.ty.clone()).collect(),
+                FieldNames::none(),
+            );
+            let builder_sym = gen.new_symbol("bs");
+            let builders = Expr::new_ident(builder_sym.clone(), builder_ty.clone()).ok()?;
+            let index =
+                Expr::new_ident(first.params[1].name.clone(), first.params[1].ty.clone()).ok()?;
+            let elem =
+                Expr::new_ident(first.params[2].name.clone(), first.params[2].ty.clone()).ok()?;
+
+            // Rewrite each loop body to merge into its field of the struct of builders, using the
+            // index and element of the first loop.
+            let mut bodies = vec![];
+            for (i, l) in loops.iter().enumerate() {
+                let mut body = l.body.clone();
+                if let Some(ref field_maps) = field_maps {
+                    let elem_sym = &l.params[2].name;
+                    if !only_fields_used(&body, elem_sym) {
                         return None;
                     }
-                }
-                ident_indices.push(map);
-                idents.push(set);
-            }
-
-            // For the future when we may support iteration over ranges.
-            if idents.is_empty() {
-                return None;
-            }
-
-            // Now, make sure each iterator has the same set of vectors (but perhaps in a different
-            // ordering).
-            if !idents.iter().all(|e| idents[0] == *e) {
-                return None;
-            }
-
-            //
-            // We now have a struct of ResForAppender patterns over the same data. Rewrite this to be a
-            // ResForAppender over a struct of appender.
-            //
-
-            // Safe to unwrap since we checked it above.
-            let first_ma = MergeSingle::extract(rfas[0].func).unwrap();
-
-            // Construct the new builder type for the loop/etc.
-
-            // For each RFA, get the first parameter of the builder function and copy its type.
-            let types: Vec<_> = rfas
-                .iter()
-                .map(|ref rfa| MergeSingle::extract(rfa.func).unwrap().params[0].ty.clone())
-                .collect();
-            let final_builder_ty = Struct(types);
-
-            let mut bodies = vec![];
-
-            for (i, rfa) in rfas.iter().enumerate() {
-                let ma = MergeSingle::extract(rfa.func).unwrap();
-                let mut new_body = ma.value.clone();
-
-                // If the element is a Struct that Zips multiple vectors, we need to rewrite the
-                // indexing to match the first body. This handles zips over the same vectors in a
-                // different order (which could be common if upstream transforms shuffle things around).
-                if rfa.iters.len() > 1 {
-                    let rev_map = &ident_indices[i];
-
-                    // third parameter is the element.
-                    let elem_name = &ma.params[2];
-
-                    // This will be a no-op for the first iterator.
-                    new_body.transform(&mut |ref mut e| {
+                    let field_map = &field_maps[i];
+                    body.transform_and_continue(&mut |ref mut e| {
                         if let GetField {
                             ref mut expr,
                             ref mut index,
                         } = e.kind
                         {
                             if let Ident(ref name) = expr.kind {
-                                if *name == elem_name.name && expr.ty == elem_name.ty {
-                                    // Get the vector identifier this index refers to.
-                                    let vec_name = rev_map.get(&(*index as usize)).unwrap();
-                                    let change_to = first_rfa_map[vec_name];
-                                    *index = change_to as u32;
+                                if name == elem_sym {
+                                    *index = field_map[*index as usize] as u32;
                                 }
                             }
                         }
-                        // Expression is modified in place.
-                        None
+                        (None, true)
                     });
                 }
-
-                // Substitute the parameter names to use the ones from the first body.
-                // Skip the first one since that's the builder, and since this is matching a
-                // MergeSingle pattern, we shouldn't have any builders in here.
-                for (j, ref param) in ma.params.iter().enumerate() {
-                    let replacement =
-                        &Expr::new_ident(first_ma.params[j].name.clone(), param.ty.clone())
-                            .unwrap();
-                    new_body.substitute(&param.name, replacement);
-                }
-                // Add the new merge expression to the list of bodies.
-                let builder_expr = Expr::new_get_field(
-                    Expr::new_ident(first_ma.params[0].name.clone(), final_builder_ty.clone())
-                        .unwrap(),
-                    i as u32,
-                )
-                .unwrap();
-                bodies.push(Expr::new_merge(builder_expr, new_body).unwrap());
+                let builder = Expr::new_get_field(builders.clone(), i as u32).ok()?;
+                body.substitute(&l.params[0].name, &builder);
+                body.substitute(&l.params[1].name, &index);
+                body.substitute(&l.params[2].name, &elem);
+                bodies.push(body);
             }
 
-            let final_iters = rfas[0].iters.clone();
-            let mut newbuilders = vec![];
-
-            // Pull out the new builders and clone them into a vector.
-            for elem in elems.iter() {
-                if let Res { ref builder } = elem.kind {
-                    if let For { ref builder, .. } = builder.kind {
-                        newbuilders.push(builder.as_ref().clone());
-                    }
-                }
-            }
-
-            // Since we extracted RFAs from all of them...
-            assert!(newbuilders.len() == elems.len());
-
-            // Build the function and final body.
-            let final_body = Expr::new_make_struct(bodies).unwrap();
-            let mut final_params = first_ma.params.clone();
-            final_params[0].ty = final_builder_ty.clone();
-
-            let final_func = Expr::new_lambda(final_params, final_body).unwrap();
-            let final_loop = Expr::new_for(
-                final_iters,
-                Expr::new_make_struct(newbuilders).unwrap(),
-                final_func,
-            )
-            .unwrap();
-
-            let mut gen = SymbolGenerator::from_expression(expr);
-            let struct_name = gen.new_symbol("tmp");
+            let mut params = first.params.clone();
+            params[0] = Parameter {
+                name: builder_sym,
+                ty: builder_ty.clone(),
+            };
+            let func = Expr::new_lambda(params, Expr::new_make_struct(bodies).ok()?).ok()?;
+            let new_builders = loops.iter().map(|l| l.builder.clone()).collect();
+            let new_builders = Expr::new_make_struct(new_builders).ok()?;
+            let fused = Expr::new_for(first.iters.clone(), new_builders, func).ok()?;
+
+            let tmp = gen.new_symbol("tmp");
+            let tmp_ident = Expr::new_ident(tmp.clone(), builder_ty).ok()?;
+            let results = (0..loops.len())
+                .map(|i| Expr::new_result(Expr::new_get_field(tmp_ident.clone(), i as u32)?))
+                .collect::<WeldResult<Vec<_>>>()
+                .ok()?;
+            let results = Expr::new_make_struct(results).ok()?;
+            return Expr::new_let(tmp, fused, results).ok();
+        }
+        None
+    });
+}
 
-            let builder_iden = Expr::new_ident(struct_name.clone(), final_builder_ty).unwrap();
+/// Returns whether two lists of iterators are equivalent, ignoring symbols defined inside them.
+fn same_iters(iters1: &[Iter], iters2: &[Iter]) -> bool {
+    iters1.len() == iters2.len()
+        && iters1
+            .iter()
+            .zip(iters2)
+            .all(|(a, b)| iters_match_ignoring_symbols(a, b).unwrap_or(false))
+}
 
-            let results = (0..rfas.len())
-                .map(|i| {
-                    Expr::new_result(Expr::new_get_field(builder_iden.clone(), i as u32).unwrap())
-                        .unwrap()
-                })
-                .collect();
-            let results = Expr::new_make_struct(results).unwrap();
-            let final_expr = Expr::new_let(struct_name, final_loop, results).unwrap();
+/// For loops that each iterate over all the elements of the same set of identifiers, returns a map
+/// for each loop from the index of an iterator to the index of the iterator over the same
+/// identifier in the first loop.
+///
+/// Returns `None` if the loops do not iterate over the same identifiers.
+fn zipped_field_indices(loops: &[ResForNewBuilder<'_>]) -> Option<Vec<Vec<usize>>> {
+    let names = |l: &ResForNewBuilder<'_>| -> Option<Vec<Symbol>> {
+        l.iters
+            .iter()
+            .map(|iter| match iter.data.kind {
+                Ident(ref name) if iter.is_simple() => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    let first = names(&loops[0])?;
+    loops
+        .iter()
+        .map(|l| {
+            let names = names(l)?;
+            if names.len() != first.len() {
+                return None;
+            }
+            names
+                .iter()
+                .map(|name| first.iter().position(|n| n == name))
+                .collect()
+        })
+        .collect()
+}
 
-            return Some(final_expr);
+/// Returns whether `expr` only uses the symbol `sym` to access its fields.
+fn only_fields_used(expr: &Expr, sym: &Symbol) -> bool {
+    let mut uses = 0;
+    let mut field_uses = 0;
+    expr.traverse(&mut |e| match e.kind {
+        Ident(ref name) if name == sym => uses += 1,
+        GetField { ref expr, .. } => {
+            if let Ident(ref name) = expr.kind {
+                if name == sym {
+                    field_uses += 1;
+                }
+            }
         }
-        None
+        _ => (),
     });
+    uses == field_uses
 }
 
 /// Are two iterators equivalent ignoring symbols defined inside each one?
@@ -596,7 +606,9 @@ fn iters_match_ignoring_symbols(iter1: &Iter, iter2: &Iter) -> WeldResult<bool>
         && iter1.data.compare_ignoring_symbols(iter2.data.as_ref())?
         && options_match_ignoring_symbols(&iter1.start, &iter2.start)?
         && options_match_ignoring_symbols(&iter1.end, &iter2.end)?
-        && options_match_ignoring_symbols(&iter1.stride, &iter2.stride)?)
+        && options_match_ignoring_symbols(&iter1.stride, &iter2.stride)?
+        && options_match_ignoring_symbols(&iter1.shape, &iter2.shape)?
+        && options_match_ignoring_symbols(&iter1.strides, &iter2.strides)?)
 }
 
 /// Are two Option<Box<Expr>> equal ignoring symbols defined inside each one?
@@ -610,3 +622,64 @@ fn options_match_ignoring_symbols(
         _ => Ok(false),
     }
 }
+
+#[cfg(test)]
+use crate::tests::*;
+
+#[cfg(test)]
+fn check_makestruct_fusion(code: &str, expected: &str) {
+    let mut e = typed_expression(code);
+    merge_makestruct_loops(&mut e);
+    let expected = typed_expression(expected);
+    assert!(
+        e.compare_ignoring_symbols(&expected).unwrap(),
+        "got {}",
+        print_expr_without_indent(&e)
+    );
+}
+
+#[test]
+fn makestruct_loop_fusion() {
+    // Several aggregates over the same vector.
+    check_makestruct_fusion(
+        "|v:vec[i32]| {result(for(v, merger[i32,+], |b,i,e| merge(b, e))),
+                       result(for(v, merger[i64,+], |b,i,e| merge(b, 1L))),
+                       result(for(v, merger[i32,min], |b,i,e| if(e > 0, merge(b, e), b)))}",
+        "|v:vec[i32]| let tmp = for(v, {merger[i32,+], merger[i64,+], merger[i32,min]},
+            |bs,i,e| {merge(bs.$0, e), merge(bs.$1, 1L), if(e > 0, merge(bs.$2, e), bs.$2)});
+          {result(tmp.$0), result(tmp.$1), result(tmp.$2)}",
+    );
+
+    // Loops with the same bounds.
+    check_makestruct_fusion(
+        "|v:vec[i32]| {result(for(iter(v, 1L, 3L, 1L), appender[i32], |b,i,e| merge(b, e))),
+                       result(for(iter(v, 1L, 3L, 1L), merger[i64,+], |b,i,e| merge(b, i)))}",
+        "|v:vec[i32]| let tmp = for(iter(v, 1L, 3L, 1L), {appender[i32], merger[i64,+]},
+            |bs,i,e| {merge(bs.$0, e), merge(bs.$1, i)});
+          {result(tmp.$0), result(tmp.$1)}",
+    );
+
+    // Zips over the same vectors in a different order.
+    check_makestruct_fusion(
+        "|a:vec[i32], c:vec[i32]|
+            {result(for(zip(a, c), merger[i32,+], |b,i,e| merge(b, e.$0 - e.$1))),
+             result(for(zip(c, a), merger[i32,+], |b,i,e| merge(b, e.$0 * 2)))}",
+        "|a:vec[i32], c:vec[i32]| let tmp = for(zip(a, c), {merger[i32,+], merger[i32,+]},
+            |bs,i,e| {merge(bs.$0, e.$0 - e.$1), merge(bs.$1, e.$1 * 2)});
+          {result(tmp.$0), result(tmp.$1)}",
+    );
+
+    // Loops over different data or with different bounds are not fused.
+    let unchanged = [
+        "|a:vec[i32], c:vec[i32]| {result(for(a, merger[i32,+], |b,i,e| merge(b, e))),
+                                   result(for(c, merger[i32,+], |b,i,e| merge(b, e)))}",
+        "|v:vec[i32]| {result(for(iter(v, 0L, 2L, 1L), merger[i32,+], |b,i,e| merge(b, e))),
+                       result(for(v, merger[i32,+], |b,i,e| merge(b, e)))}",
+        "|a:vec[i32], c:vec[i32]|
+            {result(for(zip(a, c), appender[{i32,i32}], |b,i,e| merge(b, e))),
+             result(for(zip(c, a), merger[i32,+], |b,i,e| merge(b, e.$0)))}",
+    ];
+    for code in unchanged.iter() {
+        check_makestruct_fusion(code, code);
+    }
+}
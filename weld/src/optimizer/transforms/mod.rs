@@ -1,8 +1,11 @@
 //! Common transformations on expressions.
 
+pub mod adaptive;
 pub mod algebraic;
 pub mod cse;
+pub mod dead_code;
 pub mod inliner;
+pub mod licm;
 pub mod loop_fusion;
 pub mod loop_fusion_2;
 pub mod short_circuit;
@@ -64,6 +64,60 @@ pub fn short_circuit_booleans(expr: &mut Expr) {
     }
 }
 
+/// Rewrites `&&` and `||` expressions whose right operand is expensive or may fail into `If`
+/// expressions.
+///
+/// `&&` and `||` only evaluate their right operand if the left operand does not determine the
+/// result, but code generation evaluates both operands of a binary operator. This lowering runs
+/// after the optimization passes so the semantics hold even if `short_circuit_booleans` does not
+/// run. Right operands that are cheap and cannot fail are still evaluated eagerly, since the
+/// result is the same and the generated code does not branch. SIMD operands are evaluated in
+/// every lane regardless, so the vectorizer does not vectorize `&&` or `||` whose right operand
+/// may fail.
+pub fn lower_short_circuit(expr: &mut Expr) {
+    expr.transform_up(&mut |e| match e.kind {
+        BinOp {
+            kind,
+            ref left,
+            ref right,
+        } if (kind == BinOpKind::LogicalAnd || kind == BinOpKind::LogicalOr)
+            && !e.ty.is_simd()
+            && !is_cheap(right) =>
+        {
+            let left = left.as_ref().clone();
+            let right = right.as_ref().clone();
+            let literal = |b| Expr::new_literal(LiteralKind::BoolLiteral(b)).ok();
+            if kind == BinOpKind::LogicalAnd {
+                Expr::new_if(left, right, literal(false)?).ok()
+            } else {
+                Expr::new_if(left, literal(true)?, right).ok()
+            }
+        }
+        _ => None,
+    });
+}
+
+/// Returns whether `expr` is cheap to evaluate and cannot fail.
+fn is_cheap(expr: &Expr) -> bool {
+    let mut cheap = true;
+    expr.traverse(&mut |e| {
+        cheap &= !e.kind.may_fail()
+            && matches!(
+                e.kind,
+                Literal(_)
+                    | Ident(_)
+                    | GetField { .. }
+                    | Length { .. }
+                    | BinOp { .. }
+                    | UnaryOp { .. }
+                    | Negate(_)
+                    | Not(_)
+                    | Cast { .. }
+            );
+    });
+    cheap
+}
+
 /// Parse and perform type inference on an expression.
 #[cfg(test)]
 fn typed_expression(code: &str) -> Expr {
@@ -152,3 +206,22 @@ fn predicated_if() {
     );
     assert!(e.compare_ignoring_symbols(expect).unwrap());
 }
+
+#[test]
+fn lower_expensive_operands() {
+    let mut e = typed_expression("|x:i32, v:vec[i32]| x > 0 && lookup(v, i64(x)) > 2");
+    lower_short_circuit(&mut e);
+    let expect = &typed_expression("|x:i32, v:vec[i32]| if(x > 0, lookup(v, i64(x)) > 2, false)");
+    assert!(e.compare_ignoring_symbols(expect).unwrap());
+
+    let mut e = typed_expression("|x:i32| x == 0 || 10 / x > 2");
+    lower_short_circuit(&mut e);
+    let expect = &typed_expression("|x:i32| if(x == 0, true, 10 / x > 2)");
+    assert!(e.compare_ignoring_symbols(expect).unwrap());
+
+    // Cheap operands that cannot fail are evaluated eagerly.
+    let code = "|x:i32, y:i32| x > 5 && (y < 10 || -x == y / 2)";
+    let mut e = typed_expression(code);
+    lower_short_circuit(&mut e);
+    assert!(e.compare_ignoring_symbols(&typed_expression(code)).unwrap());
+}
@@ -1,6 +1,8 @@
-//! Analyzes the `loopsize` annotation to unroll loops into a series of `Lookup` nodes at compile
-//! time.
+//! Unrolls loops with a small number of iterations into straight-line code at compile time.
 //!
+//! The number of iterations is read from the `loopsize` annotation, or inferred if the loop only
+//! iterates over vector literals. Elements are read with `Lookup` nodes, or directly from the
+//! vector literal, which is then never built.
 
 use std::error::Error;
 
@@ -44,39 +46,44 @@ impl<'a> UnrollPattern<'a> {
     /// Extracts a `UnrollPattern` from the expression, or returns `None`.
     fn extract(expr: &'a Expr) -> Option<UnrollPattern<'_>> {
         if let Res { ref builder } = expr.kind {
-            if let Some(loopsize) = builder.loopsize() {
-                if loopsize <= UNROLL_LIMIT {
-                    if let For {
-                        ref iters,
-                        ref builder,
-                        ref func,
-                    } = builder.kind
+            if let For {
+                ref iters,
+                builder: ref init,
+                ref func,
+            } = builder.kind
+            {
+                let loopsize = builder.loopsize().or_else(|| static_size(iters))?;
+                if loopsize > UNROLL_LIMIT {
+                    return None;
+                }
+                // The unrolled values replace the builder, so it must start out empty.
+                match (&init.kind, &init.ty) {
+                    (&NewBuilder(None), _) | (&NewBuilder(_), &Builder(Appender(_), _)) => (),
+                    _ => return None,
+                }
+                if let Builder(ref bk, _) = init.ty {
+                    if let Lambda {
+                        ref params,
+                        ref body,
+                    } = func.kind
                     {
-                        if let Builder(ref bk, _) = builder.ty {
-                            if let Lambda {
-                                ref params,
-                                ref body,
-                            } = func.kind
-                            {
-                                if let Merge {
-                                    builder: ref builder2,
-                                    ref value,
-                                } = body.kind
-                                {
-                                    match builder2.kind {
-                                        Ident(ref name) if *name == params[0].name => {
-                                            return Some(UnrollPattern {
-                                                loop_size: loopsize,
-                                                iters,
-                                                builder_kind: bk,
-                                                merge_params: params,
-                                                merge_value: value,
-                                            });
-                                        }
-                                        _ => {
-                                            return None;
-                                        }
-                                    }
+                        if let Merge {
+                            builder: ref builder2,
+                            ref value,
+                        } = body.kind
+                        {
+                            match builder2.kind {
+                                Ident(ref name) if *name == params[0].name => {
+                                    return Some(UnrollPattern {
+                                        loop_size: loopsize,
+                                        iters,
+                                        builder_kind: bk,
+                                        merge_params: params,
+                                        merge_value: value,
+                                    });
+                                }
+                                _ => {
+                                    return None;
                                 }
                             }
                         }
@@ -88,6 +95,25 @@ impl<'a> UnrollPattern<'a> {
     }
 }
 
+/// Returns the number of iterations of a loop over `iters` if every iterator reads all the
+/// elements of a vector literal, e.g., `for([a, b, c], ...)`.
+fn static_size(iters: &[Iter]) -> Option<u64> {
+    let mut size = None;
+    for iter in iters {
+        match iter.data.kind {
+            MakeVector { ref elems } if iter.start.is_none() => {
+                let len = elems.len() as u64;
+                if size.is_some() && size != Some(len) {
+                    return None;
+                }
+                size = Some(len);
+            }
+            _ => return None,
+        }
+    }
+    size
+}
+
 pub fn unroll_static_loop(expr: &mut Expr) {
     use crate::util::SymbolGenerator;
 
@@ -98,17 +124,38 @@ pub fn unroll_static_loop(expr: &mut Expr) {
     let mut sym_gen = SymbolGenerator::from_expression(expr);
     expr.transform_up(&mut |ref mut expr| {
         if let Some(pat) = UnrollPattern::extract(expr) {
-            // Create a vector of identifiers which will bind to the iterator data.
-            let symbols: Vec<_> = (0..pat.iters.len())
-                .map(|_| sym_gen.new_symbol("tmp"))
-                .collect();
-            let idents: Vec<_> = symbols
-                .iter()
-                .zip(pat.iters.iter())
-                .map(|ref t| Expr::new_ident(t.0.clone(), t.1.data.ty.clone()).unwrap())
-                .collect();
-
-            let vals = unroll_values(pat.merge_params, pat.merge_value, &idents, pat.loop_size);
+            // Bind the iterator data to identifiers, and build the expressions that read the
+            // element of each iterator in each iteration. Elements of vector literals are bound
+            // directly, so the vector is never built.
+            let mut bindings = vec![];
+            let mut elements = vec![];
+            for iter in pat.iters.iter() {
+                match iter.data.kind {
+                    MakeVector { ref elems } if iter.start.is_none() => {
+                        let mut idents = vec![];
+                        for elem in elems.iter() {
+                            let sym = sym_gen.new_symbol("tmp");
+                            idents.push(Expr::new_ident(sym.clone(), elem.ty.clone()).unwrap());
+                            bindings.push((sym, elem.clone()));
+                        }
+                        elements.push(idents);
+                    }
+                    _ => {
+                        let sym = sym_gen.new_symbol("tmp");
+                        let ident = Expr::new_ident(sym.clone(), iter.data.ty.clone()).unwrap();
+                        bindings.push((sym, iter.data.as_ref().clone()));
+                        let lookups = (0..pat.loop_size)
+                            .map(|i| {
+                                let index = Expr::new_literal(LiteralKind::I64Literal(i as i64));
+                                Expr::new_lookup(ident.clone(), index.unwrap()).unwrap()
+                            })
+                            .collect();
+                        elements.push(lookups);
+                    }
+                }
+            }
+
+            let vals = unroll_values(pat.merge_params, pat.merge_value, &elements, pat.loop_size);
             if let Err(err) = vals {
                 trace!("Unroller error: {}", err.description());
                 return None;
@@ -123,10 +170,8 @@ pub fn unroll_static_loop(expr: &mut Expr) {
 
             // Prepend the identifier definitions to the combined expression.
             let mut prev = combined_expr.unwrap();
-            for (ref sym, ref iter) in symbols.into_iter().rev().zip(pat.iters.iter().rev()) {
-                // Construct this explicitly instead of using the `expr` based constructor
-                // to allow a move of the boxed value, avoiding a copy.
-                prev = Expr::new_let(sym.clone(), iter.data.as_ref().clone(), prev).unwrap();
+            for (sym, value) in bindings.into_iter().rev() {
+                prev = Expr::new_let(sym, value, prev).unwrap();
             }
             Some(prev)
         } else {
@@ -144,12 +189,14 @@ fn is_same_ident(expr: &Expr, other: &Expr) -> bool {
     false
 }
 
-/// Takes a `MergeSingle` and returns a list of expressions which replace the element
-/// in the merge with a Lookup.
+/// Takes a `MergeSingle` and returns a list of expressions which replace the element in the
+/// merge with the element of each iteration.
+///
+/// `elements[k][i]` reads the element of the `k`th iterator in iteration `i`.
 fn unroll_values(
     parameters: &[Parameter],
     value: &Expr,
-    vectors: &[Expr],
+    elements: &[Vec<Expr>],
     loopsize: u64,
 ) -> WeldResult<Vec<Expr>> {
     if parameters.len() != 3 {
@@ -161,7 +208,7 @@ fn unroll_values(
     let elem_ident = &Expr::new_ident(elem_symbol.clone(), parameters[2].ty.clone())?;
 
     let mut expressions = vec![];
-    for i in 0..loopsize {
+    for i in 0..loopsize as usize {
         let mut unrolled_value = value.clone();
         unrolled_value.transform(&mut |ref mut e| {
             match e.kind {
@@ -169,31 +216,23 @@ fn unroll_values(
                     // Index identifiers can be handled by just substituting a static index.
                     Some(Expr::new_literal(LiteralKind::I64Literal(i as i64)).unwrap())
                 }
-                Ident(ref name) if name == elem_symbol && vectors.len() == 1 => {
+                Ident(ref name) if name == elem_symbol && elements.len() == 1 => {
                     // There is a single iterator, which means the type of the element is the type
-                    // of the iterator's data. Replace it with a lookup into the vector.
-                    Some(
-                        Expr::new_lookup(
-                            vectors[0].clone(),
-                            Expr::new_literal(LiteralKind::I64Literal(i as i64)).unwrap(),
-                        )
-                        .unwrap(),
-                    )
+                    // of the iterator's data.
+                    Some(elements[0][i].clone())
                 }
                 GetField {
                     ref expr,
                     ref index,
-                } if is_same_ident(expr, elem_ident) && vectors.len() > 1 => {
+                } if is_same_ident(expr, elem_ident) && elements.len() > 1 => {
                     // There are multiple iterators zipped into a struct, and this expression is
-                    // pulling one of the elements out of that struct. Replace it with a lookup into the vector.
-                    let data_expr = vectors[*index as usize].clone();
-                    Some(
-                        Expr::new_lookup(
-                            data_expr,
-                            Expr::new_literal(LiteralKind::I64Literal(i as i64)).unwrap(),
-                        )
-                        .unwrap(),
-                    )
+                    // pulling one of the elements out of that struct.
+                    Some(elements[*index as usize][i].clone())
+                }
+                Ident(ref name) if name == elem_symbol => {
+                    // The struct of zipped elements is used as a whole.
+                    let fields = elements.iter().map(|elems| elems[i].clone()).collect();
+                    Some(Expr::new_make_struct(fields).unwrap())
                 }
                 _ => None,
             }
@@ -212,9 +251,10 @@ fn combine_unrolled_values(bk: BuilderKind, values: Vec<Expr>) -> WeldResult<Exp
     if values.is_empty() {
         return compile_err!("Need at least one value to combine in unroller");
     }
+    let merge_type = bk.merge_type();
     match bk {
-        Merger(ref ty, ref binop) => {
-            if values.iter().any(|ref expr| expr.ty != *ty.as_ref()) {
+        Merger(_, ref binop) => {
+            if values.iter().any(|expr| expr.ty != merge_type) {
                 return compile_err!("Mismatched types in Merger and unrolled values.");
             }
             // Use the specified binary op to produce the final expression.
@@ -228,8 +268,8 @@ fn combine_unrolled_values(bk: BuilderKind, values: Vec<Expr>) -> WeldResult<Exp
             }
             Ok(prev.unwrap())
         }
-        Appender(ref ty) => {
-            if values.iter().any(|ref expr| expr.ty != *ty.as_ref()) {
+        Appender(_) => {
+            if values.iter().any(|expr| expr.ty != merge_type) {
                 return compile_err!("Mismatched types in Appender and unrolled values.");
             }
             Expr::new_make_vector(values)
@@ -325,3 +365,36 @@ fn large_merger_loop() {
     unroll_static_loop(&mut e);
     assert!(e.compare_ignoring_symbols(expect).unwrap());
 }
+
+#[test]
+fn vector_literal_loop() {
+    // Loops over vector literals do not need an annotation.
+    let mut e = typed_expression(
+        "|x:i32, y:i32| result(for([x, y, 3], merger[i32,+], |b,i,e| merge(b, e * e)))",
+    );
+    unroll_static_loop(&mut e);
+    let expect = &typed_expression(
+        "|x:i32, y:i32| let t0 = x; let t1 = y; let t2 = 3; t0 * t0 + t1 * t1 + t2 * t2",
+    );
+    assert!(e.compare_ignoring_symbols(expect).unwrap());
+
+    let mut e = typed_expression(
+        "|x:f64, y:f64| result(for(zip([x, y], [1.0, 2.0]), appender, |b,i,e| merge(b, e)))",
+    );
+    unroll_static_loop(&mut e);
+    let expect = &typed_expression(
+        "|x:f64, y:f64| let t0 = x; let t1 = y; let t2 = 1.0; let t3 = 2.0; [{t0, t2}, {t1, t3}]",
+    );
+    assert!(e.compare_ignoring_symbols(expect).unwrap());
+
+    // Vectors of different lengths and builders with initial values are not unrolled.
+    let codes = [
+        "|x:i32| result(for(zip([x, 2], [x]), merger[i32,+], |b,i,e| merge(b, e.$0)))",
+        "|x:i32| result(for([x, 2], merger[i32,+](5), |b,i,e| merge(b, e)))",
+    ];
+    for code in codes.iter() {
+        let mut e = typed_expression(code);
+        unroll_static_loop(&mut e);
+        assert!(e.compare_ignoring_symbols(&typed_expression(code)).unwrap());
+    }
+}
@@ -17,7 +17,10 @@ use crate::tests::*;
 
 /// Checks whether an annotation specifies predication.
 pub trait ShouldPredicate {
+    /// Returns whether the annotation requires predication.
     fn should_predicate(&self) -> bool;
+    /// Returns whether the annotation allows predication.
+    fn allows_predicate(&self) -> bool;
 }
 
 impl ShouldPredicate for Expr {
@@ -28,10 +31,30 @@ impl ShouldPredicate for Expr {
         }
         false
     }
+
+    fn allows_predicate(&self) -> bool {
+        match self.annotations.get("predicate") {
+            Some(value) => value.to_lowercase() != "false",
+            None => true,
+        }
+    }
 }
 
 /// Vectorize an expression.
+///
+/// Loops that add or multiply floating point values into a merger are not vectorized, since
+/// vectorization changes the order in which the values are combined.
 pub fn vectorize(expr: &mut Expr) {
+    vectorize_loops(expr, false)
+}
+
+/// Vectorize an expression, treating floating point addition and multiplication in mergers as
+/// associative.
+pub fn vectorize_fast_math(expr: &mut Expr) {
+    vectorize_loops(expr, true)
+}
+
+fn vectorize_loops(expr: &mut Expr, fast_math: bool) {
     let mut vectorized = false;
     // Used to create the identifiers which refer to the data items. These identifiers are
     // used to pull out the iter into a let statement. This lets us repeat the iter via an
@@ -40,7 +63,7 @@ pub fn vectorize(expr: &mut Expr) {
     let mut sym_gen = SymbolGenerator::from_expression(expr);
 
     expr.transform_and_continue_res(&mut |ref mut expr| {
-        if let Some(ref broadcast_idens) = vectorizable(expr) {
+        if let Some(ref broadcast_idens) = vectorizable(expr, fast_math) {
             info!("Vectorizing For loop!");
             if let For {
                 ref iters,
@@ -114,78 +137,73 @@ pub fn vectorize(expr: &mut Expr) {
 }
 
 /// Predicate an `If` expression by checking for if(cond, merge(b, e), b) and transforms it to
-/// merge(b, select(cond, e,identity)).
+/// merge(b, select(cond, e, identity)). The pattern if(cond, b, merge(b, e)) is predicated the same
+/// way with a negated condition.
+///
+/// Predication evaluates `e` even when `cond` is false, so unannotated expressions are only
+/// predicated if they merge into a merger and `e` is cheap and cannot fail. The `predicate`
+/// annotation overrides this check.
 pub fn predicate_merge_expr(e: &mut Expr) {
     e.transform_and_continue_res(&mut |ref mut e| {
-        if !e.should_predicate() {
+        if !e.allows_predicate() {
             return Ok((None, true));
         }
 
-        // Predication for a value merged into a merger. This pattern checks for if(cond, merge(b, e), b).
         if let If {
             ref cond,
             ref on_true,
             ref on_false,
         } = e.kind
         {
-            if let Merge {
-                ref builder,
-                ref value,
-            } = on_true.kind
-            {
-                if let Ident(ref name) = on_false.kind {
-                    if let Ident(ref name2) = builder.kind {
-                        if name == name2 {
-                            if let Builder(ref bk, _) = builder.ty {
-                                // Merge in the identity element if the predicate fails (effectively merging in nothing)
-                                let (ty, op) = match *bk {
-                                    BuilderKind::Merger(ref ty, ref op) => (ty, op),
-                                    BuilderKind::DictMerger(_, ref ty2, ref op) => (ty2, op),
-                                    BuilderKind::VecMerger(ref ty, ref op) => (ty, op),
-                                    _ => {
-                                        return Ok((None, true));
-                                    }
-                                };
-
-                                let identity = get_id_element(ty.as_ref(), *op)?;
-                                match identity {
-                                    Some(x) => {
-                                        match *bk {
-                                            BuilderKind::Merger(_, _)
-                                            | BuilderKind::VecMerger(_, _) => {
-                                                /* Change if(cond, merge(b, e), b) =>
-                                                merge(b, select(cond, e, identity). */
-                                                let expr = Expr::new_merge(
-                                                    *builder.clone(),
-                                                    Expr::new_select(
-                                                        *cond.clone(),
-                                                        *value.clone(),
-                                                        x,
-                                                    )?,
-                                                )?;
-                                                return Ok((Some(expr), true));
-                                            }
-                                            BuilderKind::DictMerger(_, _, _) => {
-                                                /* For dictmerger, need to match identity element
-                                                back to the key. */
-                                                let sel_expr = make_select_for_kv(
-                                                    *cond.clone(),
-                                                    *value.clone(),
-                                                    x,
-                                                )?;
-                                                return Ok((sel_expr, true));
-                                            }
-                                            _ => {
-                                                return Ok((None, true));
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        return Ok((None, true));
-                                    }
-                                };
-                            }
+            let (cond, builder, value) = match (
+                merged_value(on_true, on_false),
+                merged_value(on_false, on_true),
+            ) {
+                (Some((builder, value)), _) => (cond.as_ref().clone(), builder, value),
+                (None, Some((builder, value))) => {
+                    (Expr::new_not(cond.as_ref().clone())?, builder, value)
+                }
+                _ => return Ok((None, true)),
+            };
+
+            if let Builder(ref bk, _) = builder.ty {
+                // Predicating merges into other builders changes which keys or indices they
+                // write, so those are only predicated if the annotation asks for it.
+                let is_merger = matches!(*bk, BuilderKind::Merger(_, _));
+                let safe = is_merger && safe_to_predicate(value);
+                if !(safe || e.should_predicate()) {
+                    return Ok((None, true));
+                }
+
+                // Merge in the identity element if the predicate fails (effectively merging in nothing)
+                let (ty, op) = match *bk {
+                    BuilderKind::Merger(ref ty, ref op) => (ty, op),
+                    BuilderKind::DictMerger(_, ref ty2, ref op) => (ty2, op),
+                    BuilderKind::VecMerger(ref ty, ref op) => (ty, op),
+                    _ => {
+                        return Ok((None, true));
+                    }
+                };
+
+                if let Some(x) = get_id_element(ty.as_ref(), *op)? {
+                    match *bk {
+                        BuilderKind::Merger(_, _) => {
+                            /* Change if(cond, merge(b, e), b) =>
+                            merge(b, select(cond, e, identity). */
+                            let expr = Expr::new_merge(
+                                builder.clone(),
+                                Expr::new_select(cond, value.clone(), x)?,
+                            )?;
+                            return Ok((Some(expr), true));
                         }
+                        BuilderKind::DictMerger(_, _, _) | BuilderKind::VecMerger(_, _) => {
+                            /* For dictmerger and vecmerger, need to match identity element
+                            back to the key or index. */
+                            let sel_expr = make_select_for_kv(cond, value.clone(), x)?;
+                            let expr = Expr::new_merge(builder.clone(), sel_expr)?;
+                            return Ok((Some(expr), true));
+                        }
+                        _ => (),
                     }
                 }
             }
@@ -194,6 +212,31 @@ pub fn predicate_merge_expr(e: &mut Expr) {
     });
 }
 
+/// Returns the builder and value of `merge` if it is a merge into `unchanged`.
+fn merged_value<'a>(merge: &'a Expr, unchanged: &Expr) -> Option<(&'a Expr, &'a Expr)> {
+    if let Merge {
+        ref builder,
+        ref value,
+    } = merge.kind
+    {
+        if builder.as_ref() == unchanged {
+            return Some((builder, value));
+        }
+    }
+    None
+}
+
+/// Returns whether `value` is cheap to compute and can be computed even when the branch that
+/// computes it is not taken.
+fn safe_to_predicate(value: &Expr) -> bool {
+    let mut safe = true;
+    value.traverse(&mut |e| match e.kind {
+        For { .. } | Iterate { .. } | Sort { .. } => safe = false,
+        _ => safe &= !e.kind.may_fail() && !e.kind.is_builder_expr(),
+    });
+    safe
+}
+
 fn is_simple(e: &Expr) -> bool {
     match e.kind {
         Ident(_) | Literal(_) => true,
@@ -283,7 +326,7 @@ fn vectorize_expr(e: &mut Expr, broadcast_idens: &HashSet<Symbol>) -> WeldResult
                 } else {
                     e.ty = e.ty.simd_type()?;
                 }
-            } else if let Struct(_) = e.ty {
+            } else if let Struct(..) = e.ty {
                 e.ty = e.ty.simd_type()?;
             }
         }
@@ -293,6 +336,13 @@ fn vectorize_expr(e: &mut Expr, broadcast_idens: &HashSet<Symbol>) -> WeldResult
         UnaryOp { .. } => {
             e.ty = e.ty.simd_type()?;
         }
+        Negate(_) | Not(_) | Cast { .. } => {
+            e.ty = e.ty.simd_type()?;
+        }
+        Let { .. } => {
+            // The type of a `Let` is the type of its body, which may be vectorized.
+            e.ty = e.ty.simd_type()?;
+        }
         BinOp { .. } => {
             e.ty = e.ty.simd_type()?;
         }
@@ -315,13 +365,20 @@ fn vectorize_expr(e: &mut Expr, broadcast_idens: &HashSet<Symbol>) -> WeldResult
 /// Returns Some(true) if it is an appender, merger or a struct of builders with at least one appender or merger.
 /// Returns Some(false) if it is a builder, struct of builders, but no appender or mergers.
 /// Returns None if it is not a builder.
-fn vectorizable_builder(expr: &Expr) -> Option<bool> {
+fn vectorizable_builder(expr: &Expr, fast_math: bool) -> Option<bool> {
     use crate::ast::BuilderKind::*;
     match expr.kind {
         Ident(_) | NewBuilder(_) => {
             if let Builder(ref bk, _) = expr.ty {
                 match *bk {
-                    Appender(ref elem) | Merger(ref elem, _) => Some(elem.is_scalar()),
+                    Appender(ref elem) => Some(elem.is_scalar()),
+                    Merger(ref elem, op) => Some(match **elem {
+                        Scalar(kind) if kind.is_float() => {
+                            fast_math || (op != BinOpKind::Add && op != BinOpKind::Multiply)
+                        }
+                        Scalar(_) => true,
+                        _ => false,
+                    }),
                     _ => Some(false),
                 }
             } else {
@@ -331,7 +388,7 @@ fn vectorizable_builder(expr: &Expr) -> Option<bool> {
         MakeStruct { ref elems } => {
             let mut vectorizable = true;
             for elem in elems.iter() {
-                match vectorizable_builder(elem) {
+                match vectorizable_builder(elem, fast_math) {
                     Some(val) => vectorizable &= val,
                     None => return None,
                 }
@@ -345,7 +402,7 @@ fn vectorizable_builder(expr: &Expr) -> Option<bool> {
 /// Checks basic vectorizability for a loop - this is a strong check which ensure that the only
 /// expressions which appear in a function body are vectorizable expressions (see
 /// `docs/internals/vectorization.md` for details)
-fn vectorizable(for_loop: &Expr) -> Option<HashSet<Symbol>> {
+fn vectorizable(for_loop: &Expr, fast_math: bool) -> Option<HashSet<Symbol>> {
     if let For {
         ref iters,
         builder: ref init_builder,
@@ -355,7 +412,7 @@ fn vectorizable(for_loop: &Expr) -> Option<HashSet<Symbol>> {
         // Check if the iterators are consumed.
         if vectorizable_iters(&iters) {
             // Check if at least one of the builders can be vectorized.
-            if let Some(true) = vectorizable_builder(init_builder) {
+            if let Some(true) = vectorizable_builder(init_builder, fast_math) {
                 // Check the loop function.
                 if let Lambda {
                     ref params,
@@ -364,12 +421,6 @@ fn vectorizable(for_loop: &Expr) -> Option<HashSet<Symbol>> {
                 {
                     let mut passed = true;
 
-                    // Identifiers defined within the loop.
-                    let mut defined_in_loop = HashSet::new();
-                    for param in params.iter() {
-                        defined_in_loop.insert(param.name.clone());
-                    }
-
                     // Check if subexpressions in the body are all vectorizable.
                     body.traverse(&mut |f| {
                         if passed {
@@ -384,11 +435,24 @@ fn vectorizable(for_loop: &Expr) -> Option<HashSet<Symbol>> {
                                 }
 
                                 UnaryOp { .. } => {}
+                                // Vectorized code evaluates both operands of `&&` and `||`, so
+                                // the right operand must be safe to evaluate in every lane.
+                                BinOp {
+                                    kind: BinOpKind::LogicalAnd,
+                                    ref right,
+                                    ..
+                                }
+                                | BinOp {
+                                    kind: BinOpKind::LogicalOr,
+                                    ref right,
+                                    ..
+                                } => {
+                                    right.traverse(&mut |e| passed &= !e.kind.may_fail());
+                                }
                                 BinOp { .. } => {}
+                                Negate(_) | Not(_) | Cast { .. } => {}
 
-                                Let { ref name, .. } => {
-                                    defined_in_loop.insert(name.clone());
-                                }
+                                Let { .. } => {}
 
                                 // TODO: do we want to allow all GetFields and MakeStructs, or look inside them?
                                 GetField { .. } => {}
@@ -415,7 +479,7 @@ fn vectorizable(for_loop: &Expr) -> Option<HashSet<Symbol>> {
                     let mut check_arg_ty = false;
                     if let Scalar(_) = params[2].ty {
                         check_arg_ty = true;
-                    } else if let Struct(ref field_tys) = params[2].ty {
+                    } else if let Struct(ref field_tys, _) = params[2].ty {
                         if field_tys.iter().all(|t| match *t {
                             Scalar(_) => true,
                             _ => false,
@@ -433,9 +497,10 @@ fn vectorizable(for_loop: &Expr) -> Option<HashSet<Symbol>> {
 
                     // Check if there are identifiers defined outside the loop. If so, we need to
                     // broadcast them to vectorize them.
+                    let free_in_loop = func.free_variables();
                     let mut passed = true;
                     body.traverse(&mut |e| match e.kind {
-                        Ident(ref name) if !defined_in_loop.contains(name) => {
+                        Ident(ref name) if free_in_loop.contains(name) => {
                             if let Scalar(_) = e.ty {
                                 idens.insert(name.clone());
                             } else {
@@ -459,7 +524,10 @@ fn vectorizable(for_loop: &Expr) -> Option<HashSet<Symbol>> {
 }
 
 fn get_id_element(ty: &Type, op: BinOpKind) -> WeldResult<Option<Expr>> {
-    let sk = &match *ty {
+    use crate::ast::LiteralKind::*;
+    use crate::ast::ScalarKind::*;
+
+    let sk = match *ty {
         Scalar(sk) => sk,
         _ => {
             return Ok(None);
@@ -467,35 +535,41 @@ fn get_id_element(ty: &Type, op: BinOpKind) -> WeldResult<Option<Expr>> {
     };
 
     /* Dummy element to merge when predicate fails. */
-    let identity = match op {
-        BinOpKind::Add => match *sk {
-            ScalarKind::I8 => Expr::new_literal(LiteralKind::I8Literal(0))?,
-            ScalarKind::I32 => Expr::new_literal(LiteralKind::I32Literal(0))?,
-            ScalarKind::I64 => Expr::new_literal(LiteralKind::I64Literal(0))?,
-            ScalarKind::F32 => Expr::new_literal(LiteralKind::F32Literal(0f32.to_bits()))?,
-            ScalarKind::F64 => Expr::new_literal(LiteralKind::F64Literal(0f64.to_bits()))?,
-            _ => {
-                return Ok(None);
+    macro_rules! identity {
+        ($lit:ident, $zero:expr, $one:expr) => {
+            match op {
+                BinOpKind::Add => $lit($zero),
+                BinOpKind::Multiply => $lit($one),
+                _ => return Ok(None),
             }
-        },
-        BinOpKind::Multiply => match *sk {
-            ScalarKind::I8 => Expr::new_literal(LiteralKind::I8Literal(1))?,
-            ScalarKind::I32 => Expr::new_literal(LiteralKind::I32Literal(1))?,
-            ScalarKind::I64 => Expr::new_literal(LiteralKind::I64Literal(1))?,
-            ScalarKind::F32 => Expr::new_literal(LiteralKind::F32Literal(1f32.to_bits()))?,
-            ScalarKind::F64 => Expr::new_literal(LiteralKind::F64Literal(1f64.to_bits()))?,
-            _ => {
-                return Ok(None);
+        };
+        ($lit:ident, $ty:ident) => {
+            match op {
+                BinOpKind::Max => $lit($ty::MIN),
+                BinOpKind::Min => $lit($ty::MAX),
+                _ => identity!($lit, 0, 1),
             }
-        },
-        _ => {
-            return Ok(None);
-        }
+        };
+    }
+
+    let identity = match sk {
+        I8 => identity!(I8Literal, i8),
+        I16 => identity!(I16Literal, i16),
+        I32 => identity!(I32Literal, i32),
+        I64 => identity!(I64Literal, i64),
+        U8 => identity!(U8Literal, u8),
+        U16 => identity!(U16Literal, u16),
+        U32 => identity!(U32Literal, u32),
+        U64 => identity!(U64Literal, u64),
+        // Floating point minimums and maximums have no identity that preserves NaNs.
+        F32 => identity!(F32Literal, 0f32.to_bits(), 1f32.to_bits()),
+        F64 => identity!(F64Literal, 0f64.to_bits(), 1f64.to_bits()),
+        Bool => return Ok(None),
     };
-    Ok(Some(identity))
+    Ok(Some(Expr::new_literal(identity)?))
 }
 
-fn make_select_for_kv(cond: Expr, kv: Expr, ident: Expr) -> WeldResult<Option<Expr>> {
+fn make_select_for_kv(cond: Expr, kv: Expr, ident: Expr) -> WeldResult<Expr> {
     let mut sym_gen = SymbolGenerator::from_expression(&kv);
     let name = sym_gen.new_symbol("k");
 
@@ -503,8 +577,7 @@ fn make_select_for_kv(cond: Expr, kv: Expr, ident: Expr) -> WeldResult<Option<Ex
     let kv_ident = Expr::new_make_struct(vec![Expr::new_get_field(kv_struct.clone(), 0)?, ident])?; // use the original key and the identity as the value
 
     let sel = Expr::new_select(cond, kv_struct, kv_ident)?;
-    let le = Expr::new_let(name, kv, sel)?; /* avoid copying key */
-    Ok(Some(le))
+    Expr::new_let(name, kv, sel) /* avoid copying key */
 }
 
 /// Check whether a function has a vectorized Merge call. We'll use this to check whether function
@@ -546,6 +619,90 @@ fn unpredicated_merger() {
     assert!(!has_vectorized_merge(&e));
 }
 
+#[test]
+fn predicate_safe_merges() {
+    check_transform(
+        "|v:vec[i32]| for(v, merger[i32,+], |b,i,e| if(e>0, merge(b,e), b))",
+        "|v:vec[i32]| for(v, merger[i32,+], |b,i,e| merge(b, select(e>0, e, 0)))",
+        predicate_merge_expr,
+    );
+    check_transform(
+        "|v:vec[u8]| for(v, merger[u8,*], |b,i,e| if(e>0u8, b, merge(b,e)))",
+        "|v:vec[u8]| for(v, merger[u8,*], |b,i,e| merge(b, select(!(e>0u8), e, 1u8)))",
+        predicate_merge_expr,
+    );
+    check_transform(
+        "|v:vec[i32]| for(v, {merger[i32,+], appender[i32]}, |b,i,e| {if(e>0, merge(b.$0,e), b.$0), b.$1})",
+        "|v:vec[i32]| for(v, {merger[i32,+], appender[i32]}, |b,i,e| {merge(b.$0, select(e>0, e, 0)), b.$1})",
+        predicate_merge_expr,
+    );
+}
+
+#[test]
+fn predicate_unsafe_merges() {
+    // The lookup is out of bounds if the condition is false.
+    let code = "|v:vec[i32]| for(v, merger[i32,+], |b,i,e| if(i>0L, merge(b,lookup(v,i-1L)), b))";
+    check_transform(code, code, predicate_merge_expr);
+
+    // The annotation overrides the check.
+    let mut e = typed_expression(
+        "|v:vec[i32]| for(v, merger[i32,+], |b,i,e| @(predicate:true)if(i>0L, merge(b,lookup(v,i-1L)), b))",
+    );
+    predicate_merge_expr(&mut e);
+    assert!(!print_expr_without_indent(&e).contains("if"));
+
+    // Predicating a dictmerger adds keys with the identity as their value.
+    let code = "|v:vec[i32]| for(v, dictmerger[i32,i32,+], |b,i,e| if(e>0, merge(b,{e,e}), b))";
+    check_transform(code, code, predicate_merge_expr);
+    check_transform(
+        "|v:vec[i32]| for(v, dictmerger[i32,i32,+], |b,i,e| @(predicate:true)if(e>0, merge(b,{e,e}), b))",
+        "|v:vec[i32]| for(v, dictmerger[i32,i32,+], |b,i,e| merge(b, let k = {e,e}; select(e>0, k, {k.$0, 0})))",
+        predicate_merge_expr,
+    );
+
+    let code =
+        "|v:vec[i32]| for(v, merger[i32,+], |b,i,e| @(predicate:false)if(e>0, merge(b,e), b))";
+    let mut e = typed_expression(code);
+    predicate_merge_expr(&mut e);
+    assert_eq!(e, typed_expression(code));
+}
+
+#[test]
+fn casts_and_negation() {
+    let mut e = typed_expression(
+        "|v:vec[i32]| result(for(v, merger[i64,+], |b,i,e| merge(b, -i64(e) + i64(!(e > 0)))))",
+    );
+    vectorize(&mut e);
+    assert!(has_vectorized_merge(&e));
+    e.check_types().unwrap();
+}
+
+#[test]
+fn let_in_body() {
+    let mut e = typed_expression(
+        "|v:vec[i32]| result(for(v, merger[i32,+], |b,i,e| merge(b, let x = e * 2; x + 1)))",
+    );
+    vectorize(&mut e);
+    assert!(has_vectorized_merge(&e));
+    e.check_types().unwrap();
+}
+
+#[test]
+fn short_circuit_operands() {
+    // The division fails in lanes where the left operand is false.
+    let mut e = typed_expression(
+        "|v:vec[i32]| result(for(v, merger[i32,+], |b,i,e| merge(b, select(e != 0 && 10 / e > 1, 1, 0))))",
+    );
+    vectorize(&mut e);
+    assert!(!has_vectorized_merge(&e));
+
+    let mut e = typed_expression(
+        "|v:vec[i32]| result(for(v, merger[i32,+], |b,i,e| merge(b, select(e > 0 || e / 2 < -1, 1, 0))))",
+    );
+    vectorize(&mut e);
+    assert!(has_vectorized_merge(&e));
+}
+
 #[test]
 fn simple_appender() {
     let mut e =
@@ -554,6 +711,22 @@ fn simple_appender() {
     assert!(has_vectorized_merge(&e));
 }
 
+#[test]
+fn float_merger() {
+    // Vectorizing a floating point sum reorders the additions.
+    let code = "|v:vec[f64]| result(for(v, merger[f64,+], |b,i,e| merge(b,e*2.0)))";
+    let mut e = typed_expression(code);
+    vectorize(&mut e);
+    assert!(!has_vectorized_merge(&e));
+    vectorize_fast_math(&mut e);
+    assert!(has_vectorized_merge(&e));
+
+    let mut e =
+        typed_expression("|v:vec[f64]| result(for(v, merger[f64,max], |b,i,e| merge(b,e)))");
+    vectorize(&mut e);
+    assert!(has_vectorized_merge(&e));
+}
+
 #[test]
 fn predicated_appender() {
     // This code should NOT be vectorized because we can't predicate merges into vecbuilder.
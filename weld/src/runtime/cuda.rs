@@ -0,0 +1,294 @@
+//! Runs loop kernels on an NVIDIA GPU with the CUDA driver API.
+//!
+//! The driver is loaded with `dlopen` the first time a kernel runs, so Weld does not link
+//! against CUDA and runs on machines without it. If the driver cannot be loaded, there is no
+//! device, or any driver call fails, `run_kernel` returns `false` and the caller runs the loop on
+//! the CPU instead.
+
+use libc;
+
+use fnv::FnvHashMap;
+
+use libc::{c_char, c_int, c_uint, c_void};
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+use std::sync::Mutex;
+
+/// The number of threads in each block of a kernel launch.
+const BLOCK_SIZE: i64 = 256;
+
+/// The name of the kernel function in each PTX module.
+pub const KERNEL_NAME: &str = "weld_kernel";
+
+type CUresult = c_int;
+type CUdevice = c_int;
+type CUcontext = *mut c_void;
+type CUmodule = *mut c_void;
+type CUfunction = *mut c_void;
+type CUstream = *mut c_void;
+type CUdeviceptr = u64;
+
+/// The CUDA driver functions that Weld uses.
+struct Driver {
+    /// The primary context of the first device.
+    context: usize,
+    ctx_set_current: unsafe extern "C" fn(CUcontext) -> CUresult,
+    ctx_synchronize: unsafe extern "C" fn() -> CUresult,
+    module_load_data: unsafe extern "C" fn(*mut CUmodule, *const c_void) -> CUresult,
+    module_get_function: unsafe extern "C" fn(*mut CUfunction, CUmodule, *const c_char) -> CUresult,
+    mem_alloc: unsafe extern "C" fn(*mut CUdeviceptr, usize) -> CUresult,
+    mem_free: unsafe extern "C" fn(CUdeviceptr) -> CUresult,
+    memcpy_htod: unsafe extern "C" fn(CUdeviceptr, *const c_void, usize) -> CUresult,
+    memcpy_dtoh: unsafe extern "C" fn(*mut c_void, CUdeviceptr, usize) -> CUresult,
+    #[allow(clippy::type_complexity)]
+    launch_kernel: unsafe extern "C" fn(
+        CUfunction,
+        c_uint,
+        c_uint,
+        c_uint,
+        c_uint,
+        c_uint,
+        c_uint,
+        c_uint,
+        CUstream,
+        *mut *mut c_void,
+        *mut *mut c_void,
+    ) -> CUresult,
+    /// Kernels loaded so far, keyed by their PTX.
+    kernels: FnvHashMap<Vec<u8>, usize>,
+}
+
+lazy_static! {
+    /// The loaded driver, or `None` if it is not available.
+    static ref DRIVER: Mutex<Option<Driver>> = Mutex::new(unsafe { Driver::load() });
+}
+
+/// Returns an error naming `call` if `result` is not `CUDA_SUCCESS`.
+fn check(call: &str, result: CUresult) -> Result<(), String> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} failed with CUDA error {}", call, result))
+    }
+}
+
+/// Looks up the function `name` in the library `handle`.
+unsafe fn symbol<T>(handle: *mut c_void, name: &str) -> Result<T, String> {
+    let c_name = CString::new(name).unwrap();
+    let pointer = libc::dlsym(handle, c_name.as_ptr());
+    if pointer.is_null() {
+        Err(format!("CUDA driver does not define {}", name))
+    } else {
+        Ok(mem::transmute_copy(&pointer))
+    }
+}
+
+impl Driver {
+    /// Loads the driver and creates a context on the first device.
+    unsafe fn load() -> Option<Driver> {
+        match Driver::try_load() {
+            Ok(driver) => {
+                info!("Loaded CUDA driver");
+                Some(driver)
+            }
+            Err(message) => {
+                info!("GPU loops will run on the CPU: {}", message);
+                None
+            }
+        }
+    }
+
+    unsafe fn try_load() -> Result<Driver, String> {
+        let mut handle = ptr::null_mut();
+        for name in ["libcuda.so.1", "libcuda.so"].iter() {
+            let c_name = CString::new(*name).unwrap();
+            handle = libc::dlopen(c_name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if !handle.is_null() {
+                break;
+            }
+        }
+        if handle.is_null() {
+            let error = libc::dlerror();
+            return if error.is_null() {
+                Err("could not load the CUDA driver".to_string())
+            } else {
+                Err(CStr::from_ptr(error).to_string_lossy().into_owned())
+            };
+        }
+
+        let init: unsafe extern "C" fn(c_uint) -> CUresult = symbol(handle, "cuInit")?;
+        let device_get: unsafe extern "C" fn(*mut CUdevice, c_int) -> CUresult =
+            symbol(handle, "cuDeviceGet")?;
+        let retain: unsafe extern "C" fn(*mut CUcontext, CUdevice) -> CUresult =
+            symbol(handle, "cuDevicePrimaryCtxRetain")?;
+
+        check("cuInit", init(0))?;
+        let mut device = 0;
+        check("cuDeviceGet", device_get(&mut device, 0))?;
+        let mut context = ptr::null_mut();
+        check("cuDevicePrimaryCtxRetain", retain(&mut context, device))?;
+
+        Ok(Driver {
+            context: context as usize,
+            ctx_set_current: symbol(handle, "cuCtxSetCurrent")?,
+            ctx_synchronize: symbol(handle, "cuCtxSynchronize")?,
+            module_load_data: symbol(handle, "cuModuleLoadData")?,
+            module_get_function: symbol(handle, "cuModuleGetFunction")?,
+            mem_alloc: symbol(handle, "cuMemAlloc_v2")?,
+            mem_free: symbol(handle, "cuMemFree_v2")?,
+            memcpy_htod: symbol(handle, "cuMemcpyHtoD_v2")?,
+            memcpy_dtoh: symbol(handle, "cuMemcpyDtoH_v2")?,
+            launch_kernel: symbol(handle, "cuLaunchKernel")?,
+            kernels: FnvHashMap::default(),
+        })
+    }
+
+    /// Returns the kernel defined by `ptx`, loading it if necessary.
+    unsafe fn kernel(&mut self, ptx: &CStr) -> Result<CUfunction, String> {
+        if let Some(function) = self.kernels.get(ptx.to_bytes()) {
+            return Ok(*function as CUfunction);
+        }
+        let mut module = ptr::null_mut();
+        check(
+            "cuModuleLoadData",
+            (self.module_load_data)(&mut module, ptx.as_ptr() as *const c_void),
+        )?;
+        let mut function = ptr::null_mut();
+        let name = CString::new(KERNEL_NAME).unwrap();
+        check(
+            "cuModuleGetFunction",
+            (self.module_get_function)(&mut function, module, name.as_ptr()),
+        )?;
+        self.kernels
+            .insert(ptx.to_bytes().to_vec(), function as usize);
+        Ok(function)
+    }
+
+    /// Allocates `size` bytes on the device.
+    unsafe fn alloc(
+        &self,
+        size: usize,
+        allocations: &mut Vec<CUdeviceptr>,
+    ) -> Result<CUdeviceptr, String> {
+        let mut pointer = 0;
+        // Zero-byte allocations fail, and are not needed.
+        check("cuMemAlloc", (self.mem_alloc)(&mut pointer, size.max(1)))?;
+        allocations.push(pointer);
+        Ok(pointer)
+    }
+
+    /// Runs the kernel. See `run_kernel`.
+    unsafe fn run(
+        &mut self,
+        ptx: &CStr,
+        data: *const c_void,
+        data_size: usize,
+        out: *mut c_void,
+        out_size: usize,
+        mut iterations: i64,
+        mut threads: i64,
+        captures: &[*mut c_void],
+        allocations: &mut Vec<CUdeviceptr>,
+    ) -> Result<(), String> {
+        check(
+            "cuCtxSetCurrent",
+            (self.ctx_set_current)(self.context as CUcontext),
+        )?;
+        let kernel = self.kernel(ptx)?;
+
+        let mut device_data = self.alloc(data_size, allocations)?;
+        let mut device_out = self.alloc(out_size, allocations)?;
+        check(
+            "cuMemcpyHtoD",
+            (self.memcpy_htod)(device_data, data, data_size),
+        )?;
+
+        let mut params: Vec<*mut c_void> = vec![
+            &mut device_data as *mut CUdeviceptr as *mut c_void,
+            &mut device_out as *mut CUdeviceptr as *mut c_void,
+            &mut iterations as *mut i64 as *mut c_void,
+            &mut threads as *mut i64 as *mut c_void,
+        ];
+        params.extend_from_slice(captures);
+
+        let blocks = (threads + BLOCK_SIZE - 1) / BLOCK_SIZE;
+        trace!(
+            "Launching GPU kernel with {} blocks of {} threads",
+            blocks,
+            BLOCK_SIZE
+        );
+        check(
+            "cuLaunchKernel",
+            (self.launch_kernel)(
+                kernel,
+                blocks as c_uint,
+                1,
+                1,
+                BLOCK_SIZE as c_uint,
+                1,
+                1,
+                0,
+                ptr::null_mut(),
+                params.as_mut_ptr(),
+                ptr::null_mut(),
+            ),
+        )?;
+        check("cuCtxSynchronize", (self.ctx_synchronize)())?;
+        check(
+            "cuMemcpyDtoH",
+            (self.memcpy_dtoh)(out, device_out, out_size),
+        )
+    }
+}
+
+/// Runs the kernel defined by `ptx` on the GPU, and returns whether it ran.
+///
+/// The kernel reads `data_size` bytes of input from `data` and writes `out_size` bytes of output
+/// to `out`. It is launched with at least `threads` threads, and is passed pointers to the input
+/// and output on the device, `iterations`, `threads`, and the values that `captures` point to.
+pub unsafe fn run_kernel(
+    ptx: &CStr,
+    data: *const c_void,
+    data_size: usize,
+    out: *mut c_void,
+    out_size: usize,
+    iterations: i64,
+    threads: i64,
+    captures: &[*mut c_void],
+) -> bool {
+    // Kernels run one at a time, since they share the driver's context.
+    let mut guard = DRIVER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let driver = match *guard {
+        Some(ref mut driver) => driver,
+        None => return false,
+    };
+    if threads == 0 {
+        return true;
+    }
+    let mut allocations = vec![];
+    let result = driver.run(
+        ptx,
+        data,
+        data_size,
+        out,
+        out_size,
+        iterations,
+        threads,
+        captures,
+        &mut allocations,
+    );
+    for pointer in allocations {
+        (driver.mem_free)(pointer);
+    }
+    match result {
+        Ok(()) => true,
+        Err(message) => {
+            info!("Running GPU loop on the CPU: {}", message);
+            false
+        }
+    }
+}
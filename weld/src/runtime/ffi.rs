@@ -2,11 +2,24 @@
 //!
 //! These are functions with a C-based ABI and calling convention. Generated Weld code will call
 //! into these functions.
+//!
+//! Functions that can raise a runtime error use the `C-unwind` ABI: they stop the run by
+//! unwinding through the generated code back to `WeldModule::run`.
+//!
+//! The worker threads of parallel loops call these functions at the same time, so they only create
+//! shared references to the run's context.
 
 use super::*;
 
+use std::time::Instant;
+
 pub type WeldRuntimeContextRef = *mut WeldRuntimeContext;
 
+/// A function that runs one chunk of a parallel loop.
+///
+/// The function takes the loop's arguments, the index of the chunk, and the run handle.
+pub type WeldChunkFunction = unsafe extern "C-unwind" fn(Ptr, i64, WeldRuntimeContextRef);
+
 #[no_mangle]
 /// Initialize the runtime.
 ///
@@ -30,8 +43,8 @@ pub unsafe extern "C" fn weld_runst_release(run: WeldRuntimeContextRef) {
 
 #[no_mangle]
 /// Allocate memory within the provided context.
-pub unsafe extern "C" fn weld_runst_malloc(run: WeldRuntimeContextRef, size: i64) -> Ptr {
-    let run = &mut *run;
+pub unsafe extern "C-unwind" fn weld_runst_malloc(run: WeldRuntimeContextRef, size: i64) -> Ptr {
+    let run = &*run;
     run.malloc(size)
 }
 
@@ -39,66 +52,148 @@ pub unsafe extern "C" fn weld_runst_malloc(run: WeldRuntimeContextRef, size: i64
 /// Reallocate memory within the provided context.
 ///
 /// This function has semantics equal to the `realloc` function.
-pub unsafe extern "C" fn weld_runst_realloc(
+pub unsafe extern "C-unwind" fn weld_runst_realloc(
     run: WeldRuntimeContextRef,
     ptr: Ptr,
     newsize: i64,
 ) -> Ptr {
-    let run = &mut *run;
+    let run = &*run;
     run.realloc(ptr, newsize)
 }
 
 #[no_mangle]
 /// Free memory allocated in this context.
 pub unsafe extern "C" fn weld_runst_free(run: WeldRuntimeContextRef, ptr: Ptr) {
-    let run = &mut *run;
+    let run = &*run;
     run.free(ptr)
 }
 
 #[no_mangle]
 /// Set the result pointer.
 pub unsafe extern "C" fn weld_runst_set_result(run: WeldRuntimeContextRef, ptr: Ptr) {
-    let run = &mut *run;
+    let run = &*run;
     run.set_result(ptr)
 }
 
 #[no_mangle]
-/// Set the errno value.
-pub unsafe extern "C" fn weld_runst_set_errno(run: WeldRuntimeContextRef, errno: WeldRuntimeErrno) {
-    let run = &mut *run;
+/// Set the errno value and stop the run.
+pub unsafe extern "C-unwind" fn weld_runst_set_errno(
+    run: WeldRuntimeContextRef,
+    errno: WeldRuntimeErrno,
+) -> ! {
+    let run = &*run;
     run.set_errno(errno)
 }
 
+#[no_mangle]
+/// Set the errno value and the site in the program that raised it, and stop the run.
+pub unsafe extern "C-unwind" fn weld_runst_set_error(
+    run: WeldRuntimeContextRef,
+    errno: WeldRuntimeErrno,
+    site: i64,
+) -> ! {
+    let run = &*run;
+    run.set_error(errno, site)
+}
+
 #[no_mangle]
 /// Get the errno value.
 pub unsafe extern "C" fn weld_runst_get_errno(run: WeldRuntimeContextRef) -> WeldRuntimeErrno {
-    let run = &mut *run;
+    let run = &*run;
     run.errno()
 }
 
 #[no_mangle]
 /// Get the result pointer.
 pub unsafe extern "C" fn weld_runst_get_result(run: WeldRuntimeContextRef) -> Ptr {
-    let run = &mut *run;
+    let run = &*run;
     run.result()
 }
 
 #[no_mangle]
 /// Check whether cond is 0 (assertion fails).
-pub unsafe extern "C" fn weld_runst_assert(run: WeldRuntimeContextRef, cond: u8) -> u8 {
-    let run = &mut *run;
+pub unsafe extern "C-unwind" fn weld_runst_assert(run: WeldRuntimeContextRef, cond: u8) -> u8 {
     if cond == 0 {
-        // Doesn't return.
-        run.set_errno(WeldRuntimeErrno::AssertionError);
-        unreachable!()
+        weld_runst_set_errno(run, WeldRuntimeErrno::AssertionError)
     } else {
         1
     }
 }
 
 #[no_mangle]
-/// Print a value from generated code.
+/// Run the chunks of a parallel loop on the run's worker threads.
+pub unsafe extern "C-unwind" fn weld_runst_parallel_for(
+    run: WeldRuntimeContextRef,
+    function: WeldChunkFunction,
+    arguments: Ptr,
+    chunks: i64,
+) {
+    run_chunks(run, function, arguments, chunks)
+}
+
+#[no_mangle]
+/// Run a loop kernel on the GPU.
+///
+/// `ptx` is the kernel's code, `data` holds the loop's `iterations` input elements of
+/// `elem_size` bytes, and the kernel writes `out_size` bytes to `out` using `threads` threads.
+/// `captures` points to `ncaptures` pointers to the kernel's remaining arguments. Returns 1 if
+/// the kernel ran, or 0 if the loop should run on the CPU instead.
+pub unsafe extern "C" fn weld_runst_gpu_for(
+    _run: WeldRuntimeContextRef,
+    ptx: *const c_char,
+    data: Ptr,
+    elem_size: i64,
+    iterations: i64,
+    out: Ptr,
+    out_size: i64,
+    threads: i64,
+    captures: *const Ptr,
+    ncaptures: i64,
+) -> i64 {
+    let captures =
+        std::slice::from_raw_parts(captures as *const *mut libc::c_void, ncaptures as usize);
+    let ran = cuda::run_kernel(
+        CStr::from_ptr(ptx),
+        data as *const libc::c_void,
+        (elem_size * iterations) as usize,
+        out as *mut libc::c_void,
+        out_size as usize,
+        iterations,
+        threads,
+        captures,
+    );
+    i64::from(ran)
+}
+
+#[no_mangle]
+/// Load the next chunk of a chunk source.
+///
+/// Writes the chunk's data to `data` and returns its number of `elem_size`-byte elements, or
+/// returns 0 if the source is exhausted.
+pub unsafe extern "C-unwind" fn weld_runst_next_chunk(
+    run: WeldRuntimeContextRef,
+    source: i64,
+    elem_size: i64,
+    data: *mut Ptr,
+) -> i64 {
+    let run = &*run;
+    let (chunk, length) = run.next_chunk(source, elem_size);
+    *data = chunk;
+    length
+}
+
+#[no_mangle]
+/// Returns the number of nanoseconds since the first call, for timing profiled loops.
+pub extern "C" fn weld_runst_profile_time() -> i64 {
+    lazy_static! {
+        static ref START: Instant = Instant::now();
+    }
+    START.elapsed().as_nanos() as i64
+}
+
+#[no_mangle]
+/// Log a message from generated code.
 pub unsafe extern "C" fn weld_runst_print(_run: WeldRuntimeContextRef, string: *const c_char) {
     let string = CStr::from_ptr(string).to_str().unwrap();
-    println!("{} ", string);
+    info!("{}", string);
 }
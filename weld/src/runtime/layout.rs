@@ -0,0 +1,358 @@
+//! The in-memory layout of Weld values.
+//!
+//! A compiled program takes a pointer to a struct of its parameters and returns a pointer to its
+//! result. Values have the layout of the equivalent C types on a 64-bit target:
+//!
+//! * Scalars have their natural size and alignment. Booleans are a single byte, which is `0` or
+//!   `1` in results; any non-zero input is `true`.
+//! * A `vec[T]` is `{ T*, i64 }`: a pointer to its contiguous elements and their number.
+//! * A struct stores its fields in order, each at the first offset after the previous field that
+//!   is a multiple of the field's alignment. Its alignment is the largest alignment of its fields,
+//!   and its size is padded to a multiple of its alignment. Structs are never packed.
+//! * A `simd[T]` holds `LLVM_VECTOR_WIDTH` elements and is aligned to its size.
+//! * A `dict[K,V]` is a pointer to a hash table owned by the run that created it. Its format is
+//!   private, so dictionaries can only be passed between programs.
+//! * An `appender[T]` is `{ T*, i64, i64 }` and a `merger[T,op]` is `{ T, simd[T] }`. The other
+//!   builders have the layout of their result. Builder layouts may change between versions.
+//!
+//! `TypeLayout` computes these layouts. `ValueBuffer` writes a `Value` in its layout so that it
+//! can be passed to a program, and `read_value` reads a program's result back into a `Value`.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! # extern crate weld;
+//! use weld::ast::{FieldNames, Type, Value};
+//! use weld::runtime::layout::*;
+//! use weld::*;
+//!
+//! let module = WeldModule::compile("|x:i64, v:vec[i32]| {x, len(v)}", &WeldConf::new()).unwrap();
+//! let args = Value::Struct(vec![Value::I64(1), Value::vector(vec![Value::I32(2)])]);
+//! let params = Type::Struct(module.param_types(), FieldNames::none());
+//! let buffer = ValueBuffer::new(&args, &params).unwrap();
+//!
+//! let context = &mut WeldContext::new(&WeldConf::new()).unwrap();
+//! let input = &WeldValue::new_from_data(buffer.data());
+//! let result = unsafe { module.run(context, input).unwrap() };
+//! let value = unsafe { read_value(result.data(), &module.return_type()).unwrap() };
+//! assert_eq!(value, Value::Struct(vec![Value::I64(1), Value::I64(1)]));
+//! ```
+
+use std::alloc::{self, Layout};
+use std::ptr;
+
+use crate::ast::BuilderKind::*;
+use crate::ast::Type::{self, *};
+use crate::ast::Value;
+use crate::ast::{FieldNames, ScalarKind};
+use crate::codegen::LLVM_VECTOR_WIDTH;
+use crate::error::*;
+use crate::Data;
+
+/// The size and alignment of a pointer.
+const POINTER_SIZE: usize = 8;
+
+/// The size, alignment, and field offsets of a type, in bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeLayout {
+    pub size: usize,
+    pub align: usize,
+    /// The offset of each field if the type is a struct, and empty otherwise.
+    pub offsets: Vec<usize>,
+}
+
+impl TypeLayout {
+    /// Returns the layout of `ty`.
+    ///
+    /// Returns an error for functions and types that are not fully known.
+    pub fn of(ty: &Type) -> WeldResult<TypeLayout> {
+        let layout = match *ty {
+            Scalar(kind) => TypeLayout::primitive(scalar_size(kind)),
+            Simd(kind) => TypeLayout::primitive(scalar_size(kind) * LLVM_VECTOR_WIDTH as usize),
+            Vector(_) => TypeLayout::of_fields(&[
+                TypeLayout::primitive(POINTER_SIZE),
+                TypeLayout::primitive(8),
+            ]),
+            Dict(_, _) => TypeLayout::primitive(POINTER_SIZE),
+            Struct(ref tys, _) => {
+                let fields = tys
+                    .iter()
+                    .map(TypeLayout::of)
+                    .collect::<WeldResult<Vec<_>>>()?;
+                TypeLayout::of_fields(&fields)
+            }
+            Builder(Appender(_), _) => TypeLayout::of_fields(&[
+                TypeLayout::primitive(POINTER_SIZE),
+                TypeLayout::primitive(8),
+                TypeLayout::primitive(8),
+            ]),
+            Builder(Merger(ref elem, _), _) => match **elem {
+                Scalar(kind) => {
+                    TypeLayout::of(&Struct(vec![Scalar(kind), Simd(kind)], FieldNames::none()))?
+                }
+                _ => return compile_err!("Unsupported merger type {}", ty),
+            },
+            Builder(ref kind, _) => TypeLayout::of(&kind.result_type())?,
+            Alias(_, ref ty) => TypeLayout::of(ty)?,
+            Function(_, _) | Unknown => return compile_err!("Type {} has no layout", ty),
+        };
+        Ok(layout)
+    }
+
+    /// Returns the layout of a type whose size equals its alignment.
+    fn primitive(size: usize) -> TypeLayout {
+        TypeLayout {
+            size,
+            align: size,
+            offsets: vec![],
+        }
+    }
+
+    /// Returns the layout of a struct with the given fields.
+    fn of_fields(fields: &[TypeLayout]) -> TypeLayout {
+        let mut size = 0;
+        let mut align = 1;
+        let mut offsets = Vec::with_capacity(fields.len());
+        for field in fields.iter() {
+            size = round_up(size, field.align);
+            offsets.push(size);
+            size += field.size;
+            align = align.max(field.align);
+        }
+        TypeLayout {
+            size: round_up(size, align),
+            align,
+            offsets,
+        }
+    }
+}
+
+/// Returns the size of a scalar in bytes.
+fn scalar_size(kind: ScalarKind) -> usize {
+    // Booleans are stored as a byte.
+    (kind.bits() as usize).div_ceil(8)
+}
+
+fn round_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// A block of memory allocated with a given alignment.
+struct Allocation {
+    pointer: *mut u8,
+    layout: Layout,
+}
+
+impl Allocation {
+    fn new(size: usize, align: usize) -> WeldResult<Allocation> {
+        let layout = match Layout::from_size_align(size, align) {
+            Ok(layout) => layout,
+            Err(_) => return compile_err!("Invalid allocation of {} bytes", size),
+        };
+        let pointer = if size == 0 {
+            // Empty allocations only need an aligned pointer.
+            align as *mut u8
+        } else {
+            unsafe { alloc::alloc_zeroed(layout) }
+        };
+        if pointer.is_null() {
+            return compile_err!("Failed to allocate {} bytes", size);
+        }
+        Ok(Allocation { pointer, layout })
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        if self.layout.size() != 0 {
+            unsafe { alloc::dealloc(self.pointer, self.layout) }
+        }
+    }
+}
+
+/// A value written in its in-memory layout.
+///
+/// The buffer owns the memory of the value and of the elements its vectors point to, so it must
+/// outlive any run that reads the value.
+pub struct ValueBuffer {
+    data: Allocation,
+    /// The elements of the vectors in the value.
+    elements: Vec<Allocation>,
+}
+
+impl ValueBuffer {
+    /// Writes `value` in the layout of `ty`.
+    ///
+    /// To pass the arguments of a program, `ty` is a struct of the program's parameter types.
+    /// Returns an error if `value` does not have type `ty`, or if `ty` contains a dictionary,
+    /// a builder, or a SIMD value.
+    pub fn new(value: &Value, ty: &Type) -> WeldResult<ValueBuffer> {
+        let layout = TypeLayout::of(ty)?;
+        let mut buffer = ValueBuffer {
+            data: Allocation::new(layout.size, layout.align)?,
+            elements: vec![],
+        };
+        let pointer = buffer.data.pointer;
+        unsafe { buffer.write(pointer, value, ty)? };
+        Ok(buffer)
+    }
+
+    /// Returns a pointer to the value, which can be passed to `WeldValue::new_from_data`.
+    pub fn data(&self) -> Data {
+        self.data.pointer as Data
+    }
+
+    /// Writes `value` to `pointer`, which must be valid and aligned for a value of type `ty`.
+    unsafe fn write(&mut self, pointer: *mut u8, value: &Value, ty: &Type) -> WeldResult<()> {
+        match (ty, value) {
+            (&Scalar(kind), _) => write_scalar(pointer, kind, value),
+            (Vector(ref elem_ty), Value::Vector(ref elems)) => {
+                let layout = TypeLayout::of(elem_ty)?;
+                let allocation = Allocation::new(layout.size * elems.len(), layout.align)?;
+                for (i, elem) in elems.iter().enumerate() {
+                    self.write(allocation.pointer.add(i * layout.size), elem, elem_ty)?;
+                }
+                ptr::write(pointer as *mut *mut u8, allocation.pointer);
+                ptr::write(pointer.add(POINTER_SIZE) as *mut i64, elems.len() as i64);
+                self.elements.push(allocation);
+                Ok(())
+            }
+            (Struct(ref tys, _), Value::Struct(ref fields)) if tys.len() == fields.len() => {
+                let layout = TypeLayout::of(ty)?;
+                for ((field, ty), offset) in fields.iter().zip(tys).zip(layout.offsets) {
+                    self.write(pointer.add(offset), field, ty)?;
+                }
+                Ok(())
+            }
+            (Alias(_, ref ty), _) => self.write(pointer, value, ty),
+            _ => compile_err!("Cannot write {:?} as a value of type {}", value, ty),
+        }
+    }
+}
+
+/// Writes a scalar `value` to `pointer`.
+unsafe fn write_scalar(pointer: *mut u8, kind: ScalarKind, value: &Value) -> WeldResult<()> {
+    use crate::ast::ScalarKind::*;
+    match (kind, value) {
+        (Bool, &Value::Bool(v)) => ptr::write(pointer, v as u8),
+        (I8, &Value::I8(v)) => ptr::write(pointer as *mut i8, v),
+        (I16, &Value::I16(v)) => ptr::write(pointer as *mut i16, v),
+        (I32, &Value::I32(v)) => ptr::write(pointer as *mut i32, v),
+        (I64, &Value::I64(v)) => ptr::write(pointer as *mut i64, v),
+        (U8, &Value::U8(v)) => ptr::write(pointer, v),
+        (U16, &Value::U16(v)) => ptr::write(pointer as *mut u16, v),
+        (U32, &Value::U32(v)) => ptr::write(pointer as *mut u32, v),
+        (U64, &Value::U64(v)) => ptr::write(pointer as *mut u64, v),
+        (F32, &Value::F32(v)) => ptr::write(pointer as *mut f32, v),
+        (F64, &Value::F64(v)) => ptr::write(pointer as *mut f64, v),
+        _ => return compile_err!("Cannot write {:?} as a value of type {}", value, kind),
+    }
+    Ok(())
+}
+
+/// Reads a value of type `ty` from its in-memory layout at `data`.
+///
+/// The elements of vectors are copied, so the result does not borrow `data`. Returns an error if
+/// `ty` contains a dictionary, a builder, or a SIMD value.
+///
+/// # Safety
+///
+/// `data` must point to a valid value of type `ty`, such as the result of a program.
+pub unsafe fn read_value(data: Data, ty: &Type) -> WeldResult<Value> {
+    use crate::ast::ScalarKind::*;
+    let pointer = data as *const u8;
+    let value = match *ty {
+        Scalar(kind) => match kind {
+            Bool => Value::Bool(ptr::read(pointer) != 0),
+            I8 => Value::I8(ptr::read(pointer as *const i8)),
+            I16 => Value::I16(ptr::read(pointer as *const i16)),
+            I32 => Value::I32(ptr::read(pointer as *const i32)),
+            I64 => Value::I64(ptr::read(pointer as *const i64)),
+            U8 => Value::U8(ptr::read(pointer)),
+            U16 => Value::U16(ptr::read(pointer as *const u16)),
+            U32 => Value::U32(ptr::read(pointer as *const u32)),
+            U64 => Value::U64(ptr::read(pointer as *const u64)),
+            F32 => Value::F32(ptr::read(pointer as *const f32)),
+            F64 => Value::F64(ptr::read(pointer as *const f64)),
+        },
+        Vector(ref elem_ty) => {
+            let elements = ptr::read(pointer as *const *const u8);
+            let len = ptr::read(pointer.add(POINTER_SIZE) as *const i64);
+            let size = TypeLayout::of(elem_ty)?.size;
+            let elems = (0..len.max(0) as usize)
+                .map(|i| read_value(elements.add(i * size) as Data, elem_ty))
+                .collect::<WeldResult<_>>()?;
+            Value::vector(elems)
+        }
+        Struct(ref tys, _) => {
+            let offsets = TypeLayout::of(ty)?.offsets;
+            Value::Struct(
+                tys.iter()
+                    .zip(offsets)
+                    .map(|(ty, offset)| read_value(pointer.add(offset) as Data, ty))
+                    .collect::<WeldResult<_>>()?,
+            )
+        }
+        Alias(_, ref ty) => read_value(data, ty)?,
+        _ => return compile_err!("Cannot read a value of type {}", ty),
+    };
+    Ok(value)
+}
+
+// Ensures that the layouts match the sizes of the types in the backend.
+#[test]
+fn layouts_match_backend() {
+    use crate::ast::{Annotations, BinOpKind};
+    use crate::codegen::size_of;
+    use crate::syntax::parser::parse_type;
+
+    let types = [
+        "bool",
+        "i16",
+        "f64",
+        "simd[i64]",
+        "vec[i8]",
+        "dict[i32,i64]",
+        "{i8,i64,i16}",
+        "{bool,{i8,f32},vec[i32]}",
+        "{}",
+    ];
+    for code in types.iter() {
+        let ty = parse_type(code).unwrap();
+        assert_eq!(TypeLayout::of(&ty).unwrap().size, size_of(&ty), "{}", code);
+    }
+
+    let builders = [
+        Appender(Box::new(Scalar(ScalarKind::I32))),
+        Merger(Box::new(Scalar(ScalarKind::I64)), BinOpKind::Add),
+        VecMerger(Box::new(Scalar(ScalarKind::F32)), BinOpKind::Add),
+    ];
+    for kind in builders.iter() {
+        let ty = Builder(kind.clone(), Annotations::new());
+        assert_eq!(TypeLayout::of(&ty).unwrap().size, size_of(&ty), "{}", kind);
+    }
+
+    let layout = TypeLayout::of(&parse_type("{i8,i64,i16}").unwrap()).unwrap();
+    assert_eq!(layout.offsets, vec![0, 8, 16]);
+    assert_eq!((layout.size, layout.align), (24, 8));
+}
+
+#[test]
+fn write_and_read_values() {
+    use crate::syntax::parser::parse_type;
+
+    let ty = parse_type("{bool,vec[{i8,f64}],u16}").unwrap();
+    let value = Value::Struct(vec![
+        Value::Bool(true),
+        Value::vector(vec![
+            Value::Struct(vec![Value::I8(-1), Value::F64(0.5)]),
+            Value::Struct(vec![Value::I8(2), Value::F64(-3.0)]),
+        ]),
+        Value::U16(7),
+    ]);
+    let buffer = ValueBuffer::new(&value, &ty).unwrap();
+    assert_eq!(unsafe { read_value(buffer.data(), &ty).unwrap() }, value);
+
+    assert!(ValueBuffer::new(&Value::I32(1), &ty).is_err());
+    assert!(ValueBuffer::new(&Value::dict(vec![]), &parse_type("dict[i32,i32]").unwrap()).is_err());
+}
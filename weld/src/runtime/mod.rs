@@ -5,7 +5,11 @@
 use fnv;
 use libc;
 
+pub(crate) mod cuda;
 pub mod ffi;
+pub mod layout;
+mod numa;
+pub mod serialize;
 
 use self::ffi::*;
 
@@ -14,10 +18,17 @@ use std::alloc::System as Allocator;
 
 use fnv::FnvHashMap;
 
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::ffi::CStr;
 use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Once;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
 
 use std::alloc::{GlobalAlloc, Layout};
 
@@ -29,6 +40,19 @@ static ONCE: Once = Once::new();
 /// Alignment for allocations.
 const DEFAULT_ALIGN: usize = 8;
 
+/// The maximum number of parallel loops that run on several threads while nested in each other.
+///
+/// Loops nested more deeply run all of their chunks on the calling thread, which bounds the number
+/// of tasks a program with deeply nested loops creates.
+const MAX_PARALLEL_DEPTH: usize = 2;
+
+thread_local! {
+    /// The number of parallel loops whose chunks the current thread is running.
+    static LOOP_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// The CPUs that the workers of the current thread's outermost loop are pinned to.
+    static LOOP_CPUS: RefCell<Vec<usize>> = const { RefCell::new(vec![]) };
+}
+
 /// An errno set by the runtime but also used by the Weld API.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd)]
 #[repr(u64)]
@@ -42,6 +66,9 @@ pub enum WeldRuntimeErrno {
     /// Dynamic library load error.
     LoadLibraryError,
     /// Weld compilation error.
+    ///
+    /// Errors in parsing and type inference have the more specific codes `ParseError` and
+    /// `TypeError`.
     CompileError,
     /// Array out-of-bounds error.
     ArrayOutOfBounds,
@@ -67,6 +94,16 @@ pub enum WeldRuntimeErrno {
     KeyNotFoundError,
     /// An assertion evaluated to `false`.
     AssertionError,
+    /// A Weld program could not be parsed.
+    ParseError,
+    /// A Weld program failed type inference.
+    TypeError,
+    /// A chunk source was not found or returned an invalid chunk.
+    ChunkSourceError,
+    /// Integer arithmetic overflowed with `weld.compile.checkedArithmetic` enabled.
+    IntegerOverflow,
+    /// An integer was divided by zero with `weld.compile.checkedArithmetic` enabled.
+    DivideByZero,
     /// Maximum errno value.
     ///
     /// All errors will have a value less than this value and greater than 0.
@@ -80,91 +117,342 @@ impl fmt::Display for WeldRuntimeErrno {
     }
 }
 
-/// Maintains information about a single Weld run.
-#[derive(Debug, PartialEq)]
-pub struct WeldRuntimeContext {
+/// The payload of the unwind that stops a run when the runtime raises an error.
+#[derive(Clone, Copy, Debug)]
+pub struct RunError(pub WeldRuntimeErrno);
+
+/// A source of loop input that the host provides one chunk at a time.
+///
+/// A `chunkiter[T](source)` iterator in a Weld program requests chunks from the source with the
+/// handle `source` until the source is exhausted, so the input (e.g., a file or a sequence of
+/// record batches) never needs to be in memory all at once. Each chunk holds a whole number of
+/// elements of type `T` in the layout the runtime uses for `T` (see `layout::ValueBuffer`), and
+/// must be aligned for `T`.
+///
+/// A loop over a `chunkiter` runs on a single thread and consumes its source, so the source should
+/// be read by exactly one loop, which is not nested in another loop.
+pub trait ChunkSource: Send {
+    /// Returns the next chunk, or `None` if the source is exhausted.
+    ///
+    /// The chunk only needs to remain valid until the next call.
+    fn next_chunk(&mut self) -> Option<&[u8]>;
+}
+
+/// The chunk sources added to a context, indexed by their handles.
+#[derive(Default)]
+struct ChunkSources(Vec<Box<dyn ChunkSource>>);
+
+impl fmt::Debug for ChunkSources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChunkSources({})", self.0.len())
+    }
+}
+
+impl PartialEq for ChunkSources {
+    /// Sources are equal if they are the same objects.
+    fn eq(&self, other: &ChunkSources) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().zip(other.0.iter()).all(|(a, b)| {
+                ptr::eq(
+                    a.as_ref() as *const _ as *const u8,
+                    b.as_ref() as *const _ as *const u8,
+                )
+            })
+    }
+}
+
+/// The number of threads that the parallel loops of a run have started and not yet stopped.
+#[derive(Debug, Default)]
+struct BusyWorkers(AtomicI64);
+
+impl PartialEq for BusyWorkers {
+    fn eq(&self, other: &BusyWorkers) -> bool {
+        self.0.load(Ordering::Relaxed) == other.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The memory allocated by a run, and freed memory kept for later runs.
+#[derive(Debug, Default, PartialEq)]
+struct Heap {
     /// Maps pointers to allocation size in bytes.
     allocations: FnvHashMap<Ptr, Layout>,
+    /// Number of allocated bytes so far.
+    ///
+    /// This will always be equal to `allocations.values().sum()`.
+    allocated: usize,
+    /// The NUMA node of each allocation, if allocations are placed on the allocating thread's node.
+    nodes: FnvHashMap<Ptr, i32>,
+    /// Freed blocks kept for reuse, by size in bytes and NUMA node.
+    ///
+    /// Runs that repeat the allocations of an earlier run in the same context, e.g., a module run
+    /// on batches of the same size, take their memory from here instead of the system allocator.
+    /// Blocks are only reused on the node they were placed on.
+    arena: FnvHashMap<(usize, i32), Vec<Ptr>>,
+    /// Number of bytes held in `arena`.
+    ///
+    /// These bytes count towards the memory limit, and are released if a run needs them.
+    arena_bytes: usize,
+}
+
+impl Heap {
+    /// Returns the blocks in the arena to the system allocator.
+    unsafe fn release_arena(&mut self) {
+        for ((size, _), blocks) in self.arena.drain() {
+            let layout = Layout::from_size_align_unchecked(size, DEFAULT_ALIGN);
+            for pointer in blocks {
+                Allocator.dealloc(pointer, layout);
+            }
+        }
+        self.arena_bytes = 0;
+    }
+}
+
+/// The outcome of a run.
+#[derive(Debug, PartialEq)]
+struct RunStatus {
     /// An error code set for the context.
     errno: WeldRuntimeErrno,
+    /// Identifies where in the program the error was raised, if the generated code provided it.
+    error_site: Option<i64>,
     /// A result pointer set by the runtime.
     result: Ptr,
+}
+
+/// Locks `mutex`.
+///
+/// A run that raises an error unwinds out of the runtime, possibly while holding a lock (e.g.,
+/// when an allocation exceeds the memory limit), so a poisoned lock is still usable. The state a
+/// lock protects is only updated once the checks that may raise an error have passed.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Maintains information about a single Weld run.
+///
+/// Generated code, including the worker threads of parallel loops, only accesses the context
+/// through shared references created from the run handle. The state a run updates is therefore
+/// behind a `Mutex` or an atomic, and the remaining fields are only changed through `&mut self`
+/// between runs, while the host holds the context exclusively.
+#[derive(Debug)]
+pub struct WeldRuntimeContext {
+    /// The memory allocated by the run.
+    heap: Mutex<Heap>,
+    /// The error code and result of the run.
+    status: Mutex<RunStatus>,
     /// The number of worker threads.
     nworkers: i32,
+    /// Whether parallel loops pin their worker threads to cores.
+    pin_threads: bool,
+    /// Whether allocations are placed on the NUMA node of the thread that makes them.
+    numa_local: bool,
     /// A memory limit.
     memlimit: usize,
-    /// Number of allocated bytes so far.
-    ///
-    /// This will always be equal to `allocations.values().sum()`.
-    allocated: usize,
+    /// Sources of chunked loop input.
+    sources: Mutex<ChunkSources>,
+    /// Worker threads started by parallel loops, not counting the thread that started the run.
+    busy_workers: BusyWorkers,
+}
+
+impl PartialEq for WeldRuntimeContext {
+    fn eq(&self, other: &WeldRuntimeContext) -> bool {
+        // Locking a context's mutexes twice would deadlock.
+        if ptr::eq(self, other) {
+            return true;
+        }
+        *lock(&self.heap) == *lock(&other.heap)
+            && *lock(&self.status) == *lock(&other.status)
+            && self.nworkers == other.nworkers
+            && self.pin_threads == other.pin_threads
+            && self.numa_local == other.numa_local
+            && self.memlimit == other.memlimit
+            && *lock(&self.sources) == *lock(&other.sources)
+            && self.busy_workers == other.busy_workers
+    }
 }
 
 /// Private API used by the FFI.
 impl WeldRuntimeContext {
-    unsafe fn malloc(&mut self, size: i64) -> Ptr {
+    unsafe fn malloc(&self, size: i64) -> Ptr {
         if size == 0 {
             trace!("Alloc'd 0-size pointer (null)");
             return ptr::null_mut();
         }
 
         let size = size as usize;
+        let layout = Layout::from_size_align_unchecked(size, DEFAULT_ALIGN);
+
+        let node = self.current_node();
+        let mut heap = lock(&self.heap);
+        let reused = heap.arena.get_mut(&(size, node)).and_then(Vec::pop);
+        let mem = match reused {
+            Some(mem) => {
+                heap.arena_bytes -= size;
+                mem
+            }
+            None => {
+                let total = heap.allocated + size;
+                self.reserve(&mut heap, total);
+                let mem = Allocator.alloc(layout);
+                if self.numa_local {
+                    numa::prefer_node(mem, size, node);
+                }
+                mem
+            }
+        };
 
-        if self.allocated + size > self.memlimit {
-            self.errno = WeldRuntimeErrno::OutOfMemory;
-            panic!(
-                "Weld run ran out of memory (limit={}, attempted to allocate {}",
-                self.memlimit,
-                self.allocated + size
-            );
-        }
-        let layout = Layout::from_size_align_unchecked(size as usize, DEFAULT_ALIGN);
-        let mem = Allocator.alloc(layout);
-
-        self.allocated += layout.size();
+        heap.allocated += layout.size();
         trace!("Alloc'd pointer {:?} ({} bytes)", mem, layout.size());
 
-        self.allocations.insert(mem, layout);
+        heap.allocations.insert(mem, layout);
+        if self.numa_local {
+            heap.nodes.insert(mem, node);
+        }
         mem
     }
 
-    unsafe fn realloc(&mut self, pointer: Ptr, size: i64) -> Ptr {
+    unsafe fn realloc(&self, pointer: Ptr, size: i64) -> Ptr {
         if pointer.is_null() {
             return self.malloc(size);
         }
 
         let size = size as usize;
-        let old_layout = self.allocations.remove(&pointer).unwrap();
-        if self.allocated - old_layout.size() + size > self.memlimit {
-            self.errno = WeldRuntimeErrno::OutOfMemory;
-            panic!(
-                "Weld run ran out of memory (limit={}, attempted to allocate {}",
-                self.memlimit,
-                self.allocated - old_layout.size() + size
-            );
-        }
+        let mut heap = lock(&self.heap);
+        let old_layout = heap.allocations[&pointer];
+        let total = heap.allocated - old_layout.size() + size;
+        self.reserve(&mut heap, total);
+        heap.allocations.remove(&pointer);
 
         // Must pass *old* layout to realloc!
         let mem = Allocator.realloc(pointer, old_layout, size);
         let new_layout = Layout::from_size_align_unchecked(size, DEFAULT_ALIGN);
 
-        self.allocated -= old_layout.size();
-        self.allocated += new_layout.size();
+        heap.allocated -= old_layout.size();
+        heap.allocated += new_layout.size();
 
-        self.allocations.insert(mem, new_layout);
+        heap.allocations.insert(mem, new_layout);
+        if self.numa_local {
+            // Pages that were already written stay where they are.
+            let node = heap.nodes.remove(&pointer).unwrap_or(0);
+            numa::prefer_node(mem, size, node);
+            heap.nodes.insert(mem, node);
+        }
         mem
     }
 
-    fn set_errno(&mut self, errno: WeldRuntimeErrno) {
-        self.errno = errno;
-        panic!("Weld runtime threw error: {}", self.errno)
+    /// Checks that `total` allocated bytes fit in the memory limit, releasing the arena if needed.
+    ///
+    /// Stops the run with an `OutOfMemory` error if they do not fit.
+    unsafe fn reserve(&self, heap: &mut Heap, total: usize) {
+        if total + heap.arena_bytes > self.memlimit {
+            heap.release_arena();
+        }
+        if total > self.memlimit {
+            debug!(
+                "Weld run ran out of memory (limit={}, attempted to allocate {})",
+                self.memlimit, total
+            );
+            self.set_errno(WeldRuntimeErrno::OutOfMemory);
+        }
+    }
+
+    /// Reserves up to `wanted` worker threads for a parallel loop.
+    ///
+    /// Returns the number of workers already running and the number of workers reserved. The
+    /// reservation is limited so that the run never uses more than `threads()` threads, including
+    /// the thread that started it.
+    fn reserve_workers(&self, wanted: i64) -> (i64, i64) {
+        let limit = i64::from(self.nworkers) - 1;
+        let mut reserved = 0;
+        let busy = self
+            .busy_workers
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |busy| {
+                reserved = cmp::max(cmp::min(wanted, limit - busy), 0);
+                Some(busy + reserved)
+            })
+            .unwrap();
+        (busy, reserved)
     }
 
-    fn set_result(&mut self, result: Ptr) {
-        self.result = result;
+    /// Releases worker threads reserved with `reserve_workers`.
+    fn release_workers(&self, workers: i64) {
+        self.busy_workers.0.fetch_sub(workers, Ordering::Relaxed);
+    }
+
+    /// Returns the NUMA node that allocations made by the calling thread are placed on.
+    fn current_node(&self) -> i32 {
+        if self.numa_local {
+            numa::current_node()
+        } else {
+            0
+        }
+    }
+
+    /// Sets the error code of this run and stops the run.
+    ///
+    /// The run is stopped by unwinding out of the generated code with a `RunError`, which
+    /// `WeldModule::run` catches to return the error.
+    ///
+    /// If several threads of a parallel loop raise errors, the last one is kept.
+    fn set_errno(&self, errno: WeldRuntimeErrno) -> ! {
+        lock(&self.status).errno = errno;
+        debug!("Weld runtime threw error: {}", errno);
+        panic::resume_unwind(Box::new(RunError(errno)))
+    }
+
+    /// Sets the error code of this run and the site that raised it, and stops the run.
+    fn set_error(&self, errno: WeldRuntimeErrno, site: i64) -> ! {
+        lock(&self.status).error_site = Some(site);
+        self.set_errno(errno)
+    }
+
+    /// Returns the next non-empty chunk of the source `source` and its number of elements, or a
+    /// length of 0 if the source is exhausted.
+    ///
+    /// Stops the run with a `ChunkSourceError` if the source does not exist or returns a chunk that
+    /// does not hold a whole number of `elem_size`-byte elements.
+    unsafe fn next_chunk(&self, source: i64, elem_size: i64) -> (Ptr, i64) {
+        let mut sources = lock(&self.sources);
+        let chunk = match sources.0.get_mut(source as usize) {
+            Some(chunk_source) => loop {
+                match chunk_source.next_chunk() {
+                    Some([]) => continue,
+                    Some(chunk) => break Some((chunk.as_ptr(), chunk.len())),
+                    None => break None,
+                }
+            },
+            _ => {
+                debug!("Chunk source {} does not exist", source);
+                drop(sources);
+                self.set_errno(WeldRuntimeErrno::ChunkSourceError)
+            }
+        };
+        // The chunk stays valid until the next call, which only the thread running the loop makes.
+        drop(sources);
+        match chunk {
+            Some((data, size)) if elem_size > 0 && size % elem_size as usize == 0 => {
+                trace!("Loaded chunk of {} bytes from source {}", size, source);
+                (data as Ptr, (size / elem_size as usize) as i64)
+            }
+            Some((_, size)) => {
+                debug!(
+                    "Chunk of {} bytes from source {} does not hold {}-byte elements",
+                    size, source, elem_size
+                );
+                self.set_errno(WeldRuntimeErrno::ChunkSourceError)
+            }
+            None => (ptr::null_mut(), 0),
+        }
+    }
+
+    fn set_result(&self, result: Ptr) {
+        lock(&self.status).result = result;
     }
 
     fn result(&self) -> Ptr {
-        self.result
+        lock(&self.status).result
     }
 }
 
@@ -173,25 +461,54 @@ impl WeldRuntimeContext {
     /// Construct a new `WeldRuntimeContext`.
     pub fn new(nworkers: i32, memlimit: i64) -> WeldRuntimeContext {
         WeldRuntimeContext {
-            allocations: FnvHashMap::default(),
-            errno: WeldRuntimeErrno::Success,
-            result: ptr::null_mut(),
+            heap: Mutex::new(Heap::default()),
+            status: Mutex::new(RunStatus {
+                errno: WeldRuntimeErrno::Success,
+                error_site: None,
+                result: ptr::null_mut(),
+            }),
             nworkers,
+            pin_threads: false,
+            numa_local: false,
             memlimit: memlimit as usize,
-            allocated: 0,
+            sources: Mutex::new(ChunkSources::default()),
+            busy_workers: BusyWorkers::default(),
         }
     }
 
+    /// Adds a source of chunked loop input and returns its handle.
+    ///
+    /// Programs read the source with a `chunkiter` over the handle.
+    pub fn add_source(&mut self, source: Box<dyn ChunkSource>) -> i64 {
+        let sources = self.sources.get_mut().unwrap_or_else(|p| p.into_inner());
+        sources.0.push(source);
+        (sources.0.len() - 1) as i64
+    }
+
+    /// Removes the chunk sources added to this context.
+    ///
+    /// Handles of removed sources may be returned again by `add_source`.
+    pub fn clear_sources(&mut self) {
+        self.sources
+            .get_mut()
+            .unwrap_or_else(|p| p.into_inner())
+            .0
+            .clear();
+    }
+
     /// Free an allocated data value.
     ///
+    /// The memory is kept in this context's arena for later allocations.
+    ///
     /// Panics if the passed value was not allocated by the Weld runtime.
-    pub unsafe fn free(&mut self, pointer: Ptr) {
+    pub unsafe fn free(&self, pointer: Ptr) {
         if pointer.is_null() {
             trace!("Freed null pointer (no-op) in runst_free()");
             return;
         }
 
-        let layout = self.allocations.remove(&pointer).unwrap();
+        let mut heap = lock(&self.heap);
+        let layout = heap.allocations.remove(&pointer).unwrap();
 
         trace!(
             "Freeing pointer {:?} ({} bytes) in runst_free()",
@@ -199,13 +516,36 @@ impl WeldRuntimeContext {
             layout.size()
         );
 
-        Allocator.dealloc(pointer, layout);
-        self.allocated -= layout.size();
+        let node = heap.nodes.remove(&pointer).unwrap_or(0);
+        heap.allocated -= layout.size();
+        heap.arena
+            .entry((layout.size(), node))
+            .or_default()
+            .push(pointer);
+        heap.arena_bytes += layout.size();
+    }
+
+    /// Frees every value allocated in this context, keeping the memory in its arena.
+    ///
+    /// Pointers to memory allocated in this context are invalid after this call.
+    pub unsafe fn free_all(&mut self) {
+        let heap = self.heap.get_mut().unwrap_or_else(|p| p.into_inner());
+        for (pointer, layout) in heap.allocations.drain() {
+            let node = heap.nodes.remove(&pointer).unwrap_or(0);
+            heap.arena
+                .entry((layout.size(), node))
+                .or_default()
+                .push(pointer);
+        }
+        heap.arena_bytes += heap.allocated;
+        heap.allocated = 0;
     }
 
     /// Returns the number of bytes allocated by this Weld run.
+    ///
+    /// This does not include freed memory kept in the arena.
     pub fn memory_usage(&self) -> i64 {
-        self.allocated as i64
+        lock(&self.heap).allocated as i64
     }
 
     /// Returns a 64-bit ID identifying this run.
@@ -214,8 +554,25 @@ impl WeldRuntimeContext {
     }
 
     /// Returns the error code of this run.
-    fn errno(&self) -> WeldRuntimeErrno {
-        self.errno
+    pub fn errno(&self) -> WeldRuntimeErrno {
+        lock(&self.status).errno
+    }
+
+    /// Clears the error code before a new run.
+    ///
+    /// Memory allocated by a run that failed is not freed until the context is dropped or all of
+    /// its values are freed.
+    pub fn reset_errno(&mut self) {
+        let status = self.status.get_mut().unwrap_or_else(|p| p.into_inner());
+        status.errno = WeldRuntimeErrno::Success;
+        status.error_site = None;
+    }
+
+    /// Returns the site that raised the error of this run, if the generated code provided it.
+    ///
+    /// The compiled module maps sites to locations in the program.
+    pub fn error_site(&self) -> Option<i64> {
+        lock(&self.status).error_site
     }
 
     /// Returns the number of worker threads set for this run.
@@ -227,14 +584,43 @@ impl WeldRuntimeContext {
     pub fn memory_limit(&self) -> i64 {
         self.memlimit as i64
     }
+
+    /// Sets whether parallel loops pin their worker threads to cores.
+    ///
+    /// The `i`th worker of a loop runs only on the `i`th CPU the calling thread is allowed to run
+    /// on, so workers do not migrate between cores or sockets while they run. Pinning is only
+    /// supported on Linux.
+    pub fn set_thread_pinning(&mut self, pin_threads: bool) {
+        self.pin_threads = pin_threads;
+    }
+
+    /// Returns whether parallel loops pin their worker threads to cores.
+    pub fn thread_pinning(&self) -> bool {
+        self.pin_threads
+    }
+
+    /// Sets whether allocations are placed on the NUMA node of the thread that makes them.
+    ///
+    /// Builders allocated by a worker of a parallel loop are then in memory local to the worker's
+    /// socket, and freed memory is only reused by threads on the same node. This works best with
+    /// thread pinning, which keeps workers on one node. It is only supported on Linux.
+    pub fn set_numa_local(&mut self, numa_local: bool) {
+        self.numa_local = numa_local;
+    }
+
+    /// Returns whether allocations are placed on the NUMA node of the thread that makes them.
+    pub fn numa_local(&self) -> bool {
+        self.numa_local
+    }
 }
 
 impl Drop for WeldRuntimeContext {
     fn drop(&mut self) {
         // Free memory allocated by the run.
-        trace!("Allocations: {}", self.allocations.len());
+        let heap = self.heap.get_mut().unwrap_or_else(|p| p.into_inner());
+        trace!("Allocations: {}", heap.allocations.len());
         unsafe {
-            for (pointer, layout) in self.allocations.iter() {
+            for (pointer, layout) in heap.allocations.iter() {
                 trace!(
                     "Freeing pointer {:?} ({} bytes) in drop()",
                     *pointer,
@@ -242,23 +628,163 @@ impl Drop for WeldRuntimeContext {
                 );
                 Allocator.dealloc(*pointer, layout.clone());
             }
+            heap.release_arena();
         }
     }
 }
 
+/// The arguments of a parallel loop shared by its worker threads.
+struct SharedLoop {
+    run: WeldRuntimeContextRef,
+    function: WeldChunkFunction,
+    arguments: Ptr,
+    chunks: i64,
+    /// The index of the next chunk to run.
+    next: AtomicI64,
+    /// The payload of the first chunk that unwound, e.g., because it raised a runtime error.
+    failure: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+// The workers only read `run`, `function`, `arguments`, and `chunks`, and update the rest through
+// the atomic and the mutex. The generated chunk function only reads the loop's arguments, and each
+// chunk writes to its own piece of the builder. The run handle points to a context that outlives
+// the loop, and the runtime only creates shared references to it while a run is in progress (see
+// `WeldRuntimeContext`), so the workers may use it at the same time.
+unsafe impl Sync for SharedLoop {}
+
+impl SharedLoop {
+    /// Runs chunks until none remain or one of them fails.
+    unsafe fn work(&self) {
+        while self.work_once() {}
+    }
+
+    /// Runs the next chunk, returning `false` if none remain or the chunk failed.
+    unsafe fn work_once(&self) -> bool {
+        let chunk = self.next.fetch_add(1, Ordering::Relaxed);
+        if chunk >= self.chunks {
+            return false;
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            (self.function)(self.arguments, chunk, self.run)
+        }));
+        if let Err(payload) = result {
+            // Stop handing out chunks and keep the first failure.
+            self.next.store(self.chunks, Ordering::Relaxed);
+            let mut failure = self.failure.lock().unwrap();
+            failure.get_or_insert(payload);
+            return false;
+        }
+        true
+    }
+
+    /// Returns the number of chunks that no thread has started.
+    fn remaining(&self) -> i64 {
+        cmp::max(self.chunks - self.next.load(Ordering::Relaxed), 0)
+    }
+}
+
+/// Runs each of the `chunks` chunks of a loop once, using up to `threads()` threads.
+///
+/// Chunks are handed out dynamically, so a thread that finishes its chunk early takes the next one
+/// instead of waiting for slower threads. The calling thread is one of the workers. Each chunk
+/// updates its own piece of the loop's builder, so the order in which chunks run does not matter.
+///
+/// The run never uses more than `threads()` threads, so a loop nested in the body of another
+/// parallel loop only starts workers on threads that are idle, e.g., because the enclosing loop has
+/// no chunks left for them. The calling thread checks for idle threads before each of its chunks,
+/// so a long nested loop picks up threads as enclosing loops finish. Loops nested more than
+/// `MAX_PARALLEL_DEPTH` deep run on the calling thread.
+///
+/// If a chunk raises a runtime error, the remaining chunks are skipped and the error is raised
+/// again on the calling thread once all workers stop.
+unsafe fn run_chunks(
+    run: WeldRuntimeContextRef,
+    function: WeldChunkFunction,
+    arguments: Ptr,
+    chunks: i64,
+) {
+    let depth = LOOP_DEPTH.with(Cell::get);
+    let shared = SharedLoop {
+        run,
+        function,
+        arguments,
+        chunks,
+        next: AtomicI64::new(0),
+        failure: Mutex::new(None),
+    };
+    // The CPUs to pin workers to. Workers of nested loops use the CPUs of the outermost loop, and
+    // the outermost loop restores the calling thread's own affinity when it finishes.
+    let cpus = if !(*run).thread_pinning() {
+        vec![]
+    } else if depth == 0 {
+        numa::allowed_cpus()
+    } else {
+        LOOP_CPUS.with(|cpus| cpus.borrow().clone())
+    };
+    let enter = |worker: Option<i64>| {
+        LOOP_DEPTH.with(|d| d.set(depth + 1));
+        LOOP_CPUS.with(|c| c.borrow_mut().clone_from(&cpus));
+        if let Some(worker) = worker.filter(|_| !cpus.is_empty()) {
+            numa::pin_current_thread(&[cpus[worker as usize % cpus.len()]]);
+        }
+    };
+    // The calling thread of a nested loop is already pinned.
+    enter(if depth == 0 { Some(0) } else { None });
+    let mut workers = 0;
+    thread::scope(|scope| loop {
+        if depth < MAX_PARALLEL_DEPTH {
+            // Each worker takes at least one chunk, and the calling thread takes the next one.
+            let (busy, reserved) = (*run).reserve_workers(shared.remaining() - 1);
+            for worker in busy + 1..=busy + reserved {
+                let (shared, enter) = (&shared, &enter);
+                scope.spawn(move || {
+                    enter(Some(worker));
+                    shared.work();
+                    (*shared.run).release_workers(1);
+                });
+            }
+            workers += reserved;
+        }
+        if !shared.work_once() {
+            break;
+        }
+    });
+    LOOP_DEPTH.with(|d| d.set(depth));
+    trace!(
+        "Ran {} chunks on {} threads at depth {}",
+        chunks,
+        workers + 1,
+        depth
+    );
+    if depth == 0 {
+        LOOP_CPUS.with(|c| c.borrow_mut().clear());
+        if !cpus.is_empty() {
+            numa::pin_current_thread(&cpus);
+        }
+    }
+    if let Some(payload) = shared.failure.into_inner().unwrap() {
+        panic::resume_unwind(payload);
+    }
+}
+
 unsafe fn initialize() {
     ONCE.call_once(|| {
         // Hack to prevent symbols from being compiled out in a Rust binary.
-        let mut x = weld_runst_init as usize;
-        x += weld_runst_set_result as usize;
-        x += weld_runst_get_result as usize;
-        x += weld_runst_malloc as usize;
-        x += weld_runst_realloc as usize;
-        x += weld_runst_free as usize;
-        x += weld_runst_get_errno as usize;
-        x += weld_runst_set_errno as usize;
-        x += weld_runst_assert as usize;
-        x += weld_runst_print as usize;
+        let mut x = weld_runst_init as *const () as usize;
+        x += weld_runst_set_result as *const () as usize;
+        x += weld_runst_get_result as *const () as usize;
+        x += weld_runst_malloc as *const () as usize;
+        x += weld_runst_realloc as *const () as usize;
+        x += weld_runst_free as *const () as usize;
+        x += weld_runst_get_errno as *const () as usize;
+        x += weld_runst_set_errno as *const () as usize;
+        x += weld_runst_set_error as *const () as usize;
+        x += weld_runst_assert as *const () as usize;
+        x += weld_runst_print as *const () as usize;
+        x += weld_runst_parallel_for as *const () as usize;
+        x += weld_runst_gpu_for as *const () as usize;
+        x += weld_runst_next_chunk as *const () as usize;
+        x += weld_runst_profile_time as *const () as usize;
 
         trace!("Runtime initialized with hashed values {}", x);
     });
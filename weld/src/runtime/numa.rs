@@ -0,0 +1,122 @@
+//! Thread and memory placement on multi-socket machines.
+//!
+//! Parallel loops can pin their worker threads to cores, and can place the memory a worker
+//! allocates on the NUMA node the worker runs on. Both are only supported on Linux: on other
+//! platforms threads are never pinned and every thread runs on node 0.
+
+use super::Ptr;
+
+#[cfg(target_os = "linux")]
+use std::mem;
+
+/// The `mbind` policy that places pages on a node if it has free memory.
+#[cfg(target_os = "linux")]
+const MPOL_PREFERRED: libc::c_int = 1;
+
+/// Returns the CPUs the calling thread is allowed to run on, in ascending order.
+///
+/// Returns an empty list if the CPUs are not known.
+#[cfg(target_os = "linux")]
+pub fn allowed_cpus() -> Vec<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return vec![];
+        }
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cpus() -> Vec<usize> {
+    vec![]
+}
+
+/// Restricts the calling thread to run on `cpus`, returning whether the thread was pinned.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> bool {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &[usize]) -> bool {
+    false
+}
+
+/// Returns the NUMA node of the CPU the calling thread runs on.
+#[cfg(target_os = "linux")]
+pub fn current_node() -> i32 {
+    let mut cpu: libc::c_uint = 0;
+    let mut node: libc::c_uint = 0;
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_getcpu,
+            &mut cpu as *mut libc::c_uint,
+            &mut node as *mut libc::c_uint,
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    if result == 0 {
+        node as i32
+    } else {
+        0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn current_node() -> i32 {
+    0
+}
+
+/// Asks the kernel to place the pages of the `size` bytes at `pointer` on `node`.
+///
+/// Only pages that lie entirely within the allocation and that have not been touched yet are
+/// affected. Placement is a hint, so errors (e.g., on kernels without NUMA support) are ignored.
+#[cfg(target_os = "linux")]
+pub unsafe fn prefer_node(pointer: Ptr, size: usize, node: i32) {
+    let mask_bits = 8 * mem::size_of::<libc::c_ulong>();
+    if node < 0 || node as usize >= mask_bits {
+        return;
+    }
+    let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+    let start = (pointer as usize).div_ceil(page) * page;
+    let end = (pointer as usize + size) / page * page;
+    if end <= start {
+        return;
+    }
+    let mask: libc::c_ulong = 1 << node;
+    libc::syscall(
+        libc::SYS_mbind,
+        start,
+        end - start,
+        MPOL_PREFERRED,
+        &mask as *const libc::c_ulong,
+        mask_bits + 1,
+        0 as libc::c_uint,
+    );
+}
+
+#[cfg(not(target_os = "linux"))]
+pub unsafe fn prefer_node(_pointer: Ptr, _size: usize, _node: i32) {}
+
+#[test]
+fn pin_thread_to_allowed_cpu() {
+    let cpus = allowed_cpus();
+    if let Some(&cpu) = cpus.first() {
+        std::thread::spawn(move || {
+            assert!(pin_current_thread(&[cpu]));
+            assert_eq!(allowed_cpus(), vec![cpu]);
+            assert!(current_node() >= 0);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+//! Serializing values to contiguous byte buffers.
+//!
+//! `serialize` and `deserialize` use the format of the `serialize` and `deserialize[T]`
+//! expressions described in `docs/serialization.md`, so a buffer produced by a program can be
+//! read by the host and vice versa. Since the layout of a dictionary is private, a program that
+//! returns dictionaries can `serialize` its result and the host can read it with `deserialize`.
+//!
+//! Values without pointers are written with their in-memory layout (including the padding of
+//! structs), which the backend copies with a single `memcpy`. Values with pointers are written
+//! field by field or element by element, with vectors and dictionaries preceded by their 8-byte
+//! length.
+//!
+//! # Example
+//!
+//! ```rust
+//! use weld::ast::{ScalarKind, Type, Value};
+//! use weld::runtime::serialize::*;
+//!
+//! let ty = Type::Vector(Box::new(Type::Scalar(ScalarKind::I32)));
+//! let value = Value::vector(vec![Value::I32(1), Value::I32(2)]);
+//!
+//! let bytes = serialize(&value, &ty).unwrap();
+//! assert_eq!(bytes.len(), 8 + 2 * 4);
+//! assert_eq!(deserialize(&bytes, &ty).unwrap(), value);
+//! ```
+
+use std::convert::TryInto;
+
+use crate::ast::ScalarKind;
+use crate::ast::Type::{self, *};
+use crate::ast::Value;
+use crate::error::*;
+
+use super::layout::TypeLayout;
+
+/// The size of the length that precedes vectors and dictionaries.
+const LENGTH_SIZE: usize = 8;
+
+/// Serializes `value` of type `ty` into a new buffer.
+///
+/// Returns an error if `value` does not have type `ty`, or if `ty` contains a builder, a SIMD
+/// value, or a function.
+pub fn serialize(value: &Value, ty: &Type) -> WeldResult<Vec<u8>> {
+    let mut buffer = vec![];
+    put(&mut buffer, value, ty)?;
+    Ok(buffer)
+}
+
+/// Deserializes a value of type `ty` from `bytes`.
+///
+/// Returns an error if `bytes` is not exactly one serialized value of type `ty`.
+pub fn deserialize(bytes: &[u8], ty: &Type) -> WeldResult<Value> {
+    let mut reader = Reader { bytes, position: 0 };
+    let value = reader.value(ty)?;
+    if reader.position != bytes.len() {
+        return compile_err!(
+            "Serialized {} is {} bytes but the buffer has {}",
+            ty,
+            reader.position,
+            bytes.len()
+        );
+    }
+    Ok(value)
+}
+
+/// Returns whether values of type `ty` contain pointers.
+fn has_pointer(ty: &Type) -> bool {
+    match *ty {
+        Scalar(_) | Simd(_) => false,
+        Struct(ref tys, _) => tys.iter().any(has_pointer),
+        Alias(_, ref ty) => has_pointer(ty),
+        _ => true,
+    }
+}
+
+/// Appends `value` of type `ty` to `buffer`.
+fn put(buffer: &mut Vec<u8>, value: &Value, ty: &Type) -> WeldResult<()> {
+    match (ty, value) {
+        (&Scalar(kind), _) => put_scalar(buffer, kind, value),
+        (Struct(ref tys, _), Value::Struct(ref fields)) if tys.len() == fields.len() => {
+            if has_pointer(ty) {
+                for (field, ty) in fields.iter().zip(tys) {
+                    put(buffer, field, ty)?;
+                }
+            } else {
+                let layout = TypeLayout::of(ty)?;
+                let start = buffer.len();
+                buffer.resize(start + layout.size, 0);
+                for ((field, ty), offset) in fields.iter().zip(tys).zip(layout.offsets) {
+                    let bytes = serialize(field, ty)?;
+                    let position = start + offset;
+                    buffer[position..position + bytes.len()].copy_from_slice(&bytes);
+                }
+            }
+            Ok(())
+        }
+        (Vector(ref elem_ty), Value::Vector(ref elems)) => {
+            buffer.extend_from_slice(&(elems.len() as i64).to_ne_bytes());
+            for elem in elems.iter() {
+                put(buffer, elem, elem_ty)?;
+            }
+            Ok(())
+        }
+        (Dict(ref key_ty, ref value_ty), Value::Dict(ref entries)) => {
+            buffer.extend_from_slice(&(entries.len() as i64).to_ne_bytes());
+            for (key, value) in entries.iter() {
+                put(buffer, key, key_ty)?;
+                put(buffer, value, value_ty)?;
+            }
+            Ok(())
+        }
+        (Alias(_, ref ty), _) => put(buffer, value, ty),
+        _ => compile_err!("Cannot serialize {:?} as a value of type {}", value, ty),
+    }
+}
+
+/// Appends a scalar `value` to `buffer`.
+fn put_scalar(buffer: &mut Vec<u8>, kind: ScalarKind, value: &Value) -> WeldResult<()> {
+    use crate::ast::ScalarKind::*;
+    match (kind, value) {
+        (Bool, &Value::Bool(v)) => buffer.push(v as u8),
+        (I8, &Value::I8(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        (I16, &Value::I16(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        (I32, &Value::I32(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        (I64, &Value::I64(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        (U8, &Value::U8(v)) => buffer.push(v),
+        (U16, &Value::U16(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        (U32, &Value::U32(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        (U64, &Value::U64(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        (F32, &Value::F32(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        (F64, &Value::F64(v)) => buffer.extend_from_slice(&v.to_ne_bytes()),
+        _ => return compile_err!("Cannot serialize {:?} as a value of type {}", value, kind),
+    }
+    Ok(())
+}
+
+/// Reads serialized values from a buffer.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    /// The offset of the next value in `bytes`.
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Returns the next `size` bytes.
+    fn take(&mut self, size: usize) -> WeldResult<&'a [u8]> {
+        if self.bytes.len() - self.position < size {
+            return compile_err!("Serialized buffer ended after {} bytes", self.bytes.len());
+        }
+        let bytes = &self.bytes[self.position..self.position + size];
+        self.position += size;
+        Ok(bytes)
+    }
+
+    /// Reads the length of a vector or dictionary.
+    fn length(&mut self) -> WeldResult<usize> {
+        let bytes = self.take(LENGTH_SIZE)?;
+        let length = i64::from_ne_bytes(bytes.try_into().unwrap());
+        if length < 0 {
+            return compile_err!("Invalid serialized length {}", length);
+        }
+        Ok(length as usize)
+    }
+
+    /// Reads a value of type `ty`.
+    fn value(&mut self, ty: &Type) -> WeldResult<Value> {
+        let value = match *ty {
+            Scalar(kind) => self.scalar(kind)?,
+            Struct(ref tys, _) if has_pointer(ty) => Value::Struct(
+                tys.iter()
+                    .map(|ty| self.value(ty))
+                    .collect::<WeldResult<_>>()?,
+            ),
+            Struct(ref tys, _) => {
+                let layout = TypeLayout::of(ty)?;
+                let bytes = self.take(layout.size)?;
+                let fields = tys
+                    .iter()
+                    .zip(layout.offsets)
+                    .map(|(ty, offset)| {
+                        let mut reader = Reader {
+                            bytes,
+                            position: offset,
+                        };
+                        reader.value(ty)
+                    })
+                    .collect::<WeldResult<_>>()?;
+                Value::Struct(fields)
+            }
+            Vector(ref elem_ty) => {
+                let length = self.length()?;
+                let elems = (0..length)
+                    .map(|_| self.value(elem_ty))
+                    .collect::<WeldResult<_>>()?;
+                Value::vector(elems)
+            }
+            Dict(ref key_ty, ref value_ty) => {
+                let length = self.length()?;
+                let entries = (0..length)
+                    .map(|_| Ok((self.value(key_ty)?, self.value(value_ty)?)))
+                    .collect::<WeldResult<_>>()?;
+                Value::dict(entries)
+            }
+            Alias(_, ref ty) => self.value(ty)?,
+            _ => return compile_err!("Cannot deserialize a value of type {}", ty),
+        };
+        Ok(value)
+    }
+
+    /// Reads a scalar of kind `kind`.
+    fn scalar(&mut self, kind: ScalarKind) -> WeldResult<Value> {
+        use crate::ast::ScalarKind::*;
+        let bytes = self.take(TypeLayout::of(&Scalar(kind))?.size)?;
+        let value = match kind {
+            Bool => Value::Bool(bytes[0] != 0),
+            I8 => Value::I8(bytes[0] as i8),
+            I16 => Value::I16(i16::from_ne_bytes(bytes.try_into().unwrap())),
+            I32 => Value::I32(i32::from_ne_bytes(bytes.try_into().unwrap())),
+            I64 => Value::I64(i64::from_ne_bytes(bytes.try_into().unwrap())),
+            U8 => Value::U8(bytes[0]),
+            U16 => Value::U16(u16::from_ne_bytes(bytes.try_into().unwrap())),
+            U32 => Value::U32(u32::from_ne_bytes(bytes.try_into().unwrap())),
+            U64 => Value::U64(u64::from_ne_bytes(bytes.try_into().unwrap())),
+            F32 => Value::F32(f32::from_ne_bytes(bytes.try_into().unwrap())),
+            F64 => Value::F64(f64::from_ne_bytes(bytes.try_into().unwrap())),
+        };
+        Ok(value)
+    }
+}
+
+#[test]
+fn serialized_format() {
+    use crate::ast::FieldNames;
+    use crate::ast::ScalarKind::*;
+    // A struct without pointers keeps its padding, and a struct with pointers does not.
+    let elem_ty = Struct(vec![Scalar(I8), Scalar(I32)], FieldNames::none());
+    let ty = Struct(
+        vec![Scalar(I16), Vector(Box::new(elem_ty))],
+        FieldNames::none(),
+    );
+    let value = Value::Struct(vec![
+        Value::I16(-2),
+        Value::vector(vec![Value::Struct(vec![Value::I8(7), Value::I32(9)])]),
+    ]);
+    let bytes = serialize(&value, &ty).unwrap();
+
+    let mut expect = vec![];
+    expect.extend_from_slice(&(-2i16).to_ne_bytes());
+    expect.extend_from_slice(&1i64.to_ne_bytes());
+    expect.extend_from_slice(&[7, 0, 0, 0]);
+    expect.extend_from_slice(&9i32.to_ne_bytes());
+    assert_eq!(bytes, expect);
+    assert_eq!(deserialize(&bytes, &ty).unwrap(), value);
+}
+
+#[test]
+fn serialize_round_trips() {
+    use crate::ast::FieldNames;
+    use crate::ast::ScalarKind::*;
+    let ty = Dict(
+        Box::new(Scalar(I64)),
+        Box::new(Vector(Box::new(Struct(
+            vec![Scalar(Bool), Scalar(F64)],
+            FieldNames::none(),
+        )))),
+    );
+    let entry = |k, v: Vec<f64>| {
+        let elems = v
+            .into_iter()
+            .map(|x| Value::Struct(vec![Value::Bool(x > 0.0), Value::F64(x)]))
+            .collect();
+        (Value::I64(k), Value::vector(elems))
+    };
+    let value = Value::dict(vec![entry(3, vec![1.5, -2.0]), entry(-1, vec![])]);
+    let bytes = serialize(&value, &ty).unwrap();
+    assert_eq!(bytes.len(), 8 + (8 + 8 + 2 * 16) + (8 + 8));
+    assert_eq!(deserialize(&bytes, &ty).unwrap(), value);
+
+    // Buffers with missing or extra bytes are rejected.
+    assert!(deserialize(&bytes[..bytes.len() - 1], &ty).is_err());
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert!(deserialize(&longer, &ty).is_err());
+
+    assert!(serialize(&Value::I32(1), &Scalar(I64)).is_err());
+}
@@ -29,6 +29,8 @@ pub enum StatementKind {
         op: BinOpKind,
         left: Symbol,
         right: Symbol,
+        /// The location of the operator in the program, if known.
+        span: Option<Span>,
     },
     Broadcast(Symbol),
     Cast(Symbol, Type),
@@ -48,6 +50,8 @@ pub enum StatementKind {
     Lookup {
         child: Symbol,
         index: Symbol,
+        /// The location of the lookup in the program, if known.
+        span: Option<Span>,
     },
     OptLookup {
         child: Symbol,
@@ -111,6 +115,17 @@ pub struct ParallelForData {
     pub idx_arg: Symbol,
     pub body: FunctionId,
     pub innermost: bool,
+    /// The minimum number of iterations per chunk if the loop runs in parallel.
+    ///
+    /// This is set by the `grain_size` annotation on the loop.
+    pub grain_size: Option<i64>,
+    /// The number of operations in one iteration of the loop, as returned by
+    /// `Expr::operation_count` for the loop's body.
+    pub iteration_cost: usize,
+    /// The ID of the loop's expression, if known.
+    pub id: Option<NodeId>,
+    /// The location of the loop in the program, if known.
+    pub span: Option<Span>,
 }
 
 impl StatementKind {
@@ -140,6 +155,8 @@ impl StatementKind {
                         vars.push(iter.stride.as_ref().unwrap());
                         vars.push(iter.shape.as_ref().unwrap());
                         vars.push(iter.strides.as_ref().unwrap());
+                    } else if iter.kind == IterKind::ChunkIter {
+                        vars.push(iter.start.as_ref().unwrap());
                     } else if iter.start.is_some() {
                         vars.push(iter.start.as_ref().unwrap());
                         vars.push(iter.end.as_ref().unwrap());
@@ -174,6 +191,7 @@ impl StatementKind {
             Lookup {
                 ref child,
                 ref index,
+                ..
             } => {
                 vars.push(child);
                 vars.push(index);
@@ -279,6 +297,8 @@ impl StatementKind {
                         vars.push(iter.stride.as_mut().unwrap());
                         vars.push(iter.shape.as_mut().unwrap());
                         vars.push(iter.strides.as_mut().unwrap());
+                    } else if iter.kind == IterKind::ChunkIter {
+                        vars.push(iter.start.as_mut().unwrap());
                     } else if iter.start.is_some() {
                         vars.push(iter.start.as_mut().unwrap());
                         vars.push(iter.end.as_mut().unwrap());
@@ -313,6 +333,7 @@ impl StatementKind {
             Lookup {
                 ref mut child,
                 ref mut index,
+                ..
             } => {
                 vars.push(child);
                 vars.push(index);
@@ -517,6 +538,11 @@ pub enum Terminator {
         on_true: BasicBlockId,
         on_false: BasicBlockId,
     },
+    Switch {
+        value: Symbol,
+        cases: Vec<(LiteralKind, BasicBlockId)>,
+        default: BasicBlockId,
+    },
     JumpBlock(BasicBlockId),
     ProgramReturn(Symbol),
     EndFunction(Symbol),
@@ -532,6 +558,9 @@ impl Terminator {
             Branch { ref cond, .. } => {
                 vars.push(cond);
             }
+            Switch { ref value, .. } => {
+                vars.push(value);
+            }
             ProgramReturn(ref sym) => {
                 vars.push(sym);
             }
@@ -550,6 +579,9 @@ impl Terminator {
             Branch { ref mut cond, .. } => {
                 vars.push(cond);
             }
+            Switch { ref mut value, .. } => {
+                vars.push(value);
+            }
             ProgramReturn(ref mut sym) => {
                 vars.push(sym);
             }
@@ -697,6 +729,7 @@ impl fmt::Display for StatementKind {
                 ref op,
                 ref left,
                 ref right,
+                ..
             } => write!(f, "{} {} {}", op, left, right),
             Broadcast(ref child) => write!(f, "broadcast({})", child),
             Serialize(ref child) => write!(f, "serialize({})", child),
@@ -742,6 +775,7 @@ impl fmt::Display for StatementKind {
             Lookup {
                 ref child,
                 ref index,
+                ..
             } => write!(f, "lookup({}, {})", child, index),
             OptLookup {
                 ref child,
@@ -797,6 +831,17 @@ impl fmt::Display for Terminator {
                 ref on_true,
                 ref on_false,
             } => write!(f, "branch {} B{} B{}", cond, on_true, on_false),
+            Switch {
+                ref value,
+                ref cases,
+                ref default,
+            } => {
+                write!(f, "switch {}", value)?;
+                for (constant, block) in cases.iter() {
+                    write!(f, " {}:B{}", constant, block)?;
+                }
+                write!(f, " B{}", default)
+            }
             JumpBlock(block) => write!(f, "jump B{}", block),
             ProgramReturn(ref sym) => write!(f, "return {}", sym),
             EndFunction(ref sym) => write!(f, "end {}", sym),
@@ -813,20 +858,29 @@ impl fmt::Display for ParallelForIter {
             IterKind::FringeIter => "fringeiter",
             IterKind::NdIter => "nditer",
             IterKind::RangeIter => "rangeiter",
+            IterKind::ChunkIter => "chunkiter",
         };
 
         if self.shape.is_some() {
-            /* NdIter. Note: end or stride aren't important here, so skpping those.
-             * */
             write!(
                 f,
-                "{}({}, {}, {}, {})",
+                "{}({}, {}, {}, {}, {}, {})",
                 iterkind,
                 self.data,
                 self.start.clone().unwrap(),
+                self.end.clone().unwrap(),
+                self.stride.clone().unwrap(),
                 self.shape.clone().unwrap(),
                 self.strides.clone().unwrap()
             )
+        } else if self.kind == IterKind::ChunkIter {
+            write!(
+                f,
+                "{}({}, {})",
+                iterkind,
+                self.data,
+                self.start.clone().unwrap()
+            )
         } else if self.start.is_some() {
             write!(
                 f,
@@ -970,6 +1024,7 @@ fn assign_return_types_helper(prog: &mut SirProgram, func: FunctionId) -> WeldRe
         for block in function.blocks.iter() {
             match block.terminator {
                 Branch { .. } => (),
+                Switch { .. } => (),
                 JumpBlock(_) => (),
                 ProgramReturn(ref sym) | EndFunction(ref sym) => {
                     // Type should be set during AST -> SIR.
@@ -1074,6 +1129,113 @@ fn get_iter_sym(
     }
 }
 
+/// Helper method for gen_expr. Adds statements to `block` that merge `value` into `builder`.
+///
+/// If `builder` is a struct of builders, each field of `value` is merged into the corresponding
+/// builder and the returned symbol is a new struct holding the updated builders. Otherwise, the
+/// value is merged directly and `builder` is returned.
+fn gen_merge(
+    builder: Symbol,
+    builder_ty: &Type,
+    value: Symbol,
+    value_ty: &Type,
+    prog: &mut SirProgram,
+    func: FunctionId,
+    block: BasicBlockId,
+) -> WeldResult<Symbol> {
+    use self::StatementKind::*;
+    match (builder_ty, value_ty) {
+        (Struct(builder_tys, _), Struct(value_tys, _)) => {
+            let mut fields = vec![];
+            for (index, (builder_ty, value_ty)) in builder_tys.iter().zip(value_tys).enumerate() {
+                let field_builder = prog.add_local(builder_ty, func);
+                let field_value = prog.add_local(value_ty, func);
+                for (output, value) in [(&field_builder, &builder), (&field_value, &value)].iter() {
+                    prog.funcs[func].blocks[block].add_statement(Statement::new(
+                        Some((*output).clone()),
+                        GetField {
+                            value: (*value).clone(),
+                            index: index as u32,
+                        },
+                    ));
+                }
+                fields.push(gen_merge(
+                    field_builder,
+                    builder_ty,
+                    field_value,
+                    value_ty,
+                    prog,
+                    func,
+                    block,
+                )?);
+            }
+            let result = prog.add_local(builder_ty, func);
+            prog.funcs[func].blocks[block]
+                .add_statement(Statement::new(Some(result.clone()), MakeStruct(fields)));
+            Ok(result)
+        }
+        (Builder(..), _) => {
+            prog.funcs[func].blocks[block].add_statement(Statement::new(
+                None,
+                Merge {
+                    builder: builder.clone(),
+                    value,
+                },
+            ));
+            Ok(builder)
+        }
+        _ => compile_err!("Expected builder type in merge, got {}", builder_ty),
+    }
+}
+
+/// Helper method for gen_expr. Adds statements to `block` that compute the result of `builder`.
+///
+/// If `builder` is a struct of builders, the returned symbol is a struct of the results.
+fn gen_result(
+    builder: Symbol,
+    builder_ty: &Type,
+    result_ty: &Type,
+    prog: &mut SirProgram,
+    func: FunctionId,
+    block: BasicBlockId,
+) -> WeldResult<Symbol> {
+    use self::StatementKind::*;
+    match (builder_ty, result_ty) {
+        (Struct(builder_tys, _), Struct(result_tys, _)) => {
+            let mut fields = vec![];
+            for (index, (builder_ty, result_ty)) in builder_tys.iter().zip(result_tys).enumerate() {
+                let field_builder = prog.add_local(builder_ty, func);
+                prog.funcs[func].blocks[block].add_statement(Statement::new(
+                    Some(field_builder.clone()),
+                    GetField {
+                        value: builder.clone(),
+                        index: index as u32,
+                    },
+                ));
+                fields.push(gen_result(
+                    field_builder,
+                    builder_ty,
+                    result_ty,
+                    prog,
+                    func,
+                    block,
+                )?);
+            }
+            let result = prog.add_local(result_ty, func);
+            prog.funcs[func].blocks[block]
+                .add_statement(Statement::new(Some(result.clone()), MakeStruct(fields)));
+            Ok(result)
+        }
+        (Builder(..), _) => {
+            let result = prog.add_local(result_ty, func);
+            prog.funcs[func].blocks[block]
+                .add_statement(Statement::new(Some(result.clone()), Res(builder)));
+            Ok(result)
+        }
+        _ => compile_err!("Expected builder type in result, got {}", builder_ty),
+    }
+}
+
 /// Generate code to compute the expression `expr` starting at the current tail of `cur_block`,
 /// possibly creating new basic blocks and functions in the process. Return the function and
 /// basic block that the expression will be ready in, and its symbol therein.
@@ -1132,6 +1294,7 @@ fn gen_expr(
                 op: kind,
                 left: left_sym,
                 right: right_sym,
+                span: expr.span,
             };
             let res_sym = tracker.symbol_for_statement(prog, cur_func, cur_block, &expr.ty, kind);
             Ok((cur_func, cur_block, res_sym))
@@ -1216,6 +1379,7 @@ fn gen_expr(
             let kind = Lookup {
                 child: data_sym,
                 index: index_sym,
+                span: expr.span,
             };
             let res_sym = tracker.symbol_for_statement(prog, cur_func, cur_block, &expr.ty, kind);
             Ok((cur_func, cur_block, res_sym))
@@ -1375,6 +1539,54 @@ fn gen_expr(
             Ok((cur_func, cont_block, res_sym))
         }
 
+        ExprKind::Switch {
+            ref value,
+            ref cases,
+            ref default,
+        } => {
+            let (cur_func, cur_block, value_sym) =
+                gen_expr(value, prog, cur_func, cur_block, tracker)?;
+            let case_blocks: Vec<_> = cases
+                .iter()
+                .map(|_| prog.funcs[cur_func].add_block())
+                .collect();
+            let default_block = prog.funcs[cur_func].add_block();
+            prog.funcs[cur_func].blocks[cur_block].terminator = Switch {
+                value: value_sym,
+                cases: cases
+                    .iter()
+                    .map(|case| case.0.clone())
+                    .zip(case_blocks.iter().cloned())
+                    .collect(),
+                default: default_block,
+            };
+
+            // Generate each branch, and assign its result to a shared result symbol.
+            let branches = cases
+                .iter()
+                .map(|case| &case.1)
+                .chain(Some(default.as_ref()));
+            let blocks = case_blocks.into_iter().chain(Some(default_block));
+            let mut branch_ends = vec![];
+            let mut res_sym = None;
+            for (branch, block) in branches.zip(blocks) {
+                let (branch_func, branch_block, branch_sym) =
+                    gen_expr(branch, prog, cur_func, block, tracker)?;
+                if res_sym.is_none() {
+                    res_sym = Some(prog.add_local(&expr.ty, branch_func));
+                }
+                prog.funcs[branch_func].blocks[branch_block]
+                    .add_statement(Statement::new(res_sym.clone(), Assign(branch_sym)));
+                branch_ends.push((branch_func, branch_block));
+            }
+
+            let cont_block = prog.funcs[cur_func].add_block();
+            for (branch_func, branch_block) in branch_ends {
+                prog.funcs[branch_func].blocks[branch_block].terminator = JumpBlock(cont_block);
+            }
+            Ok((cur_func, cont_block, res_sym.unwrap()))
+        }
+
         ExprKind::Iterate {
             ref initial,
             ref update_func,
@@ -1396,7 +1608,12 @@ fn gen_expr(
                     if params[0].ty != initial.ty {
                         return compile_err!("Wrong argument type for body of Iterate");
                     }
-                    if func_body.ty != Struct(vec![initial.ty.clone(), Scalar(ScalarKind::Bool)]) {
+                    if func_body.ty
+                        != Struct(
+                            vec![initial.ty.clone(), Scalar(ScalarKind::Bool)],
+                            FieldNames::none(),
+                        )
+                    {
                         return compile_err!("Wrong return type for body of Iterate");
                     }
                     prog.add_local_named(&params[0].ty, argument_sym, cur_func);
@@ -1464,19 +1681,32 @@ fn gen_expr(
                 gen_expr(builder, prog, cur_func, cur_block, tracker)?;
             let (cur_func, cur_block, elem_sym) =
                 gen_expr(value, prog, cur_func, cur_block, tracker)?;
-            prog.funcs[cur_func].blocks[cur_block].add_statement(Statement::new(
-                None,
-                Merge {
-                    builder: builder_sym.clone(),
-                    value: elem_sym,
-                },
-            ));
+            let builder_sym = gen_merge(
+                builder_sym,
+                &builder.ty,
+                elem_sym,
+                &value.ty,
+                prog,
+                cur_func,
+                cur_block,
+            )?;
             Ok((cur_func, cur_block, builder_sym))
         }
 
         ExprKind::Res { ref builder } => {
             let (cur_func, cur_block, builder_sym) =
                 gen_expr(builder, prog, cur_func, cur_block, tracker)?;
+            if let Struct(..) = builder.ty {
+                let res_sym = gen_result(
+                    builder_sym,
+                    &builder.ty,
+                    &expr.ty,
+                    prog,
+                    cur_func,
+                    cur_block,
+                )?;
+                return Ok((cur_func, cur_block, res_sym));
+            }
             let kind = Res(builder_sym);
             let res_sym = tracker.symbol_for_statement(prog, cur_func, cur_block, &expr.ty, kind);
             Ok((cur_func, cur_block, res_sym))
@@ -1563,7 +1793,7 @@ fn gen_expr(
             let (cur_func, cur_block, struct_sym) =
                 gen_expr(expr, prog, cur_func, cur_block, tracker)?;
             let field_ty = match expr.ty {
-                super::ast::Type::Struct(ref v) => &v[index as usize],
+                super::ast::Type::Struct(ref v, _) => &v[index as usize],
                 _ => compile_err!("Internal error: tried to get field of type {}", &expr.ty)?,
             };
 
@@ -1684,6 +1914,14 @@ fn gen_expr(
                     data_arg: params[2].name.clone(),
                     body: body_func,
                     innermost: is_innermost,
+                    grain_size: expr
+                        .annotations
+                        .get("grain_size")
+                        .and_then(|v| v.parse().ok())
+                        .filter(|g| *g > 0),
+                    iteration_cost: body.operation_count(),
+                    id: expr.id,
+                    span: expr.span,
                 });
 
                 let res_sym =
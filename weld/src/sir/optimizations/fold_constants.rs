@@ -18,6 +18,16 @@ use crate::sir::*;
 
 use fnv;
 
+/// Returns the result of integer arithmetic, or an error if it overflowed or divided by zero.
+///
+/// Such operations are left to the generated code, which either wraps or raises an error.
+fn checked<T>(value: Option<T>) -> WeldResult<T> {
+    match value {
+        Some(value) => Ok(value),
+        None => compile_err!("Integer overflow in evaluate_binop"),
+    }
+}
+
 /// Evaluates a binary operation over two literals, returning a new literal.
 // TODO(shoumik): Maybe we should just implement this as a method or as traits (e.g., Add, Sub,
 // etc.) on LiteralKind? Not every literal implements every binop, however...
@@ -31,10 +41,10 @@ fn evaluate_binop(
     let result = match kind {
         // Just support the basics for now.
         Add => match (left, right) {
-            (I8Literal(l), I8Literal(r)) => I8Literal(l + r),
-            (I16Literal(l), I16Literal(r)) => I16Literal(l + r),
-            (I32Literal(l), I32Literal(r)) => I32Literal(l + r),
-            (I64Literal(l), I64Literal(r)) => I64Literal(l + r),
+            (I8Literal(l), I8Literal(r)) => I8Literal(checked(l.checked_add(r))?),
+            (I16Literal(l), I16Literal(r)) => I16Literal(checked(l.checked_add(r))?),
+            (I32Literal(l), I32Literal(r)) => I32Literal(checked(l.checked_add(r))?),
+            (I64Literal(l), I64Literal(r)) => I64Literal(checked(l.checked_add(r))?),
             (F32Literal(l), F32Literal(r)) => {
                 F32Literal((f32::from_bits(l) + f32::from_bits(r)).to_bits())
             }
@@ -46,10 +56,10 @@ fn evaluate_binop(
             }
         },
         Subtract => match (left, right) {
-            (I8Literal(l), I8Literal(r)) => I8Literal(l - r),
-            (I16Literal(l), I16Literal(r)) => I16Literal(l - r),
-            (I32Literal(l), I32Literal(r)) => I32Literal(l - r),
-            (I64Literal(l), I64Literal(r)) => I64Literal(l - r),
+            (I8Literal(l), I8Literal(r)) => I8Literal(checked(l.checked_sub(r))?),
+            (I16Literal(l), I16Literal(r)) => I16Literal(checked(l.checked_sub(r))?),
+            (I32Literal(l), I32Literal(r)) => I32Literal(checked(l.checked_sub(r))?),
+            (I64Literal(l), I64Literal(r)) => I64Literal(checked(l.checked_sub(r))?),
             (F32Literal(l), F32Literal(r)) => {
                 F32Literal((f32::from_bits(l) - f32::from_bits(r)).to_bits())
             }
@@ -61,10 +71,10 @@ fn evaluate_binop(
             }
         },
         Multiply => match (left, right) {
-            (I8Literal(l), I8Literal(r)) => I8Literal(l * r),
-            (I16Literal(l), I16Literal(r)) => I16Literal(l * r),
-            (I32Literal(l), I32Literal(r)) => I32Literal(l * r),
-            (I64Literal(l), I64Literal(r)) => I64Literal(l * r),
+            (I8Literal(l), I8Literal(r)) => I8Literal(checked(l.checked_mul(r))?),
+            (I16Literal(l), I16Literal(r)) => I16Literal(checked(l.checked_mul(r))?),
+            (I32Literal(l), I32Literal(r)) => I32Literal(checked(l.checked_mul(r))?),
+            (I64Literal(l), I64Literal(r)) => I64Literal(checked(l.checked_mul(r))?),
             (F32Literal(l), F32Literal(r)) => {
                 F32Literal((f32::from_bits(l) * f32::from_bits(r)).to_bits())
             }
@@ -76,10 +86,10 @@ fn evaluate_binop(
             }
         },
         Divide => match (left, right) {
-            (I8Literal(l), I8Literal(r)) => I8Literal(l / r),
-            (I16Literal(l), I16Literal(r)) => I16Literal(l / r),
-            (I32Literal(l), I32Literal(r)) => I32Literal(l / r),
-            (I64Literal(l), I64Literal(r)) => I64Literal(l / r),
+            (I8Literal(l), I8Literal(r)) => I8Literal(checked(l.checked_div(r))?),
+            (I16Literal(l), I16Literal(r)) => I16Literal(checked(l.checked_div(r))?),
+            (I32Literal(l), I32Literal(r)) => I32Literal(checked(l.checked_div(r))?),
+            (I64Literal(l), I64Literal(r)) => I64Literal(checked(l.checked_div(r))?),
             (F32Literal(l), F32Literal(r)) => {
                 F32Literal((f32::from_bits(l) / f32::from_bits(r)).to_bits())
             }
@@ -192,6 +202,7 @@ fn fold_constants_in_function(
                     ref op,
                     ref left,
                     ref right,
+                    ..
                 } if (&values).contains_key(left) && (&values).contains_key(right) => {
                     let left_val = values[left].clone();
                     let right_val = values[right].clone();
@@ -20,18 +20,23 @@ use crate::tests::print_expr_without_indent;
 
 const MAX_MACRO_DEPTH: i32 = 30;
 
-// thread_local instead of lazy_static because Rc on Symbol isn't Send.
-thread_local! {
+lazy_static! {
     /// Standard macros loaded once.
-    static STANDARD_MACROS: Vec<Macro> = {
+    static ref STANDARD_MACROS: Vec<Macro> = {
         let code = include_str!("../resources/standard_macros.weld");
         parse_macros(code).unwrap()
     };
 }
 
 /// Apply macros to a program, including the standard macros built into Weld.
+///
+/// A macro defined in the program replaces a standard macro with the same name.
 pub fn process_program(program: &Program) -> WeldResult<Expr> {
-    let mut all_macros = STANDARD_MACROS.with(|v| v.clone());
+    let mut all_macros: Vec<_> = STANDARD_MACROS
+        .iter()
+        .filter(|m| program.macros.iter().all(|p| p.name != m.name))
+        .cloned()
+        .collect();
     all_macros.extend(program.macros.iter().cloned());
     let mut expr = process_macros(&program.body, &all_macros)?;
     process_type_aliases(&mut expr, program.type_aliases.clone())?;
@@ -151,6 +156,9 @@ fn apply_macros(
                     return compile_err!("Wrong number of parameters for macro {}", mac.name);
                 }
                 update_defined_ids(&mut new_body, sym_gen);
+                // Expressions from the macro body are attributed to the call site.
+                let span = expr.span;
+                new_body.traverse_mut(&mut |e| e.span = span);
                 for (name, value) in mac.parameters.iter().zip(params) {
                     new_body.substitute(name, value);
                 }
@@ -182,6 +190,8 @@ fn update_defined_ids(expr: &mut Expr, sym_gen: &mut SymbolGenerator) {
                 kind: Ident(new_sym.clone()),
                 ty: value.ty.clone(),
                 annotations: Annotations::new(),
+                span: None,
+                id: None,
             };
             body.substitute(sym, &new_ident);
             *sym = Symbol::new(sym.name(), new_sym.id());
@@ -200,6 +210,8 @@ fn update_defined_ids(expr: &mut Expr, sym_gen: &mut SymbolGenerator) {
                     kind: Ident(new_sym.clone()),
                     ty: param.ty.clone(),
                     annotations: Annotations::new(),
+                    span: None,
+                    id: None,
                 };
                 body.substitute(sym, &new_ident);
                 *sym = Symbol::new(sym.name(), new_sym.id());
@@ -382,3 +394,58 @@ fn standard_macros() {
         "result(for([1,2,3],appender[?],|b,i,x|merge(b,(|a|(a+1))(x))))"
     );
 }
+
+#[test]
+fn program_macros_override_standard_macros() {
+    // A program can redefine a standard macro.
+    let program = parse_program("macro map(data, func) = func(data); map(1, |a| a + 1)").unwrap();
+    let result = process_program(&program).unwrap();
+    assert_eq!(print_expr_without_indent(&result).as_str(), "(|a|(a+1))(1)");
+
+    // Other standard macros are still available.
+    let program = parse_program("macro map(data, func) = func(data); sum([1,2,3])").unwrap();
+    assert!(process_program(&program).is_ok());
+
+    // Defining a macro twice in the same program is still an error.
+    let program = parse_program("macro foo(a) = a; macro foo(a) = a + a; foo(1)").unwrap();
+    assert!(process_program(&program).is_err());
+}
+
+#[test]
+fn sortedvec_macro() {
+    // The entries are sorted by key with the default comparator.
+    let program = parse_program(
+        "|x:vec[i32]| sortedvec(result(for(x, dictmerger[i32,i64,+], |b,i,e| merge(b, {e, 1L}))))",
+    )
+    .unwrap();
+    let mut result = process_program(&program).unwrap();
+    result.infer_types().unwrap();
+    if let Lambda { ref body, .. } = result.kind {
+        assert!(matches!(body.kind, Sort { .. }));
+        assert_eq!(
+            body.ty,
+            Type::Vector(Box::new(Type::Struct(
+                vec![Type::Scalar(ScalarKind::I32), Type::Scalar(ScalarKind::I64),],
+                FieldNames::none()
+            )))
+        );
+    } else {
+        panic!("expected a lambda");
+    }
+}
+
+#[test]
+fn nullable_macros() {
+    // NULL-skipping aggregations return nullable values, except for ncount.
+    let program = parse_program(
+        "|x:vec[{bool,i64}]| {nsum(x), ncount(x), coalesce(nadd(some(1L), lookup(x, 0L)), 0L)}",
+    )
+    .unwrap();
+    let mut result = process_program(&program).unwrap();
+    result.infer_types().unwrap();
+    if let Lambda { ref body, .. } = result.kind {
+        assert_eq!(body.ty.to_string(), "{{bool,i64},i64,i64}");
+    } else {
+        panic!("expected a lambda");
+    }
+}
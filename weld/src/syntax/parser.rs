@@ -3,7 +3,7 @@
 //! Weld is designed to be parseable in one left-to-right pass through the input, without
 //! backtracking, so we simply track a position as we go and keep incrementing it.
 
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::vec::Vec;
 
 use crate::ast::BinOpKind::*;
@@ -21,36 +21,34 @@ use super::program::*;
 use super::tokenizer::Token::*;
 use super::tokenizer::*;
 
-use std::error::Error;
-
 #[cfg(test)]
 use crate::tests::{print_expr_without_indent, print_typed_expr_without_indent};
 
-/// Returns a formatted parse error if the parse failed, or returns the `res`.
+/// Returns a formatted error listing every parse error (including ones the parser recovered
+/// from) with the span of the first one, or returns the `res` if there were none.
 macro_rules! check_parse_error {
     ($parser:expr, $res:expr) => {{
-        if $res.is_ok() && !$parser.is_done() {
-            return compile_err!(
-                "Unexpected token {} at {}",
-                $parser.peek(),
-                $parser.error_context()
-            );
-        } else if $res.is_err() {
-            return compile_err!(
-                "{} (at {})",
-                $res.unwrap_err().description(),
-                $parser.error_context()
-            );
-        } else {
+        match $res {
+            Ok(_) if !$parser.is_done() => {
+                let err = WeldCompileError::new(format!("Unexpected token {}", $parser.peek()));
+                $parser.report(&err);
+            }
+            Err(ref err) => $parser.report(err),
+            Ok(_) => (),
+        }
+        if $parser.diagnostics.is_empty() {
             $res
+        } else {
+            let message = $parser.diagnostics.join("\n");
+            Err(WeldCompileError::new(message).with_span($parser.error_span))
         }
     }};
 }
 
 /// Parse the complete input string as a Weld program (optional macros plus one expression).
 pub fn parse_program(input: &str) -> WeldResult<Program> {
-    let tokens = tokenize(input)?;
-    let mut parser = Parser::new(&tokens);
+    let (tokens, spans) = tokenize_with_spans(input)?;
+    let mut parser = Parser::new(&tokens, &spans);
     let res = parser.program();
 
     check_parse_error!(parser, res)
@@ -58,8 +56,8 @@ pub fn parse_program(input: &str) -> WeldResult<Program> {
 
 /// Parse the complete input string as a list of macros.
 pub fn parse_macros(input: &str) -> WeldResult<Vec<Macro>> {
-    let tokens = tokenize(input)?;
-    let mut parser = Parser::new(&tokens);
+    let (tokens, spans) = tokenize_with_spans(input)?;
+    let mut parser = Parser::new(&tokens, &spans);
     let res = parser.macros();
 
     check_parse_error!(parser, res)
@@ -67,8 +65,8 @@ pub fn parse_macros(input: &str) -> WeldResult<Vec<Macro>> {
 
 /// Parse the complete input string as a list of type aliases.
 pub fn parse_type_aliases(input: &str) -> WeldResult<Vec<TypeAlias>> {
-    let tokens = tokenize(input)?;
-    let mut parser = Parser::new(&tokens);
+    let (tokens, spans) = tokenize_with_spans(input)?;
+    let mut parser = Parser::new(&tokens, &spans);
     let res = parser.type_aliases();
 
     check_parse_error!(parser, res)
@@ -76,17 +74,20 @@ pub fn parse_type_aliases(input: &str) -> WeldResult<Vec<TypeAlias>> {
 
 /// Parse the complete input string as an expression.
 pub fn parse_expr(input: &str) -> WeldResult<Expr> {
-    let tokens = tokenize(input)?;
-    let mut parser = Parser::new(&tokens);
-    let res = parser.expr().map(|b| *b);
+    let (tokens, spans) = tokenize_with_spans(input)?;
+    let mut parser = Parser::new(&tokens, &spans);
+    let res = parser.expr().map(|mut b| {
+        b.assign_node_ids();
+        *b
+    });
 
     check_parse_error!(parser, res)
 }
 
 /// Parse the complete input string as a Type.
 pub fn parse_type(input: &str) -> WeldResult<Type> {
-    let tokens = tokenize(input)?;
-    let mut parser = Parser::new(&tokens);
+    let (tokens, spans) = tokenize_with_spans(input)?;
+    let mut parser = Parser::new(&tokens, &spans);
     let res = parser.type_();
 
     check_parse_error!(parser, res)
@@ -96,15 +97,77 @@ pub fn parse_type(input: &str) -> WeldResult<Type> {
 /// Assumes that the tokens end with a TEndOfInput.
 struct Parser<'t> {
     tokens: &'t [Token],
+    spans: &'t [Span],
     position: usize,
+    /// Formatted errors that the parser recovered from.
+    diagnostics: Vec<String>,
+    /// The span of the first error.
+    error_span: Option<Span>,
+    /// The position of the token that caused the pending error, if it was already consumed.
+    error_position: Option<usize>,
 }
 
 impl<'t> Parser<'t> {
-    fn new(tokens: &[Token]) -> Parser<'_> {
+    fn new(tokens: &'t [Token], spans: &'t [Span]) -> Parser<'t> {
         Parser {
             tokens,
+            spans,
             position: 0,
+            diagnostics: Vec::new(),
+            error_span: None,
+            error_position: None,
+        }
+    }
+
+    /// Records `err` with the line, column and context of the token that caused it.
+    fn report(&mut self, err: &WeldCompileError) {
+        let position = self.error_position.take().unwrap_or(self.position);
+        let position = min(position, self.spans.len() - 1);
+        let span = self.spans[position];
+        let message = format!("{} (at {}: {})", err, span, self.error_context(position));
+        self.diagnostics.push(message);
+        self.error_span = self.error_span.or(Some(span));
+    }
+
+    /// Marks `res`, an error about the token that was just consumed, as occurring at that token
+    /// rather than at the current position.
+    fn at_previous<T>(&mut self, res: WeldResult<T>) -> WeldResult<T> {
+        self.error_position = Some(self.position - 1);
+        res
+    }
+
+    /// Recovers from `err`, which occurred while parsing a statement that started at `start`, by
+    /// skipping past the semicolon that ends the statement.
+    ///
+    /// The error is recorded so parsing can continue and report later errors as well. If there
+    /// is no semicolon to recover at, `err` is returned instead.
+    fn recover(&mut self, err: WeldCompileError, start: usize) -> WeldResult<()> {
+        // Brackets opened before the error may never be closed, so only track the ones after it.
+        // The token that caused the error may already have been consumed.
+        let from = max(start, self.position.saturating_sub(1));
+        let mut depth = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(from) {
+            match *token {
+                TOpenParen | TOpenBracket | TOpenBrace => depth += 1,
+                TCloseParen | TCloseBracket | TCloseBrace if depth > 0 => depth -= 1,
+                TSemicolon if depth == 0 => {
+                    self.report(&err);
+                    self.position = i + 1;
+                    return Ok(());
+                }
+                _ => (),
+            }
         }
+        Err(err)
+    }
+
+    /// Sets the span of `expr`, which was parsed from the tokens starting at `start`, if it does
+    /// not have one already.
+    fn spanned(&self, start: usize, mut expr: Box<Expr>) -> Box<Expr> {
+        if expr.span.is_none() && self.position > start {
+            expr.span = Some(self.spans[start].to(&self.spans[self.position - 1]));
+        }
+        expr
     }
 
     /// Look at the next token to be parsed.
@@ -112,28 +175,27 @@ impl<'t> Parser<'t> {
         &self.tokens[self.position]
     }
 
-    /// Returns a string representing a context in the input.
-    fn error_context(&self) -> String {
+    /// Returns a string representing the context around `position` in the input.
+    fn error_context(&self, position: usize) -> String {
         let length = 10;
         let mut string = String::from("");
-        let context_length = if self.position >= length {
+        let context_length = if position >= length {
             string.push_str("...");
             length
         } else {
-            self.position
+            position
         };
 
-        for i in (self.position - context_length)
-            ..min(self.position + context_length, self.tokens.len() - 1)
+        for i in (position - context_length)..min(position + context_length, self.tokens.len() - 1)
         {
             let token_str = format!("{}", &self.tokens[i]);
-            if i == self.position {
+            if i == position {
                 string.push_str(format_color(Color::BoldRed, token_str.as_str()).as_str());
             } else {
                 string.push_str(&token_str);
             }
 
-            if i != self.position - 1
+            if i != position - 1
                 && self.tokens[i + 1].requires_space()
                 && self.tokens[i].requires_space()
             {
@@ -141,7 +203,7 @@ impl<'t> Parser<'t> {
             }
         }
 
-        if self.position != self.tokens.len() {
+        if position != self.tokens.len() {
             string.push_str("...");
         }
 
@@ -158,7 +220,7 @@ impl<'t> Parser<'t> {
     /// Consume the next token and check that it equals `expected`. If not, return an Err.
     fn consume(&mut self, expected: Token) -> WeldResult<()> {
         if *self.next() != expected {
-            compile_err!("Expected '{}'", expected)
+            self.at_previous(compile_err!("Expected '{}'", expected))
         } else {
             Ok(())
         }
@@ -173,7 +235,8 @@ impl<'t> Parser<'t> {
     fn program(&mut self) -> WeldResult<Program> {
         let type_aliases = self.type_aliases()?;
         let macros = self.macros()?;
-        let body = self.expr()?;
+        let mut body = self.expr()?;
+        body.assign_node_ids();
         Ok(Program {
             macros,
             type_aliases,
@@ -185,7 +248,11 @@ impl<'t> Parser<'t> {
     fn macros(&mut self) -> WeldResult<Vec<Macro>> {
         let mut res: Vec<Macro> = Vec::new();
         while *self.peek() == TMacro {
-            res.push(self.macro_()?);
+            let start = self.position;
+            match self.macro_() {
+                Ok(macro_) => res.push(macro_),
+                Err(err) => self.recover(err, start)?,
+            }
         }
         Ok(res)
     }
@@ -219,7 +286,11 @@ impl<'t> Parser<'t> {
     fn type_aliases(&mut self) -> WeldResult<Vec<TypeAlias>> {
         let mut res: Vec<TypeAlias> = Vec::new();
         while *self.peek() == TType {
-            res.push(self.type_alias_()?);
+            let start = self.position;
+            match self.type_alias_() {
+                Ok(alias) => res.push(alias),
+                Err(err) => self.recover(err, start)?,
+            }
         }
         Ok(res)
     }
@@ -238,34 +309,69 @@ impl<'t> Parser<'t> {
     }
 
     /// Parse an expression starting at the current position.
+    ///
+    /// A chain of `let` expressions is parsed in a loop rather than recursively, so that long
+    /// chains in generated programs do not overflow the stack.
     fn expr(&mut self) -> WeldResult<Box<Expr>> {
-        if *self.peek() == TLet {
-            self.let_expr()
-        } else if *self.peek() == TBar || *self.peek() == TLogicalOr {
-            self.lambda_expr()
+        let mut lets = vec![];
+        while *self.peek() == TLet {
+            let start = self.position;
+            let (name, value) = self.let_binding()?;
+            lets.push((start, name, value));
+        }
+
+        let start = self.position;
+        let mut expr = if *self.peek() == TBar || *self.peek() == TLogicalOr {
+            self.lambda_expr()?
         } else {
-            self.operator_expr()
+            self.operator_expr()?
+        };
+        expr = self.spanned(start, expr);
+
+        while let Some((start, name, value)) = lets.pop() {
+            let body = expr;
+            expr = self.spanned(
+                start,
+                expr_box(Let { name, value, body }, Annotations::new()),
+            );
         }
+        Ok(expr)
     }
 
-    /// Parse 'let name = value; body' starting at the current position.
-    fn let_expr(&mut self) -> WeldResult<Box<Expr>> {
+    /// Parse the 'let name = value;' of a let expression starting at the current position.
+    fn let_binding(&mut self) -> WeldResult<(Symbol, Box<Expr>)> {
         self.consume(TLet)?;
         let name = self.symbol()?;
         let ty = self.optional_type()?;
         self.consume(TEqual)?;
-        let mut value = self.operator_expr()?;
+        let start = self.position;
+        let mut value = match self.let_value() {
+            Ok(value) => value,
+            Err(err) => {
+                // Keep parsing the body so that errors in it are reported too.
+                self.recover(err, start)?;
+                Box::new(Expr::new_placeholder())
+            }
+        };
 
         // If a type was found, assign it (even if the value already has a known type).
         // Type inference will catch any type mismatches later on.
         if ty != Unknown {
             value.ty = ty;
         }
+        Ok((name, value))
+    }
 
+    /// Parse the 'value;' of a let expression starting at the current position.
+    fn let_value(&mut self) -> WeldResult<Box<Expr>> {
+        // Let-bound lambdas act as named functions, which are inlined at their call sites.
+        let value = if *self.peek() == TBar || *self.peek() == TLogicalOr {
+            self.lambda_expr()?
+        } else {
+            self.operator_expr()?
+        };
         self.consume(TSemicolon)?;
-        let body = self.expr()?;
-        let expr = expr_box(Let { name, value, body }, Annotations::new());
-        Ok(expr)
+        Ok(value)
     }
 
     /// Parse '|params| body' starting at the current position.
@@ -286,7 +392,7 @@ impl<'t> Parser<'t> {
             }
             self.consume(TBar)?;
         } else if *token != TLogicalOr {
-            return compile_err!("Expected '|' or '||'");
+            return self.at_previous(compile_err!("Expected '|' or '||'"));
         }
         let body = self.expr()?;
         Ok(expr_box(Lambda { params, body }, Annotations::new()))
@@ -299,6 +405,7 @@ impl<'t> Parser<'t> {
 
     /// Parse a logical or expression with terms separated by || (for operator precedence).
     fn logical_or_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut res = self.logical_and_expr()?;
         while *self.peek() == TLogicalOr {
             self.consume(TLogicalOr)?;
@@ -310,13 +417,15 @@ impl<'t> Parser<'t> {
                     right,
                 },
                 Annotations::new(),
-            )
+            );
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
 
     /// Parse a logical and expression with terms separated by && (for operator precedence).
     fn logical_and_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut res = self.bitwise_or_expr()?;
         while *self.peek() == TLogicalAnd {
             self.consume(TLogicalAnd)?;
@@ -328,13 +437,15 @@ impl<'t> Parser<'t> {
                     right,
                 },
                 Annotations::new(),
-            )
+            );
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
 
     /// Parse a bitwise or expression with terms separated by | (for operator precedence).
     fn bitwise_or_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut res = self.xor_expr()?;
         while *self.peek() == TBar {
             self.consume(TBar)?;
@@ -346,13 +457,15 @@ impl<'t> Parser<'t> {
                     right,
                 },
                 Annotations::new(),
-            )
+            );
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
 
     /// Parse a bitwise or expression with terms separated by ^ (for operator precedence).
     fn xor_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut res = self.bitwise_and_expr()?;
         while *self.peek() == TXor {
             self.consume(TXor)?;
@@ -364,13 +477,15 @@ impl<'t> Parser<'t> {
                     right,
                 },
                 Annotations::new(),
-            )
+            );
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
 
     /// Parse a bitwise and expression with terms separated by & (for operator precedence).
     fn bitwise_and_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut res = self.equality_expr()?;
         while *self.peek() == TBitwiseAnd {
             self.consume(TBitwiseAnd)?;
@@ -382,13 +497,15 @@ impl<'t> Parser<'t> {
                     right,
                 },
                 Annotations::new(),
-            )
+            );
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
 
     /// Parse an == or != expression (for operator precedence).
     fn equality_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut res = self.comparison_expr()?;
         // Unlike other expressions, we only allow one operator here; prevents stuff like a==b==c
         if *self.peek() == TEqualEqual || *self.peek() == TNotEqual {
@@ -413,13 +530,15 @@ impl<'t> Parser<'t> {
                     Annotations::new(),
                 )
             }
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
 
     /// Parse a <, >, <= or >= expression (for operator precedence).
     fn comparison_expr(&mut self) -> WeldResult<Box<Expr>> {
-        let mut res = self.sum_expr()?;
+        let start = self.position;
+        let mut res = self.shift_expr()?;
         // Unlike other expressions, we only allow one operator here; prevents stuff like a>b>c
         if *self.peek() == TLessThan
             || *self.peek() == TLessThanOrEqual
@@ -432,7 +551,7 @@ impl<'t> Parser<'t> {
                 TLessThanOrEqual => LessThanOrEqual,
                 _ => GreaterThanOrEqual,
             };
-            let right = self.sum_expr()?;
+            let right = self.shift_expr()?;
             res = expr_box(
                 BinOp {
                     kind: op,
@@ -440,13 +559,39 @@ impl<'t> Parser<'t> {
                     right,
                 },
                 Annotations::new(),
-            )
+            );
+            res = self.spanned(start, res);
+        }
+        Ok(res)
+    }
+
+    /// Parse a shift expression with terms separated by << and >> (for operator precedence).
+    fn shift_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
+        let mut res = self.sum_expr()?;
+        while *self.peek() == TShiftLeft || *self.peek() == TShiftRight {
+            let kind = if *self.next() == TShiftLeft {
+                ShiftLeft
+            } else {
+                ShiftRight
+            };
+            let right = self.sum_expr()?;
+            res = expr_box(
+                BinOp {
+                    kind,
+                    left: res,
+                    right,
+                },
+                Annotations::new(),
+            );
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
 
     /// Parse a sum expression with terms separated by + and - (for operator precedence).
     fn sum_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut res = self.product_expr()?;
         while *self.peek() == TPlus || *self.peek() == TMinus {
             let token = self.next();
@@ -470,12 +615,14 @@ impl<'t> Parser<'t> {
                     Annotations::new(),
                 )
             }
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
 
     /// Parse a product expression with terms separated by *, / and % (for precedence).
     fn product_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut res = self.ascribe_expr()?;
         while *self.peek() == TTimes || *self.peek() == TDivide || *self.peek() == TModulo {
             let op = match *self.next() {
@@ -491,7 +638,8 @@ impl<'t> Parser<'t> {
                     right,
                 },
                 Annotations::new(),
-            )
+            );
+            res = self.spanned(start, res);
         }
         Ok(res)
     }
@@ -507,6 +655,7 @@ impl<'t> Parser<'t> {
 
     /// Parse application chain expression such as a.0().3().
     fn apply_expr(&mut self) -> WeldResult<Box<Expr>> {
+        let start = self.position;
         let mut expr = self.leaf_expr()?;
         while *self.peek() == TDot || *self.peek() == TOpenParen {
             if *self.next() == TDot {
@@ -518,16 +667,24 @@ impl<'t> Parser<'t> {
                                     expr = expr_box(GetField { expr, index }, Annotations::new())
                                 }
                                 _ => {
-                                    return compile_err!(
+                                    return self.at_previous(compile_err!(
                                         "Expected field index but got '{}'",
                                         value
-                                    );
+                                    ));
                                 }
                             }
+                        } else {
+                            let name = value.clone();
+                            expr = expr_box(GetNamedField { expr, name }, Annotations::new())
                         }
                     }
 
-                    ref other => return compile_err!("Expected field index but got '{}'", other),
+                    ref other => {
+                        return self.at_previous(compile_err!(
+                            "Expected field index or name but got '{}'",
+                            other
+                        ));
+                    }
                 }
             } else {
                 // TOpenParen
@@ -544,6 +701,7 @@ impl<'t> Parser<'t> {
                 self.consume(TCloseParen)?;
                 expr = expr_box(Apply { func: expr, params }, Annotations::new())
             }
+            expr = self.spanned(start, expr);
         }
         Ok(expr)
     }
@@ -569,7 +727,8 @@ impl<'t> Parser<'t> {
                     stride = Some(self.expr()?);
                 }
 
-                if iter == TNdIter && *self.peek() == TComma {
+                // An nditer always takes a shape and strides.
+                if iter == TNdIter {
                     self.consume(TComma)?;
                     shape = Some(self.expr()?);
                     self.consume(TComma)?;
@@ -615,6 +774,29 @@ impl<'t> Parser<'t> {
                 self.consume(TCloseParen)?;
                 Ok(iter)
             }
+            TChunkIter => {
+                self.consume(iter)?;
+                self.consume(TOpenBracket)?;
+                let elem_ty = self.type_()?;
+                self.consume(TCloseBracket)?;
+                self.consume(TOpenParen)?;
+                let source = self.expr()?;
+                // As for a RangeIter, the data is a dummy vector that only provides the element
+                // type. The source handle is stored as the start.
+                let mut dummy_data = expr_box(MakeVector { elems: vec![] }, Annotations::new());
+                dummy_data.as_mut().ty = Vector(Box::new(elem_ty));
+                let iter = Iter {
+                    data: dummy_data,
+                    start: Some(source),
+                    end: None,
+                    stride: None,
+                    kind: ChunkIter,
+                    shape: None,
+                    strides: None,
+                };
+                self.consume(TCloseParen)?;
+                Ok(iter)
+            }
             _ => {
                 let data = self.expr()?;
                 let iter = Iter {
@@ -640,11 +822,11 @@ impl<'t> Parser<'t> {
     /// Parses a cast operation, the type being cast to is passed in as an argument.
     fn parse_cast(&mut self, kind: ScalarKind) -> WeldResult<Box<Expr>> {
         if *self.next() != TOpenParen {
-            return compile_err!("Expected '('");
+            return self.at_previous(compile_err!("Expected '('"));
         }
         let child_expr = self.expr()?;
         if *self.next() != TCloseParen {
-            return compile_err!("Expected ')'");
+            return self.at_previous(compile_err!("Expected ')'"));
         }
         let cast_expr = expr_box(Cast { kind, child_expr }, Annotations::new());
         Ok(cast_expr)
@@ -659,12 +841,16 @@ impl<'t> Parser<'t> {
                 let key = self.symbol()?;
                 self.consume(TColon)?;
                 let value = match *self.peek() {
-                    TI32Literal(ref v) => v.to_string(),
+                    TI32Literal(ref v) | TTypedI32Literal(ref v) => v.to_string(),
                     TI64Literal(ref v) => v.to_string(),
                     TF32Literal(ref v) => v.to_string(),
                     TF64Literal(ref v) => v.to_string(),
                     TI16Literal(ref v) => v.to_string(),
                     TI8Literal(ref v) => v.to_string(),
+                    TU8Literal(ref v) => v.to_string(),
+                    TU16Literal(ref v) => v.to_string(),
+                    TU32Literal(ref v) => v.to_string(),
+                    TU64Literal(ref v) => v.to_string(),
                     TBoolLiteral(ref v) => v.to_string(),
                     TStringLiteral(ref v) => v.clone(),
                     TIdent(ref v) => v.clone(),
@@ -699,6 +885,7 @@ impl<'t> Parser<'t> {
             TLog => Log,
             TSqrt => Sqrt,
             TErf => Erf,
+            TAbs => Abs,
             TSin => Sin,
             TCos => Cos,
             TTan => Tan,
@@ -729,11 +916,35 @@ impl<'t> Parser<'t> {
         let mut annotations = Annotations::new();
         self.parse_annotations(&mut annotations)?;
 
+        let start = self.position;
+        let mut expr = self.annotated_leaf_expr(annotations.clone())?;
+        expr = self.spanned(start, expr);
+
+        // Builders hold their annotations in their type. All other expressions that did not
+        // consume the annotations carry them on the expression, so optimization passes can read them.
+        if expr.annotations.is_empty() && !expr.ty.is_builder() {
+            expr.annotations = annotations;
+        }
+        Ok(expr)
+    }
+
+    /// Parse a terminal expression, given the annotations that preceded it.
+    fn annotated_leaf_expr(&mut self, annotations: Annotations) -> WeldResult<Box<Expr>> {
         match *self.next() {
             TI16Literal(v) => Ok(expr_box(Literal(I16Literal(v)), Annotations::new())),
             TI8Literal(v) => Ok(expr_box(Literal(I8Literal(v)), Annotations::new())),
             TI32Literal(v) => Ok(expr_box(Literal(I32Literal(v)), Annotations::new())),
+            // Only literals without a suffix take their type from context.
+            TTypedI32Literal(v) => {
+                let mut literal = expr_box(Literal(I32Literal(v)), Annotations::new());
+                literal.ty = Scalar(ScalarKind::I32);
+                Ok(literal)
+            }
             TI64Literal(v) => Ok(expr_box(Literal(I64Literal(v)), Annotations::new())),
+            TU8Literal(v) => Ok(expr_box(Literal(U8Literal(v)), Annotations::new())),
+            TU16Literal(v) => Ok(expr_box(Literal(U16Literal(v)), Annotations::new())),
+            TU32Literal(v) => Ok(expr_box(Literal(U32Literal(v)), Annotations::new())),
+            TU64Literal(v) => Ok(expr_box(Literal(U64Literal(v)), Annotations::new())),
             TF32Literal(v) => Ok(expr_box(
                 Literal(F32Literal(v.to_bits())),
                 Annotations::new(),
@@ -775,7 +986,7 @@ impl<'t> Parser<'t> {
             TOpenParen => {
                 let expr = self.expr()?;
                 if *self.next() != TCloseParen {
-                    return compile_err!("Expected ')' after {:?}", expr);
+                    return self.at_previous(compile_err!("Expected ')' after {:?}", expr));
                 }
                 Ok(expr)
             }
@@ -861,6 +1072,49 @@ impl<'t> Parser<'t> {
                 ))
             }
 
+            TSwitch => {
+                self.consume(TOpenParen)?;
+                let value = self.expr()?;
+                self.consume(TComma)?;
+
+                // Cases have the form `constant: branch`. The final argument is the default branch.
+                let mut cases = vec![];
+                loop {
+                    let constant = match *self.peek() {
+                        TI8Literal(v) => I8Literal(v),
+                        TI16Literal(v) => I16Literal(v),
+                        // Case constants are not expressions, so they always take the type
+                        // of the switch value.
+                        TI32Literal(v) | TTypedI32Literal(v) => I32Literal(v),
+                        TI64Literal(v) => I64Literal(v),
+                        TU8Literal(v) => U8Literal(v),
+                        TU16Literal(v) => U16Literal(v),
+                        TU32Literal(v) => U32Literal(v),
+                        TU64Literal(v) => U64Literal(v),
+                        _ => break,
+                    };
+                    if self.tokens[self.position + 1] != TColon {
+                        break;
+                    }
+                    self.next();
+                    self.consume(TColon)?;
+                    let branch = self.expr()?;
+                    self.consume(TComma)?;
+                    cases.push((constant, *branch));
+                }
+
+                let default = self.expr()?;
+                self.consume(TCloseParen)?;
+                Ok(expr_box(
+                    Switch {
+                        value,
+                        cases,
+                        default,
+                    },
+                    Annotations::new(),
+                ))
+            }
+
             TBroadcast => {
                 self.consume(TOpenParen)?;
                 let expr = self.expr()?;
@@ -1025,6 +1279,7 @@ impl<'t> Parser<'t> {
             TExp => self.unary_leaf_expr(TExp),
             TLog => self.unary_leaf_expr(TLog),
             TErf => self.unary_leaf_expr(TErf),
+            TAbs => self.unary_leaf_expr(TAbs),
             TSqrt => self.unary_leaf_expr(TSqrt),
             TSin => self.unary_leaf_expr(TSin),
             TCos => self.unary_leaf_expr(TCos),
@@ -1220,7 +1475,7 @@ impl<'t> Parser<'t> {
                 Ok(res)
             }
 
-            ref other => compile_err!("Expected expression but got '{}'", other),
+            ref other => self.at_previous(compile_err!("Expected expression but got '{}'", other)),
         }
     }
 
@@ -1228,7 +1483,10 @@ impl<'t> Parser<'t> {
     fn symbol(&mut self) -> WeldResult<Symbol> {
         match *self.next() {
             TIdent(ref name) => Ok(Symbol::new(name.as_str(), 0)),
-            ref other => compile_err!("Expected identifier but got {}", other.to_string()),
+            ref other => self.at_previous(compile_err!(
+                "Expected identifier but got {}",
+                other.to_string()
+            )),
         }
     }
 
@@ -1377,10 +1635,32 @@ impl<'t> Parser<'t> {
             }
 
             TOpenBrace => {
+                // Fields are either all positional, as in {i32,i64}, or all named, as in
+                // {a:i32,b:i64}.
                 let mut types: Vec<Type> = Vec::new();
+                let mut names: Vec<String> = Vec::new();
                 while *self.peek() != TCloseBrace {
+                    if let TIdent(ref name) = *self.peek() {
+                        if self.tokens[self.position + 1] == TColon {
+                            if names.len() != types.len() {
+                                return compile_err!("Expected all struct fields to be named");
+                            }
+                            if name.starts_with('$') {
+                                return compile_err!("Invalid struct field name '{}'", name);
+                            }
+                            if names.contains(name) {
+                                return compile_err!("Duplicate struct field name '{}'", name);
+                            }
+                            names.push(name.clone());
+                            self.next();
+                            self.consume(TColon)?;
+                        }
+                    }
                     let ty = self.type_()?;
                     types.push(ty);
+                    if !names.is_empty() && names.len() != types.len() {
+                        return compile_err!("Expected all struct fields to be named");
+                    }
                     if *self.peek() == TComma {
                         self.next();
                     } else if *self.peek() != TCloseBrace {
@@ -1388,12 +1668,16 @@ impl<'t> Parser<'t> {
                     }
                 }
                 self.consume(TCloseBrace)?;
-                Ok(Struct(types))
+                if names.is_empty() {
+                    Ok(Struct(types, FieldNames::none()))
+                } else {
+                    Ok(Struct(types, FieldNames::new(names)))
+                }
             }
 
             TQuestion => Ok(Unknown),
 
-            ref other => compile_err!("Expected type but got '{}'", other),
+            ref other => self.at_previous(compile_err!("Expected type but got '{}'", other)),
         }
     }
 }
@@ -1475,6 +1759,13 @@ fn basic_parsing() {
         "@(impl:local,num_keys:12)dictmerger[i32,i32,+]"
     );
 
+    let e = parse_expr("@(predicate:true) merge(b, 1)").unwrap();
+    assert_eq!(print_expr_without_indent(&e), "@(predicate:true)merge(b,1)");
+    assert_eq!(e.annotations.get("predicate"), Some("true"));
+
+    let e = parse_expr("@(size:1e6) x").unwrap();
+    assert_eq!(e.annotations.get("size"), Some("1000000"));
+
     let e = parse_expr("a: i32 + b").unwrap();
     assert_eq!(print_typed_expr_without_indent(&e), "(a:i32+b:?)");
 
@@ -1547,6 +1838,11 @@ fn parse_and_print_literal_expressions() {
         ("23L", "23L"),
         ("7L", "7L"),
         ("0xffL", "255L"),
+        // unsigned literal expressions
+        ("23uc", "23UC"),
+        ("0xffusi", "255usi"),
+        ("0b111U", "7U"),
+        ("23UL", "23UL"),
         // f64 literal expressions
         ("23.0", "23.0"),
         ("23.5", "23.5"),
@@ -1560,6 +1856,9 @@ fn parse_and_print_literal_expressions() {
         // bool literal expressions
         ("true", "true"),
         ("false", "false"),
+        // string literal expressions
+        ("\"abc\"", "\"abc\""),
+        (r#""a\"b\\c\n""#, r#""a\"b\\c\n""#),
     ];
 
     for test in tests {
@@ -1571,6 +1870,8 @@ fn parse_and_print_literal_expressions() {
     assert!(parse_expr("999999999999999").is_err()); // i32 literal too big
     assert!(parse_expr("999999999999999L").is_ok());
     assert!(parse_expr("999999999999999999999999999999L").is_err()); // i64 literal too big
+    assert!(parse_expr("256uc").is_err()); // u8 literal too big
+    assert!(parse_expr("4294967296U").is_err()); // u32 literal too big
 }
 
 #[test]
@@ -1602,6 +1903,12 @@ fn parse_and_print_simple_expressions() {
     let e = parse_expr("|a, b| a + b").unwrap();
     assert_eq!(print_expr_without_indent(&e).as_str(), "|a:?,b:?|(a+b)");
 
+    let e = parse_expr("let f = |a| a + 1; f(2)").unwrap();
+    assert_eq!(
+        print_expr_without_indent(&e).as_str(),
+        "(let f=(|a|(a+1));(f)(2))"
+    );
+
     let e = parse_expr("for(d, appender, |e| e+1)").unwrap();
     assert_eq!(
         print_expr_without_indent(&e).as_str(),
@@ -1646,4 +1953,141 @@ fn parse_and_print_for_expressions() {
         print_expr_without_indent(&e).as_str(),
         "for(zip(a,b,iter(c,0L,4L,1L),d),appender[?],|e|(e+1))"
     );
+
+    let e = parse_expr("for(nditer(d,0L,6L,1L,s,t), appender, |e| e+1)").unwrap();
+    assert_eq!(
+        print_expr_without_indent(&e).as_str(),
+        "for(nditer(d,0L,6L,1L,s,t),appender[?],|e|(e+1))"
+    );
+
+    // An nditer requires a shape and strides.
+    assert!(parse_expr("for(nditer(d), appender, |e| e+1)").is_err());
+    assert!(parse_expr("for(nditer(d,0L,6L,1L), appender, |e| e+1)").is_err());
+
+    let e = parse_expr("for(chunkiter[{i32,f64}](s), appender, |e| e.$0)").unwrap();
+    assert_eq!(
+        print_expr_without_indent(&e).as_str(),
+        "for(chunkiter[{i32,f64}](s),appender[?],|e|e.$0)"
+    );
+    assert!(parse_expr("for(chunkiter(s), appender, |e| e)").is_err());
+}
+
+#[test]
+fn parse_and_print_switch_expressions() {
+    let e = parse_expr("switch(x, 1: a, 2: b + 1, c)").unwrap();
+    assert_eq!(
+        print_expr_without_indent(&e).as_str(),
+        "switch(x,1:a,2:(b+1),c)"
+    );
+
+    // A switch with only a default branch.
+    let e = parse_expr("switch(x, 1)").unwrap();
+    assert_eq!(print_expr_without_indent(&e).as_str(), "switch(x,1)");
+
+    let e = parse_expr("switch(x, 1L: a, 2L: b, 3L)").unwrap();
+    assert_eq!(
+        print_expr_without_indent(&e).as_str(),
+        "switch(x,1L:a,2L:b,3L)"
+    );
+
+    // The default branch is required.
+    assert!(parse_expr("switch(x, 1: a)").is_err());
+}
+
+#[test]
+fn parse_and_print_named_fields() {
+    let e = parse_expr("|x:{a:i32,b:vec[{c:i64}]}| {x.a, x.$1}").unwrap();
+    assert_eq!(
+        print_expr_without_indent(&e).as_str(),
+        "|x:{a:i32,b:vec[{c:i64}]}|{x.a,x.$1}"
+    );
+
+    let e = parse_expr("lookup(x, 0L).b.c").unwrap();
+    assert_eq!(print_expr_without_indent(&e).as_str(), "lookup(x,0L).b.c");
+
+    // Fields are either all named or all positional.
+    assert!(parse_expr("|x:{a:i32,i64}| x").is_err());
+    assert!(parse_expr("|x:{i32,b:i64}| x").is_err());
+    assert!(parse_expr("|x:{a:i32,a:i64}| x").is_err());
+    assert!(parse_expr("|x:{$0:i32}| x").is_err());
+}
+
+#[test]
+fn parse_spans() {
+    let input = "let x = a + b;\nx * 2";
+    let e = parse_expr(input).unwrap();
+    let span = e.span.unwrap();
+    assert_eq!((span.start, span.end), (0, input.len()));
+
+    if let Let {
+        ref value,
+        ref body,
+        ..
+    } = e.kind
+    {
+        let span = value.span.unwrap();
+        assert_eq!(&input[span.start..span.end], "a + b");
+        let span = body.span.unwrap();
+        assert_eq!(&input[span.start..span.end], "x * 2");
+        assert_eq!((span.line, span.column), (2, 1));
+    } else {
+        unreachable!()
+    }
+
+    // Spans are ignored when comparing expressions.
+    assert_eq!(e, parse_expr("let x = a+b; x*2").unwrap());
+}
+
+#[test]
+fn parse_node_ids() {
+    use crate::ast::Uniquify;
+    let input = "|a:i32, b:i32| let x = a + b; x * 2";
+    let mut e = parse_expr(input).unwrap();
+    let mut ids = vec![];
+    e.traverse(&mut |e| ids.push(e.id.unwrap().0));
+    assert_eq!(ids, (0..ids.len() as u32).collect::<Vec<_>>());
+
+    // Parsing the same text gives the same IDs.
+    let mut same = vec![];
+    parse_expr(input)
+        .unwrap()
+        .traverse(&mut |e| same.push(e.id.unwrap().0));
+    assert_eq!(ids, same);
+
+    // Expressions added later get new IDs, and existing ones keep theirs.
+    e.traverse_mut(&mut |e| {
+        if let Ident(ref sym) = e.kind {
+            if sym.name() == "a" {
+                e.id = None;
+            }
+        }
+    });
+    e.uniquify().unwrap();
+    let mut renumbered = vec![];
+    e.traverse(&mut |e| renumbered.push(e.id.unwrap().0));
+    ids[3] = ids.len() as u32;
+    assert_eq!(renumbered, ids);
+}
+
+#[test]
+fn parse_error_recovery() {
+    let input = "type t = vec[;
+macro m(a) = a +;
+|x: i32|
+  let a = x + * 2;
+  let b = );
+  a +";
+    let err = parse_program(input).unwrap_err().to_string();
+    let lines: Vec<_> = err.lines().collect();
+    assert_eq!(lines.len(), 5);
+    // Errors are reported at the token that could not be parsed.
+    assert!(lines[0].starts_with("Expected identifier but got ; (at line 1, column 14"));
+    assert!(lines[1].starts_with("Expected expression but got ';' (at line 2, column 17"));
+    assert!(lines[2].starts_with("Expected expression but got '*' (at line 4, column 15"));
+    assert!(lines[3].starts_with("Expected expression but got ')' (at line 5, column 11"));
+    assert!(lines[4].starts_with("Expected expression but got '<END>' (at line 6, column 6"));
+
+    // Without recoverable errors, only the first error is reported.
+    let err = parse_expr("a + * b").unwrap_err().to_string();
+    assert_eq!(err.lines().count(), 1);
 }
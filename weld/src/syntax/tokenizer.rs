@@ -12,16 +12,24 @@ use std::vec::Vec;
 
 use regex::Regex;
 
+use crate::ast::Span;
 use crate::error::*;
+use crate::util::escape_string;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     TI32Literal(i32),
+    /// An i32 literal written with an explicit `i32` suffix, whose type is not inferred.
+    TTypedI32Literal(i32),
     TI64Literal(i64),
     TF32Literal(f32),
     TF64Literal(f64),
     TI16Literal(i16),
     TI8Literal(i8),
+    TU8Literal(u8),
+    TU16Literal(u16),
+    TU32Literal(u32),
+    TU64Literal(u64),
     TBoolLiteral(bool),
     TStringLiteral(String),
     TIdent(String),
@@ -52,6 +60,7 @@ pub enum Token {
     TFringeIter,
     TNdIter,
     TRangeIter,
+    TChunkIter,
     TLen,
     TLookup,
     TOptLookup,
@@ -70,12 +79,14 @@ pub enum Token {
     TTanh,
     TSimd,
     TSelect,
+    TSwitch,
     TAssert,
     TBroadcast,
     TSerialize,
     TDeserialize,
     TLog,
     TErf,
+    TAbs,
     TSqrt,
     TCUDF,
     TAppender,
@@ -114,6 +125,8 @@ pub enum Token {
     TLogicalOr,
     TBitwiseAnd,
     TXor,
+    TShiftLeft,
+    TShiftRight,
     TMax,
     TMin,
     TPow,
@@ -130,7 +143,8 @@ impl Token {
             | TComma | TPlus | TMinus | TTimes | TDivide | TModulo | TEqual | TBar | TAtMark
             | TDot | TColon | TSemicolon | TQuestion | TBang | TEqualEqual | TNotEqual
             | TLessThanOrEqual | TGreaterThanOrEqual | TLessThan | TGreaterThan | TLogicalAnd
-            | TLogicalOr | TBitwiseAnd | TXor | TMax | TMin | TPow | TEndOfInput => false,
+            | TLogicalOr | TBitwiseAnd | TXor | TShiftLeft | TShiftRight | TMax | TMin | TPow
+            | TEndOfInput => false,
             _ => true,
         }
     }
@@ -150,35 +164,43 @@ impl Token {
 }
 
 /// Break up a string into tokens.
-#[allow(clippy::invalid_regex)]
 pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
+    tokenize_with_spans(input).map(|(tokens, _)| tokens)
+}
+
+/// Break up a string into tokens, also returning the span of the input each token came from.
+///
+/// The returned vectors have the same length.
+#[allow(clippy::invalid_regex)]
+pub fn tokenize_with_spans(input: &str) -> WeldResult<(Vec<Token>, Vec<Span>)> {
     lazy_static! {
         // Regular expression for splitting up tokens.
         static ref TOKEN_RE: Regex = Regex::new(concat!(
-            "(?m)#.*$|",
-            r#"[0-9]+\.[0-9]+([eE]-?[0-9]+)?[fF]?|[0-9]+[eE]-?[0-9]+[fF]?|"[^"]*"|"#,
-            r#"[A-Za-z0-9$_]+|==|!=|>=|<=|&&|\|\||[-+/*%,=()\[\]{}@&\.:;?!&\|^<>]|\S+"#
+            r"(?m)#.*$|/\*(?s:.*?)\*/|/\*|",
+            r#"[0-9][0-9_]*\.[0-9_]+([eE]-?[0-9_]+)?([fF](32|64)?)?|"#,
+            r#"[0-9][0-9_]*[eE]-?[0-9_]+([fF](32|64)?)?|"(?:[^"\\]|\\.)*"|"#,
+            r#"[A-Za-z0-9$_]+|==|!=|<<|>>|>=|<=|&&|\|\||[-+/*%,=()\[\]{}@&\.:;?!&\|^<>]|\S+"#
         )).unwrap();
 
         // Regular expressions for various types of tokens.
         static ref KEYWORD_RE: Regex = Regex::new(
             "^(if|for|zip|len|lookup|optlookup|keyexists|slice|sort|exp|sin|cos|tan|asin|acos|atan|sinh|cosh|tanh|\
-             log|erf|sqrt|simd|select|assert|broadcast|serialize|deserialize|\
-             iterate|cudf|simditer|fringeiter|rangeiter|nditer|iter|merge|result|let|true|false|macro|type|\
+             log|erf|abs|sqrt|simd|select|switch|assert|broadcast|serialize|deserialize|\
+             iterate|cudf|simditer|fringeiter|rangeiter|chunkiter|nditer|iter|merge|result|let|true|false|macro|type|\
              i8|i16|i32|i64|u8|u16|u32|u64|f32|f64|bool|vec|dict|appender|merger|vecmerger|\
              dictmerger|groupmerger|tovec|min|max|pow)$").unwrap();
 
-        static ref COMMENT_RE: Regex = Regex::new("#.*$").unwrap();
-        static ref STRLIT_RE: Regex = Regex::new(r#""[^"]*""#).unwrap();
+        static ref COMMENT_RE: Regex = Regex::new(r"^(#.*|/\*(?s:.*)\*/)$").unwrap();
+        static ref STRLIT_RE: Regex = Regex::new(r#"^"(?:[^"\\]|\\.)*"$"#).unwrap();
         static ref IDENT_RE: Regex = Regex::new(r"^[A-Za-z$_][A-Za-z0-9$_]*$").unwrap();
 
         static ref I8_BASE_10_RE: Regex = Regex::new(r"^[0-9]+[cC]$").unwrap();
         static ref I8_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+[cC]$").unwrap();
         static ref I8_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+[cC]$").unwrap();
 
-    static ref I16_BASE_10_RE: Regex = Regex::new(r"[0-9]+si$").unwrap();
-    static ref I16_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+si$").unwrap();
-        static ref I16_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+si$").unwrap();
+        static ref I16_BASE_10_RE: Regex = Regex::new(r"^[0-9]+(si|SI)$").unwrap();
+        static ref I16_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+(si|SI)$").unwrap();
+        static ref I16_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+(si|SI)$").unwrap();
 
         static ref I32_BASE_10_RE: Regex = Regex::new(r"^[0-9]+$").unwrap();
         static ref I32_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+$").unwrap();
@@ -188,6 +210,22 @@ pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
         static ref I64_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+[lL]$").unwrap();
         static ref I64_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+[lL]$").unwrap();
 
+        static ref U8_BASE_10_RE: Regex = Regex::new(r"^[0-9]+(uc|UC)$").unwrap();
+        static ref U8_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+(uc|UC)$").unwrap();
+        static ref U8_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+(uc|UC)$").unwrap();
+
+        static ref U16_BASE_10_RE: Regex = Regex::new(r"^[0-9]+(usi|USI)$").unwrap();
+        static ref U16_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+(usi|USI)$").unwrap();
+        static ref U16_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+(usi|USI)$").unwrap();
+
+        static ref U32_BASE_10_RE: Regex = Regex::new(r"^[0-9]+[uU]$").unwrap();
+        static ref U32_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+[uU]$").unwrap();
+        static ref U32_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+[uU]$").unwrap();
+
+        static ref U64_BASE_10_RE: Regex = Regex::new(r"^[0-9]+(ul|UL)$").unwrap();
+        static ref U64_BASE_2_RE: Regex = Regex::new(r"^0b[0-1]+(ul|UL)$").unwrap();
+        static ref U64_BASE_16_RE: Regex = Regex::new(r"^0x[0-9a-fA-F]+(ul|UL)$").unwrap();
+
         static ref F32_RE: Regex = Regex::new(
             r"[0-9]+\.[0-9]+([eE]-?[0-9]+)?[fF]|[0-9]+([eE]-?[0-9]+)?[fF]").unwrap();
 
@@ -198,11 +236,38 @@ pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
     use self::Token::*;
 
     let mut tokens: Vec<Token> = Vec::new();
+    let mut spans: Vec<Span> = Vec::new();
+
+    // The current line number and the offset at which it starts.
+    let (mut line, mut line_start) = (1, 0);
+    // Tracks newlines between `position` and `offset`.
+    let mut position = 0;
+    let mut advance = |offset: usize, line: &mut usize, line_start: &mut usize| {
+        for (i, c) in input[position..offset].char_indices() {
+            if c == '\n' {
+                *line += 1;
+                *line_start = position + i + 1;
+            }
+        }
+        position = offset;
+    };
 
     for cap in TOKEN_RE.captures_iter(input) {
-        let text = cap.get(0).unwrap().as_str();
+        let matched = cap.get(0).unwrap();
+        let normalized;
+        let suffixed_i32 = matched.as_str().ends_with("i32");
+        let text = match matched.as_str() {
+            text if text.starts_with(|c: char| c.is_ascii_digit()) => {
+                normalized = normalize_numeric_literal(text);
+                normalized.as_str()
+            }
+            text => text,
+        };
+        advance(matched.start(), &mut line, &mut line_start);
         if COMMENT_RE.is_match(text) {
             // Do nothing - skips the token.
+        } else if text.starts_with("/*") {
+            return compile_err!("Unterminated block comment (at line {})", line);
         } else if KEYWORD_RE.is_match(text) {
             tokens.push(match text {
                 "if" => TIf,
@@ -238,6 +303,7 @@ pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
                 "fringeiter" => TFringeIter,
                 "nditer" => TNdIter,
                 "rangeiter" => TRangeIter,
+                "chunkiter" => TChunkIter,
                 "len" => TLen,
                 "lookup" => TLookup,
                 "optlookup" => TOptLookup,
@@ -256,10 +322,12 @@ pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
                 "tanh" => TTanh,
                 "log" => TLog,
                 "erf" => TErf,
+                "abs" => TAbs,
                 "sqrt" => TSqrt,
                 "cudf" => TCUDF,
                 "simd" => TSimd,
                 "select" => TSelect,
+                "switch" => TSwitch,
                 "assert" => TAssert,
                 "broadcast" => TBroadcast,
                 "serialize" => TSerialize,
@@ -272,13 +340,33 @@ pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
                 _ => return compile_err!("Invalid input token: {}", text),
             });
         } else if STRLIT_RE.is_match(text) {
-            let string = text.trim_matches('"').to_string();
-            if !(string.is_ascii()) {
-                return compile_err!("Weld strings must be valid ASCII");
-            }
-            tokens.push(TStringLiteral(string)); // Trim off quotes before tokenizing
+            tokens.push(parse_string_literal(text)?);
         } else if IDENT_RE.is_match(text) {
             tokens.push(TIdent(text.to_string()));
+        } else if U8_BASE_10_RE.is_match(text) {
+            tokens.push(parse_u8_literal(text, 10)?)
+        } else if U8_BASE_2_RE.is_match(text) {
+            tokens.push(parse_u8_literal(text, 2)?)
+        } else if U8_BASE_16_RE.is_match(text) {
+            tokens.push(parse_u8_literal(text, 16)?)
+        } else if U16_BASE_10_RE.is_match(text) {
+            tokens.push(parse_u16_literal(text, 10)?)
+        } else if U16_BASE_2_RE.is_match(text) {
+            tokens.push(parse_u16_literal(text, 2)?)
+        } else if U16_BASE_16_RE.is_match(text) {
+            tokens.push(parse_u16_literal(text, 16)?)
+        } else if U32_BASE_10_RE.is_match(text) {
+            tokens.push(parse_u32_literal(text, 10)?)
+        } else if U32_BASE_2_RE.is_match(text) {
+            tokens.push(parse_u32_literal(text, 2)?)
+        } else if U32_BASE_16_RE.is_match(text) {
+            tokens.push(parse_u32_literal(text, 16)?)
+        } else if U64_BASE_10_RE.is_match(text) {
+            tokens.push(parse_u64_literal(text, 10)?)
+        } else if U64_BASE_2_RE.is_match(text) {
+            tokens.push(parse_u64_literal(text, 2)?)
+        } else if U64_BASE_16_RE.is_match(text) {
+            tokens.push(parse_u64_literal(text, 16)?)
         } else if I8_BASE_10_RE.is_match(text) {
             tokens.push(parse_i8_literal(text, 10)?)
         } else if I8_BASE_2_RE.is_match(text) {
@@ -292,11 +380,11 @@ pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
         } else if I16_BASE_16_RE.is_match(text) {
             tokens.push(parse_i16_literal(text, 16)?)
         } else if I32_BASE_10_RE.is_match(text) {
-            tokens.push(parse_i32_literal(text, 10)?)
+            tokens.push(parse_i32_literal(text, 10, suffixed_i32)?)
         } else if I32_BASE_2_RE.is_match(text) {
-            tokens.push(parse_i32_literal(text, 2)?)
+            tokens.push(parse_i32_literal(text, 2, suffixed_i32)?)
         } else if I32_BASE_16_RE.is_match(text) {
-            tokens.push(parse_i32_literal(text, 16)?)
+            tokens.push(parse_i32_literal(text, 16, suffixed_i32)?)
         } else if I64_BASE_10_RE.is_match(text) {
             tokens.push(parse_i64_literal(text, 10)?)
         } else if I64_BASE_2_RE.is_match(text) {
@@ -345,12 +433,30 @@ pub fn tokenize(input: &str) -> WeldResult<Vec<Token>> {
                 "||" => TLogicalOr,
                 "&" => TBitwiseAnd,
                 "^" => TXor,
+                "<<" => TShiftLeft,
+                ">>" => TShiftRight,
                 _ => return compile_err!("Invalid input token: {}", text),
             });
         }
+
+        if tokens.len() > spans.len() {
+            spans.push(Span {
+                start: matched.start(),
+                end: matched.end(),
+                line,
+                column: input[line_start..matched.start()].chars().count() + 1,
+            });
+        }
     }
     tokens.push(TEndOfInput);
-    Ok(tokens)
+    advance(input.len(), &mut line, &mut line_start);
+    spans.push(Span {
+        start: input.len(),
+        end: input.len(),
+        line,
+        column: input[line_start..].chars().count() + 1,
+    });
+    Ok((tokens, spans))
 }
 
 impl fmt::Display for Token {
@@ -359,13 +465,18 @@ impl fmt::Display for Token {
         match *self {
             // Cases that return variable strings
             TI32Literal(ref value) => write!(f, "{}", value),
+            TTypedI32Literal(ref value) => write!(f, "{}i32", value),
             TI64Literal(ref value) => write!(f, "{}L", value),
             TF32Literal(ref value) => write!(f, "{}F", value),
             TF64Literal(ref value) => write!(f, "{}", value), // TODO: force .0?
             TI16Literal(ref value) => write!(f, "{}si", value),
             TI8Literal(ref value) => write!(f, "{}C", value),
+            TU8Literal(ref value) => write!(f, "{}UC", value),
+            TU16Literal(ref value) => write!(f, "{}usi", value),
+            TU32Literal(ref value) => write!(f, "{}U", value),
+            TU64Literal(ref value) => write!(f, "{}UL", value),
             TBoolLiteral(ref value) => write!(f, "{}B", value),
-            TStringLiteral(ref value) => write!(f, "\"{}\"", value),
+            TStringLiteral(ref value) => write!(f, "\"{}\"", escape_string(value)),
             TIdent(ref value) => write!(f, "{}", value),
 
             // Cases that return fixed strings
@@ -376,11 +487,16 @@ impl fmt::Display for Token {
                     match *other {
                         // These cases are handled above but repeated here for exhaustive match
                         TI32Literal(_) => "",
+                        TTypedI32Literal(_) => "",
                         TI64Literal(_) => "",
                         TF32Literal(_) => "",
                         TF64Literal(_) => "",
                         TI16Literal(_) => "",
                         TI8Literal(_) => "",
+                        TU8Literal(_) => "",
+                        TU16Literal(_) => "",
+                        TU32Literal(_) => "",
+                        TU64Literal(_) => "",
                         TBoolLiteral(_) => "",
                         TStringLiteral(_) => "",
                         TIdent(_) => "",
@@ -418,6 +534,7 @@ impl fmt::Display for Token {
                         TFringeIter => "fringeiter",
                         TNdIter => "nditer",
                         TRangeIter => "rangeiter",
+                        TChunkIter => "chunkiter",
                         TLen => "len",
                         TLookup => "lookup",
                         TOptLookup => "optlookup",
@@ -429,17 +546,19 @@ impl fmt::Display for Token {
                         TCos => "cos",
                         TTan => "tan",
                         TASin => "asin",
-                        TACos => "atan",
-                        TATan => "acos",
+                        TACos => "acos",
+                        TATan => "atan",
                         TSinh => "sinh",
                         TCosh => "cosh",
                         TTanh => "tanh",
                         TLog => "log",
                         TErf => "erf",
+                        TAbs => "abs",
                         TSqrt => "sqrt",
                         TCUDF => "cudf",
                         TSimd => "simd",
                         TSelect => "select",
+                        TSwitch => "switch",
                         TAssert => "assert",
                         TBroadcast => "broadcast",
                         TSerialize => "serialize",
@@ -474,6 +593,8 @@ impl fmt::Display for Token {
                         TLogicalOr => "||",
                         TBitwiseAnd => "&",
                         TXor => "^",
+                        TShiftLeft => "<<",
+                        TShiftRight => ">>",
                         TMin => "min",
                         TMax => "max",
                         TPow => "pow",
@@ -485,6 +606,42 @@ impl fmt::Display for Token {
     }
 }
 
+/// Removes digit separators and replaces Rust-style type suffixes in a numeric literal with the
+/// equivalent Weld suffixes, e.g., `1_000i64` becomes `1000L`.
+fn normalize_numeric_literal(text: &str) -> String {
+    const SUFFIXES: [(&str, &str); 10] = [
+        ("i8", "C"),
+        ("i16", "si"),
+        ("i32", ""),
+        ("i64", "L"),
+        ("u8", "UC"),
+        ("u16", "usi"),
+        ("u32", "U"),
+        ("u64", "UL"),
+        ("f32", "F"),
+        ("f64", ""),
+    ];
+    let text = text.replace('_', "");
+    let prefixed = text.starts_with("0x") || text.starts_with("0b");
+    for (suffix, replacement) in SUFFIXES.iter() {
+        if !text.ends_with(suffix) || text.len() == suffix.len() {
+            continue;
+        }
+        let value = &text[..text.len() - suffix.len()];
+        if suffix.starts_with('f') {
+            // Hexadecimal and binary literals are always integers: `0x1f32` is an i32.
+            if prefixed {
+                continue;
+            }
+            if *suffix == "f64" && !value.contains(&['.', 'e', 'E'][..]) {
+                return format!("{}.0", value);
+            }
+        }
+        return format!("{}{}", value, replacement);
+    }
+    text
+}
+
 fn parse_i8_literal(input: &str, base: u32) -> WeldResult<Token> {
     let slice = if base == 10 {
         &input[..input.len() - 1]
@@ -509,9 +666,10 @@ fn parse_i16_literal(input: &str, base: u32) -> WeldResult<Token> {
     }
 }
 
-fn parse_i32_literal(input: &str, base: u32) -> WeldResult<Token> {
+fn parse_i32_literal(input: &str, base: u32, suffixed: bool) -> WeldResult<Token> {
     let slice = if base == 10 { input } else { &input[2..] };
     match i32::from_str_radix(slice, base) {
+        Ok(value) if suffixed => Ok(Token::TTypedI32Literal(value)),
         Ok(value) => Ok(Token::TI32Literal(value)),
         Err(_) => compile_err!("Invalid i32 literal: {}", input),
     }
@@ -529,6 +687,86 @@ fn parse_i64_literal(input: &str, base: u32) -> WeldResult<Token> {
     }
 }
 
+fn parse_u8_literal(input: &str, base: u32) -> WeldResult<Token> {
+    let slice = if base == 10 {
+        &input[..input.len() - 2]
+    } else {
+        &input[2..input.len() - 2]
+    };
+    match u8::from_str_radix(slice, base) {
+        Ok(value) => Ok(Token::TU8Literal(value)),
+        Err(_) => compile_err!("Invalid u8 literal: {}", input),
+    }
+}
+
+fn parse_u16_literal(input: &str, base: u32) -> WeldResult<Token> {
+    let slice = if base == 10 {
+        &input[..input.len() - 3]
+    } else {
+        &input[2..input.len() - 3]
+    };
+    match u16::from_str_radix(slice, base) {
+        Ok(value) => Ok(Token::TU16Literal(value)),
+        Err(_) => compile_err!("Invalid u16 literal: {}", input),
+    }
+}
+
+fn parse_u32_literal(input: &str, base: u32) -> WeldResult<Token> {
+    let slice = if base == 10 {
+        &input[..input.len() - 1]
+    } else {
+        &input[2..input.len() - 1]
+    };
+    match u32::from_str_radix(slice, base) {
+        Ok(value) => Ok(Token::TU32Literal(value)),
+        Err(_) => compile_err!("Invalid u32 literal: {}", input),
+    }
+}
+
+fn parse_u64_literal(input: &str, base: u32) -> WeldResult<Token> {
+    let slice = if base == 10 {
+        &input[..input.len() - 2]
+    } else {
+        &input[2..input.len() - 2]
+    };
+    match u64::from_str_radix(slice, base) {
+        Ok(value) => Ok(Token::TU64Literal(value)),
+        Err(_) => compile_err!("Invalid u64 literal: {}", input),
+    }
+}
+
+/// Parses a quoted string literal, replacing escape sequences with the characters they represent.
+///
+/// The supported escape sequences are `\n`, `\r`, `\t`, `\\`, `\"`, and `\'`. Strings are
+/// NULL-terminated when compiled, so `\0` is not supported.
+fn parse_string_literal(input: &str) -> WeldResult<Token> {
+    // Trim off the surrounding quotes.
+    let input = &input[1..input.len() - 1];
+    if !input.is_ascii() {
+        return compile_err!("Weld strings must be valid ASCII");
+    }
+    let mut string = String::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some(other) => return compile_err!("Invalid escape sequence in string: \\{}", other),
+            None => return compile_err!("Unterminated escape sequence in string"),
+        };
+        string.push(escaped);
+    }
+    Ok(Token::TStringLiteral(string))
+}
+
 #[test]
 fn basic_tokenize() {
     use self::Token::*;
@@ -537,6 +775,14 @@ fn basic_tokenize() {
         tokenize("\"test string\"").unwrap(),
         vec![TStringLiteral("test string".to_string()), TEndOfInput]
     );
+    assert_eq!(
+        tokenize(r#""a \"quoted\"\tstring\n\\""#).unwrap(),
+        vec![
+            TStringLiteral("a \"quoted\"\tstring\n\\".to_string()),
+            TEndOfInput
+        ]
+    );
+    assert!(tokenize(r#""bad \q escape""#).is_err());
     assert_eq!(
         tokenize("\"test\" string").unwrap(),
         vec![
@@ -660,6 +906,21 @@ fn basic_tokenize() {
             TEndOfInput
         ]
     );
+    assert_eq!(
+        tokenize("|a:f32| abs(a)").unwrap(),
+        vec![
+            TBar,
+            TIdent("a".into()),
+            TColon,
+            TF32,
+            TBar,
+            TAbs,
+            TOpenParen,
+            TIdent("a".into()),
+            TCloseParen,
+            TEndOfInput
+        ]
+    );
     assert_eq!(
         tokenize("|a:i8| sqrt(a)").unwrap(),
         vec![
@@ -699,6 +960,17 @@ fn basic_tokenize() {
         vec![TI16Literal(42i16), TEndOfInput]
     );
     assert_eq!(tokenize("0b10").unwrap(), vec![TI32Literal(2), TEndOfInput]);
+    assert_eq!(
+        tokenize("200uc 0xFFusi 7U 0b11UL").unwrap(),
+        vec![
+            TU8Literal(200),
+            TU16Literal(255),
+            TU32Literal(7),
+            TU64Literal(3),
+            TEndOfInput
+        ]
+    );
+    assert!(tokenize("256uc").is_err());
     assert_eq!(
         tokenize("0x10").unwrap(),
         vec![TI32Literal(16), TEndOfInput]
@@ -747,3 +1019,93 @@ fn basic_tokenize() {
         ]
     );
 }
+
+#[test]
+fn tokenize_numeric_literals() {
+    use self::Token::*;
+    assert_eq!(
+        tokenize("1_000_000 0xFF_FF 0b1010_1010").unwrap(),
+        vec![
+            TI32Literal(1_000_000),
+            TI32Literal(0xFFFF),
+            TI32Literal(0b1010_1010),
+            TEndOfInput
+        ]
+    );
+    assert_eq!(
+        tokenize("1i8 2i16 3i32 4i64 5u8 6u16 7u32 8u64 9SI 10USI").unwrap(),
+        vec![
+            TI8Literal(1),
+            TI16Literal(2),
+            TTypedI32Literal(3),
+            TI64Literal(4),
+            TU8Literal(5),
+            TU16Literal(6),
+            TU32Literal(7),
+            TU64Literal(8),
+            TI16Literal(9),
+            TU16Literal(10),
+            TEndOfInput
+        ]
+    );
+    assert_eq!(
+        tokenize("0xFFu8 0b11i64 0x1f32 -0x7Fi8").unwrap(),
+        vec![
+            TU8Literal(255),
+            TI64Literal(3),
+            TI32Literal(0x1f32),
+            TMinus,
+            TI8Literal(127),
+            TEndOfInput
+        ]
+    );
+    assert_eq!(
+        tokenize("2f32 2f64 1.5f32 1_000.25f64 1e3f32").unwrap(),
+        vec![
+            TF32Literal(2.0),
+            TF64Literal(2.0),
+            TF32Literal(1.5),
+            TF64Literal(1000.25),
+            TF32Literal(1e3),
+            TEndOfInput
+        ]
+    );
+    assert!(tokenize("256u8").is_err());
+    assert!(tokenize("0x1g").is_err());
+}
+
+#[test]
+fn tokenize_spans() {
+    let (tokens, spans) = tokenize_with_spans("a + 1 # comment\n  foo").unwrap();
+    assert_eq!(tokens.len(), spans.len());
+
+    let positions: Vec<_> = spans
+        .iter()
+        .map(|s| (s.start, s.end, s.line, s.column))
+        .collect();
+    assert_eq!(
+        positions,
+        [
+            (0, 1, 1, 1),
+            (2, 3, 1, 3),
+            (4, 5, 1, 5),
+            (18, 21, 2, 3),
+            (21, 21, 2, 6)
+        ]
+    );
+
+    let (tokens, spans) = tokenize_with_spans("a /* one\ntwo # */ + \"#/*\"").unwrap();
+    assert_eq!(
+        tokens,
+        [
+            Token::TIdent("a".into()),
+            Token::TPlus,
+            Token::TStringLiteral("#/*".into()),
+            Token::TEndOfInput
+        ]
+    );
+    let positions: Vec<_> = spans.iter().map(|s| (s.line, s.column)).collect();
+    assert_eq!(positions, [(1, 1), (2, 10), (2, 12), (2, 17)]);
+
+    assert!(tokenize("a /* unterminated").is_err());
+}
@@ -31,19 +31,11 @@ pub fn check_transform(input: &str, expect: &str, transform: fn(&mut Expr)) {
 /// Print an un-indented expression for string comparison.
 #[cfg(test)]
 pub fn print_expr_without_indent(e: &Expr) -> String {
-    use crate::ast::{PrettyPrint, PrettyPrintConfig};
-    let config = PrettyPrintConfig::default()
-        .show_types(false)
-        .should_indent(false);
-    e.pretty_print_config(&config)
+    crate::ast::print_expr(e)
 }
 
 /// Print an un-indented expression for string comparison.
 #[cfg(test)]
 pub fn print_typed_expr_without_indent(e: &Expr) -> String {
-    use crate::ast::{PrettyPrint, PrettyPrintConfig};
-    let config = PrettyPrintConfig::default()
-        .show_types(true)
-        .should_indent(false);
-    e.pretty_print_config(&config)
+    crate::ast::print_typed_expr(e)
 }
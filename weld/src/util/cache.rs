@@ -0,0 +1,179 @@
+//! A cache of compiled modules.
+//!
+//! Compiling a program spends most of its time in LLVM, so hosts that compile the same program
+//! repeatedly (e.g., once per query or per batch) can reuse the module compiled the first time.
+//! Modules are keyed on the program after macro substitution, printed with the types written in
+//! it (which ignores formatting and comments), and on the configuration entries that affect
+//! compilation. When the cache is full,
+//! the least recently used module is evicted.
+
+use fnv::FnvHashMap;
+
+use crate::ast::ExprKind::CUDF;
+use crate::ast::*;
+use crate::conf::constants::*;
+use crate::WeldConf;
+
+/// Configuration keys that do not affect the compiled code.
+const RUNTIME_KEYS: &[&str] = &[
+    CONF_MEMORY_LIMIT_KEY,
+    CONF_THREAD_PINNING_KEY,
+    CONF_NUMA_LOCAL_KEY,
+    CONF_COMPILE_CACHE_KEY,
+    CONF_COMPILE_CACHE_SIZE_KEY,
+];
+
+/// Identifies a compiled module.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// The program after macro substitution, printed with its types in a normalized form.
+    program: String,
+    /// The sorted configuration entries that affect compilation.
+    conf: Vec<(String, Vec<u8>)>,
+}
+
+impl CacheKey {
+    /// Returns the key of `program` compiled with `conf`, or `None` if the compiled module should
+    /// not be cached.
+    ///
+    /// Programs that call UDFs are not cached, since the symbols they call may be registered
+    /// again with different functions.
+    pub fn new(program: &Expr, conf: &WeldConf) -> Option<CacheKey> {
+        let mut calls_udf = false;
+        program.traverse(&mut |e| {
+            if let CUDF { .. } = e.kind {
+                calls_udf = true;
+            }
+        });
+        if calls_udf {
+            return None;
+        }
+
+        let mut entries: Vec<_> = conf
+            .keys()
+            .filter(|key| key.starts_with("weld.") && !RUNTIME_KEYS.contains(key))
+            .map(|key| (key.to_string(), conf.get(key).unwrap().as_bytes().to_vec()))
+            .collect();
+        entries.sort();
+        Some(CacheKey {
+            program: print_typed_expr(program),
+            conf: entries,
+        })
+    }
+}
+
+/// A cache of values with least-recently-used eviction.
+#[derive(Debug)]
+pub struct ModuleCache<V> {
+    /// Maps keys to values and the time they were last used.
+    entries: FnvHashMap<CacheKey, (V, u64)>,
+    /// Incremented on each access.
+    clock: u64,
+}
+
+impl<V: Clone> ModuleCache<V> {
+    /// Returns a new empty cache.
+    pub fn new() -> ModuleCache<V> {
+        ModuleCache {
+            entries: FnvHashMap::default(),
+            clock: 0,
+        }
+    }
+
+    /// Returns the value for `key` and marks it as recently used.
+    pub fn get(&mut self, key: &CacheKey) -> Option<V> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.1 = clock;
+            entry.0.clone()
+        })
+    }
+
+    /// Adds a value for `key`, evicting the least recently used values so that the cache holds
+    /// at most `capacity` values.
+    pub fn insert(&mut self, key: CacheKey, value: V, capacity: usize) {
+        self.entries.remove(&key);
+        while !self.entries.is_empty() && self.entries.len() >= capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.1)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            trace!("Evicting module from the module cache");
+            self.entries.remove(&oldest);
+        }
+        if capacity > 0 {
+            self.clock += 1;
+            self.entries.insert(key, (value, self.clock));
+        }
+    }
+
+    /// Removes all values.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the number of values in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+fn key(code: &str, conf: &WeldConf) -> Option<CacheKey> {
+    let program = crate::syntax::parser::parse_program(code).unwrap();
+    let expr = crate::syntax::macro_processor::process_program(&program).unwrap();
+    CacheKey::new(&expr, conf)
+}
+
+#[test]
+fn cache_keys() {
+    let conf = &mut WeldConf::new();
+    let a = key("|x:i32| x + 1", conf).unwrap();
+    // Formatting, comments, and macros do not change the key.
+    let b = key(
+        "macro inc(v) = v + 1; |x:i32|\n  # Increment.\n  inc(x)",
+        conf,
+    )
+    .unwrap();
+    assert_eq!(a, b);
+    assert_ne!(a, key("|x:i32| x + 2", conf).unwrap());
+    // Types written in nested functions change the key.
+    assert_ne!(
+        key("|v:vec[i64]| map(v, |e:i64| e)", conf).unwrap(),
+        key("|v:vec[i64]| map(v, |e:i32| e)", conf).unwrap()
+    );
+
+    // Only configuration entries that affect compilation change the key.
+    conf.set(CONF_MEMORY_LIMIT_KEY, "1024");
+    conf.set("user.key", "value");
+    assert_eq!(a, key("|x:i32| x + 1", conf).unwrap());
+    conf.set(CONF_THREADS_KEY, "4");
+    assert_ne!(a, key("|x:i32| x + 1", conf).unwrap());
+
+    assert!(key("|x:i64| cudf[f, i64](x)", conf).is_none());
+}
+
+#[test]
+fn cache_evicts_least_recently_used() {
+    let conf = &WeldConf::new();
+    let keys: Vec<_> = (0..3)
+        .map(|i| key(&format!("|x:i32| x + {}", i), conf).unwrap())
+        .collect();
+    let mut cache = ModuleCache::new();
+    cache.insert(keys[0].clone(), 0, 2);
+    cache.insert(keys[1].clone(), 1, 2);
+    assert_eq!(cache.get(&keys[0]), Some(0));
+
+    // The second key is the least recently used.
+    cache.insert(keys[2].clone(), 2, 2);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(&keys[1]), None);
+    assert_eq!(cache.get(&keys[0]), Some(0));
+    assert_eq!(cache.get(&keys[2]), Some(2));
+
+    cache.clear();
+    assert_eq!(cache.len(), 0);
+}
@@ -3,9 +3,6 @@
 //! This module can be used both for writing code for consumption in another program (e.g., writing
 //! LLVM files that can then be passed to Clang) or for debugging.
 
-use time;
-use uuid;
-
 use crate::conf::DumpCodeConfig;
 use crate::error::*;
 
@@ -14,8 +11,6 @@ use std::io::Error as IOError;
 use std::io::Write;
 use std::path::PathBuf;
 
-use uuid::Uuid;
-
 impl From<IOError> for WeldCompileError {
     fn from(err: IOError) -> WeldCompileError {
         WeldCompileError::new(err.to_string())
@@ -26,6 +21,8 @@ impl From<IOError> for WeldCompileError {
 pub enum DumpCodeFormat {
     Weld,
     WeldOpt,
+    /// The Weld program after each optimization pass.
+    WeldPasses,
     LLVM,
     LLVMOpt,
     SIR,
@@ -36,7 +33,7 @@ impl DumpCodeFormat {
     /// Returns a vector with all formats.
     pub fn all() -> Vec<DumpCodeFormat> {
         use self::DumpCodeFormat::*;
-        vec![Weld, WeldOpt, LLVM, LLVMOpt, SIR, Assembly]
+        vec![Weld, WeldOpt, WeldPasses, LLVM, LLVMOpt, SIR, Assembly]
     }
 
     /// Returns a filename suffix for the format.
@@ -53,7 +50,7 @@ impl DumpCodeFormat {
     pub fn extension(self) -> String {
         use self::DumpCodeFormat::*;
         match self {
-            Weld | WeldOpt => "weld",
+            Weld | WeldOpt | WeldPasses => "weld",
             LLVM | LLVMOpt => "ll",
             SIR => "sir",
             Assembly => "S",
@@ -64,11 +61,35 @@ impl DumpCodeFormat {
 
 /// Writes code to a file using the given configuration.
 ///
-/// The format determines the extension used for the dumped file.
+/// The format determines the extension used for the dumped file. Files are named
+/// `<filename>-<id><suffix>.<extension>`, where `id` identifies the compilation.
 pub fn write_code<T: AsRef<str>>(
     code: T,
     format: DumpCodeFormat,
     config: &DumpCodeConfig,
+) -> WeldResult<()> {
+    write_file(code.as_ref(), format, &format.suffix(), config)
+}
+
+/// Writes the Weld program after an optimization pass using the given configuration.
+///
+/// `index` is the position of the pass in the pipeline, so that the files sort in the order the
+/// passes ran.
+pub fn write_pass_code<T: AsRef<str>>(
+    code: T,
+    index: usize,
+    pass_name: &str,
+    config: &DumpCodeConfig,
+) -> WeldResult<()> {
+    let suffix = format!("-pass{:02}-{}", index, pass_name);
+    write_file(code.as_ref(), DumpCodeFormat::WeldPasses, &suffix, config)
+}
+
+fn write_file(
+    code: &str,
+    format: DumpCodeFormat,
+    suffix: &str,
+    config: &DumpCodeConfig,
 ) -> WeldResult<()> {
     // Code dumping is not enabled - return.
     if !config.enabled {
@@ -82,7 +103,7 @@ pub fn write_code<T: AsRef<str>>(
 
     let path = &mut PathBuf::new();
     path.push(&config.directory);
-    path.push(&format!("{}{}", &config.filename, format.suffix()));
+    path.push(format!("{}-{}{}", &config.filename, &config.id, suffix));
     path.set_extension(format.extension());
 
     info!("Writing code to {}", path.to_str().unwrap());
@@ -90,15 +111,6 @@ pub fn write_code<T: AsRef<str>>(
     let mut options = OpenOptions::new();
     let mut file = options.write(true).create_new(true).open(path)?;
 
-    file.write_all(code.as_ref().as_bytes())?;
+    file.write_all(code.as_bytes())?;
     Ok(())
 }
-
-/// Return a timestamp-based filename for `dumpCode`.
-///
-/// The timestamp has a 2-character identifier attached to prevent naming conflicts.
-pub fn unique_filename() -> String {
-    let uuid = Uuid::new_v4().to_simple().to_string();
-    let suffix = &uuid[0..2];
-    format!("code-{}-{}", time::now().to_timespec().sec, suffix)
-}
@@ -0,0 +1,174 @@
+//! Compiling the fields of a program that returns a struct independently.
+//!
+//! Wide programs, such as query plans that compute several aggregates, often return a struct
+//! whose fields are independent pipelines over the program's parameters. Each field can then be
+//! typed and optimized as a program of its own, in parallel with the other fields: `split` turns
+//! such a program into one program per field, `try_for_each` runs a compiler stage on each of
+//! them, and `join` puts the fields back into a single program.
+
+use std::panic;
+use std::thread;
+
+use crate::ast::ExprKind::*;
+use crate::ast::Type::*;
+use crate::ast::*;
+use crate::error::*;
+use crate::util::compile_stack_size;
+
+#[cfg(test)]
+use crate::tests::*;
+
+/// Splits a program that returns a struct into one program per field of the struct.
+///
+/// Each program takes the same parameters as `expr`. Returns an empty list if `expr` is not a
+/// function that directly returns a struct with several fields.
+pub fn split(expr: &Expr) -> Vec<Expr> {
+    if let Lambda {
+        ref params,
+        ref body,
+    } = expr.kind
+    {
+        if let MakeStruct { ref elems } = body.kind {
+            if elems.len() > 1 {
+                return elems
+                    .iter()
+                    .map(|elem| Expr {
+                        ty: Unknown,
+                        kind: Lambda {
+                            params: params.clone(),
+                            body: Box::new(elem.clone()),
+                        },
+                        annotations: expr.annotations.clone(),
+                        span: expr.span,
+                        id: expr.id,
+                    })
+                    .collect();
+            }
+        }
+    }
+    vec![]
+}
+
+/// Replaces the fields of the struct `expr` returns with the typed bodies of `fields`.
+///
+/// `fields` are the programs `split` returned for `expr`, after they were typed and optimized.
+pub fn join(expr: &mut Expr, fields: Vec<Expr>) -> WeldResult<()> {
+    let mut typed_params = None;
+    let mut elems = Vec::with_capacity(fields.len());
+    for field in fields {
+        if let Lambda { params, body } = field.kind {
+            if typed_params.get_or_insert_with(|| params.clone()) != &params {
+                return compile_err!("Struct fields were compiled with different parameters");
+            }
+            elems.push(*body);
+        } else {
+            return compile_err!("Struct field was not compiled to a function");
+        }
+    }
+
+    if let Lambda {
+        ref mut params,
+        ref mut body,
+    } = expr.kind
+    {
+        let mut result = Expr::new_make_struct(elems)?;
+        result.annotations = body.annotations.clone();
+        result.span = body.span;
+        *params = typed_params.unwrap_or_default();
+        **body = result;
+        expr.ty = Function(
+            params.iter().map(|p| p.ty.clone()).collect(),
+            Box::new(body.ty.clone()),
+        );
+        Ok(())
+    } else {
+        compile_err!("Only a function can be split into struct fields")
+    }
+}
+
+/// Calls `func` on each expression, using up to `threads` threads, and returns the first error.
+///
+/// Expressions are divided evenly among the threads. Each thread has a stack large enough for the
+/// deepest expression it runs on.
+pub fn try_for_each<F>(exprs: &mut [Expr], threads: usize, func: F) -> WeldResult<()>
+where
+    F: Fn(&mut Expr) -> WeldResult<()> + Sync,
+{
+    if threads <= 1 || exprs.len() <= 1 {
+        return exprs.iter_mut().try_for_each(func);
+    }
+    let per_thread = exprs.len().div_ceil(threads);
+    let func = &func;
+    thread::scope(|scope| {
+        let workers: Vec<_> = exprs
+            .chunks_mut(per_thread)
+            .map(|chunk| {
+                let depth = chunk.iter().map(Expr::depth).max().unwrap_or(0);
+                let stack_size = compile_stack_size(depth);
+                thread::Builder::new()
+                    .name("weld-compile".to_string())
+                    .stack_size(stack_size)
+                    .spawn_scoped(scope, move || chunk.iter_mut().try_for_each(func))
+            })
+            .collect();
+        let mut result = Ok(());
+        for worker in workers {
+            let worker_result = match worker {
+                Ok(handle) => handle
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload)),
+                Err(err) => compile_err!("Could not start a compiler thread: {}", err),
+            };
+            result = result.and(worker_result);
+        }
+        result
+    })
+}
+
+/// Prints the program made of `exprs`.
+///
+/// `exprs` are either a single program or the fields `split` returned for a program, which are
+/// printed joined back into one program.
+pub fn pretty_print(exprs: &[Expr]) -> WeldResult<String> {
+    match exprs {
+        [expr] => Ok(expr.pretty_print()),
+        [first, ..] => {
+            let mut program = first.clone();
+            join(&mut program, exprs.to_vec())?;
+            Ok(program.pretty_print())
+        }
+        [] => Ok(String::new()),
+    }
+}
+
+#[test]
+fn split_and_join_struct_fields() {
+    let mut e = parse_expr("|x:i32, y:i64| {x + 1, y * 2L, x}").unwrap();
+    let mut fields = split(&e);
+    assert_eq!(fields.len(), 3);
+    try_for_each(&mut fields, 2, |field| field.infer_types()).unwrap();
+    assert_eq!(
+        print_typed_expr_without_indent(&fields[1]),
+        "|x:i32,y:i64|(y:i64*2L)"
+    );
+
+    // Fields are printed as the program they form.
+    assert_eq!(
+        pretty_print(&fields).unwrap(),
+        "|x:i32,y:i64|{(x+1),(y*2L),x}"
+    );
+
+    join(&mut e, fields).unwrap();
+    let expected = typed_expression("|x:i32, y:i64| {x + 1, y * 2L, x}");
+    assert_eq!(e, expected);
+
+    // Only programs that return a struct with several fields are split.
+    assert!(split(&typed_expression("|x:i32| {x}")).is_empty());
+    assert!(split(&typed_expression("|x:i32| let y = {x, x}; y")).is_empty());
+}
+
+#[test]
+fn try_for_each_returns_errors() {
+    let mut fields = split(&parse_expr("|x:i32| {x + 1, x + 1L}").unwrap());
+    assert!(try_for_each(&mut fields, 2, |field| field.infer_types()).is_err());
+}
@@ -0,0 +1,105 @@
+//! The logger used by the compiler and runtime.
+//!
+//! Weld logs with the `log` crate. The first call to `set_level` or `set_sink` installs a logger
+//! for messages from Weld, which writes them to stderr or passes them to a sink provided by the
+//! host. Both the level and the sink can be changed at any time. If the process already installed
+//! another logger with the `log` crate, Weld's messages go to that logger instead.
+
+use chrono;
+use log;
+
+use std::sync::{Arc, Mutex, Once, RwLock};
+
+use crate::util::colors::Color::*;
+use crate::util::colors::*;
+use crate::{WeldLogLevel, WeldLogSink};
+
+/// A log sink that can be called after releasing the lock on it.
+type SharedSink = Arc<dyn Fn(WeldLogLevel, &str) + Send + Sync>;
+
+lazy_static! {
+    /// The sink for log messages, or `None` to write them to stderr.
+    static ref SINK: RwLock<Option<SharedSink>> = RwLock::new(None);
+    /// Sets the log level if Weld's logger is installed.
+    static ref MAX_LEVEL: Mutex<Option<log::MaxLogLevelFilter>> = Mutex::new(None);
+}
+
+static INSTALL: Once = Once::new();
+
+/// Forwards messages from Weld to the sink.
+struct WeldLogger;
+
+impl log::Log for WeldLogger {
+    fn enabled(&self, metadata: &log::LogMetadata<'_>) -> bool {
+        metadata.level() <= log::max_log_level() && metadata.target().starts_with("weld")
+    }
+
+    fn log(&self, record: &log::LogRecord<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        // Release the lock before calling the sink, which may log or replace the sink itself.
+        let sink = SINK.read().unwrap().clone();
+        match sink {
+            Some(sink) => sink(to_weld_level(record.level()), &message),
+            None => {
+                let prefix = match record.level() {
+                    log::LogLevel::Error => format_color(Red, "error"),
+                    log::LogLevel::Warn => format_color(Yellow, "warn"),
+                    log::LogLevel::Info => format_color(Yellow, "info"),
+                    log::LogLevel::Debug => format_color(Green, "debug"),
+                    log::LogLevel::Trace => format_color(Green, "trace"),
+                };
+                let date = chrono::Local::now().format("%T%.3f");
+                eprintln!("[{}] {}: {}", prefix, date, message);
+            }
+        }
+    }
+}
+
+fn to_weld_level(level: log::LogLevel) -> WeldLogLevel {
+    match level {
+        log::LogLevel::Error => WeldLogLevel::Error,
+        log::LogLevel::Warn => WeldLogLevel::Warn,
+        log::LogLevel::Info => WeldLogLevel::Info,
+        log::LogLevel::Debug => WeldLogLevel::Debug,
+        log::LogLevel::Trace => WeldLogLevel::Trace,
+    }
+}
+
+/// Installs Weld's logger unless another logger is installed.
+fn install() {
+    INSTALL.call_once(|| {
+        let result = log::set_logger(|max_level| {
+            *MAX_LEVEL.lock().unwrap() = Some(max_level);
+            Box::new(WeldLogger)
+        });
+        if result.is_err() {
+            debug!("Using the logger installed by the host");
+        }
+    });
+}
+
+/// Sets the level of messages that Weld logs.
+pub fn set_level(level: WeldLogLevel) {
+    install();
+    if let Some(ref max_level) = *MAX_LEVEL.lock().unwrap() {
+        max_level.set(level.into());
+    }
+}
+
+/// Sets the sink that receives Weld's log messages, or writes them to stderr if `sink` is `None`.
+pub fn set_sink(sink: Option<WeldLogSink>) {
+    install();
+    *SINK.write().unwrap() = sink.map(Arc::from);
+}
+
+#[test]
+fn sink_can_replace_itself() {
+    set_level(WeldLogLevel::Error);
+    set_sink(Some(Box::new(|_, _| set_sink(None))));
+    error!("Replacing the sink");
+    assert!(SINK.read().unwrap().is_none());
+    set_level(WeldLogLevel::Off);
+}
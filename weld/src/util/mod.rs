@@ -12,10 +12,28 @@ use crate::ast::*;
 use std::cmp::max;
 use std::iter;
 
+pub mod cache;
 pub mod colors;
 pub mod dump;
+pub mod fields;
+pub mod logging;
 pub mod stats;
 
+/// Stack space reserved for each level of nesting in an expression compiled on its own thread.
+///
+/// Type inference, printing, and conversion to SIR recurse once per level of nesting. Debug builds
+/// use about 11 KiB of stack per level for a chain of nested `Let`s.
+pub const STACK_PER_LEVEL: usize = 16 << 10;
+
+/// The smallest stack of a compiler thread. Expressions that need less are compiled on the calling
+/// thread.
+pub const MIN_COMPILE_STACK: usize = 2 << 20;
+
+/// Returns the stack size of a thread that compiles an expression with the given `depth`.
+pub fn compile_stack_size(depth: usize) -> usize {
+    max(depth.saturating_mul(STACK_PER_LEVEL), MIN_COMPILE_STACK)
+}
+
 /// Utility struct that can track and generate unique IDs and symbols for use in an expression.
 /// Each SymbolGenerator tracks the maximum ID used for every symbol name, and can be used to
 /// create new symbols with the same name but a unique ID.
@@ -62,6 +80,24 @@ impl SymbolGenerator {
     }
 }
 
+/// Escapes a string so it can be printed as a Weld string literal.
+///
+/// This is the inverse of the escaping performed when tokenizing string literals.
+pub fn escape_string(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
 pub fn join<T: iter::Iterator<Item = String>>(
     start: &str,
     sep: &str,
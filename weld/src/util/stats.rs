@@ -4,35 +4,92 @@ use time;
 
 use self::time::Duration;
 
+use std::time::Duration as StdDuration;
+
 /// Tracks various compile-time statistics throughout the compiler.
+///
+/// The statistics of a compiled module are returned by `WeldModule::compilation_stats`.
+#[derive(Clone, Debug)]
 pub struct CompilationStats {
     /// Running times for various Weld compiler components.
-    pub weld_times: Vec<(String, Duration)>,
+    pub(crate) weld_times: Vec<(String, Duration)>,
     /// Running times for Weld optimization passes.
-    pub pass_times: Vec<(String, Duration)>,
+    pub(crate) pass_times: Vec<(String, Duration)>,
     /// Running times for various LLVM components.
-    pub llvm_times: Vec<(String, Duration)>,
+    pub(crate) llvm_times: Vec<(String, Duration)>,
+    /// End-to-end compilation time.
+    pub(crate) total_time: Duration,
+}
+
+/// Converts a list of timings to standard durations.
+fn to_std(times: &[(String, Duration)]) -> Vec<(&str, StdDuration)> {
+    times
+        .iter()
+        .map(|(name, dur)| (name.as_str(), dur.to_std().unwrap_or_default()))
+        .collect()
+}
+
+impl Default for CompilationStats {
+    fn default() -> Self {
+        CompilationStats::new()
+    }
 }
 
 impl CompilationStats {
+    /// Returns statistics with no recorded times.
     pub fn new() -> CompilationStats {
         CompilationStats {
             weld_times: Vec::new(),
             pass_times: Vec::new(),
             llvm_times: Vec::new(),
+            total_time: Duration::zero(),
         }
     }
 
-    /// Formats a duration for printing the statistics, in terms of milliseconds and microseconds.
+    /// Returns the time spent in each stage of the Weld compiler, such as parsing, type
+    /// inference, and conversion to SIR.
+    pub fn weld_times(&self) -> Vec<(&str, StdDuration)> {
+        to_std(&self.weld_times)
+    }
+
+    /// Returns the time spent in each optimization pass, in the order the passes ran.
+    pub fn pass_times(&self) -> Vec<(&str, StdDuration)> {
+        to_std(&self.pass_times)
+    }
+
+    /// Returns the time spent in each stage of the LLVM backend, such as code generation,
+    /// optimization, and JIT compilation.
+    pub fn llvm_times(&self) -> Vec<(&str, StdDuration)> {
+        to_std(&self.llvm_times)
+    }
+
+    /// Returns the end-to-end compilation time.
+    pub fn total_time(&self) -> StdDuration {
+        self.total_time.to_std().unwrap_or_default()
+    }
+
+    /// Returns a one-line summary of the total time spent in each part of the compiler.
+    pub fn summary(&self) -> String {
+        let sum = |times: &[(String, Duration)]| {
+            times
+                .iter()
+                .fold(Duration::zero(), |total, &(_, dur)| total + dur)
+        };
+        format!(
+            "{:.3} ms (Weld {:.3} ms, passes {:.3} ms, LLVM {:.3} ms)",
+            CompilationStats::format_time(&self.total_time),
+            CompilationStats::format_time(&sum(&self.weld_times)),
+            CompilationStats::format_time(&sum(&self.pass_times)),
+            CompilationStats::format_time(&sum(&self.llvm_times)),
+        )
+    }
+
+    /// Formats a duration for printing the statistics, in milliseconds with microsecond precision.
     fn format_time(duration: &Duration) -> f64 {
-        if duration.num_milliseconds() == 0 {
-            if let Some(v) = duration.num_microseconds() {
-                (v as f64) / 1000.0
-            } else {
-                0.0
-            }
-        } else {
-            duration.num_milliseconds() as f64
+        match duration.num_microseconds() {
+            Some(v) => (v as f64) / 1000.0,
+            // The duration is too long to count in microseconds.
+            None => duration.num_milliseconds() as f64,
         }
     }
 
@@ -87,3 +144,16 @@ impl CompilationStats {
         result
     }
 }
+
+#[test]
+fn summary_keeps_fractional_milliseconds() {
+    let mut stats = CompilationStats::default();
+    stats
+        .weld_times
+        .push(("Parsing".to_string(), Duration::microseconds(1500)));
+    stats.total_time = Duration::microseconds(2250);
+    assert_eq!(
+        stats.summary(),
+        "2.250 ms (Weld 1.500 ms, passes 0.000 ms, LLVM 0.000 ms)"
+    );
+}
@@ -1,5 +1,7 @@
 //! Tests for the Appender builder.
 
+use weld::runtime::WeldRuntimeErrno;
+
 mod common;
 use crate::common::*;
 
@@ -47,6 +49,26 @@ fn large_unaryop_for_appender_loop() {
     }
 }
 
+#[test]
+fn parallel_filter_with_grain_size() {
+    // Only the last tenth of the input passes the filter, so the work of the loop is skewed.
+    let code = "|x:vec[i64]| filter(x, |e| e >= 90000L)";
+    let conf = &mut many_threads_conf();
+    conf.set("weld.loop.grain_size", "512");
+
+    let input_vec: Vec<i64> = (0..100000).collect();
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<i64>;
+    let result = unsafe { (*data).clone() };
+
+    assert_eq!(result.len, 10000);
+    for i in 0..(result.len as isize) {
+        assert_eq!(unsafe { *result.data.offset(i) }, 90000 + i as i64)
+    }
+}
+
 #[test]
 fn simple_parallel_for_appender_loop() {
     let code = "|x:vec[i32]| result(@(grain_size: 100)for(x, appender[i64], |b,i,e| merge(b, i)))";
@@ -111,3 +133,66 @@ fn complex_parallel_for_appender_loop() {
         assert_eq!(unsafe { *result.data.offset(i * 3 + 3) }, 2)
     }
 }
+
+#[test]
+fn parallel_filter_appender_loop() {
+    // The loop is divided into several chunks per thread, and the result must keep the order of
+    // the input.
+    let code = "|x:vec[i32]| let r = for(x, {appender[i32], merger[i64,+]}, |b,i,e|
+                if(e % 3 == 0, {merge(b.$0, e), merge(b.$1, 1L)}, b)); {result(r.$0), result(r.$1)}";
+    let conf = &many_threads_conf();
+
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    struct Output {
+        filtered: WeldVec<i32>,
+        count: i64,
+    }
+
+    let input_vec: Vec<i32> = (0..100000).collect();
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const Output;
+    let result = unsafe { (*data).clone() };
+
+    let output: Vec<i32> = input_vec.into_iter().filter(|e| e % 3 == 0).collect();
+    assert_eq!(result.count, output.len() as i64);
+    assert_eq!(result.filtered.len, output.len() as i64);
+    for (i, expected) in output.iter().enumerate() {
+        assert_eq!(unsafe { *result.filtered.data.add(i) }, *expected);
+    }
+}
+
+#[test]
+fn appender_grows_from_small_capacity() {
+    let conf = &default_conf();
+    let input_vec: Vec<i32> = (0..1000).collect();
+    let input_data = &WeldVec::from(&input_vec);
+
+    for capacity in [0, 1].iter() {
+        let code = format!(
+            "|x:vec[i32]| result(for(x, appender[i32]({}L), |b,i,e| merge(b, e)))",
+            capacity
+        );
+        let ret_value = compile_and_run(&code, conf, input_data);
+        let data = ret_value.data() as *const WeldVec<i32>;
+        let result = unsafe { (*data).clone() };
+        assert_eq!(result.len as usize, input_vec.len());
+        for i in 0..(result.len as isize) {
+            assert_eq!(unsafe { *result.data.offset(i) }, input_vec[i as usize]);
+        }
+    }
+}
+
+#[test]
+fn appender_merged_twice_is_rejected() {
+    // Both results would share the appender's memory, so the program is a compile error.
+    let code = "|x:i32| let b = appender[i32]; {result(merge(b, x)), result(merge(b, x + 1))}";
+    let conf = &default_conf();
+    let input_data: &i32 = &3;
+
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::CompileError);
+    assert!(err_value.message().to_str().unwrap().contains("consumed more than once"));
+}
@@ -2,6 +2,7 @@
 
 mod common;
 use crate::common::*;
+use weld::WeldModule;
 
 #[test]
 fn basic_program() {
@@ -85,6 +86,20 @@ fn negation_double() {
     assert_eq!(result, -1.0 as f64);
 }
 
+#[test]
+fn negation_float_zero() {
+    let code = "|x:f32| -x";
+    let conf = &default_conf();
+
+    let input_data: &f32 = &0.0;
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const f32;
+    let result = unsafe { *data };
+    assert_eq!(result, 0.0);
+    assert!(result.is_sign_negative());
+}
+
 #[test]
 fn negated_arithmetic() {
     // In our language, - has the lowest precedence so the paraens around -3 are mandatory.
@@ -166,6 +181,19 @@ fn multiple_casts() {
     assert_eq!(result, 255);
 }
 
+#[test]
+fn unsigned_literals() {
+    let code = "|| u32(200uc) + 0xFFFFU + u32(0b1usi) + u32(1UL)";
+    let conf = &default_conf();
+
+    let input_data = &0;
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const u32;
+    let result = unsafe { *data };
+    assert_eq!(result, 200 + 65535 + 1 + 1);
+}
+
 #[test]
 fn program_with_args() {
     let code = "|x:i32| 40 + x";
@@ -224,6 +252,23 @@ fn let_statement() {
     assert_eq!(result, 44);
 }
 
+#[test]
+fn short_circuit_operators() {
+    // The right operands would divide by zero if they were evaluated.
+    let codes = ["|x:i32| x != 0 && 10 / x > 1", "|x:i32| !(x == 0 || 10 / x > 1)"];
+    let conf = &mut default_conf();
+    conf.set("weld.optimization.passes", "");
+
+    let input_data: &i32 = &0;
+
+    for code in codes.iter() {
+        let ret_value = compile_and_run(code, conf, input_data);
+        let data = ret_value.data() as *const bool;
+        let result = unsafe { *data };
+        assert!(!result);
+    }
+}
+
 #[test]
 fn if_statement() {
     let code = "|| if(true, 3, 4)";
@@ -237,6 +282,38 @@ fn if_statement() {
     assert_eq!(result, 3);
 }
 
+#[test]
+fn switch_statement() {
+    let code = "|x:i32| switch(x, 1: 10, 2: 20, 5: 50, -1)";
+    let conf = &default_conf();
+
+    for (input, expected) in [(1, 10), (2, 20), (5, 50), (3, -1), (-7, -1)].iter() {
+        let ret_value = compile_and_run(code, conf, input);
+        let data = ret_value.data() as *const i32;
+        let result = unsafe { *data };
+        assert_eq!(result, *expected);
+    }
+}
+
+#[test]
+fn switch_for_loop() {
+    let code = "|x:vec[i64]| map(x, |e| switch(e, 0L: 1.0, 1L: sqrt(f64(e + 3L)), 2L: 3.0, 4.0))";
+    let conf = &default_conf();
+
+    let input_vec: Vec<i64> = vec![0, 1, 2, 3, 4];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<f64>;
+    let result = unsafe { (*data).clone() };
+
+    let output = [1.0, 2.0, 3.0, 4.0, 4.0];
+    assert_eq!(result.len as usize, output.len());
+    for (i, expected) in output.iter().enumerate() {
+        assert_eq!(unsafe { *result.data.add(i) }, *expected)
+    }
+}
+
 #[test]
 fn maxmin() {
     let code = "|| max(3, min(2, 4))";
@@ -389,6 +466,36 @@ fn map_zip_loop() {
     }
 }
 
+#[test]
+fn map_zip_loop_without_optimizations() {
+    #[allow(dead_code)]
+    struct Args {
+        x: WeldVec<i32>,
+        y: WeldVec<i32>,
+    }
+
+    let code = "|x:vec[i32], y:vec[i32]| map(zip(x,y), |e| e.$0 * e.$1)";
+    let conf = &mut default_conf();
+    conf.set("weld.optimization.passes", "");
+
+    let x = vec![1, 2, 3, 4];
+    let y = vec![5, 6, 7, 8];
+    let input_data = &Args {
+        x: WeldVec::from(&x),
+        y: WeldVec::from(&y),
+    };
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<i32>;
+    let result = unsafe { (*data).clone() };
+
+    let output = [5, 12, 21, 32];
+    assert_eq!(result.len as usize, output.len());
+    for i in 0..(result.len as isize) {
+        assert_eq!(unsafe { *result.data.offset(i) }, output[i as usize])
+    }
+}
+
 #[test]
 fn iterate_non_parallel() {
     let code = "|x:i32| iterate(x, |x| {x-1, x-1>0})";
@@ -422,6 +529,97 @@ fn iterate_with_parallel_body() {
     }
 }
 
+#[test]
+fn named_struct_fields() {
+    // Named and positional accesses to the same struct can be mixed.
+    let code = "|v:vec[{price:f64,qty:i64}]|
+        {result(for(v, merger[f64,+], |b,i,e| merge(b, e.price * f64(e.qty)))), len(v)}:{total:f64,count:i64}";
+    let conf = &default_conf();
+
+    let module = WeldModule::compile(code, conf).unwrap();
+    assert_eq!(
+        module.param_types()[0].to_string(),
+        "vec[{price:f64,qty:i64}]"
+    );
+    assert_eq!(module.return_type().to_string(), "{total:f64,count:i64}");
+
+    let input_vec = vec![Pair::new(1.5, 2i64), Pair::new(2.0, 3), Pair::new(0.5, 4)];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const Pair<f64, i64>;
+    let result = unsafe { (*data).clone() };
+    assert_eq!(result, Pair::new(11.0, 3));
+}
+
+#[test]
+fn builtin_macro_pipeline() {
+    // `reduce` folds in order, so a non-commutative function builds the digits left to right.
+    let code = "|x:vec[vec[i64]]|
+        let evens = filter(flatten(x), |e| e % 2L == 0L);
+        reduce(map(evens, |e| e / 2L), 0L, |acc, e| acc * 10L + e)";
+    let conf = &default_conf();
+
+    let inner: Vec<Vec<i64>> = vec![vec![1, 2, 3], vec![], vec![4, 5, 6]];
+    let inner: Vec<_> = inner.iter().map(WeldVec::from).collect();
+    let input_data = &WeldVec::from(&inner);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i64;
+    let result = unsafe { *data };
+    assert_eq!(result, 123);
+}
+
+#[test]
+fn named_functions() {
+    let code = "let f = |x:i32| x + 1;
+                let g = |y:i32| f(y) * 2;
+                |a:i32| g(a) + f(a)";
+    let conf = &default_conf();
+
+    let input: i32 = 3;
+
+    let ret_value = compile_and_run(code, conf, &input);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+
+    assert_eq!(result, 12);
+}
+
+#[test]
+fn generic_functions() {
+    let code = "let square = |x:?| x * x;
+                |a:i64, b:f64| f64(square(a)) + square(b)";
+    let conf = &default_conf();
+
+    #[allow(dead_code)]
+    struct Args {
+        a: i64,
+        b: f64,
+    }
+    let input = Args { a: 3, b: 0.5 };
+
+    let ret_value = compile_and_run(code, conf, &input);
+    let data = ret_value.data() as *const f64;
+    let result = unsafe { *data };
+
+    assert_eq!(result, 9.25);
+}
+
+#[test]
+fn integer_literals_take_context_type() {
+    let code = "|x:i64| if(x > 3, x * 2, x + 1)";
+    let conf = &default_conf();
+
+    let input: i64 = 4_000_000_000;
+
+    let ret_value = compile_and_run(code, conf, &input);
+    let data = ret_value.data() as *const i64;
+    let result = unsafe { *data };
+
+    assert_eq!(result, 8_000_000_000);
+}
+
 #[test]
 fn serial_parlib_test() {
     let code = "|x:vec[i32]| result(for(x, merger[i32,+], |b,i,e| merge(b, e)))";
@@ -45,6 +45,21 @@ fn string_cmp() {
     assert!(result == false);
 }
 
+#[test]
+fn escaped_string_cmp() {
+    let code = r#"|| "a\"b\n" == "a\"b\n" && lookup("a\"b\n", 1L) == 34c"#;
+
+    let conf = &default_conf();
+
+    let input_data: &f64 = &0.0;
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const bool;
+    let result = unsafe { *data };
+
+    assert!(result);
+}
+
 #[test]
 fn vector_cmp() {
     #[allow(dead_code)]
@@ -192,6 +192,106 @@ fn nested_for_loops() {
     assert_eq!(row.y, 4);
 }
 
+#[test]
+fn parallel_nested_for_loops() {
+    // The first row is much longer than the others, so its inner loop can continue on the threads
+    // that finish the other rows.
+    let code = "|rows:vec[vec[i64]]| map(rows, |row| result(
+        for(row, merger[i64,+], |b,i,e| merge(b, e * 2L))
+    ))";
+    let conf = &many_threads_conf();
+
+    let mut rows: Vec<Vec<i64>> = vec![(0..200000).collect()];
+    rows.extend((0..1000).map(|i| (0..i % 100).collect::<Vec<i64>>()));
+    let row_vecs: Vec<WeldVec<i64>> = rows.iter().map(WeldVec::from).collect();
+    let input_data = &WeldVec::from(&row_vecs);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<i64>;
+    let result = unsafe { (*data).clone() };
+
+    assert_eq!(result.len as usize, rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        assert_eq!(unsafe { *result.data.add(i) }, row.iter().sum::<i64>() * 2);
+    }
+}
+
+#[test]
+fn index_after_filter_loop() {
+    // The second loop's indices are positions in the filtered vector, so the loops cannot be
+    // fused.
+    let code = "|v:vec[i32]|
+        let filtered = result(for(v, appender[i32], |b,i,e| if(e > 2, merge(b, e), b)));
+        result(for(filtered, appender[i64], |b,i,e| merge(b, i)))";
+    let conf = &default_conf();
+
+    let input_vec = vec![1, 3, 2, 5];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<i64>;
+    let result = unsafe { (*data).clone() };
+
+    let output = [0i64, 1];
+    assert_eq!(result.len, output.len() as i64);
+    for i in 0..(result.len as isize) {
+        assert_eq!(unsafe { *result.data.offset(i) }, output[i as usize])
+    }
+}
+
+#[test]
+fn loop_invariant_expression() {
+    // `len(v)` does not depend on the loop parameters, so it is computed once before the loop.
+    let code = "|v:vec[i32]| result(for(v, appender[i64], |b,i,e| merge(b, i + len(v))))";
+    let conf = &default_conf();
+
+    let input_vec = vec![1, 3, 2, 5];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<i64>;
+    let result = unsafe { (*data).clone() };
+
+    let output = [4i64, 5, 6, 7];
+    assert_eq!(result.len, output.len() as i64);
+    for i in 0..(result.len as isize) {
+        assert_eq!(unsafe { *result.data.offset(i) }, output[i as usize])
+    }
+}
+
+#[test]
+fn unused_builder_in_loop() {
+    // Only the sum is read, so the appender is removed from the loop.
+    let code = "|v:vec[i32]|
+        let r = for(v, {appender[i32], merger[i32,+]}, |b,i,e| {merge(b.$0, e), merge(b.$1, e)});
+        result(r.$1)";
+    let conf = &default_conf();
+
+    let input_vec = vec![1, 3, 2, 5];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+    assert_eq!(result, 11);
+}
+
+#[test]
+fn reduction_of_appender_with_length() {
+    let code = "|v:vec[i32]|
+        let r = result(for(v, appender[i32], |b,i,e| merge(b, e * 3)));
+        result(for(r, merger[i32,+], |b,i,e| merge(b, e))) / i32(len(r))";
+    let conf = &default_conf();
+
+    let input_vec = vec![1, 3, 2, 6];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+    assert_eq!(result, 9);
+}
+
 #[test]
 fn appender_and_dictmerger_loop() {
     #[derive(Clone)]
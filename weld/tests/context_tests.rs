@@ -145,3 +145,67 @@ fn reuse_groupmerger_test() {
     res.sort_by_key(|a| a.0);
     assert_eq!(res, expect);
 }
+
+#[test]
+fn reset_context_reuses_memory() {
+    let code = "|v: vec[i32]| map(v, |e| e + 1)";
+    let conf = &mut default_conf();
+    let module = WeldModule::compile(code, conf).unwrap();
+
+    let input_vec: Vec<i32> = (0..1000).collect();
+    let input_data = WeldVec::from(&input_vec);
+    let input_value = &WeldValue::new_from_data(&input_data as *const _ as Data);
+
+    // The limit fits the result of one run, but not two.
+    conf.set("weld.memory.limit", "6000");
+    let context = &mut WeldContext::new(conf).unwrap();
+
+    let mut buffers = vec![];
+    for _ in 0..3 {
+        let ret_value = unsafe { module.run(context, input_value).unwrap() };
+        let data = ret_value.data() as *const WeldVec<i32>;
+        let result = unsafe { (*data).clone() };
+        assert_eq!(result.len, 1000);
+        assert_eq!(unsafe { *result.data.offset(999) }, 1000);
+        assert!(context.memory_usage() >= 4000);
+        buffers.push(result.data);
+
+        unsafe { context.reset() };
+        assert_eq!(context.memory_usage(), 0);
+    }
+    // Each run reuses the buffer of the previous result.
+    assert!(buffers.iter().all(|b| *b == buffers[0]));
+
+    // Without a reset, the second run runs out of memory.
+    let _ret_value = unsafe { module.run(context, input_value).unwrap() };
+    let err = unsafe { module.run(context, input_value).unwrap_err() };
+    assert_eq!(err.code(), WeldRuntimeErrno::OutOfMemory);
+}
+
+#[test]
+fn pinned_threads_with_numa_local_memory() {
+    let code = "|v: vec[i64]| {
+        map(v, |e| e * 2L),
+        result(for(v, merger[i64,+], |b,i,e| merge(b, e)))
+    }";
+    let conf = &mut default_conf();
+    conf.set("weld.threads", "4");
+    conf.set("weld.threads.pinning", "true");
+    conf.set("weld.memory.numaLocal", "true");
+    let module = WeldModule::compile(code, conf).unwrap();
+    let context = &mut WeldContext::new(conf).unwrap();
+
+    let input_vec: Vec<i64> = (0..100000).collect();
+    let input_data = WeldVec::from(&input_vec);
+    let input_value = &WeldValue::new_from_data(&input_data as *const _ as Data);
+
+    for _ in 0..2 {
+        let ret_value = unsafe { module.run(context, input_value).unwrap() };
+        let data = ret_value.data() as *const Pair<WeldVec<i64>, i64>;
+        let result = unsafe { (*data).clone() };
+        assert_eq!(result.ele1.len, 100000);
+        assert_eq!(unsafe { *result.ele1.data.offset(99999) }, 199998);
+        assert_eq!(result.ele2, input_vec.iter().sum::<i64>());
+        unsafe { context.reset() };
+    }
+}
@@ -5,6 +5,8 @@ use fnv;
 
 use std::collections::hash_map::Entry;
 
+use weld::runtime::WeldRuntimeErrno;
+
 mod common;
 use crate::common::*;
 
@@ -49,6 +51,117 @@ fn simple_for_dictmerger_loop() {
     }
 }
 
+#[test]
+fn dictmerger_with_capacity() {
+    // The capacity is not a power-of-2, and is smaller than the number of keys.
+    let code = "|x:vec[i32], y:vec[i32]| tovec(result(for(zip(x,y), dictmerger[i32,i32,+](100L),
+                |b,i,e| merge(b, e))))";
+    let conf = &default_conf();
+    let keys: Vec<i32> = (0..1000).map(|i| i % 200).collect();
+    let vals = vec![1; keys.len()];
+    let input_data = &I32KeyValArgs {
+        x: WeldVec::from(&keys),
+        y: WeldVec::from(&vals),
+    };
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<Pair<i32, i32>>;
+    let result = unsafe { (*data).clone() };
+
+    assert_eq!(result.len, 200);
+    for i in 0..(result.len as isize) {
+        let value = unsafe { (*result.data.offset(i)).ele2 };
+        assert_eq!(value, 5);
+    }
+}
+
+#[test]
+fn parallel_dictmerger_tables() {
+    let keys: Vec<i32> = (0..10000).map(|i| (i * 7) % 100).collect();
+    let vals: Vec<i32> = (0..10000).map(|i| i % 13).collect();
+    let input_data = &I32KeyValArgs {
+        x: WeldVec::from(&keys),
+        y: WeldVec::from(&vals),
+    };
+    let mut expected = vec![(0, 0); 100];
+    for (key, val) in keys.iter().zip(vals.iter()) {
+        let entry = &mut expected[*key as usize];
+        entry.0 += i64::from(*val);
+        entry.1 += 1;
+    }
+
+    // Threads either build local tables that are merged when the loop finishes, or update a
+    // single shared table.
+    for choice in ["local", "shared"].iter() {
+        let code = format!(
+            "|x:vec[i32], y:vec[i32]| sortedvec(result(for(zip(x,y),
+                @(impl:{}) dictmerger[i32,{{i64,i32}},+],
+                |b,i,e| merge(b, {{e.$0, {{i64(e.$1), 1}}}}))))",
+            choice
+        );
+        let conf = &mut many_threads_conf();
+        conf.set("weld.loop.grain_size", "100");
+
+        let ret_value = compile_and_run(&code, conf, input_data);
+        let data = ret_value.data() as *const WeldVec<Pair<i32, Pair<i64, i32>>>;
+        let result = unsafe { (*data).clone() };
+
+        assert_eq!(result.len, 100);
+        for i in 0..(result.len as isize) {
+            let pair = unsafe { &*result.data.offset(i) };
+            assert_eq!(pair.ele1, i as i32);
+            assert_eq!(pair.ele2.ele1, expected[i as usize].0);
+            assert_eq!(pair.ele2.ele2, expected[i as usize].1);
+        }
+    }
+}
+
+#[test]
+fn dictmerger_capacity_too_large() {
+    #[allow(dead_code)]
+    struct Args {
+        x: WeldVec<i32>,
+        capacity: i64,
+    }
+
+    // The capacity cannot be rounded up to a power-of-2.
+    let code = "|x:vec[i32], c:i64| tovec(result(for(x, dictmerger[i32,i32,+](c),
+                |b,i,e| merge(b, {e, 1}))))";
+    let conf = &default_conf();
+    let keys = vec![1, 2, 3];
+    let input_data = &Args {
+        x: WeldVec::from(&keys),
+        capacity: i64::MAX,
+    };
+
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::OutOfMemory);
+}
+
+#[test]
+fn sortedvec_dictmerger_loop() {
+    let code = "|x:vec[i32], y:vec[i32]| sortedvec(result(for(zip(x,y), dictmerger[i32,i32,+],
+                |b,i,e| merge(b, e))))";
+    let conf = &default_conf();
+    let keys: Vec<i32> = (0..1000).map(|i| (i * 7) % 100).collect();
+    let vals = vec![1; keys.len()];
+    let input_data = &I32KeyValArgs {
+        x: WeldVec::from(&keys),
+        y: WeldVec::from(&vals),
+    };
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<Pair<i32, i32>>;
+    let result = unsafe { (*data).clone() };
+
+    assert_eq!(result.len, 100);
+    for i in 0..(result.len as isize) {
+        let entry = unsafe { (*result.data.offset(i)).clone() };
+        assert_eq!(entry.ele1, i as i32);
+        assert_eq!(entry.ele2, 10);
+    }
+}
+
 /// Similar case to parallel_for_vecmerger_loop but values and keys are structs
 #[test]
 fn dictmerger_with_structs() {
@@ -1,12 +1,12 @@
-//! Tests for runtime errors that Weld can throw.
+//! Tests for compilation and runtime errors that Weld can throw.
 
 use weld::runtime::WeldRuntimeErrno;
+use weld::{Data, WeldContext, WeldModule, WeldValue};
 
 mod common;
 use crate::common::*;
 
 #[test]
-#[should_panic] // XXX The new runtime throws panics for these currently.
 fn iters_outofbounds_error_test() {
     let code = "|x:vec[i32]| result(for(iter(x,0L,20000L,1L), merger[i32,+], |b,i,e| merge(b,e)))";
     let ref mut conf = many_threads_conf();
@@ -17,12 +17,41 @@ fn iters_outofbounds_error_test() {
     let input_vec = vec![4; 1000 as usize];
     let ref input_data = WeldVec::from(&input_vec);
 
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::BadIteratorLength);
+}
+
+#[test]
+fn lookup_outofbounds_error_test() {
+    let code = "|x:vec[i32]| lookup(x, 1000L)";
+    let conf = &mut default_conf();
+    conf.set("weld.compile.enableBoundsChecks", "true");
+
+    let input_vec = vec![4; 1000];
+    let input_data = &WeldVec::from(&input_vec);
+
     let err_value = compile_and_run_error(code, conf, input_data);
     assert_eq!(err_value.code(), WeldRuntimeErrno::ArrayOutOfBounds);
+    let span = err_value.span().unwrap();
+    assert_eq!((span.line, span.column), (1, 14));
+}
+
+#[test]
+fn slice_outofbounds_with_bounds_checks() {
+    // Slices are truncated to the vector, and are empty past its end, even with bounds checks.
+    let code = "|x:vec[i32]| len(slice(x, 1L, 5L)) + len(slice(x, 4L, 1L))";
+    let conf = &mut default_conf();
+    conf.set("weld.compile.enableBoundsChecks", "true");
+
+    let input_vec = vec![4; 3];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let result = unsafe { *(ret_value.data() as *const i64) };
+    assert_eq!(result, 2);
 }
 
 #[test]
-#[should_panic] // XXX The new runtime throws panics for these currently.
 fn outofmemory_error_test() {
     let code = "|x:vec[i32]| result(for(x, vecmerger[i32,+](x), |b,i,e| merge(b,{i,e+1})))";
     let ref mut conf = default_conf();
@@ -37,6 +66,30 @@ fn outofmemory_error_test() {
     assert_eq!(err_value.code(), WeldRuntimeErrno::OutOfMemory);
 }
 
+#[test]
+fn outofmemory_error_keeps_context_usable() {
+    let code = "|x:vec[i32]| result(for(x, appender[i64], |b,i,e| merge(b, i64(e))))";
+    let conf = &mut many_threads_conf();
+    conf.set("weld.memory.limit", "50000");
+    let module = WeldModule::compile(code, conf).unwrap();
+    let context = &mut WeldContext::new(conf).unwrap();
+
+    let large = vec![4; 50000 / 4];
+    let large = WeldVec::from(&large);
+    let input = &WeldValue::new_from_data(&large as *const _ as Data);
+    let err_value = unsafe { module.run(context, input).unwrap_err() };
+    assert_eq!(err_value.code(), WeldRuntimeErrno::OutOfMemory);
+    assert!(context.memory_usage() <= 50000);
+
+    // Later runs in the same context succeed if they fit in the remaining memory.
+    let small = vec![4; 10];
+    let small = WeldVec::from(&small);
+    let input = &WeldValue::new_from_data(&small as *const _ as Data);
+    let ret_value = unsafe { module.run(context, input).unwrap() };
+    let result = unsafe { (*(ret_value.data() as *const WeldVec<i64>)).clone() };
+    assert_eq!(result.len, 10);
+}
+
 #[test]
 fn assert_pass_test() {
     let code = "|x: i32| assert(x == 0)";
@@ -51,8 +104,6 @@ fn assert_pass_test() {
 }
 
 #[test]
-#[should_panic]
-// XXX The new runtime throws panics for these currently.
 fn assert_fail_test() {
     let code = "|x: i32| assert(x == 1)";
     let ref mut conf = default_conf();
@@ -62,3 +113,100 @@ fn assert_fail_test() {
     let err_value = compile_and_run_error(code, conf, input_data);
     assert_eq!(err_value.code(), WeldRuntimeErrno::AssertionError);
 }
+
+#[test]
+fn compile_error_codes_and_spans() {
+    let conf = &default_conf();
+
+    let err = WeldModule::compile("|x:i32|\n  x x", conf).unwrap_err();
+    assert_eq!(err.code(), WeldRuntimeErrno::ParseError);
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.column), (2, 5));
+
+    let err = WeldModule::compile("|x:i32| let y = 1L;\n  x + y", conf).unwrap_err();
+    assert_eq!(err.code(), WeldRuntimeErrno::TypeError);
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.column), (2, 3));
+
+    // Errors after type inference are general compile errors without a span.
+    let err = WeldModule::compile(
+        "|x:i32| let b = appender[i32]; {result(b), result(b)}",
+        conf,
+    )
+    .unwrap_err();
+    assert_eq!(err.code(), WeldRuntimeErrno::CompileError);
+    assert!(err.span().is_none());
+}
+
+#[test]
+fn checked_arithmetic_overflow_error() {
+    let code = "|x:vec[i64]| result(for(x, merger[i64,+], |b,i,e| merge(b, e * 2L)))";
+    let conf = &mut default_conf();
+
+    let input_vec: Vec<i64> = vec![1, 2, i64::MAX / 2 + 1];
+    let input_data = &WeldVec::from(&input_vec);
+
+    // Overflow wraps by default.
+    let ret_value = compile_and_run(code, conf, input_data);
+    let result = unsafe { *(ret_value.data() as *const i64) };
+    assert_eq!(result, i64::MIN + 6);
+
+    conf.set("weld.compile.checkedArithmetic", "true");
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::IntegerOverflow);
+    let span = err_value.span().unwrap();
+    assert_eq!((span.line, span.column), (1, 60));
+
+    let input_vec: Vec<i64> = vec![1, 2, 3];
+    let input_data = &WeldVec::from(&input_vec);
+    let ret_value = compile_and_run(code, conf, input_data);
+    let result = unsafe { *(ret_value.data() as *const i64) };
+    assert_eq!(result, 12);
+
+    // The minimum value divided by -1 also overflows.
+    let code = "|x:i32, y:i32| x / y";
+    let input_data = &Pair::new(i32::MIN, -1);
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::IntegerOverflow);
+
+    let input_data = &Pair::new(1, 0);
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::DivideByZero);
+    let code = "|x:u32, y:u32| x % y";
+    let input_data = &Pair::new(1u32, 0u32);
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::DivideByZero);
+}
+
+#[test]
+fn checked_arithmetic_merger_overflow_error() {
+    let code = "|x:vec[i64]| result(for(x, merger[i64,+], |b,i,e| merge(b, e)))";
+    let conf = &mut default_conf();
+    conf.set("weld.compile.checkedArithmetic", "true");
+
+    let input_vec: Vec<i64> = vec![i64::MAX, 1];
+    let input_data = &WeldVec::from(&input_vec);
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::IntegerOverflow);
+
+    // Each thread's sum fits, but merging them overflows.
+    conf.set("weld.threads", "2");
+    conf.set("weld.loop.grain_size", "1");
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::IntegerOverflow);
+
+    let input_vec: Vec<i64> = vec![i64::MAX - 1, 1];
+    let input_data = &WeldVec::from(&input_vec);
+    let ret_value = compile_and_run(code, conf, input_data);
+    let result = unsafe { *(ret_value.data() as *const i64) };
+    assert_eq!(result, i64::MAX);
+
+    // Vectorized merges are checked when the lanes are folded into the result.
+    let code = "|x:vec[i32]| result(for(x, merger[i32,*], |b,i,e| merge(b, e)))";
+    let conf = &mut default_conf();
+    conf.set("weld.compile.checkedArithmetic", "true");
+    let input_vec: Vec<i32> = vec![1 << 10; 16];
+    let input_data = &WeldVec::from(&input_vec);
+    let err_value = compile_and_run_error(code, conf, input_data);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::IntegerOverflow);
+}
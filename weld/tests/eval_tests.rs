@@ -0,0 +1,70 @@
+//! Tests that compare the interpreter with compiled programs.
+
+use weld::ast::{parse, Eval, InferTypes, Value};
+
+mod common;
+use crate::common::*;
+
+/// Checks that the interpreter and the compiled program compute the same `i64` from `input`.
+fn check_same_result(code: &str, input: &[i32]) {
+    let conf = &default_conf();
+    let input_vec = input.to_vec();
+    let input_data = &WeldVec::from(&input_vec);
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i64;
+    let compiled = unsafe { *data };
+
+    let mut expr = parse(code).unwrap();
+    expr.infer_types().unwrap();
+    let arg = Value::vector(input.iter().cloned().map(Value::I32).collect());
+    let interpreted = expr.eval(&[arg]).unwrap();
+    assert_eq!(interpreted, Value::I64(compiled), "{}", code);
+}
+
+/// Checks that the interpreter and the compiled program fail with the same error code on `input`.
+fn check_same_error(code: &str, input: &[i32]) {
+    let conf = &mut default_conf();
+    conf.set("weld.compile.enableBoundsChecks", "true");
+    let input_vec = input.to_vec();
+    let input_data = &WeldVec::from(&input_vec);
+    let compiled = compile_and_run_error(code, conf, input_data);
+
+    let mut expr = parse(code).unwrap();
+    expr.infer_types().unwrap();
+    let arg = Value::vector(input.iter().cloned().map(Value::I32).collect());
+    let interpreted = expr.eval(&[arg]).unwrap_err();
+    assert_eq!(interpreted.code(), compiled.code(), "{}", code);
+}
+
+#[test]
+fn interpreter_matches_compiled_code() {
+    let input: Vec<i32> = (-500..500).map(|i| i * 7 % 101).collect();
+    let codes = [
+        "|v:vec[i32]| result(for(v, merger[i64,+], |b,i,e| merge(b, i64(e) * i64(e))))",
+        "|v:vec[i32]| let f = filter(v, |e| e % 3 == 0); len(f) * 1000L + i64(lookup(f, 5L))",
+        "|v:vec[i32]| result(for(zip(v, map(v, |e| e >> 2)), merger[i64,max],
+            |b,i,e| merge(b, i64(e.$0 ^ e.$1) - i)))",
+        "|v:vec[i32]| iterate({0L, 0L}, |s| {{s.$0 + 1L, s.$1 + i64(lookup(v, s.$0))},
+            s.$0 < 99L}).$1",
+        "|v:vec[i32]| result(for(rangeiter(1L, len(v), 7L), merger[i64,*],
+            |b,i,e| merge(b, if(lookup(v, e) > 50, 3L, 1L))))",
+    ];
+    for code in codes.iter() {
+        check_same_result(code, &input);
+    }
+}
+
+#[test]
+fn interpreter_errors_match_compiled_code() {
+    let input: Vec<i32> = (0..100).collect();
+    let codes = [
+        "|v:vec[i32]| lookup(v, 1000L)",
+        "|v:vec[i32]| result(for(iter(v, 0L, 200L, 1L), merger[i32,+], |b,i,e| merge(b, e)))",
+        "|v:vec[i32]| result(for(zip(v, slice(v, 0L, 50L)), merger[i32,+],
+            |b,i,e| merge(b, e.$0 + e.$1)))",
+        "|v:vec[i32]| assert(len(v) == 0L)",
+    ];
+    for code in codes.iter() {
+        check_same_error(code, &input);
+    }
+}
@@ -0,0 +1,86 @@
+//! Tests for the `nvvm` backend.
+//!
+//! These tests pass whether or not the machine has a GPU, since loops run on the CPU if the GPU
+//! is not available.
+
+use weld::WeldConf;
+
+mod common;
+use crate::common::*;
+
+fn nvvm_conf() -> WeldConf {
+    let mut conf = default_conf();
+    conf.set("weld.compile.backend", "nvvm");
+    conf
+}
+
+#[test]
+fn gpu_map_loop() {
+    #[allow(dead_code)]
+    struct Args {
+        x: WeldVec<i64>,
+        k: i64,
+    }
+
+    let code = "|x:vec[i64], k:i64| map(x, |e| if(e > 2L, e * k, 0L))";
+    let conf = &nvvm_conf();
+
+    let input_vec: Vec<i64> = (0..10000).collect();
+    let input_data = &Args {
+        x: WeldVec::from(&input_vec),
+        k: 3,
+    };
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<i64>;
+    let result = unsafe { (*data).clone() };
+    assert_eq!(result.len as usize, input_vec.len());
+    for (i, e) in input_vec.iter().enumerate() {
+        let expected = if *e > 2 { e * 3 } else { 0 };
+        assert_eq!(unsafe { *result.data.add(i) }, expected);
+    }
+}
+
+#[test]
+fn gpu_reduce_loops() {
+    let code = "|x:vec[f64]| {
+        result(for(x, merger[f64,+], |b,i,e| merge(b, sqrt(e)))),
+        result(for(x, merger[f64,max], |b,i,e| merge(b, -e)))
+    }";
+    let conf = &mut nvvm_conf();
+    conf.set("weld.optimization.fastMath", "true");
+
+    let input_vec: Vec<f64> = vec![4.0; 100000];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const Pair<f64, f64>;
+    let result = unsafe { (*data).clone() };
+    assert_eq!(result.ele1, 200000.0);
+    assert_eq!(result.ele2, -4.0);
+}
+
+#[test]
+fn gpu_ineligible_loop() {
+    #[allow(dead_code)]
+    struct Args {
+        x: WeldVec<i32>,
+        y: WeldVec<i32>,
+    }
+
+    // Lookups cannot run on the GPU, so this loop always runs on the CPU.
+    let code = "|x:vec[i32], y:vec[i32]| result(for(x, merger[i32,+], |b,i,e| merge(b, e * lookup(y, i))))";
+    let conf = &mut nvvm_conf();
+    conf.set("weld.threads", "4");
+
+    let input_vec: Vec<i32> = (0..1000).collect();
+    let input_data = &Args {
+        x: WeldVec::from(&input_vec),
+        y: WeldVec::from(&input_vec),
+    };
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+    assert_eq!(result, input_vec.iter().map(|e| e * e).sum::<i32>());
+}
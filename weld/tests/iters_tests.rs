@@ -1,5 +1,10 @@
 //! Tests for the various For loop iterators.
 
+use std::mem;
+use std::slice;
+
+use weld::*;
+
 mod common;
 use crate::common::*;
 
@@ -25,6 +30,40 @@ fn range_iter_1() {
     assert_eq!(result, output);
 }
 
+#[test]
+fn range_iter_partial_stride() {
+    // The range is not a multiple of the stride, so the last element is 9.
+    let code = "|a: i64, n: i64| result(for(rangeiter(a, n, 3L), appender[i64], |b,i,e| merge(b, e)))";
+
+    #[allow(dead_code)]
+    struct Args {
+        a: i64,
+        n: i64,
+    }
+
+    for &bounds_checks in [false, true].iter() {
+        let conf = &mut default_conf();
+        conf.set("weld.compile.enableBoundsChecks", bounds_checks.to_string());
+
+        let input_data = &Args { a: 0, n: 10 };
+        let ret_value = compile_and_run(code, conf, input_data);
+        let data = ret_value.data() as *const WeldVec<i64>;
+        let result = unsafe { (*data).clone() };
+        let output = [0, 3, 6, 9];
+        assert_eq!(result.len as usize, output.len());
+        for (i, expected) in output.iter().enumerate() {
+            assert_eq!(unsafe { *result.data.add(i) }, *expected);
+        }
+
+        // Empty ranges produce no elements.
+        let input_data = &Args { a: 10, n: 10 };
+        let ret_value = compile_and_run(code, conf, input_data);
+        let data = ret_value.data() as *const WeldVec<i64>;
+        let result = unsafe { (*data).clone() };
+        assert_eq!(result.len, 0);
+    }
+}
+
 fn range_iter_zipped_helper(parallel: bool) {
     let grain_size = if parallel { 100 } else { 4096 };
     let ref conf = if parallel {
@@ -64,6 +103,43 @@ fn range_iter_parallel() {
     range_iter_zipped_helper(true)
 }
 
+#[test]
+fn nd_iter_transpose() {
+    // Reads a row-major 2x3 matrix in column-major order, i.e., its transpose.
+    let code = "|x: vec[i32], shape: vec[i64], strides: vec[i64]|
+                result(for(nditer(x, 0L, 6L, 1L, shape, strides), appender, |b,i,e| merge(b, e)))";
+
+    #[allow(dead_code)]
+    struct Args {
+        x: WeldVec<i32>,
+        shape: WeldVec<i64>,
+        strides: WeldVec<i64>,
+    }
+
+    let x = [1, 2, 3, 4, 5, 6];
+    let shape = [3, 2];
+    let strides = [1, 3];
+
+    for &bounds_checks in [false, true].iter() {
+        let conf = &mut default_conf();
+        conf.set("weld.compile.enableBoundsChecks", bounds_checks.to_string());
+
+        let input_data = &Args {
+            x: WeldVec::from(&x),
+            shape: WeldVec::from(&shape),
+            strides: WeldVec::from(&strides),
+        };
+        let ret_value = compile_and_run(code, conf, input_data);
+        let data = ret_value.data() as *const WeldVec<i32>;
+        let result = unsafe { (*data).clone() };
+        let output = [1, 4, 2, 5, 3, 6];
+        assert_eq!(result.len as usize, output.len());
+        for (i, expected) in output.iter().enumerate() {
+            assert_eq!(unsafe { *result.data.add(i) }, *expected);
+        }
+    }
+}
+
 #[test]
 fn iters_for_loop() {
     #[allow(dead_code)]
@@ -92,3 +168,73 @@ fn iters_for_loop() {
         assert_eq!(unsafe { *result.data.offset(i) }, output[i as usize])
     }
 }
+
+/// Provides chunks of `i64` values in order.
+struct I64Chunks {
+    chunks: Vec<Vec<i64>>,
+    next: usize,
+}
+
+impl ChunkSource for I64Chunks {
+    fn next_chunk(&mut self) -> Option<&[u8]> {
+        let chunk = self.chunks.get(self.next)?;
+        self.next += 1;
+        let bytes = chunk.len() * mem::size_of::<i64>();
+        Some(unsafe { slice::from_raw_parts(chunk.as_ptr() as *const u8, bytes) })
+    }
+}
+
+#[test]
+fn chunk_iter_loads_chunks_on_demand() {
+    // The loop index continues across chunks, and empty chunks are skipped.
+    let code =
+        "|s: i64| result(for(chunkiter[i64](s), appender[i64], |b,i,e| merge(b, e * 100L + i)))";
+    let conf = &many_threads_conf();
+    let module = WeldModule::compile(code, conf).unwrap();
+
+    let context = &mut WeldContext::new(conf).unwrap();
+    let source = context.add_source(Box::new(I64Chunks {
+        chunks: vec![vec![1, 2], vec![], vec![3], vec![4, 5, 6]],
+        next: 0,
+    }));
+    let input = &WeldValue::new_from_data(&source as *const i64 as Data);
+    let ret_value = unsafe { module.run(context, input).unwrap() };
+    let data = ret_value.data() as *const WeldVec<i64>;
+    let result = unsafe { (*data).clone() };
+
+    let output = [100, 201, 302, 403, 504, 605];
+    assert_eq!(result.len as usize, output.len());
+    for (i, expected) in output.iter().enumerate() {
+        assert_eq!(unsafe { *result.data.add(i) }, *expected);
+    }
+
+    // The source is exhausted, so running again reads nothing.
+    let ret_value = unsafe { module.run(context, input).unwrap() };
+    let data = ret_value.data() as *const WeldVec<i64>;
+    assert_eq!(unsafe { (*data).len }, 0);
+}
+
+#[test]
+fn chunk_iter_errors() {
+    let code = "|s: i64| result(for(chunkiter[i64](s), merger[i64,+], |b,i,e| merge(b, e)))";
+    let conf = &default_conf();
+    let module = WeldModule::compile(code, conf).unwrap();
+    let context = &mut WeldContext::new(conf).unwrap();
+
+    // The handle does not refer to a source.
+    let input = &WeldValue::new_from_data(&3i64 as *const i64 as Data);
+    let err = unsafe { module.run(context, input).unwrap_err() };
+    assert_eq!(err.code(), WeldRuntimeErrno::ChunkSourceError);
+
+    // The chunk does not hold a whole number of elements.
+    struct Bytes(Vec<u8>);
+    impl ChunkSource for Bytes {
+        fn next_chunk(&mut self) -> Option<&[u8]> {
+            Some(&self.0)
+        }
+    }
+    let source = context.add_source(Box::new(Bytes(vec![0; 12])));
+    let input = &WeldValue::new_from_data(&source as *const i64 as Data);
+    let err = unsafe { module.run(context, input).unwrap_err() };
+    assert_eq!(err.code(), WeldRuntimeErrno::ChunkSourceError);
+}
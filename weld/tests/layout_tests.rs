@@ -0,0 +1,43 @@
+//! Tests for passing values to programs with the layout helpers.
+
+use weld::ast::{FieldNames, Type, Value};
+use weld::runtime::layout::*;
+use weld::*;
+
+#[test]
+fn padded_struct_arguments_and_result() {
+    let code = "|a:i8, v:vec[{bool,i64,i16}], b:f32|
+        let kept = filter(v, |e| e.$0);
+        {a, map(kept, |e| {e.$2, e.$1 + i64(a)}), b * 2.0f}";
+    let conf = &WeldConf::new();
+    let module = WeldModule::compile(code, conf).unwrap();
+
+    let elem = |keep, x, y| Value::Struct(vec![Value::Bool(keep), Value::I64(x), Value::I16(y)]);
+    let args = Value::Struct(vec![
+        Value::I8(3),
+        Value::vector(vec![
+            elem(true, 10, 1),
+            elem(false, 20, 2),
+            elem(true, 30, 3),
+        ]),
+        Value::F32(1.5),
+    ]);
+    let buffer = ValueBuffer::new(
+        &args,
+        &Type::Struct(module.param_types(), FieldNames::none()),
+    )
+    .unwrap();
+
+    let context = &mut WeldContext::new(conf).unwrap();
+    let input = &WeldValue::new_from_data(buffer.data());
+    let result = unsafe { module.run(context, input).unwrap() };
+    let value = unsafe { read_value(result.data(), &module.return_type()).unwrap() };
+
+    let pair = |x, y| Value::Struct(vec![Value::I16(x), Value::I64(y)]);
+    let expect = Value::Struct(vec![
+        Value::I8(3),
+        Value::vector(vec![pair(1, 13), pair(3, 33)]),
+        Value::F32(3.0),
+    ]);
+    assert_eq!(value, expect);
+}
@@ -52,7 +52,7 @@ fn log_error() {
     let ref conf = default_conf();
     let input = 1;
     let err_value = compile_and_run_error(code, conf, &input);
-    assert_eq!(err_value.code(), WeldRuntimeErrno::CompileError);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::TypeError);
 }
 
 #[test]
@@ -73,7 +73,7 @@ fn exp_error() {
     let ref conf = default_conf();
     let input = 1;
     let err_value = compile_and_run_error(code, conf, &input);
-    assert_eq!(err_value.code(), WeldRuntimeErrno::CompileError);
+    assert_eq!(err_value.code(), WeldRuntimeErrno::TypeError);
 }
 
 #[test]
@@ -88,6 +88,19 @@ fn simple_erf() {
     assert!(output.close(result, 5));
 }
 
+#[test]
+fn simple_abs() {
+    let code = "|x:f64| abs(x)";
+    let conf = &default_conf();
+    let input = -4.5;
+    let ret_value = compile_and_run(code, conf, &input);
+    let data = ret_value.data() as *const f64;
+
+    let result = unsafe { *data };
+    let output = 4.5f64;
+    assert!(output.close(result, 5));
+}
+
 #[test]
 fn simple_sqrt() {
     let code = "|x:f64| sqrt(x)";
@@ -186,6 +199,43 @@ fn simple_int_mod() {
     assert_eq!(result, -1);
 }
 
+#[test]
+fn strength_reduced_int_ops() {
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    struct Output {
+        quotient: i32,
+        remainder: i32,
+        times7: i32,
+        times8: i32,
+        times9: i32,
+        shifted: i32,
+    }
+
+    // Division and modulo by powers of two are rewritten into shifts, which must still round
+    // towards zero for negative values.
+    let code = "|x:vec[i32]| map(x, |a| {a / 4, a % 4, a * 7, a * 8, a * 9, a >> 1})";
+    let conf = &default_conf();
+
+    let input_vec = vec![-9, -8, -1, 0, 7, 9];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<Output>;
+    let result = unsafe { (*data).clone() };
+
+    assert_eq!(result.len, input_vec.len() as i64);
+    for (i, a) in input_vec.iter().enumerate() {
+        let row = unsafe { (*result.data.add(i)).clone() };
+        assert_eq!(row.quotient, a / 4);
+        assert_eq!(row.remainder, a % 4);
+        assert_eq!(row.times7, a * 7);
+        assert_eq!(row.times8, a * 8);
+        assert_eq!(row.times9, a * 9);
+        assert_eq!(row.shifted, a >> 1);
+    }
+}
+
 #[test]
 fn simple_float_mod() {
     let code = "|x:f64| x % 0.04";
@@ -16,6 +16,20 @@ fn empty_merger_loop() {
     assert_eq!(result, 0);
 }
 
+#[test]
+fn vector_literal_merger_loop() {
+    // Loops over small vector literals are unrolled.
+    let code = "|x:i32| result(for([x, x + 1, 2], merger[i32,+], |b,i,e| merge(b, e * e)))";
+    let conf = &default_conf();
+
+    let input_data: &i32 = &3;
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+    assert_eq!(result, 29);
+}
+
 #[test]
 fn simple_for_merger_loop() {
     #[allow(dead_code)]
@@ -132,6 +146,176 @@ fn parallel_for_multi_merger_loop() {
     assert_eq!(result, output);
 }
 
+#[test]
+fn parallel_float_merger_loop_is_deterministic() {
+    // Floating point sums only run in parallel with fast math, so the result does not depend on
+    // the number of threads.
+    let code = "|x:vec[f64]| {result(for(x, merger[f64,+], |b,i,e| merge(b, e))),
+                result(for(x, merger[f64,max], |b,i,e| merge(b, e)))}";
+
+    #[derive(Clone, Debug, PartialEq)]
+    #[allow(dead_code)]
+    struct Output {
+        sum: f64,
+        max: f64,
+    }
+
+    let input_vec: Vec<f64> = (0..100000).map(|i| f64::from(i) * 0.1).collect();
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, &default_conf(), input_data);
+    let expected = unsafe { (*(ret_value.data() as *const Output)).clone() };
+    let ret_value = compile_and_run(code, &many_threads_conf(), input_data);
+    let result = unsafe { (*(ret_value.data() as *const Output)).clone() };
+    assert_eq!(result, expected);
+    assert_eq!(result.max, input_vec[input_vec.len() - 1]);
+}
+
+#[test]
+fn deterministic_fast_math_merger_loop() {
+    // With fast math, parallel sums are reproducible for any number of threads only if
+    // deterministic reduction is enabled.
+    let code = "|x:vec[f64]| {result(for(x, merger[f64,+], |b,i,e| merge(b, e))),
+                result(for(x, merger[f64,*], |b,i,e| merge(b, 1.0 + e / 1e9)))}";
+
+    #[derive(Clone, Copy, Debug)]
+    #[allow(dead_code)]
+    struct Output {
+        sum: f64,
+        product: f64,
+    }
+
+    let input_vec: Vec<f64> = (0..500000).map(|i| 1.0 / f64::from(i + 1)).collect();
+    let input_data = &WeldVec::from(&input_vec);
+
+    let results: Vec<_> = ["1", "2", "4", "8"]
+        .iter()
+        .map(|threads| {
+            let conf = &mut default_conf();
+            conf.set("weld.threads", *threads);
+            conf.set("weld.optimization.fastMath", "true");
+            conf.set("weld.optimization.deterministicReduction", "true");
+            let ret_value = compile_and_run(code, conf, input_data);
+            let result = unsafe { *(ret_value.data() as *const Output) };
+            (result.sum.to_bits(), result.product.to_bits())
+        })
+        .collect();
+    assert!(results.iter().all(|result| *result == results[0]));
+
+    let expected: f64 = input_vec.iter().sum();
+    let sum = f64::from_bits(results[0].0);
+    assert!((sum - expected).abs() < 1e-9);
+}
+
+#[test]
+fn struct_of_builders_merge() {
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    struct Output {
+        appended: WeldVec<i32>,
+        sum: i32,
+        max: i32,
+    }
+
+    // Merging a struct merges each field into the corresponding builder.
+    let code = "|x:vec[i32]| result(for(x, {appender[i32], {merger[i32,+], merger[i32,max]}},
+                |b,i,e| merge(b, {e * 2, {e, e}})))";
+    let conf = &default_conf();
+
+    let input_vec = [1, 5, 3, 2];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const Output;
+    let result = unsafe { (*data).clone() };
+    let output = [2, 10, 6, 4];
+    assert_eq!(result.appended.len as usize, output.len());
+    for (i, expected) in output.iter().enumerate() {
+        assert_eq!(unsafe { *result.appended.data.add(i) }, *expected);
+    }
+    assert_eq!(result.sum, 11);
+    assert_eq!(result.max, 5);
+}
+
+#[test]
+fn aggregates_over_same_vector() {
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct Output {
+        count: i64,
+        sum: i32,
+        min: i32,
+    }
+
+    // The three loops are fused into a single loop over a struct of mergers.
+    let code = "|x:vec[i32]|
+        let count = result(for(x, merger[i64,+], |b,i,e| merge(b, 1L)));
+        let sum = result(for(x, merger[i32,+], |b,i,e| merge(b, e)));
+        let low = result(for(x, merger[i32,min], |b,i,e| if(e > 1, merge(b, e), b)));
+        {count, sum, low}";
+    let conf = &default_conf();
+
+    let input_vec = [1, 5, 3, 2];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const Output;
+    let result = unsafe { (*data).clone() };
+    assert_eq!(result.count, 4);
+    assert_eq!(result.sum, 11);
+    assert_eq!(result.min, 2);
+}
+
+#[test]
+fn nullable_aggregations() {
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct Nullable {
+        valid: bool,
+        value: i64,
+    }
+
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct Output {
+        count: i64,
+        sum: Nullable,
+        min: Nullable,
+        empty_max: Nullable,
+        total: i64,
+    }
+
+    // NULLs are skipped by the aggregations, and the max over only NULLs is NULL.
+    let code = "|x:vec[{bool,i64}]|
+        let nulls = filter(x, |e| isnull(e));
+        let total = result(for(x, merger[i64,+], |b,i,e| merge(b, coalesce(nadd(e, some(1L)), 0L))));
+        {ncount(x), nsum(x), nmin(x), nmax(nulls), total}";
+    let conf = &default_conf();
+
+    let input_vec = [
+        Pair::new(true, 4i64),
+        Pair::new(false, -100),
+        Pair::new(true, 2),
+        Pair::new(false, -100),
+        Pair::new(true, 7),
+    ];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const Output;
+    let result = unsafe { (*data).clone() };
+    assert_eq!(result.count, 3);
+    assert!(result.sum.valid);
+    assert_eq!(result.sum.value, 13);
+    assert!(result.min.valid);
+    assert_eq!(result.min.value, 2);
+    assert!(!result.empty_max.valid);
+    assert_eq!(result.total, 16);
+}
+
 #[test]
 fn simple_for_merger_loop_initial_value() {
     #[allow(dead_code)]
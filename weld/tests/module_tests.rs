@@ -60,3 +60,207 @@ fn multithreaded_module_run() {
         t.join().unwrap();
     }
 }
+
+#[test]
+fn cached_module_run() {
+    let code = "|x:i32| x + 3";
+    let conf = &mut WeldConf::new();
+    let module = WeldModule::compile(code, conf).unwrap();
+
+    // A cached module runs like the original one.
+    let cached = WeldModule::compile("|x:i32|\n  # Add three.\n  x + 3", conf).unwrap();
+    assert_eq!(module.id(), cached.id());
+    let input_value = &WeldValue::new_from_data(&4 as *const i32 as Data);
+    let context = &mut WeldContext::new(conf).unwrap();
+    let ret_value = unsafe { cached.run(context, input_value).unwrap() };
+    assert_eq!(unsafe { *(ret_value.data() as *const i32) }, 7);
+
+    // Configuration that affects compilation is part of the cache key.
+    conf.set("weld.optimization.fastMath", "true");
+    let other = WeldModule::compile(code, conf).unwrap();
+    assert_ne!(module.id(), other.id());
+
+    conf.set("weld.compile.cache", "false");
+    let uncached = WeldModule::compile(code, conf).unwrap();
+    assert_ne!(other.id(), uncached.id());
+}
+
+#[test]
+fn dump_code_per_stage() {
+    let dir = std::env::temp_dir().join(format!("weld-dump-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let conf = &mut WeldConf::new();
+    conf.set("weld.compile.dumpCode", "true");
+    conf.set("weld.compile.dumpCodeDir", dir.to_str().unwrap());
+    conf.set("weld.optimization.passes", "inline-let,cse");
+    let module = WeldModule::compile("|x:i32| let y = x + 1; y * y", conf).unwrap();
+
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Every file is named after the compilation, and each pass writes its own file.
+    let prefix = format!("code-{}", module.id());
+    assert!(files.iter().all(|f| f.starts_with(&prefix)));
+    let (passes, stages): (Vec<_>, Vec<_>) = files
+        .iter()
+        .map(|f| &f[prefix.len()..])
+        .partition(|f| f.starts_with("-pass"));
+    assert_eq!(
+        stages,
+        ["-opt.ll", "-opt.weld", ".S", ".ll", ".sir", ".weld"]
+    );
+    assert!(passes.contains(&"-pass00-inline-zip.weld"));
+    assert_eq!(passes.last(), Some(&"-pass04-cse.weld"));
+}
+
+#[test]
+fn compilation_stats() {
+    let conf = &mut WeldConf::new();
+    conf.set("weld.compile.cache", "false");
+    conf.set("weld.optimization.passes", "inline-let,cse");
+    let module = WeldModule::compile("|x:i32| let y = x + 1; y * y", conf).unwrap();
+    let stats = module.compilation_stats();
+
+    let weld_stages: Vec<_> = stats.weld_times().into_iter().map(|(s, _)| s).collect();
+    assert!(weld_stages.contains(&"Parsing"));
+    assert!(weld_stages.contains(&"Type Inference"));
+    let passes: Vec<_> = stats.pass_times().into_iter().map(|(s, _)| s).collect();
+    assert_eq!(passes.last(), Some(&"cse"));
+    let llvm_stages: Vec<_> = stats.llvm_times().into_iter().map(|(s, _)| s).collect();
+    assert!(llvm_stages.contains(&"Code Generation"));
+    assert!(llvm_stages.contains(&"Module Optimization"));
+
+    // The total includes every stage.
+    let stages: std::time::Duration = stats
+        .weld_times()
+        .into_iter()
+        .chain(stats.pass_times())
+        .chain(stats.llvm_times())
+        .map(|(_, dur)| dur)
+        .sum();
+    assert!(stats.total_time() >= stages);
+}
+
+#[test]
+fn deeply_nested_program() {
+    // Each `Let` nests the rest of the program. Compiling this takes several times the default
+    // 8 MiB stack of a main thread, so it overflows unless the program is compiled on a larger one.
+    let depth = 4000;
+    let mut code = "|x:i64| let a0 = x + 1L; ".to_string();
+    for i in 1..depth {
+        code += &format!("let a{} = a{} + 1L; ", i, i - 1);
+    }
+    code += &format!("a{}", depth - 1);
+
+    let conf = &default_conf();
+    let input_data = &1i64;
+    let ret_value = compile_and_run(&code, conf, input_data);
+    let data = ret_value.data() as *const i64;
+    let result = unsafe { *data };
+    assert_eq!(result, depth + 1);
+}
+
+#[test]
+fn compile_struct_fields_in_parallel() {
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct Output {
+        count: i64,
+        sum: i32,
+        low: i32,
+        doubled: WeldVec<i32>,
+    }
+
+    let code = "|x:vec[i32]| {
+        result(for(x, merger[i64,+], |b,i,e| merge(b, 1L))),
+        result(for(x, merger[i32,+], |b,i,e| merge(b, e))),
+        result(for(x, merger[i32,min], |b,i,e| if(e > 1, merge(b, e), b))),
+        result(for(x, appender[i32], |b,i,e| merge(b, e * 2)))
+    }";
+    let conf = &mut default_conf();
+    conf.set("weld.compile.threads", "4");
+
+    let input_vec = [1, 5, 3, 2];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const Output;
+    let result = unsafe { (*data).clone() };
+    assert_eq!(result.count, 4);
+    assert_eq!(result.sum, 11);
+    assert_eq!(result.low, 2);
+    assert_eq!(result.doubled.len, 4);
+    for (i, &e) in input_vec.iter().enumerate() {
+        assert_eq!(unsafe { *result.doubled.data.add(i) }, e * 2);
+    }
+
+    // Type errors in a field are reported as for the whole program.
+    let err = WeldModule::compile("|x:vec[i32]| {len(x), x + 1}", conf).unwrap_err();
+    assert_eq!(err.code(), WeldRuntimeErrno::TypeError);
+}
+
+#[test]
+fn loop_profile() {
+    let code = "|v:vec[i32]|
+        let sum = result(for(v, merger[i64,+], |b,i,e| merge(b, i64(e))));
+        result(for(v, appender[i64], |b,i,e| if(e > 0, merge(b, i64(e) + sum), b)))";
+    let conf = &mut WeldConf::new();
+    conf.set("weld.compile.profileLoops", "true");
+    conf.set("weld.optimization.passes", "-vectorize");
+    let module = WeldModule::compile(code, conf).unwrap();
+
+    let input: Vec<i32> = (-10..10).collect();
+    let input_data = WeldVec::from(&input);
+    let input_value = &WeldValue::new_from_data(&input_data as *const _ as Data);
+    for _ in 0..2 {
+        let context = &mut WeldContext::new(conf).unwrap();
+        unsafe { module.run(context, input_value).unwrap() };
+    }
+
+    // Counters are kept for each loop and accumulate over runs.
+    let mut profile = module.loop_profile();
+    profile.sort_by_key(|p| p.span.unwrap().line);
+    let lines: Vec<_> = profile.iter().map(|p| p.span.unwrap().line).collect();
+    assert_eq!(lines, [2, 3]);
+    assert_ne!(profile[0].id, profile[1].id);
+    assert_eq!(profile[0].iterations, 40);
+    assert_eq!(profile[0].bytes_merged, 40 * 8);
+    assert_eq!(profile[1].iterations, 40);
+    assert_eq!(profile[1].bytes_merged, 2 * 9 * 8);
+
+    module.reset_loop_profile();
+    assert!(module
+        .loop_profile()
+        .iter()
+        .all(|p| p.iterations == 0 && p.bytes_merged == 0));
+
+    // Merges into a loop's builder from a nested loop are counted for the loop that owns it.
+    let code = "|v:vec[vec[i32]]|
+        result(for(v, merger[i64,+], |b,i,e| for(e, b, |b2,j,x| merge(b2, i64(x)))))";
+    let module = WeldModule::compile(code, conf).unwrap();
+    let input: Vec<Vec<i32>> = vec![vec![1, 2, 3], vec![4, 5]];
+    let inner: Vec<WeldVec<i32>> = input.iter().map(WeldVec::from).collect();
+    let input_data = WeldVec::from(&inner);
+    let input_value = &WeldValue::new_from_data(&input_data as *const _ as Data);
+    let context = &mut WeldContext::new(conf).unwrap();
+    unsafe { module.run(context, input_value).unwrap() };
+
+    let mut profile = module.loop_profile();
+    profile.sort_by_key(|p| p.span.unwrap().column);
+    assert_eq!(profile.len(), 2);
+    assert_eq!(profile[0].iterations, 2);
+    assert_eq!(profile[0].bytes_merged, 5 * 8);
+    assert_eq!(profile[1].iterations, 5);
+    assert_eq!(profile[1].bytes_merged, 5 * 8);
+
+    // Loops are not profiled by default.
+    conf.set("weld.compile.profileLoops", "false");
+    let module = WeldModule::compile(code, conf).unwrap();
+    assert!(module.loop_profile().is_empty());
+}
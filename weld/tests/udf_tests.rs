@@ -0,0 +1,48 @@
+//! Tests for calling C UDFs with `cudf`.
+
+use libc::c_void;
+
+mod common;
+use crate::common::*;
+
+extern "C" fn add_five(x: *const i64, result: *mut i64) {
+    unsafe { *result = *x + 5 };
+}
+
+extern "C" fn sum_vector(x: *const WeldVec<i32>, result: *mut i64) {
+    let x = unsafe { &*x };
+    let mut sum = 0;
+    for i in 0..(x.len as isize) {
+        sum += i64::from(unsafe { *x.data.offset(i) });
+    }
+    unsafe { *result = sum };
+}
+
+#[test]
+fn scalar_udf() {
+    unsafe { weld::register_udf("add_five", add_five as *mut c_void).unwrap() };
+
+    let code = "|x:i64| cudf[add_five,i64](x)";
+    let conf = &default_conf();
+    let input: i64 = 10;
+
+    let ret_value = compile_and_run(code, conf, &input);
+    let data = ret_value.data() as *const i64;
+    let result = unsafe { *data };
+    assert_eq!(result, 15);
+}
+
+#[test]
+fn vector_udf() {
+    unsafe { weld::register_udf("sum_vector", sum_vector as *mut c_void).unwrap() };
+
+    let code = "|x:vec[i32]| cudf[sum_vector,i64](x)";
+    let conf = &default_conf();
+    let input_vec = vec![1, 2, 3, 4, 5];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i64;
+    let result = unsafe { *data };
+    assert_eq!(result, 15);
+}
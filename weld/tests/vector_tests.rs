@@ -280,6 +280,21 @@ fn simple_vector_lookup() {
     assert_eq!(result, input_vec[3]);
 }
 
+#[test]
+fn bounds_checked_vector_lookup() {
+    let code = "|x:vec[i32]| lookup(x, 4L)";
+    let conf = &mut default_conf();
+    conf.set("weld.compile.enableBoundsChecks", "true");
+
+    let input_vec = vec![1, 2, 3, 4, 5];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+    assert_eq!(result, input_vec[4]);
+}
+
 #[test]
 fn simple_vector_slice() {
     let code = "|x:vec[i32]| slice(x, 1L, 3L)";
@@ -313,4 +328,16 @@ fn simple_vector_slice() {
     for i in 0..(result.len as isize) {
         assert_eq!(unsafe { *result.data.offset(i) }, output[i as usize])
     }
+
+    // Test slicing with a start index past the end of the vector
+    let code = "|x:vec[i32]| slice(x, 10L, 3L)";
+    let conf = &default_conf();
+
+    let input_vec = vec![1, 2];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const WeldVec<i32>;
+    let result = unsafe { (*data).clone() };
+    assert_eq!(result.len, 0);
 }
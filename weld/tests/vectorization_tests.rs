@@ -28,6 +28,21 @@ fn simple_for_vectorizable_loop() {
     assert_eq!(result, output);
 }
 
+#[test]
+fn simditer_loop_without_annotation() {
+    let code = "|x:vec[i32]| result(for(simditer(x), merger[i32,+], |b,i,e| merge(b, e+broadcast(2))))";
+    let conf = &default_conf();
+
+    let size = 1000;
+    let input_vec = vec![1; size as usize];
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+    assert_eq!(result, size * 3);
+}
+
 #[test]
 fn fringed_for_vectorizable_loop() {
     #[allow(dead_code)]
@@ -127,6 +142,44 @@ fn for_predicated_vectorizable_loop() {
     assert_eq!(result, output);
 }
 
+#[test]
+fn vectorized_casts_and_negation() {
+    #[allow(dead_code)]
+    struct Args {
+        x: WeldVec<i32>,
+    }
+
+    let code =
+        "|x:vec[i32]| result(for(x, merger[i64,+], |b,i,e| merge(b, -i64(e) + i64(!(e > 500)))))";
+    let conf = &default_conf();
+
+    let input_vec: Vec<i32> = (0..1000).collect();
+    let input_data = &Args {
+        x: WeldVec::from(&input_vec),
+    };
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i64;
+    let result = unsafe { *data };
+    assert_eq!(result, -499500 + 501);
+}
+
+#[test]
+fn vectorized_shifts() {
+    // The division and modulo are strength-reduced into shifts, which are then vectorized.
+    let code = "|x:vec[i32]| result(for(x, merger[i32,+], |b,i,e| merge(b, e / 4 + e % 8 + (e << 1))))";
+    let conf = &default_conf();
+
+    let input_vec: Vec<i32> = (-500..500).collect();
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+    let expected: i32 = input_vec.iter().map(|e| e / 4 + e % 8 + (e << 1)).sum();
+    assert_eq!(result, expected);
+}
+
 fn check_result_and_free(ret_value: WeldValue, expected: i32) {
     let data = ret_value.data() as *const i32;
     let result = unsafe { (*data).clone() };
@@ -165,3 +218,60 @@ fn predicate_if_iff_annotated() {
     let ret_value = compile_and_run(code, conf, input_data);
     check_result_and_free(ret_value, expected);
 }
+
+#[test]
+fn predicate_max_merger() {
+    #[allow(dead_code)]
+    struct Args {
+        v: WeldVec<i32>,
+    }
+
+    let input_vec = vec![-1, 2, 3, 4, 5];
+    let input_data = &Args {
+        v: WeldVec::from(&input_vec),
+    };
+
+    // Predication merges the smallest i32 when the condition is false.
+    let code = "|v:vec[i32]| result(for(v, merger[i32,max], |b,i,e| if(e<4, merge(b,e), b)))";
+    let conf = &default_conf();
+    let ret_value = compile_and_run(code, conf, input_data);
+    check_result_and_free(ret_value, 3);
+}
+
+#[test]
+fn float_merger_is_strict_by_default() {
+    // Adding these values in a different order changes the rounding of the sum.
+    let input_vec = vec![1e16, 1e16, 1.0, 1.0, -1e16, -1e16, 1.0, 1.0];
+    let input_data = &WeldVec::from(&input_vec);
+    let expected: f64 = input_vec.iter().sum();
+
+    let code = "|v:vec[f64]| result(for(v, merger[f64,+], |b,i,e| merge(b, e)))";
+    let conf = &default_conf();
+    let ret_value = compile_and_run(code, conf, input_data);
+    let result = unsafe { *(ret_value.data() as *const f64) };
+    assert_eq!(result, expected);
+
+    // With fast math, the loop is vectorized and the additions are reordered.
+    let conf = &mut default_conf();
+    conf.set("weld.optimization.fastMath", "true");
+    let ret_value = compile_and_run(code, conf, input_data);
+    let result = unsafe { *(ret_value.data() as *const f64) };
+    // Each of the four vector lanes sums every fourth value.
+    assert_eq!(result, 4.0);
+}
+
+#[test]
+fn short_circuit_without_short_circuit_pass() {
+    // Without the pass, the vectorizer must not evaluate the division for lanes where e is zero.
+    let code = "|x:vec[i32]| result(for(x, merger[i32,+], |b,i,e| merge(b, select(e != 0 && 10 / e > 1, 1, 0))))";
+    let conf = &mut default_conf();
+    conf.set("weld.optimization.passes", "-short-circuit-booleans");
+
+    let input_vec: Vec<i32> = (0..1000).map(|i| i % 4).collect();
+    let input_data = &WeldVec::from(&input_vec);
+
+    let ret_value = compile_and_run(code, conf, input_data);
+    let data = ret_value.data() as *const i32;
+    let result = unsafe { *data };
+    assert_eq!(result, 750);
+}